@@ -0,0 +1,27 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use nes::{asm::load_flat_machine, bench_programs::Workload};
+
+/// Run each of the generated workloads from start to finish. The machine is set up
+/// outside of the measurement, so only the CPU core is timed.
+fn workloads(c: &mut Criterion) {
+    let sizes = [
+        (Workload::MemcpyAbsolute, 0xff),
+        (Workload::MemcpyIndirect, 0x04),
+        (Workload::BubbleSort, 0x20),
+        (Workload::Multiply, 0xff),
+    ];
+
+    for (workload, size) in sizes.iter() {
+        let source = workload.source(*size);
+        c.bench_function(workload.name(), |b| {
+            b.iter_batched(
+                || load_flat_machine::<0x10000>(&source).unwrap().0,
+                |mut machine| while machine.cpu.tick().is_running() {},
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
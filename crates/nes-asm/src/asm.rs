@@ -1,9 +1,14 @@
-use crate::{
+use crate::symbols::Symbols;
+use colored::*;
+use nes_core::{
     constants::memory_range,
-    opcodes::{instruction_mode_to_op_code, match_instruction, Instruction, TokenMode},
+    cpu_6502::CpuVariant,
+    flat_machine::FlatMachine,
+    opcodes::{
+        instruction_mode_to_op_code, match_instruction, Instruction, OpCode, TokenMode,
+    },
 };
-use colored::*;
-use std::{collections::HashMap, str::Chars};
+use std::str::Chars;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -215,15 +220,77 @@ impl ParseError {
     }
 
     pub fn panic_nicely(self) {
-        panic!(self.nice_message);
+        panic!("{}", self.nice_message);
     }
-}
 
-pub type AddressToLabel = HashMap<u16, String>;
+    /// The message with the surrounding source code, and the error highlighted.
+    pub fn nice_message(&self) -> &str {
+        &self.nice_message
+    }
+}
 
 pub struct BytesLabels {
     pub bytes: Vec<u8>,
-    pub address_to_label: AddressToLabel,
+    pub symbols: Symbols,
+    pub listing: Listing,
+}
+
+/// An instruction in the assembled program, and the row of the source it came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListingLine {
+    /// Where the instruction is when the program is placed at the PRG ROM.
+    pub address: u16,
+    /// The row in the source, starting at 1.
+    pub row: u64,
+}
+
+/// Maps the assembled instructions back to the source, so that tools working on the
+/// bytes can report where things are. The data from .byte and .word isn't listed, so
+/// these are also the only addresses that hold code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    /// The instructions, in the order of their addresses.
+    pub fn lines(&self) -> &[ListingLine] {
+        &self.lines
+    }
+
+    /// The row of the instruction at the address.
+    pub fn row(&self, address: u16) -> Option<u64> {
+        self.line_index(address).map(|index| self.lines[index].row)
+    }
+
+    /// Does an instruction start at the address?
+    pub fn is_instruction(&self, address: u16) -> bool {
+        self.line_index(address).is_some()
+    }
+
+    /// Is the address within an instruction, including its operand?
+    pub fn contains_code(&self, address: u16, bytes: &[u8]) -> bool {
+        let index = match self
+            .lines
+            .binary_search_by_key(&address, |line| line.address)
+        {
+            Ok(_) => return true,
+            Err(0) => return false,
+            Err(index) => index - 1,
+        };
+        let start = self.lines[index].address;
+        let opcode = bytes[(start - memory_range::PRG_ROM.start) as usize];
+        let size = 1 + nes_core::opcodes::decode(CpuVariant::Nmos6502, opcode)
+            .mode
+            .operand_size();
+        address < start + size
+    }
+
+    fn line_index(&self, address: u16) -> Option<usize> {
+        self.lines
+            .binary_search_by_key(&address, |line| line.address)
+            .ok()
+    }
 }
 
 pub struct AsmLexer<'a> {
@@ -232,18 +299,23 @@ pub struct AsmLexer<'a> {
     characters: std::iter::Peekable<Chars<'a>>,
     tokens: Vec<Token>,
     labels: LabelTable,
+    /// The row of each Token::Instruction, in order.
+    instruction_rows: Vec<u64>,
+    listing: Listing,
     row: u64,
     column: u64,
 }
 
 impl<'a> AsmLexer<'a> {
-    pub fn new(text: &'a str) -> AsmLexer {
+    pub fn new(text: &'a str) -> AsmLexer<'a> {
         AsmLexer {
             text,
             characters: IntoIterator::into_iter("".chars()).peekable(),
             lines: IntoIterator::into_iter(text.lines()),
             tokens: Vec::new(),
             labels: LabelTable::new(),
+            instruction_rows: Vec::new(),
+            listing: Listing::default(),
             column: 1,
             row: 1,
         }
@@ -295,6 +367,7 @@ impl<'a> AsmLexer<'a> {
                         match match_instruction(&word) {
                             Some(instruction) => {
                                 self.tokens.push(Token::Instruction(instruction.clone()));
+                                self.instruction_rows.push(self.row);
                                 self.parse_operand(instruction)?;
                             }
                             None => {
@@ -338,7 +411,9 @@ impl<'a> AsmLexer<'a> {
 
         // Consume self to move the data we still care about, at the end, the rest
         // of the data will be dropped.
-        let AsmLexer { mut labels, .. } = self;
+        let AsmLexer {
+            labels, listing, ..
+        } = self;
 
         // Fill in the proper addresses for the labels. The code will be placed at
         // memory_range::PRG_ROM.min when placed into the emulator.
@@ -356,7 +431,7 @@ impl<'a> AsmLexer<'a> {
                         // The byte offset is for the operand, move it to the instruction.
                         + 1;
 
-                    if offset > 127 || offset < -128 {
+                    if !(-128..=127).contains(&offset) {
                         return Err(
                             "A relative label was used too far away to be generated."
                                 .into(),
@@ -378,37 +453,37 @@ impl<'a> AsmLexer<'a> {
             };
         }
 
-        // Convert the labels to a HashMap data structure that makes it easy to go
-        // from an address to the string. This new data structure will own the strings.
-        let mut address_to_label: AddressToLabel = HashMap::new();
+        // Convert the labels to symbols, in the order they were first seen, so that the
+        // first label at an address is the one that's shown.
+        let mut symbols = Symbols::new();
         if let Some(addresses) = labels.addresses {
-            for string_index in 0..labels.strings.len() {
+            for (string_index, name) in labels.strings.iter().enumerate() {
                 let address = addresses.get(string_index).expect("Unable to get address");
-
-                // Take ownership of the string.
-                let old_string = labels
-                    .strings
-                    .get_mut(string_index)
-                    .expect("Unable to get string");
-                let mut new_string = String::with_capacity(0);
-
-                std::mem::swap(&mut new_string, old_string);
-
-                address_to_label
-                    .insert(*address as u16 + memory_range::PRG_ROM.start, new_string);
+                symbols
+                    .insert_label(name, *address as u16 + memory_range::PRG_ROM.start)?;
             }
         }
 
         Ok(BytesLabels {
             bytes,
-            address_to_label,
+            symbols,
+            listing,
         })
     }
 
     fn as_bytes_before_labels(&mut self) -> Result<Vec<u8>, String> {
         let mut bytes: Vec<u8> = Vec::new();
         let mut tokens = self.tokens.iter().peekable();
+        let mut instruction_rows = self.instruction_rows.iter();
         while let Some(token) = tokens.next() {
+            if let Token::Instruction(_) = token {
+                self.listing.lines.push(ListingLine {
+                    address: bytes.len() as u16 + memory_range::PRG_ROM.start,
+                    row: *instruction_rows
+                        .next()
+                        .expect("Every instruction has a row."),
+                });
+            }
             match token {
                 Token::Instruction(instruction) => match tokens.peek() {
                     Some(Token::LabelOperand(string_index)) => {
@@ -592,7 +667,7 @@ impl<'a> AsmLexer<'a> {
             }
             character => {
                 let number = self.get_word(Some(&character))?;
-                match u8::from_str_radix(&number, 10) {
+                match number.parse::<u8>() {
                     Ok(number) => Ok(number),
                     Err(_) => Err(format!("Unable to parse as integer \"{}\"", number)),
                 }
@@ -626,7 +701,7 @@ impl<'a> AsmLexer<'a> {
             }
             character => {
                 let number = self.get_word(Some(&character))?;
-                match u16::from_str_radix(&number, 10) {
+                match number.parse::<u16>() {
                     Ok(number) => Ok(number),
                     Err(_) => Err(format!("Unable to parse as integer \"{}\"", number)),
                 }
@@ -684,7 +759,7 @@ impl<'a> AsmLexer<'a> {
                 // TODO - Is it possible to differentiate U8 or U16 here? For now assume
                 // that it's u8.
                 let number = self.get_word(Some(&character))?;
-                match u8::from_str_radix(&number, 10) {
+                match number.parse::<u8>() {
                     Ok(number) => Ok(U8OrU16::U8(number)),
                     Err(_) => Err(format!("Unable to parse as integer \"{}\"", number)),
                 }
@@ -952,10 +1027,33 @@ impl<'a> AsmLexer<'a> {
         Ok(word)
     }
 }
+
+/// Assemble a program that runs on its own, like a test or a sample. It ends with a
+/// KIL, so that the CPU stops once the program is done.
+pub fn assemble_program(text: &str) -> Result<(Vec<u8>, Symbols), String> {
+    let mut lexer = AsmLexer::new(text);
+    if let Err(parse_error) = lexer.parse() {
+        return Err(parse_error.nice_message().into());
+    }
+    let BytesLabels {
+        mut bytes, symbols, ..
+    } = lexer.into_bytes()?;
+    bytes.push(OpCode::KIL as u8);
+    Ok((bytes, symbols))
+}
+
+/// Assemble the program into a FlatMachine, see FlatMachine::with_program.
+pub fn load_flat_machine<const SIZE: usize>(
+    text: &str,
+) -> Result<(FlatMachine<SIZE>, Symbols), String> {
+    let (bytes, symbols) = assemble_program(text)?;
+    Ok((FlatMachine::with_program(&bytes)?, symbols))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::opcodes::OpCode::*;
+    use nes_core::opcodes::OpCode::*;
 
     macro_rules! assert_program {
         ( $text:expr, [$( $bytes:expr ),*] ) => {
@@ -1148,4 +1246,25 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_symbols() {
+        let mut lexer = AsmLexer::new(
+            "
+              reset:
+              start:
+                jmp end
+              end:
+            ",
+        );
+        lexer.parse().unwrap();
+        let BytesLabels { symbols, .. } = lexer.into_bytes().unwrap();
+        // Both of the labels at the same address are kept.
+        let names: Vec<&str> = symbols
+            .labels_at(0x8000)
+            .map(|symbol| symbol.name.as_str())
+            .collect();
+        assert_eq!(names, ["reset", "start"]);
+        assert_eq!(symbols.address("end"), Some(0x8003));
+    }
 }
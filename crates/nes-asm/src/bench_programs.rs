@@ -0,0 +1,230 @@
+/// Synthetic workloads for benchmarking the CPU core. Each one is an assembly
+/// template that exercises a different mix of addressing modes, and is sized by
+/// a single byte parameter. The programs end with a KIL once they are assembled.
+///
+/// Run one through the headless runner with:
+/// cargo run --bin nes -- bench bubble-sort 64 > sort.asm
+/// cargo run --bin nes -- run sort.asm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    /// Copy bytes from $0200 to $0300 using absolute indexed addressing.
+    MemcpyAbsolute,
+    /// Copy whole pages from $0200 to $0400 through zero page pointers, using
+    /// (indirect),Y addressing. The size is the number of pages.
+    MemcpyIndirect,
+    /// Sort a list of bytes at $0200 that starts out in reverse order.
+    BubbleSort,
+    /// Run an 8x8 bit shift-and-add multiply over and over, in the zero page.
+    Multiply,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 4] = [
+        Workload::MemcpyAbsolute,
+        Workload::MemcpyIndirect,
+        Workload::BubbleSort,
+        Workload::Multiply,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::MemcpyAbsolute => "memcpy-absolute",
+            Workload::MemcpyIndirect => "memcpy-indirect",
+            Workload::BubbleSort => "bubble-sort",
+            Workload::Multiply => "multiply",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Workload> {
+        Workload::ALL
+            .iter()
+            .find(|workload| workload.name() == name)
+            .copied()
+    }
+
+    /// Fill in the template for a given size. The size needs to be at least 2.
+    pub fn source(&self, size: u8) -> String {
+        assert!(size >= 2, "The benchmark size must be at least 2.");
+        let template = match self {
+            Workload::MemcpyAbsolute => MEMCPY_ABSOLUTE,
+            Workload::MemcpyIndirect => MEMCPY_INDIRECT,
+            Workload::BubbleSort => BUBBLE_SORT,
+            Workload::Multiply => MULTIPLY,
+        };
+        template
+            .replace("{size}", &format!("${:02x}", size))
+            .replace("{last}", &format!("${:02x}", size - 1))
+    }
+}
+
+const MEMCPY_ABSOLUTE: &str = "
+    ; Fill the source with incrementing values.
+    ldx #$00
+  fill:
+    txa
+    sta $0200,x
+    inx
+    cpx #{size}
+    bne fill
+
+    ; Copy it over.
+    ldx #$00
+  copy:
+    lda $0200,x
+    sta $0300,x
+    inx
+    cpx #{size}
+    bne copy
+";
+
+const MEMCPY_INDIRECT: &str = "
+    ; Source pointer at $10, destination pointer at $12.
+    lda #$00
+    sta $10
+    sta $12
+    lda #$02
+    sta $11
+    lda #$04
+    sta $13
+
+    ldx #{size}
+    ldy #$00
+  copy:
+    lda ($10),Y
+    sta ($12),Y
+    iny
+    bne copy
+    ; Move on to the next page.
+    inc $11
+    inc $13
+    dex
+    bne copy
+";
+
+const BUBBLE_SORT: &str = "
+    ; Fill the list in reverse order, which is the worst case.
+    ldx #$00
+    lda #{size}
+  fill:
+    sta $0200,x
+    sec
+    sbc #$01
+    inx
+    cpx #{size}
+    bne fill
+
+  outer:
+    ; $10 is set when anything was swapped.
+    lda #$00
+    sta $10
+    ldx #$00
+  inner:
+    lda $0200,x
+    cmp $0201,x
+    bcc next
+    beq next
+    ; Swap the pair.
+    ldy $0201,x
+    sta $0201,x
+    tya
+    sta $0200,x
+    lda #$01
+    sta $10
+  next:
+    inx
+    cpx #{last}
+    bne inner
+    lda $10
+    bne outer
+";
+
+const MULTIPLY: &str = "
+    ; Multiply each of {size} down to 1 by $5a. The result of the last
+    ; multiplication is left in $22 and $23.
+    ldy #{size}
+  again:
+    sty $20
+    lda #$5a
+    sta $21
+    lda #$00
+    sta $22
+    sta $23
+    sta $24
+    ldx #$08
+  next_bit:
+    lsr $21
+    bcc shift
+    clc
+    lda $22
+    adc $20
+    sta $22
+    lda $23
+    adc $24
+    sta $23
+  shift:
+    asl $20
+    rol $24
+    dex
+    bne next_bit
+    dey
+    bne again
+";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::load_flat_machine;
+    use nes_core::flat_machine::FlatMachine64K;
+
+    fn run(workload: Workload, size: u8) -> FlatMachine64K {
+        let (mut machine, _) = load_flat_machine(&workload.source(size)).unwrap();
+        while machine.cpu.tick().is_running() {}
+        machine
+    }
+
+    #[test]
+    fn test_names() {
+        for workload in Workload::ALL.iter() {
+            assert_eq!(Workload::from_name(workload.name()), Some(*workload));
+        }
+    }
+
+    #[test]
+    fn test_memcpy_absolute() {
+        let machine = run(Workload::MemcpyAbsolute, 0x20);
+        for i in 0..0x20 {
+            assert_eq!(machine.read_u8(0x0300 + i), i as u8);
+        }
+        assert_eq!(machine.read_u8(0x0320), 0);
+    }
+
+    #[test]
+    fn test_bubble_sort() {
+        let machine = run(Workload::BubbleSort, 0x10);
+        for i in 0..0x10 {
+            assert_eq!(machine.read_u8(0x0200 + i), i as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn test_multiply() {
+        let machine = run(Workload::Multiply, 0x03);
+        // The last multiplication is 1 * $5a.
+        assert_eq!(machine.read_u8(0x22), 0x5a);
+        assert_eq!(machine.read_u8(0x23), 0x00);
+    }
+
+    #[test]
+    fn test_memcpy_indirect() {
+        let source = Workload::MemcpyIndirect.source(0x02);
+        let (mut machine, _) = load_flat_machine::<0x10000>(&source).unwrap();
+        for i in 0..0x200 {
+            machine.set_u8(0x0200 + i, i as u8 ^ 0x5a);
+        }
+        while machine.cpu.tick().is_running() {}
+        for i in 0..0x200 {
+            assert_eq!(machine.read_u8(0x0400 + i), i as u8 ^ 0x5a);
+        }
+        assert_eq!(machine.read_u8(0x0600), 0);
+    }
+}
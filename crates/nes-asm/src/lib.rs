@@ -0,0 +1,11 @@
+//! The 6502 assembler, and the programs that are assembled for the benchmarks.
+
+// Remove this once this is a bit more mature.
+#![allow(dead_code)]
+// Clippy rules to disable.
+#![allow(clippy::new_without_default)]
+
+pub mod asm;
+pub mod bench_programs;
+pub mod peephole;
+pub mod symbols;
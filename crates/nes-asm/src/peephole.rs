@@ -0,0 +1,276 @@
+//! Look over an assembled program for the common 6502 mistakes, one instruction and
+//! its neighbors at a time. This is opt-in, see `nes lint`, as every warning has
+//! programs that do it on purpose.
+//!
+//! The analysis works on the bytes rather than the source, so it sees exactly what the
+//! CPU will run, and the Listing maps the warnings back to the rows of the source.
+use crate::asm::{BytesLabels, Listing};
+use crate::symbols::Symbols;
+use nes_core::constants::memory_range;
+use nes_core::cpu_6502::CpuVariant;
+use nes_core::opcodes::{self, Mode};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    /// A `cmp #0` right after an instruction that already set the zero and negative
+    /// flags from the accumulator.
+    RedundantCompare { after: &'static str },
+    /// An `adc` that nothing before it set the carry for, so it adds a stray 1 some of
+    /// the time.
+    MissingClc,
+    /// A branch that lands on another page, which costs a cycle when it's taken.
+    PageCrossingBranch { target: u16 },
+    /// A store into the program's own code, which is ROM on a cartridge.
+    WriteToCode { address: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// The address of the instruction.
+    pub address: u16,
+    /// The row of the instruction in the source, if the listing has it.
+    pub row: Option<u64>,
+    pub kind: WarningKind,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::RedundantCompare { after } => write!(
+                f,
+                "The cmp #0 isn't needed, the {} already set the flags.",
+                after
+            ),
+            WarningKind::MissingClc => {
+                write!(f, "The adc doesn't have a clc or sec before it.")
+            }
+            WarningKind::PageCrossingBranch { target } => write!(
+                f,
+                "The branch to ${:04x} crosses a page, which costs a cycle when taken.",
+                target
+            ),
+            WarningKind::WriteToCode { address } => write!(
+                f,
+                "The write to ${:04x} modifies the code, which is ROM on a cartridge.",
+                address
+            ),
+        }
+    }
+}
+
+/// An instruction from the listing, decoded.
+struct Decoded {
+    address: u16,
+    name: &'static str,
+    mode: Mode,
+    /// The operand, which is 0 for the instructions without one.
+    operand: u16,
+}
+
+/// The instructions that set the zero and negative flags from the accumulator.
+const SETS_FLAGS_FROM_A: [&str; 9] = [
+    "lda", "adc", "sbc", "and", "ora", "eor", "pla", "txa", "tya",
+];
+
+/// The instructions that leave the carry in a known state, or in a state that the
+/// program means to add.
+const SETS_CARRY: [&str; 12] = [
+    "clc", "sec", "adc", "sbc", "cmp", "cpx", "cpy", "asl", "lsr", "rol", "ror", "plp",
+];
+
+/// The instructions that write to their operand.
+const STORES: [&str; 9] = [
+    "sta", "stx", "sty", "inc", "dec", "asl", "lsr", "rol", "ror",
+];
+
+/// Check the assembled program, and return the warnings in the order of their
+/// addresses.
+pub fn analyze(assembled: &BytesLabels) -> Vec<Warning> {
+    let instructions = decode(&assembled.bytes, &assembled.listing);
+    let mut warnings = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let previous = &instructions[..index];
+        if let Some(kind) = check_instruction(
+            instruction,
+            previous,
+            &assembled.bytes,
+            &assembled.listing,
+            &assembled.symbols,
+        ) {
+            warnings.push(Warning {
+                address: instruction.address,
+                row: assembled.listing.row(instruction.address),
+                kind,
+            });
+        }
+    }
+    warnings
+}
+
+fn decode(bytes: &[u8], listing: &Listing) -> Vec<Decoded> {
+    listing
+        .lines()
+        .iter()
+        .filter_map(|line| {
+            let offset = (line.address - memory_range::PRG_ROM.start) as usize;
+            let entry = opcodes::decode(CpuVariant::Nmos6502, *bytes.get(offset)?);
+            let operand = match entry.mode.operand_size() {
+                1 => *bytes.get(offset + 1)? as u16,
+                2 => {
+                    u16::from_le_bytes([*bytes.get(offset + 1)?, *bytes.get(offset + 2)?])
+                }
+                _ => 0,
+            };
+            Some(Decoded {
+                address: line.address,
+                name: entry.name,
+                mode: entry.mode,
+                operand,
+            })
+        })
+        .collect()
+}
+
+/// Is the instruction the target of a label, so that it can be reached from somewhere
+/// other than the instruction before it?
+fn is_label(symbols: &Symbols, address: u16) -> bool {
+    symbols.labels_at(address).next().is_some()
+}
+
+fn check_instruction(
+    instruction: &Decoded,
+    previous: &[Decoded],
+    bytes: &[u8],
+    listing: &Listing,
+    symbols: &Symbols,
+) -> Option<WarningKind> {
+    match (instruction.name, instruction.mode) {
+        ("cmp", Mode::Immediate) if instruction.operand == 0 => {
+            if is_label(symbols, instruction.address) {
+                return None;
+            }
+            let before = previous.last()?;
+            SETS_FLAGS_FROM_A
+                .contains(&before.name)
+                .then_some(WarningKind::RedundantCompare { after: before.name })
+        }
+        ("adc", _) => {
+            // Walk back through the code that falls through to it. This goes past the
+            // labels, so that a loop is covered by the clc before it, but not past
+            // the end of a subroutine.
+            for before in previous.iter().rev() {
+                match before.name {
+                    name if SETS_CARRY.contains(&name) => return None,
+                    // A subroutine can return the carry on purpose, and the branches
+                    // on the carry leave it known.
+                    "jsr" | "bcc" | "bcs" => return None,
+                    "jmp" | "rts" | "rti" | "brk" => break,
+                    _ => {}
+                }
+            }
+            Some(WarningKind::MissingClc)
+        }
+        (_, Mode::Relative) => {
+            // The offset is from the branch itself, like the assembler writes it and
+            // the CPU runs it, and the page is compared against the next instruction.
+            let next = instruction.address.wrapping_add(2);
+            let target = instruction
+                .address
+                .wrapping_add(instruction.operand as u8 as i8 as u16);
+            (next >> 8 != target >> 8)
+                .then_some(WarningKind::PageCrossingBranch { target })
+        }
+        (name, Mode::Absolute | Mode::AbsoluteIndexedX | Mode::AbsoluteIndexedY)
+            if STORES.contains(&name) =>
+        {
+            let address = instruction.operand;
+            listing
+                .contains_code(address, bytes)
+                .then_some(WarningKind::WriteToCode { address })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::AsmLexer;
+
+    fn lint(text: &str) -> Vec<(u64, String)> {
+        let mut lexer = AsmLexer::new(text);
+        lexer.parse().unwrap();
+        analyze(&lexer.into_bytes().unwrap())
+            .iter()
+            .map(|warning| (warning.row.unwrap(), warning.kind.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_analyze() {
+        let warnings = lint(
+            "lda $10
+            adc #1
+            cmp #0
+            clc
+            adc #2
+            sta patch
+            sta $c000
+            ldx $20
+            cmp #0
+          loop:
+            adc #3
+            bne loop
+          patch:
+            lda #0
+            jsr add
+            adc #4
+            rts
+          add:
+            cmp #0
+            rts
+          done:
+            lda $40
+            adc #5",
+        );
+        assert_eq!(
+            warnings,
+            [
+                (2, "The adc doesn't have a clc or sec before it.".into()),
+                (
+                    3,
+                    "The cmp #0 isn't needed, the adc already set the flags.".into()
+                ),
+                (
+                    6,
+                    "The write to $8017 modifies the code, which is ROM on a cartridge."
+                        .into()
+                ),
+                (23, "The adc doesn't have a clc or sec before it.".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_page_crossing_branch() {
+        // The first branch is at $80fc, and lands on $8100.
+        let mut text = "nop\n".repeat(0xfc);
+        text.push_str("back:\nbeq done\nnop\nnop\ndone:\nbne back\n");
+        assert_eq!(
+            lint(&text),
+            [
+                (
+                    254,
+                    "The branch to $8100 crosses a page, which costs a cycle when taken."
+                        .into()
+                ),
+                (
+                    258,
+                    "The branch to $80fc crosses a page, which costs a cycle when taken."
+                        .into()
+                ),
+            ]
+        );
+    }
+}
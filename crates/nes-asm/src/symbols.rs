@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+/// A name for an address, or for a range of addresses like a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub start: u16,
+    /// The last address, inclusive. It's the start for a plain label.
+    pub end: u16,
+    /// The PRG bank the symbol lives in, for code that's switched in by a mapper. None
+    /// is for the addresses that don't switch, like the RAM or a fixed bank.
+    pub bank: Option<u8>,
+    pub comment: Option<String>,
+}
+
+impl Symbol {
+    pub fn label(name: &str, address: u16) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            start: address,
+            end: address,
+            bank: None,
+            comment: None,
+        }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+
+    fn is_in_bank(&self, bank: Option<u8>) -> bool {
+        self.bank.is_none() || bank.is_none() || self.bank == bank
+    }
+}
+
+/// The names of the addresses in a program. The assembler makes these for its
+/// labels, and the debugger and the disassemblers use them to show names rather than
+/// addresses. An address can have more than one label, and the first one added is
+/// the one that's shown. The names are unique, so they can be looked up either way.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Symbols {
+    /// Kept in the order they were added.
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) -> Result<(), String> {
+        if symbol.end < symbol.start {
+            return Err(format!(
+                "The symbol \"{}\" ends at ${:04x} before it starts at ${:04x}.",
+                symbol.name, symbol.end, symbol.start
+            ));
+        }
+        if let Some(&index) = self.by_name.get(&symbol.name) {
+            return Err(format!(
+                "The symbol \"{}\" is already defined at ${:04x}.",
+                symbol.name, self.symbols[index].start
+            ));
+        }
+        self.by_name.insert(symbol.name.clone(), self.symbols.len());
+        self.symbols.push(symbol);
+        Ok(())
+    }
+
+    /// Add a plain label for an address.
+    pub fn insert_label(&mut self, name: &str, address: u16) -> Result<(), String> {
+        self.insert(Symbol::label(name, address))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Every symbol, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&index| &self.symbols[index])
+    }
+
+    /// Look up the address of a name.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.get(name).map(|symbol| symbol.start)
+    }
+
+    /// The symbols that start at an address, in any bank.
+    pub fn labels_at(&self, address: u16) -> impl Iterator<Item = &Symbol> {
+        self.labels_in_bank(address, None)
+    }
+
+    /// The symbols that start at an address, and that live in the bank, or don't
+    /// switch. A bank of None matches every bank.
+    pub fn labels_in_bank(
+        &self,
+        address: u16,
+        bank: Option<u8>,
+    ) -> impl Iterator<Item = &Symbol> {
+        self.symbols
+            .iter()
+            .filter(move |symbol| symbol.start == address && symbol.is_in_bank(bank))
+    }
+
+    /// The name to show for an address, if there's a symbol that starts there.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels_at(address)
+            .next()
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /// Name an address, even in the middle of a range, e.g. "table+3".
+    pub fn describe(&self, address: u16, bank: Option<u8>) -> Option<String> {
+        if let Some(symbol) = self.labels_in_bank(address, bank).next() {
+            return Some(symbol.name.clone());
+        }
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.contains(address) && symbol.is_in_bank(bank))
+            .map(|symbol| format!("{}+{}", symbol.name, address - symbol.start))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookups() {
+        let mut symbols = Symbols::new();
+        symbols.insert_label("reset", 0x8000).unwrap();
+        symbols.insert_label("start", 0x8000).unwrap();
+        symbols
+            .insert(Symbol {
+                name: "table".into(),
+                start: 0x0300,
+                end: 0x030f,
+                bank: None,
+                comment: Some("The enemy positions".into()),
+            })
+            .unwrap();
+
+        // The first label is the one that's shown.
+        assert_eq!(symbols.label(0x8000), Some("reset"));
+        assert_eq!(symbols.labels_at(0x8000).count(), 2);
+        assert_eq!(symbols.address("start"), Some(0x8000));
+        assert_eq!(symbols.describe(0x0303, None), Some("table+3".into()));
+        assert_eq!(symbols.describe(0x0310, None), None);
+        assert_eq!(
+            symbols.insert_label("start", 0x9000),
+            Err("The symbol \"start\" is already defined at $8000.".into())
+        );
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut symbols = Symbols::new();
+        for (name, bank) in &[("title", Some(0)), ("level", Some(1)), ("nmi", None)] {
+            let mut symbol = Symbol::label(name, 0x8000);
+            symbol.bank = *bank;
+            symbols.insert(symbol).unwrap();
+        }
+        let names = |bank| -> Vec<&str> {
+            symbols
+                .labels_in_bank(0x8000, bank)
+                .map(|symbol| symbol.name.as_str())
+                .collect()
+        };
+        assert_eq!(names(Some(1)), ["level", "nmi"]);
+        assert_eq!(names(None), ["title", "level", "nmi"]);
+        assert_eq!(symbols.describe(0x8000, Some(1)), Some("level".into()));
+    }
+}
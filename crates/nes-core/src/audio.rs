@@ -0,0 +1,291 @@
+// Audio buffering between the emulator and the platform's audio device, and the
+// helpers for golden audio tests. There is no APU producing samples yet, this is the
+// part that the frontends will share.
+
+use alloc::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+pub mod mixer;
+
+/// The latency can't adapt below or above these bounds.
+const MIN_LATENCY_MS: u32 = 10;
+const MAX_LATENCY_MS: u32 = 250;
+/// How much the latency changes on each adaptation.
+const ADAPT_STEP_MS: u32 = 10;
+/// How many callbacks need to go by without an underrun before the adaptive mode
+/// tries lowering the latency again.
+const STABLE_CALLBACKS: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    /// How far ahead of the audio device the emulator tries to stay.
+    pub target_latency_ms: u32,
+    /// Raise the latency when the device runs dry, and slowly lower it again while
+    /// playback is stable. Platforms with bursty audio callbacks need a lot more
+    /// buffering than others, so this finds the lowest latency that works.
+    pub adaptive: bool,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            sample_rate: 44_100,
+            target_latency_ms: 50,
+            adaptive: false,
+        }
+    }
+}
+
+/// Counters for how well the audio buffer is keeping up.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AudioTelemetry {
+    /// The device asked for samples that weren't there yet, and got silence.
+    pub underruns: u64,
+    /// The emulator produced samples faster than the device played them, and the
+    /// oldest ones were dropped.
+    pub overruns: u64,
+    pub silent_samples: u64,
+    pub dropped_samples: u64,
+    /// The current target, which changes in the adaptive mode.
+    pub latency_ms: u32,
+}
+
+/// Sits between the emulator, which pushes samples as it runs, and the platform's
+/// audio callback, which pulls them out at its own pace.
+pub struct AudioBuffer {
+    config: AudioConfig,
+    samples: VecDeque<f32>,
+    telemetry: AudioTelemetry,
+    callbacks_since_underrun: u32,
+}
+
+impl AudioBuffer {
+    pub fn new(config: AudioConfig) -> AudioBuffer {
+        AudioBuffer {
+            config,
+            samples: VecDeque::new(),
+            telemetry: AudioTelemetry {
+                latency_ms: config.target_latency_ms,
+                ..AudioTelemetry::default()
+            },
+            callbacks_since_underrun: 0,
+        }
+    }
+
+    pub fn telemetry(&self) -> AudioTelemetry {
+        self.telemetry
+    }
+
+    pub fn buffered_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The number of samples that cover the target latency.
+    pub fn target_samples(&self) -> usize {
+        (self.config.sample_rate as u64 * self.telemetry.latency_ms as u64 / 1000)
+            as usize
+    }
+
+    /// The buffer holds up to twice the target before it drops samples.
+    fn capacity(&self) -> usize {
+        self.target_samples() * 2
+    }
+
+    /// Add samples from the emulator.
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        self.samples.extend(samples);
+        let capacity = self.capacity();
+        if self.samples.len() > capacity {
+            let dropped = self.samples.len() - capacity;
+            self.samples.drain(..dropped);
+            self.telemetry.overruns += 1;
+            self.telemetry.dropped_samples += dropped as u64;
+        }
+    }
+
+    /// Fill the device's buffer, this is called from the audio callback. Anything
+    /// that isn't available yet is filled with silence.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let available = out.len().min(self.samples.len());
+        for (sample, value) in out.iter_mut().zip(self.samples.drain(..available)) {
+            *sample = value;
+        }
+
+        let missing = out.len() - available;
+        if missing == 0 {
+            self.callbacks_since_underrun += 1;
+            if self.config.adaptive && self.callbacks_since_underrun >= STABLE_CALLBACKS {
+                self.callbacks_since_underrun = 0;
+                self.telemetry.latency_ms =
+                    (self.telemetry.latency_ms.saturating_sub(ADAPT_STEP_MS))
+                        .max(MIN_LATENCY_MS);
+            }
+            return;
+        }
+
+        for sample in out[available..].iter_mut() {
+            *sample = 0.0;
+        }
+        self.telemetry.underruns += 1;
+        self.telemetry.silent_samples += missing as u64;
+        self.callbacks_since_underrun = 0;
+        if self.config.adaptive {
+            self.telemetry.latency_ms =
+                (self.telemetry.latency_ms + ADAPT_STEP_MS).min(MAX_LATENCY_MS);
+        }
+    }
+}
+
+/// Hash the samples for golden audio tests. This uses the 32 bit FNV-1a hash over the
+/// bits of the samples, so that the value is stable across platforms and Rust
+/// versions.
+pub fn samples_hash(samples: &[f32]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for sample in samples {
+        for byte in sample.to_bits().to_le_bytes().iter() {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+    hash
+}
+
+/// Write the samples as a mono 16 bit WAV file, so that a golden audio test that
+/// fails can be listened to.
+#[cfg(feature = "std")]
+pub fn write_wav(
+    out: &mut impl Write,
+    sample_rate: u32,
+    samples: &[f32],
+) -> io::Result<()> {
+    let data_size = samples.len() as u32 * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, 1 channel.
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    // The byte rate, then the bytes per sample and the bits per sample.
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(adaptive: bool) -> AudioConfig {
+        AudioConfig {
+            sample_rate: 1000,
+            target_latency_ms: 50,
+            adaptive,
+        }
+    }
+
+    #[test]
+    fn test_target_samples() {
+        let buffer = AudioBuffer::new(config(false));
+        assert_eq!(buffer.target_samples(), 50);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut buffer = AudioBuffer::new(config(false));
+        buffer.push_samples(&[0.1, 0.2, 0.3]);
+        let mut out = [1.0; 2];
+        buffer.fill(&mut out);
+        assert_eq!(out, [0.1, 0.2]);
+        assert_eq!(buffer.buffered_samples(), 1);
+        assert_eq!(buffer.telemetry().underruns, 0);
+    }
+
+    #[test]
+    fn test_underrun() {
+        let mut buffer = AudioBuffer::new(config(false));
+        buffer.push_samples(&[0.1]);
+        let mut out = [1.0; 3];
+        buffer.fill(&mut out);
+        assert_eq!(out, [0.1, 0.0, 0.0]);
+        let telemetry = buffer.telemetry();
+        assert_eq!(telemetry.underruns, 1);
+        assert_eq!(telemetry.silent_samples, 2);
+        // The latency doesn't change without the adaptive mode.
+        assert_eq!(telemetry.latency_ms, 50);
+    }
+
+    #[test]
+    fn test_overrun() {
+        let mut buffer = AudioBuffer::new(config(false));
+        let samples: Vec<f32> = (0..120).map(|i| i as f32).collect();
+        buffer.push_samples(&samples);
+        assert_eq!(buffer.buffered_samples(), 100);
+        let telemetry = buffer.telemetry();
+        assert_eq!(telemetry.overruns, 1);
+        assert_eq!(telemetry.dropped_samples, 20);
+        // The oldest samples were dropped.
+        let mut out = [0.0; 1];
+        buffer.fill(&mut out);
+        assert_eq!(out, [20.0]);
+    }
+
+    #[test]
+    fn test_adaptive_latency() {
+        let mut buffer = AudioBuffer::new(config(true));
+        let mut out = [0.0; 10];
+        buffer.fill(&mut out);
+        assert_eq!(buffer.telemetry().latency_ms, 60);
+
+        for _ in 0..STABLE_CALLBACKS {
+            buffer.push_samples(&[0.0; 10]);
+            buffer.fill(&mut out);
+        }
+        assert_eq!(buffer.telemetry().latency_ms, 50);
+        assert_eq!(buffer.telemetry().underruns, 1);
+    }
+
+    #[test]
+    fn test_adaptive_latency_bounds() {
+        let mut buffer = AudioBuffer::new(config(true));
+        let mut out = [0.0; 10];
+        for _ in 0..100 {
+            buffer.fill(&mut out);
+        }
+        assert_eq!(buffer.telemetry().latency_ms, MAX_LATENCY_MS);
+    }
+
+    #[test]
+    fn test_samples_hash() {
+        assert_eq!(samples_hash(&[]), 0x811c_9dc5);
+        assert_ne!(samples_hash(&[0.0]), samples_hash(&[0.0, 0.0]));
+        assert_ne!(samples_hash(&[0.5]), samples_hash(&[-0.5]));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_wav() {
+        let mut wav = vec![];
+        write_wav(&mut wav, 1000, &[0.0, 1.0, -2.0]).unwrap();
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &1000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        // The samples are clamped.
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// The chips that can make sound. Famicom cartridges could add their own sound
+/// chips, which were mixed with the APU through the cartridge connector. The NES
+/// dropped those pins, so the expansion audio only plays on a Famicom.
+/// https://wiki.nesdev.com/w/index.php/Expansion_audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Chip {
+    Apu,
+    Vrc6,
+    Vrc7,
+    Fds,
+    Mmc5,
+    Namco163,
+    Sunsoft5b,
+}
+
+impl Chip {
+    /// How loud the chip is at full scale, compared to an APU pulse channel at full
+    /// volume. These are rough levels from Famicom recordings, and they vary from
+    /// console to console and from cart to cart, so they can be overridden with
+    /// Mixer::set_chip_gain.
+    pub fn default_gain(self) -> f32 {
+        match self {
+            Chip::Apu => 1.0,
+            // The MMC5 has copies of the APU's pulse channels.
+            Chip::Mmc5 => 1.0,
+            Chip::Vrc6 => 1.0,
+            Chip::Vrc7 => 1.5,
+            Chip::Fds => 2.4,
+            // The N163 time-multiplexes its channels, so it's loud and gets quieter
+            // per channel as more are enabled.
+            Chip::Namco163 => 4.0,
+            Chip::Sunsoft5b => 1.0,
+        }
+    }
+}
+
+/// A handle for a channel that was registered with the mixer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelId(usize);
+
+struct Channel {
+    chip: Chip,
+    name: String,
+    /// The last output of the channel, from -1.0 to 1.0 of the chip's full scale.
+    level: f32,
+}
+
+/// Mixes the APU with any expansion audio. Each chip registers its channels, then
+/// sets their levels as it runs, and the mixer scales them by the chip's gain. This
+/// keeps the relative levels in one place, instead of each mapper scaling its samples
+/// to fit into the APU's output.
+pub struct Mixer {
+    channels: Vec<Channel>,
+    /// The user's overrides of Chip::default_gain.
+    chip_gains: BTreeMap<Chip, f32>,
+    master_gain: f32,
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        Mixer {
+            channels: Vec::new(),
+            chip_gains: BTreeMap::new(),
+            master_gain: 1.0,
+        }
+    }
+
+    pub fn register(&mut self, chip: Chip, name: &str) -> ChannelId {
+        self.channels.push(Channel {
+            chip,
+            name: name.to_string(),
+            level: 0.0,
+        });
+        ChannelId(self.channels.len() - 1)
+    }
+
+    /// The chips and channel names, in the order they were registered.
+    pub fn channels(&self) -> impl Iterator<Item = (Chip, &str)> {
+        self.channels
+            .iter()
+            .map(|channel| (channel.chip, channel.name.as_str()))
+    }
+
+    pub fn set_level(&mut self, channel: ChannelId, level: f32) {
+        self.channels[channel.0].level = level;
+    }
+
+    pub fn chip_gain(&self, chip: Chip) -> f32 {
+        self.chip_gains
+            .get(&chip)
+            .copied()
+            .unwrap_or_else(|| chip.default_gain())
+    }
+
+    /// Override the chip's level, e.g. 0.0 to mute it.
+    pub fn set_chip_gain(&mut self, chip: Chip, gain: f32) {
+        self.chip_gains.insert(chip, gain);
+    }
+
+    pub fn reset_chip_gain(&mut self, chip: Chip) {
+        self.chip_gains.remove(&chip);
+    }
+
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.master_gain = gain;
+    }
+
+    /// The mix of the current channel levels, clipped to -1.0 to 1.0.
+    pub fn output(&self) -> f32 {
+        // Summing floats starts from -0.0, which would change the silent samples.
+        let mix = self.channels.iter().fold(0.0, |mix, channel| {
+            mix + channel.level * self.chip_gain(channel.chip)
+        });
+        (mix * self.master_gain).clamp(-1.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chip_gains() {
+        let mut mixer = Mixer::new();
+        let pulse = mixer.register(Chip::Apu, "pulse 1");
+        let wave = mixer.register(Chip::Fds, "wave");
+        mixer.set_master_gain(0.1);
+        mixer.set_level(pulse, 1.0);
+        mixer.set_level(wave, 1.0);
+        assert!((mixer.output() - 0.34).abs() < 1e-6);
+
+        mixer.set_chip_gain(Chip::Fds, 0.0);
+        assert!((mixer.output() - 0.1).abs() < 1e-6);
+        mixer.reset_chip_gain(Chip::Fds);
+        assert_eq!(mixer.chip_gain(Chip::Fds), Chip::Fds.default_gain());
+
+        assert_eq!(
+            mixer.channels().collect::<Vec<_>>(),
+            vec![(Chip::Apu, "pulse 1"), (Chip::Fds, "wave")]
+        );
+    }
+
+    #[test]
+    fn test_clipping() {
+        let mut mixer = Mixer::new();
+        assert_eq!(mixer.output(), 0.0);
+        let channel = mixer.register(Chip::Namco163, "channel 1");
+        mixer.set_level(channel, -1.0);
+        assert_eq!(mixer.output(), -1.0);
+    }
+}
@@ -0,0 +1,189 @@
+//! Some cartridges have a battery that keeps their PRG RAM at $6000-$7FFF while the
+//! power is off, which is where the games keep their saves. The RAM is kept in a .sav
+//! file next to the ROM, which is loaded when the game starts, and saved when it
+//! stops. The file is the raw RAM, like the other emulators write, so the saves can be
+//! moved between them. See SaveConfig for keeping the saves somewhere else, and for
+//! saving while the game runs.
+use crate::bus::Bus;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The .sav file that goes with a ROM, e.g. "games/zelda.nes" saves to
+/// "games/zelda.sav".
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// How the battery-backed RAM is kept on disk, see Emulator::set_save_config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveConfig {
+    /// Where the .sav files go. None keeps them next to the ROMs.
+    pub directory: Option<PathBuf>,
+    /// How often the RAM is written out while the game runs, so that a crash doesn't
+    /// lose the save. This is in the emulated time, so that runs are repeatable, and
+    /// the RAM is only written when it changed. None only saves when the game stops.
+    pub autosave_interval: Option<Duration>,
+    /// Never load or save, like for the headless test runs that should start fresh.
+    pub disabled: bool,
+}
+
+impl Default for SaveConfig {
+    fn default() -> Self {
+        SaveConfig {
+            directory: None,
+            autosave_interval: Some(Duration::from_secs(30)),
+            disabled: false,
+        }
+    }
+}
+
+impl SaveConfig {
+    /// The .sav file for a ROM, in the save directory if there is one.
+    pub fn save_path(&self, rom_path: &Path) -> PathBuf {
+        let path = save_path(rom_path);
+        match (&self.directory, path.file_name()) {
+            (Some(directory), Some(file_name)) => directory.join(file_name),
+            _ => path,
+        }
+    }
+}
+
+/// Load the .sav file into the cartridge's RAM. Returns false when the cartridge
+/// doesn't have a battery, or the game hasn't been saved yet.
+pub fn load(bus: &mut Bus, path: &Path) -> io::Result<bool> {
+    let size = match bus.battery_ram() {
+        Some(ram) => ram.len(),
+        None => return Ok(false),
+    };
+    let ram = match fs::read(path) {
+        Ok(ram) => ram,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    if ram.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The save file is {} bytes, but the cartridge has {} bytes of RAM.",
+                ram.len(),
+                size
+            ),
+        ));
+    }
+    bus.load_battery_ram(&ram);
+    Ok(true)
+}
+
+/// Save the cartridge's RAM to the .sav file. Returns false when the cartridge doesn't
+/// have a battery. The RAM is written to a temporary file first, so that a failed
+/// write doesn't lose the last save. The directory is created if it's missing.
+pub fn save(bus: &Bus, path: &Path) -> io::Result<bool> {
+    let ram = match bus.battery_ram() {
+        Some(ram) => ram,
+        None => return Ok(false),
+    };
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let temp_path = path.with_extension("sav.tmp");
+    fs::write(&temp_path, ram)?;
+    fs::rename(&temp_path, path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::SharedBus;
+    use crate::mappers::{Mapper, SimpleProgram};
+
+    /// A cartridge with 8KB of battery-backed RAM.
+    struct Battery {
+        ram: Vec<u8>,
+    }
+
+    impl Mapper for Battery {
+        fn read_cpu(&self, address: u16) -> Option<u8> {
+            match address {
+                0x6000..=0x7fff => Some(self.ram[address as usize - 0x6000]),
+                _ => None,
+            }
+        }
+
+        fn write_cpu(&mut self, address: u16, value: u8) -> bool {
+            match address {
+                0x6000..=0x7fff => {
+                    self.ram[address as usize - 0x6000] = value;
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        fn battery_ram(&self) -> Option<&[u8]> {
+            Some(&self.ram)
+        }
+
+        fn load_battery_ram(&mut self, ram: &[u8]) {
+            self.ram.copy_from_slice(ram);
+        }
+    }
+
+    fn battery_bus() -> SharedBus {
+        Bus::new_shared_bus(Box::new(Battery {
+            ram: vec![0; 0x2000],
+        }))
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(
+            save_path(Path::new("games/zelda.nes")),
+            Path::new("games/zelda.sav")
+        );
+        let config = SaveConfig {
+            directory: Some("saves".into()),
+            ..SaveConfig::default()
+        };
+        assert_eq!(
+            config.save_path(Path::new("games/zelda.nes")),
+            Path::new("saves/zelda.sav")
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir =
+            std::env::temp_dir().join(format!("nes-battery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = save_path(&dir.join("game.nes"));
+
+        let bus = battery_bus();
+        let mut bus = bus.borrow_mut();
+        // Nothing has been saved yet.
+        assert!(!load(&mut bus, &path).unwrap());
+        bus.set_u8(0x6000, 0x12);
+        bus.set_u8(0x7fff, 0x34);
+        assert!(save(&bus, &path).unwrap());
+
+        let bus = battery_bus();
+        let mut bus = bus.borrow_mut();
+        assert!(load(&mut bus, &path).unwrap());
+        assert_eq!(bus.read_u8(0x6000), 0x12);
+        assert_eq!(bus.read_u8(0x7fff), 0x34);
+
+        // A save from some other cartridge is refused.
+        fs::write(&path, [0; 16]).unwrap();
+        let error = load(&mut battery_bus().borrow_mut(), &path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Without a battery, there's nothing to save.
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        assert!(!save(&bus, &path).unwrap());
+        assert!(!load(&mut bus, &path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
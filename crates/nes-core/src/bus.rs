@@ -0,0 +1,1045 @@
+use crate::audio::mixer::Mixer;
+use crate::mappers::{CustomMemory, Mapper};
+use crate::memory_map;
+use crate::ppu::{Accuracy, PpuRegister, StubPpuRegisters};
+use crate::prelude::*;
+use crate::rom::Mirroring;
+use crate::savestate::{write_bytes, Reader};
+use crate::tile_stats::TileLayer;
+use crate::uninit_ram::{UninitRamTrap, UninitRead};
+use crate::watchpoints::{AccessKind, WatchpointHit, WatchpointId, Watchpoints};
+
+use super::constants::memory_range;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use core::any::Any;
+use core::cell::{Cell, RefCell};
+use core::ops::RangeInclusive;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const OAM_DMA: u16 = 0x4014;
+const PPUCTRL_NMI: u8 = 0b1000_0000;
+
+/**
+ * The bus contains the actual memory used by the NES. This can
+ * be referenced and used across modules. In order to allow
+ * this to be a shared mutable piece of memory, wrap it in a
+ * reference counted refcell. This incurs realtime costs to use
+ * the data, but creates a direct way to have shared memory in
+ * a way that Rust can compile.
+ */
+pub type SharedBus = Rc<RefCell<Bus>>;
+
+/// The instruction that the CPU is about to run, see BusObserver::record_instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionStart {
+    pub pc: u16,
+    /// The CPU cycle that the instruction starts on.
+    pub cycle: u64,
+    /// The size of the opcode and its operands, or 0 when the CPU is taking an
+    /// interrupt, or waiting for one.
+    pub size: u16,
+}
+
+/// A read or a write that the CPU made, see BusObserver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObservedAccess {
+    pub address: u16,
+    pub value: u8,
+    /// The address with the RAM mirrors folded onto the 2KB of RAM. Only the NES has
+    /// the mirrors, so on other memory maps this is the address.
+    pub folded: u16,
+    /// The access went to the NES's RAM, rather than the cartridge or a register. The
+    /// dummy reads don't count, as the program never sees the value.
+    pub ram: bool,
+    /// The memory-mapped register that a write went to, when the cartridge didn't
+    /// take it, see memory_map::REGISTERS.
+    pub register: Option<u16>,
+}
+
+/// A tool that watches the program run, like the RamAudit or the BusTrace. The
+/// observers are kept in one list on the bus, see Bus::set_observer. The CPU tells
+/// them about each instruction before it runs it, and the bus tells them about every
+/// read and write that the CPU makes. The peeks from tools aren't observed.
+pub trait BusObserver: Any {
+    fn record_instruction(&mut self, _instruction: &InstructionStart) {}
+
+    /// Reads come through the bus without mutable access, so an observer that counts
+    /// them needs to use a cell.
+    fn record_read(&self, _access: &ObservedAccess) {}
+
+    /// Called for every write, even the ones that were dropped.
+    fn record_write(&mut self, _access: &ObservedAccess) {}
+
+    /// Return true to drop the write, so that nothing on the bus sees it.
+    fn check_write(&mut self, _access: &ObservedAccess) -> bool {
+        false
+    }
+}
+
+/// A piece of hardware with registers on the CPU bus, like the PPU, the APU, or the
+/// controllers. Each device is attached to the range of addresses it owns, see
+/// Bus::attach_device, and the bus routes the accesses in that range to it. The
+/// addresses are passed through as is, so a device handles its own mirrors.
+pub trait BusDevice: Any {
+    /// The name to use in errors and debuggers.
+    fn name(&self) -> &str;
+
+    /// Reads come through the bus without mutable access, so registers that change
+    /// when they are read need to use a cell.
+    fn read(&self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Read without any side effects, for debuggers and tools. Devices whose reads
+    /// have side effects can return None.
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(self.read(address))
+    }
+
+    /// The data lines that the device drives when it's read. The others aren't
+    /// connected, and read as the open bus.
+    fn driven_bits(&self, _address: u16) -> u8 {
+        0xff
+    }
+
+    /// The device's registers and anything else that changes as the program runs,
+    /// see Bus::save_state.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state from save_state. A state that doesn't fit the device is an
+    /// error.
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The vblank signal, see Bus::set_vblank.
+    fn vblank(&mut self, _vblank: bool) {}
+
+    /// See Bus::set_accuracy.
+    fn set_accuracy(&mut self, _accuracy: Accuracy) {}
+}
+
+/// The value left on the data bus by the last read or write. Nothing drives the bus
+/// when an unmapped address is read, so the CPU sees this instead, which is often the
+/// high byte of the address, as that was the last thing read. Some games and test
+/// ROMs depend on it.
+/// https://wiki.nesdev.com/w/index.php/Open_bus_behavior
+struct OpenBus {
+    enabled: bool,
+    value: Cell<u8>,
+    /// The CPU cycles since the bus was last driven.
+    idle_cycles: Cell<u64>,
+    /// On the hardware the charge leaks away, and after this many cycles the bus
+    /// reads as 0.
+    decay_cycles: Option<u64>,
+}
+
+impl OpenBus {
+    fn drive(&self, value: u8) {
+        self.value.set(value);
+        self.idle_cycles.set(0);
+    }
+
+    fn value(&self) -> u8 {
+        match self.decay_cycles {
+            Some(decay_cycles) if self.idle_cycles.get() >= decay_cycles => 0,
+            _ => self.value.get(),
+        }
+    }
+}
+
+struct AttachedDevice {
+    range: RangeInclusive<u16>,
+    device: Box<dyn BusDevice>,
+}
+
+pub struct Bus {
+    // Includes the zero page, stack, and ram. There are only 2KB of it, and the
+    // upper address lines aren't connected, so it repeats every $0800 bytes.
+    //
+    // $2000 |-------------------------|-------------------------| $2000
+    //       |   Mirrors $0000 - $07FF |                         |
+    // $0800 |-------------------------|                         |
+    //       |   General RAM           |                         |
+    // $0200 |-------------------------|           RAM           |
+    //       |   Stack                 |                         |
+    // $0100 |-------------------------|                         |
+    //       |   Zero Page             |                         |
+    // $0000 |-------------------------|-------------------------| $0000
+    ram: [u8; memory_range::RAM_ACTUAL.size() as usize],
+    cartridge: Box<dyn Mapper>,
+    /// The page of the last OAM DMA, until the CPU picks it up to stall.
+    pending_oam_dma: Option<u8>,
+    /// The last value written to each memory-mapped register, keyed by the register's
+    /// address in memory_map::REGISTERS. Nothing is listening to most of them yet.
+    register_writes: BTreeMap<u16, u8>,
+    /// At most one of each type, see Bus::set_observer.
+    observers: Vec<Box<dyn BusObserver>>,
+    /// On the NES, the PPU's registers are the first device, see StubPpuRegisters.
+    devices: Vec<AttachedDevice>,
+    accuracy: Accuracy,
+    /// The time spent in the mapper's clocks, when profiling, see profiler::Profiler.
+    mapper_time: Option<Duration>,
+    open_bus: OpenBus,
+    watchpoints: Watchpoints,
+    /// The RAM mirrors, and the PPU and APU registers. Only the NES has these, see
+    /// MemoryMap.
+    nes_io: bool,
+    /// PPUSTATUS was read since the last take_ppu_status_read.
+    ppu_status_read: Cell<bool>,
+}
+
+/// How the address space is laid out, which is picked when the bus is built, see
+/// Bus::new_with_memory_map.
+pub enum MemoryMap {
+    /// The NES's 2KB of RAM and its registers, with the cartridge's mapper handling
+    /// everything else.
+    Nes(Box<dyn Mapper>),
+    /// Only the regions of the memory, for other 6502 machines. The NES's RAM and
+    /// registers aren't there, so the rest of the addresses read as the open bus.
+    Custom(CustomMemory),
+    /// Memory that decides which addresses it claims, like FlatMemory, without the
+    /// NES's RAM or registers.
+    Flat(Box<dyn Mapper>),
+}
+
+impl MemoryMap {
+    /// 64KB of RAM, and nothing else, which is all that test programs like Klaus
+    /// Dormann's functional tests need.
+    pub fn flat_ram() -> MemoryMap {
+        MemoryMap::Custom(CustomMemory::new().ram(0x0000..=0xffff))
+    }
+}
+
+/// A copy of everything on the bus that changes as the program runs, see
+/// Bus::save_state. With the "serde" feature, this can be serialized along with the
+/// CPU for a complete snapshot of the machine, see Checkpoint. It can only be loaded
+/// into a bus with the same cartridge and devices.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BusState {
+    ram: Box<[u8]>,
+    pending_oam_dma: Option<u8>,
+    /// Sorted by the address. Formats like TOML only have string keys, so this isn't
+    /// kept as a map.
+    register_writes: Vec<(u16, u8)>,
+    open_bus: u8,
+    cartridge: Vec<u8>,
+    /// The state of each attached device, in the order they were attached.
+    devices: Vec<Vec<u8>>,
+}
+
+impl BusState {
+    /// Write the state into a savestate chunk, see Checkpoint::write_chunk.
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        write_bytes(bytes, &self.ram);
+        match self.pending_oam_dma {
+            Some(page) => bytes.extend_from_slice(&[1, page]),
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&(self.register_writes.len() as u16).to_le_bytes());
+        for &(address, value) in &self.register_writes {
+            bytes.extend_from_slice(&address.to_le_bytes());
+            bytes.push(value);
+        }
+        bytes.push(self.open_bus);
+        write_bytes(bytes, &self.cartridge);
+        bytes.extend_from_slice(&(self.devices.len() as u16).to_le_bytes());
+        for device in &self.devices {
+            write_bytes(bytes, device);
+        }
+    }
+
+    pub(crate) fn read(reader: &mut Reader) -> Result<BusState, String> {
+        let ram = reader.bytes()?.into_boxed_slice();
+        let pending_oam_dma = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.u8()?),
+        };
+        let mut register_writes = Vec::new();
+        for _ in 0..reader.u16()? {
+            let address = reader.u16()?;
+            register_writes.push((address, reader.u8()?));
+        }
+        let open_bus = reader.u8()?;
+        let cartridge = reader.bytes()?;
+        let mut devices = Vec::new();
+        for _ in 0..reader.u16()? {
+            devices.push(reader.bytes()?);
+        }
+        Ok(BusState {
+            ram,
+            pending_oam_dma,
+            register_writes,
+            open_bus,
+            cartridge,
+            devices,
+        })
+    }
+}
+
+impl Bus {
+    pub fn new_shared_bus(cartridge: Box<dyn Mapper>) -> Rc<RefCell<Bus>> {
+        Bus::new_with_memory_map(MemoryMap::Nes(cartridge))
+    }
+
+    pub fn new_with_memory_map(memory_map: MemoryMap) -> Rc<RefCell<Bus>> {
+        let (cartridge, nes_io): (Box<dyn Mapper>, bool) = match memory_map {
+            MemoryMap::Nes(cartridge) => (cartridge, true),
+            MemoryMap::Custom(memory) => (Box::new(memory), false),
+            MemoryMap::Flat(memory) => (memory, false),
+        };
+        let mut bus = Bus {
+            // Little endian memory store, 2 kilobytes in size.
+            ram: [0; memory_range::RAM_ACTUAL.size() as usize],
+            cartridge,
+            pending_oam_dma: None,
+            register_writes: BTreeMap::new(),
+            observers: Vec::new(),
+            devices: Vec::new(),
+            accuracy: Accuracy::default(),
+            mapper_time: None,
+            open_bus: OpenBus {
+                enabled: true,
+                value: Cell::new(0),
+                idle_cycles: Cell::new(0),
+                decay_cycles: None,
+            },
+            watchpoints: Watchpoints::new(),
+            nes_io,
+            ppu_status_read: Cell::new(false),
+        };
+        if nes_io {
+            let range = memory_range::PPU_ACTUAL.start..=memory_range::PPU.end - 1;
+            bus.attach_device(range, Box::new(StubPpuRegisters::new()))
+                .expect("The bus starts out without any devices.");
+        }
+        Rc::new(RefCell::new(bus))
+    }
+
+    /// Map an address in $0000-$1FFF onto the 2KB of RAM. Nothing else is RAM, so the
+    /// other addresses panic until they are mapped to something.
+    fn map_ram_address(&self, address: u16) -> usize {
+        // $0000-$07FF  $0800  2KB internal RAM
+        // $0800-$0FFF  $0800  Mirrors of $0000-$07FF
+        // $1000-$17FF  $0800
+        // $1800-$1FFF  $0800
+        assert!(
+            address < memory_range::RAM.end,
+            "Nothing is mapped to ${:04x} yet.",
+            address
+        );
+        (memory_range::RAM_ACTUAL.mask() & address) as usize
+    }
+
+    /// The PPU register that an address in $2000-$3FFF maps onto, 0-7.
+    fn map_ppu_register(address: u16) -> Option<u8> {
+        // $2000-$2007  $0008  PPU registers
+        // $2008-$3FFF  $1FF8  Mirrors of $2000-$2007, repeating every 8 bytes
+        if (memory_range::PPU_ACTUAL.start..memory_range::PPU.end).contains(&address) {
+            Some((address % memory_range::PPU_ACTUAL.size()) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Signal the start or the end of vblank to the devices and the cartridge, for
+    /// PPUs that don't keep their own timing, see Cpu6502::schedule_vblank.
+    pub fn set_vblank(&mut self, vblank: bool) {
+        for attached in &mut self.devices {
+            attached.device.vblank(vblank);
+        }
+        self.clock_mapper(|cartridge| cartridge.vblank(vblank));
+    }
+
+    /// Whether PPUSTATUS was read since the last call, see Cpu6502::schedule_vblank.
+    /// Peeks don't count.
+    pub fn take_ppu_status_read(&self) -> bool {
+        self.ppu_status_read.replace(false)
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        self.accuracy
+    }
+
+    /// Choose which of the hardware's quirks to follow, see Accuracy. The devices are
+    /// told as well.
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        for attached in &mut self.devices {
+            attached.device.set_accuracy(accuracy);
+        }
+    }
+
+    /// Route the addresses to a device, which the bus then calls instead of treating
+    /// them as RAM. The cartridge still sees every access first, like on the
+    /// hardware. A range can only be owned by one device, so to replace the PPU's
+    /// registers, detach them first.
+    pub fn attach_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        mut device: Box<dyn BusDevice>,
+    ) -> Result<(), String> {
+        if let Some(attached) = self.devices.iter().find(|attached| {
+            attached.range.start() <= range.end() && range.start() <= attached.range.end()
+        }) {
+            return Err(format!(
+                "The {} at ${:04x}-${:04x} overlaps the {} at ${:04x}-${:04x}.",
+                device.name(),
+                range.start(),
+                range.end(),
+                attached.device.name(),
+                attached.range.start(),
+                attached.range.end()
+            ));
+        }
+        device.set_accuracy(self.accuracy);
+        self.devices.push(AttachedDevice { range, device });
+        Ok(())
+    }
+
+    /// Detach the device that owns an address, and hand it back.
+    pub fn detach_device(&mut self, address: u16) -> Option<Box<dyn BusDevice>> {
+        let index = self
+            .devices
+            .iter()
+            .position(|attached| attached.range.contains(&address))?;
+        Some(self.devices.remove(index).device)
+    }
+
+    /// The names and ranges of the attached devices, in the order they were attached.
+    pub fn devices(&self) -> impl Iterator<Item = (&str, RangeInclusive<u16>)> {
+        self.devices
+            .iter()
+            .map(|attached| (attached.device.name(), attached.range.clone()))
+    }
+
+    /// The device that owns an address, if it's a T, to look at its state, e.g. the
+    /// OAM of the StubPpuRegisters at $2000.
+    pub fn device_at<T: BusDevice>(&self, address: u16) -> Option<&T> {
+        let device: &dyn Any = self.device(address)?;
+        device.downcast_ref()
+    }
+
+    pub fn device_at_mut<T: BusDevice>(&mut self, address: u16) -> Option<&mut T> {
+        let device: &mut dyn Any = self.device_mut(address)?.as_mut();
+        device.downcast_mut()
+    }
+
+    fn device(&self, address: u16) -> Option<&dyn BusDevice> {
+        self.devices
+            .iter()
+            .find(|attached| attached.range.contains(&address))
+            .map(|attached| attached.device.as_ref())
+    }
+
+    fn device_mut(&mut self, address: u16) -> Option<&mut Box<dyn BusDevice>> {
+        self.devices
+            .iter_mut()
+            .find(|attached| attached.range.contains(&address))
+            .map(|attached| &mut attached.device)
+    }
+
+    /// Turn the open bus off to panic on the reads and writes of unmapped addresses,
+    /// which catches stray pointers while debugging. It's on by default.
+    pub fn set_open_bus(&mut self, enabled: bool) {
+        self.open_bus.enabled = enabled;
+    }
+
+    /// Let the open bus fade to 0 after some CPU cycles, or never with None, which is
+    /// the default.
+    pub fn set_open_bus_decay(&mut self, decay_cycles: Option<u64>) {
+        self.open_bus.decay_cycles = decay_cycles;
+    }
+
+    /// What a read of an unmapped address would return right now.
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus.value()
+    }
+
+    pub fn read_u8(&self, address: u16) -> u8 {
+        self.read_u8_recording(address, true)
+    }
+
+    /// Read a byte, with all of the side effects. The RAM reads are only passed on to
+    /// the observers as RAM when the program gets the value.
+    fn read_u8_recording(&self, address: u16, record_ram: bool) -> u8 {
+        let (value, ram) = match self.read_mapped_u8(address) {
+            Some((value, ram)) => (value, ram),
+            None => (self.open_bus.value(), false),
+        };
+        self.open_bus.drive(value);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(address, value, AccessKind::Read);
+        }
+        if !self.observers.is_empty() {
+            let access = ObservedAccess {
+                address,
+                value,
+                folded: self.fold_ram_mirrors(address),
+                ram: ram && record_ram,
+                register: None,
+            };
+            for observer in &self.observers {
+                observer.record_read(&access);
+            }
+        }
+        value
+    }
+
+    /// The value, and whether it came from the RAM.
+    fn read_mapped_u8(&self, address: u16) -> Option<(u8, bool)> {
+        if let Some(value) = self.cartridge.read_cpu(address) {
+            return Some((value, false));
+        }
+        if let Some(device) = self.device(address) {
+            if self.nes_io
+                && Bus::map_ppu_register(address) == Some(PpuRegister::Status.index())
+            {
+                self.ppu_status_read.set(true);
+            }
+            let value = self.with_open_bus(device, address, device.read(address));
+            return Some((value, false));
+        }
+        if !self.nes_io {
+            self.check_unmapped(address);
+            return None;
+        }
+        if !self.maps_to_ram(address) {
+            return None;
+        }
+        Some((self.ram[self.map_ram_address(address)], true))
+    }
+
+    /// Fill in the bits that the device doesn't drive from the open bus.
+    fn with_open_bus(&self, device: &dyn BusDevice, address: u16, value: u8) -> u8 {
+        let driven = device.driven_bits(address);
+        value & driven | self.open_bus.value() & !driven
+    }
+
+    /// The NES's unmapped addresses panic in map_ram_address, and this does the same
+    /// for the other memory maps.
+    fn check_unmapped(&self, address: u16) {
+        assert!(
+            self.open_bus.enabled,
+            "Nothing is mapped to ${:04x}.",
+            address
+        );
+    }
+
+    /// Should the access go to the RAM? With the open bus off, the unmapped addresses
+    /// go there too, so that map_ram_address panics on them.
+    fn maps_to_ram(&self, address: u16) -> bool {
+        address < memory_range::RAM.end || !self.open_bus.enabled
+    }
+
+    /// Read a byte, or None if nothing is mapped to the address yet. Only the RAM, the
+    /// cartridge, and the attached devices, like the PPU registers, are mapped so far.
+    /// Devices are peeked, so that this doesn't have side effects.
+    pub fn try_read_u8(&self, address: u16) -> Option<u8> {
+        if let Some(value) = self.cartridge.read_cpu(address) {
+            return Some(value);
+        }
+        if let Some(device) = self.device(address) {
+            return device
+                .peek(address)
+                .map(|value| self.with_open_bus(device, address, value));
+        }
+        if !self.nes_io {
+            return None;
+        }
+        if address < memory_range::RAM.end {
+            return Some(self.ram[self.map_ram_address(address)]);
+        }
+        None
+    }
+
+    /// Read a byte for a debugger, without any side effects. Devices are peeked, the
+    /// open bus and the watchpoints are left alone, and the observers don't see it.
+    /// The unmapped addresses read as the open bus, like they would for the CPU.
+    pub fn peek_u8(&self, address: u16) -> u8 {
+        self.try_read_u8(address)
+            .unwrap_or_else(|| self.open_bus.value())
+    }
+
+    /// Peek a little endian word. Unlike read_u16, this doesn't wrap around the page,
+    /// as it's for looking at data rather than emulating the CPU's pointers.
+    pub fn peek_u16(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.peek_u8(address), self.peek_u8(address.wrapping_add(1))])
+    }
+
+    /// The CPU makes reads that it throws away. They are real reads, so the registers
+    /// that react to being read do, like PPUSTATUS clearing the vblank flag, and they
+    /// are traced and watched like any other read. The RAM audit and the uninitialized
+    /// RAM trap leave them out, as the program never sees the value. With the open bus
+    /// off, the unmapped addresses are skipped rather than panicking.
+    pub fn dummy_read_u8(&self, address: u16) {
+        if !self.open_bus.enabled && self.try_read_u8(address).is_none() {
+            return;
+        }
+        self.read_u8_recording(address, false);
+    }
+
+    pub fn read_u16(&self, address: u16) -> u16 {
+        // Recreate the bug of reading a u16 over a page wraps it back
+        // to the beginning of the page.
+        let [address_low, address_high] = address.to_le_bytes();
+        let address2 = u16::from_le_bytes([address_low.wrapping_add(1), address_high]);
+        self.read_u16_disjoint(address, address2)
+    }
+
+    /**
+     * Words are little endian. Use rust's built-in features rather than relying on
+     * bit shifting.
+     *
+     * e.g.
+     * Little-Endian:  0x1000  00 10
+     *    Big-Endian:  0x1000  10 00
+     */
+    pub fn read_u16_disjoint(&self, address_a: u16, address_b: u16) -> u16 {
+        let a = self.read_u8(address_a);
+        let b = self.read_u8(address_b);
+        u16::from_le_bytes([a, b])
+    }
+
+    pub fn set_u8(&mut self, address: u16, value: u8) {
+        self.open_bus.drive(value);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(address, value, AccessKind::Write);
+        }
+        let mut access = ObservedAccess {
+            address,
+            value,
+            folded: self.fold_ram_mirrors(address),
+            ram: false,
+            register: None,
+        };
+        if self.observers.is_empty() {
+            self.write_mapped_u8(&mut access);
+            return;
+        }
+        // Every observer gets to check the write, even once one has dropped it.
+        let mut dropped = false;
+        for observer in &mut self.observers {
+            dropped |= observer.check_write(&access);
+        }
+        if !dropped {
+            self.write_mapped_u8(&mut access);
+        }
+        for observer in &mut self.observers {
+            observer.record_write(&access);
+        }
+    }
+
+    /// Route the write, and fill in where it went for the observers.
+    fn write_mapped_u8(&mut self, access: &mut ObservedAccess) {
+        let ObservedAccess { address, value, .. } = *access;
+        // The cartridge gets the first chance to handle the write, just like reads.
+        if self.cartridge.write_cpu(address, value) {
+            return;
+        }
+        // The register writes are still recorded when a device owns the register.
+        let register = memory_map::register_for(address).filter(|_| self.nes_io);
+        if let Some(register) = register {
+            self.register_writes.insert(register.address, value);
+            access.register = Some(register.address);
+            if register.address == OAM_DMA {
+                self.oam_dma(value);
+            }
+        }
+        if let Some(device) = self.device_mut(address) {
+            device.write(address, value);
+            return;
+        }
+        if !self.nes_io {
+            self.check_unmapped(address);
+            return;
+        }
+        if register.is_some() || !self.maps_to_ram(address) {
+            return;
+        }
+        access.ram = true;
+        self.ram[self.map_ram_address(address)] = value;
+    }
+
+    /// Start observing the program with a tool, like the RamAudit, see BusObserver.
+    /// There is only room for one of each type, so this replaces the one that was
+    /// there, and None removes it.
+    pub fn set_observer<T: BusObserver>(&mut self, observer: Option<T>) {
+        self.observers
+            .retain(|attached| !(attached.as_ref() as &dyn Any).is::<T>());
+        if let Some(observer) = observer {
+            self.observers.push(Box::new(observer));
+        }
+    }
+
+    /// The observer of a type, e.g. `bus.observer::<RamAudit>()`.
+    pub fn observer<T: BusObserver>(&self) -> Option<&T> {
+        self.observers
+            .iter()
+            .find_map(|observer| (observer.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    pub fn observer_mut<T: BusObserver>(&mut self) -> Option<&mut T> {
+        self.observers
+            .iter_mut()
+            .find_map(|observer| (observer.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    pub fn has_observers(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    /// The read of uninitialized RAM that should stop the run, if the trap is set to
+    /// break. Cpu6502::run takes it after every instruction.
+    pub fn take_uninit_break(&self) -> Option<UninitRead> {
+        self.observer::<UninitRamTrap>()
+            .and_then(|uninit_ram_trap| uninit_ram_trap.take_break())
+    }
+
+    /// Only the NES mirrors its RAM.
+    fn fold_ram_mirrors(&self, address: u16) -> u16 {
+        if self.nes_io && address < memory_range::RAM.end {
+            address & memory_range::RAM_ACTUAL.mask()
+        } else {
+            address
+        }
+    }
+
+    /// Tell the observers about the instruction that the CPU is about to run.
+    pub fn record_instruction(&mut self, instruction: &InstructionStart) {
+        for observer in &mut self.observers {
+            observer.record_instruction(instruction);
+        }
+    }
+
+    /// Watch a range of addresses, so that Cpu6502::run stops after an instruction
+    /// that reads or writes them, see Watchpoints. The mirrors aren't folded, so watch
+    /// each one that matters.
+    pub fn add_watchpoint(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: AccessKind,
+    ) -> WatchpointId {
+        self.watchpoints.add(range, kind)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        self.watchpoints.remove(id)
+    }
+
+    pub fn has_watchpoint_hits(&self) -> bool {
+        self.watchpoints.has_hits()
+    }
+
+    /// The watched accesses since the last call, in order. Cpu6502::run takes them
+    /// after every instruction, otherwise they pile up until they are taken.
+    pub fn take_watchpoint_hits(&self) -> Vec<WatchpointHit> {
+        self.watchpoints.take_hits()
+    }
+
+    /// The last value that was written to a register, following the mirrors.
+    pub fn last_register_write(&self, address: u16) -> Option<u8> {
+        let register = memory_map::register_for(address)?;
+        self.register_writes.get(&register.address).copied()
+    }
+
+    /// Whether the program has turned on the NMI at the start of vblank through
+    /// PPUCTRL. There's no PPU yet, so the frame loops signal it themselves.
+    pub fn is_nmi_enabled(&self) -> bool {
+        self.last_register_write(PpuRegister::Ctrl as u16)
+            .is_some_and(|value| value & PPUCTRL_NMI != 0)
+    }
+
+    pub fn set_u16(&mut self, address: u16, value: u16) {
+        let [le, be] = value.to_le_bytes();
+        self.set_u8(address, le);
+        self.set_u8(address.wrapping_add(1), be);
+    }
+
+    /// Writing a page number to $4014 copies that page of CPU memory into the OAM.
+    /// The copy is done all at once here, the CPU accounts for the time it takes with
+    /// take_pending_oam_dma. The bytes go through OAMDATA, so the copy starts at
+    /// OAMADDR and wraps around, which leaves OAMADDR where it was.
+    /// https://wiki.nesdev.com/w/index.php/PPU_registers#OAMDMA
+    fn oam_dma(&mut self, page: u8) {
+        let oam_data = PpuRegister::OamData as u16;
+        let start = u16::from_le_bytes([0, page]);
+        for offset in 0..0x100 {
+            let value = self.read_u8(start + offset);
+            if let Some(device) = self.device_mut(oam_data) {
+                device.write(oam_data, value);
+            }
+        }
+        self.pending_oam_dma = Some(page);
+    }
+
+    /// Returns the page of an OAM DMA that was triggered since the last call.
+    pub fn take_pending_oam_dma(&mut self) -> Option<u8> {
+        self.pending_oam_dma.take()
+    }
+
+    /// Copy the memory, the cartridge's state, and the devices' states. The observers
+    /// are tools rather than state, so they're left out.
+    pub fn save_state(&self) -> BusState {
+        BusState {
+            ram: self.ram.to_vec().into_boxed_slice(),
+            pending_oam_dma: self.pending_oam_dma,
+            register_writes: self
+                .register_writes
+                .iter()
+                .map(|(&address, &value)| (address, value))
+                .collect(),
+            open_bus: self.open_bus.value.get(),
+            cartridge: self.cartridge.save_state(),
+            devices: self
+                .devices
+                .iter()
+                .map(|attached| attached.device.save_state())
+                .collect(),
+        }
+    }
+
+    /// Restore the state from save_state. A state that doesn't fit this bus, like one
+    /// from another cartridge, is an error, and nothing is changed.
+    pub fn load_state(&mut self, state: &BusState) -> Result<(), String> {
+        if state.ram.len() != self.ram.len() {
+            return Err(String::from("The state's RAM is the wrong size."));
+        }
+        if state.devices.len() != self.devices.len() {
+            return Err(format!(
+                "The state has {} devices, but the bus has {}.",
+                state.devices.len(),
+                self.devices.len()
+            ));
+        }
+        // The cartridge and the devices check their own state as they load it, so
+        // keep the current state to put back if one of them turns it down.
+        let previous = self.save_state();
+        if let Err(error) = self.load_parts(state) {
+            self.load_parts(&previous)
+                .expect("The bus's own state fits it.");
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn load_parts(&mut self, state: &BusState) -> Result<(), String> {
+        self.cartridge.load_state(&state.cartridge)?;
+        for (attached, device_state) in self.devices.iter_mut().zip(&state.devices) {
+            attached
+                .device
+                .load_state(device_state)
+                .map_err(|error| format!("{}: {}", attached.device.name(), error))?;
+        }
+        self.ram.copy_from_slice(&state.ram);
+        self.pending_oam_dma = state.pending_oam_dma;
+        self.register_writes = state.register_writes.iter().copied().collect();
+        self.open_bus.drive(state.open_bus);
+        Ok(())
+    }
+
+    /// Read the cartridge's pattern tables, see Mapper::read_ppu.
+    pub fn read_ppu_u8(&self, address: u16) -> Option<u8> {
+        self.cartridge.read_ppu(address)
+    }
+
+    pub fn write_ppu_u8(&mut self, address: u16, value: u8) -> bool {
+        self.cartridge.write_ppu(address, value)
+    }
+
+    /// Fetch from the pattern tables to render, letting the cartridge see the fetch
+    /// after it's made, see Mapper::ppu_fetch.
+    pub fn fetch_ppu_u8(&mut self, address: u16) -> Option<u8> {
+        let value = self.cartridge.read_ppu(address);
+        self.clock_mapper(|cartridge| cartridge.ppu_fetch(address));
+        value
+    }
+
+    /// How the cartridge mirrors the nametables right now, see Mapper::mirroring.
+    pub fn mirroring(&self) -> Option<Mirroring> {
+        self.cartridge.mirroring()
+    }
+
+    /// The cartridge's battery-backed RAM, see battery.
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        self.cartridge.battery_ram()
+    }
+
+    pub fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.cartridge.load_battery_ram(ram);
+    }
+
+    /// Hook the cartridge's expansion audio up to the mixer, see Mapper::register_audio.
+    pub fn register_audio(&mut self, mixer: &mut Mixer) {
+        self.cartridge.register_audio(mixer);
+    }
+
+    pub fn mix_audio(&self, mixer: &mut Mixer) {
+        self.cartridge.mix_audio(mixer);
+    }
+
+    /// Start or stop timing the mapper's clocks. The mapper runs in the middle of the
+    /// CPU's instructions, so this is the only place it can be timed on its own.
+    #[cfg(feature = "std")]
+    pub fn set_mapper_timing(&mut self, enabled: bool) {
+        self.mapper_time = if enabled {
+            Some(Duration::default())
+        } else {
+            None
+        };
+    }
+
+    /// The time spent in the mapper's clocks since the last call.
+    pub fn take_mapper_time(&mut self) -> Duration {
+        self.mapper_time
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn clock_mapper(&mut self, clock: impl FnOnce(&mut dyn Mapper)) {
+        match self.mapper_time {
+            #[cfg(feature = "std")]
+            Some(ref mut mapper_time) => {
+                let start = Instant::now();
+                clock(self.cartridge.as_mut());
+                *mapper_time += start.elapsed();
+            }
+            _ => clock(self.cartridge.as_mut()),
+        }
+    }
+
+    /// Let the cartridge know that the CPU ran for some cycles. See the Mapper trait
+    /// for the order of the calls.
+    pub fn clock_cpu_cycles(&mut self, cycles: u16) {
+        let idle_cycles = &self.open_bus.idle_cycles;
+        idle_cycles.set(idle_cycles.get() + cycles as u64);
+        self.clock_mapper(|cartridge| {
+            for _ in 0..cycles {
+                cartridge.cpu_cycle();
+            }
+        });
+    }
+
+    pub fn ppu_a12_rise(&mut self) {
+        self.clock_mapper(|cartridge| cartridge.ppu_a12_rise());
+    }
+
+    pub fn scanline(&mut self) {
+        self.clock_mapper(|cartridge| cartridge.scanline());
+    }
+
+    /// See Mapper::ppu_layer.
+    pub fn ppu_layer(&mut self, layer: TileLayer) {
+        self.clock_mapper(|cartridge| cartridge.ppu_layer(layer));
+    }
+
+    pub fn is_mapper_irq_asserted(&self) -> bool {
+        self.cartridge.irq_asserted()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{ControllerPorts, JOY1};
+    use crate::mappers::SimpleProgram;
+
+    /// Records the RAM writes it sees.
+    #[derive(Default)]
+    struct RamWrites(Vec<(u16, u8)>);
+
+    impl BusObserver for RamWrites {
+        fn record_write(&mut self, access: &ObservedAccess) {
+            if access.ram {
+                self.0.push((access.address, access.value));
+            }
+        }
+    }
+
+    /// Counts the instructions.
+    #[derive(Default)]
+    struct Instructions(u64);
+
+    impl BusObserver for Instructions {
+        fn record_instruction(&mut self, _instruction: &InstructionStart) {
+            self.0 += 1;
+        }
+    }
+
+    /// Drops the writes to one address.
+    struct Blocked(u16);
+
+    impl BusObserver for Blocked {
+        fn check_write(&mut self, access: &ObservedAccess) -> bool {
+            access.address == self.0
+        }
+    }
+
+    #[test]
+    fn test_observers() {
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        bus.set_observer(Some(RamWrites::default()));
+        bus.set_observer(Some(Instructions::default()));
+
+        bus.set_u8(0x0010, 0x12);
+        // The registers aren't RAM.
+        bus.set_u8(0x4014, 0x00);
+        bus.record_instruction(&InstructionStart {
+            pc: 0x8000,
+            cycle: 0,
+            size: 1,
+        });
+        assert_eq!(bus.observer::<RamWrites>().unwrap().0, [(0x0010, 0x12)]);
+        assert_eq!(bus.observer::<Instructions>().unwrap().0, 1);
+
+        bus.set_observer(Some(Blocked(0x0020)));
+        bus.set_u8(0x0020, 0x56);
+        // The write was dropped, so it never made it to the RAM.
+        assert_eq!(bus.peek_u8(0x0020), 0x00);
+
+        // There is only room for one of each type.
+        bus.set_observer(Some(RamWrites::default()));
+        assert_eq!(bus.observers.len(), 3);
+        bus.set_observer(None::<Instructions>);
+        assert!(bus.observer::<Instructions>().is_none());
+        assert!(bus.observer::<RamWrites>().unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_load_state() {
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        let before = bus.save_state();
+        ControllerPorts::attach(&mut bus).unwrap();
+        // A state from a bus without the ports doesn't fit. The PPU registers are
+        // the first device, so the ports are the second.
+        assert!(bus.load_state(&before).is_err());
+
+        bus.set_u8(0x0000, 0x12);
+        let mut state = bus.save_state();
+        bus.set_u8(0x0000, 0x34);
+        bus.set_u8(JOY1, 1);
+
+        // The ports' state is too short, so nothing is loaded, not even the RAM.
+        state.devices[1].pop();
+        assert!(bus.load_state(&state).is_err());
+        assert_eq!(bus.read_u8(0x0000), 0x34);
+        assert_eq!(bus.save_state().devices[1][0], 1);
+
+        state.devices[1].push(0);
+        bus.load_state(&state).unwrap();
+        assert_eq!(bus.read_u8(0x0000), 0x12);
+        assert_eq!(bus.save_state().devices[1][0], 0);
+    }
+}
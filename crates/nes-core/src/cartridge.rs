@@ -0,0 +1,424 @@
+//! Load an iNES file into a cartridge that can be plugged into the bus. The file is
+//! a 16 byte iNES or NES 2.0 header, an optional trainer, and then the PRG ROM and
+//! the CHR ROM. See rom::parse_header for the header itself.
+//!
+//! Headers are often wrong in the wild, so the ROM is looked up in the RomDatabase by
+//! its CRC32 and SHA-1, and a match overrides the header's mapper and mirroring.
+//! https://wiki.nesdev.com/w/index.php/INES
+use crate::bus::{Bus, SharedBus};
+use crate::mappers::{mapper_for_rom, Mapper};
+use crate::prelude::*;
+use crate::region::Region;
+use crate::rom::{self, Header, ROMLoadError, HEADER_SIZE, ROM, TRAINER_SIZE};
+use crate::rom_database::{RomDatabase, RomEntry};
+use crate::rom_info::{crc32, sha1};
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// The memory on the board, for when the header doesn't describe it, like an iNES 1.0
+/// header for homebrew on a bigger board than the mapper usually has. The sizes are in
+/// bytes, and replace the ones from the header. `game.nes` is overridden by a
+/// `game.board.toml` next to it, e.g.
+///
+/// prg_ram = 0x8000
+/// chr_ram = 0x8000
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct BoardOverride {
+    /// The work RAM at $6000-$7FFF, which the bigger boards bank.
+    pub prg_ram: Option<u32>,
+    /// The PRG RAM that is kept by a battery.
+    pub prg_nvram: Option<u32>,
+    /// Only used when there isn't any CHR ROM.
+    pub chr_ram: Option<u32>,
+    pub chr_nvram: Option<u32>,
+}
+
+impl BoardOverride {
+    #[cfg(feature = "toml")]
+    pub fn parse(text: &str) -> Result<BoardOverride, String> {
+        toml::from_str(text).map_err(|error| error.to_string())
+    }
+
+    /// The override file for a ROM.
+    #[cfg(feature = "std")]
+    pub fn path_for(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("board.toml")
+    }
+
+    /// Load the override for a ROM, if it has one.
+    #[cfg(feature = "toml")]
+    pub fn load_for(rom_path: &Path) -> Result<Option<BoardOverride>, ROMLoadError> {
+        let path = BoardOverride::path_for(rom_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)?;
+        BoardOverride::parse(&text).map(Some).map_err(|error| {
+            ROMLoadError::BoardOverride(format!("{}: {}", path.display(), error))
+        })
+    }
+
+    /// The override files need the toml feature, so without it there's never one.
+    #[cfg(all(feature = "std", not(feature = "toml")))]
+    pub fn load_for(_rom_path: &Path) -> Result<Option<BoardOverride>, ROMLoadError> {
+        Ok(None)
+    }
+
+    pub fn apply(&self, header: &mut Header) {
+        if let Some(size) = self.prg_ram {
+            header.prg_ram_size = size;
+        }
+        if let Some(size) = self.prg_nvram {
+            header.prg_nvram_size = size;
+            header.persistent_memory = size != 0;
+        }
+        if let Some(size) = self.chr_ram {
+            header.chr_ram_size = size;
+        }
+        if let Some(size) = self.chr_nvram {
+            header.chr_nvram_size = size;
+        }
+    }
+}
+
+/// Apply the database's corrections to the header. The board's memory isn't in the
+/// database, see BoardOverride for that.
+fn apply_database_entry(entry: &RomEntry, header: &mut Header) {
+    header.mapping_number = entry.mapper as u16;
+    match entry.mirroring {
+        Some(mirroring) if !header.four_screen_vram => header.mirroring = mirroring,
+        _ => {}
+    }
+}
+
+/// A validated ROM, along with the mapper that its header asks for.
+pub struct Cartridge {
+    pub rom: ROM,
+    mapper: Box<dyn Mapper>,
+    /// The CRC32 of the PRG and CHR ROM, see rom_info::crc32.
+    crc32: u32,
+    sha1: [u8; 20],
+    /// The ROM's entry in the database, which was applied to the header.
+    database_entry: Option<RomEntry>,
+}
+
+impl Cartridge {
+    /// Load the file, along with its BoardOverride if it has one, and identify it with
+    /// the embedded database.
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Cartridge, ROMLoadError> {
+        Cartridge::load_with_database(path, &RomDatabase::embedded())
+    }
+
+    /// Load the file, identifying it with a database, e.g. the user's own merged with
+    /// RomDatabase::embedded.
+    #[cfg(feature = "std")]
+    pub fn load_with_database(
+        path: &Path,
+        database: &RomDatabase,
+    ) -> Result<Cartridge, ROMLoadError> {
+        let board = BoardOverride::load_for(path)?.unwrap_or_default();
+        Cartridge::from_bytes_with_board(&fs::read(path)?, &board, database)
+    }
+
+    /// Parse and validate the file, and identify it with the embedded database.
+    /// Anything after the ROM, like a title or the PlayChoice-10 data, is ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cartridge, ROMLoadError> {
+        Cartridge::from_bytes_with_board(
+            bytes,
+            &BoardOverride::default(),
+            &RomDatabase::embedded(),
+        )
+    }
+
+    /// Parse and validate the file, with the header corrected by the database, and the
+    /// board's memory overridden.
+    pub fn from_bytes_with_board(
+        bytes: &[u8],
+        board: &BoardOverride,
+        database: &RomDatabase,
+    ) -> Result<Cartridge, ROMLoadError> {
+        let mut header = rom::parse_file_header(bytes, bytes.len())?;
+        // NES 2.0 sizes under 16 KB have no whole banks, so go by the bytes.
+        if header.prg_rom_bytes == 0 {
+            return Err("The header says there isn't any PRG ROM.".into());
+        }
+
+        let mut offset = HEADER_SIZE;
+        let mut take = |size: usize| -> Result<Vec<u8>, ROMLoadError> {
+            let slice = bytes
+                .get(offset..offset + size)
+                .ok_or("The file is shorter than its header says.")?;
+            offset += size;
+            Ok(slice.to_vec())
+        };
+        let trainer = if header.has_trainer {
+            Some(take(TRAINER_SIZE)?)
+        } else {
+            None
+        };
+        let program_rom = take(header.prg_rom_bytes as usize)?;
+        let character_rom = take(header.character_rom_bytes as usize)?;
+
+        let rom_bytes = [&program_rom[..], &character_rom[..]].concat();
+        let crc32 = crc32(&rom_bytes);
+        let sha1 = sha1(&rom_bytes);
+        let database_entry = database.identify(crc32, &sha1).cloned();
+        if let Some(ref entry) = database_entry {
+            apply_database_entry(entry, &mut header);
+        }
+        board.apply(&mut header);
+
+        let rom = ROM {
+            header,
+            program_rom,
+            character_rom,
+            trainer,
+        };
+        let mapper = mapper_for_rom(&rom)?;
+        Ok(Cartridge {
+            rom,
+            mapper,
+            crc32,
+            sha1,
+            database_entry,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.rom.header
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    pub fn sha1(&self) -> &[u8; 20] {
+        &self.sha1
+    }
+
+    pub fn database_entry(&self) -> Option<&RomEntry> {
+        self.database_entry.as_ref()
+    }
+
+    /// The game's title, if it's in the database.
+    pub fn title(&self) -> Option<&str> {
+        self.database_entry().map(|entry| entry.name.as_str())
+    }
+
+    /// The region to run the game in, see Region::detect.
+    pub fn region(&self) -> Region {
+        Region::detect(self.header(), self.database_entry())
+    }
+
+    /// Whether the file had a trainer, which the mapper loaded at $7000.
+    pub fn has_trainer(&self) -> bool {
+        self.rom.trainer.is_some()
+    }
+
+    pub fn into_mapper(self) -> Box<dyn Mapper> {
+        self.mapper
+    }
+
+    /// Plug the cartridge into a new NES bus.
+    pub fn into_shared_bus(self) -> SharedBus {
+        Bus::new_shared_bus(self.mapper)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_6502::Cpu6502;
+    use crate::rom::Mirroring;
+
+    /// An NROM-128 file, with the program at $8000 and the reset vector pointing
+    /// to it.
+    fn nrom(program: &[u8], flags6: u8) -> Vec<u8> {
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, flags6];
+        bytes.resize(HEADER_SIZE, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        bytes.extend_from_slice(&prg);
+        bytes.extend_from_slice(&[0; 0x2000]);
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        match Cartridge::from_bytes(bytes) {
+            Ok(_) => panic!("The cartridge loaded."),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        // lda #$42, sta $10
+        let cartridge = Cartridge::from_bytes(&nrom(&[0xa9, 0x42, 0x85, 0x10], 0b11))
+            .expect("The cartridge loads.");
+        assert_eq!(cartridge.header().mapping_number, 0);
+        assert_eq!(cartridge.header().mirroring, Mirroring::Vertical);
+        assert!(cartridge.header().persistent_memory);
+        assert_eq!(cartridge.rom.character_rom.len(), 0x2000);
+
+        let mut cpu = Cpu6502::new(cartridge.into_shared_bus());
+        assert_eq!(cpu.pc, 0x8000);
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.peek(0x10), 0x42);
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_database() {
+        let bytes = nrom(&[], 0b1);
+        let cartridge = Cartridge::from_bytes(&bytes).unwrap();
+        assert_eq!(cartridge.title(), None);
+        assert_eq!(cartridge.crc32(), crc32(&bytes[HEADER_SIZE..]));
+        assert_eq!(cartridge.region(), Region::Ntsc);
+
+        // The header says vertical mirroring, but the database knows better.
+        let database = RomDatabase::parse(&format!(
+            r#"
+            [[rom]]
+            name = "Test (Europe)"
+            crc32 = {}
+            sha1 = "{}"
+            mapper = 0
+            mirroring = "horizontal"
+            region = "pal"
+            prg_rom_banks = 1
+            character_rom_banks = 1
+            "#,
+            cartridge.crc32(),
+            crate::rom_info::sha1_hex(cartridge.sha1())
+        ))
+        .unwrap();
+        let cartridge = Cartridge::from_bytes_with_board(
+            &bytes,
+            &BoardOverride::default(),
+            &database,
+        )
+        .unwrap();
+        assert_eq!(cartridge.title(), Some("Test (Europe)"));
+        assert_eq!(cartridge.header().mirroring, Mirroring::Horizontal);
+        assert_eq!(cartridge.region(), Region::Pal);
+        assert_eq!(
+            cartridge.into_mapper().mirroring(),
+            Some(Mirroring::Horizontal)
+        );
+
+        // A known mapper lets a ROM with a wrong header load.
+        let mut wrong_mapper = bytes;
+        wrong_mapper[6] |= 0x40;
+        let cartridge = Cartridge::from_bytes_with_board(
+            &wrong_mapper,
+            &BoardOverride::default(),
+            &database,
+        )
+        .unwrap();
+        assert_eq!(cartridge.header().mapping_number, 0);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut bytes = nrom(&[], 0b100);
+        let trainer: Vec<u8> = (0..TRAINER_SIZE).map(|index| index as u8).collect();
+        bytes.splice(HEADER_SIZE..HEADER_SIZE, trainer);
+        let cartridge = Cartridge::from_bytes(&bytes).unwrap();
+        assert!(cartridge.has_trainer());
+        // The PRG ROM still starts after the trainer.
+        assert_eq!(cartridge.rom.program_rom[0x3ffd], 0x80);
+
+        let mapper = cartridge.into_mapper();
+        assert_eq!(mapper.read_cpu(0x6fff), Some(0));
+        assert_eq!(mapper.read_cpu(0x7000), Some(0));
+        assert_eq!(mapper.read_cpu(0x7001), Some(1));
+        assert_eq!(mapper.read_cpu(0x71ff), Some(0xff));
+        assert_eq!(mapper.read_cpu(0x7200), Some(0));
+
+        assert!(!Cartridge::from_bytes(&nrom(&[], 0)).unwrap().has_trainer());
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_board_override() {
+        let dir = std::env::temp_dir().join(format!("nes-board-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.nes");
+        fs::write(&rom_path, nrom(&[], 0)).unwrap();
+        assert_eq!(
+            Cartridge::load(&rom_path).unwrap().header().prg_ram_size,
+            0x2000
+        );
+
+        // 2 KB of battery-backed RAM, which NROM mirrors through $6000-$7FFF.
+        fs::write(
+            BoardOverride::path_for(&rom_path),
+            "prg_ram = 0\nprg_nvram = 0x800\n",
+        )
+        .unwrap();
+        let cartridge = Cartridge::load(&rom_path).unwrap();
+        assert_eq!(cartridge.header().prg_nvram_size, 0x800);
+        assert!(cartridge.header().persistent_memory);
+        let mut mapper = cartridge.into_mapper();
+        mapper.write_cpu(0x6001, 0x42);
+        assert_eq!(mapper.read_cpu(0x6801), Some(0x42));
+        assert_eq!(mapper.battery_ram().unwrap().len(), 0x800);
+
+        fs::write(BoardOverride::path_for(&rom_path), "prg_ram = \"big\"").unwrap();
+        match Cartridge::load(&rom_path) {
+            Err(ROMLoadError::BoardOverride(message)) => {
+                assert!(message.contains("game.board.toml"))
+            }
+            _ => panic!("The override should fail to parse."),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validation() {
+        let bytes = nrom(&[], 0);
+        assert_eq!(
+            error(&bytes[..bytes.len() - 1]),
+            "The file is shorter than its header says."
+        );
+        // The trainer comes before the PRG ROM, so the file is now too short.
+        assert_eq!(
+            error(&nrom(&[], 0b100)),
+            "The file is shorter than its header says."
+        );
+        assert_eq!(
+            error(&bytes[..4]),
+            "The file is too short to contain an iNES header."
+        );
+        assert_eq!(
+            error(b"NOPE"),
+            "The file is too short to contain an iNES header."
+        );
+
+        let mut no_prg = bytes.clone();
+        no_prg[4] = 0;
+        assert_eq!(error(&no_prg), "The header says there isn't any PRG ROM.");
+
+        // An NES 2.0 header with 2^13 bytes of PRG ROM, which is less than a bank. That
+        // gets past the header, but NROM needs 16 or 32 KB.
+        let mut small_prg = vec![0x4e, 0x45, 0x53, 0x1a, 13 << 2, 0, 0, 0x08, 0, 0x0f];
+        small_prg.resize(HEADER_SIZE + 0x2000, 0);
+        assert_eq!(
+            error(&small_prg),
+            "The ROM had the incorrect sized PRG ROM for NROM."
+        );
+
+        let mut mapper_4 = bytes;
+        mapper_4[6] = 0x40;
+        assert_eq!(error(&mapper_4), "The ROM's mapper is not supported yet.");
+    }
+}
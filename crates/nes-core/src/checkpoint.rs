@@ -0,0 +1,274 @@
+use crate::bus::BusState;
+use crate::cpu_6502::{Cpu6502, CpuState, Registers};
+use crate::prelude::*;
+use crate::savestate::{Reader, SavestateFile, CHECKPOINT_CHUNK};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An in-memory savestate of the CPU and its bus, for tools that need to rewind, like
+/// bisecting a movie. Restoring a checkpoint and running again gives the same results,
+/// as long as it's restored into the machine it was taken from. It's written out to
+/// a savestate with write_chunk, or with the "serde" feature, to any other format.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint {
+    registers: Registers,
+    cycles: u16,
+    tick_count: u64,
+    total_cycles: u64,
+    overrun_cycles: u64,
+    dma_stall_cycles: u16,
+    nmi_pending: bool,
+    scheduled_nmi: Option<u64>,
+    scheduled_vblank: Option<u64>,
+    irq_sources: u8,
+    interrupt_disable_latch: Option<bool>,
+    state: CpuState,
+    bus: BusState,
+}
+
+impl Checkpoint {
+    pub fn save(cpu: &Cpu6502) -> Checkpoint {
+        Checkpoint {
+            registers: cpu.registers(),
+            cycles: cpu.cycles,
+            tick_count: cpu.tick_count,
+            total_cycles: cpu.total_cycles,
+            overrun_cycles: cpu.overrun_cycles,
+            dma_stall_cycles: cpu.dma_stall_cycles,
+            nmi_pending: cpu.nmi_pending,
+            scheduled_nmi: cpu.scheduled_nmi,
+            scheduled_vblank: cpu.scheduled_vblank,
+            irq_sources: cpu.irq_sources,
+            interrupt_disable_latch: cpu.interrupt_disable_latch,
+            state: cpu.state,
+            bus: cpu.bus.borrow().save_state(),
+        }
+    }
+
+    /// Put the CPU and the bus back the way they were. The CPU's settings and tools,
+    /// like the variant and the analyzers, are left alone. A checkpoint from another
+    /// machine is an error, see Bus::load_state.
+    pub fn restore(&self, cpu: &mut Cpu6502) -> Result<(), String> {
+        cpu.bus.borrow_mut().load_state(&self.bus)?;
+        cpu.set_registers(self.registers);
+        cpu.cycles = self.cycles;
+        cpu.tick_count = self.tick_count;
+        cpu.total_cycles = self.total_cycles;
+        cpu.overrun_cycles = self.overrun_cycles;
+        cpu.dma_stall_cycles = self.dma_stall_cycles;
+        cpu.nmi_pending = self.nmi_pending;
+        cpu.scheduled_nmi = self.scheduled_nmi;
+        cpu.scheduled_vblank = self.scheduled_vblank;
+        cpu.irq_sources = self.irq_sources;
+        cpu.interrupt_disable_latch = self.interrupt_disable_latch;
+        cpu.state = self.state;
+        Ok(())
+    }
+
+    /// Write the checkpoint into the file's checkpoint chunk, replacing the one that
+    /// was there.
+    pub fn write_chunk(&self, file: &mut SavestateFile) {
+        let registers = self.registers;
+        let mut bytes = registers.pc.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[registers.a, registers.x, registers.y, registers.s]);
+        bytes.push(registers.p);
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.tick_count.to_le_bytes());
+        bytes.extend_from_slice(&self.total_cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.overrun_cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.dma_stall_cycles.to_le_bytes());
+        bytes.push(self.nmi_pending as u8);
+        for scheduled in [self.scheduled_nmi, self.scheduled_vblank] {
+            match scheduled {
+                Some(cycle) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&cycle.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+        bytes.push(self.irq_sources);
+        bytes.push(match self.interrupt_disable_latch {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        });
+        match self.state {
+            CpuState::Running => bytes.push(0),
+            CpuState::Jammed => bytes.push(1),
+            CpuState::WaitingForInterrupt => bytes.push(2),
+            CpuState::IllegalOpcode(opcode) => bytes.extend_from_slice(&[3, opcode]),
+        }
+        self.bus.write(&mut bytes);
+        file.set_chunk(CHECKPOINT_CHUNK, bytes);
+    }
+
+    /// Read the checkpoint back out of the file's checkpoint chunk. It still has to be
+    /// restored into the machine the file was saved from.
+    pub fn read_chunk(file: &SavestateFile) -> Result<Checkpoint, String> {
+        let chunk = file
+            .chunk(CHECKPOINT_CHUNK)
+            .ok_or("The savestate doesn't have a checkpoint.")?;
+        let mut reader = Reader(chunk);
+        let pc = reader.u16()?;
+        let registers = Registers {
+            pc,
+            a: reader.u8()?,
+            x: reader.u8()?,
+            y: reader.u8()?,
+            s: reader.u8()?,
+            p: reader.u8()?,
+        };
+        let cycles = reader.u16()?;
+        let tick_count = reader.u64()?;
+        let total_cycles = reader.u64()?;
+        let overrun_cycles = reader.u64()?;
+        let dma_stall_cycles = reader.u16()?;
+        let nmi_pending = reader.u8()? != 0;
+        let mut scheduled = [None, None];
+        for cycle in &mut scheduled {
+            if reader.u8()? != 0 {
+                *cycle = Some(reader.u64()?);
+            }
+        }
+        let [scheduled_nmi, scheduled_vblank] = scheduled;
+        let irq_sources = reader.u8()?;
+        let interrupt_disable_latch = match reader.u8()? {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            _ => return Err("The checkpoint's interrupt latch is unknown.".into()),
+        };
+        let state = match reader.u8()? {
+            0 => CpuState::Running,
+            1 => CpuState::Jammed,
+            2 => CpuState::WaitingForInterrupt,
+            3 => CpuState::IllegalOpcode(reader.u8()?),
+            _ => return Err("The checkpoint's CPU state is unknown.".into()),
+        };
+        let bus = BusState::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err("The checkpoint chunk is too long.".into());
+        }
+        Ok(Checkpoint {
+            registers,
+            cycles,
+            tick_count,
+            total_cycles,
+            overrun_cycles,
+            dma_stall_cycles,
+            nmi_pending,
+            scheduled_nmi,
+            scheduled_vblank,
+            irq_sources,
+            interrupt_disable_latch,
+            state,
+            bus,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_6502::test_helpers::load_program;
+    use crate::region::NTSC_FRAME_CYCLES;
+
+    #[test]
+    fn test_restore() {
+        let mut cpu = load_program(
+            "
+          loop:
+            inc $00
+            ldx $00
+            sta $0300,x
+            jmp loop
+            ",
+        );
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        let checkpoint = Checkpoint::save(&cpu);
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        let registers = cpu.registers();
+        let memory: Vec<u8> = (0..0x800).map(|address| cpu.peek(address)).collect();
+
+        checkpoint.restore(&mut cpu).unwrap();
+        assert_ne!(cpu.registers(), registers);
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(
+            (0..0x800)
+                .map(|address| cpu.peek(address))
+                .collect::<Vec<_>>(),
+            memory
+        );
+    }
+
+    #[test]
+    fn test_savestate_chunk() {
+        let mut cpu = load_program(
+            "
+              lda #$42
+              sta $10
+              ldx #$07
+              sec
+            ",
+        );
+        cpu.run_for_cycles(20);
+        let mut file = SavestateFile::new(0);
+        Checkpoint::save(&cpu).write_chunk(&mut file);
+        let registers = cpu.registers();
+        let total_cycles = cpu.total_cycles;
+
+        cpu.reset();
+        cpu.poke(0x10, 0x00);
+        let file = SavestateFile::from_bytes(&file.to_bytes()).unwrap();
+        let checkpoint = Checkpoint::read_chunk(&file).unwrap();
+        checkpoint.restore(&mut cpu).unwrap();
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(cpu.total_cycles, total_cycles);
+        assert_eq!(cpu.peek(0x10), 0x42);
+
+        // A truncated chunk is an error.
+        let mut file = file;
+        let mut chunk = file.chunk(CHECKPOINT_CHUNK).unwrap().to_vec();
+        chunk.pop();
+        file.set_chunk(CHECKPOINT_CHUNK, chunk);
+        assert!(Checkpoint::read_chunk(&file).is_err());
+        assert!(Checkpoint::read_chunk(&SavestateFile::new(0)).is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "toml"))]
+    #[test]
+    fn test_serde() {
+        use crate::controller::{ButtonState, ControllerPorts, JOY1};
+
+        let mut cpu = load_program(
+            "
+              lda #$01
+              sta $4016
+              lda #$00
+              sta $4016
+              lda $4016
+              sta $10
+              lda #$42
+              sta $4014
+            ",
+        );
+        let [controller, _] = ControllerPorts::attach(&mut cpu.bus.borrow_mut()).unwrap();
+        controller.set_buttons(ButtonState(0b0000_0011));
+        cpu.run_for_cycles(100);
+        let checkpoint = Checkpoint::save(&cpu);
+        let text = toml::to_string(&checkpoint).unwrap();
+
+        cpu.reset();
+        cpu.poke(0x10, 0x00);
+        let restored: Checkpoint = toml::from_str(&text).unwrap();
+        restored.restore(&mut cpu).unwrap();
+        assert_eq!(cpu.registers(), checkpoint.registers);
+        assert_eq!(cpu.peek(0x10) & 1, 1);
+        // The controller's shift register picks up where it left off, with B next.
+        assert_eq!(cpu.bus.borrow().peek_u8(JOY1) & 1, 1);
+    }
+}
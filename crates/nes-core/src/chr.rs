@@ -0,0 +1,241 @@
+// Convert the CHR pattern data to and from PNG sheets, so that the tiles can be
+// edited in a graphics editor. Each tile is 8x8 pixels with 2 bits per pixel, stored
+// as two planes of 8 bytes. The low bits of the pixels come from the first plane, and
+// the high bits from the second.
+// https://wiki.nesdev.com/w/index.php/PPU_pattern_tables
+
+use crate::prelude::*;
+#[cfg(feature = "png")]
+use std::io::{Read, Write};
+
+pub const TILE_BYTES: usize = 16;
+pub const TILE_SIZE: usize = 8;
+/// The sheets are 16 tiles wide, like a pattern table.
+pub const SHEET_TILES_WIDE: usize = 16;
+pub const SHEET_WIDTH: usize = SHEET_TILES_WIDE * TILE_SIZE;
+
+/// The pixel values are shown from dark to light, as there's no palette in the CHR.
+const PALETTE: [u8; 12] = [
+    0x00, 0x00, 0x00, 0x55, 0x55, 0x55, 0xaa, 0xaa, 0xaa, 0xff, 0xff, 0xff,
+];
+
+/// An image where every pixel is a value from 0 to 3.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sheet {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Sheet {
+    /// Lay out the tiles in rows of 16. The last row is padded with empty tiles.
+    pub fn from_chr(chr: &[u8]) -> Sheet {
+        let tiles = chr.len().div_ceil(TILE_BYTES);
+        let rows = tiles.div_ceil(SHEET_TILES_WIDE);
+        let mut sheet = Sheet {
+            width: SHEET_WIDTH,
+            height: rows * TILE_SIZE,
+            pixels: vec![0; SHEET_WIDTH * rows * TILE_SIZE],
+        };
+        for (tile_index, tile) in chr.chunks(TILE_BYTES).enumerate() {
+            let left = (tile_index % SHEET_TILES_WIDE) * TILE_SIZE;
+            let top = (tile_index / SHEET_TILES_WIDE) * TILE_SIZE;
+            for y in 0..TILE_SIZE {
+                let low = tile.get(y).copied().unwrap_or(0);
+                let high = tile.get(y + TILE_SIZE).copied().unwrap_or(0);
+                for x in 0..TILE_SIZE {
+                    let bit = 7 - x;
+                    let value = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                    sheet.pixels[(top + y) * sheet.width + left + x] = value;
+                }
+            }
+        }
+        sheet
+    }
+
+    /// Read the tiles back out in rows, left to right.
+    pub fn to_chr(&self) -> Result<Vec<u8>, String> {
+        if !self.width.is_multiple_of(TILE_SIZE) || !self.height.is_multiple_of(TILE_SIZE)
+        {
+            return Err(format!(
+                "The sheet is {}x{}, but it must be a multiple of 8 pixels in both \
+                 directions.",
+                self.width, self.height
+            ));
+        }
+        let tiles_wide = self.width / TILE_SIZE;
+        let tiles_high = self.height / TILE_SIZE;
+        let mut chr = Vec::with_capacity(tiles_wide * tiles_high * TILE_BYTES);
+        for tile_index in 0..tiles_wide * tiles_high {
+            let left = (tile_index % tiles_wide) * TILE_SIZE;
+            let top = (tile_index / tiles_wide) * TILE_SIZE;
+            let mut tile = [0; TILE_BYTES];
+            for y in 0..TILE_SIZE {
+                for x in 0..TILE_SIZE {
+                    let value = self.pixels[(top + y) * self.width + left + x];
+                    tile[y] |= (value & 1) << (7 - x);
+                    tile[y + TILE_SIZE] |= ((value >> 1) & 1) << (7 - x);
+                }
+            }
+            chr.extend_from_slice(&tile);
+        }
+        Ok(chr)
+    }
+
+    /// Write an indexed PNG with a 4 color grayscale palette.
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, out: impl Write) -> Result<(), String> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(PALETTE.to_vec());
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|error| format!("Unable to write the PNG: {}", error))
+    }
+
+    /// Read an indexed PNG that uses the first 4 colors of its palette, or a
+    /// grayscale PNG where the shades are split into 4 values.
+    #[cfg(feature = "png")]
+    pub fn read_png(input: impl Read) -> Result<Sheet, String> {
+        let mut decoder = png::Decoder::new(input);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder
+            .read_info()
+            .map_err(|error| format!("Unable to read the PNG: {}", error))?;
+        let mut data = vec![0; info.buffer_size()];
+        reader
+            .next_frame(&mut data)
+            .map_err(|error| format!("Unable to read the PNG: {}", error))?;
+
+        let depth = info.bit_depth as usize;
+        if depth > 8 {
+            return Err(String::from("16 bit PNGs are not supported."));
+        }
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in data.chunks(info.line_size).take(height) {
+            for x in 0..width {
+                // Pixels smaller than a byte are packed from the most significant bit.
+                let bit = x * depth;
+                let shift = 8 - depth - (bit % 8);
+                let value = (row[bit / 8] >> shift) & ((1u16 << depth) - 1) as u8;
+                let value = match info.color_type {
+                    png::ColorType::Indexed if value < 4 => value,
+                    png::ColorType::Indexed => {
+                        return Err(format!(
+                            "The pixel at {},{} uses palette entry {}, only the first 4 \
+                             entries can be used.",
+                            x,
+                            pixels.len() / width,
+                            value
+                        ));
+                    }
+                    png::ColorType::Grayscale if depth == 1 => value * 3,
+                    png::ColorType::Grayscale => value >> (depth - 2),
+                    _ => {
+                        return Err(String::from(
+                            "Save the sheet as an indexed or grayscale PNG.",
+                        ));
+                    }
+                };
+                pixels.push(value);
+            }
+        }
+        Ok(Sheet {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// List the CHR data as .byte directives, one tile per line.
+pub fn to_asm(chr: &[u8]) -> String {
+    let mut asm = String::new();
+    for (tile_index, tile) in chr.chunks(TILE_BYTES).enumerate() {
+        let bytes: Vec<String> =
+            tile.iter().map(|byte| format!("${:02x}", byte)).collect();
+        asm.push_str(&format!(
+            "  .byte {} ; tile ${:02x}\n",
+            bytes.join(", "),
+            tile_index
+        ));
+    }
+    asm
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+
+    /// The "1/2" tile from the pattern tables wiki page.
+    const TILE: [u8; 16] = [
+        0x41, 0xc2, 0x44, 0x48, 0x10, 0x20, 0x40, 0x80, 0x01, 0x02, 0x04, 0x08, 0x16,
+        0x21, 0x42, 0x87,
+    ];
+
+    fn chr() -> Vec<u8> {
+        let mut chr: Vec<u8> = (0..=255).collect();
+        chr.extend_from_slice(&TILE);
+        chr
+    }
+
+    #[test]
+    fn test_decode_tile() {
+        let sheet = Sheet::from_chr(&TILE);
+        assert_eq!(sheet.width, 128);
+        assert_eq!(sheet.height, 8);
+        assert_eq!(&sheet.pixels[0..8], &[0, 1, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(
+            &sheet.pixels[128 * 7..128 * 7 + 8],
+            &[3, 0, 0, 0, 0, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn test_round_trip() {
+        let chr = chr();
+        let sheet = Sheet::from_chr(&chr);
+        let mut png = Vec::new();
+        sheet.write_png(&mut png).unwrap();
+        let read_sheet = Sheet::read_png(&png[..]).unwrap();
+        assert_eq!(read_sheet, sheet);
+        // The sheet is padded out to a full row of tiles.
+        let read_chr = read_sheet.to_chr().unwrap();
+        assert_eq!(&read_chr[..chr.len()], &chr[..]);
+        assert!(read_chr[chr.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn test_grayscale_png() {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 8, 8);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Two);
+            let mut writer = encoder.write_header().unwrap();
+            // Every row is 0, 1, 2, 3, 3, 2, 1, 0
+            writer
+                .write_image_data(&[0b0001_1011u8, 0b1110_0100].repeat(8))
+                .unwrap();
+        }
+        let sheet = Sheet::read_png(&png[..]).unwrap();
+        assert_eq!(&sheet.pixels[0..8], &[0, 1, 2, 3, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_to_asm() {
+        let chr = chr();
+        let asm = to_asm(&chr);
+        let mut lexer = AsmLexer::new(&asm);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, .. } = lexer.into_bytes().unwrap();
+        assert_eq!(bytes, chr);
+    }
+}
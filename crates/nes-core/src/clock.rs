@@ -0,0 +1,240 @@
+// Pace the emulator against a clock. A frontend asks the pacer how many cycles are
+// due, and hands them to Cpu6502::run_for_cycles. The budget is worked out from the
+// time since the pacer started, rather than added up from the time between calls, so
+// the rounding never builds up and the speed doesn't drift.
+//
+// The time comes from a Clock. The SystemClock is the wall clock, and the
+// ManualClock only moves when it's told to, so that the headless runs and the tests
+// are deterministic, and can fast-forward through the timers.
+
+use crate::cpu_6502::{CLOCK_DIVISOR, CLOCK_SPEED, MASTER_CLOCK_FREQUENCY};
+use crate::region::Region;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// The speed of the NTSC CPU, in Hz.
+pub const NTSC_HZ: f64 = CLOCK_SPEED * 1_000_000.0;
+
+/// The CPU speed that runs exactly 60 frames a second, which keeps the frames
+/// stable on a 60 Hz display.
+pub const SYNCHRONIZED_HZ: f64 =
+    MASTER_CLOCK_FREQUENCY * 1_000_000.0 / CLOCK_DIVISOR as f64;
+
+/// Once the emulator falls further behind than this, e.g. when the host was
+/// suspended or stopped in a debugger, the missed time is dropped instead of being
+/// run all at once.
+pub const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// A source of time, as the time since the clock started. Anything that runs on a
+/// timer takes its time from a clock, rather than asking the system.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// The wall clock.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when it's advanced. It can be advanced through a shared
+/// reference, so a test can keep an Rc to it while the emulator uses it.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// Hands out the cycles that are due at the speed of the hardware. The times are
+/// from a Clock.
+pub struct Pacer {
+    hz: f64,
+    multiplier: f64,
+    start: Duration,
+    /// The cycles that have been handed out since the start.
+    cycles_issued: u64,
+}
+
+impl Pacer {
+    pub fn new(hz: f64, now: Duration) -> Pacer {
+        Pacer {
+            hz,
+            multiplier: 1.0,
+            start: now,
+            cycles_issued: 0,
+        }
+    }
+
+    pub fn ntsc(now: Duration) -> Pacer {
+        Pacer::new(NTSC_HZ, now)
+    }
+
+    pub fn for_region(region: Region, now: Duration) -> Pacer {
+        match region {
+            Region::Ntsc => Pacer::ntsc(now),
+            Region::Pal => Pacer::new(region.cpu_hz() as f64, now),
+        }
+    }
+
+    pub fn multiplier(&self) -> f64 {
+        self.multiplier
+    }
+
+    /// Run faster or slower than the real hardware, e.g. 2.0 for double speed. The
+    /// pacer restarts from now, so the cycles that were already handed out are not
+    /// paced again at the new speed.
+    pub fn set_multiplier(&mut self, multiplier: f64, now: Duration) {
+        self.multiplier = multiplier.max(0.0);
+        self.start = now;
+        self.cycles_issued = 0;
+    }
+
+    fn cycles_per_second(&self) -> f64 {
+        self.hz * self.multiplier
+    }
+
+    /// The cycles that are due to run by now.
+    pub fn budget(&mut self, now: Duration) -> u64 {
+        let elapsed = now.saturating_sub(self.start).as_secs_f64();
+        let due = (elapsed * self.cycles_per_second()) as u64;
+        let max_catch_up = (MAX_CATCH_UP.as_secs_f64() * self.cycles_per_second()) as u64;
+        if due > self.cycles_issued + max_catch_up {
+            self.cycles_issued = due - max_catch_up;
+        }
+        let budget = due.saturating_sub(self.cycles_issued);
+        self.cycles_issued += budget;
+        budget
+    }
+
+    /// How long to wait until the next `cycles` are due, so that a frontend can
+    /// sleep instead of spinning.
+    pub fn time_until(&self, cycles: u64, now: Duration) -> Duration {
+        if self.cycles_per_second() == 0.0 {
+            return MAX_CATCH_UP;
+        }
+        let seconds = (self.cycles_issued + cycles) as f64 / self.cycles_per_second();
+        (self.start + Duration::from_secs_f64(seconds)).saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let start = Duration::from_secs(5);
+        let mut pacer = Pacer::ntsc(start);
+        assert_eq!(pacer.budget(start), 0);
+        assert_eq!(pacer.budget(start + Duration::from_millis(1)), 1789);
+        assert_eq!(pacer.budget(start + Duration::from_millis(1)), 0);
+
+        // The fractions of a cycle are kept, so a second adds up to exactly 1.789773
+        // MHz, however it's split up.
+        let mut total = 1789;
+        for millisecond in 2..=1000 {
+            total += pacer.budget(start + Duration::from_millis(millisecond));
+        }
+        assert_eq!(total, 1_789_773);
+    }
+
+    #[test]
+    fn test_synchronized() {
+        let start = Duration::from_secs(5);
+        let mut pacer = Pacer::new(SYNCHRONIZED_HZ, start);
+        let budget: u64 = (1..=60)
+            .map(|frame| pacer.budget(start + Duration::from_secs(frame) / 60))
+            .sum();
+        // 60 frames of 341×262-0.5 PPU dots, at 3 dots per cycle.
+        assert_eq!(budget, 1_786_830);
+        assert_eq!((budget as f64 / 60.0) * 3.0, 341.0 * 262.0 - 0.5);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let start = Duration::from_secs(5);
+        let mut pacer = Pacer::ntsc(start);
+        pacer.set_multiplier(2.0, start);
+        assert_eq!(pacer.budget(start + Duration::from_millis(10)), 35_795);
+
+        let now = start + Duration::from_millis(10);
+        pacer.set_multiplier(0.5, now);
+        assert_eq!(pacer.budget(now + Duration::from_millis(10)), 8_948);
+    }
+
+    #[test]
+    fn test_drops_missed_time() {
+        let start = Duration::from_secs(5);
+        let mut pacer = Pacer::ntsc(start);
+        let max_catch_up = (NTSC_HZ * MAX_CATCH_UP.as_secs_f64()) as u64;
+        assert_eq!(pacer.budget(start + Duration::from_secs(10)), max_catch_up);
+        // It carries on from the new time, rather than owing the missed cycles.
+        assert_eq!(pacer.budget(start + Duration::from_secs(10)), 0);
+        assert_eq!(pacer.budget(start + Duration::from_millis(10_001)), 1789);
+    }
+
+    #[test]
+    fn test_time_until() {
+        let start = Duration::from_secs(5);
+        let mut pacer = Pacer::ntsc(start);
+        let frame = pacer.time_until(29_781, start);
+        assert_eq!(frame.as_micros(), 16_639);
+        pacer.budget(start + frame);
+        assert_eq!(pacer.time_until(0, start + frame), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let mut pacer = Pacer::ntsc(clock.now());
+        assert_eq!(pacer.budget(clock.now()), 0);
+        // A second goes by instantly, and is paced the same as the wall clock.
+        let mut total = 0;
+        for _ in 0..100 {
+            clock.advance(Duration::from_millis(10));
+            total += pacer.budget(clock.now());
+        }
+        assert_eq!(clock.now(), Duration::from_secs(1));
+        assert_eq!(total, 1_789_773);
+        clock.set(Duration::from_millis(1001));
+        assert_eq!(pacer.budget(clock.now()), 1789);
+    }
+}
@@ -60,7 +60,7 @@ pub mod memory_range {
 
 pub enum InterruptVectors {
     // The Non-Maskable Interrupt or NMI ($FFFA)
-    NonMaskableInterrupt = 0xFFFA,
+    NmiVector = 0xFFFA,
     ResetVector = 0xFFFC,
     IrqBrkVector = 0xFFFE,
 }
@@ -0,0 +1,215 @@
+use crate::bus::{Bus, BusDevice};
+use crate::prelude::*;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+/// The strobe is written to $4016 for both ports.
+pub const JOY1: u16 = 0x4016;
+pub const JOY2: u16 = 0x4017;
+
+/// The buttons of a standard controller, in the order the shift register hands
+/// them out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Which buttons are held, with A in the low bit, like movie::Ports.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ButtonState(pub u8);
+
+impl ButtonState {
+    pub fn is_pressed(self, button: Button) -> bool {
+        self.0 & button.mask() != 0
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.0 |= button.mask();
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.0 &= !button.mask();
+    }
+}
+
+#[derive(Default)]
+struct ShiftRegister {
+    buttons: ButtonState,
+    shift: u8,
+}
+
+/// A standard controller plugged into one of the ports. The port owns the shift
+/// register on the bus, and the frontend keeps this to set the buttons each frame.
+#[derive(Clone, Default)]
+pub struct Controller {
+    state: Rc<RefCell<ShiftRegister>>,
+}
+
+impl Controller {
+    /// The game only sees the new buttons once it strobes the controller.
+    pub fn set_buttons(&self, buttons: ButtonState) {
+        self.state.borrow_mut().buttons = buttons;
+    }
+
+    pub fn buttons(&self) -> ButtonState {
+        self.state.borrow().buttons
+    }
+}
+
+/// The controller ports at $4016 and $4017. Writing 1 to bit 0 of $4016 holds the
+/// strobe, which keeps loading the buttons into the shift registers, and writing 0
+/// releases it. Then each read hands out the next button in bit 0, and after all 8,
+/// a standard controller reads as 1. Only the low 5 bits are driven, so the rest read
+/// as the open bus, which is usually $40 from the high byte of the address.
+///
+/// Writes to $4017 belong to the APU's frame counter, so the ports ignore them.
+/// https://wiki.nesdev.com/w/index.php/Standard_controller
+pub struct ControllerPorts {
+    controllers: [Controller; 2],
+    strobe: bool,
+}
+
+impl ControllerPorts {
+    /// Attach the ports to the bus, and return the controllers plugged into them.
+    pub fn attach(bus: &mut Bus) -> Result<[Controller; 2], String> {
+        let controllers = [Controller::default(), Controller::default()];
+        let ports = ControllerPorts {
+            controllers: controllers.clone(),
+            strobe: false,
+        };
+        bus.attach_device(JOY1..=JOY2, Box::new(ports))?;
+        Ok(controllers)
+    }
+
+    fn controller(&self, address: u16) -> &Controller {
+        &self.controllers[(address - JOY1) as usize]
+    }
+
+    fn reload(&self) {
+        for controller in &self.controllers {
+            let mut state = controller.state.borrow_mut();
+            state.shift = state.buttons.0;
+        }
+    }
+}
+
+impl BusDevice for ControllerPorts {
+    fn name(&self) -> &str {
+        "controller ports"
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let mut state = self.controller(address).state.borrow_mut();
+        let bit = state.shift & 1;
+        state.shift = (state.shift >> 1) | 0x80;
+        bit
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address != JOY1 {
+            return;
+        }
+        // The buttons are loaded for as long as the strobe is held, so releasing it
+        // leaves them in the shift registers.
+        let was_strobe = self.strobe;
+        self.strobe = value & 1 == 1;
+        if self.strobe || was_strobe {
+            self.reload();
+        }
+    }
+
+    /// The button that the next read will return.
+    fn peek(&self, address: u16) -> Option<u8> {
+        let state = self.controller(address).state.borrow();
+        Some(if self.strobe {
+            state.buttons.0 & 1
+        } else {
+            state.shift & 1
+        })
+    }
+
+    fn driven_bits(&self, _address: u16) -> u8 {
+        0b0001_1111
+    }
+
+    /// The strobe, and what's left in the shift registers. The buttons are input
+    /// rather than state, so they are left to the frontend.
+    fn save_state(&self) -> Vec<u8> {
+        let [one, two] = &self.controllers;
+        vec![
+            self.strobe as u8,
+            one.state.borrow().shift,
+            two.state.borrow().shift,
+        ]
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let [strobe, one, two] = *state else {
+            return Err(format!(
+                "The state is {} bytes, but should be 3.",
+                state.len()
+            ));
+        };
+        self.strobe = strobe != 0;
+        self.controllers[0].state.borrow_mut().shift = one;
+        self.controllers[1].state.borrow_mut().shift = two;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mappers::SimpleProgram;
+
+    #[test]
+    fn test_shift_register() {
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        let [one, two] = ControllerPorts::attach(&mut bus).unwrap();
+        let mut buttons = ButtonState::default();
+        buttons.press(Button::A);
+        buttons.press(Button::Start);
+        one.set_buttons(buttons);
+        two.set_buttons(ButtonState(0b1000_0000));
+
+        // While the strobe is held, the reads keep returning A.
+        bus.set_u8(JOY1, 1);
+        assert_eq!(bus.read_u8(JOY1) & 1, 1);
+        assert_eq!(bus.read_u8(JOY1) & 1, 1);
+        bus.set_u8(JOY1, 0);
+
+        let read = |bus: &Bus, address| -> Vec<u8> {
+            (0..10).map(|_| bus.read_u8(address) & 1).collect()
+        };
+        assert_eq!(read(&bus, JOY1), [1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+        assert_eq!(read(&bus, JOY2), [0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        ControllerPorts::attach(&mut bus).unwrap();
+        // The CPU's last read is usually the high byte of the address.
+        bus.set_u8(0x0000, 0x40);
+        assert_eq!(bus.read_u8(JOY1), 0x40);
+        assert_eq!(bus.peek_u8(JOY2), 0x40);
+    }
+}
@@ -0,0 +1,1258 @@
+use crate::bus::{InstructionStart, SharedBus};
+use crate::constants::{memory_range, InterruptVectors};
+use crate::opcodes::{self, Mode, OpcodeEntry};
+use crate::prelude::*;
+use crate::uninit_ram::UninitRead;
+use crate::watchpoints::WatchpointHit;
+use branch_stats::BranchStats;
+use cpu_stats::CpuStats;
+use stack_analyzer::StackAnalyzer;
+use vblank_analyzer::VblankAnalyzer;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+pub mod branch_stats;
+pub mod cpu_stats;
+pub mod opcodes_65c02;
+pub mod opcodes_illegal;
+pub mod opcodes_jump;
+pub mod opcodes_logical;
+pub mod opcodes_move;
+pub mod stack_analyzer;
+pub mod vblank_analyzer;
+
+#[cfg(test)]
+pub(crate) mod test_helpers;
+
+// Test must be after test_helpers, rust format tries to move things around.
+#[cfg(test)]
+mod test;
+
+#[cfg(test)]
+mod processor_tests;
+
+// Mhz
+pub const CLOCK_SPEED: f64 = 1.789773;
+// Mhz
+pub const CLOCK_DIVISOR: u32 = 12;
+// Emulator authors may wish to emulate the NTSC NES/Famicom CPU at 21441960 Hz
+// ((341×262-0.5)×4×60) to ensure a synchronised/stable 60 frames per second.
+// Mhz
+pub const MASTER_CLOCK_FREQUENCY: f64 = 21.441960;
+// This is the true frequency:
+// const MASTER_CLOCK_FREQUENCY: f64 = 21.477272;
+// Mhz
+const COLOR_SUBCARRIER_FREQUENCY: f64 = 3.57954545;
+
+const RESET_STATUS_FLAG: u8 = 0b00110100;
+
+#[rustfmt::skip]
+pub enum StatusFlag {
+  Carry            = 0b00000001,
+  Zero             = 0b00000010,
+  InterruptDisable = 0b00000100,
+  Decimal          = 0b00001000,
+  Break            = 0b00010000,
+  Push             = 0b00100000,
+  Overflow         = 0b01000000,
+  Negative         = 0b10000000,
+}
+
+/// Some operations take longer depending on what happens while they run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtraCycle {
+    None,
+    /// An indexed read takes a cycle to fix up the high byte of the address when
+    /// adding the index crosses a page.
+    PageBoundary,
+    /// A branch takes a cycle when it is taken, and another when the target is on a
+    /// different page than the next instruction.
+    IfTaken,
+}
+
+impl ExtraCycle {
+    pub fn cycles(self) -> u8 {
+        match self {
+            ExtraCycle::None => 0,
+            ExtraCycle::PageBoundary | ExtraCycle::IfTaken => 1,
+        }
+    }
+}
+
+/// The IRQ line is shared by several pieces of hardware, and it stays asserted as
+/// long as any one of them is holding it. Each source needs to acknowledge its own
+/// interrupt before the line is released. The Mapper source follows the cartridge's
+/// Mapper::irq_asserted at the start of every tick.
+/// https://wiki.nesdev.com/w/index.php/IRQ
+#[rustfmt::skip]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+  FrameCounter = 0b001,
+  Dmc          = 0b010,
+  Mapper       = 0b100,
+}
+
+/// The NES's 2A03 has the decimal mode circuitry cut out, so the Decimal flag can be
+/// set, but ADC and SBC ignore it. Other 6502 systems use it for BCD arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DecimalMode {
+    Disabled,
+    /// Implement the NMOS 6502's BCD behavior, including the N, V, and Z flags, which
+    /// aren't meaningful for BCD, but match the real chip.
+    Enabled,
+}
+
+/// The 6502 family has chips that decode the opcodes differently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CpuVariant {
+    /// The original NMOS 6502, which is what the NES's 2A03 is based on. This
+    /// includes the undocumented opcodes, and the JMP indirect page wrap bug.
+    Nmos6502,
+    /// The WDC 65C02, which adds new instructions, fixes the JMP indirect bug, and
+    /// turns the undocumented opcodes into NOPs. STP halts the CPU, and WAI waits for
+    /// an interrupt.
+    Wdc65C02,
+}
+
+/// Whether the CPU is executing instructions, and if not, why it stopped. This is
+/// returned by every tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CpuState {
+    Running,
+    /// A KIL, or the 65C02's STP, stopped the CPU. Only a reset will start it again.
+    Jammed,
+    /// The 65C02's WAI is waiting for an NMI or IRQ. An IRQ wakes it up even when
+    /// interrupts are disabled, and then it moves on without servicing the IRQ.
+    WaitingForInterrupt,
+    /// The opcode was trapped by IllegalOpcodePolicy::Trap. The PC is left pointing
+    /// at it, and only a reset will start the CPU again.
+    IllegalOpcode(u8),
+}
+
+/// When Cpu6502::run should stop, on top of the CPU jamming or trapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopCondition {
+    /// Stop once at least this many cycles have run.
+    MaxCycles(u64),
+    /// Stop before running the instruction at the address. This stops right away if
+    /// the pc is already there.
+    PcEquals(u16),
+    /// Stop once a byte of memory has the value, which is checked between
+    /// instructions. Test ROMs often report their results this way.
+    MemoryEquals { address: u16, value: u8 },
+    /// Like PcEquals, but at least one instruction is run first, so that a run can
+    /// be continued from the breakpoint.
+    Breakpoint(u16),
+    /// Only stop when the CPU jams, e.g. on a KIL.
+    Jam,
+}
+
+/// Why Cpu6502::run stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    MaxCycles,
+    PcEquals,
+    MemoryEquals,
+    Breakpoint,
+    Jammed,
+    IllegalOpcode(u8),
+    /// An instruction touched a watched address, see Bus::add_watchpoint. The pc is
+    /// where the instruction started, and the hit is the first watched access it
+    /// made.
+    Watchpoint {
+        pc: u16,
+        hit: WatchpointHit,
+    },
+    /// An instruction read RAM that was never written, see UninitRamTrap.
+    UninitializedRead(UninitRead),
+}
+
+impl CpuState {
+    pub fn is_running(self) -> bool {
+        self == CpuState::Running
+    }
+}
+
+/// What to do when an NMOS 6502 program runs one of the undocumented opcodes. KIL is
+/// left out of this, as it's still used to halt the CPU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IllegalOpcodePolicy {
+    /// Run the opcode the way the real chip does, which some games rely on. The
+    /// unstable XAA, AHX, SHX, SHY and TAS opcodes use their commonly documented
+    /// behavior.
+    Emulate,
+    /// Skip over the opcode and its operand, taking the same number of cycles.
+    TreatAsNop,
+    /// Stop before the opcode is run with CpuState::IllegalOpcode. This lets test
+    /// harnesses fail fast on programs that go off the rails.
+    Trap,
+}
+
+/// A snapshot of the CPU registers, see the Cpu6502 fields for what each one does.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub pc: u16,
+    pub s: u8,
+    pub p: u8,
+}
+
+/// What the CPU is about to run, see Cpu6502::set_trace_hook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub opcode: u8,
+    /// The bytes after the opcode, which are 0 to 2 long depending on the mode.
+    pub operands: Vec<u8>,
+    pub mode: Mode,
+    /// The registers before the instruction runs, the PC points at the opcode.
+    pub registers: Registers,
+    /// The total cycles before the instruction runs.
+    pub cycles: u64,
+}
+
+pub type TraceHook = Box<dyn FnMut(&TraceEvent)>;
+
+/// This struct implements the CPU for the NES, the MOS Technology 6502.
+///
+/// http://www.6502.org/
+/// https://en.wikipedia.org/wiki/MOS_Technology_6502
+/// http://wiki.nesdev.com/w/index.php/CPU
+///
+/// With the "serde" feature, the CPU's own state can be serialized. The bus is shared
+/// with the rest of the machine, so it's left out. A deserialized CPU starts with an
+/// empty bus, so point it at the machine's bus before running it. The analyzers and
+/// the trace hook are tools rather than state, so they are left out too.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Cpu6502 {
+    // The bus is what holds all the memory access for the program.
+    #[cfg_attr(feature = "serde", serde(skip, default = "detached_bus"))]
+    pub bus: SharedBus,
+    // "A" register - The accumulator. Typical results of operations are stored here.
+    // In combination with the status register, supports using the status register for
+    // carrying, overflow detection, and so on.
+    pub a: u8,
+    /// "X" register.
+    /// Used for several addressing modes  They can be used as loop counters easily, using
+    /// INC/DEC and branch instructions. Not being the accumulator, they have limited
+    /// addressing modes themselves when loading and saving.
+    pub x: u8,
+    /// "Y" register.
+    pub y: u8,
+
+    /// "PC" - Program counter.
+    /// The 2-byte program counter PC supports 65536 direct (unbanked) memory locations,
+    /// however not all values are sent to the cartridge. It can be accessed either by
+    /// allowing CPU's internal fetch logic increment the address bus, an interrupt
+    /// (NMI, Reset, IRQ/BRQ), and using the RTS/JMP/JSR/Branch instructions.
+    /// "PC"
+    pub pc: u16,
+
+    /// "S" - Stack pointer
+    ///
+    /// The 6502 has hardware support for a stack implemented using a 256-byte array
+    /// whose location is hardcoded at page 0x01 (0x0100-0x01FF), using the S register
+    /// for a stack pointer.
+    ///
+    /// The 6502 uses a descending stack (it grows downwards)
+    /// https://wiki.nesdev.com/w/index.php/Stack
+    pub s: u8,
+
+    /// "P" - Status register.
+    /// P has 6 bits used by the ALU but is byte-wide. PHP, PLP, arithmetic, testing,
+    /// and branch instructions can access this register.
+    ///
+    /// http://wiki.nesdev.com/w/index.php/Status_flags
+    ///
+    ///   7  bit  0
+    /// ---- ----
+    /// NVss DIZC
+    /// |||| ||||
+    /// |||| |||+- Carry
+    /// |||| ||+-- Zero
+    /// |||| |+--- Interrupt Disable
+    /// |||| +---- Decimal
+    /// ||++------ No CPU effect, see: the B flag
+    /// |+-------- Overflow
+    /// +--------- Negative
+    pub p: u8,
+
+    /// The number of cycles that were done while operating on an instruction. The
+    /// emulator will then need to wait the proper amount of time after executing
+    /// the commands.
+    pub cycles: u16,
+
+    pub tick_count: u64,
+
+    /// Every cycle that has been run, including the DMA stalls.
+    pub total_cycles: u64,
+
+    /// How far the last run_for_cycles went past its budget. This is taken out of the
+    /// next budget.
+    pub overrun_cycles: u64,
+
+    /// Cycles where the CPU is halted by a DMA, which are run at the start of the
+    /// next tick. See Cpu6502::dma_stall.
+    pub dma_stall_cycles: u16,
+
+    /// The NMI line is edge triggered, so once it is signaled it stays latched until
+    /// the CPU gets around to servicing it at the start of the next tick.
+    pub nmi_pending: bool,
+
+    /// An NMI that is signaled partway through an instruction, once total_cycles
+    /// reaches this cycle. See Cpu6502::schedule_nmi.
+    pub scheduled_nmi: Option<u64>,
+
+    /// The cycle that the PPU enters vblank, see Cpu6502::schedule_vblank.
+    pub scheduled_vblank: Option<u64>,
+
+    /// A bitmask of the IrqSource values that are currently asserting the IRQ line.
+    pub irq_sources: u8,
+
+    /// CLI, SEI and PLP change the InterruptDisable flag too late for the IRQ check
+    /// before the next instruction, so that check sees the old value latched here.
+    pub interrupt_disable_latch: Option<bool>,
+
+    /// This defaults to Nmos6502, like the NES.
+    pub variant: CpuVariant,
+
+    /// This defaults to Disabled, like the NES. Enable it to use the core as a
+    /// generic 6502.
+    pub decimal_mode: DecimalMode,
+
+    /// This defaults to Emulate, like the NES. It has no effect on the 65C02, which
+    /// doesn't have any undocumented opcodes.
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+
+    pub state: CpuState,
+
+    /// Optionally collect branch taken/not-taken counts, and find the hot loops.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub branch_stats: Option<BranchStats>,
+
+    /// Optionally count the executions and cycles of every opcode and address.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stats: Option<CpuStats>,
+
+    /// Optionally track the stack depth, and warn when it gets into trouble.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stack_analyzer: Option<StackAnalyzer>,
+
+    /// Optionally measure the cycles spent in the NMI handler against the vblank budget.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub vblank_analyzer: Option<VblankAnalyzer>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace_hook: Option<TraceHook>,
+}
+
+/// A placeholder for deserialized CPUs, which don't have a bus of their own.
+#[cfg(feature = "serde")]
+fn detached_bus() -> SharedBus {
+    crate::bus::Bus::new_shared_bus(Box::new(crate::mappers::SimpleProgram::new()))
+}
+
+impl Cpu6502 {
+    pub fn new(bus: SharedBus) -> Cpu6502 {
+        // Go ahead and read the first instruction from the reset vector. If the reset
+        // vector is set again, the program will end.
+        let pc = bus.borrow().read_u16(InterruptVectors::ResetVector as u16);
+
+        Cpu6502 {
+            bus,
+            // Accumulator
+            a: 0,
+            // X & Y Registers.
+            x: 0,
+            y: 0,
+            // The program counter.
+            pc,
+            // Stack pointer - It grows down, so initialize it at the top.
+            s: 0xFF,
+            // Status register
+            p: 0b0011_0100,
+            cycles: 0,
+            tick_count: 0,
+            total_cycles: 0,
+            overrun_cycles: 0,
+            dma_stall_cycles: 0,
+            nmi_pending: false,
+            scheduled_nmi: None,
+            scheduled_vblank: None,
+            irq_sources: 0,
+            interrupt_disable_latch: None,
+            variant: CpuVariant::Nmos6502,
+            decimal_mode: DecimalMode::Disabled,
+            illegal_opcode_policy: IllegalOpcodePolicy::Emulate,
+            state: CpuState::Running,
+            branch_stats: None,
+            stats: None,
+            stack_analyzer: None,
+            vblank_analyzer: None,
+            trace_hook: None,
+        }
+    }
+
+    /// Create a CPU that starts from the given registers, instead of the power up
+    /// state and the reset vector. This is useful for running raw 6502 test vectors,
+    /// and for resuming from a snapshot.
+    pub fn with_state(bus: SharedBus, registers: Registers) -> Cpu6502 {
+        let mut cpu = Cpu6502::new(bus);
+        cpu.set_registers(registers);
+        cpu
+    }
+
+    /// Emulate the RES signal, like pressing the reset button on the console. The
+    /// reset sequence goes through the motions of an interrupt, but the stack writes
+    /// are turned into reads, so S is decremented by 3 without touching memory. The
+    /// other registers and the RAM are left alone. It takes 7 cycles, like an interrupt.
+    /// https://wiki.nesdev.com/w/index.php/CPU_power_up_state
+    pub fn reset(&mut self) {
+        self.s = self.s.wrapping_sub(3);
+        self.set_status_flag(StatusFlag::InterruptDisable, true);
+        self.pc = self
+            .bus
+            .borrow()
+            .read_u16(InterruptVectors::ResetVector as u16);
+        // A pending NMI is lost during the reset sequence, along with anything else
+        // that was waiting for the next tick.
+        self.nmi_pending = false;
+        self.scheduled_nmi = None;
+        self.dma_stall_cycles = 0;
+        self.interrupt_disable_latch = None;
+        self.state = CpuState::Running;
+        self.cycles = 7;
+        self.clock_mapper(7);
+    }
+
+    // This is the stable API for tools and tests that need to look at or change the
+    // CPU state from the outside.
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn s(&self) -> u8 {
+        self.s
+    }
+
+    /// The "P" register.
+    pub fn status(&self) -> u8 {
+        self.p
+    }
+
+    pub fn cycles(&self) -> u16 {
+        self.cycles
+    }
+
+    pub fn tick_count(&self) -> u64 {
+        self.tick_count
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn state(&self) -> CpuState {
+        self.state
+    }
+
+    pub fn set_a(&mut self, value: u8) {
+        self.a = value;
+    }
+
+    pub fn set_x(&mut self, value: u8) {
+        self.x = value;
+    }
+
+    pub fn set_y(&mut self, value: u8) {
+        self.y = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn set_s(&mut self, value: u8) {
+        self.s = value;
+    }
+
+    pub fn set_status(&mut self, value: u8) {
+        self.p = value;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            pc: self.pc,
+            s: self.s,
+            p: self.p,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        let Registers { a, x, y, pc, s, p } = registers;
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.pc = pc;
+        self.s = s;
+        self.p = p;
+    }
+
+    /// Call the hook before every instruction runs, which lets debuggers and loggers
+    /// watch the execution. Interrupts aren't instructions, so they don't trigger it.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TraceEvent) + 'static) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Look at a byte on the bus, like a debugger would. This doesn't have any of the
+    /// side effects of a read, see Bus::peek_u8.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.bus.borrow().peek_u8(address)
+    }
+
+    /// Write a byte to the bus, as the CPU would.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.bus.borrow_mut().set_u8(address, value);
+    }
+
+    /// Read the PC without incrementing.
+    fn peek_u8(&mut self) -> u8 {
+        self.bus.borrow().read_u8(self.pc)
+    }
+
+    /// Increment the program counter and read the next u8 value following
+    /// the current pc.
+    fn next_u8(&mut self) -> u8 {
+        let value = self.bus.borrow().read_u8(self.pc);
+        self.pc += 1;
+        value
+    }
+
+    /// Increment the program counter and read the next u16 value following
+    /// the current pc.
+    fn next_u16(&mut self) -> u16 {
+        let value = self.bus.borrow().read_u16(self.pc);
+        self.pc += 2;
+        value
+    }
+
+    /// Run instructions until the condition is met, or the CPU stops on its own. This
+    /// is the loop that tests and tools use to drive the CPU, without having to check
+    /// the CPU state themselves. Waiting for an interrupt doesn't stop the run, the
+    /// time keeps passing so that something can signal one.
+    pub fn run(&mut self, condition: StopCondition) -> StopReason {
+        let mut cycles = 0;
+        let mut instructions = 0;
+        loop {
+            match condition {
+                StopCondition::MaxCycles(max_cycles) if cycles >= max_cycles => {
+                    return StopReason::MaxCycles;
+                }
+                StopCondition::PcEquals(pc) if self.pc == pc => {
+                    return StopReason::PcEquals;
+                }
+                StopCondition::MemoryEquals { address, value }
+                    if self.peek(address) == value =>
+                {
+                    return StopReason::MemoryEquals;
+                }
+                StopCondition::Breakpoint(pc) if instructions > 0 && self.pc == pc => {
+                    return StopReason::Breakpoint;
+                }
+                _ => {}
+            }
+            let pc = self.pc;
+            match self.tick() {
+                CpuState::Running | CpuState::WaitingForInterrupt => {}
+                CpuState::Jammed => return StopReason::Jammed,
+                CpuState::IllegalOpcode(opcode) => {
+                    return StopReason::IllegalOpcode(opcode)
+                }
+            }
+            cycles += self.cycles as u64;
+            instructions += 1;
+            if let Some(hit) = self.take_watchpoint_hit() {
+                return StopReason::Watchpoint { pc, hit };
+            }
+            if let Some(read) = self.bus.borrow().take_uninit_break() {
+                return StopReason::UninitializedRead(read);
+            }
+        }
+    }
+
+    /// Take the first watchpoint hit of the last instruction.
+    fn take_watchpoint_hit(&self) -> Option<WatchpointHit> {
+        let bus = self.bus.borrow();
+        if !bus.has_watchpoint_hits() {
+            return None;
+        }
+        bus.take_watchpoint_hits().into_iter().next()
+    }
+
+    /// The source for the comments on the modes is coming from:
+    /// http://www.emulator101.com/6502-addressing-modes.html
+    fn get_operand_address(&mut self, mode: Mode, page_boundary_cycle: u8) -> u16 {
+        match mode {
+            // Absolute addressing specifies the memory location explicitly in the two bytes
+            // following the opcode. So JMP $4032 will set the PC to $4032. The hex for
+            // this is 4C 32 40, here 4C is the opcode. The 6502 is a little endian machine,
+            // so any 16 bit (2 byte) value is stored with the LSB first. All instructions
+            // that use absolute addressing are 3 bytes including the opcode.
+            Mode::Absolute => self.next_u16(),
+            // Absolute indexing gets the target address by adding the contents of the X or Y
+            // register to an absolute address. For example, this 6502 code can be used
+            // to fill 10 bytes with $FF starting at address $1009, counting down to
+            // address $1000.
+            //
+            //    LDA #$FF    ; Load 0xff into the A register
+            //    LDY #$09    ; Load 0x09 ito the Y register
+            //    loop:       ; Create a label
+            //    STA $1000,Y ; Store 0xff at address 0x1000 + Y
+            //    DEY         ; Decrement Y
+            //    BPL loop    ; Loop until Y is 0
+            Mode::AbsoluteIndexedX => {
+                let base_address = self.next_u16();
+                self.index_address(base_address, self.x, page_boundary_cycle)
+            }
+            Mode::AbsoluteIndexedY => {
+                let base_address = self.next_u16();
+                self.index_address(base_address, self.y, page_boundary_cycle)
+            }
+            // These instructions have their data defined as the next byte after the
+            // opcode. ORA #$B2 will perform a logical (also called bitwise) of the
+            // value B2 with the accumulator. Remember that in assembly when you see
+            // a # sign, it indicates an immediate value. If $B2 was written without
+            // a #, it would indicate an address or offset.
+            Mode::Immediate => {
+                // Return the current program counter as the address, but also increment
+                // the program counter.
+                let address = self.pc;
+                self.pc += 1;
+                address
+            }
+            // In an implied instruction, the data and/or destination is mandatory for
+            // the instruction. For example, the CLC instruction is implied, it is going
+            // to clear the processor's Carry flag.
+            Mode::Implied => {
+                panic!("An implied mode should never be directly activated.")
+            }
+            // The indirect addressing mode is similar to the absolute mode, but the
+            // next u16 is actually a pointer to another address. Use this next address
+            // for the operation.
+            Mode::Indirect => {
+                let address = self.next_u16();
+                return self.bus.borrow().read_u16(address);
+            }
+            Mode::IndirectX => self.next_u8().wrapping_add(self.x) as u16,
+            // Indirect indexed addressing reads a 16 bit pointer out of the zero page,
+            // and then adds Y to it. LDA ($10),Y with $10 = $00 and $11 = $02 and
+            // Y = $05 will load from $0205.
+            //
+            // 6502 bug: The pointer's high byte for ($ff),Y is read from $00, since
+            // the pointer can't leave the zero page.
+            Mode::IndirectY => {
+                let pointer = self.next_u8();
+                let base_address = {
+                    let bus = self.bus.borrow();
+                    u16::from_le_bytes([
+                        bus.read_u8(pointer as u16),
+                        bus.read_u8(pointer.wrapping_add(1) as u16),
+                    ])
+                };
+                self.index_address(base_address, self.y, page_boundary_cycle)
+            }
+            // Relative addressing on the 6502 is only used for branch operations. The byte
+            // after the opcode is the branch offset. If the branch is taken, the new address
+            // will the the current PC plus the offset. The offset is a signed byte, so it can
+            // jump a maximum of 127 bytes forward, or 128 bytes backward.
+            //
+            // For more info about signed numbers, check here:
+            // http://www.emulator101.com/more-about-binary-numbers.html
+            Mode::Relative => {
+                let relative_offset = self.next_u8() as i8;
+                // We already read the instruction and operand, which incremented the
+                // pc by 2 bytes. Get the base address by moving backwards 2.
+                let base_address = self.pc - 2;
+
+                // Due to the nature of binary representaion of numbers, just adding the
+                // negative number will result in it being subtract. It will wrap,
+                // hence allow the wrapping operation.
+                let offset_address = base_address.wrapping_add(relative_offset as u16);
+
+                // The page is compared against the next instruction, which is where
+                // the pc would have gone without the branch.
+                self.incur_extra_cycle_on_page_boundary(
+                    self.pc,
+                    offset_address,
+                    page_boundary_cycle,
+                );
+                offset_address
+            }
+            // Zero-Page is an addressing mode that is only capable of addressing the
+            // first 256 bytes of the CPU's memory map. You can think of it as absolute
+            // addressing for the first 256 bytes. The instruction LDA $35 will put the
+            // value stored in memory location $35 into A. The advantage of zero-page are
+            // two - the instruction takes one less byte to specify, and it executes in
+            // less CPU cycles. Most programs are written to store the most frequently
+            // used variables in the first 256 memory locations so they can take advantage
+            // of zero page addressing.
+            Mode::ZeroPage => self.next_u8() as u16,
+            // This works just like absolute indexed, but the target address is limited to
+            // the first 0xFF bytes. The target address will wrap around and will always
+            // be in the zero page. If the instruction is LDA $C0,X, and X is $60, then
+            // the target address will be $20. $C0+$60 = $120, but the carry is discarded
+            // in the calculation of the target address.
+            //
+            // 6502 bug: Zeropage index will not leave zeropage when page boundary is crossed.
+            //           Make sure and do a wrapping add in u8 space.
+            Mode::ZeroPageX => (self.next_u8().wrapping_add(self.x)) as u16,
+            Mode::ZeroPageY => (self.next_u8().wrapping_add(self.y)) as u16,
+            // For some reason NOP is being called with this. Originally it was
+            // a panic.
+            Mode::None => 0,
+        }
+    }
+
+    fn get_operand(&mut self, mode: Mode, extra_cycle: u8) -> (u16, u8) {
+        let address = self.get_operand_address(mode, extra_cycle);
+        let value = self.bus.borrow().read_u8(address);
+        (address, value)
+    }
+
+    /// Indexing first only adds to the low byte of the address, and the CPU reads from
+    /// that partial address while the carry goes into the high byte. Reads that stay
+    /// on the page skip the fix up, but stores and read-modify-write instructions
+    /// always take it, as they can't undo a write to the wrong address. The 65C02
+    /// re-reads the last operand byte instead.
+    fn index_address(
+        &mut self,
+        base_address: u16,
+        index: u8,
+        page_boundary_cycle: u8,
+    ) -> u16 {
+        let offset_address = base_address.wrapping_add(index as u16);
+        let [base_low, base_page] = base_address.to_le_bytes();
+        if base_low.checked_add(index).is_none() || page_boundary_cycle == 0 {
+            let dummy_address = match self.variant {
+                CpuVariant::Nmos6502 => {
+                    u16::from_le_bytes([base_low.wrapping_add(index), base_page])
+                }
+                CpuVariant::Wdc65C02 => self.pc.wrapping_sub(1),
+            };
+            self.bus.borrow().dummy_read_u8(dummy_address);
+        }
+        self.incur_extra_cycle_on_page_boundary(
+            base_address,
+            offset_address,
+            page_boundary_cycle,
+        );
+        offset_address
+    }
+
+    /// Read-modify-write instructions write the unmodified value back while they work
+    /// out the result, and then write the result. The 65C02 reads the address again
+    /// instead.
+    fn write_modified_operand(&mut self, address: u16, operand: u8, result: u8) {
+        let mut bus = self.bus.borrow_mut();
+        match self.variant {
+            CpuVariant::Nmos6502 => bus.set_u8(address, operand),
+            CpuVariant::Wdc65C02 => bus.dummy_read_u8(address),
+        }
+        bus.set_u8(address, result);
+    }
+
+    fn incur_extra_cycle_on_page_boundary(
+        &mut self,
+        base_address: u16,
+        offset_address: u16,
+        extra_cycles: u8,
+    ) {
+        let [_, base_page] = base_address.to_le_bytes();
+        let [_, offset_page] = offset_address.to_le_bytes();
+        if base_page != offset_page {
+            self.cycles += extra_cycles as u16;
+        }
+    }
+
+    /// Does one operational tick of the CPU, and returns the state it's left in. Once
+    /// the CPU is Jammed, or has trapped an IllegalOpcode, the ticks do nothing.
+    pub fn tick(&mut self) -> CpuState {
+        self.cycles = 0;
+        match self.state {
+            CpuState::Running | CpuState::WaitingForInterrupt => {}
+            CpuState::Jammed | CpuState::IllegalOpcode(_) => return self.state,
+        }
+        self.tick_count += 1;
+
+        // A DMA halts the CPU before it can start on the next instruction. The mapper
+        // sees these cycles first, and the rest of the tick's cycles at the end.
+        let stall = core::mem::take(&mut self.dma_stall_cycles);
+        self.cycles = stall;
+        self.clock_mapper(stall);
+
+        let instruction_pc = self.pc;
+        if let Some(ref mut stack_analyzer) = self.stack_analyzer {
+            stack_analyzer.record_instruction(instruction_pc);
+        }
+
+        self.poll_mapper_irq();
+        let interrupt_disable = match self.interrupt_disable_latch.take() {
+            Some(interrupt_disable) => interrupt_disable,
+            None => self.is_status_flag_set(StatusFlag::InterruptDisable),
+        };
+
+        if self
+            .scheduled_vblank
+            .is_some_and(|cycle| cycle <= self.total_cycles)
+        {
+            self.start_vblank();
+        }
+        self.poll_scheduled_nmi(self.total_cycles);
+
+        // Interrupts are checked between instructions, and take a full tick.
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.state = CpuState::Running;
+            self.observe_instruction(instruction_pc, false);
+            self.handle_nmi();
+            self.clock_mapper(self.cycles - stall);
+            return self.state;
+        }
+        if self.irq_sources != 0 {
+            self.state = CpuState::Running;
+            if !interrupt_disable {
+                self.observe_instruction(instruction_pc, false);
+                self.handle_irq();
+                self.clock_mapper(self.cycles - stall);
+                return self.state;
+            }
+        }
+        if self.state == CpuState::WaitingForInterrupt {
+            // Let the time pass, so that something can signal an interrupt.
+            self.observe_instruction(instruction_pc, false);
+            self.cycles += 1;
+            self.clock_mapper(self.cycles - stall);
+            return self.state;
+        }
+
+        self.observe_instruction(instruction_pc, true);
+        let opcode = self.next_u8();
+
+        // The NMOS 6502 has twelve opcodes that jam it, and the 65C02 has STP.
+        let is_halt = match self.variant {
+            CpuVariant::Nmos6502 => opcodes::is_kil(opcode),
+            CpuVariant::Wdc65C02 => opcode == opcodes::WDC_65C02_STP,
+        };
+        if is_halt {
+            self.state = CpuState::Jammed;
+            return self.state;
+        }
+
+        // The operations are all contained in tables that match up the opcode to its
+        // particular implementation details.
+        let OpcodeEntry {
+            operation: mut operation_fn,
+            mode,
+            cycles,
+            extra_cycle,
+            ..
+        } = opcodes::decode(self.variant, opcode);
+
+        if let Some(ref mut trace_hook) = self.trace_hook {
+            let operands_pc = self.pc;
+            let operands = {
+                let bus = self.bus.borrow();
+                (0..mode.operand_size())
+                    .map(|offset| bus.peek_u8(operands_pc.wrapping_add(offset)))
+                    .collect()
+            };
+            trace_hook(&TraceEvent {
+                opcode,
+                operands,
+                mode,
+                registers: Registers {
+                    pc: instruction_pc,
+                    a: self.a,
+                    x: self.x,
+                    y: self.y,
+                    s: self.s,
+                    p: self.p,
+                },
+                cycles: self.total_cycles,
+            });
+        }
+
+        if self.illegal_opcode_policy != IllegalOpcodePolicy::Emulate
+            && self.variant == CpuVariant::Nmos6502
+            && opcodes::is_illegal(opcode)
+        {
+            match self.illegal_opcode_policy {
+                IllegalOpcodePolicy::Emulate => {}
+                IllegalOpcodePolicy::TreatAsNop => {
+                    // The NOP still goes through the addressing mode, so the PC ends
+                    // up past the operand.
+                    operation_fn = opcodes_jump::nop;
+                }
+                IllegalOpcodePolicy::Trap => {
+                    self.pc = instruction_pc;
+                    self.state = CpuState::IllegalOpcode(opcode);
+                    return self.state;
+                }
+            }
+        }
+        self.cycles += cycles as u16;
+
+        // The reads of an instruction that loads from an address happen on its last
+        // cycle, so that's where a PPUSTATUS read can race the vblank.
+        let read_cycle = self.total_cycles + cycles as u64 - 1;
+        let vblank_race = match self.scheduled_vblank {
+            Some(cycle) if cycle < read_cycle => {
+                self.start_vblank();
+                false
+            }
+            Some(cycle) => cycle == read_cycle,
+            None => false,
+        };
+        if vblank_race {
+            self.bus.borrow().take_ppu_status_read();
+        }
+
+        operation_fn(self, mode, extra_cycle.cycles());
+
+        if vblank_race && self.bus.borrow().take_ppu_status_read() {
+            // The read saw the flag still clear, and the flag and the NMI are lost.
+            self.scheduled_vblank = None;
+        }
+
+        if let Some(ref mut branch_stats) = self.branch_stats {
+            branch_stats.record_instruction(instruction_pc, self.cycles as u64);
+        }
+        if let Some(ref mut stats) = self.stats {
+            stats.record_instruction(
+                instruction_pc,
+                opcode,
+                (self.cycles - stall) as u64,
+            );
+        }
+        if let Some(ref mut vblank_analyzer) = self.vblank_analyzer {
+            vblank_analyzer.record_instruction(self.cycles as u64);
+        }
+        self.clock_mapper(self.cycles - stall);
+
+        // Writing to $4014 halts the CPU while 256 bytes are copied to the OAM, with
+        // a read and a write for each. There's one cycle to wait for the write to
+        // finish, and another if the DMA needs to line up with an even cycle.
+        if self.bus.borrow_mut().take_pending_oam_dma().is_some() {
+            self.dma_stall(if self.total_cycles % 2 == 1 { 514 } else { 513 });
+        }
+
+        self.state
+    }
+
+    /// Run instructions until the budget of cycles is used up, and return how many
+    /// cycles actually ran. This is how frontends run a frame's worth of cycles at a
+    /// time. Instructions don't line up with the budget, so the last one usually runs
+    /// over, and the extra cycles are taken out of the next budget. This stops early if
+    /// the CPU is jammed or trapped. To run at the speed of the hardware, take the
+    /// budget from a `clock::Pacer`.
+    pub fn run_for_cycles(&mut self, budget: u64) -> u64 {
+        if self.overrun_cycles >= budget {
+            self.overrun_cycles -= budget;
+            return 0;
+        }
+        let target = budget - self.overrun_cycles;
+        let mut cycles = 0;
+        while cycles < target {
+            match self.tick() {
+                CpuState::Running | CpuState::WaitingForInterrupt => {}
+                CpuState::Jammed | CpuState::IllegalOpcode(_) => {
+                    self.overrun_cycles = 0;
+                    return cycles;
+                }
+            }
+            cycles += self.cycles as u64;
+        }
+        self.overrun_cycles = cycles - target;
+        cycles
+    }
+
+    /// Halt the CPU for some cycles, like the OAM and DMC DMAs do. The stall is run at
+    /// the start of the next tick, and is included in its cycles.
+    pub fn dma_stall(&mut self, cycles: u16) {
+        self.dma_stall_cycles += cycles;
+    }
+
+    /// Tell the bus's observers about the instruction at the pc, before it's fetched,
+    /// with a single borrow of the bus. Taking an interrupt, or waiting for one,
+    /// doesn't run an instruction, so it has a size of 0.
+    fn observe_instruction(&self, pc: u16, runs_instruction: bool) {
+        let mut bus = self.bus.borrow_mut();
+        if !bus.has_observers() {
+            return;
+        }
+        let size = if runs_instruction {
+            1 + opcodes::decode(self.variant, bus.peek_u8(pc))
+                .mode
+                .operand_size()
+        } else {
+            0
+        };
+        bus.record_instruction(&InstructionStart {
+            pc,
+            cycle: self.total_cycles,
+            size,
+        });
+    }
+
+    /// Run the mapper's clock for the cycles of this tick. See the Mapper trait for
+    /// the ordering.
+    fn clock_mapper(&mut self, cycles: u16) {
+        self.total_cycles += cycles as u64;
+        self.bus.borrow_mut().clock_cpu_cycles(cycles);
+    }
+
+    /// The Mapper source of the IRQ line follows the cartridge.
+    fn poll_mapper_irq(&mut self) {
+        if self.bus.borrow().is_mapper_irq_asserted() {
+            self.assert_irq(IrqSource::Mapper);
+        } else {
+            self.acknowledge_irq(IrqSource::Mapper);
+        }
+    }
+
+    /// Signal a non-maskable interrupt, e.g. when the PPU enters vblank. It will be
+    /// serviced before the next instruction, regardless of the InterruptDisable flag.
+    pub fn set_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Signal an NMI once total_cycles reaches the cycle, like the PPU does when it
+    /// enters vblank partway through an instruction. This is usually the same as
+    /// calling set_nmi before the next instruction, but the timing matters for BRK,
+    /// which the NMI can hijack.
+    pub fn schedule_nmi(&mut self, cycle: u64) {
+        self.scheduled_nmi = Some(cycle);
+    }
+
+    /// Set the vblank flag in PPUSTATUS once total_cycles reaches the cycle, and signal
+    /// the NMI if PPUCTRL has it turned on. This is for frame loops driving a PPU that
+    /// doesn't keep its own timing. Reading PPUSTATUS on the very cycle that the flag
+    /// is set reads it as clear, and the flag and the NMI never happen, which games
+    /// polling PPUSTATUS in a loop will sometimes hit.
+    /// https://wiki.nesdev.com/w/index.php/PPU_frame_timing#VBL_Flag_Timing
+    pub fn schedule_vblank(&mut self, cycle: u64) {
+        self.scheduled_vblank = Some(cycle);
+    }
+
+    fn start_vblank(&mut self) {
+        let cycle = match self.scheduled_vblank.take() {
+            Some(cycle) => cycle,
+            None => return,
+        };
+        let mut bus = self.bus.borrow_mut();
+        bus.set_vblank(true);
+        let nmi_enabled = bus.is_nmi_enabled();
+        drop(bus);
+        if nmi_enabled {
+            self.schedule_nmi(cycle);
+        }
+    }
+
+    /// Latch the scheduled NMI if it was signaled by the cycle.
+    fn poll_scheduled_nmi(&mut self, cycle: u64) {
+        match self.scheduled_nmi {
+            Some(nmi_cycle) if nmi_cycle <= cycle => {
+                self.scheduled_nmi = None;
+                self.nmi_pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Hold the IRQ line for a given source. It will be serviced before the next
+    /// instruction where the InterruptDisable flag is clear.
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq_sources |= source as u8;
+    }
+
+    /// Release the IRQ line for a given source. Other sources may still be holding it.
+    pub fn acknowledge_irq(&mut self, source: IrqSource) {
+        self.irq_sources &= !(source as u8);
+    }
+
+    pub fn is_irq_asserted(&self) -> bool {
+        self.irq_sources != 0
+    }
+
+    /// The IRQ line is polled before the last cycle of an instruction, so when CLI,
+    /// SEI or PLP change the InterruptDisable flag, it doesn't take effect until after
+    /// the next instruction. RTI changes the flag earlier, and doesn't have the delay.
+    /// https://wiki.nesdev.com/w/index.php/CPU_interrupts#Delayed_IRQ_response_after_CLI.2C_SEI.2C_and_PLP
+    fn latch_interrupt_disable(&mut self) {
+        self.interrupt_disable_latch =
+            Some(self.is_status_flag_set(StatusFlag::InterruptDisable));
+    }
+
+    /// These flags are commonly set together.
+    fn update_zero_and_negative_flag(&mut self, value: u8) {
+        // Numbers can be interpreted as signed or unsigned. The negative flag only
+        // cares if the most-significant bit is 1 or 0.
+        let negative = 0b1000_0000;
+        self.set_status_flag(StatusFlag::Zero, value == 0);
+        self.set_status_flag(StatusFlag::Negative, value & negative == negative);
+    }
+
+    /// ADC and SBC operate on 9 bits. 8 of them are the register A, while the last bit
+    /// is the carry flag. Store this 9th bit onto the status flag.
+    fn update_carry_flag(&mut self, result: u16) {
+        let carry = 0b1_0000_0000;
+        self.set_status_flag(StatusFlag::Carry, result & carry == carry);
+    }
+
+    /// Overflow for ADC and SBC indicates if we overflow from bit 6 to bit 7 of the u8,
+    /// and change the meaning of a number from being negative or positive.
+    /// e.g. 0b0111_1111 + 0b0000_0001 = 0b1000_0000
+    ///        |             |             |
+    ///        positive      positive      negative result
+    fn update_overflow_flag(&mut self, operand: u8, result: u8) {
+        let bit_7_mask = 0b1000_0000;
+
+        let does_overflow = (
+            // Only look at bit 7, the most significant bit (MSB)
+            bit_7_mask &
+        // A and operand have the same MSB.
+        !(self.a ^ operand) &
+        // A and result have a different MSB
+        (self.a ^ result)
+        ) == bit_7_mask; // Are both conditions correct as commented above?
+
+        self.set_status_flag(StatusFlag::Overflow, does_overflow);
+    }
+
+    fn set_status_flag(&mut self, status_flag: StatusFlag, value: bool) {
+        if value {
+            self.p |= status_flag as u8;
+        } else {
+            self.p &= !(status_flag as u8);
+        }
+    }
+
+    fn get_carry(&self) -> u8 {
+        self.p & (StatusFlag::Carry as u8)
+    }
+
+    fn is_status_flag_set(&self, status_flag: StatusFlag) -> bool {
+        let flag = status_flag as u8;
+        self.p & flag == flag
+    }
+
+    /// This function implements pushing to the stack.
+    /// See the "S" register for more details.
+    fn push_stack_u8(&mut self, value: u8) {
+        if let Some(ref mut stack_analyzer) = self.stack_analyzer {
+            stack_analyzer.record_push(self.s);
+        }
+        // The stack page is hard coded.
+        let address = u16::from_le_bytes([self.s, memory_range::STACK_PAGE]);
+        // The stack points to the next available memory.
+        self.bus.borrow_mut().set_u8(address, value);
+        // Grow down only after setting the memory.
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    /// This function implements pulling to the stack.
+    /// See the "S" register for more details.
+    fn pull_stack_u8(&mut self) -> u8 {
+        if let Some(ref mut stack_analyzer) = self.stack_analyzer {
+            stack_analyzer.record_pull(self.s);
+        }
+        // The current stack pointer points at available memory, decrement it first.
+        self.s = self.s.wrapping_add(1);
+        // Now read out the memory that is being pulled.
+        let address = u16::from_le_bytes([self.s, memory_range::STACK_PAGE]);
+        self.bus.borrow().read_u8(address)
+    }
+
+    /// This function implements pushing to the stack. The high byte goes first, and
+    /// each byte is pushed on its own, so the stack wraps within page 1 like it does
+    /// on the CPU.
+    fn push_stack_u16(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.push_stack_u8(high);
+        self.push_stack_u8(low);
+    }
+
+    /// This function implements pulling from the stack, low byte first, one byte at
+    /// a time like push_stack_u16.
+    fn pull_stack_u16(&mut self) -> u16 {
+        let low = self.pull_stack_u8();
+        let high = self.pull_stack_u8();
+        u16::from_le_bytes([low, high])
+    }
+
+    /// Push the PC and status register, and jump to the address in the NMI vector.
+    /// The B flag is only set on the stack when the interrupt comes from a BRK.
+    /// https://wiki.nesdev.com/w/index.php/CPU_interrupts
+    fn handle_nmi(&mut self) {
+        if let Some(ref mut stack_analyzer) = self.stack_analyzer {
+            stack_analyzer.enter_interrupt();
+        }
+        self.push_stack_u16(self.pc);
+        self.push_stack_u8(
+            (self.p & !(StatusFlag::Break as u8)) | StatusFlag::Push as u8,
+        );
+        self.set_status_flag(StatusFlag::InterruptDisable, true);
+        self.pc = self
+            .bus
+            .borrow()
+            .read_u16(InterruptVectors::NmiVector as u16);
+        self.cycles += 7;
+        if let Some(ref mut vblank_analyzer) = self.vblank_analyzer {
+            vblank_analyzer.enter_nmi(self.pc, self.cycles as u64);
+        }
+    }
+
+    /// Push the PC and status register, and jump to the address in the IRQ/BRK
+    /// vector. Like the NMI, the B flag is left clear on the stack.
+    fn handle_irq(&mut self) {
+        if let Some(ref mut stack_analyzer) = self.stack_analyzer {
+            stack_analyzer.enter_interrupt();
+        }
+        self.push_stack_u16(self.pc);
+        self.push_stack_u8(
+            (self.p & !(StatusFlag::Break as u8)) | StatusFlag::Push as u8,
+        );
+        self.set_status_flag(StatusFlag::InterruptDisable, true);
+        self.pc = self
+            .bus
+            .borrow()
+            .read_u16(InterruptVectors::IrqBrkVector as u16);
+        self.cycles += 7;
+    }
+}
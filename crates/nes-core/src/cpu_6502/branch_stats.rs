@@ -0,0 +1,141 @@
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// The number of times a single branch instruction was taken or skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCounts {
+    pub fn total(&self) -> u64 {
+        self.taken + self.not_taken
+    }
+}
+
+/// A loop is any branch that jumps backwards. The body of the loop is everything
+/// from the branch target up to and including the branch instruction.
+///
+/// $8002 loop: dex      <- start
+/// $8003       bne loop <- end
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotLoop {
+    pub start: u16,
+    pub end: u16,
+    /// How many times the backwards branch was taken.
+    pub iterations: u64,
+    /// The cycles spent on instructions inside of the loop body.
+    pub cycles: u64,
+    /// The percentage of all of the executed cycles, ranged 0.0 to 1.0.
+    pub cycle_share: f64,
+}
+
+/// Collects the taken/not-taken counts for every branch instruction, and enough
+/// cycle information to figure out which loops the program spends its time in.
+/// This is opt-in, as it does a few map lookups per instruction.
+pub struct BranchStats {
+    branches: BTreeMap<u16, BranchCounts>,
+    // Maps the address of the branch instruction to the address it branches to.
+    targets: BTreeMap<u16, u16>,
+    cycles_by_pc: BTreeMap<u16, u64>,
+    total_cycles: u64,
+}
+
+impl BranchStats {
+    pub fn new() -> BranchStats {
+        BranchStats {
+            branches: BTreeMap::new(),
+            targets: BTreeMap::new(),
+            cycles_by_pc: BTreeMap::new(),
+            total_cycles: 0,
+        }
+    }
+
+    pub fn record_branch(&mut self, pc: u16, target: u16, taken: bool) {
+        let counts = self.branches.entry(pc).or_default();
+        if taken {
+            counts.taken += 1;
+        } else {
+            counts.not_taken += 1;
+        }
+        self.targets.insert(pc, target);
+    }
+
+    pub fn record_instruction(&mut self, pc: u16, cycles: u64) {
+        *self.cycles_by_pc.entry(pc).or_insert(0) += cycles;
+        self.total_cycles += cycles;
+    }
+
+    pub fn branch_counts(&self, pc: u16) -> Option<BranchCounts> {
+        self.branches.get(&pc).copied()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Find all of the backwards branches that were taken, sorted so that the loops
+    /// that used the most cycles come first.
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut hot_loops: Vec<HotLoop> = self
+            .branches
+            .iter()
+            .filter_map(|(&end, counts)| {
+                let start = *self.targets.get(&end)?;
+                if start > end || counts.taken == 0 {
+                    return None;
+                }
+                let cycles: u64 = self
+                    .cycles_by_pc
+                    .iter()
+                    .filter(|(&pc, _)| pc >= start && pc <= end)
+                    .map(|(_, cycles)| cycles)
+                    .sum();
+                Some(HotLoop {
+                    start,
+                    end,
+                    iterations: counts.taken,
+                    cycles,
+                    cycle_share: if self.total_cycles == 0 {
+                        0.0
+                    } else {
+                        cycles as f64 / self.total_cycles as f64
+                    },
+                })
+            })
+            .collect();
+
+        hot_loops.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+        hot_loops
+    }
+
+    /// Create a human readable report of the branches and the hottest loops.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "Branch statistics ({} branches, {} cycles)\n",
+            self.branches.len(),
+            self.total_cycles
+        );
+
+        for (pc, counts) in &self.branches {
+            report.push_str(&format!(
+                "  ${:04x}  taken {:>8}  not taken {:>8}\n",
+                pc, counts.taken, counts.not_taken
+            ));
+        }
+
+        report.push_str("Hot loops\n");
+        for hot_loop in self.hot_loops() {
+            report.push_str(&format!(
+                "  ${:04x}-${:04x}  iterations {:>8}  cycles {:>10}  {:>5.1}%\n",
+                hot_loop.start,
+                hot_loop.end,
+                hot_loop.iterations,
+                hot_loop.cycles,
+                hot_loop.cycle_share * 100.0
+            ));
+        }
+        report
+    }
+}
@@ -0,0 +1,147 @@
+use crate::opcodes::OPCODES;
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// How many times something ran, and the cycles it took.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ExecutionCounts {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+impl ExecutionCounts {
+    fn record(&mut self, cycles: u64) {
+        self.executions += 1;
+        self.cycles += cycles;
+    }
+}
+
+/// Counts the executions and cycles of every opcode and every instruction address,
+/// to find where a program spends its time. The cycles are also split up by frame,
+/// to check them against the frame's budget. The frames are ended by the caller, e.g.
+/// Emulator::run_frame does it after every frame. This is opt-in, as it does a map
+/// lookup per instruction.
+pub struct CpuStats {
+    opcodes: [ExecutionCounts; 256],
+    pcs: BTreeMap<u16, ExecutionCounts>,
+    total: ExecutionCounts,
+    /// The total cycles when the current frame started.
+    frame_start: u64,
+    frame_cycles: Vec<u64>,
+}
+
+impl CpuStats {
+    pub fn new() -> CpuStats {
+        CpuStats {
+            opcodes: [ExecutionCounts::default(); 256],
+            pcs: BTreeMap::new(),
+            total: ExecutionCounts::default(),
+            frame_start: 0,
+            frame_cycles: Vec::new(),
+        }
+    }
+
+    /// The cycles include any page crossing or branch penalties, but not DMA stalls or
+    /// interrupts, which don't belong to the instruction.
+    pub fn record_instruction(&mut self, pc: u16, opcode: u8, cycles: u64) {
+        self.opcodes[opcode as usize].record(cycles);
+        self.pcs.entry(pc).or_default().record(cycles);
+        self.total.record(cycles);
+    }
+
+    /// Finish the current frame, and return how many instruction cycles it used.
+    pub fn end_frame(&mut self) -> u64 {
+        let cycles = self.total.cycles - self.frame_start;
+        self.frame_start = self.total.cycles;
+        self.frame_cycles.push(cycles);
+        cycles
+    }
+
+    pub fn opcode_counts(&self, opcode: u8) -> ExecutionCounts {
+        self.opcodes[opcode as usize]
+    }
+
+    pub fn pc_counts(&self, pc: u16) -> Option<ExecutionCounts> {
+        self.pcs.get(&pc).copied()
+    }
+
+    pub fn total(&self) -> ExecutionCounts {
+        self.total
+    }
+
+    /// The instruction cycles of every frame that was ended.
+    pub fn frame_cycles(&self) -> &[u64] {
+        &self.frame_cycles
+    }
+
+    /// The opcodes that ran, sorted so that the ones that used the most cycles come
+    /// first.
+    pub fn hot_opcodes(&self) -> Vec<(u8, ExecutionCounts)> {
+        let mut opcodes: Vec<(u8, ExecutionCounts)> = (0..=255)
+            .map(|opcode| (opcode, self.opcodes[opcode as usize]))
+            .filter(|(_, counts)| counts.executions > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        opcodes
+    }
+
+    /// The instruction addresses that ran, sorted so that the ones that used the most
+    /// cycles come first.
+    pub fn hot_pcs(&self) -> Vec<(u16, ExecutionCounts)> {
+        let mut pcs: Vec<(u16, ExecutionCounts)> =
+            self.pcs.iter().map(|(&pc, &counts)| (pc, counts)).collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        pcs
+    }
+
+    /// Create a human readable report of the hottest opcodes and addresses, and the
+    /// frame budgets.
+    pub fn report(&self, limit: usize) -> String {
+        let mut report = format!(
+            "CPU statistics ({} instructions, {} cycles)\n",
+            self.total.executions, self.total.cycles
+        );
+
+        report.push_str("Hot opcodes\n");
+        for (opcode, counts) in self.hot_opcodes().into_iter().take(limit) {
+            report.push_str(&format!(
+                "  ${:02x} {}  executions {:>10}  cycles {:>10}  {:>5.1}%\n",
+                opcode,
+                OPCODES[opcode as usize].name,
+                counts.executions,
+                counts.cycles,
+                self.cycle_share(counts) * 100.0
+            ));
+        }
+
+        report.push_str("Hot addresses\n");
+        for (pc, counts) in self.hot_pcs().into_iter().take(limit) {
+            report.push_str(&format!(
+                "  ${:04x}  executions {:>10}  cycles {:>10}  {:>5.1}%\n",
+                pc,
+                counts.executions,
+                counts.cycles,
+                self.cycle_share(counts) * 100.0
+            ));
+        }
+
+        if let Some(&max) = self.frame_cycles.iter().max() {
+            let sum: u64 = self.frame_cycles.iter().sum();
+            report.push_str(&format!(
+                "Frames {}  average cycles {}  max cycles {}\n",
+                self.frame_cycles.len(),
+                sum / self.frame_cycles.len() as u64,
+                max
+            ));
+        }
+        report
+    }
+
+    fn cycle_share(&self, counts: ExecutionCounts) -> f64 {
+        if self.total.cycles == 0 {
+            0.0
+        } else {
+            counts.cycles as f64 / self.total.cycles as f64
+        }
+    }
+}
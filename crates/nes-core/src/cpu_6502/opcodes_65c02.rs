@@ -0,0 +1,87 @@
+use crate::cpu_6502::opcodes_jump::branch;
+use crate::cpu_6502::*;
+
+// Instructions that were added in the WDC 65C02. They are only decoded when the CPU
+// is using the Wdc65C02 variant, see opcodes::decode.
+
+/// Push X to the stack
+/// Function: (S)-:=X
+/// Flags:
+pub fn phx(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.push_stack_u8(cpu.x);
+}
+
+/// Pull X
+/// Function: X:=+(S)
+/// Flags: N Z
+pub fn plx(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.x = cpu.pull_stack_u8();
+    cpu.update_zero_and_negative_flag(cpu.x);
+}
+
+/// Push Y to the stack
+/// Function: (S)-:=Y
+/// Flags:
+pub fn phy(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.push_stack_u8(cpu.y);
+}
+
+/// Pull Y
+/// Function: Y:=+(S)
+/// Flags: N Z
+pub fn ply(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.y = cpu.pull_stack_u8();
+    cpu.update_zero_and_negative_flag(cpu.y);
+}
+
+/// Store zero
+/// Function: {adr}:=0
+/// Flags:
+pub fn stz(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let address = cpu.get_operand_address(mode, extra_cycle);
+    cpu.bus.borrow_mut().set_u8(address, 0);
+}
+
+/// Branch always
+/// Function: branch
+/// Flags:
+pub fn bra(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    branch(cpu, mode, extra_cycle, true);
+}
+
+/// Test and set bits
+/// Function: Z:=A&{adr} {adr}:={adr}|A
+/// Flags: Z
+pub fn tsb(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.set_status_flag(StatusFlag::Zero, cpu.a & operand == 0);
+    cpu.bus.borrow_mut().set_u8(address, operand | cpu.a);
+}
+
+/// Test and reset bits
+/// Function: Z:=A&{adr} {adr}:={adr}&!A
+/// Flags: Z
+pub fn trb(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.set_status_flag(StatusFlag::Zero, cpu.a & operand == 0);
+    cpu.bus.borrow_mut().set_u8(address, operand & !cpu.a);
+}
+
+/// Jump indirect, without the NMOS bug. The pointer's high byte is read from the
+/// next page when the pointer is at the end of a page.
+/// Function: PC:=({adr})
+/// Flags:
+pub fn jmp_indirect(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    let pointer = cpu.next_u16();
+    cpu.pc = cpu
+        .bus
+        .borrow()
+        .read_u16_disjoint(pointer, pointer.wrapping_add(1));
+}
+
+/// Wait for interrupt
+/// Function: Stop running instructions until an NMI or IRQ comes in.
+/// Flags:
+pub fn wai(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.state = CpuState::WaitingForInterrupt;
+}
@@ -0,0 +1,198 @@
+use crate::cpu_6502::opcodes_logical::{add_with_carry, subtract_with_carry};
+use crate::cpu_6502::*;
+
+/// Function: {adr}:={adr}*2 A:=A or {adr}
+/// Flags: N Z C
+pub fn slo(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result_u16 = operand as u16 * 2;
+    let result_u8 = result_u16 as u8;
+    cpu.write_modified_operand(address, operand, result_u8);
+    cpu.a |= result_u8;
+    cpu.update_zero_and_negative_flag(cpu.a);
+    cpu.update_carry_flag(result_u16);
+}
+
+/// Function: {adr}:={adr}rol A:=A and {adr}
+/// Flags: N Z C
+pub fn rla(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result = (operand << 1) | cpu.get_carry();
+    cpu.set_status_flag(StatusFlag::Carry, operand & 0b1000_0000 != 0);
+    cpu.write_modified_operand(address, operand, result);
+    cpu.a &= result;
+    cpu.update_zero_and_negative_flag(cpu.a);
+}
+
+/// Function: {adr}:={adr}/2 A:=A exor {adr}
+/// Flags: N Z C
+pub fn sre(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result = operand >> 1;
+    cpu.set_status_flag(StatusFlag::Carry, operand & 0b0000_0001 != 0);
+    cpu.write_modified_operand(address, operand, result);
+    cpu.a ^= result;
+    cpu.update_zero_and_negative_flag(cpu.a);
+}
+
+/// Function: {adr}:={adr}ror A:=A adc {adr}
+/// Flags: N V Z C
+pub fn rra(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result = (operand >> 1) | (cpu.get_carry() << 7);
+    // The bit shifted out by the rotate is the carry going into the add.
+    cpu.set_status_flag(StatusFlag::Carry, operand & 0b0000_0001 != 0);
+    cpu.write_modified_operand(address, operand, result);
+    add_with_carry(cpu, result);
+}
+
+/// Function: {adr}:=A&X
+/// Flags:
+pub fn sax(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let address = cpu.get_operand_address(mode, extra_cycle);
+    cpu.bus.borrow_mut().set_u8(address, cpu.a & cpu.x);
+}
+
+/// Function: A,X:={adr}
+/// Flags: N Z
+pub fn lax(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.a = operand;
+    cpu.x = operand;
+    cpu.update_zero_and_negative_flag(operand);
+}
+
+/// Function: {adr}:={adr}-1 A-{adr}
+/// Flags: N Z C
+pub fn dcp(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result = operand.wrapping_sub(1);
+    cpu.write_modified_operand(address, operand, result);
+    cpu.update_zero_and_negative_flag(cpu.a.wrapping_sub(result));
+    cpu.set_status_flag(StatusFlag::Carry, cpu.a >= result);
+}
+
+/// Function: {adr}:={adr}+1 A:=A-{adr}
+/// Flags: N V Z C
+pub fn isc(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (address, operand) = cpu.get_operand(mode, extra_cycle);
+    let result = operand.wrapping_add(1);
+    cpu.write_modified_operand(address, operand, result);
+    subtract_with_carry(cpu, result);
+}
+
+/// Function: A:=A&#{imm}
+/// Flags: N Z C
+pub fn anc(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.a &= operand;
+    cpu.update_zero_and_negative_flag(cpu.a);
+    // The carry is a copy of the negative flag, as if the result was shifted.
+    cpu.set_status_flag(StatusFlag::Carry, cpu.a & 0b1000_0000 != 0);
+}
+
+/// Function: A:=(A&#{imm})/2
+/// Flags: N Z C
+pub fn alr(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    let value = cpu.a & operand;
+    cpu.set_status_flag(StatusFlag::Carry, value & 0b0000_0001 != 0);
+    cpu.a = value >> 1;
+    cpu.update_zero_and_negative_flag(cpu.a);
+}
+
+/// The rotate goes through the adder, so C and V come from bits 6 and 5 of the
+/// result rather than the bit that was shifted out. This is the binary mode
+/// behavior, the NES's 2A03 has no decimal mode.
+/// Function: A:=(A&#{imm})/2
+/// Flags: N V Z C
+pub fn arr(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.a = ((cpu.a & operand) >> 1) | (cpu.get_carry() << 7);
+    cpu.update_zero_and_negative_flag(cpu.a);
+    let bit_6 = cpu.a & 0b0100_0000 != 0;
+    let bit_5 = cpu.a & 0b0010_0000 != 0;
+    cpu.set_status_flag(StatusFlag::Carry, bit_6);
+    cpu.set_status_flag(StatusFlag::Overflow, bit_6 != bit_5);
+}
+
+/// This opcode is unstable on real hardware, the bits ORed into A depend on the
+/// chip and its temperature. This uses the commonly documented $EE.
+/// Function: A:=X&#{imm}
+/// Flags: N Z
+pub fn xaa(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    cpu.a = (cpu.a | 0xee) & cpu.x & operand;
+    cpu.update_zero_and_negative_flag(cpu.a);
+}
+
+/// Function: X:=A&X-#{imm}
+/// Flags: N Z C
+pub fn axs(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    // This is a compare rather than a subtract, it ignores the carry and decimal
+    // flags, and leaves V alone.
+    let value = cpu.a & cpu.x;
+    cpu.x = value.wrapping_sub(operand);
+    cpu.update_zero_and_negative_flag(cpu.x);
+    cpu.set_status_flag(StatusFlag::Carry, value >= operand);
+}
+
+/// The shared store for AHX, SHY, SHX and TAS. The value is ANDed with the high
+/// byte of the base address plus one, "H". When indexing crosses a page the
+/// high byte of the address that's written is replaced by the stored value.
+fn store_and_high(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8, index: u8, value: u8) {
+    let address = cpu.get_operand_address(mode, extra_cycle);
+    let base = address.wrapping_sub(index as u16);
+    let [_, base_high] = base.to_le_bytes();
+    let result = value & base_high.wrapping_add(1);
+    let [low, high] = address.to_le_bytes();
+    let address = if high != base_high {
+        u16::from_le_bytes([low, result])
+    } else {
+        address
+    };
+    cpu.bus.borrow_mut().set_u8(address, result);
+}
+
+/// Function: {adr}:=A&X&H
+/// Flags:
+pub fn ahx(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    store_and_high(cpu, mode, extra_cycle, cpu.y, cpu.a & cpu.x);
+}
+
+/// Function: {adr}:=Y&H
+/// Flags:
+pub fn shy(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    store_and_high(cpu, mode, extra_cycle, cpu.x, cpu.y);
+}
+
+/// Function: {adr}:=X&H
+/// Flags:
+pub fn shx(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    store_and_high(cpu, mode, extra_cycle, cpu.y, cpu.x);
+}
+
+/// Function: S:=A&X {adr}:=S&H
+/// Flags:
+pub fn tas(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    cpu.s = cpu.a & cpu.x;
+    store_and_high(cpu, mode, extra_cycle, cpu.y, cpu.s);
+}
+
+/// Function: A,X,S:={adr}&S
+/// Flags: N Z
+pub fn las(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
+    let (_, operand) = cpu.get_operand(mode, extra_cycle);
+    let value = operand & cpu.s;
+    cpu.a = value;
+    cpu.x = value;
+    cpu.s = value;
+    cpu.update_zero_and_negative_flag(value);
+}
+
+/// The CPU jams on these opcodes before they are dispatched, see
+/// Cpu6502::tick, so this is never run.
+/// Function: halts the CPU. the data bus will be set to #$FF
+/// Flags:
+pub fn kil(_cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {}
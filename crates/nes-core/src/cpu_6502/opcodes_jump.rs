@@ -1,13 +1,24 @@
 use crate::cpu_6502::*;
 
-fn branch(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8, do_branch: bool) {
-    if do_branch {
+pub(super) fn branch(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8, do_branch: bool) {
+    // The opcode has already been read, step back to the instruction.
+    let instruction_pc = cpu.pc - 1;
+    let address = if do_branch {
+        // A taken branch costs an extra cycle, and the operand address adds another
+        // one if it crosses a page.
+        cpu.cycles += extra_cycle as u16;
         let (address, _) = cpu.get_operand(mode, extra_cycle);
-        cpu.pc = address
+        cpu.pc = address;
+        address
     } else {
         // Just move the pc forward, but ignore the extra cycles, since the memory
         // won't actually be accessed.
-        cpu.get_operand(mode, 0);
+        let (address, _) = cpu.get_operand(mode, 0);
+        address
+    };
+
+    if let Some(ref mut branch_stats) = cpu.branch_stats {
+        branch_stats.record_branch(instruction_pc, address, do_branch);
     }
 }
 
@@ -108,15 +119,41 @@ pub fn beq(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
 }
 
 /// Break - This stops the execution of the program, and saves the PC to the stack.
-///         It also sets the status flags so we know what state the CPU is in.
+///         It also sets the B flag on the pushed status, so that the handler can tell
+///         it apart from an IRQ. The byte after BRK is skipped, and is often used
+///         to signal what the break is for.
 /// Function: (S)-:=PC,P PC:=($FFFE)
 /// Flags: B I
 pub fn brk(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
-    cpu.push_stack_u16(cpu.pc);
-    cpu.push_stack_u8(cpu.p);
-    cpu.pc = InterruptVectors::ResetVector as u16;
-    cpu.set_status_flag(StatusFlag::Break, true);
+    if let Some(ref mut stack_analyzer) = cpu.stack_analyzer {
+        stack_analyzer.enter_interrupt();
+    }
+    cpu.push_stack_u16(cpu.pc.wrapping_add(1));
+    cpu.push_stack_u8(cpu.p | StatusFlag::Break as u8 | StatusFlag::Push as u8);
     cpu.set_status_flag(StatusFlag::InterruptDisable, true);
+
+    // The NMI line is polled through the first four cycles of the BRK. An NMI that
+    // comes in then hijacks the BRK, which jumps to the NMI vector instead, but still
+    // leaves the B flag set on the stack. The 65C02 fixed this.
+    // https://wiki.nesdev.com/w/index.php/CPU_interrupts#Interrupt_hijacking
+    if cpu.variant == CpuVariant::Nmos6502 {
+        cpu.poll_scheduled_nmi(cpu.total_cycles + 3);
+        if cpu.nmi_pending {
+            cpu.nmi_pending = false;
+            cpu.pc = cpu
+                .bus
+                .borrow()
+                .read_u16(InterruptVectors::NmiVector as u16);
+            if let Some(ref mut vblank_analyzer) = cpu.vblank_analyzer {
+                vblank_analyzer.enter_nmi(cpu.pc, cpu.cycles as u64);
+            }
+            return;
+        }
+    }
+    cpu.pc = cpu
+        .bus
+        .borrow()
+        .read_u16(InterruptVectors::IrqBrkVector as u16);
 }
 
 /// Return from Interrupt
@@ -124,7 +161,13 @@ pub fn brk(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
 /// Flags: N V D I Z C
 pub fn rti(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
     cpu.p = cpu.pull_stack_u8();
-    cpu.pc = cpu.pull_stack_u16()
+    cpu.pc = cpu.pull_stack_u16();
+    if let Some(ref mut stack_analyzer) = cpu.stack_analyzer {
+        stack_analyzer.exit_interrupt();
+    }
+    if let Some(ref mut vblank_analyzer) = cpu.vblank_analyzer {
+        vblank_analyzer.exit_nmi();
+    }
 }
 
 /// Jump to subroutine
@@ -134,6 +177,12 @@ pub fn jsr(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     let (address, _operand) = cpu.get_operand(mode, extra_cycle);
     cpu.push_stack_u16(cpu.pc);
     cpu.pc = address;
+    if let Some(ref mut stack_analyzer) = cpu.stack_analyzer {
+        stack_analyzer.enter_subroutine(address);
+    }
+    if let Some(ref mut vblank_analyzer) = cpu.vblank_analyzer {
+        vblank_analyzer.enter_subroutine(address);
+    }
 }
 
 /// Return from Sub Routine
@@ -141,6 +190,12 @@ pub fn jsr(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
 /// Flags:
 pub fn rts(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
     cpu.pc = cpu.pull_stack_u16();
+    if let Some(ref mut stack_analyzer) = cpu.stack_analyzer {
+        stack_analyzer.exit_subroutine();
+    }
+    if let Some(ref mut vblank_analyzer) = cpu.vblank_analyzer {
+        vblank_analyzer.exit_subroutine();
+    }
 }
 
 /// Jump
@@ -194,6 +249,7 @@ pub fn sed(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
 /// Function: I:=0
 /// Flags: I
 pub fn cli(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.latch_interrupt_disable();
     cpu.set_status_flag(StatusFlag::InterruptDisable, false);
 }
 
@@ -201,6 +257,7 @@ pub fn cli(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
 /// Function: I:=1
 /// Flags: I
 pub fn sei(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.latch_interrupt_disable();
     cpu.set_status_flag(StatusFlag::InterruptDisable, true);
 }
 
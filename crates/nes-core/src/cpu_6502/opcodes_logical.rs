@@ -52,7 +52,73 @@ fn add_impl(cpu: &mut Cpu6502, operand: u8) {
 /// Flags: N V Z C
 pub fn adc(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     let (_, operand) = cpu.get_operand(mode, extra_cycle);
-    add_impl(cpu, operand);
+    add_with_carry(cpu, operand);
+}
+
+/// A:=A+operand+C, in decimal mode when it's enabled. This is shared with the
+/// undocumented opcodes that add, like RRA.
+pub(crate) fn add_with_carry(cpu: &mut Cpu6502, operand: u8) {
+    if is_decimal(cpu) {
+        add_decimal(cpu, operand);
+    } else {
+        add_impl(cpu, operand);
+    }
+}
+
+fn is_decimal(cpu: &Cpu6502) -> bool {
+    cpu.decimal_mode == DecimalMode::Enabled
+        && cpu.is_status_flag_set(StatusFlag::Decimal)
+}
+
+/// The NMOS 6502's decimal mode addition, following Bruce Clark's tutorial:
+/// http://www.6502.org/tutorials/decimal_mode.html#A
+fn add_decimal(cpu: &mut Cpu6502, operand: u8) {
+    let carry = cpu.get_carry() as i16;
+    let a = cpu.a as i16;
+    let b = operand as i16;
+
+    // Z is set from the binary result.
+    cpu.set_status_flag(StatusFlag::Zero, (a + b + carry) as u8 == 0);
+
+    // Add the low digits, and carry into the high digit.
+    let mut low = (a & 0x0f) + (b & 0x0f) + carry;
+    if low >= 0x0a {
+        low = ((low + 0x06) & 0x0f) + 0x10;
+    }
+
+    // N and V come from the high digits before they are adjusted, using signed
+    // arithmetic.
+    let high_signed = |value: i16| (value & 0xf0) as u8 as i8 as i16;
+    let signed = high_signed(a) + high_signed(b) + low;
+    cpu.set_status_flag(StatusFlag::Negative, signed & 0x80 == 0x80);
+    cpu.set_status_flag(StatusFlag::Overflow, !(-128..=127).contains(&signed));
+
+    let mut result = (a & 0xf0) + (b & 0xf0) + low;
+    if result >= 0xa0 {
+        result += 0x60;
+    }
+    cpu.set_status_flag(StatusFlag::Carry, result >= 0x100);
+    cpu.a = result as u8;
+}
+
+/// The NMOS 6502's decimal mode subtraction. The flags are all the same as in binary
+/// mode, only the accumulator differs.
+/// http://www.6502.org/tutorials/decimal_mode.html#A
+fn subtract_decimal(cpu: &mut Cpu6502, operand: u8) {
+    let carry = cpu.get_carry() as i16;
+    let a = cpu.a as i16;
+    let b = operand as i16;
+    add_impl(cpu, !operand);
+
+    let mut low = (a & 0x0f) - (b & 0x0f) + carry - 1;
+    if low < 0 {
+        low = ((low - 0x06) & 0x0f) - 0x10;
+    }
+    let mut result = (a & 0xf0) - (b & 0xf0) + low;
+    if result < 0 {
+        result -= 0x60;
+    }
+    cpu.a = result as u8;
 }
 
 /// Subtract with Carry
@@ -80,7 +146,17 @@ pub fn sbc(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     // the carry flag be the + 1.
     //
     // Because of this, it's assumed the assembly will run SEC before running sbc.
-    add_impl(cpu, !operand);
+    subtract_with_carry(cpu, operand);
+}
+
+/// A:=A-operand-(1-C), in decimal mode when it's enabled. This is shared with
+/// the undocumented opcodes that subtract, like ISC.
+pub(crate) fn subtract_with_carry(cpu: &mut Cpu6502, operand: u8) {
+    if is_decimal(cpu) {
+        subtract_decimal(cpu, operand);
+    } else {
+        add_impl(cpu, !operand);
+    }
 }
 
 /// Compare A with source
@@ -120,7 +196,7 @@ pub fn dec(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     let (address, operand) = cpu.get_operand(mode, extra_cycle);
     let result = operand.wrapping_sub(1);
     cpu.update_zero_and_negative_flag(result);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
 
 /// Decrement X
@@ -136,7 +212,7 @@ pub fn dex(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
 /// Flags: N Z
 pub fn dey(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
     cpu.y = cpu.y.wrapping_sub(1);
-    cpu.update_zero_and_negative_flag(cpu.x);
+    cpu.update_zero_and_negative_flag(cpu.y);
 }
 
 /// Increment the address
@@ -146,7 +222,7 @@ pub fn inc(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     let (address, operand) = cpu.get_operand(mode, extra_cycle);
     let result = operand.wrapping_add(1);
     cpu.update_zero_and_negative_flag(result);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
 
 /// Increment X
@@ -174,7 +250,7 @@ pub fn asl(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     cpu.update_zero_and_negative_flag(result);
     // The Carry flag contains the bit that was shifted out:
     cpu.set_status_flag(StatusFlag::Carry, operand & 0b1000_0000 != 0);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
 
 /// Rotate left
@@ -186,7 +262,7 @@ pub fn rol(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     cpu.update_zero_and_negative_flag(result);
     // The Carry flag contains the bit that was shifted out:
     cpu.set_status_flag(StatusFlag::Carry, operand & 0b1000_0000 != 0);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
 
 /// Logical shift right
@@ -198,7 +274,7 @@ pub fn lsr(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     cpu.update_zero_and_negative_flag(result);
     // The Carry flag contains the bit that was shifted out:
     cpu.set_status_flag(StatusFlag::Carry, operand & 0b0000_0001 != 0);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
 
 /// Rotate right
@@ -217,5 +293,5 @@ pub fn ror(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
     cpu.update_zero_and_negative_flag(result);
     // The Carry flag contains the bit that was shifted out:
     cpu.set_status_flag(StatusFlag::Carry, operand & 0b0000_0001 != 0);
-    cpu.bus.borrow_mut().set_u8(address, result);
+    cpu.write_modified_operand(address, operand, result);
 }
@@ -13,7 +13,7 @@ pub fn lda(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
 /// Function: {adr}:=A
 /// Flags:
 pub fn sta(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
-    let (address, _operand) = cpu.get_operand(mode, extra_cycle);
+    let address = cpu.get_operand_address(mode, extra_cycle);
     cpu.bus.borrow_mut().set_u8(address, cpu.a);
 }
 
@@ -30,7 +30,7 @@ pub fn ldx(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
 /// Function: {adr}:=X
 /// Flags:
 pub fn stx(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
-    let (address, _operand) = cpu.get_operand(mode, extra_cycle);
+    let address = cpu.get_operand_address(mode, extra_cycle);
     cpu.bus.borrow_mut().set_u8(address, cpu.x);
 }
 
@@ -47,7 +47,7 @@ pub fn ldy(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
 /// Function: {adr}:=Y
 /// Flags:
 pub fn sty(cpu: &mut Cpu6502, mode: Mode, extra_cycle: u8) {
-    let (address, _operand) = cpu.get_operand(mode, extra_cycle);
+    let address = cpu.get_operand_address(mode, extra_cycle);
     cpu.bus.borrow_mut().set_u8(address, cpu.y);
 }
 
@@ -118,6 +118,7 @@ pub fn pha(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
 /// Function: P:=+(S)
 /// Flags: N V D I Z C
 pub fn plp(cpu: &mut Cpu6502, _mode: Mode, _extra_cycle: u8) {
+    cpu.latch_interrupt_disable();
     cpu.p = cpu.pull_stack_u8();
 }
 
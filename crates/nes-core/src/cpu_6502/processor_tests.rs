@@ -0,0 +1,251 @@
+//! Run Tom Harte's single instruction tests, which have 10,000 randomized cases for
+//! every opcode, including the undocumented ones. Each case sets up the registers and
+//! some RAM, runs one instruction, and lists the state afterwards, along with every
+//! bus access the real chip makes.
+//! https://github.com/TomHarte/ProcessorTests
+//!
+//! The tests are too big to check in, so this is ignored by default. Point it at a
+//! checkout of the JSON files, and run it with:
+//!
+//! PROCESSOR_TESTS=ProcessorTests/nes6502/v1 cargo test processor_tests -- --ignored
+//!
+//! The nes6502 set is for the NES CPU, which doesn't have a decimal mode. Any other
+//! directory, like the 6502 set, is run with decimal mode enabled.
+use crate::bus::Bus;
+use crate::cpu_6502::{Cpu6502, Registers};
+use crate::cpu_6502::{CpuState, DecimalMode};
+use crate::mappers::{FlatMemory64K, Mapper};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::{env, fs};
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    /// The bus access for every cycle, as [address, value, "read" or "write"].
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+type Accesses = Rc<RefCell<Vec<(u16, u8, String)>>>;
+
+/// The 64K of memory, recording every access made through it.
+struct RecordingMemory {
+    memory: FlatMemory64K,
+    accesses: Accesses,
+}
+
+impl Mapper for RecordingMemory {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        let value = self.memory.read_cpu(addr);
+        if let Some(value) = value {
+            self.accesses
+                .borrow_mut()
+                .push((addr, value, String::from("read")));
+        }
+        value
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        self.accesses
+            .borrow_mut()
+            .push((addr, value, String::from("write")));
+        self.memory.write_cpu(addr, value)
+    }
+}
+
+enum Outcome {
+    Passed,
+    /// The case jams the CPU, which the tests don't model in a useful way.
+    Skipped,
+    Failed(String),
+}
+
+fn run_case(case: &TestCase, decimal_mode: DecimalMode) -> Outcome {
+    let accesses: Accesses = Rc::new(RefCell::new(Vec::new()));
+    let memory = RecordingMemory {
+        memory: FlatMemory64K::new(),
+        accesses: Rc::clone(&accesses),
+    };
+    let bus = Bus::new_shared_bus(Box::new(memory));
+    for &(address, value) in &case.initial.ram {
+        bus.borrow_mut().set_u8(address, value);
+    }
+    let State {
+        pc, s, a, x, y, p, ..
+    } = case.initial;
+    let mut cpu = Cpu6502::with_state(bus, Registers { a, x, y, pc, s, p });
+    cpu.decimal_mode = decimal_mode;
+    // Only record the instruction, not the setup.
+    accesses.borrow_mut().clear();
+
+    if cpu.tick() == CpuState::Jammed {
+        return Outcome::Skipped;
+    }
+
+    let mut differences = Vec::new();
+    let expected = &case.expected;
+    let registers = cpu.registers();
+    let expected_registers = Registers {
+        a: expected.a,
+        x: expected.x,
+        y: expected.y,
+        pc: expected.pc,
+        s: expected.s,
+        p: expected.p,
+    };
+    if registers != expected_registers {
+        differences.push(format!(
+            "registers: {:x?}, expected {:x?}",
+            registers, expected_registers
+        ));
+    }
+    let bus_accesses = accesses.borrow().clone();
+    for &(address, value) in &expected.ram {
+        let actual = cpu.peek(address);
+        if actual != value {
+            differences.push(format!(
+                "${:04x} is ${:02x}, expected ${:02x}",
+                address, actual, value
+            ));
+        }
+    }
+    if cpu.cycles as usize != case.cycles.len() {
+        differences.push(format!(
+            "took {} cycles, expected {}",
+            cpu.cycles,
+            case.cycles.len()
+        ));
+    }
+    if bus_accesses != case.cycles {
+        differences.push(format!(
+            "bus accesses: {:x?}, expected {:x?}",
+            bus_accesses, case.cycles
+        ));
+    }
+
+    if differences.is_empty() {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(format!("{}\n  {}", case.name, differences.join("\n  ")))
+    }
+}
+
+#[test]
+#[ignore]
+fn processor_tests() {
+    let directory = match env::var("PROCESSOR_TESTS") {
+        Ok(directory) => directory,
+        Err(_) => {
+            eprintln!("Set PROCESSOR_TESTS to the directory with the JSON files.");
+            return;
+        }
+    };
+    let decimal_mode = if directory.contains("nes6502") {
+        DecimalMode::Disabled
+    } else {
+        DecimalMode::Enabled
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(Path::new(&directory))
+        .expect("Unable to read the PROCESSOR_TESTS directory.")
+        .map(|entry| entry.expect("Unable to read the directory entry.").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(
+        !paths.is_empty(),
+        "No JSON files were found in {}",
+        directory
+    );
+
+    let mut failed_opcodes = Vec::new();
+    for path in &paths {
+        let text = fs::read_to_string(path).expect("Unable to read the test file.");
+        let cases: Vec<TestCase> =
+            serde_json::from_str(&text).expect("Unable to parse the test file.");
+        let mut failures = Vec::new();
+        let mut skipped = 0;
+        for case in &cases {
+            match run_case(case, decimal_mode) {
+                Outcome::Passed => {}
+                Outcome::Skipped => skipped += 1,
+                Outcome::Failed(message) => failures.push(message),
+            }
+        }
+        let opcode = path.file_stem().unwrap().to_string_lossy().to_string();
+        if skipped == cases.len() {
+            println!("{}: skipped, it jams the CPU", opcode);
+        } else if failures.is_empty() {
+            println!("{}: passed", opcode);
+        } else {
+            // The first failure is usually enough to see what's wrong.
+            println!(
+                "{}: {} of {} failed, e.g. {}",
+                opcode,
+                failures.len(),
+                cases.len(),
+                failures[0]
+            );
+            failed_opcodes.push(opcode);
+        }
+    }
+
+    assert!(
+        failed_opcodes.is_empty(),
+        "{} opcodes failed: {}",
+        failed_opcodes.len(),
+        failed_opcodes.join(", ")
+    );
+}
+
+#[test]
+fn test_run_case() {
+    // lda #$42
+    let text = r#"{
+        "name": "a9 42",
+        "initial": {
+            "pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+            "ram": [[512, 169], [513, 66]]
+        },
+        "final": {
+            "pc": 514, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36,
+            "ram": [[512, 169], [513, 66]]
+        },
+        "cycles": [[512, 169, "read"], [513, 66, "read"]]
+    }"#;
+    let mut case: TestCase = serde_json::from_str(text).unwrap();
+    assert!(matches!(
+        run_case(&case, DecimalMode::Disabled),
+        Outcome::Passed
+    ));
+
+    case.expected.a = 0x43;
+    case.cycles.push((514, 0, String::from("read")));
+    match run_case(&case, DecimalMode::Disabled) {
+        Outcome::Failed(message) => {
+            assert!(message.contains("registers"));
+            assert!(message.contains("took 2 cycles, expected 3"));
+            assert!(message.contains("bus accesses"));
+        }
+        _ => panic!("The case should fail."),
+    }
+}
@@ -0,0 +1,234 @@
+use crate::constants::memory_range;
+use crate::prelude::*;
+use core::ops::Range;
+
+/// When the stack gets within this many bytes of wrapping or of reserved data, a
+/// warning is issued.
+const DEFAULT_MARGIN: u8 = 16;
+
+const MAX_CALL_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackWarning {
+    /// The stack is within the margin of wrapping around from $0100 to $01FF.
+    NearOverflow { pc: u16, depth: u16 },
+    /// A push to $0100 wrapped S around to $FF, so the next push lands on the top of
+    /// the stack page.
+    Overflow { pc: u16 },
+    /// A pull went past $01FF, and wrapped around to the bottom of the stack page.
+    Underflow { pc: u16 },
+    /// The stack is within the margin of running into reserved data.
+    NearReserved { pc: u16, address: u16 },
+    /// A push wrote over memory that was reserved for data.
+    Collision { pc: u16, address: u16 },
+    /// An RTS ran without a JSR to return from, so it returned to whatever was on the
+    /// stack. This is also how the "RTS trick" for jump tables shows up.
+    UnbalancedReturn { pc: u16 },
+}
+
+impl StackWarning {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            StackWarning::NearOverflow { pc, .. }
+            | StackWarning::Overflow { pc }
+            | StackWarning::Underflow { pc }
+            | StackWarning::NearReserved { pc, .. }
+            | StackWarning::Collision { pc, .. }
+            | StackWarning::UnbalancedReturn { pc } => pc,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match *self {
+            StackWarning::NearOverflow { pc, depth } => format!(
+                "${:04x}: stack depth of {} bytes is close to wrapping around",
+                pc, depth
+            ),
+            StackWarning::Overflow { pc } => {
+                format!("${:04x}: stack overflowed and wrapped around to $01ff", pc)
+            }
+            StackWarning::Underflow { pc } => {
+                format!("${:04x}: stack underflowed and wrapped around to $0100", pc)
+            }
+            StackWarning::NearReserved { pc, address } => format!(
+                "${:04x}: stack pushed to ${:04x}, which is close to reserved data",
+                pc, address
+            ),
+            StackWarning::Collision { pc, address } => format!(
+                "${:04x}: stack pushed to ${:04x}, which overwrites reserved data",
+                pc, address
+            ),
+            StackWarning::UnbalancedReturn { pc } => {
+                format!("${:04x}: RTS without a matching JSR", pc)
+            }
+        }
+    }
+}
+
+/// Watches the stack pointer as the program runs, and records how deep the stack
+/// gets, including while inside of nested interrupt handlers. Games often put data
+/// in the bottom of page 1, and a stack that silently grows into it is a notoriously
+/// hard bug to track down.
+///
+/// $0100 ┌──────────────┐
+///       │ reserved     │ <- Collision
+///       ├──────────────┤
+///       │ margin       │ <- NearReserved
+///       ├──────────────┤
+///       │              │
+///       │ stack ↓      │
+/// $01FF └──────────────┘
+pub struct StackAnalyzer {
+    /// Ranges in the stack page that hold program data, where the end is exclusive.
+    reserved: Vec<Range<u16>>,
+    margin: u8,
+    /// The pc of the instruction currently being executed.
+    pc: u16,
+    max_depth: u16,
+    interrupt_depth: u8,
+    max_interrupt_depth: u8,
+    /// The deepest the stack got while handling an interrupt.
+    max_interrupt_stack_depth: u16,
+    /// The addresses of the subroutines that have been called, and not returned from.
+    call_stack: Vec<u16>,
+    warnings: Vec<StackWarning>,
+}
+
+impl StackAnalyzer {
+    pub fn new() -> StackAnalyzer {
+        StackAnalyzer {
+            reserved: Vec::new(),
+            margin: DEFAULT_MARGIN,
+            pc: 0,
+            max_depth: 0,
+            interrupt_depth: 0,
+            max_interrupt_depth: 0,
+            max_interrupt_stack_depth: 0,
+            call_stack: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Mark a range of addresses in the stack page as holding program data.
+    pub fn reserve(&mut self, range: Range<u16>) {
+        self.reserved.push(range);
+    }
+
+    /// Change how close the stack can get to trouble before a warning is issued.
+    pub fn set_margin(&mut self, margin: u8) {
+        self.margin = margin;
+    }
+
+    pub fn max_depth(&self) -> u16 {
+        self.max_depth
+    }
+
+    pub fn max_interrupt_depth(&self) -> u8 {
+        self.max_interrupt_depth
+    }
+
+    pub fn max_interrupt_stack_depth(&self) -> u16 {
+        self.max_interrupt_stack_depth
+    }
+
+    /// The subroutines that are currently running, from the outermost call.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.call_stack
+    }
+
+    pub fn warnings(&self) -> &[StackWarning] {
+        &self.warnings
+    }
+
+    pub fn record_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn enter_interrupt(&mut self) {
+        self.interrupt_depth = self.interrupt_depth.saturating_add(1);
+        self.max_interrupt_depth = self.max_interrupt_depth.max(self.interrupt_depth);
+    }
+
+    pub fn exit_interrupt(&mut self) {
+        self.interrupt_depth = self.interrupt_depth.saturating_sub(1);
+    }
+
+    pub fn enter_subroutine(&mut self, address: u16) {
+        // Only 128 return addresses fit on the stack. Deeper than that, the program
+        // must be dropping them with PLA, so forget the oldest call.
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(address);
+    }
+
+    pub fn exit_subroutine(&mut self) {
+        if self.call_stack.pop().is_none() {
+            self.warn(StackWarning::UnbalancedReturn { pc: self.pc });
+        }
+    }
+
+    /// Record a single byte being pushed. `s` is the stack pointer before the push.
+    pub fn record_push(&mut self, s: u8) {
+        let address = u16::from_le_bytes([s, memory_range::STACK_PAGE]);
+        let depth = 0xFF - s as u16 + 1;
+
+        self.max_depth = self.max_depth.max(depth);
+        if self.interrupt_depth > 0 {
+            self.max_interrupt_stack_depth = self.max_interrupt_stack_depth.max(depth);
+        }
+
+        if s == 0x00 {
+            self.warn(StackWarning::Overflow { pc: self.pc });
+        } else if s < self.margin {
+            self.warn(StackWarning::NearOverflow { pc: self.pc, depth });
+        }
+
+        if self.reserved.iter().any(|range| range.contains(&address)) {
+            self.warn(StackWarning::Collision {
+                pc: self.pc,
+                address,
+            });
+        } else if self
+            .reserved
+            .iter()
+            .any(|range| range.end <= address && address - range.end < self.margin as u16)
+        {
+            self.warn(StackWarning::NearReserved {
+                pc: self.pc,
+                address,
+            });
+        }
+    }
+
+    /// Record a single byte being pulled. `s` is the stack pointer before the pull.
+    pub fn record_pull(&mut self, s: u8) {
+        if s == 0xFF {
+            self.warn(StackWarning::Underflow { pc: self.pc });
+        }
+    }
+
+    /// Only keep one of each kind of warning per instruction, otherwise a deep loop
+    /// would flood the list.
+    fn warn(&mut self, warning: StackWarning) {
+        let is_duplicate = self.warnings.iter().any(|existing| {
+            core::mem::discriminant(existing) == core::mem::discriminant(&warning)
+                && existing.pc() == warning.pc()
+        });
+        if !is_duplicate {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Create a human readable report of the stack usage and any warnings.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "Stack usage (max depth {} bytes, {} bytes inside interrupts, {} nested interrupts)\n",
+            self.max_depth, self.max_interrupt_stack_depth, self.max_interrupt_depth
+        );
+        for warning in &self.warnings {
+            report.push_str(&format!("  {}\n", warning.message()));
+        }
+        report
+    }
+}
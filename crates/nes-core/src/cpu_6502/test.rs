@@ -0,0 +1,3330 @@
+use crate::cpu_6502::test_helpers::*;
+use crate::cpu_6502::StopCondition;
+
+// These tests assert the various operations the CPU can do. They use a high-level
+// API based off of macros to tersely assert the behavior.
+// For instance this command will run the test:
+//
+// `cargo test cpu_6502::test::immediate_mode::adc1`
+//
+//      TestName Register Status  Program
+//             |     |     |      |
+//             v     v     v      v
+// register_a!(adc1, 0x33, P, "lda #$22\nadc #$11",);
+
+/// Test all of the immedate mode instructions.
+#[rustfmt::skip]
+mod immediate_mode {
+  use super::*;
+
+  mod adc_basics {
+    use super::*;
+    // This first test shows: 0x22 + 0x11 == 0x33.
+    // P is the default "P" or status register values.
+    register_a!(adc1, 0x33, P, "
+      lda #$22
+      adc #$11
+    ");
+    // This add doesn't do anything, but the N, or negative flag is set since the most
+    // significant bit is 1.
+    register_a!(adc2, 0xff, P | N, "
+      lda #$FF
+      adc #$00
+    ");
+    // Here we overflow the u8.
+    register_a!(
+      adc3,
+      0x00,
+      P
+      | C // For unsigned numbers, the carry bit is flipped, since the result carries over.
+      | Z, // The result is 0x00 (with the carry only in the status register)
+      "
+        lda #$FF  ; 255 signed, or -1 unsigned
+        adc #$01  ;   1 signed, or 1 unsigned
+      "
+    );
+    // This is a similar result as above, but the final resut is not 0.
+    register_a!(adc4, 0x01, P | C, "lda #$FF\nadc #$02");
+    // Check that this uses the carry flag.
+    register_a!(adc_carry, 0x34, P, "
+      sec      ; Set the carry flag
+      lda #$11 ; Load A with a value
+      adc #$22 ; This should add all three values
+              ; = 0x01 + 0x11 + 0x22
+    ");
+  }
+
+  mod adc_overflow_carry {
+    // This section tests the adc cases from:
+    // http://www.6502.org/tutorials/vflag.html
+    use super::*;
+    register_a!(test_1_1, 0x02, P, "
+      CLC      ; 1 + 1 = 2, returns C = 0
+      LDA #$01 ;            returns V = 0
+      ADC #$01
+    ");
+    // 0b0000_0001
+    // 0x1111_1111
+    // 1_0000_0000
+    register_a!(test_1_neg1, 0x00, P | C | Z, "
+      CLC      ; 1 + -1 = 0, returns C = 1
+      LDA #$01 ;                     V = 0
+      ADC #$FF
+    ");
+
+    // 0b0111_1111
+    // 0b0000_0001
+    // 0b1000_0000
+    register_a!(test_127_1, 0b1000_0000, P | V | N, "
+      CLC      ; 127 + 1 = 128, returns C = 0
+      LDA #$7F ;                        V = 1
+      ADC #$01
+    ");
+
+    // 0x80 + 0xff
+    // 0b1000_0000
+    // 0b1111_1111
+    // 1_0111_1111
+    register_a!(neg128_negative_1, 0b0111_1111, P | C | V, "
+      CLC      ; -128 + -1 = -129, returns C = 1
+      LDA #$80 ;                           V = 1
+      ADC #$FF
+    ");
+
+    // 0b0011_1111  a
+    // 0b0100_0000  operand
+    // 0b0000_0001  carry
+    // 0b0000_0000  result
+    register_a!(carry, 0b1000_0000, P | V | N, "
+      SEC      ; Note: SEC, not CLC
+      LDA #$3F ; 63 + 64 + 1 = 128, returns V = 1
+      ADC #$40
+    ");
+  }
+
+  mod sbc_overflow_carry {
+    // This section tests the sbc cases from:
+    // http://www.6502.org/tutorials/vflag.html
+    use super::*;
+    // 0b0000_0000   two's comp   0b0000_0000
+    // 0b0000_0001       ->       0b1111_1111
+    //                            0b1111_1111
+    register_a!(test_0_minus_1, negative(1), P | N, "
+      SEC      ; 0 - 1 = -1, returns V = 0
+      LDA #$00
+      SBC #$01
+    ");
+
+    // 0b1000_0000    0b1000_0000
+    // 0b0000_0001 -> 0b1111_1111
+    //              0b1_0111_1111
+    register_a!(neg128_minus_1, negative(129), P | C | V, "
+      SEC      ; -128 - 1 = -129, returns V = 1
+      LDA #$80
+      SBC #$01
+    ");
+
+    // 0b0111_1111    0b0111_1111
+    // 0b1111_1111 -> 0b0000_0001
+    //                0b1000_0000
+    register_a!(test_127_minus_neg1, 128, P | V | N, "
+      SEC      ; 127 - -1 = 128, returns V = 1
+      LDA #$7F
+      SBC #$FF
+    ");
+
+    //   0b1100_0000    0b1100_0000
+    // - 0b0100_0000 => 0b1011_1111
+    //                  1_0111_1111
+    register_a!(clc, negative(129), P | C | V, "
+      CLC      ; Note: CLC, not SEC
+      LDA #$C0 ; -64 - 64 - 1 = -129, returns V = 1
+      SBC #$40
+    ");
+  }
+
+  mod compare {
+    use super::*;
+    // http://6502.org/tutorials/compare_instructions.html
+    status!(cmp_lt, P | N,     "lda #$11\ncmp #$22");
+    status!(cmp_gt, P | C,     "lda #$22\ncmp #$11");
+    status!(cmp_eq, P | C | Z, "lda #$11\ncmp #$11");
+    status!(cpx_lt, P | N,     "ldx #$11\ncpx #$22");
+    status!(cpx_gt, P | C,     "ldx #$22\ncpx #$11");
+    status!(cpx_eq, P | C | Z, "ldx #$11\ncpx #$11");
+    status!(cpy_lt, P | N,     "ldy #$11\ncpy #$22");
+    status!(cpy_gt, P | C,     "ldy #$22\ncpy #$11");
+    status!(cpy_eq, P | C | Z, "ldy #$11\ncpy #$11");
+  }
+
+  register_a!(and, 0b1010_0000, P | N, "
+    lda #%11110000
+    and #%10101010
+  ");
+  register_a!(eor, 0b0101_1010, P, "
+    lda #%11110000
+    eor #%10101010
+  ");
+  register_a!(ora, 0b1111_1010, P | N, "
+    lda #%11110000
+    ora #%10101010
+  ");
+
+  register_a!(lda, 0x22, P, "lda #$22");
+  register_x!(ldx, 0x22, P, "ldx #$22");
+  register_y!(ldy, 0x22, P, "ldy #$22");
+  register_y!(dey, 0x01, P, "ldy #$02\ndey");
+  register_y!(dey_zero, 0x00, P | Z, "ldx #$05\nldy #$01\ndey");
+
+  register_a!(nop, 0x00, P, "nop #$22");
+
+  register_a!(sbc1, 0x22,        P | C, "
+    sec       ; Always set the carry flag first.
+    lda #$33
+    sbc #$11
+  ");
+  register_a!(sbc2, 0x00,        P | Z | C, "
+    sec       ; Always set the carry flag first.
+    lda #$33
+    sbc #$33
+  ");
+  register_a!(sbc3, negative(1), P | N, "
+    sec       ; Always set the carry flag first.
+    lda #$33
+    sbc #$34
+  ");
+
+  mod illegal {
+    // register_a!(, "alr #$22", 0x22, P);
+    // register_a!(, "anc #$22", 0x22, P);
+    // register_a!(, "axs #$22", 0x22, P);
+    // register_a!(, "arr #$22", 0x22, P);
+    // register_a!(, "lax #$22", 0x22, P);
+    // register_a!(, "xaa #$22", 0x22, P);
+  }
+}
+
+#[rustfmt::skip]
+mod zero_page {
+  use super::*;
+  register_a!(adc_zp, 0x33, P, "
+    lda #$22
+    sta $10
+    lda #$11
+    clc
+    adc $10
+  ");
+  register_a!(adc_zpx, 0x33, P, "
+    ; Load up the zero page.
+    lda #$22
+    sta $12   ; 0x10 + 0x02
+    ; Load up the registers
+    lda #$11
+    ldx #$02
+    ; Do the math
+    clc
+    adc $10,x
+  ");
+  register_a!(and_zp, 0b1010_0000, P | N, "
+    lda #%10101010
+    sta $10
+    lda #%11110000
+    clc
+    and $10
+  ");
+  register_a!(and_zpx, 0b1010_0000, P | N, "
+    ; Load up the zero page.
+    lda #%10101010
+    sta $12   ; 0x10 + 0x02
+    ; Load up the registers
+    lda #%11110000
+    ldx #$02
+    ; Do the math
+    clc
+    and $10,x
+  ");
+  register_a!(asl_zp, 0b0101_0100, P | C, "
+    lda #%10101010
+    sta $03
+    asl $03
+    lda $03
+  ");
+  register_a!(asl_zp_no_carry, 0b0101_0100, P, "
+    lda #%00101010
+    sta $03
+    asl $03
+    lda $03
+  ");
+  register_a!(asl_zpx, 0b0101_0100, P | C, "
+    lda #%10101010
+    sta $03
+    ldx #$01
+    asl $02,x
+    lda $03
+  ");
+  status!(bit_zp_n, P | N, "
+    lda #%10000000
+    sta $03
+    bit $03
+  ");
+  status!(bit_zp_v, P | V, "
+    lda #%01000000
+    sta $03
+    bit $03
+  ");
+  status!(bit_zp_no_zero_flag, P | V | N, "
+    lda #$ff
+    sta $03
+    lda #$ff
+    bit $03 ; The zero flag is set if accumulator and the value are 0
+  ");
+  status!(bit_zp_zero, P | Z, "
+    lda #$00
+    sta $03
+    lda #$ff
+    bit $03 ; The zero flag is set if accumulator and the value are 0
+  ");
+  status!(cmp_zp_lt, P | N, "
+    lda #$22
+    sta $03
+    lda #$11
+    cmp $03
+  ");
+  status!(cmp_zp_gt, P | C, "
+    lda #$11
+    sta $03
+    lda #$22
+    cmp $03
+  ");
+  status!(cmp_zp_eq, P | C | Z, "
+    lda #$11
+    sta $03
+    lda #$11
+    cmp $03
+  ");
+  status!(cpx_zp_lt, P | N,     "
+    lda #$22
+    sta 03
+    ldx #$11
+    cpx $03
+  ");
+  status!(cpx_zp_gt, P | C,     "
+    lda #$11
+    sta 03
+    ldx #$22
+    cpx $03
+  ");
+  status!(cpx_zp_eq, P | C | Z, "
+    lda #$11
+    sta 03
+    ldx #$11
+    cpx $03
+  ");
+
+  // register_a!(dcp_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   dcp
+  // ");
+  // register_a!(dcp_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   dcp
+  // ");
+  // register_a!(dec_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   dec
+  // ");
+  // register_a!(dec_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   dec
+  // ");
+  // register_a!(eor_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   eor
+  // ");
+  // register_a!(eor_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   eor
+  // ");
+  // register_a!(inc_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   inc
+  // ");
+  // register_a!(inc_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   inc
+  // ");
+  // register_a!(isc_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   isc
+  // ");
+  // register_a!(isc_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   isc
+  // ");
+  // register_a!(lax_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lax
+  // ");
+  // register_a!(lax_zpy, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lax
+  // ");
+  // register_a!(lda_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lda
+  // ");
+  // register_a!(lda_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lda
+  // ");
+  // register_a!(ldx_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ldx
+  // ");
+  // register_a!(ldx_zpy, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ldx
+  // ");
+  // register_a!(ldy_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ldy
+  // ");
+  // register_a!(ldy_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ldy
+  // ");
+  // register_a!(lsr_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lsr
+  // ");
+  // register_a!(lsr_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   lsr
+  // ");
+  // register_a!(nop_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   nop
+  // ");
+  // register_a!(nop_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   nop
+  // ");
+  // register_a!(ora_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ora
+  // ");
+  // register_a!(ora_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ora
+  // ");
+  // register_a!(rla_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rla
+  // ");
+  // register_a!(rla_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rla
+  // ");
+  // register_a!(rol_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rol
+  // ");
+  // register_a!(rol_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rol
+  // ");
+  // register_a!(ror_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ror
+  // ");
+  // register_a!(ror_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   ror
+  // ");
+  // register_a!(rra_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rra
+  // ");
+  // register_a!(rra_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   rra
+  // ");
+  // register_a!(sax_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sax
+  // ");
+  // register_a!(sax_zpy, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sax
+  // ");
+  // register_a!(sbc_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sbc
+  // ");
+  // register_a!(sbc_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sbc
+  // ");
+  // register_a!(slo_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   slo
+  // ");
+  // register_a!(slo_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   slo
+  // ");
+  // register_a!(sre_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sre
+  // ");
+  // register_a!(sre_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sre
+  // ");
+  // register_a!(sta_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sta
+  // ");
+  // register_a!(sta_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sta
+  // ");
+  // register_a!(stx_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   stx
+  // ");
+  // register_a!(stx_zpy, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   stx
+  // ");
+  // register_a!(sty_zp, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sty
+  // ");
+  // register_a!(sty_zpx, 0b10101010, P, "
+  //   lda #%10101010
+  //   sta $03
+  //   sty
+  // ");
+}
+
+/// Test the (indirect),Y addressing mode, which reads a pointer from the zero page.
+#[rustfmt::skip]
+mod indirect_y {
+  use super::*;
+  register_a!(lda_izy, 0x42, P, "
+    ; Store $42 at $0305.
+    lda #$42
+    sta $0305
+    ; Point $10 at $0300.
+    lda #$00
+    sta $10
+    lda #$03
+    sta $11
+    ldy #$05
+    lda ($10),Y
+  ");
+  register_a!(lda_izy_page_cross, 0x42, P, "
+    ; Store $42 at $0401.
+    lda #$42
+    sta $0401
+    ; Point $10 at $03ff.
+    lda #$ff
+    sta $10
+    lda #$03
+    sta $11
+    ldy #$02
+    lda ($10),Y
+  ");
+  register_a!(lda_izy_pointer_wraps, 0x42, P, "
+    ; Store $42 at $0300.
+    lda #$42
+    sta $0300
+    ; The pointer at $ff reads its high byte from $00.
+    lda #$00
+    sta $ff
+    lda #$03
+    sta $00
+    ldy #$00
+    lda ($ff),Y
+  ");
+  register_a!(sta_izy, 0x42, P, "
+    ; Point $10 at $0300.
+    lda #$00
+    sta $10
+    lda #$03
+    sta $11
+    ldy #$05
+    lda #$42
+    sta ($10),Y
+    lda #$00
+    lda $0305
+  ");
+}
+
+mod branch_stats {
+    use super::*;
+    use crate::cpu_6502::branch_stats::{BranchCounts, BranchStats};
+
+    const COUNT_DOWN: &str = "
+        ldx #$03  ; $8000
+      loop:
+        dex       ; $8002
+        bne loop  ; $8003
+    ";
+
+    #[test]
+    fn counts_taken_and_not_taken() {
+        let mut cpu = load_program(COUNT_DOWN);
+        cpu.branch_stats = Some(BranchStats::new());
+        cpu.run(StopCondition::Jam);
+
+        let branch_stats = cpu.branch_stats.unwrap();
+        assert_eq!(
+            branch_stats.branch_counts(0x8003),
+            Some(BranchCounts {
+                taken: 2,
+                not_taken: 1
+            })
+        );
+        assert_eq!(branch_stats.branch_counts(0x8002), None);
+    }
+
+    #[test]
+    fn finds_hot_loops() {
+        let mut cpu = load_program(COUNT_DOWN);
+        cpu.branch_stats = Some(BranchStats::new());
+        cpu.run(StopCondition::Jam);
+
+        let branch_stats = cpu.branch_stats.unwrap();
+        let hot_loops = branch_stats.hot_loops();
+        assert_eq!(hot_loops.len(), 1);
+        let hot_loop = &hot_loops[0];
+        assert_eq!((hot_loop.start, hot_loop.end), (0x8002, 0x8003));
+        assert_eq!(hot_loop.iterations, 2);
+        // ldx is outside of the loop, the dex and bne run 3 times each, and the bne
+        // is taken twice.
+        assert_eq!(hot_loop.cycles, 14);
+        assert_eq!(branch_stats.total_cycles(), 16);
+    }
+
+    #[test]
+    fn forward_branches_are_not_loops() {
+        let mut cpu = load_program(
+            "
+              lda #$01
+              bne skip
+              nop
+            skip:
+              nop
+            ",
+        );
+        cpu.branch_stats = Some(BranchStats::new());
+        cpu.run(StopCondition::Jam);
+
+        let branch_stats = cpu.branch_stats.unwrap();
+        assert_eq!(branch_stats.branch_counts(0x8002).unwrap().taken, 1);
+        assert!(branch_stats.hot_loops().is_empty());
+    }
+}
+
+mod stack_analyzer {
+    use super::*;
+    use crate::cpu_6502::stack_analyzer::{StackAnalyzer, StackWarning};
+
+    fn run_with_analyzer(text: &str, analyzer: StackAnalyzer) -> StackAnalyzer {
+        let mut cpu = load_program(text);
+        cpu.stack_analyzer = Some(analyzer);
+        cpu.run(StopCondition::Jam);
+        cpu.stack_analyzer.unwrap()
+    }
+
+    #[test]
+    fn tracks_max_depth() {
+        let analyzer = run_with_analyzer(
+            "
+                jsr first
+                jmp done
+              first:
+                jsr second
+                rts
+              second:
+                pha
+                pla
+                rts
+              done:
+            ",
+            StackAnalyzer::new(),
+        );
+        // Two return addresses, and the accumulator.
+        assert_eq!(analyzer.max_depth(), 5);
+        assert_eq!(analyzer.max_interrupt_depth(), 0);
+        assert_eq!(analyzer.warnings(), &[]);
+    }
+
+    #[test]
+    fn warns_on_overflow() {
+        let analyzer = run_with_analyzer(
+            "
+                ldx #$00
+              loop:
+                pha       ; $8002
+                dex
+                bne loop
+            ",
+            StackAnalyzer::new(),
+        );
+        assert_eq!(analyzer.max_depth(), 256);
+        assert_eq!(
+            analyzer.warnings(),
+            &[
+                StackWarning::NearOverflow {
+                    pc: 0x8002,
+                    depth: 241
+                },
+                StackWarning::Overflow { pc: 0x8002 },
+            ]
+        );
+    }
+
+    #[test]
+    fn wraps_return_address_in_stack_page() {
+        let mut cpu = load_program(
+            "
+                ldx #$00
+                txs
+                jsr sub   ; $8003
+                nop       ; $8006
+              sub:
+                rts       ; $8007
+            ",
+        );
+        cpu.stack_analyzer = Some(StackAnalyzer::new());
+        cpu.run(StopCondition::PcEquals(0x8007));
+        // The high byte goes to $0100, and then the low byte wraps to $01FF.
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.read_u8(0x0100), 0x80);
+        assert_eq!(bus.read_u8(0x01ff), 0x06);
+        assert_eq!(bus.read_u8(0x00ff), 0x00);
+        assert_eq!(cpu.s, 0xfe);
+        drop(bus);
+
+        // The return address is pulled back across the wrap.
+        cpu.run(StopCondition::PcEquals(0x8006));
+        assert_eq!(cpu.s, 0x00);
+        assert_eq!(
+            cpu.stack_analyzer.as_ref().unwrap().warnings(),
+            &[
+                StackWarning::Overflow { pc: 0x8003 },
+                StackWarning::Underflow { pc: 0x8007 },
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_on_reserved_data() {
+        let mut analyzer = StackAnalyzer::new();
+        analyzer.reserve(0x0100..0x01fa);
+        let analyzer = run_with_analyzer(
+            "
+                ldx #$fa
+                txs
+                pha       ; $8003
+                pha       ; $8004
+            ",
+            analyzer,
+        );
+        assert_eq!(
+            analyzer.warnings(),
+            &[
+                StackWarning::NearReserved {
+                    pc: 0x8003,
+                    address: 0x01fa
+                },
+                StackWarning::Collision {
+                    pc: 0x8004,
+                    address: 0x01f9
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_calls() {
+        let mut cpu = load_program(
+            "
+                jsr first  ; $8000
+                jmp done
+              first:
+                jsr second ; $8006
+                rts
+              second:
+                nop        ; $800a
+                rts
+              done:
+            ",
+        );
+        cpu.stack_analyzer = Some(StackAnalyzer::new());
+        cpu.run(StopCondition::PcEquals(0x800a));
+        let call_stack = cpu.stack_analyzer.as_ref().unwrap().call_stack();
+        assert_eq!(call_stack, &[0x8006, 0x800a]);
+
+        cpu.run(StopCondition::Jam);
+        let stack_analyzer = cpu.stack_analyzer.unwrap();
+        assert!(stack_analyzer.call_stack().is_empty());
+        assert_eq!(stack_analyzer.warnings(), &[]);
+    }
+
+    #[test]
+    fn warns_on_unbalanced_return() {
+        let analyzer = run_with_analyzer(
+            "
+                lda #$80
+                pha
+                lda #$07
+                pha
+                rts       ; $8006
+                nop
+            ",
+            StackAnalyzer::new(),
+        );
+        assert_eq!(
+            analyzer.warnings(),
+            &[StackWarning::UnbalancedReturn { pc: 0x8006 }]
+        );
+    }
+}
+
+mod nmi {
+    use super::*;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::{Cpu6502, CpuVariant};
+
+    const PROGRAM: &str = "
+        lda #$01  ; $8000
+        nop       ; $8002
+        jmp done
+      nmi:
+        ldx #$05
+        rti
+      done:
+    ";
+
+    #[test]
+    fn services_nmi_before_next_instruction() {
+        let mut cpu = load_program_with_vectors(
+            PROGRAM,
+            &[(InterruptVectors::NmiVector as u16, "nmi")],
+        );
+        cpu.tick();
+        cpu.set_nmi();
+        cpu.tick();
+
+        assert_eq!(cpu.pc, 0x8006);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.s, 0xFC);
+        assert!(!cpu.nmi_pending);
+        assert_eq!(cpu.p & I, I);
+
+        let bus = cpu.bus.borrow();
+        // The return address, followed by the status with B clear.
+        assert_eq!(bus.read_u16(0x01FE), 0x8002);
+        assert_eq!(bus.read_u8(0x01FD), P & !B);
+    }
+
+    #[test]
+    fn returns_from_nmi() {
+        let mut cpu = load_program_with_vectors(
+            PROGRAM,
+            &[(InterruptVectors::NmiVector as u16, "nmi")],
+        );
+        cpu.tick();
+        cpu.set_nmi();
+        cpu.run(StopCondition::Jam);
+
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(cpu.x, 0x05);
+        assert_eq!(cpu.s, 0xFF);
+    }
+
+    const BRK_PROGRAM: &str = "
+        brk         ; $8000
+        .byte $ff
+      nmi:
+        ldx #$05    ; $8002
+        rti
+      irq:
+        ldy #$07    ; $8005
+        rti
+    ";
+
+    fn load_brk() -> Cpu6502 {
+        load_program_with_vectors(
+            BRK_PROGRAM,
+            &[
+                (InterruptVectors::NmiVector as u16, "nmi"),
+                (InterruptVectors::IrqBrkVector as u16, "irq"),
+            ],
+        )
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let mut cpu = load_brk();
+        cpu.schedule_nmi(cpu.total_cycles + 3);
+        cpu.tick();
+
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.cycles, 7);
+        assert!(!cpu.nmi_pending);
+        assert_eq!(cpu.scheduled_nmi, None);
+        // The B flag is still set, even though this is the NMI handler.
+        assert_eq!(cpu.bus.borrow().read_u8(0x01FD) & B, B);
+        // The NMI isn't serviced again.
+        cpu.tick();
+        assert_eq!(cpu.x, 0x05);
+    }
+
+    #[test]
+    fn late_nmi_runs_after_brk() {
+        let mut cpu = load_brk();
+        cpu.schedule_nmi(cpu.total_cycles + 4);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8005);
+        assert_eq!(cpu.scheduled_nmi, Some(4));
+
+        // The NMI comes in before the first instruction of the BRK handler.
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.bus.borrow().read_u16(0x01FB), 0x8005);
+    }
+
+    #[test]
+    fn wdc_65c02_brk_is_not_hijacked() {
+        let mut cpu = load_brk();
+        cpu.variant = CpuVariant::Wdc65C02;
+        cpu.schedule_nmi(cpu.total_cycles + 3);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8005);
+    }
+}
+
+mod vblank_race {
+    use super::*;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::Cpu6502;
+
+    /// Run up to the PPUSTATUS read, and schedule the vblank relative to the cycle of
+    /// the read. Then return the status that was read, and the NMIs that ran.
+    fn race(vblank_offset: i64) -> (Cpu6502, u8, u8) {
+        let mut cpu = load_program_with_vectors(
+            "
+                lda #$80
+                sta $2000  ; Turn on the NMI.
+                lda $2002
+                sta $10
+                jmp done
+              nmi:
+                inc $11
+                rti
+              done:
+            ",
+            &[(InterruptVectors::NmiVector as u16, "nmi")],
+        );
+        cpu.tick();
+        cpu.tick();
+        // The read is on the last of lda's 4 cycles.
+        let read_cycle = cpu.total_cycles + 3;
+        cpu.schedule_vblank((read_cycle as i64 + vblank_offset) as u64);
+        cpu.run(StopCondition::Jam);
+        let status = cpu.peek(0x10);
+        let nmis = cpu.peek(0x11);
+        (cpu, status, nmis)
+    }
+
+    #[test]
+    fn read_before_vblank() {
+        let (cpu, status, nmis) = race(1);
+        assert_eq!(status & 0x80, 0);
+        assert_eq!(nmis, 1);
+        assert_eq!(cpu.bus.borrow().peek_u8(0x2002) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn read_after_vblank() {
+        let (cpu, status, nmis) = race(-1);
+        assert_eq!(status & 0x80, 0x80);
+        assert_eq!(nmis, 1);
+        // The read cleared the flag.
+        assert_eq!(cpu.bus.borrow().peek_u8(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn read_on_the_vblank_cycle() {
+        let (cpu, status, nmis) = race(0);
+        assert_eq!(status & 0x80, 0);
+        assert_eq!(nmis, 0);
+        assert_eq!(cpu.bus.borrow().peek_u8(0x2002) & 0x80, 0);
+        assert_eq!(cpu.scheduled_vblank, None);
+    }
+}
+
+mod vblank_analyzer {
+    use super::*;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::vblank_analyzer::{VblankAnalyzer, VblankOverrun};
+
+    const PROGRAM: &str = "
+        nop         ; $8000
+        jmp done    ; $8001
+      nmi:
+        jsr slow    ; $8004
+        rti         ; $8007
+      slow:
+        ldx #$05    ; $8008
+      loop:
+        dex
+        bne loop
+        rts
+      done:
+    ";
+
+    fn run_nmi(analyzer: VblankAnalyzer) -> VblankAnalyzer {
+        let mut cpu = load_program_with_vectors(
+            PROGRAM,
+            &[(InterruptVectors::NmiVector as u16, "nmi")],
+        );
+        cpu.vblank_analyzer = Some(analyzer);
+        cpu.tick();
+        cpu.set_nmi();
+        cpu.run(StopCondition::Jam);
+        cpu.vblank_analyzer.unwrap()
+    }
+
+    #[test]
+    fn measures_nmi_cycles() {
+        let analyzer = run_nmi(VblankAnalyzer::new());
+        assert_eq!(analyzer.frames(), 1);
+        assert_eq!(analyzer.max_cycles(), 51);
+        assert_eq!(analyzer.overruns(), &[]);
+    }
+
+    #[test]
+    fn reports_overruns() {
+        let analyzer = run_nmi(VblankAnalyzer::with_budget(20));
+        assert_eq!(
+            analyzer.overruns(),
+            &[VblankOverrun {
+                frame: 0,
+                cycles: 51,
+                longest_paths: vec![(0x8008, 38), (0x8004, 13)],
+            }]
+        );
+    }
+}
+
+mod irq {
+    use super::*;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::{Cpu6502, IrqSource};
+
+    const PROGRAM: &str = "
+        cli         ; $8000
+        nop         ; $8001
+        jmp done    ; $8002
+      irq:
+        inx         ; $8005
+        rti
+      done:
+    ";
+
+    fn load() -> Cpu6502 {
+        load_program_with_vectors(
+            PROGRAM,
+            &[(InterruptVectors::IrqBrkVector as u16, "irq")],
+        )
+    }
+
+    #[test]
+    fn services_irq() {
+        let mut cpu = load();
+        cpu.tick();
+        cpu.assert_irq(IrqSource::FrameCounter);
+        // The cli doesn't take effect until after the nop.
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8002);
+        cpu.tick();
+
+        assert_eq!(cpu.pc, 0x8005);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.p & I, I);
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.read_u16(0x01FE), 0x8002);
+        assert_eq!(bus.read_u8(0x01FD), P & !B & !I);
+    }
+
+    #[test]
+    fn stays_asserted_until_acknowledged() {
+        let mut cpu = load();
+        cpu.tick();
+        cpu.assert_irq(IrqSource::FrameCounter);
+        cpu.assert_irq(IrqSource::Dmc);
+        // Run the nop, service the interrupt, and run the handler.
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        cpu.acknowledge_irq(IrqSource::FrameCounter);
+        assert!(cpu.is_irq_asserted());
+        cpu.tick();
+        // The DMC is still holding the line, so it runs again right after the rti.
+        assert_eq!(cpu.pc, 0x8002);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8005);
+        cpu.acknowledge_irq(IrqSource::Dmc);
+        assert!(!cpu.is_irq_asserted());
+        cpu.run(StopCondition::Jam);
+
+        assert_eq!(cpu.x, 2);
+        assert_eq!(cpu.s, 0xFF);
+    }
+
+    #[test]
+    fn respects_interrupt_disable() {
+        let mut cpu = load_program_with_vectors(
+            "
+              sei
+              inx
+              inx
+            irq:
+              ldy #$01
+            ",
+            &[(InterruptVectors::IrqBrkVector as u16, "irq")],
+        );
+        cpu.assert_irq(IrqSource::FrameCounter);
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.x, 2);
+        assert_eq!(cpu.s, 0xFF);
+    }
+
+    #[test]
+    fn irq_sneaks_in_after_sei() {
+        let mut cpu = load_program_with_vectors(
+            "
+              cli
+              nop
+              sei         ; $8002
+              inx         ; $8003
+            irq:
+              ldy #$01    ; $8004
+            ",
+            &[(InterruptVectors::IrqBrkVector as u16, "irq")],
+        );
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        // The sei has run, but the IRQ still sees the old flag.
+        cpu.assert_irq(IrqSource::FrameCounter);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8004);
+        assert_eq!(cpu.bus.borrow().read_u16(0x01FE), 0x8003);
+        assert_eq!(cpu.bus.borrow().read_u8(0x01FD) & I, I);
+    }
+
+    #[test]
+    fn plp_is_delayed() {
+        let mut cpu = load_program_with_vectors(
+            "
+              lda #$00
+              pha
+              plp         ; $8003
+              inx         ; $8004
+              inx         ; $8005
+            irq:
+              ldy #$01    ; $8006
+            ",
+            &[(InterruptVectors::IrqBrkVector as u16, "irq")],
+        );
+        cpu.assert_irq(IrqSource::FrameCounter);
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.x, 1);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x8006);
+        assert_eq!(cpu.bus.borrow().read_u16(0x01FE), 0x8005);
+    }
+
+    #[test]
+    fn brk_uses_the_irq_vector() {
+        let mut cpu = load_program_with_vectors(
+            "
+                brk         ; $8000
+                .byte $ff   ; This byte is skipped.
+                jmp done    ; $8002
+              irq:
+                ldx #$07
+                rti
+              done:
+            ",
+            &[(InterruptVectors::IrqBrkVector as u16, "irq")],
+        );
+        cpu.tick();
+        {
+            let bus = cpu.bus.borrow();
+            assert_eq!(bus.read_u16(0x01FE), 0x8002);
+            assert_eq!(bus.read_u8(0x01FD) & B, B);
+        }
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.x, 0x07);
+        assert_eq!(cpu.s, 0xFF);
+    }
+}
+
+mod reset {
+    use super::*;
+
+    #[test]
+    fn reloads_pc_and_keeps_ram() {
+        let mut cpu = load_program(
+            "
+              cli
+              lda #$42
+              sta $10
+              ldx #$05
+              pha
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.s, 0xFE);
+
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.s, 0xFB);
+        assert_eq!(cpu.p & I, I);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.x, 0x05);
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.read_u8(0x10), 0x42);
+        // Nothing was written to the stack.
+        assert_eq!(bus.read_u8(0x01FE), 0x00);
+        assert_eq!(bus.read_u8(0x01FD), 0x00);
+    }
+
+    #[test]
+    fn runs_the_program_again() {
+        let mut cpu = load_program("inx");
+        cpu.run(StopCondition::Jam);
+        cpu.reset();
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.x, 2);
+    }
+
+    #[test]
+    fn during_a_dma() {
+        let mut cpu = load_program(
+            "
+              sei
+              lda #$02
+              sta $4014
+              cli
+            ",
+        );
+        for _ in 0..3 {
+            cpu.tick();
+        }
+        assert!(cpu.dma_stall_cycles > 0);
+        cpu.interrupt_disable_latch = Some(true);
+        cpu.schedule_nmi(cpu.total_cycles + 10);
+
+        let total_cycles = cpu.total_cycles;
+        cpu.reset();
+        assert_eq!(cpu.total_cycles, total_cycles + 7);
+        assert_eq!(cpu.dma_stall_cycles, 0);
+        assert_eq!(cpu.scheduled_nmi, None);
+        assert_eq!(cpu.interrupt_disable_latch, None);
+
+        // The first instruction from the reset vector isn't stalled, or interrupted.
+        cpu.tick();
+        assert_eq!(cpu.cycles, 2);
+        assert_eq!(cpu.pc, 0x8001);
+    }
+}
+
+/// Check the instruction timings against the published cycle counts.
+mod cycles {
+    use super::*;
+    use crate::flat_machine::FlatMachine64K;
+
+    /// Run the program, and return the cycles that the last instruction took.
+    fn last_instruction_cycles(text: &str) -> u16 {
+        let mut cpu = load_program(text);
+        let mut cycles = 0;
+        while cpu.tick().is_running() {
+            cycles = cpu.cycles;
+        }
+        cycles
+    }
+
+    #[test]
+    fn branch_not_taken() {
+        assert_eq!(last_instruction_cycles("lda #$00\nbne skip\nskip:"), 2);
+    }
+
+    #[test]
+    fn branch_taken() {
+        assert_eq!(last_instruction_cycles("lda #$01\nbne skip\nnop\nskip:"), 3);
+    }
+
+    #[test]
+    fn branch_taken_across_page() {
+        // $80fc: ldx #$01
+        // $80fe: bne $80fc
+        // The page is compared against the next instruction at $8100, so branching
+        // back to the branch's own page still crosses a page.
+        let mut machine = FlatMachine64K::new(&[0xa2, 0x01, 0xd0, 0xfe], 0x80fc, 0x80fc);
+        machine.cpu.tick();
+        machine.cpu.tick();
+        assert_eq!(machine.cpu.pc, 0x80fc);
+        assert_eq!(machine.cpu.cycles, 4);
+    }
+
+    #[test]
+    fn branch_taken_within_next_page() {
+        // $80fe: bne $8102, which is on the same page as the next instruction.
+        let mut machine = FlatMachine64K::new(&[0xd0, 0x04], 0x80fe, 0x80fe);
+        machine.cpu.p &= !Z;
+        machine.cpu.tick();
+        assert_eq!(machine.cpu.pc, 0x8102);
+        assert_eq!(machine.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn absolute_indexed_read() {
+        assert_eq!(last_instruction_cycles("ldx #$01\nlda $0200,x"), 4);
+        assert_eq!(last_instruction_cycles("ldy #$01\nlda $0200,y"), 4);
+    }
+
+    #[test]
+    fn absolute_indexed_read_across_page() {
+        assert_eq!(last_instruction_cycles("ldx #$01\nlda $02ff,x"), 5);
+        assert_eq!(last_instruction_cycles("ldy #$01\nldx $02ff,y"), 5);
+        assert_eq!(last_instruction_cycles("ldx #$01\nldy $02ff,x"), 5);
+    }
+
+    #[test]
+    fn absolute_indexed_write_across_page() {
+        // Writes and read-modify-writes always take the fix up cycle.
+        assert_eq!(last_instruction_cycles("ldx #$01\nsta $0200,x"), 5);
+        assert_eq!(last_instruction_cycles("ldx #$01\nsta $02ff,x"), 5);
+        assert_eq!(last_instruction_cycles("ldx #$01\ninc $02ff,x"), 7);
+    }
+    #[test]
+    fn indirect_y_read() {
+        assert_eq!(last_instruction_cycles("ldy #$01\nlda ($10),Y"), 5);
+    }
+
+    #[test]
+    fn indirect_y_read_across_page() {
+        let text = "
+            lda #$ff
+            sta $10
+            lda #$02
+            sta $11
+            ldy #$01
+            lda ($10),Y
+        ";
+        assert_eq!(last_instruction_cycles(text), 6);
+    }
+
+    #[test]
+    fn indirect_y_write_across_page() {
+        let text = "
+            lda #$ff
+            sta $10
+            lda #$02
+            sta $11
+            ldy #$01
+            sta ($10),Y
+        ";
+        assert_eq!(last_instruction_cycles(text), 6);
+    }
+}
+
+/// Drive the mapper clocking hooks through the CPU, with a cartridge that has the
+/// MMC3's IRQ counter.
+mod mapper_irq {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::Cpu6502;
+    use crate::mappers::{Mapper, Mmc3Irq, SimpleProgram};
+
+    struct IrqCartridge {
+        program: SimpleProgram,
+        irq: Mmc3Irq,
+    }
+
+    impl Mapper for IrqCartridge {
+        fn read_cpu(&self, addr: u16) -> Option<u8> {
+            self.program.read_cpu(addr)
+        }
+
+        fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+            if addr >= 0xc000 {
+                self.irq.write_register(addr, value);
+            }
+            self.program.write_cpu(addr, value)
+        }
+
+        fn cpu_cycle(&mut self) {
+            self.irq.cpu_cycle();
+        }
+
+        fn ppu_a12_rise(&mut self) {
+            self.irq.ppu_a12_rise();
+        }
+
+        fn irq_asserted(&self) -> bool {
+            self.irq.is_asserted()
+        }
+    }
+
+    const PROGRAM: &str = "
+        lda #$02
+        sta $c000   ; Latch
+        sta $c001   ; Reload
+        sta $e001   ; Enable
+        cli
+      loop:
+        jmp loop
+      irq:
+        inx
+        sta $e000   ; Acknowledge
+        sta $e001   ; Enable
+        rti
+    ";
+
+    fn load() -> Cpu6502 {
+        let program =
+            assemble_program(PROGRAM, &[(InterruptVectors::IrqBrkVector as u16, "irq")]);
+        Cpu6502::new(Bus::new_shared_bus(Box::new(IrqCartridge {
+            program,
+            irq: Mmc3Irq::new(),
+        })))
+    }
+
+    /// Run a scanline's worth of instructions, and then clock A12.
+    fn scanline(cpu: &mut Cpu6502) {
+        for _ in 0..40 {
+            cpu.tick();
+        }
+        cpu.bus.borrow_mut().ppu_a12_rise();
+    }
+
+    #[test]
+    fn filters_a12_between_instructions() {
+        let mut cpu = load();
+        scanline(&mut cpu);
+        scanline(&mut cpu);
+        // These come too quickly after the last rise, without any CPU cycles in
+        // between, so the MMC3 filters them out.
+        cpu.bus.borrow_mut().ppu_a12_rise();
+        cpu.bus.borrow_mut().ppu_a12_rise();
+        cpu.tick();
+        assert_eq!(cpu.x, 0);
+        assert!(!cpu.is_irq_asserted());
+    }
+
+    #[test]
+    fn fires_after_the_latched_scanlines() {
+        let mut cpu = load();
+        // Reload to 2, then count down to 1.
+        scanline(&mut cpu);
+        scanline(&mut cpu);
+        assert_eq!(cpu.x, 0);
+        // The counter hits 0, and the IRQ is serviced before the next instruction.
+        scanline(&mut cpu);
+        cpu.tick();
+        assert!(cpu.is_irq_asserted());
+        assert_eq!(cpu.p & I, I);
+        // The handler acknowledges it.
+        for _ in 0..3 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.x, 1);
+        assert!(!cpu.is_irq_asserted());
+    }
+
+    #[test]
+    fn fires_every_latch_period() {
+        let mut cpu = load();
+        for _ in 0..9 {
+            scanline(&mut cpu);
+        }
+        // Reloaded on the first scanline, then fires on scanlines 3, 6 and 9.
+        for _ in 0..10 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.x, 3);
+    }
+}
+
+mod accessors {
+    use super::*;
+    use crate::cpu_6502::Registers;
+
+    #[test]
+    fn reads_registers() {
+        let cpu = run_program("lda #$11\nldx #$22\nldy #$33\npha");
+        assert_eq!((cpu.a(), cpu.x(), cpu.y()), (0x11, 0x22, 0x33));
+        // The pc is left past the KIL that the program ends with.
+        assert_eq!(cpu.pc(), 0x8008);
+        assert_eq!(cpu.s(), 0xFE);
+        assert_eq!(cpu.status(), P);
+        assert_eq!(
+            cpu.registers(),
+            Registers {
+                a: 0x11,
+                x: 0x22,
+                y: 0x33,
+                pc: 0x8008,
+                s: 0xFE,
+                p: P,
+            }
+        );
+    }
+
+    #[test]
+    fn sets_registers() {
+        let mut cpu = load_program("inx\niny\nkil\ndex");
+        let mut registers = cpu.registers();
+        registers.x = 0x10;
+        registers.y = 0x20;
+        cpu.set_registers(registers);
+        cpu.run(StopCondition::PcEquals(0x8002));
+        assert_eq!((cpu.x(), cpu.y()), (0x11, 0x21));
+
+        // Skip over the KIL.
+        cpu.set_pc(cpu.pc() + 1);
+        cpu.set_status(P | C);
+        cpu.tick();
+        assert_eq!(cpu.x(), 0x10);
+        assert_eq!(cpu.status(), P | C);
+    }
+
+    #[test]
+    fn peeks_and_pokes() {
+        let mut cpu = load_program("lda $10");
+        cpu.poke(0x10, 0x42);
+        assert_eq!(cpu.peek(0x10), 0x42);
+        // RAM is mirrored.
+        assert_eq!(cpu.peek(0x0810), 0x42);
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.a(), 0x42);
+    }
+}
+
+mod with_state {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu_6502::{Cpu6502, Registers};
+    use crate::mappers::FlatMemory64K;
+
+    #[test]
+    fn starts_from_the_registers() {
+        let mut memory = FlatMemory64K::new();
+        // $0400: inx
+        // $0401: pha
+        memory.load(0x0400, &[0xe8, 0x48]);
+        let bus = Bus::new_shared_bus(Box::new(memory));
+        let mut cpu = Cpu6502::with_state(
+            bus,
+            Registers {
+                a: 0x42,
+                x: 0x10,
+                y: 0x20,
+                pc: 0x0400,
+                s: 0x80,
+                p: P | C,
+            },
+        );
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.x(), 0x11);
+        assert_eq!(cpu.y(), 0x20);
+        assert_eq!(cpu.s(), 0x7F);
+        assert_eq!(cpu.peek(0x0180), 0x42);
+        assert_eq!(cpu.status(), P | C);
+    }
+}
+
+mod decimal_mode {
+    use super::*;
+    use crate::cpu_6502::DecimalMode;
+
+    fn run_decimal(text: &str) -> crate::cpu_6502::Cpu6502 {
+        let mut cpu = load_program(text);
+        cpu.decimal_mode = DecimalMode::Enabled;
+        cpu.run(StopCondition::Jam);
+        cpu
+    }
+
+    #[test]
+    fn ignored_on_the_nes() {
+        let cpu = run_program("sed\nclc\nlda #$09\nadc #$01");
+        assert_eq!(cpu.a(), 0x0a);
+    }
+
+    #[test]
+    fn adds_bcd() {
+        let cpu = run_decimal("sed\nclc\nlda #$09\nadc #$01");
+        assert_eq!(cpu.a(), 0x10);
+        assert_eq!(cpu.status(), P | D);
+
+        let cpu = run_decimal("sed\nsec\nlda #$58\nadc #$46");
+        assert_eq!(cpu.a(), 0x05);
+        assert_eq!(cpu.status() & C, C);
+    }
+
+    #[test]
+    fn subtracts_bcd() {
+        let cpu = run_decimal("sed\nsec\nlda #$10\nsbc #$01");
+        assert_eq!(cpu.a(), 0x09);
+        assert_eq!(cpu.status() & C, C);
+
+        let cpu = run_decimal("sed\nsec\nlda #$00\nsbc #$01");
+        assert_eq!(cpu.a(), 0x99);
+        assert_eq!(cpu.status() & C, 0);
+    }
+
+    /// Bruce Clark's test program, which checks every combination of operands and
+    /// the carry against predictions that only use binary arithmetic. This is the
+    /// 6502 version, with the variables moved to the zero page, and ERROR at $0b.
+    /// http://www.6502.org/tutorials/decimal_mode.html#B
+    const BRUCE_CLARK_TEST: &str = "
+        ; $00 N1, $01 N2, $02 HA, $03 HNVZC, $04 DA, $05 DNVZC, $06 AR, $07 NF,
+        ; $08 VF, $09 ZF, $0a CF, $0b ERROR, $0c N1L, $0d N1H, $0e N2L, $0f N2H
+        ; and $10 N2H+1
+        ldy #$01
+        sty $0b
+        lda #$00
+        sta $00
+        sta $01
+      loop1:
+        lda $01
+        and #$0f
+        sta $0e
+        lda $01
+        and #$f0
+        sta $0f
+        ora #$0f
+        sta $10
+      loop2:
+        lda $00
+        and #$0f
+        sta $0c
+        lda $00
+        and #$f0
+        sta $0d
+        jsr add_test
+        jsr predict_add
+        jsr compare
+        bne done
+        jsr sub_test
+        jsr predict_sub
+        jsr compare
+        bne done
+        inc $00
+        bne loop2
+        inc $01
+        bne loop1
+        dey
+        bpl loop1
+        lda #$00
+        sta $0b
+      done:
+        jmp end
+
+      add_test:
+        sed
+        cpy #$01
+        lda $00
+        adc $01
+        sta $04
+        php
+        pla
+        sta $05
+        cld
+        cpy #$01
+        lda $00
+        adc $01
+        sta $02
+        php
+        pla
+        sta $03
+        cpy #$01
+        lda $0c
+        adc $0e
+        cmp #$0a
+        ldx #$00
+        bcc a1
+        inx
+        adc #$05
+        and #$0f
+        sec
+      a1:
+        ora $0d
+        adc $0f,x
+        php
+        bcs a2
+        cmp #$a0
+        bcc a3
+      a2:
+        adc #$5f
+        sec
+      a3:
+        sta $06
+        php
+        pla
+        sta $0a
+        pla
+        sta $08
+        rts
+
+      sub_test:
+        sed
+        cpy #$01
+        lda $00
+        sbc $01
+        sta $04
+        php
+        pla
+        sta $05
+        cld
+        cpy #$01
+        lda $00
+        sbc $01
+        sta $02
+        php
+        pla
+        sta $03
+        rts
+
+      sub1:
+        cpy #$01
+        lda $0c
+        sbc $0e
+        ldx #$00
+        bcs s11
+        inx
+        sbc #$05
+        and #$0f
+        clc
+      s11:
+        ora $0d
+        sbc $0f,x
+        bcs s12
+        sbc #$5f
+      s12:
+        sta $06
+        rts
+
+      compare:
+        lda $04
+        cmp $06
+        bne c1
+        lda $05
+        eor $07
+        and #$80
+        bne c1
+        lda $05
+        eor $08
+        and #$40
+        bne c1
+        lda $05
+        eor $09
+        and #$02
+        bne c1
+        lda $05
+        eor $0a
+        and #$01
+      c1:
+        rts
+
+      predict_add:
+        lda $08
+        sta $07
+        lda $03
+        sta $09
+        rts
+
+      predict_sub:
+        jsr sub1
+        lda $03
+        sta $07
+        sta $08
+        sta $09
+        sta $0a
+        rts
+      end:
+    ";
+
+    #[test]
+    fn passes_bruce_clarks_test() {
+        let mut machine = load_flat_machine(BRUCE_CLARK_TEST);
+        machine.cpu.decimal_mode = DecimalMode::Enabled;
+        while machine.cpu.tick().is_running() {}
+        if machine.read_u8(0x0b) != 0 {
+            panic!(
+                "Failed with N1 = ${:02x}, N2 = ${:02x}, carry = {}, A = ${:02x}, \
+                 predicted ${:02x}",
+                machine.read_u8(0x00),
+                machine.read_u8(0x01),
+                machine.cpu.y(),
+                machine.read_u8(0x04),
+                machine.read_u8(0x06),
+            );
+        }
+    }
+}
+
+/// The assembler doesn't know the 65C02 instructions, so they are written out as
+/// bytes.
+mod wdc_65c02 {
+    use super::*;
+    use crate::cpu_6502::{Cpu6502, CpuVariant};
+
+    fn load_65c02(text: &str) -> Cpu6502 {
+        // The NMOS KIL is a NOP on the 65C02, so stop with an STP instead.
+        let mut cpu = load_program(&format!("{}\n.byte $db", text));
+        cpu.variant = CpuVariant::Wdc65C02;
+        cpu
+    }
+
+    fn run_65c02(text: &str) -> Cpu6502 {
+        let mut cpu = load_65c02(text);
+        for _ in 0..1000 {
+            if !cpu.tick().is_running() {
+                return cpu;
+            }
+        }
+        panic!("The program did not stop.");
+    }
+
+    #[test]
+    fn phx_plx() {
+        let cpu = run_65c02(
+            "
+              ldx #$42
+              .byte $da ; phx
+              ldx #$00
+              .byte $fa ; plx
+            ",
+        );
+        assert_eq!(cpu.x(), 0x42);
+        assert_eq!(cpu.s(), 0xFF);
+    }
+
+    #[test]
+    fn phy_ply() {
+        let cpu = run_65c02(
+            "
+              ldy #$80
+              .byte $5a ; phy
+              ldy #$00
+              .byte $7a ; ply
+            ",
+        );
+        assert_eq!(cpu.y(), 0x80);
+        assert_eq!(cpu.status(), P | N);
+    }
+
+    #[test]
+    fn stz() {
+        let cpu = run_65c02(
+            "
+              lda #$ff
+              sta $10
+              sta $0234
+              .byte $64, $10       ; stz $10
+              .byte $9c, $34, $02  ; stz $0234
+            ",
+        );
+        assert_eq!(cpu.peek(0x10), 0x00);
+        assert_eq!(cpu.peek(0x0234), 0x00);
+        assert_eq!(cpu.a(), 0xff);
+    }
+
+    #[test]
+    fn bra() {
+        let mut cpu = load_65c02(
+            "
+              .byte $80, $04 ; bra to the opcode address + 4
+              ldx #$01
+              ldy #$01
+            ",
+        );
+        cpu.tick();
+        assert_eq!(cpu.pc(), 0x8004);
+        assert_eq!(cpu.cycles(), 3);
+        while cpu.tick().is_running() {}
+        assert_eq!((cpu.x(), cpu.y()), (0x00, 0x01));
+    }
+
+    #[test]
+    fn tsb_trb() {
+        let cpu = run_65c02(
+            "
+              lda #%00001100
+              sta $10
+              sta $11
+              lda #%00000110
+              .byte $04, $10 ; tsb $10
+              .byte $14, $11 ; trb $11
+            ",
+        );
+        assert_eq!(cpu.peek(0x10), 0b0000_1110);
+        assert_eq!(cpu.peek(0x11), 0b0000_1000);
+        // A & memory was not zero.
+        assert_eq!(cpu.status() & Z, 0);
+    }
+
+    #[test]
+    fn jmp_indirect_crosses_the_page() {
+        let text = "
+            lda #$34
+            sta $02ff
+            lda #$07
+            sta $0300
+            lda #$05
+            sta $0200
+            jmp ($02ff)
+        ";
+        let mut cpu = load_65c02(text);
+        for _ in 0..7 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.pc(), 0x0734);
+        assert_eq!(cpu.cycles(), 6);
+
+        // The NMOS bug reads the high byte from the start of the page.
+        let mut cpu = load_program(text);
+        for _ in 0..7 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.pc(), 0x0534);
+    }
+
+    #[test]
+    fn illegal_opcodes_are_nops() {
+        let cpu = run_65c02(
+            "
+              lda #$01
+              .byte $03      ; slo on the NMOS, 1 byte
+              .byte $02, $ff ; kil on the NMOS, 2 bytes
+              .byte $5c, $ff, $ff ; 3 bytes
+              ldx #$02
+            ",
+        );
+        assert_eq!(cpu.a(), 0x01);
+        assert_eq!(cpu.x(), 0x02);
+        assert_eq!(cpu.peek(0x00), 0x00);
+    }
+}
+
+/// The undocumented NMOS opcodes, run with the default Emulate policy.
+#[rustfmt::skip]
+mod illegal_opcodes {
+  use super::*;
+
+  register_a!(slo, 0b1000_0011, P | N | C, "
+    lda #%11000001
+    sta $10
+    lda #%10000001
+    .byte $07, $10 ; slo $10
+  ");
+  // N and Z come from A, not the shifted value.
+  register_a!(slo_flags, 0x81, P | N, "
+    lda #$00
+    sta $10
+    lda #$81
+    .byte $07, $10 ; slo $10
+  ");
+  register_a!(rla, 0x03, P | C, "
+    lda #$81
+    sta $10
+    sec
+    lda #$ff
+    .byte $27, $10 ; rla $10, $10 = $03, C = 1, A = $ff & $03
+  ");
+  register_a!(sre, 0xfe, P | N | C, "
+    lda #$03
+    sta $10
+    lda #$ff
+    .byte $47, $10 ; sre $10, $10 = $01, C = 1, A = $ff ^ $01
+  ");
+  register_a!(rra, 0x12, P, "
+    lda #$03
+    sta $10
+    clc
+    lda #$10
+    .byte $67, $10 ; rra $10, $10 = $01, and the carry it shifted out is added
+  ");
+  register_a!(sax, 0x30, P, "
+    lda #$f0
+    ldx #$3c
+    .byte $87, $10 ; sax $10
+    lda $10
+  ");
+  register_x!(lax, 0x85, P | N, "
+    lda #$85
+    sta $10
+    lda #$00
+    .byte $a7, $10 ; lax $10
+  ");
+  register_a!(dcp, 0x04, P | C | Z, "
+    lda #$05
+    sta $10
+    lda #$04
+    .byte $c7, $10 ; dcp $10, $10 = $04, and is compared with A
+  ");
+  register_a!(isc, 0x03, P | C, "
+    lda #$01
+    sta $10
+    lda #$05
+    sec
+    .byte $e7, $10 ; isc $10, $10 = $02, A = $05 - $02
+  ");
+  register_a!(anc, 0x80, P | N | C, "
+    lda #$ff
+    .byte $0b, $80 ; anc #$80
+  ");
+  register_a!(alr, 0x01, P | C, "
+    lda #$ff
+    .byte $4b, $03 ; alr #$03
+  ");
+  register_a!(arr, 0xe0, P | N | C, "
+    sec
+    lda #$ff
+    .byte $6b, $c0 ; arr #$c0
+  ");
+  register_a!(arr_overflow, 0x40, P | C | V, "
+    clc
+    lda #$ff
+    .byte $6b, $80 ; arr #$80
+  ");
+  register_a!(xaa, 0x0e, P, "
+    ldx #$0f
+    lda #$00
+    .byte $8b, $ff ; xaa #$ff
+  ");
+  register_x!(axs, 0x0a, P | C, "
+    lda #$0f
+    ldx #$fc
+    .byte $cb, $02 ; axs #$02
+  ");
+  register_a!(las, 0x30, P, "
+    ldx #$f0
+    txs
+    lda #$3f
+    sta $0300
+    ldy #$00
+    .byte $bb, $00, $03 ; las $0300,y
+  ");
+
+  #[test]
+  fn shx() {
+    let cpu = run_program("
+      ldx #$ff
+      ldy #$01
+      .byte $9e, $00, $02 ; shx $0200,y
+    ");
+    // X is ANDed with the high byte of the address plus one.
+    assert_eq!(cpu.peek(0x0201), 0x03);
+  }
+
+  #[test]
+  fn shx_page_crossing() {
+    let cpu = run_program("
+      ldx #$01
+      ldy #$ff
+      .byte $9e, $01, $02 ; shx $0201,y
+    ");
+    // The page crossing replaces the high byte of the address with the value.
+    assert_eq!(cpu.peek(0x0300), 0x00);
+    assert_eq!(cpu.peek(0x0100), 0x01);
+  }
+}
+
+mod illegal_opcode_policy {
+    use super::*;
+    use crate::cpu_6502::{Cpu6502, CpuState, IllegalOpcodePolicy};
+
+    const TEXT: &str = "
+        lda #%10000001
+        sta $10
+        .byte $07, $10 ; slo $10
+        ldx #$02
+    ";
+
+    fn run_with_policy(policy: IllegalOpcodePolicy) -> Cpu6502 {
+        let mut cpu = load_program(TEXT);
+        cpu.illegal_opcode_policy = policy;
+        while cpu.tick().is_running() {}
+        cpu
+    }
+
+    #[test]
+    fn emulate() {
+        let cpu = run_with_policy(IllegalOpcodePolicy::Emulate);
+        assert_eq!(cpu.peek(0x10), 0b0000_0010);
+        assert_eq!(cpu.a(), 0b1000_0011);
+        assert_eq!(cpu.x(), 0x02);
+        assert_eq!(cpu.state(), CpuState::Jammed);
+    }
+
+    #[test]
+    fn treat_as_nop() {
+        let mut cpu = load_program(TEXT);
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::TreatAsNop;
+        cpu.tick();
+        cpu.tick();
+        cpu.tick();
+        // The operand was skipped, and it took the SLO's cycles.
+        assert_eq!(cpu.pc(), 0x8006);
+        assert_eq!(cpu.cycles(), 5);
+
+        while cpu.tick().is_running() {}
+        assert_eq!(cpu.peek(0x10), 0b1000_0001);
+        assert_eq!(cpu.a(), 0b1000_0001);
+        assert_eq!(cpu.x(), 0x02);
+        assert_eq!(cpu.state(), CpuState::Jammed);
+    }
+
+    #[test]
+    fn trap() {
+        let cpu = run_with_policy(IllegalOpcodePolicy::Trap);
+        assert_eq!(cpu.state(), CpuState::IllegalOpcode(0x07));
+        assert_eq!(cpu.pc(), 0x8004);
+        assert_eq!(cpu.peek(0x10), 0b1000_0001);
+        assert_eq!(cpu.x(), 0x00);
+    }
+
+    #[test]
+    fn official_opcodes_are_not_trapped() {
+        let mut cpu = load_program("lda #$01\nnop\nsbc #$01");
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::Trap;
+        while cpu.tick().is_running() {}
+        assert_eq!(cpu.state(), CpuState::Jammed);
+        assert_eq!(cpu.a(), 0xff);
+    }
+}
+
+mod cpu_state {
+    use super::*;
+    use crate::constants::InterruptVectors;
+    use crate::cpu_6502::{Cpu6502, CpuState, CpuVariant, IrqSource};
+
+    fn load_65c02(text: &str, vectors: &[(u16, &str)]) -> Cpu6502 {
+        let mut cpu = load_program_with_vectors(text, vectors);
+        cpu.variant = CpuVariant::Wdc65C02;
+        cpu
+    }
+
+    #[test]
+    fn jammed() {
+        let mut cpu = load_program("lda #$01");
+        assert_eq!(cpu.tick(), CpuState::Running);
+        assert_eq!(cpu.tick(), CpuState::Jammed);
+        let pc = cpu.pc();
+        let tick_count = cpu.tick_count();
+
+        // Nothing happens until a reset.
+        assert_eq!(cpu.tick(), CpuState::Jammed);
+        assert_eq!(cpu.pc(), pc);
+        assert_eq!(cpu.tick_count(), tick_count);
+        assert_eq!(cpu.cycles(), 0);
+
+        cpu.reset();
+        assert_eq!(cpu.state(), CpuState::Running);
+        assert_eq!(cpu.tick(), CpuState::Running);
+        assert_eq!(cpu.a(), 0x01);
+    }
+
+    #[test]
+    fn every_kil_opcode_jams() {
+        for opcode in [
+            0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+        ] {
+            let mut cpu = load_program(&format!(".byte ${:02x}\nlda #$01", opcode));
+            assert_eq!(cpu.tick(), CpuState::Jammed, "${:02x}", opcode);
+            assert_eq!(cpu.a(), 0x00);
+        }
+    }
+
+    #[test]
+    fn wai_waits_for_nmi() {
+        let mut cpu = load_65c02(
+            "
+                .byte $cb ; wai
+                ldx #$02
+            nmi:
+                ldy #$03
+            ",
+            &[(InterruptVectors::NmiVector as u16, "nmi")],
+        );
+        assert_eq!(cpu.tick(), CpuState::WaitingForInterrupt);
+        assert_eq!(cpu.cycles(), 3);
+        assert_eq!(cpu.tick(), CpuState::WaitingForInterrupt);
+        assert_eq!(cpu.cycles(), 1);
+        assert_eq!(cpu.pc(), 0x8001);
+
+        cpu.set_nmi();
+        assert_eq!(cpu.tick(), CpuState::Running);
+        cpu.tick();
+        assert_eq!(cpu.y(), 0x03);
+        assert_eq!(cpu.x(), 0x00);
+    }
+
+    #[test]
+    fn wai_wakes_on_a_disabled_irq() {
+        let mut cpu = load_65c02(
+            "
+                sei
+                .byte $cb ; wai
+                ldx #$02
+            ",
+            &[],
+        );
+        cpu.tick();
+        assert_eq!(cpu.tick(), CpuState::WaitingForInterrupt);
+
+        // The IRQ isn't serviced, the next instruction runs instead.
+        cpu.assert_irq(IrqSource::FrameCounter);
+        assert_eq!(cpu.tick(), CpuState::Running);
+        assert_eq!(cpu.x(), 0x02);
+    }
+
+    #[test]
+    fn wai_is_a_nop_on_the_nmos() {
+        let mut cpu = load_program(".byte $cb, $01");
+        assert_eq!(cpu.tick(), CpuState::Running);
+    }
+}
+
+mod stores {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu_6502::Cpu6502;
+    use crate::mappers::{Mapper, SimpleProgram};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A cartridge with a write only port at $6000, which can't be read.
+    struct PortCartridge {
+        program: SimpleProgram,
+        writes: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Mapper for PortCartridge {
+        fn read_cpu(&self, addr: u16) -> Option<u8> {
+            assert_ne!(addr, 0x6000, "The port was read.");
+            self.program.read_cpu(addr)
+        }
+
+        fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+            if addr == 0x6000 {
+                self.writes.borrow_mut().push(value);
+                return true;
+            }
+            self.program.write_cpu(addr, value)
+        }
+    }
+
+    #[test]
+    fn stores_do_not_read_the_address() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let cartridge = PortCartridge {
+            program: assemble_program(
+                "
+                  lda #$01
+                  ldx #$02
+                  ldy #$03
+                  sta $6000
+                  stx $6000
+                  sty $6000
+                ",
+                &[],
+            ),
+            writes: Rc::clone(&writes),
+        };
+        let mut cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(cartridge)));
+        while cpu.tick().is_running() {}
+        assert_eq!(*writes.borrow(), vec![0x01, 0x02, 0x03]);
+    }
+}
+
+mod oam_dma {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu_6502::Cpu6502;
+    use crate::ppu::{Accuracy, StubPpuRegisters};
+
+    fn oam(bus: &Bus) -> [u8; 0x100] {
+        *bus.device_at::<StubPpuRegisters>(0x2000).unwrap().oam()
+    }
+
+    /// Run the setup, then the DMA, and return the CPU before the next instruction.
+    fn run_dma(setup: &str) -> Cpu6502 {
+        let mut cpu = load_program(&format!(
+            "
+              {}
+              lda #$aa
+              sta $0200
+              lda #$55
+              sta $02ff
+              lda #$02
+              sta $4014
+              nop
+            ",
+            setup
+        ));
+        while cpu.peek(cpu.pc()) != 0xea {
+            cpu.tick();
+        }
+        cpu
+    }
+
+    #[test]
+    fn copies_the_page() {
+        let cpu = run_dma("");
+        let bus = cpu.bus.borrow();
+        assert_eq!(oam(&bus)[0x00], 0xaa);
+        assert_eq!(oam(&bus)[0xff], 0x55);
+    }
+
+    #[test]
+    fn stalls_on_an_even_cycle() {
+        let mut cpu = run_dma("");
+        assert_eq!(cpu.total_cycles(), 18);
+        assert_eq!(cpu.dma_stall_cycles, 513);
+
+        // The next tick includes the stall, and it only counts as one tick.
+        let tick_count = cpu.tick_count();
+        cpu.tick();
+        assert_eq!(cpu.cycles(), 513 + 2);
+        assert_eq!(cpu.total_cycles(), 18 + 513 + 2);
+        assert_eq!(cpu.tick_count(), tick_count + 1);
+    }
+
+    #[test]
+    fn stalls_on_an_odd_cycle() {
+        let mut cpu = run_dma("lda $00");
+        assert_eq!(cpu.total_cycles(), 21);
+        assert_eq!(cpu.dma_stall_cycles, 514);
+        cpu.tick();
+        assert_eq!(cpu.cycles(), 514 + 2);
+    }
+
+    #[test]
+    fn starts_at_the_oam_address() {
+        let cpu = run_dma("lda #$80\nsta $2003");
+        let bus = cpu.bus.borrow();
+        assert_eq!(oam(&bus)[0x80], 0xaa);
+        // It wrapped around, and left OAMADDR where it was.
+        assert_eq!(oam(&bus)[0x7f], 0x55);
+        assert_eq!(bus.peek_u8(0x2004), 0xaa);
+    }
+
+    #[test]
+    fn oam_data() {
+        let mut cpu = load_program(
+            "
+              lda #$10
+              sta $2003
+              lda #$12
+              sta $2004
+              lda #$34
+              sta $200c  ; A mirror of OAMDATA.
+              lda #$11
+              sta $2003
+              lda $2004
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!(oam(&cpu.bus.borrow())[0x10..0x12], [0x12, 0x34]);
+        assert_eq!(cpu.a(), 0x34);
+    }
+
+    #[test]
+    fn corrupted_while_rendering() {
+        let program = "
+              lda #$08
+              sta $2003
+              lda #$12
+              sta $2004  ; Sprite 2, byte 0.
+              lda #$18   ; Show the background.
+              sta $2001
+              lda #$34
+              sta $2004  ; Dropped, and moves on to sprite 3, byte 1.
+              lda #$00
+              sta $2001
+              lda #$56
+              sta $2004
+            ";
+        let mut cpu = load_program(program);
+        cpu.run(StopCondition::Jam);
+        assert_eq!(oam(&cpu.bus.borrow())[0x08..0x0b], [0x12, 0x34, 0x56]);
+
+        let mut cpu = load_program(program);
+        cpu.bus.borrow_mut().set_accuracy(Accuracy::Hardware);
+        cpu.run(StopCondition::Jam);
+        let mut bus = cpu.bus.borrow_mut();
+        assert_eq!(oam(&bus)[0x08..0x0b], [0x12, 0x00, 0x00]);
+        assert_eq!(oam(&bus)[0x0d], 0x56);
+
+        // Setting the address copies its row over the first one.
+        bus.set_u8(0x2001, 0x18);
+        bus.set_u8(0x2003, 0x0a);
+        assert_eq!(oam(&bus)[0x00..0x06], [0x12, 0x00, 0x00, 0x00, 0x00, 0x56]);
+    }
+
+    #[test]
+    fn manual_stall() {
+        let mut cpu = load_program("nop");
+        cpu.dma_stall(4);
+        cpu.tick();
+        assert_eq!(cpu.cycles(), 4 + 2);
+        assert_eq!(cpu.dma_stall_cycles, 0);
+    }
+}
+
+mod dummy_accesses {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu_6502::{Cpu6502, CpuVariant};
+    use crate::mappers::{Mapper, SimpleProgram};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(u16),
+        Write(u16, u8),
+    }
+    use Access::*;
+
+    /// A cartridge with RAM at $6000 that traces every access to it.
+    struct TraceCartridge {
+        program: SimpleProgram,
+        ram: RefCell<Vec<u8>>,
+        accesses: Rc<RefCell<Vec<Access>>>,
+    }
+
+    impl Mapper for TraceCartridge {
+        fn read_cpu(&self, addr: u16) -> Option<u8> {
+            if let 0x6000..=0x7fff = addr {
+                self.accesses.borrow_mut().push(Read(addr));
+                return Some(self.ram.borrow()[(addr - 0x6000) as usize]);
+            }
+            self.program.read_cpu(addr)
+        }
+
+        fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+            if let 0x6000..=0x7fff = addr {
+                self.accesses.borrow_mut().push(Write(addr, value));
+                self.ram.borrow_mut()[(addr - 0x6000) as usize] = value;
+                return true;
+            }
+            self.program.write_cpu(addr, value)
+        }
+    }
+
+    /// Run the setup without tracing, then return the accesses of one instruction.
+    fn trace(variant: CpuVariant, setup: &str, instruction: &str) -> Vec<Access> {
+        let accesses = Rc::new(RefCell::new(vec![]));
+        let cartridge = TraceCartridge {
+            program: assemble_program(
+                &format!(
+                    "
+                      {}
+                      nop
+                      {}
+                    ",
+                    setup, instruction
+                ),
+                &[],
+            ),
+            ram: RefCell::new(vec![0; 0x2000]),
+            accesses: Rc::clone(&accesses),
+        };
+        let mut cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(cartridge)));
+        cpu.variant = variant;
+        while cpu.peek(cpu.pc()) != 0xea {
+            cpu.tick();
+        }
+        cpu.tick();
+        accesses.borrow_mut().clear();
+        cpu.tick();
+        accesses.replace(vec![])
+    }
+
+    #[test]
+    fn indexed_reads_only_read_twice_across_a_page() {
+        let setup = "ldx #$10";
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, setup, "lda $6000,x"),
+            vec![Read(0x6010)]
+        );
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, setup, "lda $60f8,x"),
+            vec![Read(0x6008), Read(0x6108)]
+        );
+    }
+
+    #[test]
+    fn indirect_indexed_reads_across_a_page() {
+        let setup = "
+          lda #$f0
+          sta $00
+          lda #$60
+          sta $01
+          ldy #$20
+        ";
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, setup, "lda ($00),Y"),
+            vec![Read(0x6010), Read(0x6110)]
+        );
+    }
+
+    #[test]
+    fn indexed_stores_always_read_first() {
+        let setup = "
+          ldx #$10
+          lda #$aa
+        ";
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, setup, "sta $6000,x"),
+            vec![Read(0x6010), Write(0x6010, 0xaa)]
+        );
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, setup, "sta $60f8,x"),
+            vec![Read(0x6008), Write(0x6108, 0xaa)]
+        );
+    }
+
+    #[test]
+    fn read_modify_write_writes_twice() {
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, "", "inc $6020"),
+            vec![Read(0x6020), Write(0x6020, 0x00), Write(0x6020, 0x01)]
+        );
+        assert_eq!(
+            trace(CpuVariant::Nmos6502, "ldx #$10", "asl $60f8,x"),
+            vec![
+                Read(0x6008),
+                Read(0x6108),
+                Write(0x6108, 0x00),
+                Write(0x6108, 0x00)
+            ]
+        );
+    }
+
+    #[test]
+    fn dummy_reads_have_side_effects() {
+        // The dummy read of the page crossing lands on $3F02, a mirror of PPUSTATUS,
+        // and the real read goes on to $4002.
+        let mut cpu = load_program("ldx #$10\nlda $3ff2,x");
+        cpu.bus.borrow_mut().set_vblank(true);
+        cpu.tick();
+        cpu.tick();
+        let bus = cpu.bus.borrow();
+        assert!(bus.take_ppu_status_read());
+        assert_eq!(bus.peek_u8(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn the_65c02_reads_instead_of_writing() {
+        assert_eq!(
+            trace(CpuVariant::Wdc65C02, "", "dec $6020"),
+            vec![Read(0x6020), Read(0x6020), Write(0x6020, 0xff)]
+        );
+        // The dummy read of the indexing goes to the operand, which isn't traced.
+        assert_eq!(
+            trace(CpuVariant::Wdc65C02, "ldx #$10", "lda $60f8,x"),
+            vec![Read(0x6108)]
+        );
+    }
+}
+
+mod trace_hook {
+    use super::*;
+    use crate::bus::{BusObserver, ObservedAccess};
+    use crate::cpu_6502::{Registers, TraceEvent};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[test]
+    fn fires_before_each_instruction() {
+        let mut cpu = load_program(
+            "
+              lda #$22
+              sta $1234
+              inx
+            ",
+        );
+        let events = Rc::new(RefCell::new(vec![]));
+        {
+            let events = Rc::clone(&events);
+            cpu.set_trace_hook(move |event: &TraceEvent| {
+                events.borrow_mut().push(event.clone())
+            });
+        }
+        cpu.run(StopCondition::Jam);
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].opcode, 0xa9);
+        assert_eq!(events[0].operands, vec![0x22]);
+        assert_eq!(events[0].registers.pc, 0x8000);
+        assert_eq!(events[0].cycles, 0);
+
+        assert_eq!(events[1].opcode, 0x8d);
+        assert_eq!(events[1].operands, vec![0x34, 0x12]);
+        assert_eq!(
+            events[1].registers,
+            Registers {
+                a: 0x22,
+                x: 0,
+                y: 0,
+                pc: 0x8002,
+                s: 0xff,
+                p: P,
+            }
+        );
+        assert_eq!(events[1].cycles, 2);
+
+        assert_eq!(events[2].opcode, 0xe8);
+        assert!(events[2].operands.is_empty());
+        assert_eq!(events[2].registers.x, 0);
+        assert_eq!(events[2].cycles, 6);
+    }
+
+    #[test]
+    fn can_be_cleared() {
+        let mut cpu = load_program("inx\ninx");
+        let count = Rc::new(RefCell::new(0));
+        {
+            let count = Rc::clone(&count);
+            cpu.set_trace_hook(move |_: &TraceEvent| *count.borrow_mut() += 1);
+        }
+        cpu.tick();
+        cpu.clear_trace_hook();
+        cpu.tick();
+        assert_eq!(*count.borrow(), 1);
+    }
+
+    /// Counts the reads of the program.
+    #[derive(Default)]
+    struct ProgramReads(Cell<usize>);
+
+    impl BusObserver for ProgramReads {
+        fn record_read(&self, access: &ObservedAccess) {
+            if access.address >= 0x8000 {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_read_the_bus() {
+        let run = |trace: bool| {
+            let mut cpu = load_program("lda $1234\nsta $10");
+            cpu.bus
+                .borrow_mut()
+                .set_observer(Some(ProgramReads::default()));
+            if trace {
+                cpu.set_trace_hook(|_: &TraceEvent| {});
+            }
+            cpu.run(StopCondition::Jam);
+            let bus = cpu.bus.borrow();
+            bus.observer::<ProgramReads>().unwrap().0.get()
+        };
+        assert_eq!(run(true), run(false));
+    }
+}
+
+mod register_writes {
+    use super::*;
+
+    #[test]
+    fn records_the_last_write() {
+        let mut cpu = load_program(
+            "
+              lda #$80
+              sta $2000
+              lda #$1e
+              sta $3ff9
+              lda #$0f
+              sta $4015
+              lda #$00
+              sta $2000
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.last_register_write(0x2000), Some(0x00));
+        // $3ff9 is a mirror of PPUMASK.
+        assert_eq!(bus.last_register_write(0x2001), Some(0x1e));
+        assert_eq!(bus.last_register_write(0x4015), Some(0x0f));
+        assert_eq!(bus.last_register_write(0x2002), None);
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "toml"))]
+mod serde_state {
+    use super::*;
+    use crate::cpu_6502::{Cpu6502, IrqSource};
+    use std::rc::Rc;
+
+    #[test]
+    fn round_trips_the_state() {
+        let mut cpu = load_program(
+            "
+              lda #$12
+              ldx #$34
+              ldy #$56
+              sec
+              pha
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        cpu.nmi_pending = true;
+        cpu.irq_sources = IrqSource::Mapper as u8;
+        cpu.dma_stall(513);
+
+        let text = toml::to_string(&cpu).unwrap();
+        let mut restored: Cpu6502 = toml::from_str(&text).unwrap();
+        assert_eq!(restored.registers(), cpu.registers());
+        assert_eq!(restored.cycles, cpu.cycles);
+        assert_eq!(restored.tick_count, cpu.tick_count);
+        assert_eq!(restored.total_cycles, cpu.total_cycles);
+        assert_eq!(restored.dma_stall_cycles, 513);
+        assert!(restored.nmi_pending);
+        assert_eq!(restored.irq_sources, IrqSource::Mapper as u8);
+        assert_eq!(restored.state, cpu.state);
+
+        // The bus isn't part of the state, so hook it back up.
+        restored.bus = Rc::clone(&cpu.bus);
+        assert_eq!(restored.peek(0x01ff), 0x12);
+    }
+}
+
+mod run_for_cycles {
+    use super::*;
+
+    /// A loop of 8 cycles that runs forever.
+    const LOOP: &str = "
+      loop:
+      inc $00
+      jmp loop
+    ";
+
+    #[test]
+    fn carries_over_the_remainder() {
+        let mut cpu = load_program(LOOP);
+        // The instructions take 5 and 3 cycles, so 10 cycles runs over by 3.
+        assert_eq!(cpu.run_for_cycles(10), 13);
+        assert_eq!(cpu.overrun_cycles, 3);
+        // The overrun is taken out of this budget, so only 4 more are needed.
+        assert_eq!(cpu.run_for_cycles(7), 8);
+        assert_eq!(cpu.overrun_cycles, 4);
+        assert_eq!(cpu.total_cycles(), 21);
+    }
+
+    #[test]
+    fn budgets_smaller_than_the_overrun() {
+        let mut cpu = load_program(LOOP);
+        assert_eq!(cpu.run_for_cycles(1), 5);
+        assert_eq!(cpu.run_for_cycles(2), 0);
+        assert_eq!(cpu.overrun_cycles, 2);
+    }
+
+    #[test]
+    fn stops_when_jammed() {
+        let mut cpu = load_program("lda #$01");
+        assert_eq!(cpu.run_for_cycles(100), 2);
+        assert_eq!(cpu.overrun_cycles, 0);
+        assert_eq!(cpu.run_for_cycles(100), 0);
+    }
+}
+
+mod run {
+    use super::*;
+    use crate::cpu_6502::{IllegalOpcodePolicy, StopReason};
+
+    const PROGRAM: &str = "
+        ldx #$00      ; $8000
+      loop:
+        inx           ; $8002
+        stx $10       ; $8003
+        cpx #$05      ; $8005
+        bne loop      ; $8007
+    ";
+
+    #[test]
+    fn stops_on_jam() {
+        let mut cpu = load_program(PROGRAM);
+        assert_eq!(cpu.run(StopCondition::Jam), StopReason::Jammed);
+        assert_eq!(cpu.x, 5);
+        // A jammed CPU stops right away.
+        assert_eq!(cpu.run(StopCondition::MaxCycles(100)), StopReason::Jammed);
+    }
+
+    #[test]
+    fn stops_on_max_cycles() {
+        let mut cpu = load_program(PROGRAM);
+        // ldx takes 2 cycles, then inx 2, and stx 3.
+        assert_eq!(cpu.run(StopCondition::MaxCycles(6)), StopReason::MaxCycles);
+        assert_eq!(cpu.total_cycles(), 7);
+        assert_eq!(cpu.run(StopCondition::MaxCycles(0)), StopReason::MaxCycles);
+    }
+
+    #[test]
+    fn stops_on_memory() {
+        let mut cpu = load_program(PROGRAM);
+        let condition = StopCondition::MemoryEquals {
+            address: 0x10,
+            value: 3,
+        };
+        assert_eq!(cpu.run(condition), StopReason::MemoryEquals);
+        assert_eq!(cpu.pc, 0x8005);
+    }
+
+    #[test]
+    fn breakpoints_can_be_continued() {
+        let mut cpu = load_program(PROGRAM);
+        let breakpoint = StopCondition::Breakpoint(0x8002);
+        assert_eq!(cpu.run(breakpoint), StopReason::Breakpoint);
+        assert_eq!(cpu.x, 0);
+        assert_eq!(cpu.run(breakpoint), StopReason::Breakpoint);
+        assert_eq!(cpu.x, 1);
+        // PcEquals doesn't move on from where it is.
+        assert_eq!(
+            cpu.run(StopCondition::PcEquals(0x8002)),
+            StopReason::PcEquals
+        );
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn stops_on_illegal_opcodes() {
+        let mut cpu = load_program("nop\n.byte $04, $00");
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::Trap;
+        assert_eq!(cpu.run(StopCondition::Jam), StopReason::IllegalOpcode(0x04));
+    }
+}
+
+mod cpu_stats {
+    use super::*;
+    use crate::cpu_6502::cpu_stats::{CpuStats, ExecutionCounts};
+
+    #[test]
+    fn counts_opcodes_and_pcs() {
+        let mut cpu = load_program(
+            "
+                ldx #$03  ; $8000
+              loop:
+                dex       ; $8002
+                bne loop  ; $8003
+            ",
+        );
+        cpu.stats = Some(CpuStats::new());
+        cpu.run(StopCondition::Jam);
+
+        let stats = cpu.stats.unwrap();
+        // dex
+        assert_eq!(
+            stats.opcode_counts(0xca),
+            ExecutionCounts {
+                executions: 3,
+                cycles: 6
+            }
+        );
+        // The bne is taken twice, for an extra cycle each time.
+        assert_eq!(
+            stats.pc_counts(0x8003),
+            Some(ExecutionCounts {
+                executions: 3,
+                cycles: 8
+            })
+        );
+        assert_eq!(stats.pc_counts(0x8005), None);
+        assert_eq!(
+            stats.total(),
+            ExecutionCounts {
+                executions: 7,
+                cycles: 16
+            }
+        );
+        assert_eq!(stats.hot_pcs()[0].0, 0x8003);
+        assert_eq!(stats.hot_opcodes()[0].0, 0xd0);
+    }
+
+    #[test]
+    fn splits_frames() {
+        let mut cpu = load_program(
+            "
+              loop:
+                inc $00
+                jmp loop
+            ",
+        );
+        cpu.stats = Some(CpuStats::new());
+        cpu.run_for_cycles(100);
+        let first = cpu.stats.as_mut().unwrap().end_frame();
+        cpu.run_for_cycles(100);
+        let second = cpu.stats.as_mut().unwrap().end_frame();
+
+        let stats = cpu.stats.unwrap();
+        assert_eq!(stats.frame_cycles(), [first, second]);
+        // Each frame runs over by less than one loop.
+        assert!((100..108).contains(&first), "{}", first);
+        assert_eq!(first + second, stats.total().cycles);
+        assert!(stats.report(5).contains("Frames 2"));
+    }
+}
+
+mod bus_devices {
+    use super::*;
+    use crate::bus::BusDevice;
+    use std::cell::Cell;
+
+    /// A controller's shift register, which hands out one button per read.
+    struct Controller {
+        buttons: u8,
+        shift: Cell<u8>,
+    }
+
+    impl BusDevice for Controller {
+        fn name(&self) -> &str {
+            "controller"
+        }
+
+        fn read(&self, _address: u16) -> u8 {
+            let shift = self.shift.get();
+            self.shift.set(shift >> 1);
+            shift & 1
+        }
+
+        fn write(&mut self, _address: u16, value: u8) {
+            if value & 1 == 1 {
+                self.shift.set(self.buttons);
+            }
+        }
+
+        fn peek(&self, _address: u16) -> Option<u8> {
+            None
+        }
+    }
+
+    #[test]
+    fn routes_accesses() {
+        let mut cpu = load_program(
+            "
+              lda #$01
+              sta $4016  ; Latch the buttons.
+              lda $4016
+              sta $00
+              lda $4016
+              sta $01
+            ",
+        );
+        let controller = Controller {
+            buttons: 0b10,
+            shift: Cell::new(0),
+        };
+        cpu.bus
+            .borrow_mut()
+            .attach_device(0x4016..=0x4016, Box::new(controller))
+            .unwrap();
+        cpu.run(StopCondition::Jam);
+
+        assert_eq!((cpu.peek(0x00), cpu.peek(0x01)), (0, 1));
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.try_read_u8(0x4016), None);
+        assert_eq!(bus.last_register_write(0x4016), Some(0x01));
+    }
+
+    #[test]
+    fn rejects_overlaps() {
+        let cpu = load_program("");
+        let mut bus = cpu.bus.borrow_mut();
+        let controller = || {
+            Box::new(Controller {
+                buttons: 0,
+                shift: Cell::new(0),
+            })
+        };
+        bus.attach_device(0x4016..=0x4017, controller()).unwrap();
+        assert_eq!(
+            bus.attach_device(0x4017..=0x4017, controller()),
+            Err(String::from(
+                "The controller at $4017-$4017 overlaps the controller at $4016-$4017."
+            ))
+        );
+        assert!(bus.detach_device(0x4017).is_some());
+        // Only the PPU registers are left.
+        let ranges: Vec<_> = bus.devices().map(|(_, range)| range).collect();
+        assert_eq!(ranges, [0x2000..=0x3fff]);
+    }
+}
+
+mod ram_mirroring {
+    use super::*;
+
+    #[test]
+    fn writes_show_up_in_every_mirror() {
+        let mut cpu = load_program(
+            "
+              lda #$42
+              sta $0000
+              lda #$43
+              sta $1fff
+              lda $0800
+              ldx $1000
+              ldy $07ff
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!((cpu.a(), cpu.x(), cpu.y()), (0x42, 0x42, 0x43));
+
+        let bus = cpu.bus.borrow();
+        for mirror in &[0x0000, 0x0800, 0x1000, 0x1800] {
+            assert_eq!(bus.read_u8(*mirror), 0x42);
+            assert_eq!(bus.try_read_u8(mirror + 0x07ff), Some(0x43));
+        }
+    }
+
+    #[test]
+    fn zero_page_indexing_stays_in_the_zero_page() {
+        // The index wraps inside the zero page, rather than reaching the mirror.
+        let mut cpu = load_program(
+            "
+              lda #$11
+              sta $0000
+              lda #$22
+              sta $0100
+              ldx #$01
+              lda $ff,x
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.a(), 0x11);
+    }
+
+    #[test]
+    #[should_panic(expected = "Nothing is mapped to $5000 yet.")]
+    fn unmapped_addresses_are_not_ram() {
+        let cpu = load_program("");
+        let mut bus = cpu.bus.borrow_mut();
+        bus.set_open_bus(false);
+        bus.set_u8(0x5000, 0x01);
+    }
+}
+
+mod ppu_register_mirroring {
+    use super::*;
+    use crate::bus::BusDevice;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the addresses that are accessed.
+    struct Recorder {
+        accesses: Rc<RefCell<Vec<(char, u16)>>>,
+    }
+
+    impl BusDevice for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn read(&self, address: u16) -> u8 {
+            self.accesses.borrow_mut().push(('r', address));
+            0x80
+        }
+
+        fn write(&mut self, address: u16, _value: u8) {
+            self.accesses.borrow_mut().push(('w', address));
+        }
+
+        fn peek(&self, _address: u16) -> Option<u8> {
+            Some(0x80)
+        }
+    }
+
+    #[test]
+    fn routes_the_mirrors_to_the_device() {
+        let mut cpu = load_program(
+            "
+              lda #$01
+              sta $2000
+              sta $2008
+              sta $3fff
+              lda $3ffa
+              sta $00
+            ",
+        );
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut bus = cpu.bus.borrow_mut();
+            let registers = bus.detach_device(0x2000).unwrap();
+            assert_eq!(registers.name(), "PPU registers");
+            let recorder = Box::new(Recorder {
+                accesses: Rc::clone(&accesses),
+            });
+            bus.attach_device(0x2000..=0x3fff, recorder).unwrap();
+        }
+        cpu.run(StopCondition::Jam);
+
+        // The whole range goes to the device, which folds the mirrors itself.
+        assert_eq!(
+            *accesses.borrow(),
+            [('w', 0x2000), ('w', 0x2008), ('w', 0x3fff), ('r', 0x3ffa)]
+        );
+        assert_eq!(cpu.peek(0x00), 0x80);
+        assert_eq!(cpu.bus.borrow().try_read_u8(0x2ff2), Some(0x80));
+    }
+
+    #[test]
+    fn the_stub_keeps_the_latch() {
+        let mut cpu = load_program(
+            "
+              lda #$3f
+              sta $2006
+              lda $2000  ; PPUCTRL is write-only.
+              ldx $3ff2  ; PPUSTATUS only has the low bits of the latch.
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!((cpu.a(), cpu.x()), (0x3f, 0x1f));
+    }
+}
+
+mod open_bus {
+    use super::*;
+
+    #[test]
+    fn reads_the_last_value_on_the_bus() {
+        let mut cpu = load_program(
+            "
+              lda $5000  ; The high byte of the address was the last thing read.
+              ldx $4015  ; There's no APU to drive it yet.
+              sta $6000  ; Unmapped writes are dropped.
+              ldy $6000
+            ",
+        );
+        cpu.run(StopCondition::Jam);
+        assert_eq!((cpu.a(), cpu.x(), cpu.y()), (0x50, 0x40, 0x60));
+        assert_eq!(cpu.bus.borrow().try_read_u8(0x5000), None);
+    }
+
+    #[test]
+    fn decays() {
+        let cpu = load_program("");
+        let mut bus = cpu.bus.borrow_mut();
+        bus.set_open_bus_decay(Some(10));
+        bus.set_u8(0x0000, 0x42);
+        bus.clock_cpu_cycles(9);
+        assert_eq!(bus.open_bus(), 0x42);
+        bus.clock_cpu_cycles(1);
+        assert_eq!(bus.read_u8(0x5000), 0x00);
+    }
+}
+
+mod watchpoints {
+    use super::*;
+    use crate::cpu_6502::StopReason;
+    use crate::watchpoints::{AccessKind, WatchpointHit};
+
+    #[test]
+    fn stops_after_the_access() {
+        let mut cpu = load_program(
+            "
+              lda $f3    ; $8000
+              ldx #$07   ; $8002
+              stx $f3    ; $8004, the clobber.
+              lda #$01
+            ",
+        );
+        let id = cpu
+            .bus
+            .borrow_mut()
+            .add_watchpoint(0x00f3..=0x00f3, AccessKind::Write);
+
+        let reason = cpu.run(StopCondition::Jam);
+        assert_eq!(
+            reason,
+            StopReason::Watchpoint {
+                pc: 0x8004,
+                hit: WatchpointHit {
+                    id,
+                    address: 0x00f3,
+                    value: 0x07,
+                    access: AccessKind::Write,
+                },
+            }
+        );
+        assert_eq!(cpu.pc(), 0x8006);
+
+        // It can be continued from there.
+        cpu.bus.borrow_mut().remove_watchpoint(id);
+        assert_eq!(cpu.run(StopCondition::Jam), StopReason::Jammed);
+        assert_eq!(cpu.a(), 0x01);
+    }
+}
+
+mod peek {
+    use super::*;
+    use crate::watchpoints::AccessKind;
+
+    #[test]
+    fn has_no_side_effects() {
+        let cpu = load_program("");
+        let mut bus = cpu.bus.borrow_mut();
+        bus.add_watchpoint(0x0000..=0x07ff, AccessKind::Read);
+        bus.set_u8(0x0010, 0x34);
+        bus.set_u8(0x0011, 0x12);
+
+        assert_eq!(bus.peek_u16(0x0010), 0x1234);
+        // The open bus is still the last write, and reads as the open bus.
+        assert_eq!(bus.open_bus(), 0x12);
+        assert_eq!(bus.peek_u8(0x5000), 0x12);
+        assert!(!bus.has_watchpoint_hits());
+    }
+
+    #[test]
+    fn does_not_trip_read_watchpoints() {
+        let mut cpu = load_program(
+            "
+              ldx #5
+            loop:
+              dex
+              stx $10
+              bne loop
+            ",
+        );
+        cpu.bus
+            .borrow_mut()
+            .add_watchpoint(0x0010..=0x0010, AccessKind::Read);
+        assert_eq!(cpu.peek(0x0010), 0);
+        assert!(!cpu.bus.borrow().has_watchpoint_hits());
+
+        // Polling the address for the stop condition isn't a read by the program.
+        let reason = cpu.run(StopCondition::MemoryEquals {
+            address: 0x0010,
+            value: 2,
+        });
+        assert_eq!(reason, crate::cpu_6502::StopReason::MemoryEquals);
+        assert!(!cpu.bus.borrow().has_watchpoint_hits());
+    }
+}
+
+mod memory_map {
+    use super::*;
+    use crate::bus::{Bus, MemoryMap};
+    use crate::cpu_6502::Cpu6502;
+    use crate::mappers::CustomMemory;
+    use crate::opcodes::OpCode;
+    use nes_asm::asm::AsmLexer;
+
+    #[test]
+    fn runs_from_custom_rom() {
+        let mut lexer = AsmLexer::new(
+            "
+              lda #$42
+              sta $2000  ; RAM, rather than PPUCTRL.
+              sta $e000  ; The ROM ignores the write.
+              lda $9000  ; Nothing is there, so this reads the open bus.
+              sta $10
+            ",
+        );
+        lexer.parse().unwrap();
+        let mut rom = lexer.into_bytes().unwrap().bytes;
+        rom.push(OpCode::KIL as u8);
+        rom.resize(0x2000, 0);
+        // Reset to the start of the ROM.
+        rom[0x1ffc..0x1ffe].copy_from_slice(&[0x00, 0xe0]);
+
+        let memory = CustomMemory::new().ram(0x0000..=0x7fff).rom(0xe000, &rom);
+        let mut cpu = Cpu6502::new(Bus::new_with_memory_map(MemoryMap::Custom(memory)));
+        cpu.run(StopCondition::Jam);
+        assert_eq!(cpu.peek(0x2000), 0x42);
+        assert_eq!(cpu.peek(0xe000), 0xa9);
+        // The high byte of the address was the last thing on the bus.
+        assert_eq!(cpu.peek(0x10), 0x90);
+        // The NES's RAM mirrors aren't there.
+        assert_eq!(cpu.peek(0x0810), 0x00);
+    }
+
+    #[test]
+    fn flat_ram() {
+        let bus = Bus::new_with_memory_map(MemoryMap::flat_ram());
+        let mut bus = bus.borrow_mut();
+        // None of these are registers.
+        bus.set_u8(0x4014, 0x02);
+        bus.set_u8(0x2004, 0x03);
+        bus.set_u8(0xffff, 0x04);
+        assert_eq!(bus.take_pending_oam_dma(), None);
+        assert_eq!(bus.read_u8(0x4014), 0x02);
+        assert_eq!(bus.read_u8(0x2004), 0x03);
+        assert_eq!(bus.read_u8(0xffff), 0x04);
+        assert_eq!(bus.last_register_write(0x4014), None);
+    }
+}
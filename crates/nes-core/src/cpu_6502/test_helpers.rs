@@ -2,9 +2,12 @@
 
 use crate::bus::Bus;
 use crate::cpu_6502::*;
-use crate::{
-    asm::{AsmLexer, BytesLabels},
-    mappers::SimpleProgram,
+use crate::flat_machine::FlatMachine64K;
+use crate::mappers::SimpleProgram;
+use crate::opcodes::OpCode;
+use nes_asm::{
+    asm::{self, AsmLexer, BytesLabels},
+    symbols::Symbols,
 };
 
 pub const P: u8 = RESET_STATUS_FLAG;
@@ -17,18 +20,44 @@ pub const T: u8 = StatusFlag::Push as u8;
 pub const V: u8 = StatusFlag::Overflow as u8;
 pub const N: u8 = StatusFlag::Negative as u8;
 
-pub fn run_program(text: &str) -> Cpu6502 {
+/// Assemble the program and load it into a CPU, but don't run it yet.
+pub fn load_program(text: &str) -> Cpu6502 {
+    load_program_with_vectors(text, &[])
+}
+
+/// Assemble the program and load it into a CPU, and keep the labels.
+pub fn load_program_with_symbols(text: &str) -> (Cpu6502, Symbols) {
+    let (bytes, symbols) = asm::assemble_program(text).unwrap();
+    let cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(SimpleProgram::load(&bytes))));
+    (cpu, symbols)
+}
+
+/// Assemble the program, and point the interrupt vectors at labels in the program,
+/// e.g. `&[(InterruptVectors::NmiVector as u16, "nmi")]`.
+pub fn load_program_with_vectors(text: &str, vectors: &[(u16, &str)]) -> Cpu6502 {
+    let mapper = assemble_program(text, vectors);
+    Cpu6502::new(Bus::new_shared_bus(Box::new(mapper)))
+}
+
+/// Assemble the program into a SimpleProgram cartridge, so that it can be wrapped
+/// by test mappers.
+pub fn assemble_program(text: &str, vectors: &[(u16, &str)]) -> SimpleProgram {
     let mut lexer = AsmLexer::new(text);
 
     match lexer.parse() {
         Ok(_) => {
-            let BytesLabels { mut bytes, .. } = lexer.into_bytes().unwrap();
+            let BytesLabels {
+                mut bytes, symbols, ..
+            } = lexer.into_bytes().unwrap();
             bytes.push(OpCode::KIL as u8);
-            let mut cpu =
-                Cpu6502::new(Bus::new_shared_bus(Box::new(SimpleProgram::load(&bytes))));
-
-            cpu.run();
-            cpu
+            let mut mapper = SimpleProgram::load(&bytes);
+            for (vector, label) in vectors {
+                let address = symbols
+                    .address(label)
+                    .unwrap_or_else(|| panic!("Could not find the label {:?}", label));
+                mapper.set_u16(*vector, address);
+            }
+            mapper
         }
         Err(parse_error) => {
             parse_error.panic_nicely();
@@ -37,6 +66,18 @@ pub fn run_program(text: &str) -> Cpu6502 {
     }
 }
 
+/// Assemble the program into a FlatMachine, see FlatMachine::with_program.
+pub fn load_flat_machine(text: &str) -> FlatMachine64K {
+    let (bytes, _) = asm::assemble_program(text).unwrap();
+    FlatMachine64K::with_program(&bytes).unwrap()
+}
+
+pub fn run_program(text: &str) -> Cpu6502 {
+    let mut cpu = load_program(text);
+    cpu.run(StopCondition::Jam);
+    cpu
+}
+
 /// Run two's complement on a u8.
 pub fn negative(n: u8) -> u8 {
     !n + 1
@@ -68,8 +109,8 @@ pub fn assert_register_y(text: &str, value: u8, status: u8) {
     let cpu = run_program(text);
     if cpu.y != value {
         panic!(
-            "\n{}\nExpected register X to be {:#x} ({:#b}) but it was {:#x} ({:#b})",
-            text, value, value, cpu.x, cpu.x
+            "\n{}\nExpected register Y to be {:#x} ({:#b}) but it was {:#x} ({:#b})",
+            text, value, value, cpu.y, cpu.y
         );
     }
     assert_status(&cpu, status);
@@ -0,0 +1,186 @@
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+/// On NTSC, vblank lasts 20 scanlines of 341 PPU dots, and the CPU runs at a third
+/// of the PPU's speed. (20 * 341) / 3 = 2273.33 cycles
+/// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+pub const NTSC_VBLANK_CYCLES: u64 = 2273;
+
+/// How many of the most expensive routines to keep for each overrun.
+const LONGEST_PATHS_COUNT: usize = 3;
+
+/// A frame where the NMI handler didn't finish before vblank ran out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VblankOverrun {
+    pub frame: u64,
+    pub cycles: u64,
+    /// The routines that used the most cycles in this frame, as (address, cycles).
+    /// The first routine is the NMI handler itself, the rest are entered with JSR.
+    pub longest_paths: Vec<(u16, u64)>,
+}
+
+/// Measures the cycles spent in the NMI handler every frame, and compares it to the
+/// vblank budget. Any work that is still running after vblank ends will happen
+/// while the PPU is drawing, which can cause visual glitches.
+pub struct VblankAnalyzer {
+    budget: u64,
+    frame: u64,
+    in_nmi: bool,
+    cycles: u64,
+    /// The routine addresses, starting with the NMI handler.
+    call_stack: Vec<u16>,
+    cycles_by_routine: BTreeMap<u16, u64>,
+    /// Returns are applied after the instruction is recorded, so that the RTS and RTI
+    /// cycles count towards the routine they return from.
+    pending_returns: u8,
+    pending_nmi_exit: bool,
+    max_cycles: u64,
+    overruns: Vec<VblankOverrun>,
+}
+
+impl VblankAnalyzer {
+    pub fn new() -> VblankAnalyzer {
+        VblankAnalyzer::with_budget(NTSC_VBLANK_CYCLES)
+    }
+
+    pub fn with_budget(budget: u64) -> VblankAnalyzer {
+        VblankAnalyzer {
+            budget,
+            frame: 0,
+            in_nmi: false,
+            cycles: 0,
+            call_stack: Vec::new(),
+            cycles_by_routine: BTreeMap::new(),
+            pending_returns: 0,
+            pending_nmi_exit: false,
+            max_cycles: 0,
+            overruns: Vec::new(),
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// The number of NMIs that have been started.
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    /// The most cycles spent in a single NMI handler.
+    pub fn max_cycles(&self) -> u64 {
+        self.max_cycles
+    }
+
+    pub fn overruns(&self) -> &[VblankOverrun] {
+        &self.overruns
+    }
+
+    /// The NMI was serviced, and will jump to the handler. The cycles of the
+    /// interrupt sequence itself count towards the frame.
+    pub fn enter_nmi(&mut self, handler: u16, cycles: u64) {
+        if self.in_nmi {
+            // The next vblank arrived before the handler was done.
+            self.finish_frame();
+        }
+        self.frame += 1;
+        self.in_nmi = true;
+        self.cycles = 0;
+        self.call_stack.clear();
+        self.call_stack.push(handler);
+        self.cycles_by_routine.clear();
+        self.record_cycles(cycles);
+    }
+
+    pub fn exit_nmi(&mut self) {
+        if self.in_nmi {
+            self.pending_nmi_exit = true;
+        }
+    }
+
+    pub fn enter_subroutine(&mut self, address: u16) {
+        if self.in_nmi {
+            self.call_stack.push(address);
+        }
+    }
+
+    pub fn exit_subroutine(&mut self) {
+        if self.in_nmi {
+            self.pending_returns += 1;
+        }
+    }
+
+    pub fn record_instruction(&mut self, cycles: u64) {
+        if !self.in_nmi {
+            return;
+        }
+        self.record_cycles(cycles);
+
+        for _ in 0..self.pending_returns {
+            // Never pop the NMI handler off, as an unbalanced RTS would lose track
+            // of the frame.
+            if self.call_stack.len() > 1 {
+                self.call_stack.pop();
+            }
+        }
+        self.pending_returns = 0;
+
+        if self.pending_nmi_exit {
+            self.finish_frame();
+        }
+    }
+
+    fn record_cycles(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if let Some(&routine) = self.call_stack.last() {
+            *self.cycles_by_routine.entry(routine).or_insert(0) += cycles;
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        self.in_nmi = false;
+        self.pending_nmi_exit = false;
+        self.pending_returns = 0;
+        self.max_cycles = self.max_cycles.max(self.cycles);
+
+        if self.cycles > self.budget {
+            let mut longest_paths: Vec<(u16, u64)> = self
+                .cycles_by_routine
+                .iter()
+                .map(|(&address, &cycles)| (address, cycles))
+                .collect();
+            longest_paths.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            longest_paths.truncate(LONGEST_PATHS_COUNT);
+
+            self.overruns.push(VblankOverrun {
+                // Frames are reported starting at 0.
+                frame: self.frame - 1,
+                cycles: self.cycles,
+                longest_paths,
+            });
+        }
+    }
+
+    /// Create a human readable report of the vblank usage and any overruns.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "Vblank budget ({} frames, max {} of {} cycles, {} overruns)\n",
+            self.frame,
+            self.max_cycles,
+            self.budget,
+            self.overruns.len()
+        );
+        for overrun in &self.overruns {
+            report.push_str(&format!(
+                "  frame {:>6}  {} cycles, {} over\n",
+                overrun.frame,
+                overrun.cycles,
+                overrun.cycles - self.budget
+            ));
+            for (address, cycles) in &overrun.longest_paths {
+                report.push_str(&format!("    ${:04x}  {:>8} cycles\n", address, cycles));
+            }
+        }
+        report
+    }
+}
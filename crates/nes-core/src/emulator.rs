@@ -0,0 +1,509 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::battery::{self, SaveConfig};
+use crate::controller::{Controller, ControllerPorts};
+use crate::cpu_6502::Cpu6502;
+use crate::ppu::Ppu;
+use crate::profiler::{Profiler, Subsystem};
+use crate::region::Region;
+#[cfg(feature = "snapshots")]
+use crate::snapshot::SnapshotPublisher;
+use crate::telemetry::TelemetryLog;
+use crate::{
+    audio::{mixer::Mixer, AudioConfig},
+    bus::{Bus, SharedBus},
+    mappers::Mapper,
+};
+
+/// Give the game more CPU time per frame than the hardware has, which cuts down on
+/// the slowdown in busy scenes. Some games depend on the exact timing, so this can
+/// cause glitches. Either way, the audio stays in time, as it's only clocked for the
+/// frame's normal length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overclock {
+    /// Scanlines added after the picture is drawn, where the game is still running.
+    pub extra_scanlines: u64,
+    /// Multiplies all of the cycles in a frame, including the extra scanlines.
+    pub cpu_multiplier: u64,
+}
+
+impl Default for Overclock {
+    fn default() -> Self {
+        Overclock {
+            extra_scanlines: 0,
+            cpu_multiplier: 1,
+        }
+    }
+}
+
+pub struct Emulator {
+    pub bus: SharedBus,
+    pub cpu: Cpu6502,
+    pub ppu: Ppu,
+    /// The standard controllers in the two ports, for the frontend to set the buttons.
+    pub controllers: [Controller; 2],
+    region: Region,
+    overclock: Overclock,
+    pub mixer: Mixer,
+    /// Optionally publish a snapshot at the end of every frame, for other threads.
+    #[cfg(feature = "snapshots")]
+    pub snapshots: Option<SnapshotPublisher>,
+    profiler: Option<Profiler>,
+    telemetry: Option<TelemetryLog>,
+    /// Where the cartridge's battery-backed RAM is saved, see battery.
+    battery_path: Option<PathBuf>,
+    save_config: SaveConfig,
+    /// The RAM as it was last loaded or saved, so that it's only written when it
+    /// changed.
+    saved_battery: Vec<u8>,
+    /// The emulated time since the last autosave.
+    since_autosave: Duration,
+    /// Why the last autosave failed, see last_autosave_error.
+    autosave_error: Option<io::Error>,
+    sample_rate: u32,
+    /// The fraction of a sample that is left over from the last frame, measured in
+    /// CPU cycles times the sample rate times the normal frame length, so that it stays
+    /// exact when overclocked.
+    sample_remainder: u64,
+}
+
+impl Emulator {
+    pub fn new(cartridge: Box<dyn Mapper>) -> Emulator {
+        let bus = Bus::new_shared_bus(cartridge);
+        let mut mixer = Mixer::new();
+        bus.borrow_mut().register_audio(&mut mixer);
+        let controllers = ControllerPorts::attach(&mut bus.borrow_mut())
+            .expect("Nothing else is attached to a new bus.");
+        Emulator {
+            cpu: Cpu6502::new(Rc::clone(&bus)),
+            ppu: Ppu::new(Rc::clone(&bus)),
+            controllers,
+            // Take ownership of the initial bus.
+            bus,
+            region: Region::Ntsc,
+            overclock: Overclock::default(),
+            mixer,
+            #[cfg(feature = "snapshots")]
+            snapshots: None,
+            profiler: None,
+            telemetry: None,
+            battery_path: None,
+            save_config: SaveConfig::default(),
+            saved_battery: Vec::new(),
+            since_autosave: Duration::ZERO,
+            autosave_error: None,
+            sample_rate: AudioConfig::default().sample_rate,
+            sample_remainder: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_remainder = 0;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch between the NTSC and PAL timing. A console can't change regions while
+    /// it's running, so this presses the reset button. See Region::detect to pick the
+    /// region for a ROM.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.sample_remainder = 0;
+        self.cpu.overrun_cycles = 0;
+        self.cpu.reset();
+    }
+
+    pub fn overclock(&self) -> Overclock {
+        self.overclock
+    }
+
+    pub fn set_overclock(&mut self, overclock: Overclock) {
+        assert!(
+            overclock.cpu_multiplier > 0,
+            "The CPU multiplier can't be 0."
+        );
+        self.overclock = overclock;
+        self.sample_remainder = 0;
+    }
+
+    /// Start timing the emulator's subsystems, see Profiler.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.bus.borrow_mut().set_mapper_timing(profiler.is_some());
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Log the session, see TelemetryLog. The frames are counted as they run, and the
+    /// frontend records everything else.
+    pub fn set_telemetry(&mut self, telemetry: Option<TelemetryLog>) {
+        self.telemetry = telemetry;
+    }
+
+    pub fn telemetry_mut(&mut self) -> Option<&mut TelemetryLog> {
+        self.telemetry.as_mut()
+    }
+
+    pub fn save_config(&self) -> &SaveConfig {
+        &self.save_config
+    }
+
+    /// Change where and how often the battery-backed RAM is saved. Call this before
+    /// load_battery, as that picks the path of the save.
+    pub fn set_save_config(&mut self, save_config: SaveConfig) {
+        self.save_config = save_config;
+    }
+
+    /// Load the ROM's save into the cartridge's battery-backed RAM, and keep the path
+    /// for save_battery and the autosaves. Returns false if there was nothing to load,
+    /// or the saves are disabled.
+    pub fn load_battery(&mut self, rom_path: &Path) -> io::Result<bool> {
+        if self.save_config.disabled {
+            return Ok(false);
+        }
+        let path = self.save_config.save_path(rom_path);
+        let mut bus = self.bus.borrow_mut();
+        let loaded = battery::load(&mut bus, &path)?;
+        self.saved_battery = bus.battery_ram().map(<[u8]>::to_vec).unwrap_or_default();
+        self.battery_path = Some(path);
+        self.since_autosave = Duration::ZERO;
+        self.autosave_error = None;
+        Ok(loaded)
+    }
+
+    /// Write out the battery-backed RAM, like when the game stops, see load_battery.
+    /// Returns false when there was nothing to save, including when the RAM hasn't
+    /// changed since it was last loaded or saved.
+    pub fn save_battery(&mut self) -> io::Result<bool> {
+        let path = match self.battery_path {
+            Some(ref path) => path,
+            None => return Ok(false),
+        };
+        let bus = self.bus.borrow();
+        match bus.battery_ram() {
+            Some(ram) if ram != &self.saved_battery[..] => {}
+            _ => return Ok(false),
+        }
+        let saved = battery::save(&bus, path)?;
+        if let Some(ram) = bus.battery_ram() {
+            self.saved_battery = ram.to_vec();
+        }
+        Ok(saved)
+    }
+
+    /// Save the battery-backed RAM once the autosave interval has passed, see
+    /// SaveConfig::autosave_interval.
+    fn autosave(&mut self) {
+        let interval = match self.save_config.autosave_interval {
+            Some(interval) if self.battery_path.is_some() => interval,
+            _ => return,
+        };
+        self.since_autosave +=
+            Duration::from_secs_f64(1.0 / self.region.frames_per_second());
+        if self.since_autosave < interval {
+            return;
+        }
+        self.since_autosave = Duration::ZERO;
+        self.autosave_error = self.save_battery().err();
+    }
+
+    /// The error from the last autosave, if it failed. The emulator keeps running, and
+    /// the next autosave tries again, so it's up to the frontend to tell the player.
+    pub fn last_autosave_error(&self) -> Option<&io::Error> {
+        self.autosave_error.as_ref()
+    }
+
+    /// The cycles in a frame, including any overclock.
+    pub fn frame_cycles(&self) -> u64 {
+        let Overclock {
+            extra_scanlines,
+            cpu_multiplier,
+        } = self.overclock;
+        (self.region.frame_cycles() + self.region.scanline_cycles(extra_scanlines))
+            * cpu_multiplier
+    }
+
+    /// Run the CPU for one frame, and return how many cycles it ran. See
+    /// Cpu6502::run_for_cycles for how the frames are lined up with the instructions.
+    /// If the CPU or the PPU are collecting stats, this ends the frame for them, any
+    /// snapshot is published, and the frame is counted in the telemetry. When
+    /// profiling, this starts the profiler's next frame. The battery-backed RAM is
+    /// autosaved, see SaveConfig.
+    pub fn run_frame(&mut self) -> u64 {
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.start_frame();
+        }
+        let start = Instant::now();
+        let cycles = self.cpu.run_for_cycles(self.frame_cycles());
+        if let Some(ref mut profiler) = self.profiler {
+            let mapper_time = self.bus.borrow_mut().take_mapper_time();
+            profiler.record(Subsystem::Cpu, start.elapsed().saturating_sub(mapper_time));
+            profiler.record(Subsystem::Mapper, mapper_time);
+        }
+        if let Some(ref mut stats) = self.cpu.stats {
+            stats.end_frame();
+        }
+        if let Some(ref mut tile_stats) = self.ppu.tile_stats {
+            tile_stats.end_frame();
+        }
+        #[cfg(feature = "snapshots")]
+        if let Some(ref mut snapshots) = self.snapshots {
+            snapshots.publish(&self.cpu, cycles);
+        }
+        if let Some(ref mut telemetry) = self.telemetry {
+            telemetry.record_frame();
+        }
+        self.autosave();
+        cycles
+    }
+
+    /// Run one frame, and return the audio samples it produced. The number of samples
+    /// only depends on the cycles that ran, so the same program always produces the
+    /// same buffers, which makes them usable for golden tests. There is no APU yet,
+    /// so only the expansion audio is mixed in, and its level is only sampled at the
+    /// end of the frame.
+    pub fn run_frame_collect_audio(&mut self) -> Vec<f32> {
+        let cycles = self.run_frame();
+        let start = Instant::now();
+        // Scale the cycles back down to the frame's normal length when overclocked.
+        let cpu_hz = self.region.cpu_hz() * self.frame_cycles();
+        let elapsed = self.sample_remainder
+            + cycles * self.sample_rate as u64 * self.region.frame_cycles();
+        self.sample_remainder = elapsed % cpu_hz;
+        self.bus.borrow().mix_audio(&mut self.mixer);
+        let samples = vec![self.mixer.output(); (elapsed / cpu_hz) as usize];
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(Subsystem::Apu, start.elapsed());
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio;
+    use crate::mappers::SimpleProgram;
+    use crate::region::NTSC_FRAME_CYCLES;
+    use crate::region::PAL_FRAME_CYCLES;
+    use std::time::Duration;
+
+    fn emulator() -> Emulator {
+        // loop:
+        //   inc $00
+        //   jmp loop
+        Emulator::new(Box::new(SimpleProgram::load(&[
+            0xe6, 0x00, 0x4c, 0x00, 0x80,
+        ])))
+    }
+
+    fn collect_frames(emulator: &mut Emulator, frames: usize) -> Vec<Vec<f32>> {
+        (0..frames)
+            .map(|_| emulator.run_frame_collect_audio())
+            .collect()
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut emulator = emulator();
+        let cycles = emulator.run_frame() + emulator.run_frame();
+        assert_eq!(emulator.cpu.total_cycles(), cycles);
+        // The cycles that ran past the end of the frame are owed to the next one.
+        assert_eq!(cycles, NTSC_FRAME_CYCLES * 2 + emulator.cpu.overrun_cycles);
+    }
+
+    #[test]
+    fn test_audio_is_deterministic() {
+        let a = collect_frames(&mut emulator(), 10);
+        let b = collect_frames(&mut emulator(), 10);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_audio_golden() {
+        let mut emulator = emulator();
+        let frames = collect_frames(&mut emulator, 60);
+        let lengths: Vec<usize> = frames.iter().take(4).map(Vec::len).collect();
+        assert_eq!(lengths, vec![733, 734, 734, 734]);
+        // One second of frames is a bit short of a second of audio, as the NES runs
+        // slightly faster than 60 frames per second.
+        let samples: Vec<f32> = frames.concat();
+        assert_eq!(samples.len(), 44_028);
+        // Update the hash when the APU changes the audio on purpose. `audio::write_wav`
+        // can write out the samples to listen to them.
+        assert_eq!(audio::samples_hash(&samples), 0xd75d_3285);
+    }
+
+    #[test]
+    fn test_overclock() {
+        let mut emulator = emulator();
+        emulator.set_overclock(Overclock {
+            extra_scanlines: 0,
+            cpu_multiplier: 2,
+        });
+        let frames = collect_frames(&mut emulator, 60);
+        assert_eq!(
+            emulator.cpu.total_cycles(),
+            emulator.frame_cycles() * 60 + emulator.cpu.overrun_cycles
+        );
+        // The audio is the same length as without the overclock.
+        let samples: usize = frames.iter().map(Vec::len).sum();
+        assert_eq!(samples, 44_028);
+
+        emulator.set_overclock(Overclock {
+            extra_scanlines: 30,
+            cpu_multiplier: 1,
+        });
+        assert_eq!(emulator.frame_cycles(), NTSC_FRAME_CYCLES + 3410);
+        let samples: usize = collect_frames(&mut emulator, 60).iter().map(Vec::len).sum();
+        assert_eq!(samples, 44_028);
+    }
+
+    #[test]
+    fn test_pal() {
+        let mut emulator = emulator();
+        emulator.run_frame();
+        emulator.set_region(Region::Pal);
+        // The switch resets the console.
+        assert_eq!(emulator.cpu.pc, 0x8000);
+        let cycles = emulator.run_frame();
+        assert_eq!(cycles, PAL_FRAME_CYCLES + emulator.cpu.overrun_cycles);
+
+        // Like NTSC, 50 frames of PAL are a bit short of a second of audio.
+        let samples: usize = collect_frames(&mut emulator, 50).iter().map(Vec::len).sum();
+        assert_eq!(samples, 44_094);
+    }
+
+    #[test]
+    fn test_profiler() {
+        let mut emulator = emulator();
+        emulator.set_profiler(Some(Profiler::new(60)));
+        collect_frames(&mut emulator, 3);
+        // The third frame is finished by the next one.
+        let profiler = emulator.profiler().unwrap();
+        assert_eq!(profiler.frames().len(), 2);
+        let frame = profiler.last_frame().unwrap();
+        assert!(frame.get(Subsystem::Cpu) > Duration::default());
+        assert_eq!(frame.get(Subsystem::Ppu), Duration::default());
+
+        emulator.set_profiler(None);
+        emulator.run_frame();
+        assert_eq!(
+            emulator.bus.borrow_mut().take_mapper_time(),
+            Duration::default()
+        );
+    }
+
+    /// An NROM cartridge with 8 KB of battery-backed RAM, running:
+    ///
+    /// loop:
+    ///   inc $6000
+    ///   jmp loop
+    fn battery_emulator() -> Emulator {
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0b10];
+        bytes.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[..6].copy_from_slice(&[0xee, 0x00, 0x60, 0x4c, 0x00, 0x80]);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        bytes.extend_from_slice(&prg);
+        let cartridge = crate::cartridge::Cartridge::from_bytes(&bytes).unwrap();
+        Emulator::new(cartridge.into_mapper())
+    }
+
+    #[test]
+    fn test_battery_autosave() {
+        let dir =
+            std::env::temp_dir().join(format!("nes-autosave-{}", std::process::id()));
+        let rom_path = dir.join("game.nes");
+        let save_config = SaveConfig {
+            directory: Some(dir.join("saves")),
+            autosave_interval: Some(Duration::from_secs(1)),
+            disabled: false,
+        };
+        let save_path = dir.join("saves/game.sav");
+
+        let mut emulator = battery_emulator();
+        emulator.set_save_config(save_config.clone());
+        assert!(!emulator.load_battery(&rom_path).unwrap());
+        for _ in 0..30 {
+            emulator.run_frame();
+        }
+        assert!(!save_path.exists());
+        // A second of frames is a bit short of a second, as the NES runs slightly
+        // faster than 60 frames per second.
+        for _ in 0..31 {
+            emulator.run_frame();
+        }
+        assert_eq!(
+            std::fs::read(&save_path).unwrap()[0],
+            emulator.cpu.peek(0x6000)
+        );
+
+        // The RAM is only written when it changed.
+        emulator.run_frame();
+        assert!(emulator.save_battery().unwrap());
+        assert!(!emulator.save_battery().unwrap());
+        let saved = emulator.cpu.peek(0x6000);
+
+        let mut emulator = battery_emulator();
+        emulator.set_save_config(save_config.clone());
+        assert!(emulator.load_battery(&rom_path).unwrap());
+        assert_eq!(emulator.cpu.peek(0x6000), saved);
+
+        let mut emulator = battery_emulator();
+        emulator.set_save_config(SaveConfig {
+            disabled: true,
+            ..save_config
+        });
+        assert!(!emulator.load_battery(&rom_path).unwrap());
+        assert_eq!(emulator.cpu.peek(0x6000), 0);
+        emulator.run_frame();
+        assert!(!emulator.save_battery().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_battery_autosave_error() {
+        let dir = std::env::temp_dir()
+            .join(format!("nes-autosave-error-{}", std::process::id()));
+        let mut emulator = battery_emulator();
+        emulator.set_save_config(SaveConfig {
+            directory: Some(dir.join("saves")),
+            autosave_interval: Some(Duration::from_secs(1)),
+            disabled: false,
+        });
+        assert!(!emulator.load_battery(&dir.join("game.nes")).unwrap());
+        // A file is in the way of the save directory.
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("saves"), []).unwrap();
+        for _ in 0..61 {
+            emulator.run_frame();
+        }
+        assert!(emulator.last_autosave_error().is_some());
+
+        // The next autosave tries again.
+        std::fs::remove_file(dir.join("saves")).unwrap();
+        for _ in 0..61 {
+            emulator.run_frame();
+        }
+        assert!(emulator.last_autosave_error().is_none());
+        assert!(dir.join("saves/game.sav").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
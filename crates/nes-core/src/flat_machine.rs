@@ -0,0 +1,157 @@
+use crate::bus::{Bus, MemoryMap};
+use crate::constants::{memory_range, InterruptVectors};
+use crate::cpu_6502::Cpu6502;
+use crate::mappers::FlatMemory;
+use crate::prelude::*;
+use crate::virtual_console::{ConsoleHandle, VirtualConsole, CONSOLE_RANGE};
+
+/// A 6502 with SIZE bytes of RAM, and nothing else. There is no NES I/O or
+/// mirroring, so this can run standalone programs like the Klaus Dormann functional
+/// tests, or the visualizer's .asm samples. The RAM repeats through the address
+/// space like FlatMemory, so a smaller machine sees its RAM at every multiple of
+/// SIZE.
+pub struct FlatMachine<const SIZE: usize> {
+    pub cpu: Cpu6502,
+    /// The assembled programs can print and exit through a VirtualConsole.
+    pub console: Option<ConsoleHandle>,
+}
+
+pub type FlatMachine64K = FlatMachine<0x10000>;
+
+impl<const SIZE: usize> FlatMachine<SIZE> {
+    /// Copy the image into memory at the load address, and point the reset vector
+    /// at the entry. The image can cover the whole memory, but then the entry will
+    /// overwrite its reset vector.
+    pub fn new(image: &[u8], load_address: u16, entry: u16) -> FlatMachine<SIZE> {
+        let mut memory = FlatMemory::<SIZE>::new();
+        memory.load(load_address, image);
+        memory.load(InterruptVectors::ResetVector as u16, &entry.to_le_bytes());
+
+        FlatMachine {
+            cpu: Cpu6502::new(Bus::new_with_memory_map(MemoryMap::Flat(Box::new(
+                memory,
+            )))),
+            console: None,
+        }
+    }
+
+    /// Load the program at $8000, and start it there, like the assembled programs. The
+    /// VirtualConsole takes over its addresses from the memory.
+    pub fn with_program(program: &[u8]) -> Result<FlatMachine<SIZE>, String> {
+        let start = memory_range::PRG_ROM.start;
+        let mut memory = FlatMemory::<SIZE>::new();
+        memory.load(start, program);
+        memory.load(InterruptVectors::ResetVector as u16, &start.to_le_bytes());
+        // The memory claims its addresses before any device, so it leaves a gap for
+        // the console.
+        memory.unmap(CONSOLE_RANGE);
+        let bus = Bus::new_with_memory_map(MemoryMap::Flat(Box::new(memory)));
+        let console = VirtualConsole::attach(&mut bus.borrow_mut())?;
+
+        Ok(FlatMachine {
+            cpu: Cpu6502::new(bus),
+            console: Some(console),
+        })
+    }
+
+    pub fn read_u8(&self, address: u16) -> u8 {
+        self.cpu.bus.borrow().read_u8(address)
+    }
+
+    pub fn set_u8(&mut self, address: u16, value: u8) {
+        self.cpu.bus.borrow_mut().set_u8(address, value);
+    }
+
+    /// Test programs signal that they are done by jumping or branching to
+    /// themselves. Run until that happens, and return the address of the trap.
+    /// None is returned if the program halts with a KIL, or runs out of instructions.
+    pub fn run_until_trap(&mut self, max_instructions: u64) -> Option<u16> {
+        for _ in 0..max_instructions {
+            let pc = self.cpu.pc;
+            if !self.cpu.tick().is_running() {
+                return None;
+            }
+            if self.cpu.pc == pc {
+                return Some(pc);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_6502::test_helpers::load_flat_machine;
+
+    #[test]
+    fn test_no_mirroring() {
+        let mut machine = load_flat_machine(
+            "
+                lda #$11
+                sta $0000
+                lda #$22
+                sta $0800
+                lda #$33
+                sta $2000
+            ",
+        );
+        while machine.cpu.tick().is_running() {}
+        assert_eq!(machine.read_u8(0x0000), 0x11);
+        assert_eq!(machine.read_u8(0x0800), 0x22);
+        assert_eq!(machine.read_u8(0x2000), 0x33);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut machine = load_flat_machine(
+            "
+                lda #$05      ; $8000
+                sta $8006     ; Change the operand of the next instruction.
+                ldx #$00      ; $8005
+            ",
+        );
+        while machine.cpu.tick().is_running() {}
+        assert_eq!(machine.cpu.x, 0x05);
+    }
+
+    #[test]
+    fn test_console() {
+        let mut machine = load_flat_machine(
+            "
+                lda #$21
+                sta $401b
+                sta $401a
+            ",
+        );
+        while machine.cpu.tick().is_running() {}
+        assert_eq!(machine.console.as_ref().unwrap().output(), "!");
+        // The rest of the test mode range is still memory.
+        assert_eq!(machine.read_u8(0x401a), 0x21);
+    }
+
+    #[test]
+    fn test_run_until_trap() {
+        // $0400: inx
+        // $0401: jmp $0401
+        let mut machine = FlatMachine64K::new(&[0xe8, 0x4c, 0x01, 0x04], 0x0400, 0x0400);
+        assert_eq!(machine.run_until_trap(100), Some(0x0401));
+        assert_eq!(machine.cpu.x, 1);
+    }
+
+    #[test]
+    fn test_smaller_memory() {
+        // $0400: stx $1400
+        // $0403: jmp $0403
+        let mut machine = FlatMachine::<0x1000>::new(
+            &[0x8e, 0x00, 0x14, 0x4c, 0x03, 0x04],
+            0x0400,
+            0x0400,
+        );
+        machine.cpu.x = 0x42;
+        assert_eq!(machine.run_until_trap(100), Some(0x0403));
+        // The 4K of RAM repeats, so the write landed at $0400, over the program.
+        assert_eq!(machine.read_u8(0x0400), 0x42);
+        assert_eq!(machine.read_u8(0xf400), 0x42);
+    }
+}
@@ -0,0 +1,353 @@
+//! The pieces a frontend plugs into the emulator. The run loop drives the machine one
+//! frame at a time, and hands the picture and the sound to the sinks, so a terminal
+//! UI, a window, a browser, or a headless runner only needs to adapt these traits
+//! rather than having a main loop of its own.
+use crate::controller::ButtonState;
+use crate::emulator::Emulator;
+use crate::movie::{MoviePlayer, SOFT_RESET};
+use crate::ppu::FRAME_BYTES;
+use crate::profiler::Subsystem;
+use std::io;
+use std::time::Instant;
+
+/// Receives every frame as 256x240 RGB pixels, see ppu::FRAME_BYTES.
+pub trait VideoSink {
+    fn present_frame(&mut self, rgb: &[u8]) -> Result<(), String>;
+}
+
+/// Receives the samples for every frame, at the emulator's sample rate.
+pub trait AudioSink {
+    fn queue_samples(&mut self, samples: &[f32]) -> Result<(), String>;
+}
+
+/// The input for one frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Input {
+    /// The buttons held on the first controller, see movie::Ports for the bits.
+    pub buttons: u8,
+    /// Press the reset button before the frame runs.
+    pub reset: bool,
+}
+
+pub trait InputSource {
+    /// Get the input for the next frame, or None to stop running, e.g. when the
+    /// window is closed or the movie is over.
+    fn poll(&mut self) -> Option<Input>;
+}
+
+/// Throws the frames away, for headless runs.
+pub struct NullVideo;
+
+impl VideoSink for NullVideo {
+    fn present_frame(&mut self, _rgb: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Throws the samples away, for headless runs.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn queue_samples(&mut self, _samples: &[f32]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Plays back a movie, one frame of input at a time.
+pub struct MovieInput<'a> {
+    player: MoviePlayer<'a>,
+}
+
+impl<'a> MovieInput<'a> {
+    pub fn new(player: MoviePlayer<'a>) -> MovieInput<'a> {
+        MovieInput { player }
+    }
+
+    pub fn player(&self) -> &MoviePlayer<'a> {
+        &self.player
+    }
+}
+
+impl<'a> InputSource for MovieInput<'a> {
+    fn poll(&mut self) -> Option<Input> {
+        if self.player.is_finished() {
+            return None;
+        }
+        let input = Input {
+            reset: self.player.commands() & SOFT_RESET != 0,
+            buttons: self.player.latch()[0],
+        };
+        self.player.end_frame();
+        Some(input)
+    }
+}
+
+pub type AutosaveErrorHook = Box<dyn FnMut(&io::Error)>;
+
+/// Drives the emulator for a frontend.
+pub struct RunLoop {
+    /// Stop after this many frames, even if the input keeps going.
+    pub max_frames: Option<u64>,
+    /// There's no PPU rendering yet, so the frames stay black.
+    frame: Vec<u8>,
+    autosave_error_hook: Option<AutosaveErrorHook>,
+}
+
+impl RunLoop {
+    pub fn new() -> RunLoop {
+        RunLoop {
+            max_frames: None,
+            frame: vec![0; FRAME_BYTES],
+            autosave_error_hook: None,
+        }
+    }
+
+    /// Call the hook when the autosaves start failing, so the frontend can tell the
+    /// player. The run keeps going, and the emulator tries again at the next autosave,
+    /// see Emulator::last_autosave_error. The hook is called again if they fail after
+    /// working for a while.
+    pub fn set_autosave_error_hook(&mut self, hook: impl FnMut(&io::Error) + 'static) {
+        self.autosave_error_hook = Some(Box::new(hook));
+    }
+
+    /// Run frames until the input stops, or max_frames is hit, and return how many
+    /// frames ran. The battery-backed RAM is saved once it stops, see
+    /// Emulator::load_battery, even when a sink fails. When the program turns on the
+    /// NMI through PPUCTRL, it's signaled at the end of every frame, like the monkey
+    /// tester does.
+    pub fn run(
+        &mut self,
+        emulator: &mut Emulator,
+        video: &mut dyn VideoSink,
+        audio: &mut dyn AudioSink,
+        input: &mut dyn InputSource,
+    ) -> Result<u64, String> {
+        let result = self.run_frames(emulator, video, audio, input);
+        let saved = emulator
+            .save_battery()
+            .map_err(|error| format!("Unable to save the battery-backed RAM: {}", error));
+        match (result, saved) {
+            (Ok(frames), Ok(_)) => Ok(frames),
+            (Err(error), Ok(_)) | (Ok(_), Err(error)) => Err(error),
+            (Err(error), Err(save_error)) => Err(format!("{} {}", error, save_error)),
+        }
+    }
+
+    fn run_frames(
+        &mut self,
+        emulator: &mut Emulator,
+        video: &mut dyn VideoSink,
+        audio: &mut dyn AudioSink,
+        input: &mut dyn InputSource,
+    ) -> Result<u64, String> {
+        let mut frames = 0;
+        let mut autosave_failing = false;
+        while self.max_frames.is_none_or(|max_frames| frames < max_frames) {
+            let Input { buttons, reset } = match input.poll() {
+                Some(input) => input,
+                None => break,
+            };
+            if reset {
+                emulator.cpu.reset();
+            }
+            emulator.controllers[0].set_buttons(ButtonState(buttons));
+            let samples = emulator.run_frame_collect_audio();
+            match emulator.last_autosave_error() {
+                Some(error) if !autosave_failing => {
+                    autosave_failing = true;
+                    if let Some(ref mut hook) = self.autosave_error_hook {
+                        hook(error);
+                    }
+                }
+                Some(_) => {}
+                None => autosave_failing = false,
+            }
+            if emulator.bus.borrow().is_nmi_enabled() {
+                emulator.cpu.set_nmi();
+            }
+            let start = Instant::now();
+            video.present_frame(&self.frame)?;
+            audio.queue_samples(&samples)?;
+            if let Some(profiler) = emulator.profiler_mut() {
+                profiler.record(Subsystem::Presentation, start.elapsed());
+            }
+            frames += 1;
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::battery::SaveConfig;
+    use crate::cartridge::Cartridge;
+    use crate::constants::InterruptVectors;
+    use crate::mappers::SimpleProgram;
+    use crate::movie::Movie;
+    use std::cell::Cell;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: usize,
+        samples: usize,
+    }
+
+    impl VideoSink for Recorder {
+        fn present_frame(&mut self, rgb: &[u8]) -> Result<(), String> {
+            assert_eq!(rgb.len(), FRAME_BYTES);
+            self.frames += 1;
+            Ok(())
+        }
+    }
+
+    impl AudioSink for Recorder {
+        fn queue_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+            self.samples += samples.len();
+            Ok(())
+        }
+    }
+
+    /// Read A and B from the first controller into $10 and $11 in the NMI.
+    fn emulator() -> Emulator {
+        let mut program = SimpleProgram::load(&[
+            0xa9, 0x80, // lda #$80
+            0x8d, 0x00, 0x20, // sta $2000
+            0x4c, 0x05, 0x80, // loop: jmp loop
+            0xa9, 0x01, // nmi: lda #1
+            0x8d, 0x16, 0x40, // sta $4016
+            0xa9, 0x00, // lda #0
+            0x8d, 0x16, 0x40, // sta $4016
+            0xad, 0x16, 0x40, // lda $4016
+            0x29, 0x01, // and #1
+            0x85, 0x10, // sta $10
+            0xad, 0x16, 0x40, // lda $4016
+            0x29, 0x01, // and #1
+            0x85, 0x11, // sta $11
+            0x40, // rti
+        ]);
+        program.set_u16(InterruptVectors::NmiVector as u16, 0x8008);
+        Emulator::new(Box::new(program))
+    }
+
+    #[test]
+    fn test_run_movie() {
+        let movie = Movie::parse_fm2(
+            "version 3\n\
+             |0|.......A|........||\n\
+             |0|......B.|........||\n\
+             |0|......B.|........||\n",
+        )
+        .unwrap();
+        let mut emulator = emulator();
+        let mut recorder = Recorder::default();
+        let mut audio = Recorder::default();
+        let mut input = MovieInput::new(MoviePlayer::new(&movie));
+        let frames = RunLoop::new()
+            .run(&mut emulator, &mut recorder, &mut audio, &mut input)
+            .unwrap();
+
+        assert_eq!(frames, 3);
+        assert_eq!(recorder.frames, 3);
+        assert!(audio.samples > 0);
+        assert_eq!(emulator.cpu.peek(0x10), 0);
+        assert_eq!(emulator.cpu.peek(0x11), 1);
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let mut run_loop = RunLoop::new();
+        run_loop.max_frames = Some(5);
+        let frames = run_loop
+            .run(&mut emulator(), &mut NullVideo, &mut NullAudio, &mut Held)
+            .unwrap();
+        assert_eq!(frames, 5);
+    }
+
+    struct Held;
+
+    impl InputSource for Held {
+        fn poll(&mut self) -> Option<Input> {
+            Some(Input::default())
+        }
+    }
+
+    /// An NROM cartridge with battery-backed RAM, which keeps incrementing $6000.
+    fn battery_emulator(dir: &Path, autosave_interval: Option<Duration>) -> Emulator {
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0b10];
+        bytes.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[..6].copy_from_slice(&[0xee, 0x00, 0x60, 0x4c, 0x00, 0x80]);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        bytes.extend_from_slice(&prg);
+        let cartridge = Cartridge::from_bytes(&bytes).unwrap();
+        let mut emulator = Emulator::new(cartridge.into_mapper());
+        emulator.set_save_config(SaveConfig {
+            directory: Some(dir.join("saves")),
+            autosave_interval,
+            disabled: false,
+        });
+        emulator.load_battery(&dir.join("game.nes")).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn test_autosave_error() {
+        let dir = std::env::temp_dir()
+            .join(format!("nes-run-loop-autosave-{}", std::process::id()));
+        let mut emulator = battery_emulator(&dir, Some(Duration::from_secs(1)));
+        // A file is in the way of the save directory.
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("saves"), []).unwrap();
+
+        let errors = Rc::new(Cell::new(0));
+        let mut run_loop = RunLoop::new();
+        run_loop.max_frames = Some(200);
+        run_loop.set_autosave_error_hook({
+            let errors = Rc::clone(&errors);
+            let dir = dir.clone();
+            move |_| {
+                errors.set(errors.get() + 1);
+                std::fs::remove_file(dir.join("saves")).unwrap();
+            }
+        });
+        let frames = run_loop
+            .run(&mut emulator, &mut NullVideo, &mut NullAudio, &mut Held)
+            .unwrap();
+
+        // The run kept going after the failed autosave, and the later ones worked.
+        assert_eq!(frames, 200);
+        assert_eq!(errors.get(), 1);
+        assert_eq!(
+            std::fs::read(dir.join("saves/game.sav")).unwrap()[0],
+            emulator.cpu.peek(0x6000)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_after_error() {
+        struct Broken;
+        impl VideoSink for Broken {
+            fn present_frame(&mut self, _rgb: &[u8]) -> Result<(), String> {
+                Err(String::from("The window is gone."))
+            }
+        }
+        let dir = std::env::temp_dir()
+            .join(format!("nes-run-loop-save-{}", std::process::id()));
+        let mut emulator = battery_emulator(&dir, None);
+        let result =
+            RunLoop::new().run(&mut emulator, &mut Broken, &mut NullAudio, &mut Held);
+
+        assert_eq!(result, Err(String::from("The window is gone.")));
+        // The frame that ran is still saved.
+        assert_eq!(
+            std::fs::read(dir.join("saves/game.sav")).unwrap()[0],
+            emulator.cpu.peek(0x6000)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
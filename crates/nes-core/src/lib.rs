@@ -0,0 +1,61 @@
+//! The emulator itself: the CPU, the bus, the PPU, the mappers, and the pieces a
+//! frontend plugs into. Without the "std" feature, this is only the hardware, and the
+//! parts that need files, clocks, or threads are left out, like the Emulator and the
+//! RunLoop.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// Remove this once this is a bit more mature.
+#![allow(dead_code)]
+// Clippy rules to disable.
+#![allow(clippy::new_without_default)]
+
+extern crate alloc;
+
+/// The parts of the standard prelude that are in alloc, for the builds without std.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod battery;
+pub mod bus;
+pub mod cartridge;
+pub mod checkpoint;
+pub mod chr;
+#[cfg(feature = "std")]
+pub mod clock;
+pub mod constants;
+pub mod controller;
+pub mod cpu_6502;
+#[cfg(feature = "std")]
+pub mod emulator;
+pub mod flat_machine;
+#[cfg(feature = "std")]
+pub mod frontend;
+pub mod mappers;
+pub mod memory_map;
+pub mod movie;
+pub mod nametable;
+pub mod opcodes;
+pub mod ppu;
+#[cfg(feature = "std")]
+pub mod profiler;
+pub mod region;
+pub mod rom;
+pub mod rom_database;
+pub mod rom_info;
+pub mod savestate;
+#[cfg(feature = "savestate-import")]
+pub mod savestate_import;
+#[cfg(feature = "snapshots")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod telemetry;
+pub mod tile_stats;
+pub mod uninit_ram;
+pub mod virtual_console;
+pub mod watchpoints;
@@ -0,0 +1,130 @@
+use super::{check_state_size, Mapper};
+use crate::prelude::*;
+use core::ops::RangeInclusive;
+
+struct Region {
+    range: RangeInclusive<u16>,
+    memory: Box<[u8]>,
+    writable: bool,
+}
+
+/// RAM and ROM at any addresses, for the 6502 machines that aren't an NES, see
+/// MemoryMap::Custom. The addresses that no region covers are left to the bus, where
+/// they read as the open bus, or go to an attached device. When regions overlap, the
+/// one that was added first wins.
+///
+/// A simple monitor might have:
+///
+/// ```
+/// use nes_core::mappers::CustomMemory;
+///
+/// let memory = CustomMemory::new()
+///     .ram(0x0000..=0x7fff)
+///     .rom(0xe000, &[0xea; 0x2000]);
+/// ```
+#[derive(Default)]
+pub struct CustomMemory {
+    regions: Vec<Region>,
+}
+
+impl CustomMemory {
+    pub fn new() -> CustomMemory {
+        CustomMemory::default()
+    }
+
+    pub fn ram(mut self, range: RangeInclusive<u16>) -> CustomMemory {
+        let size = *range.end() as usize - *range.start() as usize + 1;
+        self.regions.push(Region {
+            range,
+            memory: vec![0; size].into_boxed_slice(),
+            writable: true,
+        });
+        self
+    }
+
+    /// Map the bytes starting at the address. Writes to them are ignored.
+    pub fn rom(mut self, start: u16, bytes: &[u8]) -> CustomMemory {
+        assert!(!bytes.is_empty(), "The ROM can't be empty.");
+        let end = start as usize + bytes.len() - 1;
+        assert!(
+            end <= 0xffff,
+            "The ROM runs past the end of the address space."
+        );
+        self.regions.push(Region {
+            range: start..=end as u16,
+            memory: bytes.into(),
+            writable: false,
+        });
+        self
+    }
+
+    /// Copy the bytes into the regions starting at the address, like a loader would,
+    /// so this can write to the ROM too. The bytes that fall outside of the regions
+    /// are dropped.
+    pub fn load(mut self, address: u16, bytes: &[u8]) -> CustomMemory {
+        for (offset, value) in bytes.iter().enumerate() {
+            if let Some((region, index)) =
+                self.region_mut(address.wrapping_add(offset as u16))
+            {
+                region.memory[index] = *value;
+            }
+        }
+        self
+    }
+
+    fn region(&self, address: u16) -> Option<(&Region, usize)> {
+        self.regions
+            .iter()
+            .find(|region| region.range.contains(&address))
+            .map(|region| (region, (address - region.range.start()) as usize))
+    }
+
+    fn region_mut(&mut self, address: u16) -> Option<(&mut Region, usize)> {
+        self.regions
+            .iter_mut()
+            .find(|region| region.range.contains(&address))
+            .map(|region| {
+                let index = (address - region.range.start()) as usize;
+                (region, index)
+            })
+    }
+}
+
+impl Mapper for CustomMemory {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        self.region(addr)
+            .map(|(region, index)| region.memory[index])
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        match self.region_mut(addr) {
+            Some((region, index)) => {
+                if region.writable {
+                    region.memory[index] = value;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The RAM regions, one after the other. The ROM doesn't change, so it's left out.
+    fn save_state(&self) -> Vec<u8> {
+        self.regions
+            .iter()
+            .filter(|region| region.writable)
+            .flat_map(|region| region.memory.iter().copied())
+            .collect()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, self.save_state().len())?;
+        let mut state = state;
+        for region in self.regions.iter_mut().filter(|region| region.writable) {
+            let (memory, rest) = state.split_at(region.memory.len());
+            region.memory.copy_from_slice(memory);
+            state = rest;
+        }
+        Ok(())
+    }
+}
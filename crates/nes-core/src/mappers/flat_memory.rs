@@ -0,0 +1,70 @@
+use super::{check_state_size, Mapper};
+use crate::prelude::*;
+use core::ops::RangeInclusive;
+
+/// Plain memory that claims every address, without any of the NES mirroring or
+/// I/O registers. This is useful for running standalone 6502 programs. The memory
+/// repeats every SIZE bytes, so a SIZE of 0x10000 gives the full 64K address space.
+pub struct FlatMemory<const SIZE: usize> {
+    // This is boxed as a slice, as a 64K array is too big to comfortably put
+    // on the stack.
+    memory: Box<[u8]>,
+    /// The addresses that are left to the bus, e.g. for a device.
+    unmapped: Vec<RangeInclusive<u16>>,
+}
+
+pub type FlatMemory64K = FlatMemory<0x10000>;
+
+impl<const SIZE: usize> FlatMemory<SIZE> {
+    pub fn new() -> FlatMemory<SIZE> {
+        FlatMemory {
+            memory: vec![0; SIZE].into_boxed_slice(),
+            unmapped: Vec::new(),
+        }
+    }
+
+    /// Stop claiming the addresses, so that the bus can route them to a device that's
+    /// attached there, see Bus::attach_device.
+    pub fn unmap(&mut self, range: RangeInclusive<u16>) {
+        self.unmapped.push(range);
+    }
+
+    fn is_mapped(&self, addr: u16) -> bool {
+        !self.unmapped.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Copy the bytes into memory starting at the address, wrapping around at the
+    /// end of the memory.
+    pub fn load(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, value) in bytes.iter().enumerate() {
+            self.memory[(address as usize + offset) % SIZE] = *value;
+        }
+    }
+}
+
+impl<const SIZE: usize> Mapper for FlatMemory<SIZE> {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        if !self.is_mapped(addr) {
+            return None;
+        }
+        Some(self.memory[addr as usize % SIZE])
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        if !self.is_mapped(addr) {
+            return false;
+        }
+        self.memory[addr as usize % SIZE] = value;
+        true
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.memory.to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, SIZE)?;
+        self.memory.copy_from_slice(state);
+        Ok(())
+    }
+}
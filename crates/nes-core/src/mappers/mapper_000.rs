@@ -0,0 +1,141 @@
+use crate::prelude::*;
+use crate::rom::{Mirroring, ROMLoadError, ROM};
+
+use super::{
+    character_memory, check_state_size, header_mirroring, prg_ram, prg_ram_size, Mapper,
+};
+
+// NROM has no bank switching, the program ROM is wired straight to the CPU. It's
+// what the earliest games like Super Mario Bros. and Donkey Kong use.
+// https://wiki.nesdev.com/w/index.php/NROM
+
+// CPU $6000-$7FFF: Up to 8 KB PRG RAM, only on the Family Basic board, but it's
+//                  harmless to provide 8 KB for everything that doesn't give a size.
+//                  Smaller RAM is mirrored through the window.
+// CPU $8000-$BFFF: First 16 KB of ROM.
+// CPU $C000-$FFFF: Last 16 KB of ROM (NROM-256) or mirror of $8000-$BFFF (NROM-128).
+// PPU $0000-$1FFF: 8 KB of CHR ROM, or CHR RAM when the ROM doesn't have any.
+
+const RAM_SIZE: usize = 0x2000; // 8kb
+
+pub struct Mapper000 {
+    ram: Vec<u8>,
+    /// The header says the RAM is kept by a battery.
+    battery: bool,
+    program_rom: Vec<u8>,
+    character: Vec<u8>,
+    character_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Mapper000 {
+    pub fn new(rom: &ROM) -> Result<Mapper000, ROMLoadError> {
+        match rom.program_rom.len() {
+            0x4000 | 0x8000 => {}
+            _ => return Err("The ROM had the incorrect sized PRG ROM for NROM.".into()),
+        }
+        let (character, character_ram) = character_memory(rom);
+        // There's no banking, so anything past the window is left out.
+        let ram_size = match prg_ram_size(rom) {
+            0 => RAM_SIZE,
+            size => size.min(RAM_SIZE),
+        };
+        Ok(Mapper000 {
+            ram: prg_ram(rom, ram_size),
+            battery: rom.header.persistent_memory,
+            program_rom: rom.program_rom.clone(),
+            character,
+            character_ram,
+            mirroring: header_mirroring(rom),
+        })
+    }
+}
+
+impl Mapper000 {
+    fn ram_index(&self, addr: u16) -> usize {
+        (addr as usize - 0x6000) % self.ram.len()
+    }
+}
+
+impl Mapper for Mapper000 {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => Some(self.ram[self.ram_index(addr)]),
+            // The 16 KB ROMs are mirrored by the mask.
+            0x8000..=0xffff => {
+                let mask = self.program_rom.len() - 1;
+                Some(self.program_rom[(addr as usize) & mask])
+            }
+            _ => None,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x6000..=0x7fff => {
+                let index = self.ram_index(addr);
+                self.ram[index] = value;
+                true
+            }
+            // Writes to the ROM are ignored.
+            0x8000..=0xffff => true,
+            _ => false,
+        }
+    }
+
+    fn read_ppu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => self.character.get(addr as usize).copied(),
+            _ => None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x1fff => {
+                if self.character_ram {
+                    if let Some(byte) = self.character.get_mut(addr as usize) {
+                        *byte = value;
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    /// The PRG RAM, followed by the CHR RAM if there is any.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.ram.to_vec();
+        if self.character_ram {
+            state.extend_from_slice(&self.character);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, self.save_state().len())?;
+        let (ram, character) = state.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        if self.character_ram {
+            self.character.copy_from_slice(character);
+        }
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(&self.ram[..])
+        } else {
+            None
+        }
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.ram.copy_from_slice(ram);
+    }
+}
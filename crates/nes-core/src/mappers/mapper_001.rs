@@ -1,13 +1,15 @@
-use crate::rom::{Header, ROMLoadError};
+use crate::prelude::*;
+use crate::rom::{Mirroring, ROMLoadError, ROM};
 
-use super::Mapper;
+use super::{character_memory, check_state_size, prg_ram, prg_ram_size, Mapper};
 
 // The Nintendo MMC1 is a mapper ASIC used in Nintendo's SxROM and NES-EVENT
 // Game Pak boards. Most common SxROM boards are assigned to iNES Mapper 1.
 // This chip first appeared in the April of 1987.
 // https://wiki.nesdev.com/w/index.php/MMC1
 
-// CPU $6000-$7FFF: 8 KB PRG RAM bank, (optional)
+// CPU $6000-$7FFF: 8 KB PRG RAM bank, (optional) out of 8 KB, 16 KB (SOROM), or 32 KB
+//                  (SXROM)
 // CPU $8000-$BFFF: 16 KB PRG ROM bank, either switchable or fixed to the first bank
 // CPU $C000-$FFFF: 16 KB PRG ROM bank, either fixed to the last bank or switchable
 // PPU $0000-$0FFF: 4 KB switchable CHR bank
@@ -33,19 +35,24 @@ use super::Mapper;
 
 const RAM_SIZE: usize = 0x2000; // 8kb
 const RAM_MASK: u16 = 0x1fff;
-const BANK_SIZE: usize = 0x4000; // 16kb
 const BANK_MASK: u16 = 0x3fff;
-
-type ProgramBank = [u8; BANK_SIZE];
+const CHR_BANK_SIZE: usize = 0x1000; // 4kb
 
 pub struct Mapper001 {
-    ram: Option<Box<[u8; RAM_SIZE]>>,
+    /// Empty when the board doesn't have any.
+    ram: Vec<u8>,
+    /// The header says the RAM is kept by a battery.
+    battery: bool,
     program_rom: Vec<u8>,
     last_bank: u8,
+    character: Vec<u8>,
+    character_ram: bool,
     // Only use the last 5 bits.
     // 0b0000_0000
     //      ^ ^^^^
     shift_register: u8,
+    /// The address of the last write to the shift register. Only the fifth write's
+    /// address picks the register.
     shift_register_address: u16,
     shift_register_bits_shifted: u8,
 
@@ -98,26 +105,37 @@ pub struct Mapper001 {
 }
 
 impl Mapper001 {
-    fn new(header: &Header) -> Result<Mapper001, ROMLoadError> {
-        // The RAM is optional, but is always sized 0x2000.
-        let ram = match header.prg_ram_size {
-            0 => None,
-            0x2000 => Some(Box::new([0; 0x2000])),
+    pub fn new(rom: &ROM) -> Result<Mapper001, ROMLoadError> {
+        let header = &rom.header;
+        // The RAM is optional. The bigger boards bank it with the CHR bank register.
+        let ram = match prg_ram_size(rom) {
+            size @ (0 | 0x2000 | 0x4000 | 0x8000) => prg_ram(rom, size),
             _ => {
                 return Err(
                     "The ROM had the incorrect sized RAM for a Mapper 001.".into()
                 );
             }
         };
+        if header.prg_rom_banks == 0 || header.prg_rom_banks > 16 {
+            return Err(
+                "The ROM had the incorrect sized PRG ROM for a Mapper 001.".into()
+            );
+        }
+        let (character, character_ram) = character_memory(rom);
 
         Ok(Mapper001 {
+            battery: header.persistent_memory && !ram.is_empty(),
             ram,
-            program_rom: vec![0; header.prg_rom_bytes as usize],
-            last_bank: header.prg_rom_banks - 1,
+            program_rom: rom.program_rom.clone(),
+            last_bank: (header.prg_rom_banks - 1) as u8,
+            character,
+            character_ram,
             shift_register: 0,
             shift_register_address: 0,
             shift_register_bits_shifted: 0,
-            control_register: 0,
+            // The last bank is fixed at $C000 on power up, so that the reset vector is
+            // always there.
+            control_register: 0b0_11_00,
             chr_bank_0_register: 0,
             chr_bank_1_register: 0,
             prg_bank_register: 0,
@@ -125,7 +143,25 @@ impl Mapper001 {
     }
 
     fn get_prg_rom_bank_mode(&self) -> u8 {
-        self.control_register & 0b0000_1100 >> 2
+        (self.control_register & 0b0000_1100) >> 2
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.prg_bank_register & 0b0001_0000 == 0
+    }
+
+    /// The index into the PRG RAM, or None when it's missing or disabled. SOROM picks
+    /// the 8 KB bank with bit 3 of the CHR bank register, and SXROM with bits 2 and 3.
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() || !self.is_ram_enabled() {
+            return None;
+        }
+        let bank = match self.ram.len() {
+            0x4000 => (self.chr_bank_0_register >> 3) & 0b1,
+            0x8000 => (self.chr_bank_0_register >> 2) & 0b11,
+            _ => 0,
+        };
+        Some(bank as usize * RAM_SIZE + (addr & RAM_MASK) as usize)
     }
 
     fn get_prg_rom_bank(&self) -> u8 {
@@ -136,28 +172,34 @@ impl Mapper001 {
         self.prg_bank_register & 0b0000_1110
     }
 
+    /// The banks past the end of the ROM wrap around, as the unused bank lines aren't
+    /// connected.
     fn read_prg_bank(&self, bank: u8, absolute_addr: u16) -> u8 {
-        *self
-            .program_rom
-            .get(((bank as u16) * 0x4000 + (absolute_addr & BANK_MASK)) as usize)
-            .expect("The mapper is going out of bounds")
+        let bank = bank % (self.last_bank + 1);
+        self.program_rom[bank as usize * 0x4000 + (absolute_addr & BANK_MASK) as usize]
     }
-}
 
-type Memory = [u8; 0xFFFF];
+    /// The index into the CHR memory for a PPU address.
+    fn character_index(&self, addr: u16) -> usize {
+        let bank = if self.control_register & 0b1_0000 == 0 {
+            // 8 KB mode, the low bit of the first register is ignored.
+            (self.chr_bank_0_register & 0b1_1110) as usize + (addr >> 12) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank_0_register as usize
+        } else {
+            self.chr_bank_1_register as usize
+        };
+        (bank * CHR_BANK_SIZE + (addr & 0x0fff) as usize) % self.character.len()
+    }
+}
 
 impl Mapper for Mapper001 {
     fn read_cpu(&self, addr: u16) -> Option<u8> {
         match addr {
             // PRG RAM bank - 8 KB (optional)
             0x6000..=0x7fff => {
-                match self.ram {
-                    Some(ref ram) => {
-                        // Map $6000-$7FFF to $0000-$1FFF
-                        Some(ram[(addr & RAM_MASK) as usize])
-                    }
-                    None => None,
-                }
+                // Map $6000-$7FFF to $0000-$1FFF
+                self.ram_index(addr).map(|index| self.ram[index])
             }
 
             // Map memory for the PRG-ROM Lower Bank.
@@ -204,9 +246,8 @@ impl Mapper for Mapper001 {
         match addr {
             0x6000..=0x7fff => {
                 // 8 KB PRG RAM bank, (optional)
-                if let Some(ref mut ram) = self.ram {
-                    // Map $6000-$7FFF to $0000-$1FFF
-                    ram[(addr & RAM_MASK) as usize] = value;
+                if let Some(index) = self.ram_index(addr) {
+                    self.ram[index] = value;
                 }
             }
             0x8000..=0xffff => {
@@ -227,24 +268,21 @@ impl Mapper for Mapper001 {
                 //    LSR A
                 //    STA $9FFF    ; final 5th bit written -- full write is complete
 
-                // Shift register address changed, invalidate it.
-                if addr != self.shift_register_address {
-                    self.shift_register = 0;
-                    self.shift_register_address = addr;
-                    self.shift_register_bits_shifted = 0;
-                }
-
+                self.shift_register_address = addr;
                 let bit_7 = 0b1000_0000;
 
                 if value & bit_7 == bit_7 {
+                    // The reset also fixes the last bank at $C000.
                     self.shift_register = 0;
                     self.shift_register_bits_shifted = 0;
+                    self.control_register |= 0b0_11_00;
                 } else {
                     let bit_0 = value & 0b0000_0001;
 
                     // Shift the register, and place our new bit value into the it at bit 4.
                     self.shift_register =
                         (self.shift_register >> 1) & 0b1110_1111 | (bit_0 << 4);
+                    self.shift_register_bits_shifted += 1;
 
                     if self.shift_register_bits_shifted == 5 {
                         // The shift register is full, save it to the appropriate register.
@@ -275,4 +313,84 @@ impl Mapper for Mapper001 {
         };
         true
     }
+
+    fn read_ppu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => Some(self.character[self.character_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x1fff => {
+                if self.character_ram {
+                    let index = self.character_index(addr);
+                    self.character[index] = value;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control_register & 0b11 {
+            0 => Mirroring::OneScreenLower,
+            1 => Mirroring::OneScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    /// The registers come first, followed by the PRG RAM if there is any, and then the
+    /// CHR RAM if there is any.
+    fn save_state(&self) -> Vec<u8> {
+        let [address_low, address_high] = self.shift_register_address.to_le_bytes();
+        let mut state = vec![
+            self.shift_register,
+            address_low,
+            address_high,
+            self.shift_register_bits_shifted,
+            self.control_register,
+            self.chr_bank_0_register,
+            self.chr_bank_1_register,
+            self.prg_bank_register,
+        ];
+        state.extend_from_slice(&self.ram);
+        if self.character_ram {
+            state.extend_from_slice(&self.character);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, self.save_state().len())?;
+        let (registers, rest) = state.split_at(8);
+        self.shift_register = registers[0];
+        self.shift_register_address = u16::from_le_bytes([registers[1], registers[2]]);
+        self.shift_register_bits_shifted = registers[3];
+        self.control_register = registers[4];
+        self.chr_bank_0_register = registers[5];
+        self.chr_bank_1_register = registers[6];
+        self.prg_bank_register = registers[7];
+        let (ram, character) = rest.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        if self.character_ram {
+            self.character.copy_from_slice(character);
+        }
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(&self.ram[..])
+        } else {
+            None
+        }
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.ram.copy_from_slice(ram);
+    }
 }
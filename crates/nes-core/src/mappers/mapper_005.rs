@@ -0,0 +1,582 @@
+use crate::prelude::*;
+use crate::rom::{Mirroring, ROMLoadError, ROM};
+use crate::tile_stats::TileLayer;
+use core::cell::Cell;
+
+use super::{character_memory, check_state_size, prg_ram, prg_ram_size, Mapper};
+
+// The Nintendo MMC5 is the most complex of Nintendo's mappers, used by games like
+// Castlevania III and Just Breed. This covers the banking, the ExRAM, the scanline
+// IRQ, and the separate CHR banks for 8x16 sprites. The registers for the vertical
+// split and the expansion audio are kept, but don't do anything yet.
+// https://wiki.nesdev.com/w/index.php/MMC5
+
+// CPU $5000-$5015: Expansion audio, only kept for now
+// CPU $5100-$5130: Configuration and bank registers, see write_cpu
+// CPU $5200-$5206: The vertical split, the scanline IRQ, and the multiplier
+// CPU $5C00-$5FFF: 1 KB of ExRAM
+// CPU $6000-$7FFF: 8 KB PRG RAM bank
+// CPU $8000-$FFFF: PRG ROM, or RAM below $E000, in 8, 16, or 32 KB banks
+// PPU $0000-$1FFF: CHR in 1, 2, 4, or 8 KB banks, with two sets of banks
+// PPU $2000-$2FFF: Each nametable from the CIRAM, the ExRAM, or the fill
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8kb
+const CHR_BANK_SIZE: usize = 0x0400; // 1kb
+const EXRAM_SIZE: usize = 0x0400; // 1kb
+const MAX_RAM_SIZE: usize = 0x10000; // 64kb
+const AUDIO_REGISTERS: usize = 0x16;
+/// The nametable bytes after this are the attributes.
+const ATTRIBUTE_OFFSET: usize = 0x3c0;
+
+/// Where each nametable comes from, set through $5105.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NametableSource {
+    /// The console's own VRAM, which the PPU handles.
+    Ciram(u8),
+    ExRam,
+    /// Every tile and attribute is the one set through $5106 and $5107.
+    Fill,
+}
+
+pub struct Mapper005 {
+    /// Up to 64 KB, banked 8 KB at a time.
+    ram: Vec<u8>,
+    /// The header says the RAM is kept by a battery.
+    battery: bool,
+    program_rom: Vec<u8>,
+    character: Vec<u8>,
+    character_ram: bool,
+    exram: [u8; EXRAM_SIZE],
+    /// $5100, 0 for 32 KB banks up to 3 for 8 KB banks.
+    prg_mode: u8,
+    /// $5101, 0 for 8 KB banks up to 3 for 1 KB banks.
+    chr_mode: u8,
+    /// $5102 and $5103, which have to be 2 and 1 to write to the PRG RAM.
+    ram_protect: [u8; 2],
+    /// $5104
+    /// 0: An extra nametable.
+    /// 1: Extended attributes, a CHR bank and palette for every background tile.
+    /// 2: RAM for the CPU.
+    /// 3: ROM for the CPU.
+    exram_mode: u8,
+    /// $5105, two bits for each nametable.
+    nametable_mapping: u8,
+    /// $5106 and $5107
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// $5113
+    ram_bank: u8,
+    /// $5114-$5117. Bit 7 picks the ROM over the RAM, except for $5117 which is always
+    /// ROM.
+    prg_banks: [u8; 4],
+    /// $5120-$5127, for the sprites, or everything with 8x8 sprites. These have the
+    /// upper bits from $5130 already in them.
+    sprite_chr_banks: [u16; 8],
+    /// $5128-$512B, for the background with 8x16 sprites.
+    background_chr_banks: [u16; 4],
+    /// $5130
+    chr_upper_bits: u8,
+    /// The background banks were written after the sprite banks, which picks the banks
+    /// that the CPU sees through PPUDATA with 8x16 sprites.
+    background_chr_written_last: bool,
+    /// $5200-$5202, the vertical split. This isn't hooked up yet.
+    split: [u8; 3],
+    /// $5203
+    irq_scanline: u8,
+    /// $5204
+    irq_enabled: bool,
+    /// Reading $5204 acknowledges the IRQ, and reads don't have mutable access.
+    irq_pending: Cell<bool>,
+    /// The PPU is rendering the picture, rather than being in vblank or turned off.
+    in_frame: bool,
+    /// The scanline that the PPU is on, while it's in the frame.
+    scanline: u8,
+    /// $5205 and $5206
+    multiplicands: [u8; 2],
+    /// These are seen by snooping the CPU's writes to PPUCTRL and PPUMASK.
+    tall_sprites: bool,
+    rendering_enabled: bool,
+    in_vblank: bool,
+    /// What the PPU is fetching the tiles for, while it renders.
+    fetching: Option<TileLayer>,
+    /// The ExRAM byte of the background tile being fetched, for the extended
+    /// attributes.
+    extended_attribute: u8,
+    /// $5000-$5015, the expansion audio. These are kept for when the channels are
+    /// added.
+    audio_registers: [u8; AUDIO_REGISTERS],
+}
+
+impl Mapper005 {
+    pub fn new(rom: &ROM) -> Result<Mapper005, ROMLoadError> {
+        let prg_size = rom.program_rom.len();
+        if prg_size == 0 || !prg_size.is_multiple_of(PRG_BANK_SIZE) {
+            return Err(
+                "The ROM had the incorrect sized PRG ROM for a Mapper 005.".into()
+            );
+        }
+        let ram_size = prg_ram_size(rom).min(MAX_RAM_SIZE);
+        let (character, character_ram) = character_memory(rom);
+        Ok(Mapper005 {
+            ram: prg_ram(rom, ram_size),
+            battery: rom.header.persistent_memory && ram_size != 0,
+            program_rom: rom.program_rom.clone(),
+            character,
+            character_ram,
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            ram_bank: 0,
+            // The last bank is at $E000, for the reset vector.
+            prg_banks: [0, 0, 0, 0xff],
+            sprite_chr_banks: [0; 8],
+            background_chr_banks: [0; 4],
+            chr_upper_bits: 0,
+            background_chr_written_last: false,
+            split: [0; 3],
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            in_frame: false,
+            scanline: 0,
+            multiplicands: [0xff; 2],
+            tall_sprites: false,
+            rendering_enabled: false,
+            in_vblank: false,
+            fetching: None,
+            extended_attribute: 0,
+            audio_registers: [0; AUDIO_REGISTERS],
+        })
+    }
+
+    /// Which of the $5114-$5117 registers banks a CPU address in $8000-$FFFF, and
+    /// the size of its window, in 8 KB banks.
+    fn prg_window(&self, addr: u16) -> (usize, usize) {
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        match (self.prg_mode, slot) {
+            (0, _) => (3, 4),
+            (1, 0 | 1) => (1, 2),
+            (1, _) => (3, 2),
+            (2, 0 | 1) => (1, 2),
+            (_, slot) => (slot, 1),
+        }
+    }
+
+    /// Where the CPU address in $6000-$FFFF goes, either the PRG RAM or the ROM.
+    fn prg_index(&self, addr: u16) -> PrgIndex {
+        if addr < 0x8000 {
+            return self.ram_index(self.ram_bank as usize, addr as usize % PRG_BANK_SIZE);
+        }
+        let (register_index, banks) = self.prg_window(addr);
+        let register = self.prg_banks[register_index];
+        let window_size = banks * PRG_BANK_SIZE;
+        // The larger windows ignore the low bits of the bank.
+        let bank = (register & 0x7f) as usize & !(banks - 1);
+        let offset = (addr as usize - 0x8000) % window_size;
+        // $5117 doesn't have the RAM bit, so its window is ROM whatever bit 7 is.
+        let is_rom = register & 0x80 != 0 || register_index == 3;
+        if !is_rom {
+            return self.ram_index(bank + offset / PRG_BANK_SIZE, offset % PRG_BANK_SIZE);
+        }
+        let index = bank * PRG_BANK_SIZE + offset;
+        PrgIndex::Rom(index % self.program_rom.len())
+    }
+
+    fn ram_index(&self, bank: usize, offset: usize) -> PrgIndex {
+        if self.ram.is_empty() {
+            return PrgIndex::None;
+        }
+        PrgIndex::Ram(((bank & 0b111) * PRG_BANK_SIZE + offset) % self.ram.len())
+    }
+
+    fn is_ram_writable(&self) -> bool {
+        self.ram_protect == [0b10, 0b01]
+    }
+
+    /// Which set of CHR banks is used. The background has its own set only with 8x16
+    /// sprites.
+    fn uses_background_banks(&self) -> bool {
+        if !self.tall_sprites {
+            return false;
+        }
+        match self.fetching {
+            Some(TileLayer::Background) => true,
+            Some(TileLayer::Sprites) => false,
+            None => self.background_chr_written_last,
+        }
+    }
+
+    fn character_index(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        let index =
+            if self.exram_mode == 1 && self.fetching == Some(TileLayer::Background) {
+                // The extended attributes pick a 4 KB bank for every background tile.
+                let bank = (self.extended_attribute as usize & 0x3f)
+                    | ((self.chr_upper_bits as usize) << 6);
+                bank * 0x1000 + (addr & 0xfff)
+            } else {
+                let slots = 1 << self.chr_mode;
+                let slot_size = 0x2000 / slots;
+                let slot = addr / slot_size;
+                // The register at the end of the slot's range is the one that's used.
+                let register = (slot + 1) * (8 / slots) - 1;
+                let bank = if self.uses_background_banks() {
+                    self.background_chr_banks[register & 0b11]
+                } else {
+                    self.sprite_chr_banks[register]
+                };
+                bank as usize * slot_size + (addr % slot_size)
+            };
+        index % self.character.len().max(CHR_BANK_SIZE)
+    }
+
+    fn nametable_source(&self, addr: u16) -> NametableSource {
+        let nametable = (addr as usize - 0x2000) / 0x400 % 4;
+        match (self.nametable_mapping >> (nametable * 2)) & 0b11 {
+            0 => NametableSource::Ciram(0),
+            1 => NametableSource::Ciram(1),
+            2 => NametableSource::ExRam,
+            _ => NametableSource::Fill,
+        }
+    }
+
+    fn read_nametable(&self, addr: u16) -> Option<u8> {
+        let offset = addr as usize % 0x400;
+        let is_attribute = offset >= ATTRIBUTE_OFFSET;
+        if is_attribute
+            && self.exram_mode == 1
+            && self.fetching == Some(TileLayer::Background)
+        {
+            // The palette of the extended attribute, for every quadrant.
+            return Some((self.extended_attribute >> 6) * 0b0101_0101);
+        }
+        match self.nametable_source(addr) {
+            NametableSource::Ciram(_) => None,
+            // The ExRAM is only a nametable in the first two modes.
+            NametableSource::ExRam if self.exram_mode < 2 => Some(self.exram[offset]),
+            NametableSource::ExRam => Some(0),
+            NametableSource::Fill if is_attribute => {
+                Some((self.fill_attribute & 0b11) * 0b0101_0101)
+            }
+            NametableSource::Fill => Some(self.fill_tile),
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio_registers[(addr - 0x5000) as usize] = value,
+            0x5100 => self.prg_mode = value & 0b11,
+            0x5101 => self.chr_mode = value & 0b11,
+            0x5102 => self.ram_protect[0] = value & 0b11,
+            0x5103 => self.ram_protect[1] = value & 0b11,
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5113 => self.ram_bank = value & 0b111,
+            0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = value,
+            0x5120..=0x5127 => {
+                self.sprite_chr_banks[(addr - 0x5120) as usize] = self.chr_bank(value);
+                self.background_chr_written_last = false;
+            }
+            0x5128..=0x512b => {
+                self.background_chr_banks[(addr - 0x5128) as usize] =
+                    self.chr_bank(value);
+                self.background_chr_written_last = true;
+            }
+            0x5130 => self.chr_upper_bits = value & 0b11,
+            0x5200..=0x5202 => self.split[(addr - 0x5200) as usize] = value,
+            0x5203 => self.irq_scanline = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicands[0] = value,
+            0x5206 => self.multiplicands[1] = value,
+            _ => {}
+        }
+    }
+
+    fn chr_bank(&self, value: u8) -> u16 {
+        value as u16 | ((self.chr_upper_bits as u16) << 8)
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicands[0] as u16 * self.multiplicands[1] as u16
+    }
+}
+
+enum PrgIndex {
+    Rom(usize),
+    Ram(usize),
+    /// The board doesn't have any PRG RAM.
+    None,
+}
+
+impl Mapper for Mapper005 {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => {
+                let status = (self.irq_pending.replace(false) as u8) << 7
+                    | (self.in_frame as u8) << 6;
+                Some(status)
+            }
+            0x5205 => Some(self.product().to_le_bytes()[0]),
+            0x5206 => Some(self.product().to_le_bytes()[1]),
+            // The CPU can only read the ExRAM in the last two modes.
+            0x5c00..=0x5fff if self.exram_mode >= 2 => {
+                Some(self.exram[(addr - 0x5c00) as usize])
+            }
+            0x6000..=0xffff => match self.prg_index(addr) {
+                PrgIndex::Rom(index) => Some(self.program_rom[index]),
+                PrgIndex::Ram(index) => Some(self.ram[index]),
+                PrgIndex::None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            // The MMC5 snoops on the PPU's registers, and lets them through.
+            0x2000..=0x3fff => {
+                match addr & 0x2007 {
+                    0x2000 => self.tall_sprites = value & 0b10_0000 != 0,
+                    0x2001 => self.rendering_enabled = value & 0b1_1000 != 0,
+                    _ => {}
+                }
+                false
+            }
+            0x5000..=0x5015 | 0x5100..=0x5130 | 0x5200..=0x5206 => {
+                self.write_register(addr, value);
+                true
+            }
+            0x5c00..=0x5fff => {
+                // The ExRAM is read only in the last mode. In the nametable modes it
+                // can only be written while rendering, which isn't checked yet.
+                if self.exram_mode != 3 {
+                    self.exram[(addr - 0x5c00) as usize] = value;
+                }
+                true
+            }
+            0x6000..=0xffff => {
+                if let PrgIndex::Ram(index) = self.prg_index(addr) {
+                    if self.is_ram_writable() {
+                        self.ram[index] = value;
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read_ppu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => self.character.get(self.character_index(addr)).copied(),
+            0x2000..=0x2fff => self.read_nametable(addr),
+            _ => None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x1fff => {
+                if self.character_ram {
+                    let index = self.character_index(addr);
+                    if let Some(byte) = self.character.get_mut(index) {
+                        *byte = value;
+                    }
+                }
+                true
+            }
+            0x2000..=0x2fff => match self.nametable_source(addr) {
+                NametableSource::Ciram(_) => false,
+                NametableSource::ExRam => {
+                    if self.exram_mode < 2 {
+                        self.exram[addr as usize % 0x400] = value;
+                    }
+                    true
+                }
+                NametableSource::Fill => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn ppu_fetch(&mut self, addr: u16) {
+        // Keep the ExRAM byte of each background tile, for the extended attributes
+        // of the pattern and attribute fetches that follow it.
+        let offset = addr as usize % 0x400;
+        if (0x2000..=0x2fff).contains(&addr)
+            && offset < ATTRIBUTE_OFFSET
+            && self.fetching == Some(TileLayer::Background)
+        {
+            self.extended_attribute = self.exram[offset];
+        }
+    }
+
+    fn ppu_layer(&mut self, layer: TileLayer) {
+        self.fetching = Some(layer);
+    }
+
+    fn vblank(&mut self, vblank: bool) {
+        self.in_vblank = vblank;
+        if vblank {
+            self.in_frame = false;
+            self.fetching = None;
+        }
+    }
+
+    /// The MMC5 counts the scanlines that the PPU renders. The end of the pre-render
+    /// line starts the frame at scanline 0, and the IRQ is pending once the counter
+    /// reaches $5203.
+    fn scanline(&mut self) {
+        if self.in_vblank || !self.rendering_enabled {
+            self.in_frame = false;
+            return;
+        }
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            return;
+        }
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_scanline {
+            self.irq_pending.set(true);
+        }
+    }
+
+    fn irq_asserted(&self) -> bool {
+        self.irq_enabled && self.irq_pending.get()
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        let sources: Vec<NametableSource> = (0..4)
+            .map(|nametable| self.nametable_source(0x2000 + nametable * 0x400))
+            .collect();
+        match sources[..] {
+            [NametableSource::Ciram(a), NametableSource::Ciram(b), NametableSource::Ciram(c), NametableSource::Ciram(d)] => {
+                match [a, b, c, d] {
+                    [0, 0, 0, 0] => Some(Mirroring::OneScreenLower),
+                    [1, 1, 1, 1] => Some(Mirroring::OneScreenUpper),
+                    [0, 1, 0, 1] => Some(Mirroring::Vertical),
+                    [0, 0, 1, 1] => Some(Mirroring::Horizontal),
+                    _ => None,
+                }
+            }
+            // The ExRAM and the fill are read through read_ppu.
+            _ => None,
+        }
+    }
+
+    /// The registers, then the ExRAM, the PRG RAM, and the CHR RAM if there is any.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.prg_mode,
+            self.chr_mode,
+            self.ram_protect[0],
+            self.ram_protect[1],
+            self.exram_mode,
+            self.nametable_mapping,
+            self.fill_tile,
+            self.fill_attribute,
+            self.ram_bank,
+            self.chr_upper_bits,
+            self.background_chr_written_last as u8,
+            self.irq_scanline,
+            self.irq_enabled as u8,
+            self.irq_pending.get() as u8,
+            self.in_frame as u8,
+            self.scanline,
+            self.tall_sprites as u8,
+            self.rendering_enabled as u8,
+            self.in_vblank as u8,
+            self.extended_attribute,
+        ];
+        state.extend_from_slice(&self.prg_banks);
+        for bank in self
+            .sprite_chr_banks
+            .iter()
+            .chain(&self.background_chr_banks)
+        {
+            state.extend_from_slice(&bank.to_le_bytes());
+        }
+        state.extend_from_slice(&self.split);
+        state.extend_from_slice(&self.multiplicands);
+        state.extend_from_slice(&self.audio_registers);
+        state.extend_from_slice(&self.exram);
+        state.extend_from_slice(&self.ram);
+        if self.character_ram {
+            state.extend_from_slice(&self.character);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, self.save_state().len())?;
+        let (flags, rest) = state.split_at(20);
+        self.prg_mode = flags[0];
+        self.chr_mode = flags[1];
+        self.ram_protect = [flags[2], flags[3]];
+        self.exram_mode = flags[4];
+        self.nametable_mapping = flags[5];
+        self.fill_tile = flags[6];
+        self.fill_attribute = flags[7];
+        self.ram_bank = flags[8];
+        self.chr_upper_bits = flags[9];
+        self.background_chr_written_last = flags[10] != 0;
+        self.irq_scanline = flags[11];
+        self.irq_enabled = flags[12] != 0;
+        self.irq_pending.set(flags[13] != 0);
+        self.in_frame = flags[14] != 0;
+        self.scanline = flags[15];
+        self.tall_sprites = flags[16] != 0;
+        self.rendering_enabled = flags[17] != 0;
+        self.in_vblank = flags[18] != 0;
+        self.extended_attribute = flags[19];
+        self.fetching = None;
+
+        let (prg_banks, rest) = rest.split_at(4);
+        self.prg_banks.copy_from_slice(prg_banks);
+        let (chr_banks, rest) = rest.split_at(24);
+        let mut chr_banks = chr_banks
+            .chunks(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+        for bank in self
+            .sprite_chr_banks
+            .iter_mut()
+            .chain(self.background_chr_banks.iter_mut())
+        {
+            *bank = chr_banks.next().expect("There are 12 CHR banks.");
+        }
+        let (split, rest) = rest.split_at(3);
+        self.split.copy_from_slice(split);
+        let (multiplicands, rest) = rest.split_at(2);
+        self.multiplicands.copy_from_slice(multiplicands);
+        let (audio_registers, rest) = rest.split_at(AUDIO_REGISTERS);
+        self.audio_registers.copy_from_slice(audio_registers);
+        let (exram, rest) = rest.split_at(EXRAM_SIZE);
+        self.exram.copy_from_slice(exram);
+        let (ram, character) = rest.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        if self.character_ram {
+            self.character.copy_from_slice(character);
+        }
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(&self.ram[..])
+        } else {
+            None
+        }
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.ram.copy_from_slice(ram);
+    }
+}
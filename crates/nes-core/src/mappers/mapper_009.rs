@@ -0,0 +1,225 @@
+use crate::prelude::*;
+use crate::rom::{Mirroring, ROMLoadError, ROM};
+
+use super::{
+    character_memory, check_state_size, header_mirroring, prg_ram, prg_ram_size, Mapper,
+};
+
+// The Nintendo MMC2 is only used by Mike Tyson's Punch-Out!! and Punch-Out!!. It
+// switches the CHR banks on its own while the PPU renders, which lets the fighters be
+// drawn from more tiles than the pattern tables can hold at once.
+// https://wiki.nesdev.com/w/index.php/MMC2
+
+// CPU $6000-$7FFF: 8 KB PRG RAM, only on the PlayChoice-10 version.
+// CPU $8000-$9FFF: 8 KB switchable PRG ROM bank
+// CPU $A000-$FFFF: Three 8 KB PRG ROM banks, fixed to the last three banks
+// PPU $0000-$0FFF: Two 4 KB switchable CHR ROM banks, picked by latch 0
+// PPU $1000-$1FFF: Two 4 KB switchable CHR ROM banks, picked by latch 1
+
+// The latches switch after the PPU fetches tile $FD or $FE. Only the fetch of the
+// tile's last byte counts, which is the second plane of its bottom row. The tile
+// that set the latch is still drawn from the old bank.
+//
+// PPU $0FD8:        latch 0 = $FD
+// PPU $0FE8:        latch 0 = $FE
+// PPU $1FD8-$1FDF:  latch 1 = $FD
+// PPU $1FE8-$1FEF:  latch 1 = $FE
+
+const RAM_SIZE: usize = 0x2000; // 8kb
+const PRG_BANK_SIZE: usize = 0x2000; // 8kb
+const CHR_BANK_SIZE: usize = 0x1000; // 4kb
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Latch {
+    Fd,
+    Fe,
+}
+
+pub struct Mapper009 {
+    /// Empty when the board doesn't have any.
+    ram: Vec<u8>,
+    /// The header says the RAM is kept by a battery.
+    battery: bool,
+    program_rom: Vec<u8>,
+    character: Vec<u8>,
+    character_ram: bool,
+    /// $A000-$AFFF
+    prg_bank_register: u8,
+    /// The CHR banks, for latch 0 at $FD and $FE, and then latch 1 at $FD and $FE.
+    /// $B000-$EFFF
+    chr_bank_registers: [u8; 4],
+    /// The latches power on at $FE.
+    latches: [Latch; 2],
+    /// $F000-$FFFF
+    mirroring: Mirroring,
+}
+
+impl Mapper009 {
+    pub fn new(rom: &ROM) -> Result<Mapper009, ROMLoadError> {
+        let prg_size = rom.program_rom.len();
+        if prg_size < 4 * PRG_BANK_SIZE || !prg_size.is_multiple_of(PRG_BANK_SIZE) {
+            return Err(
+                "The ROM had the incorrect sized PRG ROM for a Mapper 009.".into()
+            );
+        }
+        let ram_size = match prg_ram_size(rom) {
+            0 => 0,
+            size => size.min(RAM_SIZE),
+        };
+        let (character, character_ram) = character_memory(rom);
+        Ok(Mapper009 {
+            ram: prg_ram(rom, ram_size),
+            battery: rom.header.persistent_memory && ram_size != 0,
+            program_rom: rom.program_rom.clone(),
+            character,
+            character_ram,
+            prg_bank_register: 0,
+            chr_bank_registers: [0; 4],
+            latches: [Latch::Fe; 2],
+            mirroring: header_mirroring(rom),
+        })
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        self.program_rom.len() / PRG_BANK_SIZE
+    }
+
+    fn program_index(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0x9fff => self.prg_bank_register as usize % self.prg_bank_count(),
+            // The last three banks, in order.
+            _ => self.prg_bank_count() - 4 + ((addr as usize - 0x8000) / PRG_BANK_SIZE),
+        };
+        bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)
+    }
+
+    fn character_index(&self, addr: u16) -> usize {
+        let table = (addr as usize) / CHR_BANK_SIZE;
+        let register = table * 2
+            + match self.latches[table] {
+                Latch::Fd => 0,
+                Latch::Fe => 1,
+            };
+        let bank_count = (self.character.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_bank_registers[register] as usize % bank_count;
+        bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl Mapper for Mapper009 {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if !self.ram.is_empty() => {
+                Some(self.ram[(addr as usize - 0x6000) % self.ram.len()])
+            }
+            0x8000..=0xffff => Some(self.program_rom[self.program_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x6000..=0x7fff if !self.ram.is_empty() => {
+                let index = (addr as usize - 0x6000) % self.ram.len();
+                self.ram[index] = value;
+            }
+            0x8000..=0x9fff => {}
+            0xa000..=0xafff => self.prg_bank_register = value & 0b1111,
+            0xb000..=0xefff => {
+                let register = (addr as usize - 0xb000) / 0x1000;
+                self.chr_bank_registers[register] = value & 0b1_1111;
+            }
+            0xf000..=0xffff => {
+                self.mirroring = if value & 0b1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_ppu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => self.character.get(self.character_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x1fff => {
+                if self.character_ram {
+                    let index = self.character_index(addr);
+                    if let Some(byte) = self.character.get_mut(index) {
+                        *byte = value;
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ppu_fetch(&mut self, addr: u16) {
+        match addr {
+            0x0fd8 => self.latches[0] = Latch::Fd,
+            0x0fe8 => self.latches[0] = Latch::Fe,
+            0x1fd8..=0x1fdf => self.latches[1] = Latch::Fd,
+            0x1fe8..=0x1fef => self.latches[1] = Latch::Fe,
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    /// The registers and latches, then the PRG RAM, and the CHR RAM if there is any.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.prg_bank_register];
+        state.extend_from_slice(&self.chr_bank_registers);
+        state.extend(self.latches.iter().map(|&latch| (latch == Latch::Fd) as u8));
+        state.push((self.mirroring == Mirroring::Horizontal) as u8);
+        state.extend_from_slice(&self.ram);
+        if self.character_ram {
+            state.extend_from_slice(&self.character);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, self.save_state().len())?;
+        let (registers, rest) = state.split_at(8);
+        self.prg_bank_register = registers[0];
+        self.chr_bank_registers.copy_from_slice(&registers[1..5]);
+        for (latch, &value) in self.latches.iter_mut().zip(&registers[5..7]) {
+            *latch = if value == 1 { Latch::Fd } else { Latch::Fe };
+        }
+        self.mirroring = if registers[7] == 1 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        let (ram, character) = rest.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        if self.character_ram {
+            self.character.copy_from_slice(character);
+        }
+        Ok(())
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(&self.ram[..])
+        } else {
+            None
+        }
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.ram.copy_from_slice(ram);
+    }
+}
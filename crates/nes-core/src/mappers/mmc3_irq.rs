@@ -0,0 +1,181 @@
+// The MMC3 counts scanlines by watching the PPU's A12 address line, and can fire an
+// IRQ when the count runs out. Games use this to split the screen, e.g. for a status
+// bar. This is the counter on its own, so that it can be tested against the cases
+// of the mmc3_test ROMs before there is a PPU or a full MMC3 mapper to run them.
+// https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
+
+// A12 is low for a long time while the background is fetched, and then toggles
+// quickly during the sprite fetches. The MMC3 filters the toggles by only counting
+// a rise after A12 has been low for a few M2 (CPU) cycles.
+const A12_FILTER_CYCLES: u8 = 3;
+
+pub struct Mmc3Irq {
+    // Write to: $C000 (even)
+    latch: u8,
+    counter: u8,
+    // Write to: $C001 (odd). The counter is reloaded on the next clock.
+    reload: bool,
+    // Write to: $E000 (even) disables and acknowledges, $E001 (odd) enables.
+    enabled: bool,
+    asserted: bool,
+    // How many CPU cycles since the last A12 rise, saturating at the filter length.
+    cycles_since_rise: u8,
+}
+
+impl Mmc3Irq {
+    pub fn new() -> Mmc3Irq {
+        Mmc3Irq {
+            latch: 0,
+            counter: 0,
+            reload: false,
+            enabled: false,
+            asserted: false,
+            cycles_since_rise: A12_FILTER_CYCLES,
+        }
+    }
+
+    /// Handle a write to the IRQ registers at $C000-$FFFF.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match (addr & 0xe000, addr & 0b1) {
+            (0xc000, 0) => self.latch = value,
+            (0xc000, _) => {
+                self.counter = 0;
+                self.reload = true;
+            }
+            (0xe000, 0) => {
+                self.enabled = false;
+                self.asserted = false;
+            }
+            (0xe000, _) => self.enabled = true,
+            _ => panic!("The address is not an MMC3 IRQ register."),
+        }
+    }
+
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted
+    }
+
+    pub fn cpu_cycle(&mut self) {
+        if self.cycles_since_rise < A12_FILTER_CYCLES {
+            self.cycles_since_rise += 1;
+        }
+    }
+
+    pub fn ppu_a12_rise(&mut self) {
+        let is_filtered = self.cycles_since_rise < A12_FILTER_CYCLES;
+        self.cycles_since_rise = 0;
+        if !is_filtered {
+            self.clock();
+        }
+    }
+
+    /// Clock the counter, this is the behavior of the newer (Sharp) MMC3 chips, where
+    /// a latch of 0 fires on every clock.
+    fn clock(&mut self) {
+        if self.counter == 0 || self.reload {
+            self.counter = self.latch;
+            self.reload = false;
+        } else {
+            self.counter -= 1;
+        }
+        if self.counter == 0 && self.enabled {
+            self.asserted = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scanlines(irq: &mut Mmc3Irq, count: usize) {
+        // There are about 113 CPU cycles in a scanline.
+        for _ in 0..count {
+            for _ in 0..113 {
+                irq.cpu_cycle();
+            }
+            irq.ppu_a12_rise();
+        }
+    }
+
+    fn setup(latch: u8) -> Mmc3Irq {
+        let mut irq = Mmc3Irq::new();
+        irq.write_register(0xc000, latch);
+        irq.write_register(0xc001, 0);
+        irq.write_register(0xe001, 0);
+        irq
+    }
+
+    // 1-clocking: The counter is reloaded from the latch, and then counts down.
+    #[test]
+    fn test_clocking() {
+        let mut irq = setup(3);
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 3);
+        scanlines(&mut irq, 2);
+        assert_eq!(irq.counter(), 1);
+        assert!(!irq.is_asserted());
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 0);
+        assert!(irq.is_asserted());
+        // Going past 0 reloads it.
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 3);
+    }
+
+    // 2-details: Writing to $C001 reloads on the next clock, even in the middle
+    // of a count.
+    #[test]
+    fn test_reload_mid_count() {
+        let mut irq = setup(5);
+        scanlines(&mut irq, 3);
+        assert_eq!(irq.counter(), 3);
+        irq.write_register(0xc000, 2);
+        irq.write_register(0xc001, 0);
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 2);
+    }
+
+    // 2-details: The IRQ fires on every clock when the latch is 0.
+    #[test]
+    fn test_latch_zero() {
+        let mut irq = setup(0);
+        scanlines(&mut irq, 1);
+        assert!(irq.is_asserted());
+        irq.write_register(0xe000, 0);
+        irq.write_register(0xe001, 0);
+        scanlines(&mut irq, 1);
+        assert!(irq.is_asserted());
+    }
+
+    // 3-A12_clocking: Rises that come too quickly after each other are filtered out.
+    #[test]
+    fn test_a12_filter() {
+        let mut irq = setup(5);
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 5);
+        irq.cpu_cycle();
+        irq.ppu_a12_rise();
+        irq.ppu_a12_rise();
+        assert_eq!(irq.counter(), 5);
+        scanlines(&mut irq, 1);
+        assert_eq!(irq.counter(), 4);
+    }
+
+    // 5-MMC3: Disabling acknowledges the IRQ, and the counter keeps running.
+    #[test]
+    fn test_disable_acknowledges() {
+        let mut irq = setup(1);
+        scanlines(&mut irq, 2);
+        assert!(irq.is_asserted());
+        irq.write_register(0xe000, 0);
+        assert!(!irq.is_asserted());
+        scanlines(&mut irq, 2);
+        assert!(!irq.is_asserted());
+        assert_eq!(irq.counter(), 0);
+    }
+}
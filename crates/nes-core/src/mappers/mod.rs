@@ -0,0 +1,509 @@
+use crate::audio::mixer::Mixer;
+use crate::prelude::*;
+use crate::rom::{Mirroring, ROMLoadError, ROM, TRAINER_ADDRESS};
+use crate::tile_stats::TileLayer;
+
+mod custom_memory;
+mod flat_memory;
+mod mapper_000;
+mod mapper_001;
+mod mapper_005;
+mod mapper_009;
+mod mmc3_irq;
+mod simple;
+
+// Re-export the mappers.
+pub use custom_memory::*;
+pub use flat_memory::*;
+pub use mapper_000::*;
+pub use mapper_001::*;
+pub use mapper_005::*;
+pub use mapper_009::*;
+pub use mmc3_irq::*;
+pub use simple::*;
+
+/// The cartridge's side of the NES. The mapper decides what the CPU and the PPU see
+/// in the cartridge's part of their address spaces, so all of the bank switching lives
+/// behind this trait, and a new mapper only needs an implementation, and an entry in
+/// mapper_for_rom.
+///
+/// Mappers can also watch the system's clocks, e.g. to count scanlines and fire an
+/// IRQ. The clocking calls all have default implementations that do nothing, so a
+/// mapper only needs to implement the ones it uses. The calls are made in a fixed
+/// order for every CPU instruction:
+///
+/// 1. irq_asserted is polled, and the CPU's IRQ line follows it. If the line is
+///    asserted and interrupts are enabled, the IRQ is serviced instead of the
+///    instruction.
+/// 2. The instruction's memory accesses go through read_cpu and write_cpu.
+/// 3. cpu_cycle is called once for each cycle the instruction took.
+///
+/// The PPU calls ppu_layer, ppu_fetch, ppu_a12_rise, scanline, and vblank as it
+/// renders. These are never
+/// interleaved with the steps above, they happen between instructions. So an IRQ
+/// fired from any of the clocks is seen before the very next instruction.
+pub trait Mapper {
+    fn read_cpu(&self, addr: u16) -> Option<u8>;
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool;
+
+    /// Read the PPU's pattern tables at $0000-$1FFF, from the CHR ROM or RAM. Returns
+    /// None for the addresses that the cartridge doesn't handle, like the test
+    /// programs that don't have any CHR at all, or the nametables at $2000-$2FFF
+    /// that are in the console's own VRAM. This doesn't have side effects, so
+    /// tools can look at the pattern tables, see ppu_fetch for the rendering.
+    fn read_ppu(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Called after the PPU fetches from the pattern tables to render, with the
+    /// address it fetched. The MMC2 switches its CHR banks this way.
+    fn ppu_fetch(&mut self, _addr: u16) {}
+
+    /// Called when the PPU moves between fetching the background tiles and the sprite
+    /// tiles. The MMC5 uses its own CHR banks for each with 8x16 sprites.
+    fn ppu_layer(&mut self, _layer: TileLayer) {}
+
+    /// Write to the PPU's pattern tables, which only sticks for CHR RAM. Returns false
+    /// for the addresses that the cartridge doesn't handle.
+    fn write_ppu(&mut self, _addr: u16, _value: u8) -> bool {
+        false
+    }
+
+    /// How the nametables are mirrored. This comes from the header, unless the mapper
+    /// can switch it. None when the cartridge doesn't say.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    /// Called once for every CPU cycle.
+    fn cpu_cycle(&mut self) {}
+
+    /// Called when the PPU's A12 address line goes from low to high. With the usual
+    /// pattern table setup this happens once per scanline, when the PPU switches from
+    /// fetching background tiles at $0xxx to sprite tiles at $1xxx. The MMC3 counts
+    /// scanlines this way.
+    fn ppu_a12_rise(&mut self) {}
+
+    /// Called at the end of every scanline, including the ones outside of the
+    /// visible picture.
+    fn scanline(&mut self) {}
+
+    /// Called when the PPU enters and leaves vblank.
+    fn vblank(&mut self, _vblank: bool) {}
+
+    /// Whether the mapper is holding the CPU's IRQ line.
+    fn irq_asserted(&self) -> bool {
+        false
+    }
+
+    /// The state that changes as the game runs, like the bank registers and the PRG
+    /// RAM, for checkpoints. The ROM never changes, so it's left out. Mappers without
+    /// any state can leave these alone.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state from save_state. A state of the wrong size, like one from
+    /// another cartridge, is an error.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        check_state_size(state, 0)
+    }
+
+    /// The PRG RAM, when the cartridge has a battery to keep it while the power is
+    /// off, see battery.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Restore the RAM from battery_ram, which is the same size.
+    fn load_battery_ram(&mut self, _ram: &[u8]) {}
+
+    /// Cartridges with an expansion audio chip register its channels here, with the
+    /// chip they belong to, so that the mixer can apply the chip's level.
+    fn register_audio(&mut self, _mixer: &mut Mixer) {}
+
+    /// Set the current level of each channel from register_audio.
+    fn mix_audio(&self, _mixer: &mut Mixer) {}
+}
+
+/// Create the mapper that the ROM's header asks for.
+pub fn mapper_for_rom(rom: &ROM) -> Result<Box<dyn Mapper>, ROMLoadError> {
+    match rom.header.mapping_number {
+        0 => Ok(Box::new(Mapper000::new(rom)?)),
+        1 => Ok(Box::new(Mapper001::new(rom)?)),
+        5 => Ok(Box::new(Mapper005::new(rom)?)),
+        9 => Ok(Box::new(Mapper009::new(rom)?)),
+        _ => Err("The ROM's mapper is not supported yet.".into()),
+    }
+}
+
+/// Check that the state is the size that save_state makes before load_state picks it
+/// apart, so that a state from another cartridge is an error instead of a panic.
+pub fn check_state_size(state: &[u8], expected: usize) -> Result<(), String> {
+    if state.len() != expected {
+        return Err(format!(
+            "The state is {} bytes, but the mapper's state is {} bytes.",
+            state.len(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// The pattern tables on the cartridge. Boards without any CHR ROM have 8 KB of CHR
+/// RAM instead, unless an NES 2.0 header says otherwise. Returns the memory, and
+/// whether it's RAM.
+fn character_memory(rom: &ROM) -> (Vec<u8>, bool) {
+    if rom.character_rom.is_empty() {
+        let size = match rom.header.chr_ram_size + rom.header.chr_nvram_size {
+            0 => 0x2000,
+            size => size as usize,
+        };
+        (vec![0; size], true)
+    } else {
+        (rom.character_rom.clone(), false)
+    }
+}
+
+/// The PRG RAM at $6000-$7FFF, along with the battery-backed PRG RAM, as they are
+/// one chip on the boards that this supports.
+fn prg_ram_size(rom: &ROM) -> usize {
+    (rom.header.prg_ram_size + rom.header.prg_nvram_size) as usize
+}
+
+/// The PRG RAM at $6000-$7FFF, with the ROM's trainer loaded at $7000 in its first
+/// bank, or wherever $7000 mirrors to in a smaller RAM. Boards without any PRG RAM
+/// can't hold a trainer.
+fn prg_ram(rom: &ROM, size: usize) -> Vec<u8> {
+    let mut ram = vec![0; size];
+    if let (Some(trainer), false) = (&rom.trainer, ram.is_empty()) {
+        let start = (TRAINER_ADDRESS - 0x6000) as usize;
+        for (index, &byte) in trainer.iter().enumerate() {
+            ram[(start + index) % size] = byte;
+        }
+    }
+    ram
+}
+
+/// The mirroring from the header, for the mappers that can't switch it.
+fn header_mirroring(rom: &ROM) -> Mirroring {
+    if rom.header.four_screen_vram {
+        Mirroring::FourScreen
+    } else {
+        rom.header.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Header;
+
+    fn rom(mapping_number: u16, program_rom: Vec<u8>, character_rom: Vec<u8>) -> ROM {
+        ROM {
+            header: Header {
+                mapping_number,
+                prg_rom_bytes: program_rom.len() as u32,
+                prg_rom_banks: (program_rom.len() / 0x4000) as u16,
+                character_rom_bytes: character_rom.len() as u32,
+                character_rom_banks: (character_rom.len() / 0x2000) as u16,
+                mirroring: Mirroring::Vertical,
+                prg_ram_size: 0x2000,
+                ..Header::default()
+            },
+            program_rom,
+            character_rom,
+            trainer: None,
+        }
+    }
+
+    /// Write the value to the MMC1's register one bit at a time, like the games do.
+    fn write_mmc1(mapper: &mut dyn Mapper, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.write_cpu(addr, value >> bit);
+        }
+    }
+
+    #[test]
+    fn test_nrom() {
+        let mut mapper = mapper_for_rom(&rom(0, vec![0xea; 0x4000], vec![])).unwrap();
+        // NROM-128 is mirrored into both halves.
+        assert_eq!(mapper.read_cpu(0xc000), Some(0xea));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+        // Without CHR ROM the pattern tables are RAM.
+        assert!(mapper.write_ppu(0x1234, 0x42));
+        assert_eq!(mapper.read_ppu(0x1234), Some(0x42));
+        assert_eq!(mapper.read_ppu(0x2000), None);
+
+        let mut mapper =
+            mapper_for_rom(&rom(0, vec![0xea; 0x4000], vec![0x11; 0x2000])).unwrap();
+        mapper.write_ppu(0x1234, 0x42);
+        assert_eq!(mapper.read_ppu(0x1234), Some(0x11));
+
+        assert!(mapper_for_rom(&rom(4, vec![0xea; 0x4000], vec![])).is_err());
+    }
+
+    #[test]
+    fn test_load_state_size() {
+        let mut memory = FlatMemory64K::new();
+        memory.load(0x0000, &[0x42]);
+        assert!(memory.load_state(&[0; 0x800]).is_err());
+        assert_eq!(memory.read_cpu(0x0000), Some(0x42));
+
+        // A state from a cartridge with CHR RAM doesn't fit one with CHR ROM.
+        let chr_ram = mapper_for_rom(&rom(1, vec![0xea; 0x8000], vec![])).unwrap();
+        let mut chr_rom =
+            mapper_for_rom(&rom(1, vec![0xea; 0x8000], vec![0x11; 0x2000])).unwrap();
+        assert!(chr_rom.load_state(&chr_ram.save_state()).is_err());
+        assert!(chr_rom.load_state(&chr_rom.save_state()).is_ok());
+    }
+
+    #[test]
+    fn test_mmc1() {
+        // Every 16 KB bank of PRG ROM, and 4 KB bank of CHR ROM, is filled with its
+        // own number.
+        let program_rom = (0..8).flat_map(|bank| vec![bank; 0x4000]).collect();
+        let character_rom = (0..4).flat_map(|bank| vec![bank; 0x1000]).collect();
+        let mut mapper = mapper_for_rom(&rom(1, program_rom, character_rom)).unwrap();
+        let mapper = mapper.as_mut();
+
+        // It powers on with the last bank fixed at $C000.
+        assert_eq!(mapper.read_cpu(0x8000), Some(0));
+        assert_eq!(mapper.read_cpu(0xc000), Some(7));
+        write_mmc1(mapper, 0xe000, 3);
+        assert_eq!(mapper.read_cpu(0x8000), Some(3));
+        assert_eq!(mapper.read_cpu(0xffff), Some(7));
+
+        // Switch 32 KB at a time, ignoring the low bit, with 4 KB CHR banks and
+        // horizontal mirroring.
+        write_mmc1(mapper, 0x8000, 0b1_00_11);
+        assert_eq!(mapper.read_cpu(0x8000), Some(2));
+        assert_eq!(mapper.read_cpu(0xc000), Some(3));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+        write_mmc1(mapper, 0xa000, 2);
+        write_mmc1(mapper, 0xc000, 1);
+        assert_eq!(mapper.read_ppu(0x0000), Some(2));
+        assert_eq!(mapper.read_ppu(0x1000), Some(1));
+
+        // Writing bit 7 resets the shift register, and fixes the last bank again.
+        mapper.write_cpu(0x8000, 0b1);
+        mapper.write_cpu(0x8000, 0x80);
+        assert_eq!(mapper.read_cpu(0xc000), Some(7));
+        write_mmc1(mapper, 0x8000, 0b0_11_00);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::OneScreenLower));
+
+        // The PRG RAM can be disabled, and the state survives a round trip.
+        mapper.write_cpu(0x6000, 0x42);
+        let state = mapper.save_state();
+        write_mmc1(mapper, 0xe000, 0b1_0000);
+        assert_eq!(mapper.read_cpu(0x6000), None);
+        mapper.load_state(&state).unwrap();
+        assert_eq!(mapper.read_cpu(0x6000), Some(0x42));
+        assert_eq!(mapper.read_cpu(0x8000), Some(3));
+    }
+
+    #[test]
+    fn test_mmc1_ram_banks() {
+        // SXROM has 32 KB of RAM, banked by bits 2 and 3 of the CHR bank register.
+        let mut rom = rom(1, vec![0; 0x8000], vec![]);
+        rom.header.prg_ram_size = 0;
+        rom.header.prg_nvram_size = 0x8000;
+        rom.header.persistent_memory = true;
+        let mut mapper = mapper_for_rom(&rom).unwrap();
+        let mapper = mapper.as_mut();
+        for bank in 0..4 {
+            write_mmc1(mapper, 0xa000, bank << 2);
+            mapper.write_cpu(0x6000, bank);
+        }
+        write_mmc1(mapper, 0xa000, 0b1000);
+        assert_eq!(mapper.read_cpu(0x6000), Some(2));
+        let ram = mapper.battery_ram().unwrap();
+        assert_eq!(ram.len(), 0x8000);
+        assert_eq!(ram[0x6000], 3);
+
+        rom.header.prg_nvram_size = 0x1000;
+        assert!(mapper_for_rom(&rom).is_err());
+    }
+
+    #[test]
+    fn test_mmc2() {
+        // Every 8 KB bank of PRG ROM, and 4 KB bank of CHR ROM, is filled with its
+        // own number.
+        let program_rom = (0..8).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let character_rom = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+        let mut mapper = mapper_for_rom(&rom(9, program_rom, character_rom)).unwrap();
+
+        // The last three banks are fixed.
+        mapper.write_cpu(0xa000, 2);
+        assert_eq!(mapper.read_cpu(0x8000), Some(2));
+        assert_eq!(mapper.read_cpu(0xa000), Some(5));
+        assert_eq!(mapper.read_cpu(0xffff), Some(7));
+        mapper.write_cpu(0xf000, 1);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+
+        // The banks for $FD and $FE, for each pattern table.
+        for (register, bank) in [(0xb000, 1), (0xc000, 2), (0xd000, 3), (0xe000, 4)] {
+            mapper.write_cpu(register, bank);
+        }
+        // The latches power on at $FE.
+        assert_eq!(mapper.read_ppu(0x0000), Some(2));
+        assert_eq!(mapper.read_ppu(0x1000), Some(4));
+
+        // Reading doesn't move the latches, only the rendering fetches do.
+        mapper.read_ppu(0x0fd8);
+        assert_eq!(mapper.read_ppu(0x0000), Some(2));
+        mapper.ppu_fetch(0x0fd8);
+        assert_eq!(mapper.read_ppu(0x0000), Some(1));
+        assert_eq!(mapper.read_ppu(0x1000), Some(4));
+        // Latch 0 only switches on the exact address, and latch 1 on the whole row.
+        mapper.ppu_fetch(0x0fe9);
+        assert_eq!(mapper.read_ppu(0x0000), Some(1));
+        mapper.ppu_fetch(0x1fdd);
+        assert_eq!(mapper.read_ppu(0x1000), Some(3));
+
+        // The latches survive a round trip.
+        let state = mapper.save_state();
+        mapper.ppu_fetch(0x0fe8);
+        mapper.write_cpu(0xa000, 0);
+        assert_eq!(mapper.read_ppu(0x0000), Some(2));
+        mapper.load_state(&state).unwrap();
+        assert_eq!(mapper.read_ppu(0x0000), Some(1));
+        assert_eq!(mapper.read_cpu(0x8000), Some(2));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_mmc5() {
+        // Every 8 KB bank of PRG ROM, and 1 KB bank of CHR ROM, is filled with its
+        // own number.
+        let program_rom = (0..16).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let character_rom = (0..64).flat_map(|bank| vec![bank; 0x0400]).collect();
+        let mut rom = rom(5, program_rom, character_rom);
+        rom.header.prg_ram_size = 0x10000;
+        let mut mapper = mapper_for_rom(&rom).unwrap();
+
+        // It powers on with 8 KB banks, and the last bank at $E000.
+        assert_eq!(mapper.read_cpu(0xe000), Some(15));
+        for (register, bank) in [(0x5114, 0x82), (0x5115, 0x83), (0x5116, 0x84)] {
+            mapper.write_cpu(register, bank);
+        }
+        assert_eq!(mapper.read_cpu(0x8000), Some(2));
+        assert_eq!(mapper.read_cpu(0xa000), Some(3));
+        assert_eq!(mapper.read_cpu(0xc000), Some(4));
+        // 16 KB banks ignore the low bit.
+        mapper.write_cpu(0x5100, 1);
+        mapper.write_cpu(0x5115, 0x87);
+        assert_eq!(mapper.read_cpu(0x8000), Some(6));
+        assert_eq!(mapper.read_cpu(0xa000), Some(7));
+        assert_eq!(mapper.read_cpu(0xc000), Some(14));
+        // A single 32 KB bank.
+        mapper.write_cpu(0x5100, 0);
+        mapper.write_cpu(0x5117, 0x84);
+        assert_eq!(mapper.read_cpu(0x8000), Some(4));
+        assert_eq!(mapper.read_cpu(0xe000), Some(7));
+        // $5117 is always ROM, even without bit 7, in the 32 KB and 16 KB modes.
+        mapper.write_cpu(0x5117, 0x05);
+        assert_eq!(mapper.read_cpu(0x8000), Some(4));
+        assert_eq!(mapper.read_cpu(0xc000), Some(6));
+        mapper.write_cpu(0x5100, 1);
+        assert_eq!(mapper.read_cpu(0xc000), Some(4));
+        assert_eq!(mapper.read_cpu(0xe000), Some(5));
+
+        // The PRG RAM is only written once $5102 and $5103 are 2 and 1, and its banks
+        // can be at $6000, or in the PRG ROM's windows.
+        mapper.write_cpu(0x5100, 3);
+        mapper.write_cpu(0x6000, 0x11);
+        assert_eq!(mapper.read_cpu(0x6000), Some(0));
+        mapper.write_cpu(0x5102, 2);
+        mapper.write_cpu(0x5103, 1);
+        mapper.write_cpu(0x6000, 0x11);
+        mapper.write_cpu(0x5113, 1);
+        mapper.write_cpu(0x6000, 0x22);
+        mapper.write_cpu(0x5114, 0x01);
+        assert_eq!(mapper.read_cpu(0x8000), Some(0x22));
+        mapper.write_cpu(0x5113, 0);
+        assert_eq!(mapper.read_cpu(0x6000), Some(0x11));
+
+        // 1 KB CHR banks, with a set for the sprites and one for the background.
+        mapper.write_cpu(0x5101, 3);
+        for register in 0..8 {
+            mapper.write_cpu(0x5120 + register, 10 + register as u8);
+        }
+        for register in 0..4 {
+            mapper.write_cpu(0x5128 + register, 20 + register as u8);
+        }
+        // 8x8 sprites only use the first set.
+        assert_eq!(mapper.read_ppu(0x0400), Some(11));
+        // The write to PPUCTRL is seen, and still goes on to the PPU.
+        assert!(!mapper.write_cpu(0x2000, 0b10_0000));
+        // Outside of rendering, the set that was written last is used.
+        assert_eq!(mapper.read_ppu(0x1400), Some(21));
+        mapper.ppu_layer(TileLayer::Sprites);
+        assert_eq!(mapper.read_ppu(0x1400), Some(15));
+        mapper.ppu_layer(TileLayer::Background);
+        assert_eq!(mapper.read_ppu(0x1400), Some(21));
+        // An 8 KB bank, from the last register.
+        mapper.ppu_layer(TileLayer::Sprites);
+        mapper.write_cpu(0x5101, 0);
+        mapper.write_cpu(0x5127, 2);
+        assert_eq!(mapper.read_ppu(0x0400), Some(17));
+
+        // The nametables from the CIRAM, the ExRAM, and the fill.
+        mapper.write_cpu(0x5105, 0b11_10_01_00);
+        assert_eq!(mapper.mirroring(), None);
+        assert_eq!(mapper.read_ppu(0x2000), None);
+        mapper.write_cpu(0x5c05, 0x42);
+        assert_eq!(mapper.read_ppu(0x2805), Some(0x42));
+        assert_eq!(mapper.read_cpu(0x5c05), None);
+        mapper.write_cpu(0x5104, 2);
+        assert_eq!(mapper.read_cpu(0x5c05), Some(0x42));
+        mapper.write_cpu(0x5106, 0x33);
+        mapper.write_cpu(0x5107, 2);
+        assert_eq!(mapper.read_ppu(0x2c00), Some(0x33));
+        assert_eq!(mapper.read_ppu(0x2fc0), Some(0b1010_1010));
+        mapper.write_cpu(0x5105, 0b01_00_01_00);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+
+        // The extended attributes pick the 4 KB bank and the palette of each tile.
+        mapper.write_cpu(0x5104, 1);
+        mapper.write_cpu(0x5c03, 0b11_000101);
+        mapper.write_cpu(0x5130, 1);
+        mapper.ppu_layer(TileLayer::Background);
+        mapper.ppu_fetch(0x2003);
+        // Bank $45 of 4 KB, wrapped around the 64 KB of CHR.
+        assert_eq!(mapper.read_ppu(0x0000), Some(20));
+        assert_eq!(mapper.read_ppu(0x23c0), Some(0xff));
+
+        // The scanline IRQ.
+        mapper.write_cpu(0x2001, 0b1_1000);
+        mapper.write_cpu(0x5203, 2);
+        mapper.write_cpu(0x5204, 0x80);
+        mapper.vblank(false);
+        mapper.scanline();
+        assert_eq!(mapper.read_cpu(0x5204), Some(0x40));
+        mapper.scanline();
+        assert!(!mapper.irq_asserted());
+        mapper.scanline();
+        assert!(mapper.irq_asserted());
+        // Reading the status acknowledges it.
+        assert_eq!(mapper.read_cpu(0x5204), Some(0xc0));
+        assert!(!mapper.irq_asserted());
+        mapper.vblank(true);
+        assert_eq!(mapper.read_cpu(0x5204), Some(0));
+
+        mapper.write_cpu(0x5205, 200);
+        mapper.write_cpu(0x5206, 3);
+        assert_eq!(mapper.read_cpu(0x5205), Some(0x58));
+        assert_eq!(mapper.read_cpu(0x5206), Some(0x02));
+
+        // The registers and the memory survive a round trip.
+        let state = mapper.save_state();
+        mapper.write_cpu(0x5114, 0x80);
+        mapper.write_cpu(0x5c05, 0);
+        mapper.load_state(&state).unwrap();
+        assert_eq!(mapper.read_cpu(0x8000), Some(0x22));
+        assert_eq!(mapper.read_cpu(0x5206), Some(0x02));
+        mapper.write_cpu(0x5104, 2);
+        assert_eq!(mapper.read_cpu(0x5c05), Some(0x42));
+    }
+}
@@ -20,7 +20,7 @@ impl SimpleProgram {
 
     pub fn load(program: &[u8]) -> SimpleProgram {
         let mut mapper = SimpleProgram::new();
-        if mapper.program.len() > PROGRAM_SIZE {
+        if program.len() > PROGRAM_SIZE {
             panic!(
                 "Attempting to load a program that is larger than the SimpleProgram cartridge space."
             );
@@ -31,7 +31,7 @@ impl SimpleProgram {
             mapper.program[index] = *value;
         }
 
-        let [low, high] = (memory_range::PRG_ROM.start as u16).to_le_bytes();
+        let [low, high] = memory_range::PRG_ROM.start.to_le_bytes();
         let reset_byte_add = (InterruptVectors::ResetVector as u16 & 0x7fff) as usize;
 
         // Set the reset vector to the first byte of the program.
@@ -39,6 +39,15 @@ impl SimpleProgram {
         mapper.program[reset_byte_add + 1] = high;
         mapper
     }
+
+    /// Write a little endian u16 directly into the program, which is useful for
+    /// setting up the interrupt vectors.
+    pub fn set_u16(&mut self, addr: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        let index = (addr & 0x7fff) as usize;
+        self.program[index] = low;
+        self.program[index + 1] = high;
+    }
 }
 
 impl Mapper for SimpleProgram {
@@ -0,0 +1,422 @@
+// A description of the NES memory map, with the names of the regions and the layout
+// of the memory-mapped registers. This is used to annotate addresses for people, the
+// bus doesn't use it to route anything.
+// https://wiki.nesdev.com/w/index.php/CPU_memory_map
+// https://wiki.nesdev.com/w/index.php/PPU_registers
+// https://wiki.nesdev.com/w/index.php/APU_registers
+
+use crate::prelude::*;
+use core::fmt;
+
+/// A named range of the address space, the end is inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: u16,
+    pub end: u16,
+    /// Set when the region repeats memory from elsewhere.
+    pub mirror: Option<Mirror>,
+}
+
+/// The chips don't decode all of the address lines, so the same `size` bytes from
+/// `start` repeat over and over through the region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mirror {
+    pub start: u16,
+    pub size: u16,
+}
+
+impl Region {
+    /// The address that the address in this region is a mirror of. Addresses outside
+    /// of a mirror are their own canonical address.
+    pub fn canonical_address(&self, address: u16) -> u16 {
+        match self.mirror {
+            Some(mirror) => mirror.start + (address - self.start) % mirror.size,
+            None => address,
+        }
+    }
+}
+
+pub const REGIONS: &[Region] = &[
+    region("Zero Page", 0x0000, 0x00ff),
+    region("Stack", 0x0100, 0x01ff),
+    region("RAM", 0x0200, 0x07ff),
+    mirror("RAM Mirrors", 0x0800, 0x1fff, 0x0000, 0x0800),
+    region("PPU Registers", 0x2000, 0x2007),
+    mirror("PPU Register Mirrors", 0x2008, 0x3fff, 0x2000, 0x0008),
+    region("APU and I/O Registers", 0x4000, 0x4017),
+    region("CPU Test Mode", 0x4018, 0x401f),
+    region("Expansion ROM", 0x4020, 0x5fff),
+    region("SRAM", 0x6000, 0x7fff),
+    region("PRG-ROM Lower Bank", 0x8000, 0xbfff),
+    region("PRG-ROM Upper Bank", 0xc000, 0xffff),
+];
+
+const fn region(name: &'static str, start: u16, end: u16) -> Region {
+    Region {
+        name,
+        start,
+        end,
+        mirror: None,
+    }
+}
+
+const fn mirror(
+    name: &'static str,
+    start: u16,
+    end: u16,
+    mirror_start: u16,
+    mirror_size: u16,
+) -> Region {
+    Region {
+        name,
+        start,
+        end,
+        mirror: Some(Mirror {
+            start: mirror_start,
+            size: mirror_size,
+        }),
+    }
+}
+
+/// Find the region for an address. The regions cover the whole address space.
+pub fn region_for(address: u16) -> &'static Region {
+    REGIONS
+        .iter()
+        .find(|region| region.start <= address && address <= region.end)
+        .expect("The regions cover every address.")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Access::Read => "R",
+            Access::Write => "W",
+            Access::ReadWrite => "RW",
+        })
+    }
+}
+
+/// How to show the bits of a field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldKind {
+    /// Show the bits as a number.
+    Number,
+    /// Show the bits as a hex number.
+    Hex,
+    /// The bits pick one of the values.
+    Choice(&'static [&'static str]),
+}
+
+/// A group of bits in a register.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    /// The lowest bit of the field.
+    pub shift: u8,
+    pub width: u8,
+    pub kind: FieldKind,
+}
+
+impl Field {
+    pub fn value(&self, byte: u8) -> u8 {
+        let mask = ((1u16 << self.width) - 1) as u8;
+        (byte >> self.shift) & mask
+    }
+
+    pub fn describe(&self, byte: u8) -> String {
+        let value = self.value(byte);
+        match self.kind {
+            FieldKind::Number => format!("{}={}", self.name, value),
+            FieldKind::Hex => format!("{}=${:02x}", self.name, value),
+            FieldKind::Choice(choices) => {
+                format!("{}={}", self.name, choices[value as usize])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Register {
+    pub name: &'static str,
+    pub address: u16,
+    pub access: Access,
+    /// Registers like PPUADDR that take a pair of writes have no fields, since one
+    /// byte doesn't mean much on its own.
+    pub fields: &'static [Field],
+}
+
+impl Register {
+    /// Describe a value going through the register, e.g. "PPUCTRL (W): NMI=1".
+    pub fn describe(&self, value: u8) -> String {
+        let mut description = format!("{} ({}):", self.name, self.access);
+        if self.fields.is_empty() {
+            description.push_str(&format!(" ${:02x}", value));
+        }
+        for (index, field) in self.fields.iter().enumerate() {
+            if index > 0 {
+                description.push(',');
+            }
+            description.push(' ');
+            description.push_str(&field.describe(value));
+        }
+        description
+    }
+}
+
+const fn bit(name: &'static str, shift: u8) -> Field {
+    Field {
+        name,
+        shift,
+        width: 1,
+        kind: FieldKind::Number,
+    }
+}
+
+const fn bits(name: &'static str, shift: u8, width: u8, kind: FieldKind) -> Field {
+    Field {
+        name,
+        shift,
+        width,
+        kind,
+    }
+}
+
+const fn register(
+    name: &'static str,
+    address: u16,
+    access: Access,
+    fields: &'static [Field],
+) -> Register {
+    Register {
+        name,
+        address,
+        access,
+        fields,
+    }
+}
+
+const PATTERN_TABLES: FieldKind = FieldKind::Choice(&["$0000", "$1000"]);
+
+const PULSE_FIELDS: &[Field] = &[
+    bits("duty", 6, 2, FieldKind::Number),
+    bit("halt", 5),
+    bit("constant", 4),
+    bits("volume", 0, 4, FieldKind::Number),
+];
+
+const SWEEP_FIELDS: &[Field] = &[
+    bit("enabled", 7),
+    bits("period", 4, 3, FieldKind::Number),
+    bit("negate", 3),
+    bits("shift", 0, 3, FieldKind::Number),
+];
+
+const LENGTH_FIELDS: &[Field] = &[
+    bits("length", 3, 5, FieldKind::Number),
+    bits("timer_high", 0, 3, FieldKind::Number),
+];
+
+pub const REGISTERS: &[Register] = &[
+    register(
+        "PPUCTRL",
+        0x2000,
+        Access::Write,
+        &[
+            bits(
+                "nametable",
+                0,
+                2,
+                FieldKind::Choice(&["$2000", "$2400", "$2800", "$2c00"]),
+            ),
+            bits("increment", 2, 1, FieldKind::Choice(&["1", "32"])),
+            bits("sprites", 3, 1, PATTERN_TABLES),
+            bits("bg", 4, 1, PATTERN_TABLES),
+            bits("size", 5, 1, FieldKind::Choice(&["8x8", "8x16"])),
+            bit("slave", 6),
+            bit("NMI", 7),
+        ],
+    ),
+    register(
+        "PPUMASK",
+        0x2001,
+        Access::Write,
+        &[
+            bit("grayscale", 0),
+            bit("bg_left", 1),
+            bit("sprites_left", 2),
+            bit("bg", 3),
+            bit("sprites", 4),
+            bit("red", 5),
+            bit("green", 6),
+            bit("blue", 7),
+        ],
+    ),
+    register(
+        "PPUSTATUS",
+        0x2002,
+        Access::Read,
+        &[bit("overflow", 5), bit("sprite0", 6), bit("vblank", 7)],
+    ),
+    register("OAMADDR", 0x2003, Access::Write, &[]),
+    register("OAMDATA", 0x2004, Access::ReadWrite, &[]),
+    register("PPUSCROLL", 0x2005, Access::Write, &[]),
+    register("PPUADDR", 0x2006, Access::Write, &[]),
+    register("PPUDATA", 0x2007, Access::ReadWrite, &[]),
+    register("SQ1_VOL", 0x4000, Access::Write, PULSE_FIELDS),
+    register("SQ1_SWEEP", 0x4001, Access::Write, SWEEP_FIELDS),
+    register("SQ1_LO", 0x4002, Access::Write, &[]),
+    register("SQ1_HI", 0x4003, Access::Write, LENGTH_FIELDS),
+    register("SQ2_VOL", 0x4004, Access::Write, PULSE_FIELDS),
+    register("SQ2_SWEEP", 0x4005, Access::Write, SWEEP_FIELDS),
+    register("SQ2_LO", 0x4006, Access::Write, &[]),
+    register("SQ2_HI", 0x4007, Access::Write, LENGTH_FIELDS),
+    register(
+        "TRI_LINEAR",
+        0x4008,
+        Access::Write,
+        &[bit("control", 7), bits("reload", 0, 7, FieldKind::Number)],
+    ),
+    register("TRI_LO", 0x400a, Access::Write, &[]),
+    register("TRI_HI", 0x400b, Access::Write, LENGTH_FIELDS),
+    register(
+        "NOISE_VOL",
+        0x400c,
+        Access::Write,
+        &[
+            bit("halt", 5),
+            bit("constant", 4),
+            bits("volume", 0, 4, FieldKind::Number),
+        ],
+    ),
+    register(
+        "NOISE_LO",
+        0x400e,
+        Access::Write,
+        &[bit("loop", 7), bits("period", 0, 4, FieldKind::Number)],
+    ),
+    register(
+        "NOISE_HI",
+        0x400f,
+        Access::Write,
+        &[bits("length", 3, 5, FieldKind::Number)],
+    ),
+    register(
+        "DMC_FREQ",
+        0x4010,
+        Access::Write,
+        &[
+            bit("IRQ", 7),
+            bit("loop", 6),
+            bits("rate", 0, 4, FieldKind::Number),
+        ],
+    ),
+    register("DMC_RAW", 0x4011, Access::Write, &[]),
+    register("DMC_START", 0x4012, Access::Write, &[]),
+    register("DMC_LEN", 0x4013, Access::Write, &[]),
+    register(
+        "OAMDMA",
+        0x4014,
+        Access::Write,
+        &[bits("page", 0, 8, FieldKind::Hex)],
+    ),
+    register(
+        "SND_CHN",
+        0x4015,
+        Access::ReadWrite,
+        &[
+            bit("pulse1", 0),
+            bit("pulse2", 1),
+            bit("triangle", 2),
+            bit("noise", 3),
+            bit("dmc", 4),
+        ],
+    ),
+    register("JOY1", 0x4016, Access::ReadWrite, &[bit("strobe", 0)]),
+    register(
+        "JOY2",
+        0x4017,
+        Access::ReadWrite,
+        &[
+            bits("mode", 7, 1, FieldKind::Choice(&["4-step", "5-step"])),
+            bit("IRQ_inhibit", 6),
+        ],
+    ),
+];
+
+/// Follow the mirrors back to the address that they repeat, e.g. $0873 is $0073.
+pub fn canonical_address(address: u16) -> u16 {
+    region_for(address).canonical_address(address)
+}
+
+/// Find the register at an address, following the PPU register mirrors.
+pub fn register_for(address: u16) -> Option<&'static Register> {
+    let address = canonical_address(address);
+    REGISTERS
+        .iter()
+        .find(|register| register.address == address)
+}
+
+/// Describe a value going to or from an address, if it's a register.
+pub fn annotate(address: u16, value: u8) -> Option<String> {
+    register_for(address).map(|register| register.describe(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        assert_eq!(region_for(0x0000).name, "Zero Page");
+        assert_eq!(region_for(0x01ff).name, "Stack");
+        assert_eq!(region_for(0x3456).name, "PPU Register Mirrors");
+        assert_eq!(region_for(0xffff).name, "PRG-ROM Upper Bank");
+        // The regions are in order, and don't leave any gaps.
+        for pair in REGIONS.windows(2) {
+            assert_eq!(pair[0].end + 1, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_canonical_address() {
+        assert_eq!(canonical_address(0x0873), 0x0073);
+        assert_eq!(canonical_address(0x1fff), 0x07ff);
+        assert_eq!(canonical_address(0x2008), 0x2000);
+        assert_eq!(canonical_address(0x3ff9), 0x2001);
+        assert_eq!(canonical_address(0x0073), 0x0073);
+        assert_eq!(canonical_address(0x8000), 0x8000);
+        // Every mirror lands on memory that isn't a mirror itself.
+        for region in REGIONS.iter().filter(|region| region.mirror.is_some()) {
+            let canonical = canonical_address(region.end);
+            assert_eq!(region_for(canonical).mirror, None);
+        }
+    }
+
+    #[test]
+    fn test_register_mirrors() {
+        assert_eq!(register_for(0x2000).unwrap().name, "PPUCTRL");
+        assert_eq!(register_for(0x3ff9).unwrap().name, "PPUMASK");
+        assert_eq!(register_for(0x4009), None);
+        assert_eq!(register_for(0x0000), None);
+    }
+
+    #[test]
+    fn test_annotate() {
+        assert_eq!(
+            annotate(0x2000, 0b1001_0000).unwrap(),
+            "PPUCTRL (W): nametable=$2000, increment=1, sprites=$0000, bg=$1000, \
+             size=8x8, slave=0, NMI=1"
+        );
+        assert_eq!(annotate(0x2006, 0x3f).unwrap(), "PPUADDR (W): $3f");
+        assert_eq!(annotate(0x4014, 0x02).unwrap(), "OAMDMA (W): page=$02");
+        assert_eq!(annotate(0x0200, 0x02), None);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// The buttons in the order that FM2 files write them, from the high bit to the low
+// bit. The low bit is A, which matches the order the NES reads out the controller.
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/// The buttons for both controller ports, with A in the low bit: RLDUTSBA, where T is
+/// start and S is select.
+pub type Ports = [u8; 2];
+
+/// The MovieFrame command for pressing the reset button.
+pub const SOFT_RESET: u8 = 1;
+
+/// The input for one frame of a movie.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MovieFrame {
+    /// Commands like soft reset (1) and power (2), for the frame.
+    pub commands: u8,
+    /// The input for each time the game latches the controllers during the frame.
+    /// Regular movies have a single latch, and the game sees it no matter how many
+    /// times it reads the controllers. Subframe movies record every latch, and a lag
+    /// frame, where the game never reads the controllers, has none.
+    pub latches: Vec<Ports>,
+}
+
+/// A recording of controller input, as written by FCEUX in the FM2 format.
+/// http://fceux.com/web/help/fm2.html
+///
+/// Subframe movies have a "subframe 1" header. Then each input line only has the
+/// commands for the frame, and is followed by a "+" line for every latch:
+///
+/// |0|
+/// +|.......A|........||
+/// +|........|........||
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// All of the header lines, like "romFilename" and "rerecordCount".
+    pub header: BTreeMap<String, String>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Movie::parse_fm2(&text)
+    }
+
+    /// The header comes first as "key value" lines, and then there is one line of
+    /// input per frame, e.g. "|0|.......A|........||".
+    pub fn parse_fm2(text: &str) -> Result<Movie, String> {
+        let mut header = BTreeMap::new();
+        let mut frames: Vec<MovieFrame> = Vec::new();
+        let mut is_subframe = false;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            let invalid = || format!("Invalid input on line {}.", index + 1);
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('|') {
+                let mut fields = line.split('|').skip(1);
+                let commands = fields
+                    .next()
+                    .and_then(|commands| commands.parse().ok())
+                    .ok_or_else(invalid)?;
+                let latches = if is_subframe {
+                    vec![]
+                } else {
+                    vec![parse_fm2_ports(fields).ok_or_else(invalid)?]
+                };
+                frames.push(MovieFrame { commands, latches });
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("+|") {
+                let frame = match frames.last_mut() {
+                    Some(frame) if is_subframe => frame,
+                    _ => return Err(invalid()),
+                };
+                frame
+                    .latches
+                    .push(parse_fm2_ports(rest.split('|')).ok_or_else(invalid)?);
+                continue;
+            }
+            if !frames.is_empty() {
+                return Err(format!(
+                    "Found a header after the input started on line {}.",
+                    index + 1
+                ));
+            }
+            let (key, value) = match line.find(' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => (line, ""),
+            };
+            if key == "subframe" {
+                is_subframe = value == "1";
+            }
+            header.insert(key.to_string(), value.to_string());
+        }
+
+        if header.get("version").map(String::as_str) != Some("3") {
+            return Err("Only version 3 FM2 movies are supported.".into());
+        }
+        Ok(Movie { header, frames })
+    }
+
+    pub fn rom_filename(&self) -> Option<&str> {
+        self.header.get("romFilename").map(String::as_str)
+    }
+
+    /// Whether every latch was recorded, see the Movie docs.
+    pub fn is_subframe(&self) -> bool {
+        self.header.get("subframe").map(String::as_str) == Some("1")
+    }
+
+    pub fn is_pal(&self) -> bool {
+        self.header.get("palFlag").map(String::as_str) == Some("1")
+    }
+}
+
+fn parse_fm2_ports<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<Ports> {
+    // "port0|port1|port2|", the ports can be empty.
+    let mut ports = [0; 2];
+    for port in ports.iter_mut() {
+        let field = fields.next()?.as_bytes();
+        if field.is_empty() {
+            continue;
+        }
+        if field.len() != FM2_BUTTONS.len() {
+            return None;
+        }
+        for (index, &button) in field.iter().enumerate() {
+            // Released buttons are written as a "." or a space.
+            if button != b'.' && button != b' ' {
+                *port |= 1 << (7 - index);
+            }
+        }
+    }
+    Some(ports)
+}
+
+/// Feeds a movie's input to the game one latch at a time, and keeps track of where
+/// the game's polling didn't line up with the recording.
+pub struct MoviePlayer<'a> {
+    movie: &'a Movie,
+    frame: usize,
+    latch: usize,
+    last_ports: Ports,
+    lag_frames: u64,
+    unused_latches: u64,
+}
+
+impl<'a> MoviePlayer<'a> {
+    pub fn new(movie: &'a Movie) -> MoviePlayer<'a> {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            latch: 0,
+            last_ports: [0; 2],
+            lag_frames: 0,
+            unused_latches: 0,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// The commands for the current frame.
+    pub fn commands(&self) -> u8 {
+        self.movie
+            .frames
+            .get(self.frame)
+            .map_or(0, |frame| frame.commands)
+    }
+
+    /// The game latched the controllers, return the input for it. Once a frame's
+    /// latches run out, the last one is repeated, which is also how a regular movie
+    /// gives the same input to every latch in the frame. A latch in a frame that was
+    /// recorded as lag gets the last input that was seen.
+    pub fn latch(&mut self) -> Ports {
+        if let Some(frame) = self.movie.frames.get(self.frame) {
+            let index = self.latch.min(frame.latches.len().saturating_sub(1));
+            if let Some(&ports) = frame.latches.get(index) {
+                self.last_ports = ports;
+            }
+        }
+        self.latch += 1;
+        self.last_ports
+    }
+
+    /// Move on to the next frame.
+    pub fn end_frame(&mut self) {
+        if self.latch == 0 {
+            self.lag_frames += 1;
+        }
+        if let Some(frame) = self.movie.frames.get(self.frame) {
+            if self.movie.is_subframe() {
+                self.unused_latches +=
+                    frame.latches.len().saturating_sub(self.latch) as u64;
+            }
+        }
+        self.frame += 1;
+        self.latch = 0;
+    }
+
+    /// Frames where the game never latched the controllers.
+    pub fn lag_frames(&self) -> u64 {
+        self.lag_frames
+    }
+
+    /// Recorded latches that the game never asked for in a subframe movie. Anything
+    /// other than 0 means the playback has desynced from the recording.
+    pub fn unused_latches(&self) -> u64 {
+        self.unused_latches
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOVIE: &str = "version 3
+emuVersion 22020
+rerecordCount 12
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 00000000-0000-0000-0000-000000000000
+fourscore 0
+port0 1
+port1 1
+port2 0
+|1|........|........||
+|0|.......A|........||
+|0|R..UT...|.L.....A||
+";
+
+    #[test]
+    fn test_parse_header() {
+        let movie = Movie::parse_fm2(MOVIE).unwrap();
+        assert_eq!(movie.rom_filename(), Some("smb"));
+        assert_eq!(movie.header.get("rerecordCount").unwrap(), "12");
+        assert!(!movie.is_pal());
+    }
+
+    #[test]
+    fn test_parse_frames() {
+        let movie = Movie::parse_fm2(MOVIE).unwrap();
+        assert_eq!(
+            movie.frames,
+            vec![
+                MovieFrame {
+                    commands: 1,
+                    latches: vec![[0, 0]]
+                },
+                MovieFrame {
+                    commands: 0,
+                    latches: vec![[0b0000_0001, 0]]
+                },
+                MovieFrame {
+                    commands: 0,
+                    latches: vec![[0b1001_1000, 0b0100_0001]]
+                },
+            ]
+        );
+    }
+
+    const SUBFRAME_MOVIE: &str = "version 3
+subframe 1
+|0|
++|.......A|........||
++|......B.|........||
+|0|
+|0|
++|T.......|........||
+";
+
+    #[test]
+    fn test_parse_subframe() {
+        let movie = Movie::parse_fm2(SUBFRAME_MOVIE).unwrap();
+        let latches: Vec<_> = movie
+            .frames
+            .iter()
+            .map(|frame| frame.latches.clone())
+            .collect();
+        assert_eq!(
+            latches,
+            vec![
+                vec![[0b0000_0001, 0], [0b0000_0010, 0]],
+                vec![],
+                vec![[0b1000_0000, 0]],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latch_outside_of_subframe() {
+        let text = "version 3\n|0|........|........||\n+|........|........||\n";
+        assert_eq!(
+            Movie::parse_fm2(text),
+            Err("Invalid input on line 3.".into())
+        );
+    }
+
+    #[test]
+    fn test_play_regular_movie() {
+        let movie = Movie::parse_fm2(MOVIE).unwrap();
+        let mut player = MoviePlayer::new(&movie);
+        assert_eq!(player.commands(), 1);
+        player.end_frame();
+        // Every latch in the frame gets the same input.
+        assert_eq!(player.latch(), [0b0000_0001, 0]);
+        assert_eq!(player.latch(), [0b0000_0001, 0]);
+        player.end_frame();
+        assert_eq!(player.latch(), [0b1001_1000, 0b0100_0001]);
+        player.end_frame();
+        assert!(player.is_finished());
+        assert_eq!(player.lag_frames(), 1);
+        // Regular movies record a latch for lag frames too, so it isn't a desync.
+        assert_eq!(player.unused_latches(), 0);
+    }
+
+    #[test]
+    fn test_play_subframe_movie() {
+        let movie = Movie::parse_fm2(SUBFRAME_MOVIE).unwrap();
+        let mut player = MoviePlayer::new(&movie);
+        assert_eq!(player.latch(), [0b0000_0001, 0]);
+        assert_eq!(player.latch(), [0b0000_0010, 0]);
+        player.end_frame();
+        // A lag frame.
+        player.end_frame();
+        assert_eq!(player.latch(), [0b1000_0000, 0]);
+        player.end_frame();
+        assert_eq!(player.lag_frames(), 1);
+        assert_eq!(player.unused_latches(), 0);
+    }
+
+    #[test]
+    fn test_subframe_desync() {
+        let movie = Movie::parse_fm2(SUBFRAME_MOVIE).unwrap();
+        let mut player = MoviePlayer::new(&movie);
+        // The game only latched once, so the second latch is left over.
+        player.latch();
+        player.end_frame();
+        assert_eq!(player.unused_latches(), 1);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let text = "version 3\n|0|..A|........||\n";
+        assert_eq!(
+            Movie::parse_fm2(text),
+            Err("Invalid input on line 2.".into())
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        assert!(Movie::parse_fm2("version 2\n").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+// Export the nametables as maps, so that level layouts can be looked at in a
+// spreadsheet or rebuilt in Tiled. A nametable is 1KB, with 32x30 tile indexes,
+// followed by a 64 byte attribute table that picks a background palette for each
+// 16x16 pixel area.
+// https://wiki.nesdev.com/w/index.php/PPU_nametables
+// https://wiki.nesdev.com/w/index.php/PPU_attribute_tables
+// https://doc.mapeditor.org/en/stable/reference/tmx-map-format/
+
+use crate::chr::{SHEET_WIDTH, TILE_SIZE};
+use crate::prelude::*;
+
+pub const NAMETABLE_SIZE: usize = 0x400;
+pub const COLUMNS: usize = 32;
+pub const ROWS: usize = 30;
+const ATTRIBUTES_START: usize = COLUMNS * ROWS;
+/// Each attribute byte covers 4x4 tiles.
+const ATTRIBUTE_COLUMNS: usize = COLUMNS / 4;
+
+pub struct Nametable<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Nametable<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Nametable<'a>, String> {
+        if bytes.len() != NAMETABLE_SIZE {
+            return Err(format!(
+                "A nametable is {} bytes, but {} bytes were given.",
+                NAMETABLE_SIZE,
+                bytes.len()
+            ));
+        }
+        Ok(Nametable { bytes })
+    }
+
+    pub fn tile(&self, column: usize, row: usize) -> u8 {
+        self.bytes[row * COLUMNS + column]
+    }
+
+    /// The attribute byte holds 2 bits for each 2x2 tile quadrant, in the order of
+    /// top left, top right, bottom left, and bottom right, from the lowest bits.
+    pub fn palette(&self, column: usize, row: usize) -> u8 {
+        let attribute =
+            self.bytes[ATTRIBUTES_START + (row / 4) * ATTRIBUTE_COLUMNS + column / 4];
+        let shift = ((row / 2) % 2) * 4 + ((column / 2) % 2) * 2;
+        (attribute >> shift) & 0b11
+    }
+
+    /// The tile indexes, one row of the nametable per line.
+    pub fn tiles_csv(&self) -> String {
+        csv(|column, row| self.tile(column, row))
+    }
+
+    /// The palette of each tile, so that it lines up with `tiles_csv`.
+    pub fn palettes_csv(&self) -> String {
+        csv(|column, row| self.palette(column, row))
+    }
+
+    /// A Tiled map with the pattern table as the tileset. The tileset image is the
+    /// 128x128 sheet from `chr::Sheet`. Tiled can't color tiles by palette, so the
+    /// palettes are stored in a map property.
+    pub fn to_tmx(&self, tileset_image: &str) -> String {
+        let tiles_high = 256 / (SHEET_WIDTH / TILE_SIZE);
+        // Tiled's tile ids start at 1, as 0 is an empty cell.
+        let tile_ids = csv(|column, row| self.tile(column, row) as u16 + 1);
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.0" orientation="orthogonal" renderorder="right-down" width="{columns}" height="{rows}" tilewidth="{tile_size}" tileheight="{tile_size}">
+ <properties>
+  <property name="palettes">
+{palettes}</property>
+ </properties>
+ <tileset firstgid="1" name="pattern table" tilewidth="{tile_size}" tileheight="{tile_size}" tilecount="256" columns="{tiles_wide}">
+  <image source="{image}" width="{image_width}" height="{image_height}"/>
+ </tileset>
+ <layer name="nametable" width="{columns}" height="{rows}">
+  <data encoding="csv">
+{tile_ids}</data>
+ </layer>
+</map>
+"#,
+            columns = COLUMNS,
+            rows = ROWS,
+            tile_size = TILE_SIZE,
+            tiles_wide = SHEET_WIDTH / TILE_SIZE,
+            image = escape_xml(tileset_image),
+            image_width = SHEET_WIDTH,
+            image_height = tiles_high * TILE_SIZE,
+            palettes = self.palettes_csv(),
+            tile_ids = tile_ids,
+        )
+    }
+}
+
+fn csv<T: ToString>(value: impl Fn(usize, usize) -> T) -> String {
+    let mut csv = String::new();
+    for row in 0..ROWS {
+        let values: Vec<String> = (0..COLUMNS)
+            .map(|column| value(column, row).to_string())
+            .collect();
+        csv.push_str(&values.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nametable_bytes() -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..ATTRIBUTES_START).map(|index| index as u8).collect();
+        // The first attribute byte is 3, 2, 1, 0 for its quadrants.
+        bytes.push(0b00_01_10_11);
+        bytes.resize(NAMETABLE_SIZE, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_palettes() {
+        let bytes = nametable_bytes();
+        let nametable = Nametable::new(&bytes).unwrap();
+        assert_eq!(nametable.palette(0, 0), 3);
+        assert_eq!(nametable.palette(1, 1), 3);
+        assert_eq!(nametable.palette(2, 0), 2);
+        assert_eq!(nametable.palette(0, 2), 1);
+        assert_eq!(nametable.palette(3, 3), 0);
+        assert_eq!(nametable.palette(4, 0), 0);
+        assert!(Nametable::new(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let bytes = nametable_bytes();
+        let nametable = Nametable::new(&bytes).unwrap();
+        let tiles = nametable.tiles_csv();
+        let lines: Vec<&str> = tiles.lines().collect();
+        assert_eq!(lines.len(), ROWS);
+        assert!(lines[0].starts_with("0,1,2,3,"));
+        assert!(lines[1].starts_with("32,33,"));
+        assert!(nametable.palettes_csv().starts_with("3,3,2,2,0,0,"));
+    }
+
+    #[test]
+    fn test_tmx() {
+        let bytes = nametable_bytes();
+        let tmx = Nametable::new(&bytes).unwrap().to_tmx("level & tiles.png");
+        assert!(tmx.contains(
+            r#"<image source="level &amp; tiles.png" width="128" height="128"/>"#
+        ));
+        assert!(tmx.contains("\n1,2,3,4,"));
+    }
+}
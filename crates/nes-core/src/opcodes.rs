@@ -1,8 +1,10 @@
+use crate::cpu_6502::opcodes_65c02::*;
 use crate::cpu_6502::opcodes_illegal::*;
 use crate::cpu_6502::opcodes_jump::*;
 use crate::cpu_6502::opcodes_logical::*;
 use crate::cpu_6502::opcodes_move::*;
-use crate::cpu_6502::Cpu6502;
+use crate::cpu_6502::{Cpu6502, CpuVariant, ExtraCycle};
+use crate::prelude::*;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
@@ -21,6 +23,26 @@ pub enum Mode {
     None,             // non - This last one is fake.
 }
 
+impl Mode {
+    /// How many bytes of operand follow the opcode.
+    pub fn operand_size(self) -> u16 {
+        match self {
+            Mode::Absolute
+            | Mode::AbsoluteIndexedX
+            | Mode::AbsoluteIndexedY
+            | Mode::Indirect => 2,
+            Mode::Immediate
+            | Mode::IndirectX
+            | Mode::IndirectY
+            | Mode::Relative
+            | Mode::ZeroPage
+            | Mode::ZeroPageX
+            | Mode::ZeroPageY => 1,
+            Mode::Implied | Mode::None => 0,
+        }
+    }
+}
+
 /**
  * Tokens don't necessarily have enough information to know the mode.
  */
@@ -586,7 +608,8 @@ pub fn instruction_mode_to_op_code(
         (Instruction::LSR, TokenMode::AbsoluteIndexedX) => OpCode::LSR_abx,
         (Instruction::LSR, TokenMode::ZeroPageOrRelative) => OpCode::LSR_zp,
         (Instruction::LSR, TokenMode::ZeroPageX) => OpCode::LSR_zpx,
-        (Instruction::NOP, TokenMode::None) => OpCode::NOP,
+        // $EA is the official NOP, the others are undocumented copies.
+        (Instruction::NOP, TokenMode::None) => OpCode::NOP5,
         (Instruction::NOP, TokenMode::Absolute) => OpCode::NOP_abs,
         (Instruction::NOP, TokenMode::AbsoluteIndexedX) => OpCode::NOP_abx,
         (Instruction::NOP, TokenMode::Immediate) => OpCode::NOP_imm,
@@ -691,7 +714,7 @@ pub fn instruction_mode_to_op_code(
     })
 }
 
-pub const CYCLES_TABLE: [u8; 256] = [
+const CYCLES_TABLE: [u8; 256] = [
     7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, 2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7,
     4, 4, 7, 7, 6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, 2, 5, 0, 8, 4, 4, 6, 6,
     2, 4, 2, 7, 4, 4, 7, 7, 6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, 2, 5, 0, 8,
@@ -704,21 +727,268 @@ pub const CYCLES_TABLE: [u8; 256] = [
     4, 4, 7, 7,
 ];
 
-// TODO
-pub const EXTRA_CYCLES_TABLE: [u8; 256] = [
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-    0, 0, 0, 0,
+/// Read operations take an extra cycle when an indexed address crosses a page,
+/// and branches take an extra cycle when they are taken. See `ExtraCycle`.
+const EXTRA_CYCLES_TABLE: [ExtraCycle; 256] = [
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::IfTaken,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::PageBoundary,
+    ExtraCycle::None,
+    ExtraCycle::None,
 ];
 
-pub const ADDRESSING_MODE_TABLE: [Mode; 256] = [
+const ADDRESSING_MODE_TABLE: [Mode; 256] = [
     Mode::None,
     Mode::IndirectX,
     Mode::None,
@@ -977,7 +1247,7 @@ pub const ADDRESSING_MODE_TABLE: [Mode; 256] = [
     Mode::AbsoluteIndexedX,
 ];
 
-pub const OPCODE_STRING_TABLE: [&str; 256] = [
+const OPCODE_STRING_TABLE: [&str; 256] = [
     "brk", "ora", "kil", "slo", "nop", "ora", "asl", "slo", "php", "ora", "asl", "anc",
     "nop", "ora", "asl", "slo", "bpl", "ora", "kil", "slo", "nop", "ora", "asl", "slo",
     "clc", "ora", "nop", "slo", "nop", "ora", "asl", "slo", "jsr", "and", "kil", "rla",
@@ -1002,9 +1272,9 @@ pub const OPCODE_STRING_TABLE: [&str; 256] = [
     "nop", "sbc", "inc", "isc",
 ];
 
-type OperationFn = fn(&mut Cpu6502, Mode, u8);
+pub type OperationFn = fn(&mut Cpu6502, Mode, u8);
 
-pub const OPERATION_FN_TABLE: [OperationFn; 256] = [
+const OPERATION_FN_TABLE: [OperationFn; 256] = [
     brk, ora, kil, slo, nop, ora, asl, slo, php, ora, asl, anc, nop, ora, asl, slo, bpl,
     ora, kil, slo, nop, ora, asl, slo, clc, ora, nop, slo, nop, ora, asl, slo, jsr, and,
     kil, rla, bit, and, rol, rla, plp, and, rol, anc, bit, and, rol, rla, bmi, and, kil,
@@ -1022,3 +1292,199 @@ pub const OPERATION_FN_TABLE: [OperationFn; 256] = [
     inc, isc, beq, sbc, kil, isc, nop, sbc, inc, isc, sed, sbc, nop, isc, nop, sbc, inc,
     isc,
 ];
+
+/// The 65C02's STP instruction stops the clock until a reset.
+pub const WDC_65C02_STP: u8 = 0xdb;
+
+/// The twelve NMOS opcodes that jam the CPU.
+pub const KIL_OPCODES: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+];
+
+const IS_KIL: [bool; 256] = {
+    let mut is_kil = [false; 256];
+    let mut index = 0;
+    while index < KIL_OPCODES.len() {
+        is_kil[KIL_OPCODES[index] as usize] = true;
+        index += 1;
+    }
+    is_kil
+};
+
+/// The CPU checks this on every instruction, so it's a lookup rather than going
+/// through the names.
+pub fn is_kil(opcode: u8) -> bool {
+    IS_KIL[opcode as usize]
+}
+
+const fn name_is(name: &str, other: &str) -> bool {
+    let (name, other) = (name.as_bytes(), other.as_bytes());
+    if name.len() != other.len() {
+        return false;
+    }
+    let mut index = 0;
+    while index < name.len() {
+        if name[index] != other[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+const IS_ILLEGAL: [bool; 256] = {
+    const ILLEGAL_NAMES: [&str; 19] = [
+        "kil", "slo", "rla", "sre", "rra", "sax", "lax", "dcp", "isc", "anc", "alr",
+        "arr", "xaa", "axs", "ahx", "shy", "shx", "tas", "las",
+    ];
+    let mut is_illegal = [false; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        let name = OPCODE_STRING_TABLE[opcode];
+        let mut index = 0;
+        while index < ILLEGAL_NAMES.len() {
+            if name_is(name, ILLEGAL_NAMES[index]) {
+                is_illegal[opcode] = true;
+            }
+            index += 1;
+        }
+        // Only $EA is the official NOP, and $EB is a copy of SBC immediate.
+        if name_is(name, "nop") && opcode != OpCode::NOP5 as usize {
+            is_illegal[opcode] = true;
+        }
+        if opcode == OpCode::SBC_imm1 as usize {
+            is_illegal[opcode] = true;
+        }
+        opcode += 1;
+    }
+    is_illegal
+};
+
+/// The undocumented NMOS opcodes, which are emulated by opcodes_illegal. The table
+/// is worked out from the names at compile time.
+pub fn is_illegal(opcode: u8) -> bool {
+    IS_ILLEGAL[opcode as usize]
+}
+
+/// Everything about an opcode, for running it and for showing it.
+#[derive(Clone, Copy)]
+pub struct OpcodeEntry {
+    pub name: &'static str,
+    pub operation: OperationFn,
+    pub mode: Mode,
+    /// The base number of cycles, before any ExtraCycle.
+    pub cycles: u8,
+    pub extra_cycle: ExtraCycle,
+}
+
+const fn entry(
+    name: &'static str,
+    operation: OperationFn,
+    mode: Mode,
+    cycles: u8,
+    extra_cycle: ExtraCycle,
+) -> OpcodeEntry {
+    OpcodeEntry {
+        name,
+        operation,
+        mode,
+        cycles,
+        extra_cycle,
+    }
+}
+
+/// The NMOS 6502 opcodes, which the NES uses. The CPU, the disassemblers, and the
+/// visualizer all read from this one table, which is put together from the columns
+/// above at compile time.
+pub static OPCODES: [OpcodeEntry; 256] = {
+    let mut entries = [entry("kil", kil, Mode::None, 0, ExtraCycle::None); 256];
+    let mut index = 0;
+    while index < 256 {
+        entries[index] = entry(
+            OPCODE_STRING_TABLE[index],
+            OPERATION_FN_TABLE[index],
+            ADDRESSING_MODE_TABLE[index],
+            CYCLES_TABLE[index],
+            EXTRA_CYCLES_TABLE[index],
+        );
+        index += 1;
+    }
+    entries
+};
+
+/// Look up how to run an opcode for a CPU variant.
+pub fn decode(variant: CpuVariant, opcode: u8) -> OpcodeEntry {
+    if variant == CpuVariant::Wdc65C02 {
+        if let Some(decoded) = decode_65c02(opcode) {
+            return decoded;
+        }
+    }
+    OPCODES[opcode as usize]
+}
+
+/// The 65C02 adds new instructions in the slots of the NMOS illegal opcodes, and
+/// turns the rest of them into NOPs. Only some of the new instructions are here so
+/// far, the (zp) addressing mode, INC A, DEC A, BIT #imm, JMP (abs,x), and the
+/// RMB/SMB/BBR/BBS bit instructions are still NOPs.
+/// http://6502.org/tutorials/65c02opcodes.html
+fn decode_65c02(opcode: u8) -> Option<OpcodeEntry> {
+    let none = ExtraCycle::None;
+    Some(match opcode {
+        0xda => entry("phx", phx, Mode::None, 3, none),
+        0xfa => entry("plx", plx, Mode::None, 4, none),
+        0x5a => entry("phy", phy, Mode::None, 3, none),
+        0x7a => entry("ply", ply, Mode::None, 4, none),
+        0x64 => entry("stz", stz, Mode::ZeroPage, 3, none),
+        0x74 => entry("stz", stz, Mode::ZeroPageX, 4, none),
+        0x9c => entry("stz", stz, Mode::Absolute, 4, none),
+        0x9e => entry("stz", stz, Mode::AbsoluteIndexedX, 5, none),
+        0x80 => entry("bra", bra, Mode::Relative, 2, ExtraCycle::IfTaken),
+        0x04 => entry("tsb", tsb, Mode::ZeroPage, 5, none),
+        0x0c => entry("tsb", tsb, Mode::Absolute, 6, none),
+        0x14 => entry("trb", trb, Mode::ZeroPage, 5, none),
+        0x1c => entry("trb", trb, Mode::Absolute, 6, none),
+        0x6c => entry("jmp", jmp_indirect, Mode::None, 6, none),
+        0xcb => entry("wai", wai, Mode::None, 3, none),
+        _ if is_illegal(opcode) => match opcode {
+            // The NOPs take up different amounts of bytes and cycles.
+            0x44 => entry("nop", nop, Mode::ZeroPage, 3, none),
+            0x54 | 0xd4 | 0xf4 => entry("nop", nop, Mode::ZeroPageX, 4, none),
+            0x5c => entry("nop", nop, Mode::Absolute, 8, none),
+            0xdc | 0xfc => entry("nop", nop, Mode::Absolute, 4, none),
+            _ if opcode & 0x0f == 0x02 => entry("nop", nop, Mode::Immediate, 2, none),
+            _ if opcode & 0b11 == 0b11 => entry("nop", nop, Mode::None, 1, none),
+            _ => entry("nop", nop, OPCODES[opcode as usize].mode, 2, none),
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let lda = decode(CpuVariant::Nmos6502, 0xb9);
+        assert_eq!(
+            (lda.name, lda.mode, lda.cycles, lda.extra_cycle),
+            ("lda", Mode::AbsoluteIndexedY, 4, ExtraCycle::PageBoundary)
+        );
+        let stz = decode(CpuVariant::Wdc65C02, 0x9c);
+        assert_eq!((stz.name, stz.mode, stz.cycles), ("stz", Mode::Absolute, 4));
+        assert_eq!(OPCODES[0x9c].name, "shy");
+    }
+
+    #[test]
+    fn test_illegal_tables() {
+        for opcode in 0..=255 {
+            let name = OPCODES[opcode as usize].name;
+            assert_eq!(is_kil(opcode), name == "kil", "{:02x}", opcode);
+        }
+        assert_eq!((0..=255).filter(|&opcode| is_illegal(opcode)).count(), 105);
+        assert!(is_illegal(0x1a));
+        assert!(!is_illegal(0xea));
+        assert!(is_illegal(0xeb));
+        assert!(!is_illegal(0xe9));
+    }
+}
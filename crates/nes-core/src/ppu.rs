@@ -0,0 +1,725 @@
+use crate::bus::{BusDevice, SharedBus};
+/// The PPU is a picture processing unit. It generates 240 lines of pixels.
+/// It has its own address space, consisting of 10kb of memory (possibly more with
+/// memory mappers). 8 kilobytes of ROM or RAM on the Game Pak, that contained tiles.
+/// Then 2kb for maps and other things.
+use crate::chr::TILE_SIZE;
+use crate::prelude::*;
+use crate::tile_stats::{TileLayer, TileStats};
+use core::cell::Cell;
+
+pub const FRAME_WIDTH: usize = 256;
+pub const FRAME_HEIGHT: usize = 240;
+/// The frames are handed out as RGB, see frontend::VideoSink.
+pub const FRAME_BYTES: usize = FRAME_WIDTH * FRAME_HEIGHT * 3;
+
+// Frame size:
+// 341 × 261
+// Scanlines: 342
+// Cycles: 261
+
+// Palette information: https://wiki.nesdev.com/w/index.php/PPU_palettes
+
+// Implement:
+// Pattern memory   - holds the sprites
+// Nametable memory - holds tile layout
+// Palette memory   - holds color info
+
+#[derive(Clone, Copy)]
+pub(crate) enum PpuRegister {
+    /// PPU control register - Write only
+    Ctrl = 0x2000,
+    /// PPU mask register - Write only
+    Mask = 0x2001,
+    /// PPU status register - Read only
+    Status = 0x2002,
+    /// OAM address port - Write
+    Oam = 0x2003,
+    /// OAM read/write
+    OamData = 0x2004,
+    /// Scroll - write x2
+    Scroll = 0x2005,
+    /// Address - write x2
+    Address = 0x2006,
+    /// VRAM read/write data register
+    Data = 0x2007,
+}
+
+impl PpuRegister {
+    /// The register's number on the bus, 0-7.
+    pub(crate) fn index(self) -> u8 {
+        (self as u16 - 0x2000) as u8
+    }
+}
+
+/// PPU control register
+/// Controller ($2000) > write
+///
+/// https://wiki.nesdev.com/w/index.php/PPU_registers#PPUCTRL
+///
+/// VPHB SINN
+/// |||| ||||
+/// |||| ||++- Base nametable address
+/// |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+/// |||| |+--- VRAM address increment per CPU read/write of PPUDATA
+/// |||| |     (0: add 1, going across; 1: add 32, going down)
+/// |||| +---- Sprite pattern table address for 8x8 sprites
+/// ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
+/// |||+------ Background pattern table address (0: $0000; 1: $1000)
+/// ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+/// |+-------- PPU master/slave select
+/// |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
+/// +--------- Generate an NMI at the start of the
+///            vertical blanking interval (0: off; 1: on)
+enum PpuCtrl {
+    V = 0b1000_0000,
+    P = 0b0100_0000,
+    H = 0b0010_0000,
+    B = 0b0001_0000,
+    S = 0b0000_1000,
+    I = 0b0000_0100,
+    N = 0b0000_0011,
+}
+
+/// This register controls the rendering of sprites and backgrounds, as well as color effects.
+///
+/// https://wiki.nesdev.com/w/index.php/PPU_registers#PPUMASK
+///
+/// 7  bit  0
+/// ---- ----
+/// BGRs bMmG
+/// |||| ||||
+/// |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+/// |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+/// |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+/// |||| +---- 1: Show background
+/// |||+------ 1: Show sprites
+/// ||+------- Emphasize red
+/// |+-------- Emphasize green
+/// +--------- Emphasize blue
+enum PpuMask {
+    Blue = 0b1000_0000,
+    Green = 0b0100_0000,
+    Red = 0b0010_0000,
+    ShowSprites = 0b0001_0000,
+    ShowBackground = 0b0000_1000,
+    ShowLeftmostSprites = 0b0000_0100,
+    ShowLeftmostBackground = 0b0000_0010,
+    GrayScale = 0b0000_0001,
+}
+
+/// This register reflects the state of various functions inside the PPU. It is often
+/// used for determining timing. To determine when the PPU has reached a given pixel of
+/// the screen, put an opaque (non-transparent) pixel of sprite 0 there.
+///
+/// 7  bit  0
+/// ---- ----
+/// VSO. ....
+/// |||| ||||
+/// |||+-++++- Least significant bits previously written into a PPU register
+/// |||        (due to register not being updated for this address)
+/// ||+------- Sprite overflow. The intent was for this flag to be set
+/// ||         whenever more than eight sprites appear on a scanline, but a
+/// ||         hardware bug causes the actual behavior to be more complicated
+/// ||         and generate false positives as well as false negatives; see
+/// ||         PPU sprite evaluation. This flag is set during sprite
+/// ||         evaluation and cleared at dot 1 (the second dot) of the
+/// ||         pre-render line.
+/// |+-------- Sprite 0 Hit.  Set when a nonzero pixel of sprite 0 overlaps
+/// |          a nonzero background pixel; cleared at dot 1 of the pre-render
+/// |          line.  Used for raster timing.
+/// +--------- Vertical blank has started (0: not in vblank; 1: in vblank).
+///            Set at dot 1 of line 241 (the line *after* the post-render
+///            line); cleared after reading $2002 and at dot 1 of the
+///            pre-render line.
+enum PpuStatus {
+    VerticalBlank = 0b1000_0000,
+    SpriteHit = 0b0100_0000,
+    SpriteOverflow = 0b0010_0000,
+}
+
+/// How closely to follow the quirks of the hardware. Some of them are only relied on
+/// by test ROMs and a handful of games, and they glitch games that only ever worked on
+/// forgiving emulators.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Accuracy {
+    /// Leave out the quirks that corrupt the PPU's state.
+    #[default]
+    Compatible,
+    /// Accessing OAMADDR, OAMDATA, or PPUDATA while the PPU is rendering corrupts the
+    /// OAM and the VRAM address, like on the hardware.
+    /// https://wiki.nesdev.com/w/index.php/PPU_registers
+    Hardware,
+}
+
+/// The eight registers the PPU has on the CPU bus, and the OAM behind OAMADDR and
+/// OAMDATA. The registers repeat every 8 bytes through $3FFF, see register_index.
+/// Until there is a real PPU, this stands in for it, with just the side effects that
+/// don't need any rendering. The bus attaches it at $2000-$3FFF, see
+/// Bus::new_with_memory_map.
+///
+/// Every write is remembered, and fills the PPU's I/O latch, which is what the
+/// write-only registers read back as. Reading PPUSTATUS clears the vblank flag and the
+/// write toggle of PPUSCROLL and PPUADDR. Nothing sets the vblank flag yet, apart from
+/// the bus's vblank signal, see Bus::set_vblank. PPUADDR sets the VRAM address, and
+/// each PPUDATA access moves it along, but PPUSCROLL doesn't touch it.
+/// https://wiki.nesdev.com/w/index.php/PPU_registers
+pub struct StubPpuRegisters {
+    writes: [u8; 8],
+    latch: Cell<u8>,
+    vblank: Cell<bool>,
+    /// PPUSCROLL and PPUADDR take two writes, this is true after the first one.
+    write_toggle: Cell<bool>,
+    /// The "t" register, which holds the first PPUADDR write until the second one.
+    temp_address: u16,
+    /// The "v" register, where the next PPUDATA access goes.
+    vram_address: Cell<u16>,
+    /// The sprite memory, which is filled by OAM DMA, or a byte at a time through
+    /// OAMDATA.
+    oam: [u8; 0x100],
+    /// Where the next OAMDATA access goes, set through OAMADDR.
+    oam_address: u8,
+    accuracy: Accuracy,
+}
+
+impl Default for StubPpuRegisters {
+    fn default() -> Self {
+        StubPpuRegisters::new()
+    }
+}
+
+/// The register that an address in $2000-$3FFF maps onto, 0-7, in the order of
+/// PpuRegister.
+pub(crate) fn register_index(address: u16) -> u8 {
+    // $2000-$2007  $0008  PPU registers
+    // $2008-$3FFF  $1FF8  Mirrors of $2000-$2007, repeating every 8 bytes
+    (address % 8) as u8
+}
+
+impl StubPpuRegisters {
+    pub fn new() -> StubPpuRegisters {
+        StubPpuRegisters {
+            writes: [0; 8],
+            latch: Cell::new(0),
+            vblank: Cell::new(false),
+            write_toggle: Cell::new(false),
+            temp_address: 0,
+            vram_address: Cell::new(0),
+            oam: [0; 0x100],
+            oam_address: 0,
+            accuracy: Accuracy::default(),
+        }
+    }
+
+    /// The last value written to a register, 0-7.
+    pub fn last_write(&self, register: u8) -> u8 {
+        self.writes[register as usize]
+    }
+
+    pub fn write_toggle(&self) -> bool {
+        self.write_toggle.get()
+    }
+
+    pub fn vram_address(&self) -> u16 {
+        self.vram_address.get()
+    }
+
+    pub fn oam(&self) -> &[u8; 0x100] {
+        &self.oam
+    }
+
+    /// The background or the sprites are turned on, and the PPU is outside of vblank.
+    pub fn is_rendering(&self) -> bool {
+        let mask = self.writes[PpuRegister::Mask.index() as usize];
+        let shown = PpuMask::ShowBackground as u8 | PpuMask::ShowSprites as u8;
+        !self.vblank.get() && mask & shown != 0
+    }
+
+    /// PPUDATA moves the VRAM address along by PPUCTRL's increment. While rendering,
+    /// the PPU is using the address to fetch the tiles, so the access bumps the
+    /// coarse X and the Y scroll at the same time instead.
+    /// https://wiki.nesdev.com/w/index.php/PPU_scrolling#Wrapping_around
+    fn increment_vram_address(&self) {
+        let address = self.vram_address.get();
+        let address = if self.accuracy == Accuracy::Hardware && self.is_rendering() {
+            increment_y(increment_coarse_x(address))
+        } else if self.writes[PpuRegister::Ctrl.index() as usize] & PpuCtrl::I as u8 != 0
+        {
+            address.wrapping_add(32)
+        } else {
+            address.wrapping_add(1)
+        };
+        self.vram_address.set(address & 0x7fff);
+    }
+
+    fn status(&self) -> u8 {
+        let vblank = if self.vblank.get() {
+            PpuStatus::VerticalBlank as u8
+        } else {
+            0
+        };
+        // The low bits aren't driven, so they keep what's in the latch.
+        vblank | (self.latch.get() & 0b0001_1111)
+    }
+
+    /// OAMADDR and OAMDATA. While rendering, the sprite evaluation is using the OAM,
+    /// and with Accuracy::Hardware the accesses get in its way.
+    fn write_oam(&mut self, register: u8, value: u8) {
+        let glitch = self.accuracy == Accuracy::Hardware && self.is_rendering();
+        if register == PpuRegister::Oam.index() {
+            if glitch {
+                // The sprite evaluation picks up from the new address, and copies its
+                // row of 8 bytes over the first row of the OAM.
+                let row = (value & 0xf8) as usize;
+                self.oam.copy_within(row..row + 8, 0);
+            }
+            self.oam_address = value;
+        } else if glitch {
+            // The sprite evaluation owns the OAM, so the write is dropped, and only
+            // the sprite index of the address is bumped.
+            self.oam_address = self.oam_address.wrapping_add(4);
+        } else {
+            self.oam[self.oam_address as usize] = value;
+            self.oam_address = self.oam_address.wrapping_add(1);
+        }
+    }
+}
+
+/// The size of StubPpuRegisters::save_state: the registers, the latch and the two
+/// flags, the two VRAM addresses, OAMADDR, and the OAM.
+const STATE_SIZE: usize = 8 + 3 + 4 + 1 + 0x100;
+
+impl BusDevice for StubPpuRegisters {
+    fn name(&self) -> &str {
+        "PPU registers"
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        let register = register_index(address);
+        if register == PpuRegister::OamData.index() {
+            return self.oam[self.oam_address as usize];
+        }
+        if register == PpuRegister::Data.index() {
+            self.increment_vram_address();
+        }
+        if register != PpuRegister::Status.index() {
+            return self.latch.get();
+        }
+        let status = self.status();
+        self.vblank.set(false);
+        self.write_toggle.set(false);
+        self.latch.set(status);
+        status
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let register = register_index(address);
+        if register == PpuRegister::Oam.index()
+            || register == PpuRegister::OamData.index()
+        {
+            self.write_oam(register, value);
+        }
+        self.writes[register as usize] = value;
+        self.latch.set(value);
+        if register == PpuRegister::Address.index() {
+            if self.write_toggle.get() {
+                self.temp_address = (self.temp_address & 0xff00) | value as u16;
+                self.vram_address.set(self.temp_address);
+            } else {
+                self.temp_address =
+                    (self.temp_address & 0x00ff) | ((value as u16 & 0x3f) << 8);
+            }
+        } else if register == PpuRegister::Data.index() {
+            self.increment_vram_address();
+        }
+        if register == PpuRegister::Scroll.index()
+            || register == PpuRegister::Address.index()
+        {
+            self.write_toggle.set(!self.write_toggle.get());
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        let register = register_index(address);
+        Some(if register == PpuRegister::OamData.index() {
+            self.oam[self.oam_address as usize]
+        } else if register == PpuRegister::Status.index() {
+            self.status()
+        } else {
+            self.latch.get()
+        })
+    }
+
+    fn vblank(&mut self, vblank: bool) {
+        self.vblank.set(vblank);
+    }
+
+    fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.writes.to_vec();
+        state.push(self.latch.get());
+        state.push(self.vblank.get() as u8);
+        state.push(self.write_toggle.get() as u8);
+        state.extend(self.temp_address.to_le_bytes());
+        state.extend(self.vram_address.get().to_le_bytes());
+        state.push(self.oam_address);
+        state.extend(self.oam);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() != STATE_SIZE {
+            return Err(format!(
+                "The state is {} bytes, but should be {}.",
+                state.len(),
+                STATE_SIZE
+            ));
+        }
+        let (writes, state) = state.split_at(8);
+        self.writes.copy_from_slice(writes);
+        self.latch.set(state[0]);
+        self.vblank.set(state[1] != 0);
+        self.write_toggle.set(state[2] != 0);
+        self.temp_address = u16::from_le_bytes([state[3], state[4]]);
+        self.vram_address
+            .set(u16::from_le_bytes([state[5], state[6]]));
+        self.oam_address = state[7];
+        self.oam.copy_from_slice(&state[8..]);
+        Ok(())
+    }
+}
+
+/// The VRAM address is laid out as 0yyy NNYY YYYX XXXX, with the fine Y, the
+/// nametable, the coarse Y, and the coarse X. Moving past the last tile of a
+/// nametable moves on to the next one.
+fn increment_coarse_x(address: u16) -> u16 {
+    if address & 0x001f == 31 {
+        (address & !0x001f) ^ 0x0400
+    } else {
+        address + 1
+    }
+}
+
+fn increment_y(address: u16) -> u16 {
+    if address & 0x7000 != 0x7000 {
+        return address + 0x1000;
+    }
+    let address = address & !0x7000;
+    let coarse_y = (address & 0x03e0) >> 5;
+    let (coarse_y, address) = match coarse_y {
+        // The 30th row is the last one of the nametable.
+        29 => (0, address ^ 0x0800),
+        // The attribute rows wrap without switching the nametable.
+        31 => (0, address),
+        _ => (coarse_y + 1, address),
+    };
+    (address & !0x03e0) | (coarse_y << 5)
+}
+
+/// Debug switches for hiding parts of the picture, to isolate what's being drawn
+/// while reverse engineering a game. These only change the output, the PPU still
+/// runs as normal, e.g. sprite 0 hits still happen with the sprites hidden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLayers {
+    pub background: bool,
+    pub sprites: bool,
+    /// The four nametables at $2000, $2400, $2800, and $2C00.
+    pub nametables: [bool; 4],
+}
+
+impl Default for DebugLayers {
+    fn default() -> Self {
+        DebugLayers {
+            background: true,
+            sprites: true,
+            nametables: [true; 4],
+        }
+    }
+}
+
+impl DebugLayers {
+    /// Should a background tile that was fetched from this nametable address be
+    /// drawn? The mirrors at $3000-$3EFF map back onto the four nametables.
+    pub fn is_background_visible(&self, nametable_address: u16) -> bool {
+        let nametable = (nametable_address >> 10) & 0b11;
+        self.background && self.nametables[nametable as usize]
+    }
+
+    pub fn is_sprite_visible(&self) -> bool {
+        self.sprites
+    }
+}
+
+pub struct Ppu {
+    bus: SharedBus,
+    /// The layers to hide, see fetch_pattern.
+    pub debug_layers: DebugLayers,
+    /// When set, the pattern table fetches are recorded here, see fetch_pattern.
+    pub tile_stats: Option<TileStats>,
+    /// The layer that the fetches are for, set by start_fetches.
+    fetching: Option<TileLayer>,
+    /// The nametable entry of the background tile that's being fetched, set by
+    /// start_background_tile.
+    nametable_address: u16,
+}
+
+impl Ppu {
+    pub fn new(bus: SharedBus) -> Ppu {
+        Ppu {
+            bus,
+            debug_layers: DebugLayers::default(),
+            tile_stats: None,
+            fetching: None,
+            nametable_address: 0x2000,
+        }
+    }
+
+    /// Fetch a byte of a tile from the pattern tables while rendering. The cartridge
+    /// sees the fetch, so mappers like the MMC2 can switch their banks. The addresses
+    /// without any CHR read as 0. The fetch is counted in the tile_stats, for the layer
+    /// from start_fetches. When the debug_layers hide the layer, the fetch still
+    /// happens, but it reads as 0, so the tile's pixels are transparent.
+    pub fn fetch_pattern(&mut self, address: u16) -> u8 {
+        if let (Some(tile_stats), Some(layer)) = (&mut self.tile_stats, self.fetching) {
+            tile_stats.record_fetch(layer, address);
+        }
+        let value = self
+            .bus
+            .borrow_mut()
+            .fetch_ppu_u8(address)
+            .unwrap_or_default();
+        if self.is_fetching_visible() {
+            value
+        } else {
+            0
+        }
+    }
+
+    /// Fetch both planes of a row of a tile, and decode the row into the pixels'
+    /// values from 0 to 3, left to right. The address is the row in the first plane.
+    pub fn fetch_tile_row(&mut self, address: u16) -> [u8; TILE_SIZE] {
+        let low = self.fetch_pattern(address);
+        let high = self.fetch_pattern(address + TILE_SIZE as u16);
+        core::array::from_fn(|x| {
+            let bit = 7 - x;
+            ((low >> bit) & 1) | (((high >> bit) & 1) << 1)
+        })
+    }
+
+    /// Let the cartridge know which layer the next fetches are for, before the fetches
+    /// for the background tiles or the sprites.
+    pub fn start_fetches(&mut self, layer: TileLayer) {
+        self.fetching = Some(layer);
+        self.bus.borrow_mut().ppu_layer(layer);
+    }
+
+    /// Note the nametable entry that the next background tile comes from, so that the
+    /// debug_layers can hide the tiles of one nametable.
+    pub fn start_background_tile(&mut self, nametable_address: u16) {
+        self.nametable_address = nametable_address;
+    }
+
+    fn is_fetching_visible(&self) -> bool {
+        match self.fetching {
+            Some(TileLayer::Background) => self
+                .debug_layers
+                .is_background_visible(self.nametable_address),
+            Some(TileLayer::Sprites) => self.debug_layers.is_sprite_visible(),
+            None => true,
+        }
+    }
+
+    fn get_register(&self, register: PpuRegister) -> u8 {
+        self.bus.borrow().read_u8(register as u16)
+    }
+
+    fn set_register(&self, register: PpuRegister, value: u8) {
+        self.bus.borrow_mut().set_u8(register as u16, value);
+    }
+
+    fn get_register_flag(&self, register: PpuRegister, flag: u8) -> bool {
+        self.get_register(register) & flag == flag
+    }
+
+    fn get_base_name_table(&self) -> u16 {
+        match self.get_register(PpuRegister::Ctrl) & (PpuCtrl::N as u8) {
+            0 => 0x2000,
+            1 => 0x2400,
+            2 => 0x2800,
+            3 => 0x2C00,
+            _ => panic!("Getting the base name table failed."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::mappers::{Mapper, SimpleProgram};
+
+    #[test]
+    fn test_debug_layers() {
+        let mut layers = DebugLayers::default();
+        assert!(layers.is_background_visible(0x2000));
+        assert!(layers.is_sprite_visible());
+
+        layers.nametables[1] = false;
+        assert!(layers.is_background_visible(0x23ff));
+        assert!(!layers.is_background_visible(0x2400));
+        assert!(!layers.is_background_visible(0x27ff));
+        // $3400 is a mirror of $2400.
+        assert!(!layers.is_background_visible(0x3400));
+        assert!(layers.is_background_visible(0x2800));
+
+        layers.background = false;
+        assert!(!layers.is_background_visible(0x2000));
+        assert!(layers.is_sprite_visible());
+    }
+
+    /// Serves the pattern tables from an array.
+    struct PatternTables(Vec<u8>);
+
+    impl Mapper for PatternTables {
+        fn read_cpu(&self, _addr: u16) -> Option<u8> {
+            None
+        }
+
+        fn write_cpu(&mut self, _addr: u16, _value: u8) -> bool {
+            false
+        }
+
+        fn read_ppu(&self, addr: u16) -> Option<u8> {
+            self.0.get(addr as usize).copied()
+        }
+    }
+
+    #[test]
+    fn test_hidden_layers_are_blank() {
+        // The first row of tile 1, in both pattern tables, is 0, 1, 2, 3, 0, 1, 2, 3.
+        let mut pattern_tables = vec![0; 0x2000];
+        for table in [0x0000, 0x1000] {
+            pattern_tables[table + 0x10] = 0b0101_0101;
+            pattern_tables[table + 0x18] = 0b0011_0011;
+        }
+        let mut ppu =
+            Ppu::new(Bus::new_shared_bus(Box::new(PatternTables(pattern_tables))));
+        let row = [0, 1, 2, 3, 0, 1, 2, 3];
+        ppu.tile_stats = Some(TileStats::new());
+
+        ppu.start_fetches(TileLayer::Background);
+        ppu.start_background_tile(0x2400);
+        assert_eq!(ppu.fetch_tile_row(0x0010), row);
+        ppu.debug_layers.nametables[1] = false;
+        assert_eq!(ppu.fetch_tile_row(0x0010), [0; 8]);
+        // The other nametables are still drawn.
+        ppu.start_background_tile(0x2000);
+        assert_eq!(ppu.fetch_tile_row(0x0010), row);
+        ppu.debug_layers.background = false;
+        assert_eq!(ppu.fetch_tile_row(0x0010), [0; 8]);
+
+        ppu.start_fetches(TileLayer::Sprites);
+        assert_eq!(ppu.fetch_tile_row(0x1010), row);
+        ppu.debug_layers.sprites = false;
+        assert_eq!(ppu.fetch_tile_row(0x1010), [0; 8]);
+        assert_eq!(ppu.fetch_pattern(0x1010), 0);
+
+        // The hidden tiles were still fetched.
+        let tile_stats = ppu.tile_stats.as_mut().unwrap();
+        tile_stats.end_frame();
+        assert_eq!(tile_stats.last_frame().count(TileLayer::Background, 1), 8);
+        assert_eq!(tile_stats.last_frame().count(TileLayer::Sprites, 257), 5);
+    }
+
+    #[test]
+    fn test_tile_stats() {
+        let mut ppu = Ppu::new(Bus::new_shared_bus(Box::new(SimpleProgram::new())));
+        ppu.tile_stats = Some(TileStats::new());
+        ppu.start_fetches(TileLayer::Background);
+        ppu.fetch_pattern(0x0010);
+        ppu.fetch_pattern(0x0018);
+        ppu.start_fetches(TileLayer::Sprites);
+        ppu.fetch_pattern(0x1000);
+
+        let tile_stats = ppu.tile_stats.as_mut().unwrap();
+        tile_stats.end_frame();
+        let counts = tile_stats.last_frame();
+        assert_eq!(counts.count(TileLayer::Background, 1), 2);
+        assert_eq!(counts.used(TileLayer::Sprites), [256]);
+    }
+
+    #[test]
+    fn test_stub_registers() {
+        let status = PpuRegister::Status as u16;
+        let mut registers = StubPpuRegisters::new();
+        registers.write(PpuRegister::Address as u16, 0x3f);
+        assert!(registers.write_toggle());
+        // A write-only register reads back the latch.
+        assert_eq!(registers.read(PpuRegister::Ctrl as u16), 0x3f);
+
+        registers.vblank(true);
+        assert_eq!(registers.peek(status), Some(0b1001_1111));
+        assert_eq!(registers.read(status), 0b1001_1111);
+        // The read cleared the flag and the toggle, and the mirrors are the same.
+        assert_eq!(registers.read(status + 0x1ff8), 0b0001_1111);
+        assert!(!registers.write_toggle());
+        assert_eq!(registers.last_write(PpuRegister::Address.index()), 0x3f);
+    }
+
+    #[test]
+    fn test_vram_address() {
+        let address = PpuRegister::Address as u16;
+        let data = PpuRegister::Data as u16;
+        let mut registers = StubPpuRegisters::new();
+        registers.vblank(true);
+        registers.write(PpuRegister::Mask as u16, PpuMask::ShowBackground as u8);
+        registers.write(address, 0x23);
+        registers.write(address, 0xc0);
+        assert_eq!(registers.vram_address(), 0x23c0);
+        registers.read(data);
+        assert_eq!(registers.vram_address(), 0x23c1);
+        registers.write(PpuRegister::Ctrl as u16, PpuCtrl::I as u8);
+        registers.write(data, 0);
+        assert_eq!(registers.vram_address(), 0x23e1);
+
+        // While rendering, the access is compatible until the accuracy is raised.
+        registers.vblank(false);
+        registers.read(data);
+        assert_eq!(registers.vram_address(), 0x2401);
+        registers.set_accuracy(Accuracy::Hardware);
+        registers.read(data);
+        // Coarse X and fine Y both went up.
+        assert_eq!(registers.vram_address(), 0x3402);
+    }
+
+    #[test]
+    fn test_stub_state() {
+        let mut registers = StubPpuRegisters::new();
+        registers.write(PpuRegister::Address as u16, 0x21);
+        registers.write(PpuRegister::Address as u16, 0x08);
+        registers.write(PpuRegister::Oam as u16, 0x40);
+        registers.write(PpuRegister::OamData as u16, 0x99);
+        let state = registers.save_state();
+
+        let mut loaded = StubPpuRegisters::new();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.vram_address(), 0x2108);
+        assert_eq!(loaded.oam()[0x40], 0x99);
+        assert_eq!(loaded.save_state(), state);
+        assert!(loaded.load_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn test_scroll_increments() {
+        // The last tile of the row moves over to the next nametable.
+        assert_eq!(increment_coarse_x(0x001f), 0x0400);
+        assert_eq!(increment_y(0x0000), 0x1000);
+        // The last fine Y of the last row moves down to the next nametable.
+        assert_eq!(increment_y(0x73a0), 0x0800);
+        assert_eq!(increment_y(0x73e0), 0x0000);
+        assert_eq!(increment_y(0x7020), 0x0040);
+    }
+}
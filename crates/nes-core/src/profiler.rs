@@ -0,0 +1,233 @@
+//! Time how long the emulator itself takes to run each part of a frame, to find what
+//! to optimize in the core. This measures the host's wall clock time, unlike CpuStats,
+//! which counts the emulated cycles. Turn it on with Emulator::set_profiler.
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsystem {
+    /// Running the instructions, without the time spent in the mapper.
+    Cpu,
+    /// There's no PPU rendering yet, so this stays at zero.
+    Ppu,
+    /// Mixing the audio and making the samples.
+    Apu,
+    /// Clocking the cartridge's mapper.
+    Mapper,
+    /// Handing the frame and the samples to the frontend, see frontend::RunLoop.
+    Presentation,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 5] = [
+        Subsystem::Cpu,
+        Subsystem::Ppu,
+        Subsystem::Apu,
+        Subsystem::Mapper,
+        Subsystem::Presentation,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cpu => "cpu",
+            Subsystem::Ppu => "ppu",
+            Subsystem::Apu => "apu",
+            Subsystem::Mapper => "mapper",
+            Subsystem::Presentation => "present",
+        }
+    }
+}
+
+/// The time each subsystem took in one frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameTimings {
+    durations: [Duration; 5],
+}
+
+impl FrameTimings {
+    pub fn get(&self, subsystem: Subsystem) -> Duration {
+        self.durations[subsystem as usize]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
+
+/// The performance counter of one subsystem, over the recent frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerformanceCounter {
+    pub subsystem: Subsystem,
+    pub last: Duration,
+    pub average: Duration,
+    pub max: Duration,
+}
+
+/// Collects the timings frame by frame. The emulator starts a frame at the start of
+/// Emulator::run_frame, which finishes the last one, so everything that's recorded
+/// after a frame runs, like presenting it, still counts towards that frame.
+pub struct Profiler {
+    /// The finished frames, up to the history length.
+    frames: Vec<FrameTimings>,
+    history: usize,
+    current: Option<FrameTimings>,
+}
+
+impl Profiler {
+    /// Keep the timings of the last `history` frames.
+    pub fn new(history: usize) -> Profiler {
+        assert!(
+            history > 0,
+            "The profiler needs to keep at least one frame."
+        );
+        Profiler {
+            frames: Vec::new(),
+            history,
+            current: None,
+        }
+    }
+
+    /// Finish the current frame, if there is one, and start the next.
+    pub fn start_frame(&mut self) {
+        if let Some(frame) = self.current.take() {
+            if self.frames.len() == self.history {
+                self.frames.remove(0);
+            }
+            self.frames.push(frame);
+        }
+        self.current = Some(FrameTimings::default());
+    }
+
+    /// Add time to a subsystem in the current frame. Nothing is recorded until the
+    /// first frame starts.
+    pub fn record(&mut self, subsystem: Subsystem, duration: Duration) {
+        if let Some(ref mut frame) = self.current {
+            frame.durations[subsystem as usize] += duration;
+        }
+    }
+
+    /// Run the closure, and add the time it took to a subsystem.
+    pub fn time<T>(&mut self, subsystem: Subsystem, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(subsystem, start.elapsed());
+        result
+    }
+
+    /// The finished frames, from the oldest to the newest.
+    pub fn frames(&self) -> &[FrameTimings] {
+        &self.frames
+    }
+
+    pub fn last_frame(&self) -> Option<&FrameTimings> {
+        self.frames.last()
+    }
+
+    /// The counters for every subsystem, or None before the first frame finishes.
+    pub fn counters(&self) -> Option<Vec<PerformanceCounter>> {
+        let last = self.last_frame()?;
+        Some(
+            Subsystem::ALL
+                .iter()
+                .map(|&subsystem| {
+                    let durations = self.frames.iter().map(|frame| frame.get(subsystem));
+                    PerformanceCounter {
+                        subsystem,
+                        last: last.get(subsystem),
+                        average: durations.clone().sum::<Duration>()
+                            / self.frames.len() as u32,
+                        max: durations.max().unwrap_or_default(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// A short summary of the average timings, to draw over the picture, e.g.
+    /// "cpu 1.20 ppu 0.00 apu 0.05 mapper 0.10 present 0.30 = 1.65ms".
+    pub fn overlay(&self) -> String {
+        let counters = match self.counters() {
+            Some(counters) => counters,
+            None => return String::new(),
+        };
+        let mut overlay = String::new();
+        let mut total = Duration::default();
+        for counter in counters {
+            overlay.push_str(&format!(
+                "{} {:.2} ",
+                counter.subsystem.name(),
+                milliseconds(counter.average)
+            ));
+            total += counter.average;
+        }
+        overlay.push_str(&format!("= {:.2}ms", milliseconds(total)));
+        overlay
+    }
+
+    pub fn report(&self) -> String {
+        let counters = match self.counters() {
+            Some(counters) => counters,
+            None => return String::from("No frames were profiled.\n"),
+        };
+        let mut report = format!("Emulator timings ({} frames, ms)\n", self.frames.len());
+        for counter in counters {
+            report.push_str(&format!(
+                "  {:<8} last {:>7.3}  average {:>7.3}  max {:>7.3}\n",
+                counter.subsystem.name(),
+                milliseconds(counter.last),
+                milliseconds(counter.average),
+                milliseconds(counter.max)
+            ));
+        }
+        report
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut profiler = Profiler::new(2);
+        // Nothing is recorded before the first frame.
+        profiler.record(Subsystem::Cpu, ms(100));
+        assert_eq!(profiler.counters(), None);
+
+        for cpu in &[1, 2, 4] {
+            profiler.start_frame();
+            profiler.record(Subsystem::Cpu, ms(*cpu));
+            profiler.record(Subsystem::Presentation, ms(1));
+            profiler.record(Subsystem::Presentation, ms(1));
+        }
+        // The last frame isn't finished yet.
+        assert_eq!(profiler.frames().len(), 2);
+        profiler.start_frame();
+        assert_eq!(profiler.frames().len(), 2);
+
+        let last = profiler.last_frame().unwrap();
+        assert_eq!(last.get(Subsystem::Cpu), ms(4));
+        assert_eq!(last.total(), ms(6));
+        let counters = profiler.counters().unwrap();
+        assert_eq!(
+            counters[0],
+            PerformanceCounter {
+                subsystem: Subsystem::Cpu,
+                last: ms(4),
+                average: ms(3),
+                max: ms(4),
+            }
+        );
+        assert_eq!(
+            profiler.overlay(),
+            "cpu 3.00 ppu 0.00 apu 0.00 mapper 0.00 present 2.00 = 5.00ms"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// The NES was sold in NTSC and PAL versions, which run at different speeds. A PAL
+// console has a slower CPU, but 50 taller frames a second instead of 60, so the
+// games were usually released in separate regional versions.
+// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+
+use crate::rom::{Header, TvSystem};
+use crate::rom_database::RomEntry;
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+
+/// The NTSC master clock is 21.477272 MHz, and the CPU divides it by 12.
+pub const NTSC_CPU_HZ: u64 = 1_789_773;
+
+/// The NTSC PPU draws 262 scanlines of 341 dots, and the CPU runs at a third of the
+/// speed. (262 * 341) / 3 = 29780.67 cycles
+pub const NTSC_FRAME_CYCLES: u64 = 29781;
+
+/// The PAL master clock is 26.601712 MHz, and the CPU divides it by 16.
+pub const PAL_CPU_HZ: u64 = 1_662_607;
+
+/// The PAL PPU draws 312 scanlines of 341 dots, at 3.2 dots per CPU cycle.
+/// (312 * 341) / 3.2 = 33247.5 cycles
+pub const PAL_FRAME_CYCLES: u64 = 33248;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(rename_all = "lowercase"))]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    /// Pick the region from the database if the dump is in it, as many headers don't
+    /// set it. Otherwise, use the PAL flag in byte 9 of the header, or the unofficial
+    /// one in byte 10. Dual compatible games run as NTSC, and Dendy games as PAL, which
+    /// has the same frame rate.
+    pub fn detect(header: &Header, database_entry: Option<&RomEntry>) -> Region {
+        if let Some(region) = database_entry.and_then(|entry| entry.region) {
+            return region;
+        }
+        if header.tv_system_rarely_used == TvSystem::PAL
+            || header.tv_system == TvSystem::PAL
+            || header.tv_system == TvSystem::Dendy
+        {
+            return Region::Pal;
+        }
+        Region::Ntsc
+    }
+
+    pub fn cpu_hz(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_CPU_HZ,
+            Region::Pal => PAL_CPU_HZ,
+        }
+    }
+
+    pub fn frame_cycles(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_FRAME_CYCLES,
+            Region::Pal => PAL_FRAME_CYCLES,
+        }
+    }
+
+    /// How many CPU cycles a number of scanlines take. A scanline is 341 PPU dots, and
+    /// the PPU runs 3 dots per CPU cycle on NTSC, and 3.2 on PAL.
+    pub fn scanline_cycles(self, scanlines: u64) -> u64 {
+        match self {
+            Region::Ntsc => scanlines * 341 / 3,
+            Region::Pal => scanlines * 341 * 5 / 16,
+        }
+    }
+
+    pub fn frames_per_second(self) -> f64 {
+        self.cpu_hz() as f64 / self.frame_cycles() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom;
+
+    fn header(flag9: u8) -> Header {
+        let bytes = [
+            0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, flag9, 0, 0, 0, 0, 0, 0,
+        ];
+        rom::parse_header(&bytes).ok().unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_detect() {
+        use crate::rom_database::RomDatabase;
+        assert_eq!(Region::detect(&header(0), None), Region::Ntsc);
+        assert_eq!(Region::detect(&header(1), None), Region::Pal);
+
+        let database = RomDatabase::parse(
+            r#"
+            [[rom]]
+            name = "Test (Europe)"
+            crc32 = 1
+            mapper = 0
+            region = "pal"
+            prg_rom_banks = 1
+            character_rom_banks = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Region::detect(&header(0), database.find(1)), Region::Pal);
+    }
+
+    #[test]
+    fn test_timing() {
+        assert_eq!(Region::Ntsc.cpu_hz(), 1_789_773);
+        assert_eq!(Region::Ntsc.frames_per_second().round(), 60.0);
+        assert_eq!(Region::Pal.frames_per_second().round(), 50.0);
+        assert_eq!(Region::Ntsc.scanline_cycles(262), NTSC_FRAME_CYCLES - 1);
+        assert_eq!(Region::Pal.scanline_cycles(312), PAL_FRAME_CYCLES - 1);
+    }
+}
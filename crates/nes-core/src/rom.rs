@@ -0,0 +1,575 @@
+use crate::prelude::*;
+use core::fmt;
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, prelude::*};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(rename_all = "lowercase"))]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    /// Every nametable is the first 1 KB of the VRAM, which mappers like the MMC1 can
+    /// switch to.
+    #[cfg_attr(feature = "toml", serde(skip))]
+    OneScreenLower,
+    /// Every nametable is the second 1 KB of the VRAM.
+    #[cfg_attr(feature = "toml", serde(skip))]
+    OneScreenUpper,
+    /// The cartridge has its own VRAM for the other two nametables.
+    #[cfg_attr(feature = "toml", serde(skip))]
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TvSystem {
+    NTSC,
+    PAL,
+    DualCompatible,
+    /// The Famicom clones sold in Russia, which run at 50 frames a second like PAL,
+    /// but with a faster CPU. Only NES 2.0 headers can say this.
+    Dendy,
+}
+
+/// What kind of console the ROM was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the extended console types from byte 13 of an NES 2.0 header, like the
+    /// VT01 famiclones.
+    Extended(u8),
+}
+
+/// The header of an iNES or NES 2.0 file. The NES 2.0 fields have the values that
+/// iNES 1.0 implies when the header is iNES 1.0.
+#[derive(Debug)]
+pub struct Header {
+    pub prg_rom_bytes: u32,
+    /// The PRG ROM in 16 KB banks. An NES 2.0 ROM that isn't a multiple of the bank
+    /// size is rounded down, so go by prg_rom_bytes.
+    pub prg_rom_banks: u16,
+    pub character_rom_bytes: u32,
+    pub character_rom_banks: u16,
+    pub mirroring: Mirroring,
+    pub persistent_memory: bool,
+    pub has_trainer: bool,
+    pub four_screen_vram: bool,
+    /// 8 bits in iNES 1.0, and 12 bits in NES 2.0.
+    pub mapping_number: u16,
+    /// The variant of the mapper, only in NES 2.0.
+    pub submapper: u8,
+    pub vs_unisystem: bool,
+    pub playchoice_10: bool,
+    pub console_type: ConsoleType,
+    pub nes_2_0: bool,
+    /// The volatile PRG RAM. In iNES 1.0 this is at least 8 KB, and the battery flag
+    /// says whether it is kept.
+    pub prg_ram_size: u32,
+    /// The battery-backed PRG RAM, only in NES 2.0.
+    pub prg_nvram_size: u32,
+    /// In iNES 1.0, a board without CHR ROM has 8 KB of CHR RAM.
+    pub chr_ram_size: u32,
+    pub chr_nvram_size: u32,
+    pub tv_system_rarely_used: TvSystem,
+    pub tv_system: TvSystem,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            prg_rom_bytes: 0,
+            prg_rom_banks: 0,
+            character_rom_bytes: 0,
+            character_rom_banks: 0,
+            mirroring: Mirroring::Horizontal,
+            persistent_memory: false,
+            has_trainer: false,
+            four_screen_vram: false,
+            mapping_number: 0,
+            submapper: 0,
+            vs_unisystem: false,
+            playchoice_10: false,
+            console_type: ConsoleType::Nes,
+            nes_2_0: false,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            tv_system_rarely_used: TvSystem::NTSC,
+            tv_system: TvSystem::NTSC,
+        }
+    }
+}
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+/// Where the trainer is loaded, in the PRG RAM.
+pub const TRAINER_ADDRESS: u16 = 0x7000;
+
+#[derive(Debug)]
+pub enum ROMLoadError {
+    #[cfg(feature = "std")]
+    IoError(io::Error),
+    Message(&'static str),
+    /// The board override file next to the ROM couldn't be used.
+    BoardOverride(String),
+}
+
+impl fmt::Display for ROMLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            ROMLoadError::IoError(error) => write!(f, "{}", error),
+            ROMLoadError::Message(message) => write!(f, "{}", message),
+            ROMLoadError::BoardOverride(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for ROMLoadError {
+    fn from(error: io::Error) -> Self {
+        ROMLoadError::IoError(error)
+    }
+}
+
+impl From<&'static str> for ROMLoadError {
+    fn from(string: &'static str) -> Self {
+        ROMLoadError::Message(string)
+    }
+}
+
+struct Byte {
+    value: u8,
+}
+
+impl Byte {
+    /// Get the bit where 0 is the least significant bit, and 7 is the most
+    fn bit(&self, n: u8) -> bool {
+        (0b0000_0001 << n) & self.value != 0
+    }
+}
+
+pub struct ROM {
+    pub header: Header,
+    pub program_rom: Vec<u8>,
+    pub character_rom: Vec<u8>,
+    // http://forums.nesdev.com/viewtopic.php?t=3657
+    // NES trainers are 512 bytes of code which is loaded into $7000 before the game
+    // starts. Famicom copiers used them to hold code to translate mapper writes into
+    // the copier's own mapper system. The games were altered so that instead of
+    // writing to the original mapper, they jumped to a subroutine in the trainer.
+    // They probably aren't necessary to emulate today now that we have non-hacked
+    // ROM dumps of all games. There might be some old hacks which use them because
+    // the hackers couldn't allocate static space in the ROM for their new code. The
+    // mappers load it into their PRG RAM at TRAINER_ADDRESS.
+    pub trainer: Option<Vec<u8>>,
+}
+
+impl ROM {
+    /// https://wiki.nesdev.com/w/index.php/INES
+    #[cfg(feature = "std")]
+    pub fn load_ines_file(path: &Path) -> Result<ROM, ROMLoadError> {
+        let mut file = File::open(path)?;
+        let header_bytes = read_bytes(&mut file, HEADER_SIZE)?;
+        let file_size = file.metadata()?.len() as usize;
+        let header = parse_file_header(&header_bytes[..], file_size)?;
+
+        let trainer = if header.has_trainer {
+            Some(read_bytes(&mut file, TRAINER_SIZE)?)
+        } else {
+            None
+        };
+
+        let program_rom = read_bytes(&mut file, header.prg_rom_bytes as usize)?;
+        let character_rom = read_bytes(&mut file, header.character_rom_bytes as usize)?;
+
+        let _play_choice_inst_rom = if header.playchoice_10 {
+            eprintln!("Found play choice data in the NES file, this is not supported.");
+            Some(read_bytes(&mut file, 8192))
+        } else {
+            None
+        };
+
+        let _play_choice_prom = if header.playchoice_10 {
+            // (16 bytes Data, 16 bytes CounterOut)
+            Some(read_bytes(&mut file, 32))
+        } else {
+            None
+        };
+
+        // Some ROM-Images additionally contain a 128-byte (or sometimes 127-byte) title
+        // at the end of the file.
+        let mut title = Vec::new();
+        file.read_to_end(&mut title)?;
+        if !title.is_empty() {
+            eprintln!("Found some information at the end of the file.");
+        }
+
+        Ok(ROM {
+            program_rom,
+            character_rom,
+            header,
+            trainer,
+        })
+    }
+}
+
+/// Parse the 16 byte header at the start of the file. Headers marked as NES 2.0 are
+/// parsed as NES 2.0, see parse_file_header to check that against the file's size.
+pub fn parse_header(header: &[u8]) -> Result<Header, ROMLoadError> {
+    let nes_2_0 = header.len() >= HEADER_SIZE && header[7] & 0b0000_1100 == 0b0000_1000;
+    parse_header_as(header, nes_2_0)
+}
+
+/// Parse the header of a file of the given size. Some old dumps have junk in bytes
+/// 7-15 that looks like the NES 2.0 marker, so like the other emulators, the header is
+/// only treated as NES 2.0 if the ROM sizes it gives fit in the file. Otherwise it
+/// falls back to iNES 1.0.
+/// https://wiki.nesdev.com/w/index.php/NES_2.0#Identifying_NES_2.0_format
+pub fn parse_file_header(
+    header: &[u8],
+    file_size: usize,
+) -> Result<Header, ROMLoadError> {
+    let parsed = parse_header(header)?;
+    let rom_size = parsed.prg_rom_bytes as u64 + parsed.character_rom_bytes as u64;
+    if parsed.nes_2_0 && HEADER_SIZE as u64 + rom_size > file_size as u64 {
+        return parse_header_as(header, false);
+    }
+    Ok(parsed)
+}
+
+fn parse_header_as(header: &[u8], nes_2_0: bool) -> Result<Header, ROMLoadError> {
+    if header.len() < HEADER_SIZE {
+        return Err("The file is too short to contain an iNES header.".into());
+    }
+    // 0-3: Constant $4E $45 $53 $1A ("NES" followed by MS-DOS end-of-file)
+    if header[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+        return Err(ROMLoadError::Message(
+            "This does not appear to be an NES file.",
+        ));
+    }
+
+    let flag6 = Byte { value: header[6] };
+    let flag7 = Byte { value: header[7] };
+    let flag8 = Byte { value: header[8] };
+    let flag9 = Byte { value: header[9] };
+    let flag10 = Byte { value: header[10] };
+
+    // 6: Flags 6 - Mapper, mirroring, battery, trainer
+    //
+    // 76543210
+    // ||||||||
+    // |||||||+- Mirroring: 0: horizontal (vertical arrangement) (CIRAM A10 = PPU A11)
+    // |||||||              1: vertical (horizontal arrangement) (CIRAM A10 = PPU A10)
+    // ||||||+-- 1: Cartridge contains battery-backed PRG RAM ($6000-7FFF) or other persistent memory
+    // |||||+--- 1: 512-byte trainer at $7000-$71FF (stored before PRG data)
+    // ||||+---- 1: Ignore mirroring control or above mirroring bit; instead provide four-screen VRAM
+    // ++++----- Lower nybble of mapper number
+    let mirroring = if flag6.bit(0) {
+        Mirroring::Vertical
+    } else {
+        Mirroring::Horizontal
+    };
+    let persistent_memory = flag6.bit(1);
+    let has_trainer = flag6.bit(2);
+    let four_screen_vram = flag6.bit(3);
+    let mapping_number_lower = flag6.value >> 4; // Move 0bXXXX_0000 to 0b0000_XXXX
+
+    // 7: Flags 7 - Mapper, VS/Playchoice, NES 2.0
+    // 76543210
+    // ||||||||
+    // |||||||+- VS Unisystem
+    // ||||||+-- PlayChoice-10 (8KB of Hint Screen data stored after CHR data)
+    // ||||++--- If equal to 2, flags 8-15 are in NES 2.0 format
+    // ++++----- Upper nybble of mapper number
+    let mapping_number_upper = flag7.value & 0b1111_0000; // Mask the upper bits.
+    let mapping_number = (mapping_number_upper | mapping_number_lower) as u16;
+
+    if nes_2_0 {
+        return parse_nes_2_0(
+            header,
+            Header {
+                mirroring,
+                persistent_memory,
+                has_trainer,
+                four_screen_vram,
+                mapping_number,
+                nes_2_0,
+                ..Header::default()
+            },
+        );
+    }
+
+    let vs_unisystem = flag7.bit(0);
+    let playchoice_10 = flag7.bit(1);
+    let console_type = if vs_unisystem {
+        ConsoleType::VsSystem
+    } else if playchoice_10 {
+        ConsoleType::Playchoice10
+    } else {
+        ConsoleType::Nes
+    };
+
+    // 4: Size of PRG ROM in 16 KB units
+    let prg_rom_banks = header[4] as u16;
+    let prg_rom_bytes: u32 = prg_rom_banks as u32 * 16 * 1024;
+
+    // 5: Size of CHR ROM in 8 KB units (Value 0 means the board uses CHR RAM)
+    let character_rom_banks = header[5] as u16;
+    let character_rom_bytes: u32 = character_rom_banks as u32 * 8 * 1024;
+
+    // 8: Flags 8 - PRG-RAM size (rarely used extension)
+    let prg_ram_size = flag8.value.max(1) as u32 * 8 * 1024;
+
+    // 9: Flags 9 - TV system (rarely used extension)
+    // 76543210
+    // ||||||||
+    // |||||||+- TV system (0: NTSC; 1: PAL)
+    // +++++++-- Reserved, set to zero
+    let tv_system_rarely_used = if flag9.bit(0) {
+        TvSystem::PAL
+    } else {
+        TvSystem::NTSC
+    };
+
+    // 10: Flags 10 - TV system, PRG-RAM presence (unofficial, rarely used extension)
+    // 76543210
+    // ||  ||
+    // ||  ++- TV system (0: NTSC; 2: PAL; 1/3: dual compatible)
+    // |+----- PRG RAM ($6000-$7FFF) (0: present; 1: not present)
+    // +------ 0: Board has no bus conflicts; 1: Board has bus conflicts
+    let tv_system = match flag10.value & 0b0000_1100 >> 2 {
+        0 => TvSystem::NTSC,
+        2 => TvSystem::PAL,
+        _ => TvSystem::DualCompatible,
+    };
+
+    // 11-15: Unused padding (should be filled with zero, but some rippers put their name across bytes 7-15)
+
+    Ok(Header {
+        prg_rom_banks,
+        prg_rom_bytes,
+        character_rom_banks,
+        character_rom_bytes,
+        mirroring,
+        persistent_memory,
+        has_trainer,
+        four_screen_vram,
+        mapping_number,
+        submapper: 0,
+        vs_unisystem,
+        playchoice_10,
+        console_type,
+        nes_2_0,
+        prg_ram_size,
+        prg_nvram_size: 0,
+        chr_ram_size: if character_rom_banks == 0 { 0x2000 } else { 0 },
+        chr_nvram_size: 0,
+        tv_system_rarely_used,
+        tv_system,
+    })
+}
+
+/// Parse the bytes 4, 5, and 7-15 of an NES 2.0 header. The rest is shared with iNES
+/// 1.0 and already in the header.
+/// https://wiki.nesdev.com/w/index.php/NES_2.0
+fn parse_nes_2_0(bytes: &[u8], header: Header) -> Result<Header, ROMLoadError> {
+    // 7: Flags 7 - Console type, NES 2.0, mapper
+    // 76543210
+    // ||||||||
+    // ||||||++- Console type (0: NES/Famicom; 1: Vs. System; 2: PlayChoice-10;
+    // ||||||                  3: Extended, see byte 13)
+    // ||||++--- NES 2.0 identifier
+    // ++++----- Mapper number D4..D7
+    let console_type = match bytes[7] & 0b11 {
+        0 => ConsoleType::Nes,
+        1 => ConsoleType::VsSystem,
+        2 => ConsoleType::Playchoice10,
+        _ => ConsoleType::Extended(bytes[13] & 0b1111),
+    };
+
+    // 8: Mapper MSB/Submapper
+    // 76543210
+    // ||||||||
+    // ||||++++- Mapper number D8..D11
+    // ++++----- Submapper number
+    let mapping_number = header.mapping_number | ((bytes[8] & 0b1111) as u16) << 8;
+    let submapper = bytes[8] >> 4;
+
+    // 4, 5, 9: PRG ROM size LSB, CHR ROM size LSB, and their MSBs
+    // 76543210
+    // ||||||||
+    // ||||++++- PRG ROM size MSB
+    // ++++----- CHR ROM size MSB
+    let prg_rom_bytes = rom_size(bytes[4], bytes[9] & 0b1111, 16 * 1024)
+        .ok_or("The PRG ROM size in the NES 2.0 header is too large.")?;
+    let character_rom_bytes = rom_size(bytes[5], bytes[9] >> 4, 8 * 1024)
+        .ok_or("The CHR ROM size in the NES 2.0 header is too large.")?;
+
+    // 10: PRG-RAM/EEPROM size, 11: CHR-RAM size
+    // 76543210
+    // ||||||||
+    // ||||++++- Volatile RAM shift count
+    // ++++----- Non-volatile RAM shift count
+    // The size is 64 << shift, or none if the shift is 0.
+    let shift_size = |shift: u8| match shift {
+        0 => 0,
+        shift => 64u32 << shift.min(20),
+    };
+
+    // 12: CPU/PPU Timing
+    // 76543210
+    // ||||||||
+    // ||||||++- 0: NTSC NES; 1: PAL NES; 2: Multiple-region; 3: Dendy
+    let tv_system = match bytes[12] & 0b11 {
+        0 => TvSystem::NTSC,
+        1 => TvSystem::PAL,
+        2 => TvSystem::DualCompatible,
+        _ => TvSystem::Dendy,
+    };
+
+    Ok(Header {
+        prg_rom_bytes,
+        prg_rom_banks: (prg_rom_bytes / (16 * 1024)).min(u16::MAX as u32) as u16,
+        character_rom_bytes,
+        character_rom_banks: (character_rom_bytes / (8 * 1024)).min(u16::MAX as u32)
+            as u16,
+        mapping_number,
+        submapper,
+        vs_unisystem: console_type == ConsoleType::VsSystem,
+        playchoice_10: console_type == ConsoleType::Playchoice10,
+        console_type,
+        prg_ram_size: shift_size(bytes[10] & 0b1111),
+        prg_nvram_size: shift_size(bytes[10] >> 4),
+        chr_ram_size: shift_size(bytes[11] & 0b1111),
+        chr_nvram_size: shift_size(bytes[11] >> 4),
+        // Byte 9 isn't the TV system in NES 2.0, so both have the timing.
+        tv_system_rarely_used: tv_system,
+        tv_system,
+        ..header
+    })
+}
+
+/// The NES 2.0 ROM sizes are a 12 bit count of units. When the upper 4 bits are all
+/// set, the lower byte is an exponent and a multiplier instead, for sizes that aren't
+/// a multiple of the unit: EEEEEEMM is 2^E * (MM * 2 + 1) bytes.
+fn rom_size(lsb: u8, msb: u8, unit: u32) -> Option<u32> {
+    if msb == 0b1111 {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as u32 * 2 + 1;
+        2u32.checked_pow(exponent)?.checked_mul(multiplier)
+    } else {
+        (((msb as u32) << 8) | lsb as u32).checked_mul(unit)
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_bytes(file: &mut File, size: usize) -> Result<Vec<u8>, ROMLoadError> {
+    let mut vec = Vec::new();
+    let read_bytes = file.take(size as u64).read_to_end(&mut vec)?;
+    if read_bytes != size {
+        return Err("The file is shorter than its header says.".into());
+    }
+    Ok(vec)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An NES 2.0 header for mapper 321.3, with 32 KB of PRG ROM, 8 KB of CHR ROM, 8 KB
+    /// of battery-backed PRG RAM, and 8 KB of CHR RAM.
+    const NES_2_0: [u8; HEADER_SIZE] = [
+        0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x12, 0x48, 0x31, 0x00, 0x70, 0x07, 3, 0, 0, 0,
+    ];
+    const FILE_SIZE: usize = HEADER_SIZE + 0x8000 + 0x2000;
+
+    #[test]
+    fn test_nes_2_0() {
+        let header = parse_file_header(&NES_2_0, FILE_SIZE).unwrap();
+        assert!(header.nes_2_0);
+        assert_eq!(header.mapping_number, 321);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_rom_bytes, 0x8000);
+        assert_eq!(header.prg_rom_banks, 2);
+        assert_eq!(header.character_rom_bytes, 0x2000);
+        assert!(header.persistent_memory);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.chr_nvram_size, 0);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(header.tv_system, TvSystem::Dendy);
+
+        let mut bytes = NES_2_0;
+        // Vs. System, and 12 bits of PRG ROM banks.
+        bytes[7] = 0x49;
+        bytes[9] = 0x01;
+        let header = parse_header(&bytes).unwrap();
+        assert_eq!(header.console_type, ConsoleType::VsSystem);
+        assert!(header.vs_unisystem);
+        assert_eq!(header.prg_rom_banks, 0x102);
+        // The exponent and multiplier form, for 2^10 * 3 bytes.
+        bytes[4] = 10 << 2 | 1;
+        bytes[9] = 0x0f;
+        assert_eq!(parse_header(&bytes).unwrap().prg_rom_bytes, 3072);
+        bytes[4] = 63 << 2;
+        assert!(parse_header(&bytes).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_truncated_file() {
+        let dir = std::env::temp_dir().join(format!("nes-rom-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("truncated.nes");
+        // An iNES 1.0 header for 16 KB of PRG ROM, with only half of it in the file.
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0];
+        bytes.resize(HEADER_SIZE + 0x2000, 0);
+        std::fs::write(&path, &bytes).unwrap();
+        match ROM::load_ines_file(&path) {
+            Ok(_) => panic!("The ROM loaded."),
+            Err(error) => {
+                assert_eq!(
+                    error.to_string(),
+                    "The file is shorter than its header says."
+                )
+            }
+        }
+
+        std::fs::write(&path, &bytes[..4]).unwrap();
+        assert!(ROM::load_ines_file(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ines_fallback() {
+        // The ROM sizes don't fit in the file, so the marker is junk.
+        let header = parse_file_header(&NES_2_0, FILE_SIZE - 1).unwrap();
+        assert!(!header.nes_2_0);
+        assert_eq!(header.mapping_number, 0x41);
+        assert_eq!(header.submapper, 0);
+        // Byte 8 is read as the iNES 1.0 count of 8 KB PRG RAM banks.
+        assert_eq!(header.prg_ram_size, 0x31 * 0x2000);
+        assert_eq!(header.prg_nvram_size, 0);
+        // The board has CHR ROM, so no CHR RAM.
+        assert_eq!(header.chr_ram_size, 0);
+
+        let mut bytes = NES_2_0;
+        bytes[5] = 0;
+        bytes[7] = 0;
+        let header = parse_header(&bytes).unwrap();
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.tv_system_rarely_used, TvSystem::NTSC);
+    }
+}
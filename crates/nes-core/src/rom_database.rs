@@ -0,0 +1,161 @@
+use crate::prelude::*;
+use crate::region::Region;
+use crate::rom::Mirroring;
+use crate::rom_info::sha1_hex;
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+#[cfg(feature = "toml")]
+use std::fs;
+#[cfg(feature = "toml")]
+use std::path::Path;
+
+/// A database of known good dumps, used to check and repair the iNES headers, and to
+/// override them when a cartridge is loaded. The entries are matched by the CRC32 of
+/// the PRG and CHR ROM, leaving out the header, which is how NesCartDB and the
+/// No-Intro sets identify a dump. The SHA-1 is optional, and tells apart the dumps
+/// that share a CRC32.
+///
+/// [[rom]]
+/// name = "Example Game (USA)"
+/// crc32 = 0x1234abcd
+/// sha1 = "0123456789abcdef0123456789abcdef01234567"
+/// mapper = 1
+/// mirroring = "vertical"
+/// region = "ntsc"
+/// prg_rom_banks = 8
+/// character_rom_banks = 0
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct RomDatabase {
+    #[cfg_attr(feature = "toml", serde(default, rename = "rom"))]
+    pub entries: Vec<RomEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct RomEntry {
+    pub name: String,
+    pub crc32: u32,
+    /// In lowercase hex.
+    pub sha1: Option<String>,
+    pub mapper: u8,
+    /// Boards with mapper controlled mirroring leave this out.
+    pub mirroring: Option<Mirroring>,
+    /// Most dumps don't set the region in the header, so it can be given here.
+    pub region: Option<Region>,
+    /// In 16 KB units.
+    pub prg_rom_banks: u8,
+    /// In 8 KB units, 0 means the board uses CHR RAM.
+    pub character_rom_banks: u8,
+}
+
+impl RomDatabase {
+    #[cfg(feature = "toml")]
+    pub fn load(path: &Path) -> Result<RomDatabase, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+        RomDatabase::parse(&text)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn parse(text: &str) -> Result<RomDatabase, String> {
+        toml::from_str(text).map_err(|error| error.to_string())
+    }
+
+    /// The database that is built into the emulator, see rom_database.toml.
+    #[cfg(feature = "toml")]
+    pub fn embedded() -> RomDatabase {
+        RomDatabase::parse(include_str!("rom_database.toml"))
+            .expect("The embedded ROM database parses.")
+    }
+
+    /// The embedded database needs the toml feature, so without it this is empty.
+    #[cfg(not(feature = "toml"))]
+    pub fn embedded() -> RomDatabase {
+        RomDatabase::default()
+    }
+
+    /// Add the other database's entries after this one's, so that this one's are found
+    /// first.
+    pub fn merge(&mut self, other: RomDatabase) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Find a dump by the CRC32 of its PRG and CHR ROM.
+    pub fn find(&self, crc32: u32) -> Option<&RomEntry> {
+        self.entries.iter().find(|entry| entry.crc32 == crc32)
+    }
+
+    /// Find a dump by the CRC32 and SHA-1 of its PRG and CHR ROM. The SHA-1 is only
+    /// checked for the entries that have one.
+    pub fn identify(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&RomEntry> {
+        let sha1 = sha1_hex(sha1);
+        self.entries.iter().find(|entry| {
+            entry.crc32 == crc32
+                && entry
+                    .sha1
+                    .as_ref()
+                    .is_none_or(|entry_sha1| entry_sha1.eq_ignore_ascii_case(&sha1))
+        })
+    }
+}
+
+#[cfg(all(test, feature = "toml"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let database = RomDatabase::parse(
+            r#"
+            [[rom]]
+            name = "Test"
+            crc32 = 0xcafef00d
+            mapper = 1
+            mirroring = "vertical"
+            prg_rom_banks = 2
+            character_rom_banks = 1
+            "#,
+        )
+        .unwrap();
+        let entry = database.find(0xcafef00d).unwrap();
+        assert_eq!(entry.name, "Test");
+        assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+        assert_eq!(database.find(0), None);
+    }
+
+    #[test]
+    fn test_identify() {
+        let sha1 = crate::rom_info::sha1(b"ROM");
+        let mut database = RomDatabase::parse(&format!(
+            r#"
+            [[rom]]
+            name = "Bad dump"
+            crc32 = 1
+            sha1 = "{}"
+            mapper = 0
+            prg_rom_banks = 1
+            character_rom_banks = 0
+
+            [[rom]]
+            name = "Good dump"
+            crc32 = 1
+            mapper = 0
+            prg_rom_banks = 1
+            character_rom_banks = 0
+            "#,
+            "0".repeat(40)
+        ))
+        .unwrap();
+        assert_eq!(database.identify(1, &sha1).unwrap().name, "Good dump");
+        assert_eq!(database.identify(1, &[0; 20]).unwrap().name, "Bad dump");
+
+        // The user's entries are found before the embedded ones.
+        database.merge(RomDatabase::embedded());
+        let entry = database.find(0x3337_ec46).unwrap();
+        assert_eq!(entry.name, "Super Mario Bros. (World)");
+        assert_eq!(entry.region, Some(Region::Ntsc));
+    }
+}
@@ -0,0 +1,390 @@
+// Inspect iNES files for problems, like truncated dumps, and the headers that old
+// tools filled with junk. The problems that can be fixed are written to a corrected
+// copy, with the help of the ROM database.
+// https://wiki.nesdev.com/w/index.php/INES
+
+use crate::prelude::*;
+use crate::rom::{self, Header, Mirroring, HEADER_SIZE, TRAINER_SIZE};
+use crate::rom_database::{RomDatabase, RomEntry};
+use core::fmt;
+
+/// The PlayChoice-10 hint screen, and the PROM that follows it.
+const PLAYCHOICE_SIZE: usize = 8192 + 32;
+
+/// Some dumps have a title of 127 or 128 bytes added at the end of the file.
+const TITLE_SIZES: [usize; 2] = [127, 128];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The file is shorter than the header says it should be.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// There's data at the end of the file that isn't a title.
+    ExtraData {
+        bytes: usize,
+    },
+    /// Bytes 7-15 were filled in by an old tool, e.g. with "DiskDude!", which makes
+    /// the upper bits of the mapper number junk.
+    DirtyHeader,
+    MapperMismatch {
+        header: u16,
+        database: u8,
+    },
+    MirroringMismatch {
+        header: Mirroring,
+        database: Mirroring,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Truncated { expected, actual } => write!(
+                f,
+                "The file is {} bytes, but the header says it should be {} bytes.",
+                actual, expected
+            ),
+            Problem::ExtraData { bytes } => {
+                write!(f, "There are {} extra bytes at the end of the file.", bytes)
+            }
+            Problem::DirtyHeader => {
+                write!(f, "The unused bytes of the header contain junk.")
+            }
+            Problem::MapperMismatch { header, database } => write!(
+                f,
+                "The header uses mapper {}, but the database says {}.",
+                header, database
+            ),
+            Problem::MirroringMismatch { header, database } => write!(
+                f,
+                "The header uses {:?} mirroring, but the database says {:?}.",
+                header, database
+            ),
+        }
+    }
+}
+
+pub struct RomInfo<'a> {
+    /// The header with the junk bytes cleaned out, if it was dirty.
+    pub header: Header,
+    pub file_crc32: u32,
+    /// The CRC32 of the PRG and CHR ROM, which is what the database uses.
+    pub rom_crc32: u32,
+    /// The SHA-1 of the PRG and CHR ROM, to tell apart the dumps with the same CRC32.
+    pub rom_sha1: [u8; 20],
+    pub database_entry: Option<&'a RomEntry>,
+    pub problems: Vec<Problem>,
+}
+
+impl<'a> RomInfo<'a> {
+    pub fn inspect(
+        bytes: &[u8],
+        database: Option<&'a RomDatabase>,
+    ) -> Result<RomInfo<'a>, String> {
+        let mut problems = Vec::new();
+        let header_bytes = match clean_header(bytes) {
+            Some(header_bytes) => {
+                problems.push(Problem::DirtyHeader);
+                header_bytes
+            }
+            None => bytes[..HEADER_SIZE.min(bytes.len())].to_vec(),
+        };
+        let header = rom::parse_file_header(&header_bytes, bytes.len())
+            .map_err(|error| error.to_string())?;
+
+        let trainer_size = if header.has_trainer { TRAINER_SIZE } else { 0 };
+        let playchoice_size = if header.playchoice_10 {
+            PLAYCHOICE_SIZE
+        } else {
+            0
+        };
+        let rom_start = HEADER_SIZE + trainer_size;
+        let rom_end = rom_start
+            + header.prg_rom_bytes as usize
+            + header.character_rom_bytes as usize;
+        let expected = rom_end + playchoice_size;
+        if bytes.len() < expected {
+            problems.push(Problem::Truncated {
+                expected,
+                actual: bytes.len(),
+            });
+        } else if bytes.len() > expected {
+            let extra = bytes.len() - expected;
+            if !TITLE_SIZES.contains(&extra) {
+                problems.push(Problem::ExtraData { bytes: extra });
+            }
+        }
+
+        let rom_bytes = &bytes[rom_start.min(bytes.len())..rom_end.min(bytes.len())];
+        let rom_crc32 = crc32(rom_bytes);
+        let rom_sha1 = sha1(rom_bytes);
+        let database_entry =
+            database.and_then(|database| database.identify(rom_crc32, &rom_sha1));
+        if let Some(entry) = database_entry {
+            if entry.mapper as u16 != header.mapping_number {
+                problems.push(Problem::MapperMismatch {
+                    header: header.mapping_number,
+                    database: entry.mapper,
+                });
+            }
+            match entry.mirroring {
+                Some(mirroring)
+                    if !header.four_screen_vram && mirroring != header.mirroring =>
+                {
+                    problems.push(Problem::MirroringMismatch {
+                        header: header.mirroring,
+                        database: mirroring,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(RomInfo {
+            header,
+            file_crc32: crc32(bytes),
+            rom_crc32,
+            rom_sha1,
+            database_entry,
+            problems,
+        })
+    }
+
+    /// Write a corrected copy of the file, or explain why it can't be fixed.
+    pub fn fix(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let mut fixed = bytes.to_vec();
+        for problem in &self.problems {
+            match *problem {
+                Problem::Truncated { .. } => {
+                    return Err(format!("Unable to fix the ROM. {}", problem));
+                }
+                Problem::ExtraData { bytes } => {
+                    fixed.truncate(fixed.len() - bytes);
+                }
+                Problem::DirtyHeader => {
+                    let header = clean_header(&fixed).expect("The header is dirty.");
+                    fixed[..HEADER_SIZE].copy_from_slice(&header);
+                }
+                Problem::MapperMismatch { database, .. } => {
+                    fixed[6] = (fixed[6] & 0b0000_1111) | (database << 4);
+                    fixed[7] = (fixed[7] & 0b0000_1111) | (database & 0b1111_0000);
+                    if fixed[7] & 0b0000_1100 == 0b0000_1000 {
+                        // The upper bits of an NES 2.0 mapper number.
+                        fixed[8] &= 0b1111_0000;
+                    }
+                }
+                Problem::MirroringMismatch { database, .. } => {
+                    fixed[6] = (fixed[6] & !1) | (database == Mirroring::Vertical) as u8;
+                }
+            }
+        }
+        Ok(fixed)
+    }
+}
+
+/// Return the header with bytes 7-15 zeroed, if it's dirty. A clean iNES header has
+/// bytes 12-15 zeroed, unless it's NES 2.0.
+/// https://wiki.nesdev.com/w/index.php/INES#Variant_comparison
+fn clean_header(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+    let is_nes_2_0 = bytes[7] & 0b0000_1100 == 0b0000_1000;
+    if is_nes_2_0 || bytes[12..HEADER_SIZE].iter().all(|&byte| byte == 0) {
+        return None;
+    }
+    let mut header = bytes[..HEADER_SIZE].to_vec();
+    for byte in &mut header[7..] {
+        *byte = 0;
+    }
+    Some(header)
+}
+
+/// The CRC32 used by zip files, and by the ROM databases.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// The SHA-1 used by the No-Intro ROM sets.
+/// https://en.wikipedia.org/wiki/SHA-1
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    // Pad to a whole number of 64 byte blocks, ending with the length in bits.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, chunk) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3]
+                ^ words[index - 8]
+                ^ words[index - 14]
+                ^ words[index - 16])
+                .rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, &word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Format a SHA-1 as lowercase hex, like the databases write it.
+pub fn sha1_hex(digest: &[u8; 20]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A mapper 0 ROM with one bank of PRG and CHR ROM, and horizontal mirroring.
+    fn rom_bytes() -> Vec<u8> {
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend((0..16 * 1024 + 8 * 1024).map(|index| index as u8));
+        bytes
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            sha1_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            sha1_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        // More than one block.
+        assert_eq!(
+            sha1_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_clean_rom() {
+        let bytes = rom_bytes();
+        let info = RomInfo::inspect(&bytes, None).unwrap();
+        assert_eq!(info.problems, vec![]);
+        assert_eq!(info.rom_crc32, crc32(&bytes[HEADER_SIZE..]));
+        assert_eq!(info.fix(&bytes).unwrap(), bytes);
+
+        // A title at the end is allowed.
+        let mut bytes = rom_bytes();
+        bytes.extend(&[0; 128]);
+        assert_eq!(RomInfo::inspect(&bytes, None).unwrap().problems, vec![]);
+    }
+
+    #[test]
+    fn test_dirty_header() {
+        let mut bytes = rom_bytes();
+        bytes[7..HEADER_SIZE].copy_from_slice(b"DiskDude!");
+        bytes.extend(&[0xff; 3]);
+        let info = RomInfo::inspect(&bytes, None).unwrap();
+        assert_eq!(info.header.mapping_number, 0);
+        assert_eq!(
+            info.problems,
+            vec![Problem::DirtyHeader, Problem::ExtraData { bytes: 3 }]
+        );
+        assert_eq!(info.fix(&bytes).unwrap(), rom_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_database_mismatch() {
+        let bytes = rom_bytes();
+        let database = RomDatabase::parse(&format!(
+            "
+            [[rom]]
+            name = \"Test\"
+            crc32 = {}
+            mapper = 18
+            mirroring = \"vertical\"
+            prg_rom_banks = 1
+            character_rom_banks = 1
+            ",
+            crc32(&bytes[HEADER_SIZE..])
+        ))
+        .unwrap();
+        let info = RomInfo::inspect(&bytes, Some(&database)).unwrap();
+        assert_eq!(info.database_entry.unwrap().name, "Test");
+        assert_eq!(info.problems.len(), 2);
+
+        let fixed = info.fix(&bytes).unwrap();
+        let info = RomInfo::inspect(&fixed, Some(&database)).unwrap();
+        assert_eq!(info.problems, vec![]);
+        assert_eq!(info.header.mapping_number, 18);
+        assert_eq!(info.header.mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut bytes = rom_bytes();
+        bytes.truncate(1000);
+        let info = RomInfo::inspect(&bytes, None).unwrap();
+        assert_eq!(
+            info.problems,
+            vec![Problem::Truncated {
+                expected: HEADER_SIZE + 16 * 1024 + 8 * 1024,
+                actual: 1000
+            }]
+        );
+        assert!(info.fix(&bytes).is_err());
+    }
+}
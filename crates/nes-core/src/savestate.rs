@@ -0,0 +1,574 @@
+//! The savestate file, which is a list of chunks after a small header, like the
+//! FCEUX savestates that savestate_import reads. The header is "NESS", a u16 version,
+//! and the CRC32 of the ROM the state was saved from, see rom_info::crc32. Each chunk
+//! has a 4 character name and a u32 size. Everything is little endian.
+//!
+//! Chunks that a reader doesn't know about are skipped, so tools can add their own.
+//! The debugging context is kept in optional chunks, so that a whole debugging
+//! session can be shared as a single file, and the one loading it can choose whether
+//! to restore it or ignore it, see DebugContext.
+//!
+//! The metadata chunks describe the state for a load-state picker: when it was saved,
+//! the frame, a note, and a small screenshot, see SavestateMetadata. The ROM is
+//! already in the header. list_savestates reads them for every file in a directory.
+//!
+//! The machine itself, the CPU and everything on the bus, is kept in the checkpoint
+//! chunk, see Checkpoint::write_chunk.
+use crate::ppu::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::prelude::*;
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"NESS";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 10;
+
+pub const CHEATS_CHUNK: [u8; 4] = *b"CHTS";
+pub const FROZEN_CHUNK: [u8; 4] = *b"FRZN";
+pub const BREAKPOINTS_CHUNK: [u8; 4] = *b"BRKP";
+pub const METADATA_CHUNK: [u8; 4] = *b"META";
+pub const THUMBNAIL_CHUNK: [u8; 4] = *b"THMB";
+pub const CHECKPOINT_CHUNK: [u8; 4] = *b"CHKP";
+
+/// The thumbnails are a quarter of the frame in each direction, which is 64x60.
+pub const THUMBNAIL_SCALE: usize = 4;
+
+/// The file extension of the savestates, for list_savestates.
+pub const EXTENSION: &str = "ness";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub name: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavestateFile {
+    pub rom_crc32: u32,
+    pub chunks: Vec<Chunk>,
+}
+
+impl SavestateFile {
+    pub fn new(rom_crc32: u32) -> SavestateFile {
+        SavestateFile {
+            rom_crc32,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn chunk(&self, name: [u8; 4]) -> Option<&[u8]> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.name == name)
+            .map(|chunk| &chunk.data[..])
+    }
+
+    /// Add the chunk, or replace the one with the same name.
+    pub fn set_chunk(&mut self, name: [u8; 4], data: Vec<u8>) {
+        match self.chunks.iter_mut().find(|chunk| chunk.name == name) {
+            Some(chunk) => chunk.data = data,
+            None => self.chunks.push(Chunk { name, data }),
+        }
+    }
+
+    pub fn remove_chunk(&mut self, name: [u8; 4]) {
+        self.chunks.retain(|chunk| chunk.name != name);
+    }
+
+    /// Refuse to load a state into a different game, which would only crash it.
+    pub fn check_rom(&self, rom_crc32: u32) -> Result<(), String> {
+        if self.rom_crc32 == rom_crc32 {
+            Ok(())
+        } else {
+            Err(format!(
+                "The savestate is for the ROM with the CRC32 {:08x}, not {:08x}.",
+                self.rom_crc32, rom_crc32
+            ))
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        for chunk in &self.chunks {
+            bytes.extend_from_slice(&chunk.name);
+            bytes.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&chunk.data);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SavestateFile, String> {
+        if bytes.len() < HEADER_SIZE || !bytes.starts_with(MAGIC) {
+            return Err("The savestate header is missing.".into());
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(format!(
+                "The savestate version {} is not supported.",
+                version
+            ));
+        }
+        let mut file = SavestateFile::new(read_u32(bytes, 6)?);
+
+        let mut offset = HEADER_SIZE;
+        while offset < bytes.len() {
+            let name: [u8; 4] = bytes
+                .get(offset..offset + 4)
+                .ok_or("A savestate chunk is truncated.")?
+                .try_into()
+                .unwrap();
+            let size = read_u32(bytes, offset + 4)? as usize;
+            let start = offset + 8;
+            let data = bytes
+                .get(start..start + size)
+                .ok_or("A savestate chunk is truncated.")?;
+            file.chunks.push(Chunk {
+                name,
+                data: data.to_vec(),
+            });
+            offset = start + size;
+        }
+        Ok(file)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|slice| u32::from_le_bytes(slice.try_into().unwrap()))
+        .ok_or_else(|| "The savestate is truncated.".into())
+}
+
+/// An address that a cheat holds at a value, no matter what the game writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrozenAddress {
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakpointInfo {
+    pub pc: u16,
+    pub enabled: bool,
+    /// A note about why the breakpoint is there, or the label it was set on.
+    pub label: String,
+}
+
+/// The debugging state that goes along with a savestate. None of it is part of the
+/// machine, so loading the state without it runs the game the same way.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugContext {
+    /// The active cheat codes, as they were typed in.
+    pub cheats: Vec<String>,
+    pub frozen: Vec<FrozenAddress>,
+    pub breakpoints: Vec<BreakpointInfo>,
+}
+
+impl DebugContext {
+    /// Write the context into the file's chunks, replacing any that were there.
+    /// Empty lists are left out.
+    pub fn save(&self, file: &mut SavestateFile) {
+        let mut set = |name, data: Vec<u8>| {
+            if data.is_empty() {
+                file.remove_chunk(name);
+            } else {
+                file.set_chunk(name, data);
+            }
+        };
+
+        let mut cheats = Vec::new();
+        for code in &self.cheats {
+            write_string(&mut cheats, code);
+        }
+        set(CHEATS_CHUNK, cheats);
+
+        let mut frozen = Vec::new();
+        for &FrozenAddress { address, value } in &self.frozen {
+            frozen.extend_from_slice(&address.to_le_bytes());
+            frozen.push(value);
+        }
+        set(FROZEN_CHUNK, frozen);
+
+        let mut breakpoints = Vec::new();
+        for breakpoint in &self.breakpoints {
+            breakpoints.extend_from_slice(&breakpoint.pc.to_le_bytes());
+            breakpoints.push(breakpoint.enabled as u8);
+            write_string(&mut breakpoints, &breakpoint.label);
+        }
+        set(BREAKPOINTS_CHUNK, breakpoints);
+    }
+
+    /// Read the context back out of the file. A file without any of the chunks has an
+    /// empty context.
+    pub fn load(file: &SavestateFile) -> Result<DebugContext, String> {
+        let mut context = DebugContext::default();
+
+        let mut reader = Reader(file.chunk(CHEATS_CHUNK).unwrap_or(&[]));
+        while !reader.is_empty() {
+            context.cheats.push(reader.string()?);
+        }
+
+        let mut reader = Reader(file.chunk(FROZEN_CHUNK).unwrap_or(&[]));
+        while !reader.is_empty() {
+            let address = reader.u16()?;
+            let value = reader.u8()?;
+            context.frozen.push(FrozenAddress { address, value });
+        }
+
+        let mut reader = Reader(file.chunk(BREAKPOINTS_CHUNK).unwrap_or(&[]));
+        while !reader.is_empty() {
+            let pc = reader.u16()?;
+            let enabled = reader.u8()? != 0;
+            let label = reader.string()?;
+            context
+                .breakpoints
+                .push(BreakpointInfo { pc, enabled, label });
+        }
+        Ok(context)
+    }
+}
+
+/// A small RGB screenshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    /// The pixels, 3 bytes each, row by row.
+    pub rgb: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Shrink a 256x240 RGB frame, see ppu::FRAME_BYTES, by averaging each block of
+    /// THUMBNAIL_SCALE pixels.
+    pub fn from_frame(frame: &[u8]) -> Thumbnail {
+        let width = FRAME_WIDTH / THUMBNAIL_SCALE;
+        let height = FRAME_HEIGHT / THUMBNAIL_SCALE;
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..3 {
+                    let mut total = 0;
+                    for block_y in 0..THUMBNAIL_SCALE {
+                        for block_x in 0..THUMBNAIL_SCALE {
+                            let frame_x = x * THUMBNAIL_SCALE + block_x;
+                            let frame_y = y * THUMBNAIL_SCALE + block_y;
+                            total += frame
+                                [(frame_y * FRAME_WIDTH + frame_x) * 3 + channel]
+                                as usize;
+                        }
+                    }
+                    rgb.push((total / (THUMBNAIL_SCALE * THUMBNAIL_SCALE)) as u8);
+                }
+            }
+        }
+        Thumbnail {
+            width: width as u16,
+            height: height as u16,
+            rgb,
+        }
+    }
+}
+
+/// What a load-state picker shows for a savestate. None of it is part of the machine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SavestateMetadata {
+    /// When the state was saved, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The frames that had run, see Emulator::run_frame.
+    pub frame: u64,
+    pub note: String,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+impl SavestateMetadata {
+    /// The metadata for a state that is being saved right now.
+    #[cfg(feature = "std")]
+    pub fn now(
+        frame: u64,
+        note: &str,
+        thumbnail: Option<Thumbnail>,
+    ) -> SavestateMetadata {
+        SavestateMetadata {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            frame,
+            note: note.into(),
+            thumbnail,
+        }
+    }
+
+    /// Write the metadata into the file's chunks, replacing any that were there.
+    pub fn save(&self, file: &mut SavestateFile) {
+        let mut metadata = Vec::new();
+        metadata.extend_from_slice(&self.timestamp.to_le_bytes());
+        metadata.extend_from_slice(&self.frame.to_le_bytes());
+        write_string(&mut metadata, &self.note);
+        file.set_chunk(METADATA_CHUNK, metadata);
+
+        match &self.thumbnail {
+            Some(thumbnail) => {
+                let mut data = Vec::new();
+                data.extend_from_slice(&thumbnail.width.to_le_bytes());
+                data.extend_from_slice(&thumbnail.height.to_le_bytes());
+                data.extend_from_slice(&thumbnail.rgb);
+                file.set_chunk(THUMBNAIL_CHUNK, data);
+            }
+            None => file.remove_chunk(THUMBNAIL_CHUNK),
+        }
+    }
+
+    /// Read the metadata back out of the file, or None if it was saved without any.
+    pub fn load(file: &SavestateFile) -> Result<Option<SavestateMetadata>, String> {
+        let mut reader = match file.chunk(METADATA_CHUNK) {
+            Some(chunk) => Reader(chunk),
+            None => return Ok(None),
+        };
+        let timestamp = reader.u64()?;
+        let frame = reader.u64()?;
+        let note = reader.string()?;
+
+        let thumbnail = match file.chunk(THUMBNAIL_CHUNK) {
+            Some(chunk) => {
+                let mut reader = Reader(chunk);
+                let width = reader.u16()?;
+                let height = reader.u16()?;
+                let rgb = reader.take(width as usize * height as usize * 3)?.to_vec();
+                Some(Thumbnail { width, height, rgb })
+            }
+            None => None,
+        };
+        Ok(Some(SavestateMetadata {
+            timestamp,
+            frame,
+            note,
+            thumbnail,
+        }))
+    }
+}
+
+/// A savestate in a directory, for a load-state picker.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavestateListing {
+    pub path: PathBuf,
+    pub rom_crc32: u32,
+    /// None for the states saved without any metadata.
+    pub metadata: Option<SavestateMetadata>,
+}
+
+/// List the savestates in the directory, the newest first, and the ones without
+/// metadata last. Only the files with the savestate extension are read, and the ones
+/// that can't be parsed are left out, as a picker couldn't load them anyway.
+#[cfg(feature = "std")]
+pub fn list_savestates(dir: &Path) -> io::Result<Vec<SavestateListing>> {
+    let mut listings = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != EXTENSION)
+        {
+            continue;
+        }
+        let file = match SavestateFile::from_bytes(&fs::read(&path)?) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let metadata = match SavestateMetadata::load(&file) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        listings.push(SavestateListing {
+            path,
+            rom_crc32: file.rom_crc32,
+            metadata,
+        });
+    }
+    // The directory isn't in any order, so the ties are sorted by their paths.
+    listings.sort_by(|a, b| {
+        let timestamp = |listing: &SavestateListing| {
+            listing.metadata.as_ref().map(|metadata| metadata.timestamp)
+        };
+        timestamp(b)
+            .cmp(&timestamp(a))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(listings)
+}
+
+/// Strings are a u16 length, and then the UTF-8.
+fn write_string(bytes: &mut Vec<u8>, text: &str) {
+    let text = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+    bytes.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(text);
+}
+
+/// Byte strings are a u32 length, and then the bytes.
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < size {
+            return Err("A savestate chunk is truncated.".into());
+        }
+        let (taken, rest) = self.0.split_at(size);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a byte string from write_bytes.
+    pub(crate) fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let size = self.u32()? as usize;
+        Ok(self.take(size)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let size = self.u16()? as usize;
+        String::from_utf8(self.take(size)?.to_vec())
+            .map_err(|_| "A savestate string is not UTF-8.".into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_debug_context() {
+        let context = DebugContext {
+            cheats: vec![String::from("SXIOPO"), String::from("AAEAULPA")],
+            frozen: vec![FrozenAddress {
+                address: 0x075a,
+                value: 9,
+            }],
+            breakpoints: vec![BreakpointInfo {
+                pc: 0x8123,
+                enabled: false,
+                label: String::from("nmi"),
+            }],
+        };
+        let mut file = SavestateFile::new(0x1234_5678);
+        file.set_chunk(*b"CPU ", vec![1, 2, 3]);
+        context.save(&mut file);
+
+        let file = SavestateFile::from_bytes(&file.to_bytes()).unwrap();
+        assert!(file.check_rom(0x1234_5678).is_ok());
+        assert!(file.check_rom(0).is_err());
+        assert_eq!(file.chunk(*b"CPU "), Some(&[1, 2, 3][..]));
+        assert_eq!(DebugContext::load(&file).unwrap(), context);
+
+        // Clearing the context removes its chunks, and leaves the machine's alone.
+        let mut file = file;
+        DebugContext::default().save(&mut file);
+        assert_eq!(file.chunks.len(), 1);
+        assert_eq!(DebugContext::load(&file).unwrap(), DebugContext::default());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_listing() {
+        let dir =
+            std::env::temp_dir().join(format!("nes-savestates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // A frame that's red on the left, and blue on the right.
+        let mut frame = Vec::new();
+        for _ in 0..FRAME_HEIGHT {
+            for x in 0..FRAME_WIDTH {
+                frame.extend_from_slice(if x < 128 {
+                    &[255, 0, 0]
+                } else {
+                    &[0, 0, 255]
+                });
+            }
+        }
+
+        let older = SavestateMetadata {
+            timestamp: 100,
+            frame: 3600,
+            note: String::from("Before the boss"),
+            thumbnail: Some(Thumbnail::from_frame(&frame)),
+        };
+        let mut file = SavestateFile::new(0xabcd_ef01);
+        older.save(&mut file);
+        fs::write(dir.join("1.ness"), file.to_bytes()).unwrap();
+
+        let newer = SavestateMetadata::now(7200, "", None);
+        let mut file = SavestateFile::new(0xabcd_ef01);
+        newer.save(&mut file);
+        fs::write(dir.join("2.ness"), file.to_bytes()).unwrap();
+        fs::write(dir.join("0.ness"), SavestateFile::new(0).to_bytes()).unwrap();
+        // These are skipped.
+        fs::write(dir.join("broken.ness"), b"NESS").unwrap();
+        fs::write(dir.join("notes.txt"), b"NESS").unwrap();
+
+        let listings = list_savestates(&dir).unwrap();
+        let names: Vec<_> = listings
+            .iter()
+            .map(|listing| listing.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["2.ness", "1.ness", "0.ness"]);
+        assert_eq!(listings[0].metadata, Some(newer));
+        assert_eq!(listings[1].rom_crc32, 0xabcd_ef01);
+        assert_eq!(listings[2].metadata, None);
+
+        let thumbnail = listings[1]
+            .metadata
+            .as_ref()
+            .unwrap()
+            .thumbnail
+            .as_ref()
+            .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (64, 60));
+        assert_eq!(&thumbnail.rgb[..3], &[255, 0, 0]);
+        assert_eq!(&thumbnail.rgb[thumbnail.rgb.len() - 3..], &[0, 0, 255]);
+        assert_eq!(listings[1].metadata, Some(older));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut file = SavestateFile::new(0);
+        file.set_chunk(FROZEN_CHUNK, vec![0x00, 0x03]);
+        let bytes = file.to_bytes();
+        assert!(SavestateFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let file = SavestateFile::from_bytes(&bytes).unwrap();
+        assert!(DebugContext::load(&file).is_err());
+        assert!(SavestateFile::from_bytes(b"FCSX").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+//! Best effort importers for the savestates of other emulators, so that a debugging
+//! session can be moved over to this core. This is lossy. Only the CPU registers, the
+//! internal RAM, and the PPU memories are read out. The cartridge, mapper, APU, and
+//! timing state are all skipped, so the game may need a frame or two to settle.
+use crate::cpu_6502::{Cpu6502, Registers};
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use std::convert::TryInto;
+
+const RAM_SIZE: usize = 0x800;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SavestateFormat {
+    /// FCEUX's .fcs files, which start with "FCSX".
+    Fceux,
+    /// Mesen 2's .mss files, which start with "MSS".
+    Mesen,
+}
+
+/// The PPU memories that were found in the savestate. Only the PPU's registers are
+/// emulated, not its VRAM, so these are only kept around for inspection.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PpuMemory {
+    pub nametables: Option<Vec<u8>>,
+    pub palette: Option<Vec<u8>>,
+    pub oam: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedState {
+    pub format: SavestateFormat,
+    pub registers: Registers,
+    /// The 2KB of internal RAM at $0000-$07FF.
+    pub ram: Vec<u8>,
+    pub ppu: PpuMemory,
+    /// The names of the chunks or keys in the savestate that were not imported.
+    pub skipped: Vec<String>,
+}
+
+impl ImportedState {
+    /// Load the registers and RAM into the CPU.
+    pub fn apply(&self, cpu: &mut Cpu6502) {
+        cpu.set_registers(self.registers);
+        for (address, value) in self.ram.iter().enumerate() {
+            cpu.poke(address as u16, *value);
+        }
+    }
+}
+
+/// Look at the magic bytes to figure out which emulator the savestate is from.
+pub fn import(bytes: &[u8]) -> Result<ImportedState, String> {
+    if bytes.starts_with(b"FCSX") {
+        import_fceux(bytes)
+    } else if bytes.starts_with(b"MSS") {
+        import_mesen(bytes)
+    } else {
+        Err("The savestate is not from FCEUX or Mesen.".into())
+    }
+}
+
+/// FCEUX savestates have a 16 byte header: "FCSX", the uncompressed size, the
+/// version, and the compressed size, or -1 when the data isn't compressed. The data
+/// is a list of sections, each with a u8 type and a u32 size. The sections then have
+/// chunks with a 4 character name and a u32 size. Everything is little endian.
+pub fn import_fceux(bytes: &[u8]) -> Result<ImportedState, String> {
+    if bytes.len() < 16 || !bytes.starts_with(b"FCSX") {
+        return Err("The FCEUX savestate header is missing.".into());
+    }
+    let compressed_size = read_u32(bytes, 12)?;
+    let data = if compressed_size == u32::MAX {
+        bytes[16..].to_vec()
+    } else {
+        let end = 16 + compressed_size as usize;
+        let compressed = bytes
+            .get(16..end)
+            .ok_or("The FCEUX savestate is truncated.")?;
+        decompress_to_vec_zlib(compressed)
+            .map_err(|_| "The FCEUX savestate could not be decompressed.")?
+    };
+
+    let mut registers = Registers::default();
+    let mut ram = None;
+    let mut ppu = PpuMemory::default();
+    let mut skipped = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let section = data[offset];
+        let size = read_u32(&data, offset + 1)? as usize;
+        let start = offset + 5;
+        let end = start + size;
+        let section_data = data
+            .get(start..end)
+            .ok_or("An FCEUX savestate section is truncated.")?;
+        offset = end;
+
+        let mut chunk_offset = 0;
+        while chunk_offset < section_data.len() {
+            let name = section_data
+                .get(chunk_offset..chunk_offset + 4)
+                .ok_or("An FCEUX savestate chunk is truncated.")?;
+            let name: String = name
+                .iter()
+                .take_while(|byte| **byte != 0)
+                .map(|byte| *byte as char)
+                .collect();
+            let size = read_u32(section_data, chunk_offset + 4)? as usize;
+            let start = chunk_offset + 8;
+            let chunk = section_data
+                .get(start..start + size)
+                .ok_or("An FCEUX savestate chunk is truncated.")?;
+            chunk_offset = start + size;
+
+            // Section 1 is the CPU, and section 3 is the PPU.
+            match (section, name.as_str(), chunk) {
+                (1, "PC", [low, high]) => {
+                    registers.pc = u16::from_le_bytes([*low, *high])
+                }
+                (1, "A", [value]) => registers.a = *value,
+                (1, "X", [value]) => registers.x = *value,
+                (1, "Y", [value]) => registers.y = *value,
+                (1, "S", [value]) => registers.s = *value,
+                (1, "P", [value]) => registers.p = *value,
+                (1, "RAM", _) if chunk.len() == RAM_SIZE => ram = Some(chunk.to_vec()),
+                (3, "NTAR", _) => ppu.nametables = Some(chunk.to_vec()),
+                (3, "PRAM", _) => ppu.palette = Some(chunk.to_vec()),
+                (3, "SPRA", _) => ppu.oam = Some(chunk.to_vec()),
+                _ => skipped.push(format!("{}:{}", section, name)),
+            }
+        }
+    }
+
+    Ok(ImportedState {
+        format: SavestateFormat::Fceux,
+        registers,
+        ram: ram.ok_or("The FCEUX savestate has no RAM.")?,
+        ppu,
+        skipped,
+    })
+}
+
+/// Mesen 2 savestates start with "MSS" and some version numbers, then a screenshot,
+/// and then the zlib compressed state of every component. The layout of the header
+/// changes between versions, so rather than rely on it, look for the compressed
+/// block that decodes to the state.
+///
+/// The state is a list of entries, each with a null terminated key like "cpu.pc", a
+/// u32 size, and the value. The keys are matched loosely, as the prefixes and
+/// capitalization depend on the version.
+pub fn import_mesen(bytes: &[u8]) -> Result<ImportedState, String> {
+    if !bytes.starts_with(b"MSS") {
+        return Err("The Mesen savestate header is missing.".into());
+    }
+    let entries = (3..bytes.len().saturating_sub(1))
+        // A zlib stream starts with a deflate method byte, and a check byte.
+        .filter(|&offset| {
+            let header = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+            bytes[offset] & 0x0f == 8 && header.is_multiple_of(31)
+        })
+        .filter_map(|offset| decompress_to_vec_zlib(&bytes[offset..]).ok())
+        .filter_map(|data| parse_mesen_entries(&data))
+        .find(|entries| {
+            entries
+                .iter()
+                .any(|(key, _)| mesen_key_matches(key, "cpu", "pc"))
+        })
+        .ok_or("Could not find the CPU state in the Mesen savestate.")?;
+
+    let mut registers = Registers::default();
+    let mut ram = None;
+    let mut ppu = PpuMemory::default();
+    let mut skipped = Vec::new();
+
+    for (key, value) in entries {
+        let is_cpu = |name| mesen_key_matches(&key, "cpu", name);
+        let field = key.rsplit('.').next().unwrap_or("").to_lowercase();
+        match value.as_slice() {
+            [low, high] if is_cpu("pc") => {
+                registers.pc = u16::from_le_bytes([*low, *high])
+            }
+            [value] if is_cpu("a") => registers.a = *value,
+            [value] if is_cpu("x") => registers.x = *value,
+            [value] if is_cpu("y") => registers.y = *value,
+            [value] if is_cpu("sp") => registers.s = *value,
+            [value] if is_cpu("ps") => registers.p = *value,
+            _ if field.trim_start_matches('_') == "internalram"
+                && value.len() == RAM_SIZE =>
+            {
+                ram = Some(value)
+            }
+            _ if field.trim_start_matches('_') == "nametableram" => {
+                ppu.nametables = Some(value)
+            }
+            _ if field.trim_start_matches('_') == "paletteram" => {
+                ppu.palette = Some(value)
+            }
+            _ if field.trim_start_matches('_') == "spriteram" => ppu.oam = Some(value),
+            _ => skipped.push(key),
+        }
+    }
+
+    Ok(ImportedState {
+        format: SavestateFormat::Mesen,
+        registers,
+        ram: ram.ok_or("The Mesen savestate has no RAM.")?,
+        ppu,
+        skipped,
+    })
+}
+
+/// Split the decompressed state into its key and value entries. This returns None if
+/// the data doesn't look like a list of entries.
+fn parse_mesen_entries(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let key_length = data[offset..].iter().position(|byte| *byte == 0)?;
+        let key = &data[offset..offset + key_length];
+        if key.is_empty() || !key.iter().all(|byte| byte.is_ascii_graphic()) {
+            return None;
+        }
+        let key = String::from_utf8(key.to_vec()).ok()?;
+        offset += key_length + 1;
+        let size = read_u32(data, offset).ok()? as usize;
+        offset += 4;
+        let value = data.get(offset..offset + size)?;
+        offset += size;
+        entries.push((key, value.to_vec()));
+    }
+    Some(entries)
+}
+
+/// Match a key like "cpu.pc" or "nes.cpu._state.PC" against a component and field.
+fn mesen_key_matches(key: &str, component: &str, field: &str) -> bool {
+    let parts: Vec<String> = key
+        .split('.')
+        .map(|part| part.trim_start_matches('_').to_lowercase())
+        .filter(|part| part != "state")
+        .collect();
+    match parts.as_slice() {
+        [.., last_component, last_field] => {
+            last_component == component && last_field == field
+        }
+        _ => false,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|slice| u32::from_le_bytes(slice.try_into().unwrap()))
+        .ok_or_else(|| "The savestate is truncated.".into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::mappers::SimpleProgram;
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+
+    fn fceux_chunk(name: &str, value: &[u8]) -> Vec<u8> {
+        let mut chunk = name.as_bytes().to_vec();
+        chunk.resize(4, 0);
+        chunk.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(value);
+        chunk
+    }
+
+    fn fceux_section(section: u8, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let data = chunks.concat();
+        let mut bytes = vec![section];
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn fceux_state(compress: bool) -> Vec<u8> {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[0x10] = 0x42;
+        let data = [
+            fceux_section(
+                1,
+                &[
+                    fceux_chunk("PC", &[0x34, 0x82]),
+                    fceux_chunk("A", &[0x01]),
+                    fceux_chunk("P", &[0x24]),
+                    fceux_chunk("X", &[0x02]),
+                    fceux_chunk("Y", &[0x03]),
+                    fceux_chunk("S", &[0xfd]),
+                    fceux_chunk("DB", &[0x00]),
+                    fceux_chunk("RAM", &ram),
+                ],
+            ),
+            fceux_section(3, &[fceux_chunk("PRAM", &[0x0f; 0x20])]),
+        ]
+        .concat();
+
+        let mut bytes = b"FCSX".to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&22000u32.to_le_bytes());
+        if compress {
+            let compressed = compress_to_vec_zlib(&data, 6);
+            bytes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            bytes.extend(compressed);
+        } else {
+            bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    fn expected_registers() -> Registers {
+        Registers {
+            a: 0x01,
+            x: 0x02,
+            y: 0x03,
+            pc: 0x8234,
+            s: 0xfd,
+            p: 0x24,
+        }
+    }
+
+    #[test]
+    fn test_fceux() {
+        for compress in &[false, true] {
+            let state = import(&fceux_state(*compress)).unwrap();
+            assert_eq!(state.format, SavestateFormat::Fceux);
+            assert_eq!(state.registers, expected_registers());
+            assert_eq!(state.ram[0x10], 0x42);
+            assert_eq!(state.ppu.palette, Some(vec![0x0f; 0x20]));
+            assert_eq!(state.ppu.nametables, None);
+            assert_eq!(state.skipped, vec![String::from("1:DB")]);
+        }
+    }
+
+    #[test]
+    fn test_mesen() {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[0x10] = 0x42;
+        let entries: &[(&str, &[u8])] = &[
+            ("cpu.pc", &[0x34, 0x82]),
+            ("cpu.sp", &[0xfd]),
+            ("cpu.ps", &[0x24]),
+            ("cpu.a", &[0x01]),
+            ("cpu.x", &[0x02]),
+            ("cpu.y", &[0x03]),
+            ("cpu.cycleCount", &[0; 8]),
+            ("memoryManager.internalRam", &ram),
+            ("ppu.spriteRam", &[0xff; 0x100]),
+        ];
+        let mut data = Vec::new();
+        for (key, value) in entries {
+            data.extend_from_slice(key.as_bytes());
+            data.push(0);
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+
+        let mut bytes = b"MSS".to_vec();
+        bytes.extend_from_slice(&[2, 0, 0, 0, 4, 0, 0, 0]);
+        // A compressed screenshot comes before the state.
+        bytes.extend(compress_to_vec_zlib(&[0; 256], 6));
+        bytes.extend(compress_to_vec_zlib(&data, 6));
+
+        let state = import(&bytes).unwrap();
+        assert_eq!(state.format, SavestateFormat::Mesen);
+        assert_eq!(state.registers, expected_registers());
+        assert_eq!(state.ram[0x10], 0x42);
+        assert_eq!(state.ppu.oam, Some(vec![0xff; 0x100]));
+        assert_eq!(state.skipped, vec![String::from("cpu.cycleCount")]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let state = import(&fceux_state(false)).unwrap();
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut cpu = Cpu6502::new(bus);
+        state.apply(&mut cpu);
+        assert_eq!(cpu.registers(), expected_registers());
+        assert_eq!(cpu.peek(0x10), 0x42);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(import(b"NES\x1a").is_err());
+        let mut truncated = fceux_state(false);
+        truncated.truncate(100);
+        assert!(import(&truncated).is_err());
+        assert!(import(b"MSS\x02\x00\x00\x00").is_err());
+    }
+}
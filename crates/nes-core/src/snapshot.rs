@@ -0,0 +1,150 @@
+//! Share the state of the machine with other threads, like a UI. The emulation
+//! thread publishes a snapshot at the end of every frame, and readers load the latest
+//! one without a lock. A snapshot is never changed after it's published, so a panel
+//! always sees the registers and memory from the same moment, even if the machine
+//! has moved on.
+use crate::cpu_6502::{Cpu6502, Registers};
+use arc_swap::ArcSwap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A copy of a range of memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemorySlice {
+    pub start: u16,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSnapshot {
+    /// How many frames were published before this one.
+    pub frame: u64,
+    /// The CPU's total cycles at the end of the frame.
+    pub total_cycles: u64,
+    /// The cycles that ran during the frame.
+    pub frame_cycles: u64,
+    pub registers: Registers,
+    pub memory: Vec<MemorySlice>,
+}
+
+impl StateSnapshot {
+    /// Read a byte from the copied memory, or None if it wasn't one of the ranges.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        self.memory.iter().find_map(|slice| {
+            let offset = address.checked_sub(slice.start)? as usize;
+            slice.bytes.get(offset).copied()
+        })
+    }
+}
+
+/// A cheap handle for reading the latest snapshot. It can be cloned and sent to any
+/// thread.
+#[derive(Clone)]
+pub struct SnapshotHandle {
+    latest: Arc<ArcSwap<StateSnapshot>>,
+}
+
+impl SnapshotHandle {
+    /// The latest snapshot. Hold on to it for as long as it's needed, as publishing a
+    /// new snapshot doesn't change it.
+    pub fn load(&self) -> Arc<StateSnapshot> {
+        self.latest.load_full()
+    }
+}
+
+/// Lives with the machine on the emulation thread, and publishes the snapshots.
+pub struct SnapshotPublisher {
+    ranges: Vec<Range<u16>>,
+    latest: Arc<ArcSwap<StateSnapshot>>,
+    frame: u64,
+}
+
+impl SnapshotPublisher {
+    /// Only the given memory ranges are copied, to keep the snapshots cheap. Until
+    /// the first frame is published, readers see an empty snapshot.
+    pub fn new(ranges: Vec<Range<u16>>) -> SnapshotPublisher {
+        let empty = StateSnapshot {
+            frame: 0,
+            total_cycles: 0,
+            frame_cycles: 0,
+            registers: Registers::default(),
+            memory: Vec::new(),
+        };
+        SnapshotPublisher {
+            ranges,
+            latest: Arc::new(ArcSwap::from_pointee(empty)),
+            frame: 0,
+        }
+    }
+
+    pub fn handle(&self) -> SnapshotHandle {
+        SnapshotHandle {
+            latest: Arc::clone(&self.latest),
+        }
+    }
+
+    /// Copy the CPU and the memory ranges, and swap them in for the readers. The
+    /// memory is read without side effects, so that registers that react to reads
+    /// aren't disturbed. Unmapped addresses read as 0.
+    pub fn publish(&mut self, cpu: &Cpu6502, frame_cycles: u64) {
+        let memory = {
+            let bus = cpu.bus.borrow();
+            self.ranges
+                .iter()
+                .map(|range| MemorySlice {
+                    start: range.start,
+                    bytes: range
+                        .clone()
+                        .map(|address| bus.try_read_u8(address).unwrap_or(0))
+                        .collect(),
+                })
+                .collect()
+        };
+        self.latest.store(Arc::new(StateSnapshot {
+            frame: self.frame,
+            total_cycles: cpu.total_cycles,
+            frame_cycles,
+            registers: cpu.registers(),
+            memory,
+        }));
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_6502::test_helpers::load_program;
+    use crate::region::NTSC_FRAME_CYCLES;
+    use std::thread;
+
+    #[test]
+    fn test_publish() {
+        let mut cpu = load_program(
+            "
+          loop:
+            inc $10
+            jmp loop
+            ",
+        );
+        let mut publisher = SnapshotPublisher::new(vec![0x0010..0x0011, 0x0300..0x0310]);
+        let handle = publisher.handle();
+        assert!(handle.load().memory.is_empty());
+
+        let cycles = cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        publisher.publish(&cpu, cycles);
+        let first = handle.load();
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        publisher.publish(&cpu, cycles);
+
+        // The reader's copy doesn't change, and the new one is on another thread.
+        assert_eq!(first.frame, 0);
+        let latest = thread::spawn(move || handle.load()).join().unwrap();
+        assert_eq!(latest.frame, 1);
+        assert_eq!(latest.registers, cpu.registers());
+        assert_eq!(latest.read(0x0010), Some(cpu.peek(0x0010)));
+        assert_ne!(first.read(0x0010), latest.read(0x0010));
+        assert_eq!(latest.read(0x030f), Some(0));
+        assert_eq!(latest.read(0x0310), None);
+    }
+}
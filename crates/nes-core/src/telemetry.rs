@@ -0,0 +1,236 @@
+use crate::clock::{Clock, SystemClock};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How often the frame count is logged, which is once a minute at 60 fps.
+const FRAMES_PER_ENTRY: u64 = 3600;
+
+/// Something that happened in a session, which is worth knowing when reproducing a
+/// bug report.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryEvent {
+    SessionStarted {
+        rom: String,
+    },
+    /// The total frames run so far in the session.
+    Frames {
+        frames: u64,
+    },
+    StateSaved {
+        slot: String,
+    },
+    StateLoaded {
+        slot: String,
+    },
+    CheatToggled {
+        code: String,
+        enabled: bool,
+    },
+    BreakpointHit {
+        pc: u16,
+    },
+    Crashed {
+        pc: u16,
+        message: String,
+    },
+    SessionEnded {
+        frames: u64,
+    },
+}
+
+impl TelemetryEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            TelemetryEvent::SessionStarted { .. } => "session_started",
+            TelemetryEvent::Frames { .. } => "frames",
+            TelemetryEvent::StateSaved { .. } => "state_saved",
+            TelemetryEvent::StateLoaded { .. } => "state_loaded",
+            TelemetryEvent::CheatToggled { .. } => "cheat_toggled",
+            TelemetryEvent::BreakpointHit { .. } => "breakpoint_hit",
+            TelemetryEvent::Crashed { .. } => "crashed",
+            TelemetryEvent::SessionEnded { .. } => "session_ended",
+        }
+    }
+
+    /// The fields after the event name, as JSON.
+    fn fields(&self) -> String {
+        match self {
+            TelemetryEvent::SessionStarted { rom } => {
+                format!(r#""rom":{}"#, json_string(rom))
+            }
+            TelemetryEvent::Frames { frames }
+            | TelemetryEvent::SessionEnded { frames } => {
+                format!(r#""frames":{}"#, frames)
+            }
+            TelemetryEvent::StateSaved { slot }
+            | TelemetryEvent::StateLoaded { slot } => {
+                format!(r#""slot":{}"#, json_string(slot))
+            }
+            TelemetryEvent::CheatToggled { code, enabled } => {
+                format!(r#""code":{},"enabled":{}"#, json_string(code), enabled)
+            }
+            TelemetryEvent::BreakpointHit { pc } => format!(r#""pc":{}"#, pc),
+            TelemetryEvent::Crashed { pc, message } => {
+                format!(r#""pc":{},"message":{}"#, pc, json_string(message))
+            }
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            character if (character as u32) < 0x20 => {
+                json.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// An opt-in log of a session, written as a JSON object per line, e.g.
+///
+/// {"ms":61250,"event":"state_loaded","slot":"1"}
+///
+/// The times are milliseconds since the log was started. Each line is flushed as it's
+/// written, so that the log is complete even if the emulator crashes. The frames are
+/// only logged every so often, to keep long sessions small. Logging is best effort,
+/// so if a write fails the log stops, and the error is kept.
+pub struct TelemetryLog {
+    out: Box<dyn Write>,
+    clock: Rc<dyn Clock>,
+    start: Duration,
+    frames: u64,
+    error: Option<io::Error>,
+}
+
+impl TelemetryLog {
+    pub fn new(out: Box<dyn Write>) -> TelemetryLog {
+        TelemetryLog::with_clock(out, Rc::new(SystemClock::new()))
+    }
+
+    /// Take the times from the clock, e.g. a ManualClock for a deterministic log.
+    pub fn with_clock(out: Box<dyn Write>, clock: Rc<dyn Clock>) -> TelemetryLog {
+        TelemetryLog {
+            out,
+            start: clock.now(),
+            clock,
+            frames: 0,
+            error: None,
+        }
+    }
+
+    /// Append to the log file, so that it keeps the earlier sessions.
+    pub fn open(path: &Path) -> io::Result<TelemetryLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TelemetryLog::new(Box::new(BufWriter::new(file))))
+    }
+
+    pub fn record(&mut self, event: TelemetryEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = format!(
+            r#"{{"ms":{},"event":"{}",{}}}"#,
+            (self.clock.now() - self.start).as_millis(),
+            event.name(),
+            event.fields()
+        );
+        if let Err(error) = writeln!(self.out, "{}", line).and_then(|_| self.out.flush())
+        {
+            self.error = Some(error);
+        }
+    }
+
+    /// Count a frame, see Emulator::set_telemetry.
+    pub fn record_frame(&mut self) {
+        self.frames += 1;
+        if self.frames.is_multiple_of(FRAMES_PER_ENTRY) {
+            self.record(TelemetryEvent::Frames {
+                frames: self.frames,
+            });
+        }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Log the final frame count.
+    pub fn end_session(&mut self) {
+        self.record(TelemetryEvent::SessionEnded {
+            frames: self.frames,
+        });
+    }
+
+    /// The write that stopped the log.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::cell::RefCell;
+
+    /// Collects the log where the test can see it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_log() {
+        let buffer = SharedBuffer::default();
+        let clock = Rc::new(ManualClock::new());
+        clock.set(Duration::from_secs(10));
+        let mut log = TelemetryLog::with_clock(Box::new(buffer.clone()), clock.clone());
+        log.record(TelemetryEvent::SessionStarted {
+            rom: String::from("say \"hi\".nes"),
+        });
+        for _ in 0..FRAMES_PER_ENTRY + 1 {
+            log.record_frame();
+        }
+        clock.advance(Duration::from_millis(61_250));
+        log.record(TelemetryEvent::Crashed {
+            pc: 0x8000,
+            message: String::from("line 1\nline 2"),
+        });
+        log.end_session();
+
+        let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["event"], "session_started");
+        assert_eq!(lines[0]["rom"], "say \"hi\".nes");
+        assert_eq!(lines[1]["frames"], 3600);
+        assert_eq!(lines[2]["message"], "line 1\nline 2");
+        assert_eq!(lines[2]["pc"], 0x8000);
+        assert_eq!(lines[3]["event"], "session_ended");
+        assert_eq!(lines[3]["frames"], 3601);
+        assert_eq!(lines[0]["ms"], 0);
+        assert_eq!(lines[3]["ms"], 61_250);
+    }
+}
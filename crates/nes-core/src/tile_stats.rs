@@ -0,0 +1,192 @@
+use crate::chr::{Sheet, SHEET_TILES_WIDE, SHEET_WIDTH, TILE_BYTES, TILE_SIZE};
+use crate::prelude::*;
+
+/// The two pattern tables at $0000-$1FFF hold 512 tiles.
+pub const PATTERN_TILES: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileLayer {
+    Background,
+    Sprites,
+}
+
+/// How many times each tile was fetched, for the background and the sprites. The
+/// tiles are numbered by where they are in the pattern tables, so with a mapper that
+/// banks the CHR, the same tile number can be different graphics over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileCounts {
+    background: Vec<u64>,
+    sprites: Vec<u64>,
+}
+
+impl TileCounts {
+    fn new() -> TileCounts {
+        TileCounts {
+            background: vec![0; PATTERN_TILES],
+            sprites: vec![0; PATTERN_TILES],
+        }
+    }
+
+    fn layer(&self, layer: TileLayer) -> &[u64] {
+        match layer {
+            TileLayer::Background => &self.background,
+            TileLayer::Sprites => &self.sprites,
+        }
+    }
+
+    fn add(&mut self, other: &TileCounts) {
+        for (count, other) in self.background.iter_mut().zip(&other.background) {
+            *count += other;
+        }
+        for (count, other) in self.sprites.iter_mut().zip(&other.sprites) {
+            *count += other;
+        }
+    }
+
+    pub fn count(&self, layer: TileLayer, tile: usize) -> u64 {
+        self.layer(layer)[tile]
+    }
+
+    /// The tiles that were fetched at least once, in order.
+    pub fn used(&self, layer: TileLayer) -> Vec<usize> {
+        let counts = self.layer(layer);
+        (0..PATTERN_TILES)
+            .filter(|&tile| counts[tile] > 0)
+            .collect()
+    }
+
+    /// The tiles that neither the background nor the sprites fetched.
+    pub fn unused(&self) -> Vec<usize> {
+        (0..PATTERN_TILES)
+            .filter(|&tile| self.background[tile] == 0 && self.sprites[tile] == 0)
+            .collect()
+    }
+
+    /// Lay out the tiles like a pattern table sheet, see chr::Sheet, with each tile
+    /// filled in from dark to light by how often it was fetched. The tiles that were
+    /// never fetched are black, so the sheet can be put next to the CHR to find the
+    /// graphics.
+    pub fn heatmap(&self, layer: TileLayer) -> Sheet {
+        let counts = self.layer(layer);
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let rows = PATTERN_TILES / SHEET_TILES_WIDE;
+        let mut sheet = Sheet {
+            width: SHEET_WIDTH,
+            height: rows * TILE_SIZE,
+            pixels: vec![0; SHEET_WIDTH * rows * TILE_SIZE],
+        };
+        for (tile, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            // Spread the fetched tiles over the 3 lighter shades.
+            let value = (1 + (count - 1) * 3 / max) as u8;
+            let left = (tile % SHEET_TILES_WIDE) * TILE_SIZE;
+            let top = (tile / SHEET_TILES_WIDE) * TILE_SIZE;
+            for y in top..top + TILE_SIZE {
+                let row = y * sheet.width;
+                sheet.pixels[row + left..row + left + TILE_SIZE].fill(value);
+            }
+        }
+        sheet
+    }
+}
+
+/// Tracks which CHR tiles the PPU fetches each frame, to help find the tiles a game
+/// never uses, or which graphics belong to an object on the screen. The PPU records
+/// its pattern fetches, see Ppu::tile_stats, and Emulator::run_frame ends each frame.
+pub struct TileStats {
+    current: TileCounts,
+    last_frame: TileCounts,
+    totals: TileCounts,
+    frames: u64,
+}
+
+impl TileStats {
+    pub fn new() -> TileStats {
+        TileStats {
+            current: TileCounts::new(),
+            last_frame: TileCounts::new(),
+            totals: TileCounts::new(),
+            frames: 0,
+        }
+    }
+
+    /// Record a fetch from the pattern tables. Any address within the tile counts,
+    /// as the two planes of a row are fetched separately.
+    pub fn record_fetch(&mut self, layer: TileLayer, pattern_address: u16) {
+        let tile = (pattern_address as usize / TILE_BYTES) % PATTERN_TILES;
+        match layer {
+            TileLayer::Background => self.current.background[tile] += 1,
+            TileLayer::Sprites => self.current.sprites[tile] += 1,
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.totals.add(&self.current);
+        self.last_frame = core::mem::replace(&mut self.current, TileCounts::new());
+        self.frames += 1;
+    }
+
+    /// The fetches from the last frame that ended.
+    pub fn last_frame(&self) -> &TileCounts {
+        &self.last_frame
+    }
+
+    /// The fetches from every frame that ended.
+    pub fn totals(&self) -> &TileCounts {
+        &self.totals
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tile_stats() {
+        let mut stats = TileStats::new();
+        // Both planes of a row of tile $01 in the first pattern table.
+        stats.record_fetch(TileLayer::Background, 0x0010);
+        stats.record_fetch(TileLayer::Background, 0x0018);
+        // Tile $00 in the second pattern table.
+        stats.record_fetch(TileLayer::Sprites, 0x1000);
+        stats.end_frame();
+        stats.record_fetch(TileLayer::Background, 0x0010);
+        stats.end_frame();
+
+        assert_eq!(stats.frames(), 2);
+        assert_eq!(stats.last_frame().count(TileLayer::Background, 1), 1);
+        assert!(stats.last_frame().used(TileLayer::Sprites).is_empty());
+        assert_eq!(stats.totals().count(TileLayer::Background, 1), 3);
+        assert_eq!(stats.totals().used(TileLayer::Sprites), [256]);
+
+        let unused = stats.totals().unused();
+        assert_eq!(unused.len(), PATTERN_TILES - 2);
+        assert!(!unused.contains(&1));
+        assert!(!unused.contains(&256));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let mut stats = TileStats::new();
+        for _ in 0..4 {
+            stats.record_fetch(TileLayer::Background, 0x0010);
+        }
+        stats.record_fetch(TileLayer::Background, 0x0020);
+        stats.end_frame();
+
+        let sheet = stats.totals().heatmap(TileLayer::Background);
+        assert_eq!((sheet.width, sheet.height), (128, 256));
+        let pixel = |tile: usize| sheet.pixels[tile * TILE_SIZE];
+        assert_eq!(pixel(0), 0);
+        assert_eq!(pixel(1), 3);
+        assert_eq!(pixel(2), 1);
+        // The whole tile is filled in.
+        assert_eq!(sheet.pixels[7 * sheet.width + 15], 3);
+    }
+}
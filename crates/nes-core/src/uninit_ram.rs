@@ -0,0 +1,248 @@
+use crate::bus::{BusObserver, InstructionStart, ObservedAccess};
+use crate::constants::memory_range;
+use crate::prelude::*;
+use alloc::collections::BTreeSet;
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+
+/// What happens when the program reads a byte it never wrote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UninitPolicy {
+    /// Keep a list of the reads, to look at once the run is done.
+    Log,
+    /// Keep the list, and stop Cpu6502::run after the instruction that made the read.
+    Break,
+}
+
+/// A read of a byte of RAM that hadn't been written since power-on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UninitRead {
+    /// The pc of the instruction that made the read.
+    pub pc: u16,
+    /// The address in the 2KB of RAM, with the mirrors folded onto it.
+    pub address: u16,
+}
+
+/// Traps the reads of RAM that the program never wrote. The emulator powers on with
+/// the RAM cleared to 0, but a real console powers on with whatever the chips settle
+/// on, so a program that forgets to clear a variable can work in every emulator and
+/// then break on hardware.
+///
+/// Some programs read the RAM on purpose before they write it, like a check for a
+/// signature that survives the reset button, or seeding a random number generator
+/// from the noise. Those are known to be safe, so their instructions or addresses can
+/// be allowed. The CPU's dummy reads, and the peeks from the tools, never count.
+///
+/// This lives on the bus, so that it sees every access. Start it with
+/// Bus::set_observer, and the CPU keeps it up to date with the pc of the
+/// instruction being run. Each instruction is only reported once per address, so a
+/// loop doesn't flood the log, and a run that broke on a read can be continued.
+pub struct UninitRamTrap {
+    policy: UninitPolicy,
+    /// Which bytes have been written since power-on.
+    written: Vec<bool>,
+    allowed_pcs: BTreeSet<u16>,
+    allowed_ranges: Vec<RangeInclusive<u16>>,
+    /// The pc of the instruction currently being executed.
+    pc: u16,
+    /// Reads come through the bus without mutable access.
+    reads: RefCell<Vec<UninitRead>>,
+    reported: RefCell<BTreeSet<(u16, u16)>>,
+    pending_break: RefCell<Option<UninitRead>>,
+}
+
+impl UninitRamTrap {
+    pub fn new(policy: UninitPolicy) -> UninitRamTrap {
+        UninitRamTrap {
+            policy,
+            written: vec![false; memory_range::RAM_ACTUAL.size() as usize],
+            allowed_pcs: BTreeSet::new(),
+            allowed_ranges: Vec::new(),
+            pc: 0,
+            reads: RefCell::new(Vec::new()),
+            reported: RefCell::new(BTreeSet::new()),
+            pending_break: RefCell::new(None),
+        }
+    }
+
+    /// The instruction is known to read the RAM before it's written, like a warm boot
+    /// check.
+    pub fn allow_pc(&mut self, pc: u16) {
+        self.allowed_pcs.insert(pc);
+    }
+
+    /// The addresses are known to be read before they are written, like the seed of a
+    /// random number generator. The mirrors are folded onto the 2KB of RAM.
+    pub fn allow_range(&mut self, range: RangeInclusive<u16>) {
+        self.allowed_ranges.push(range);
+    }
+
+    /// Forget every write, like the console was switched off and on again. The reset
+    /// button keeps the RAM, so this isn't called for a reset.
+    pub fn power_on(&mut self) {
+        self.written.iter_mut().for_each(|written| *written = false);
+    }
+
+    pub fn record_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Map the address to an index into the RAM, leaving out the mirrors, and
+    /// everything that isn't RAM.
+    fn index(address: u16) -> Option<usize> {
+        if address >= memory_range::RAM.end {
+            return None;
+        }
+        Some((address & memory_range::RAM_ACTUAL.mask()) as usize)
+    }
+
+    pub fn record_write(&mut self, address: u16) {
+        if let Some(index) = UninitRamTrap::index(address) {
+            self.written[index] = true;
+        }
+    }
+
+    pub fn is_written(&self, address: u16) -> bool {
+        UninitRamTrap::index(address).is_some_and(|index| self.written[index])
+    }
+
+    pub fn record_read(&self, address: u16) {
+        let index = match UninitRamTrap::index(address) {
+            Some(index) if !self.written[index] => index,
+            _ => return,
+        };
+        let address = index as u16;
+        if self.allowed_pcs.contains(&self.pc)
+            || self
+                .allowed_ranges
+                .iter()
+                .any(|range| range.contains(&address))
+        {
+            return;
+        }
+        if !self.reported.borrow_mut().insert((self.pc, address)) {
+            return;
+        }
+        let read = UninitRead {
+            pc: self.pc,
+            address,
+        };
+        self.reads.borrow_mut().push(read);
+        let mut pending_break = self.pending_break.borrow_mut();
+        if self.policy == UninitPolicy::Break && pending_break.is_none() {
+            *pending_break = Some(read);
+        }
+    }
+
+    /// The first read that should stop the run, since the last call.
+    pub fn take_break(&self) -> Option<UninitRead> {
+        self.pending_break.borrow_mut().take()
+    }
+
+    /// The reads of uninitialized RAM, in the order they were made.
+    pub fn reads(&self) -> Vec<UninitRead> {
+        self.reads.borrow().clone()
+    }
+
+    /// A line per read, e.g. "$8123 read $0010 before it was written".
+    pub fn report(&self) -> String {
+        let reads = self.reads.borrow();
+        let mut report = format!("Reads of uninitialized RAM ({}):\n", reads.len());
+        for read in reads.iter() {
+            report.push_str(&format!(
+                "  ${:04x} read ${:04x} before it was written\n",
+                read.pc, read.address
+            ));
+        }
+        report
+    }
+}
+
+impl BusObserver for UninitRamTrap {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        UninitRamTrap::record_instruction(self, instruction.pc);
+    }
+
+    fn record_read(&self, access: &ObservedAccess) {
+        if access.ram {
+            UninitRamTrap::record_read(self, access.address);
+        }
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        if access.ram {
+            UninitRamTrap::record_write(self, access.address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_6502::test_helpers::load_program_with_symbols;
+    use crate::cpu_6502::{StopCondition, StopReason};
+
+    #[test]
+    fn test_uninit_ram_trap() {
+        let (mut cpu, symbols) = load_program_with_symbols(
+            "
+              lda $0810     ; A mirror of $0010, which was never written.
+              sta $20
+              lda $20
+              ldx #2
+            warm_boot:
+              lda $0300     ; Allowed, like a check for a signature.
+              lda $40       ; Allowed, like the seed of a random number generator.
+              lda $11       ; Only reported once, even though it loops.
+              dex
+              bne warm_boot
+            break:
+              lda $12
+              nop
+            ",
+        );
+        let mut trap = UninitRamTrap::new(UninitPolicy::Break);
+        trap.allow_pc(symbols.address("warm_boot").unwrap());
+        trap.allow_range(0x40..=0x4f);
+        cpu.bus.borrow_mut().set_observer(Some(trap));
+
+        let start = cpu.pc;
+        match cpu.run(StopCondition::MaxCycles(1000)) {
+            StopReason::UninitializedRead(read) => {
+                assert_eq!(
+                    read,
+                    UninitRead {
+                        pc: start,
+                        address: 0x10
+                    }
+                )
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+        match cpu.run(StopCondition::MaxCycles(1000)) {
+            StopReason::UninitializedRead(read) => assert_eq!(read.address, 0x11),
+            reason => panic!("Unexpected {:?}", reason),
+        }
+        let break_pc = symbols.address("break").unwrap();
+        match cpu.run(StopCondition::MaxCycles(1000)) {
+            StopReason::UninitializedRead(read) => {
+                assert_eq!(
+                    read,
+                    UninitRead {
+                        pc: break_pc,
+                        address: 0x12
+                    }
+                )
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+
+        let bus = cpu.bus.borrow();
+        let trap = bus.observer::<UninitRamTrap>().unwrap();
+        assert!(trap.is_written(0x0820));
+        assert_eq!(trap.reads().len(), 3);
+        assert!(trap
+            .report()
+            .starts_with("Reads of uninitialized RAM (3):\n  $8000 read $0010"));
+    }
+}
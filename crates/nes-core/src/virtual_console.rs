@@ -0,0 +1,116 @@
+use crate::bus::{Bus, BusDevice};
+use crate::prelude::*;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+
+/// Each byte written here is printed.
+pub const CONSOLE_OUTPUT: u16 = 0x401b;
+/// Writing here ends the program with the value as the exit code, where 0 is a pass.
+pub const CONSOLE_EXIT: u16 = 0x401c;
+/// The addresses are in the unused CPU test mode range, so they don't get in the way
+/// of any NES hardware.
+pub const CONSOLE_RANGE: RangeInclusive<u16> = CONSOLE_OUTPUT..=CONSOLE_EXIT;
+
+#[derive(Default)]
+struct ConsoleState {
+    output: Vec<u8>,
+    exit_code: Option<u8>,
+}
+
+/// A debug device that doesn't exist on the hardware, so that test programs can
+/// report their own results without a PPU, e.g.
+///
+/// ```asm
+///   lda #$4f        ; "O"
+///   sta $401b
+///   lda #$00        ; Pass.
+///   sta $401c
+/// ```
+///
+/// The device is moved into the bus, so its output is read through a ConsoleHandle.
+pub struct VirtualConsole {
+    state: Rc<RefCell<ConsoleState>>,
+}
+
+impl VirtualConsole {
+    /// Attach a console at CONSOLE_RANGE, and return the handle to its output.
+    pub fn attach(bus: &mut Bus) -> Result<ConsoleHandle, String> {
+        let state = Rc::new(RefCell::new(ConsoleState::default()));
+        let console = VirtualConsole {
+            state: Rc::clone(&state),
+        };
+        bus.attach_device(CONSOLE_RANGE, Box::new(console))?;
+        Ok(ConsoleHandle { state })
+    }
+}
+
+impl BusDevice for VirtualConsole {
+    fn name(&self) -> &str {
+        "virtual console"
+    }
+
+    /// The registers are write-only.
+    fn read(&self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let mut state = self.state.borrow_mut();
+        match address {
+            CONSOLE_OUTPUT => state.output.push(value),
+            // Only the first exit counts, like a process can only exit once.
+            _ => {
+                state.exit_code.get_or_insert(value);
+            }
+        }
+    }
+}
+
+/// Reads what a program wrote to its VirtualConsole.
+#[derive(Clone)]
+pub struct ConsoleHandle {
+    state: Rc<RefCell<ConsoleState>>,
+}
+
+impl ConsoleHandle {
+    /// Everything printed so far. The bytes are treated as ASCII, and anything that
+    /// isn't valid UTF-8 is replaced.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.state.borrow().output).into_owned()
+    }
+
+    /// The number of bytes printed so far, without copying them.
+    pub fn output_len(&self) -> usize {
+        self.state.borrow().output.len()
+    }
+
+    /// The exit code, once the program has exited.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.state.borrow().exit_code
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mappers::SimpleProgram;
+
+    #[test]
+    fn test_console() {
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&[])));
+        let mut bus = bus.borrow_mut();
+        let console = VirtualConsole::attach(&mut bus).unwrap();
+        for &byte in b"ok\n" {
+            bus.set_u8(CONSOLE_OUTPUT, byte);
+        }
+        assert_eq!(console.exit_code(), None);
+        bus.set_u8(CONSOLE_EXIT, 3);
+        bus.set_u8(CONSOLE_EXIT, 0);
+
+        assert_eq!(console.output(), "ok\n");
+        assert_eq!(console.exit_code(), Some(3));
+        // Nothing was written to memory.
+        assert_eq!(bus.read_u8(CONSOLE_OUTPUT), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::prelude::*;
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl AccessKind {
+    fn matches(self, access: AccessKind) -> bool {
+        self == AccessKind::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchpointId(usize);
+
+/// An access that a watchpoint caught.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchpointHit {
+    pub id: WatchpointId,
+    pub address: u16,
+    /// The value that was read or written.
+    pub value: u8,
+    /// Either a Read or a Write.
+    pub access: AccessKind,
+}
+
+struct Watchpoint {
+    id: WatchpointId,
+    range: RangeInclusive<u16>,
+    kind: AccessKind,
+}
+
+/// Watches ranges of addresses for reads or writes, to find out who is touching
+/// them. This lives on the bus, which flags the accesses as they happen, see
+/// Bus::add_watchpoint. Cpu6502::run stops after the instruction that made them. Peeks
+/// don't count, so that tools can look at the watched memory.
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+    /// Reads come through the bus without mutable access.
+    hits: RefCell<Vec<WatchpointHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            watchpoints: Vec::new(),
+            next_id: 0,
+            hits: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn add(&mut self, range: RangeInclusive<u16>, kind: AccessKind) -> WatchpointId {
+        let id = WatchpointId(self.next_id);
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    /// Returns false if there was no such watchpoint.
+    pub fn remove(&mut self, id: WatchpointId) -> bool {
+        let length = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    /// Flag the access if it's being watched. The access is a Read or a Write.
+    pub fn check(&self, address: u16, value: u8, access: AccessKind) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.kind.matches(access) && watchpoint.range.contains(&address) {
+                self.hits.borrow_mut().push(WatchpointHit {
+                    id: watchpoint.id,
+                    address,
+                    value,
+                    access,
+                });
+            }
+        }
+    }
+
+    pub fn has_hits(&self) -> bool {
+        !self.hits.borrow().is_empty()
+    }
+
+    /// The accesses that were caught since the last call, in order.
+    pub fn take_hits(&self) -> Vec<WatchpointHit> {
+        self.hits.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_watchpoints() {
+        let mut watchpoints = Watchpoints::new();
+        let writes = watchpoints.add(0x00f0..=0x00ff, AccessKind::Write);
+        let both = watchpoints.add(0x00f3..=0x00f3, AccessKind::ReadWrite);
+
+        watchpoints.check(0x00f3, 0x12, AccessKind::Read);
+        watchpoints.check(0x00f4, 0x34, AccessKind::Write);
+        watchpoints.check(0x0100, 0x56, AccessKind::Write);
+        assert!(watchpoints.has_hits());
+        assert_eq!(
+            watchpoints.take_hits(),
+            [
+                WatchpointHit {
+                    id: both,
+                    address: 0x00f3,
+                    value: 0x12,
+                    access: AccessKind::Read,
+                },
+                WatchpointHit {
+                    id: writes,
+                    address: 0x00f4,
+                    value: 0x34,
+                    access: AccessKind::Write,
+                },
+            ]
+        );
+        assert!(!watchpoints.has_hits());
+
+        assert!(watchpoints.remove(writes));
+        assert!(!watchpoints.remove(writes));
+        watchpoints.check(0x00f4, 0x34, AccessKind::Write);
+        assert!(!watchpoints.has_hits());
+    }
+}
@@ -0,0 +1,254 @@
+//! Name the code in ROMs that come without any symbols. Subroutines are found from
+//! the JSRs that call them, jump table targets from where the indirect JMPs land, and
+//! the interrupt handlers from the vectors. The code can be found by tracing it
+//! statically from the vectors, or by watching it run, which also finds the targets
+//! that are only known at run time, like the jump tables.
+use nes_asm::symbols::{Symbol, Symbols};
+use nes_core::bus::Bus;
+use nes_core::constants::{memory_range, InterruptVectors};
+use nes_core::cpu_6502::TraceEvent;
+use nes_core::opcodes::{self, Mode, OpCode, OPCODES};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelKind {
+    Reset,
+    Nmi,
+    Irq,
+    Subroutine,
+    /// Where an indirect JMP landed, which is usually an entry of a jump table.
+    JumpTarget,
+}
+
+impl LabelKind {
+    /// The label for the code at an address, e.g. "sub_8123".
+    pub fn name(self, address: u16) -> String {
+        match self {
+            LabelKind::Reset => "reset".into(),
+            LabelKind::Nmi => "nmi".into(),
+            LabelKind::Irq => "irq".into(),
+            LabelKind::Subroutine => format!("sub_{:04x}", address),
+            LabelKind::JumpTarget => format!("jump_{:04x}", address),
+        }
+    }
+
+    fn comment(self) -> &'static str {
+        match self {
+            LabelKind::Reset => "The reset handler",
+            LabelKind::Nmi => "The NMI handler",
+            LabelKind::Irq => "The IRQ and BRK handler",
+            LabelKind::Subroutine => "Called with JSR",
+            LabelKind::JumpTarget => "Reached with an indirect JMP",
+        }
+    }
+}
+
+pub struct AutoLabeler {
+    found: BTreeMap<u16, LabelKind>,
+    /// The last instruction that ran was an indirect JMP.
+    after_indirect_jump: bool,
+}
+
+impl AutoLabeler {
+    pub fn new() -> AutoLabeler {
+        AutoLabeler {
+            found: BTreeMap::new(),
+            after_indirect_jump: false,
+        }
+    }
+
+    /// The code that was found so far, and what it is.
+    pub fn found(&self) -> &BTreeMap<u16, LabelKind> {
+        &self.found
+    }
+
+    /// An address keeps the first kind it was found as, so a handler that's also
+    /// called with a JSR stays a handler.
+    fn add(&mut self, address: u16, kind: LabelKind) {
+        self.found.entry(address).or_insert(kind);
+    }
+
+    /// Label the code from an instruction that's about to run, see
+    /// Cpu6502::set_trace_hook. The interrupts don't show up as instructions, so the
+    /// handlers are found from the vectors by trace_static.
+    pub fn record_instruction(&mut self, event: &TraceEvent) {
+        if self.after_indirect_jump {
+            self.add(event.registers.pc, LabelKind::JumpTarget);
+        }
+        self.after_indirect_jump = event.opcode == OpCode::JMP_ind as u8;
+        if event.opcode == OpCode::JSR_abs as u8 {
+            if let [low, high] = event.operands[..] {
+                self.add(u16::from_le_bytes([low, high]), LabelKind::Subroutine);
+            }
+        }
+    }
+
+    /// Follow the code from the interrupt vectors without running it, through the
+    /// branches, jumps, and subroutine calls. Only the vectors that point into the
+    /// cartridge are followed. The targets of indirect jumps are only
+    /// known at run time, so the tracing stops there, as it does at an illegal opcode,
+    /// which is most likely data.
+    pub fn trace_static(&mut self, bus: &Bus) {
+        let read_u16 = |address: u16| {
+            let low = bus.try_read_u8(address)?;
+            let high = bus.try_read_u8(address.wrapping_add(1))?;
+            Some(u16::from_le_bytes([low, high]))
+        };
+
+        let mut queue = Vec::new();
+        for &(vector, kind) in &[
+            (InterruptVectors::ResetVector as u16, LabelKind::Reset),
+            (InterruptVectors::NmiVector as u16, LabelKind::Nmi),
+            (InterruptVectors::IrqBrkVector as u16, LabelKind::Irq),
+        ] {
+            // An unused vector is often left pointing at $0000.
+            if let Some(address) = read_u16(vector)
+                .filter(|&address| address >= memory_range::CARTRIDGE_SPACE.start)
+            {
+                self.add(address, kind);
+                queue.push(address);
+            }
+        }
+
+        let mut visited = HashSet::new();
+        while let Some(mut pc) = queue.pop() {
+            while visited.insert(pc) {
+                let opcode = match bus.try_read_u8(pc) {
+                    Some(opcode) if !opcodes::is_illegal(opcode) => opcode,
+                    _ => break,
+                };
+                let mode = OPCODES[opcode as usize].mode;
+                let operand = pc.wrapping_add(1);
+                if opcode == OpCode::JSR_abs as u8 {
+                    if let Some(target) = read_u16(operand) {
+                        self.add(target, LabelKind::Subroutine);
+                        queue.push(target);
+                    }
+                } else if opcode == OpCode::JMP_abs as u8 {
+                    if let Some(target) = read_u16(operand) {
+                        queue.push(target);
+                    }
+                    break;
+                } else if opcode == OpCode::JMP_ind as u8
+                    || opcode == OpCode::RTS as u8
+                    || opcode == OpCode::RTI as u8
+                    || opcode == OpCode::BRK as u8
+                {
+                    break;
+                } else if mode == Mode::Relative {
+                    // Branches are relative to the instruction, like Cpu6502 does.
+                    if let Some(offset) = bus.try_read_u8(operand) {
+                        queue.push(pc.wrapping_add(offset as i8 as u16));
+                    }
+                }
+                pc = operand.wrapping_add(mode.operand_size());
+            }
+        }
+    }
+
+    /// Add a label for everything that was found, and return how many were added. The
+    /// addresses that already have a label are left alone, and a name that's already
+    /// taken gets the address added to it, e.g. "nmi_8123".
+    pub fn seed(&self, symbols: &mut Symbols) -> usize {
+        let mut added = 0;
+        for (&address, &kind) in &self.found {
+            if symbols.label(address).is_some() {
+                continue;
+            }
+            let mut name = kind.name(address);
+            if symbols.get(&name).is_some() {
+                name = format!("{}_{:04x}", name, address);
+            }
+            let mut symbol = Symbol::label(&name, address);
+            symbol.comment = Some(kind.comment().into());
+            if symbols.insert(symbol).is_ok() {
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+    use nes_core::cpu_6502::{Cpu6502, StopCondition};
+    use nes_core::mappers::SimpleProgram;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// The subroutine is reached through a jump table in RAM.
+    const PROGRAM: &str = "
+        jsr init
+        jmp ($0010)
+      init:
+        lda #$0f
+        sta $10
+        lda #$80
+        sta $11
+        rts
+      handler:
+        lda #$01
+        .byte $02
+    ";
+
+    fn cpu() -> (Cpu6502, Symbols) {
+        let mut lexer = AsmLexer::new(PROGRAM);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, symbols, .. } = lexer.into_bytes().unwrap();
+        let mut program = SimpleProgram::load(&bytes);
+        let handler = symbols.address("handler").unwrap();
+        assert_eq!(handler, 0x800f);
+        program.set_u16(InterruptVectors::NmiVector as u16, handler);
+        let cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(program)));
+        (cpu, symbols)
+    }
+
+    #[test]
+    fn test_trace_static() {
+        let (cpu, _) = cpu();
+        let mut labeler = AutoLabeler::new();
+        labeler.trace_static(&cpu.bus.borrow());
+        let found: Vec<(u16, LabelKind)> = labeler
+            .found()
+            .iter()
+            .map(|(&address, &kind)| (address, kind))
+            .collect();
+        // SimpleProgram points the reset vector at the start of the program.
+        assert_eq!(
+            found,
+            [
+                (0x8000, LabelKind::Reset),
+                (0x8006, LabelKind::Subroutine),
+                (0x800f, LabelKind::Nmi),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_instruction() {
+        let (mut cpu, mut symbols) = cpu();
+        let labeler = Rc::new(RefCell::new(AutoLabeler::new()));
+        let hook_labeler = Rc::clone(&labeler);
+        cpu.set_trace_hook(move |event| {
+            hook_labeler.borrow_mut().record_instruction(event)
+        });
+        cpu.run(StopCondition::Jam);
+
+        let labeler = labeler.borrow();
+        assert_eq!(labeler.found().get(&0x800f), Some(&LabelKind::JumpTarget));
+        // The assembler's labels are kept.
+        assert_eq!(labeler.seed(&mut symbols), 0);
+        assert_eq!(symbols.label(0x8006), Some("init"));
+
+        let mut symbols = Symbols::new();
+        assert_eq!(labeler.seed(&mut symbols), 2);
+        assert_eq!(symbols.label(0x8006), Some("sub_8006"));
+        assert_eq!(symbols.label(0x800f), Some("jump_800f"));
+        assert_eq!(
+            symbols.get("sub_8006").unwrap().comment.as_deref(),
+            Some("Called with JSR")
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Find the first frame of a movie where a bug shows up. The movie is played once to
+//! take checkpoints, and then the checkpoints are binary searched for the frame where
+//! the condition first becomes true. Only the frames between two checkpoints ever
+//! need to be run again, so this is much faster than replaying the movie for every
+//! guess. It assumes the condition stays true once it's true, like a desync does.
+use crate::scenario::frame_hash;
+use nes_core::checkpoint::Checkpoint;
+use nes_core::cpu_6502::Cpu6502;
+use nes_core::movie::{Movie, SOFT_RESET};
+use nes_core::region::NTSC_FRAME_CYCLES;
+
+/// What to look for at the end of each frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    MemoryEquals {
+        address: u16,
+        value: u8,
+    },
+    /// The frame hash doesn't match the hash from a good run, e.g. one recorded with
+    /// Bisector::frame_hashes. The frames past the end of the list are never a
+    /// mismatch.
+    FrameHashMismatch {
+        hashes: Vec<u32>,
+    },
+}
+
+impl Condition {
+    pub fn check(&self, cpu: &Cpu6502, frame: u64) -> bool {
+        match self {
+            Condition::MemoryEquals { address, value } => cpu.peek(*address) == *value,
+            Condition::FrameHashMismatch { hashes } => hashes
+                .get(frame as usize)
+                .is_some_and(|&hash| hash != frame_hash(cpu)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BisectReport {
+    /// The first frame where the condition was true, or None if it never was.
+    pub frame: Option<u64>,
+    /// How many times the condition was checked.
+    pub checks: u64,
+    /// How many frames were run, including the first pass through the movie.
+    pub frames_run: u64,
+}
+
+pub struct Bisector<'a> {
+    movie: &'a Movie,
+    /// The movie is replayed on a bare CPU without the controller ports, so the
+    /// buttons for the first port are written here at the start of every frame.
+    pub input_address: u16,
+    /// How many frames apart the checkpoints are.
+    pub interval: u64,
+}
+
+impl<'a> Bisector<'a> {
+    pub fn new(movie: &'a Movie) -> Bisector<'a> {
+        Bisector {
+            movie,
+            input_address: 0x00ff,
+            interval: 60,
+        }
+    }
+
+    /// Find the first frame where the condition is true at the end of the frame. The
+    /// condition is given the CPU and the frame number. Afterwards, the CPU is left at
+    /// the end of the frame that was found, so that it can be inspected.
+    pub fn run(
+        &self,
+        cpu: &mut Cpu6502,
+        mut condition: impl FnMut(&Cpu6502, u64) -> bool,
+    ) -> BisectReport {
+        let frames = self.movie.frames.len() as u64;
+        let interval = self.interval.max(1);
+        let mut report = BisectReport {
+            frame: None,
+            checks: 0,
+            frames_run: 0,
+        };
+
+        // Each checkpoint is saved with the number of frames that have run.
+        let mut checkpoints = vec![(0, Checkpoint::save(cpu))];
+        for frame in 0..frames {
+            self.run_frame(cpu, frame);
+            report.frames_run += 1;
+            let frames_done = frame + 1;
+            if frames_done % interval == 0 || frames_done == frames {
+                checkpoints.push((frames_done, Checkpoint::save(cpu)));
+            }
+        }
+        if frames == 0 {
+            return report;
+        }
+        report.checks += 1;
+        if !condition(cpu, frames - 1) {
+            return report;
+        }
+
+        // Find the first checkpoint where the condition is true. The first checkpoint
+        // is from before any frames ran, so it's never checked.
+        let mut low = 0;
+        let mut high = checkpoints.len() - 1;
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            let (frames_done, ref checkpoint) = checkpoints[middle];
+            checkpoint
+                .restore(cpu)
+                .expect("The checkpoint was saved from this CPU.");
+            report.checks += 1;
+            if condition(cpu, frames_done - 1) {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+
+        // Then search the frames in between, running forward from the latest frame
+        // where the condition was still false.
+        let (mut low, ref low_checkpoint) = checkpoints[low];
+        let mut low_checkpoint = low_checkpoint.clone();
+        let mut high = checkpoints[high].0;
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            low_checkpoint
+                .restore(cpu)
+                .expect("The checkpoint was saved from this CPU.");
+            for frame in low..middle {
+                self.run_frame(cpu, frame);
+                report.frames_run += 1;
+            }
+            report.checks += 1;
+            if condition(cpu, middle - 1) {
+                high = middle;
+            } else {
+                low = middle;
+                low_checkpoint = Checkpoint::save(cpu);
+            }
+        }
+
+        low_checkpoint
+            .restore(cpu)
+            .expect("The checkpoint was saved from this CPU.");
+        self.run_frame(cpu, low);
+        report.frames_run += 1;
+        report.frame = Some(high - 1);
+        report
+    }
+
+    /// Play the whole movie, and record the hash at the end of every frame. Use this
+    /// on a good build to get the hashes for Condition::FrameHashMismatch.
+    pub fn frame_hashes(&self, cpu: &mut Cpu6502) -> Vec<u32> {
+        (0..self.movie.frames.len() as u64)
+            .map(|frame| {
+                self.run_frame(cpu, frame);
+                frame_hash(cpu)
+            })
+            .collect()
+    }
+
+    /// There's no PPU yet, so when the program turns on the NMI through PPUCTRL, it's
+    /// signaled at the end of every frame, like the monkey tester does.
+    fn run_frame(&self, cpu: &mut Cpu6502, frame: u64) {
+        if self.commands(frame) & SOFT_RESET != 0 {
+            cpu.reset();
+        }
+        cpu.poke(self.input_address, self.buttons(frame));
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        if cpu.bus.borrow().is_nmi_enabled() {
+            cpu.set_nmi();
+        }
+    }
+
+    fn commands(&self, frame: u64) -> u8 {
+        self.movie
+            .frames
+            .get(frame as usize)
+            .map_or(0, |movie_frame| movie_frame.commands)
+    }
+
+    /// The first latch of the frame. A lag frame gets the last input that was seen,
+    /// like the MoviePlayer.
+    fn buttons(&self, frame: u64) -> u8 {
+        let frames = &self.movie.frames;
+        let frame = frame as usize;
+        if let Some(ports) = frames
+            .get(frame)
+            .and_then(|movie_frame| movie_frame.latches.first())
+        {
+            return ports[0];
+        }
+        frames[..frame.min(frames.len())]
+            .iter()
+            .rev()
+            .find_map(|movie_frame| movie_frame.latches.last())
+            .map_or(0, |ports| ports[0])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+    use nes_core::bus::Bus;
+    use nes_core::constants::InterruptVectors;
+    use nes_core::mappers::SimpleProgram;
+
+    /// Count the frames where A is held in $10, and crash the count in $20 once it
+    /// gets to 3.
+    const PROGRAM: &str = "
+        lda #$80
+        sta $2000
+      loop:
+        jmp loop
+      nmi:
+        lda $ff
+        and #$01
+        beq return
+        inc $10
+        lda $10
+        cmp #$03
+        bne return
+        lda #$ff
+        sta $20
+      return:
+        rti
+    ";
+
+    fn cpu() -> Cpu6502 {
+        let mut lexer = AsmLexer::new(PROGRAM);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, symbols, .. } = lexer.into_bytes().unwrap();
+        let mut program = SimpleProgram::load(&bytes);
+        let nmi = symbols.address("nmi").unwrap();
+        program.set_u16(InterruptVectors::NmiVector as u16, nmi);
+        Cpu6502::new(Bus::new_shared_bus(Box::new(program)))
+    }
+
+    fn movie(frames: usize, pressed: &[usize]) -> Movie {
+        let input: String = (0..frames)
+            .map(|frame| {
+                if pressed.contains(&frame) {
+                    "|0|.......A|........||\n"
+                } else {
+                    "|0|........|........||\n"
+                }
+            })
+            .collect();
+        Movie::parse_fm2(&format!("version 3\n{}", input)).unwrap()
+    }
+
+    #[test]
+    fn test_finds_frame() {
+        let movie = movie(400, &[10, 100, 331, 390]);
+        let bisector = Bisector::new(&movie);
+        let mut cpu = cpu();
+        let condition = Condition::MemoryEquals {
+            address: 0x20,
+            value: 0xff,
+        };
+        let report = bisector.run(&mut cpu, |cpu, frame| condition.check(cpu, frame));
+        // The NMI from the end of the frame before runs first, and sees the input.
+        assert_eq!(report.frame, Some(331));
+        assert_eq!(cpu.peek(0x20), 0xff);
+        // It's a lot less than replaying the movie for each guess.
+        assert!(report.checks < 20, "{:?}", report);
+        assert!(report.frames_run < 500, "{:?}", report);
+    }
+
+    #[test]
+    fn test_never_true() {
+        let movie = movie(100, &[10]);
+        let report = Bisector::new(&movie).run(&mut cpu(), |cpu, _| cpu.peek(0x20) != 0);
+        assert_eq!(
+            report,
+            BisectReport {
+                frame: None,
+                checks: 1,
+                frames_run: 100
+            }
+        );
+    }
+
+    #[test]
+    fn test_frame_hash_mismatch() {
+        let good = movie(200, &[]);
+        let hashes = Bisector::new(&good).frame_hashes(&mut cpu());
+        let bad = movie(200, &[150]);
+        let condition = Condition::FrameHashMismatch { hashes };
+        let report =
+            Bisector::new(&bad).run(&mut cpu(), |cpu, frame| condition.check(cpu, frame));
+        assert_eq!(report.frame, Some(150));
+    }
+}
@@ -0,0 +1,182 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::memory_map;
+use nes_core::watchpoints::AccessKind;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+/// One read or write that went over the bus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusAccess {
+    /// The CPU cycle that the instruction making the access started on.
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+    /// Either a Read or a Write.
+    pub access: AccessKind,
+}
+
+/// A log of the last accesses that went over the bus, which is the quickest way to see
+/// what a program is doing to the mapper and the registers. Start it with
+/// Bus::set_observer, and the CPU tells it the cycle of each instruction it runs.
+/// Only the reads and writes the CPU makes are logged, not the peeks from tools.
+pub struct BusTrace {
+    /// Reads come through the bus without mutable access.
+    accesses: RefCell<VecDeque<BusAccess>>,
+    capacity: usize,
+    cycle: u64,
+}
+
+impl BusTrace {
+    /// Keep the last `capacity` accesses, the older ones are dropped.
+    pub fn new(capacity: usize) -> BusTrace {
+        assert!(capacity > 0, "The bus trace needs room for an access.");
+        BusTrace {
+            accesses: RefCell::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            cycle: 0,
+        }
+    }
+
+    pub fn record_instruction(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    pub fn record(&self, address: u16, value: u8, access: AccessKind) {
+        let mut accesses = self.accesses.borrow_mut();
+        if accesses.len() == self.capacity {
+            accesses.pop_front();
+        }
+        accesses.push_back(BusAccess {
+            cycle: self.cycle,
+            address,
+            value,
+            access,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.accesses.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accesses.borrow().is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses.borrow_mut().clear();
+    }
+
+    /// Every access in the log, from the oldest to the newest.
+    pub fn accesses(&self) -> Vec<BusAccess> {
+        self.filter(|_| true)
+    }
+
+    pub fn filter(&self, predicate: impl Fn(&BusAccess) -> bool) -> Vec<BusAccess> {
+        self.accesses
+            .borrow()
+            .iter()
+            .filter(|access| predicate(access))
+            .copied()
+            .collect()
+    }
+
+    /// The accesses to a range of addresses, e.g. $8000-$FFFF for the mapper's
+    /// registers.
+    pub fn accesses_to(&self, range: RangeInclusive<u16>) -> Vec<BusAccess> {
+        self.filter(|access| range.contains(&access.address))
+    }
+
+    /// Write out the accesses one per line, with the registers named, e.g.
+    /// "      1234  W $2000 = $80  PPUCTRL".
+    pub fn dump(accesses: &[BusAccess]) -> String {
+        let mut dump = String::new();
+        for access in accesses {
+            let kind = match access.access {
+                AccessKind::Read => 'R',
+                _ => 'W',
+            };
+            dump.push_str(&format!(
+                "{:>10}  {} ${:04x} = ${:02x}",
+                access.cycle, kind, access.address, access.value
+            ));
+            if let Some(register) = memory_map::register_for(access.address) {
+                dump.push_str(&format!("  {}", register.name));
+            }
+            dump.push('\n');
+        }
+        dump
+    }
+}
+
+impl BusObserver for BusTrace {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        BusTrace::record_instruction(self, instruction.cycle);
+    }
+
+    fn record_read(&self, access: &ObservedAccess) {
+        self.record(access.address, access.value, AccessKind::Read);
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        self.record(access.address, access.value, AccessKind::Write);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+    use nes_core::cpu_6502::StopCondition;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut trace = BusTrace::new(3);
+        trace.record_instruction(7);
+        trace.record(0x8000, 0xa9, AccessKind::Read);
+        trace.record(0x8001, 0x80, AccessKind::Read);
+        trace.record_instruction(9);
+        trace.record(0x8002, 0x8d, AccessKind::Read);
+        trace.record(0x2000, 0x80, AccessKind::Write);
+
+        // The oldest access was dropped.
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace.accesses()[0].address, 0x8001);
+        let writes = trace.filter(|access| access.access == AccessKind::Write);
+        assert_eq!(
+            BusTrace::dump(&writes),
+            "         9  W $2000 = $80  PPUCTRL\n"
+        );
+        assert_eq!(trace.accesses_to(0x8000..=0xffff).len(), 2);
+
+        trace.clear();
+        assert!(trace.is_empty());
+    }
+
+    #[test]
+    fn logs_the_accesses() {
+        let (mut cpu, _) = assemble(
+            "
+            lda #$80
+            sta $2000
+            lda $0010
+            ",
+        )
+        .unwrap();
+        cpu.bus.borrow_mut().set_observer(Some(BusTrace::new(64)));
+        cpu.run(StopCondition::Jam);
+
+        let bus = cpu.bus.borrow();
+        let bus_trace = bus.observer::<BusTrace>().unwrap();
+        let writes = bus_trace.filter(|access| access.access == AccessKind::Write);
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].address, 0x2000);
+        assert_eq!(writes[0].value, 0x80);
+        // The store started after the two cycle load.
+        let first_cycle = bus_trace.accesses()[0].cycle;
+        assert_eq!(writes[0].cycle, first_cycle + 2);
+        let ram_reads = bus_trace.accesses_to(0x0010..=0x0010);
+        assert_eq!(ram_reads.len(), 1);
+        assert_eq!(ram_reads[0].access, AccessKind::Read);
+    }
+}
@@ -0,0 +1,210 @@
+//! Turn a panic inside the emulator into a report that can be attached to a bug. The
+//! binaries install a panic hook, which remembers where the panic happened, and catch
+//! the panics around the CPU, so that the machine can still be looked at. Then the
+//! CrashReporter writes out a directory with:
+//!
+//! - report.txt, with the panic, the backtrace, and the registers.
+//! - trace.txt, with the last accesses over the bus, see BusTrace.
+//! - savestate.ness, with a checkpoint of the CPU and the bus, see
+//!   Checkpoint::write_chunk. It loads back into the same machine, to pick up right
+//!   before the crash.
+use crate::bus_trace::BusTrace;
+use nes_core::checkpoint::Checkpoint;
+use nes_core::cpu_6502::Cpu6502;
+use nes_core::savestate::SavestateFile;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many bus accesses to keep for the report, which is a few hundred instructions.
+const TRACE_CAPACITY: usize = 1024;
+
+/// What the panic hook saw.
+struct PanicDetails {
+    message: String,
+    location: String,
+    backtrace: String,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
+/// Remember the details of every panic on its thread, for the CrashReporter. The
+/// panic is still printed like before. Installing it more than once does nothing.
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = if let Some(message) = info.payload().downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = info.payload().downcast_ref::<String>() {
+                message.clone()
+            } else {
+                String::from("Unknown panic")
+            };
+            let details = PanicDetails {
+                message,
+                location: info
+                    .location()
+                    .map_or(String::from("unknown"), |location| location.to_string()),
+                backtrace: Backtrace::force_capture().to_string(),
+            };
+            LAST_PANIC.with(|last_panic| *last_panic.borrow_mut() = Some(details));
+            previous_hook(info);
+        }));
+    });
+}
+
+/// Writes the crash reports into a directory, one directory per crash.
+pub struct CrashReporter {
+    dir: PathBuf,
+}
+
+impl CrashReporter {
+    pub fn new(dir: &Path) -> CrashReporter {
+        CrashReporter {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Keep the reports in the system's temporary directory.
+    pub fn in_temp_dir() -> CrashReporter {
+        CrashReporter::new(&std::env::temp_dir().join("nes-crashes"))
+    }
+
+    /// Start a bus trace on the CPU, if it doesn't already have one, so that the report
+    /// has the accesses leading up to the crash.
+    pub fn prepare(&self, cpu: &mut Cpu6502) {
+        let mut bus = cpu.bus.borrow_mut();
+        if bus.observer::<BusTrace>().is_none() {
+            bus.set_observer(Some(BusTrace::new(TRACE_CAPACITY)));
+        }
+    }
+
+    /// Write out the report for the last panic on this thread, and return its
+    /// directory. Call this after the panic has been caught.
+    pub fn write(&self, cpu: &Cpu6502) -> io::Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let mut dir = self.dir.join(format!("crash-{}", seconds));
+        let mut attempt = 1;
+        while dir.exists() {
+            attempt += 1;
+            dir = self.dir.join(format!("crash-{}-{}", seconds, attempt));
+        }
+        fs::create_dir_all(&dir)?;
+
+        let details = LAST_PANIC.with(|last_panic| last_panic.borrow_mut().take());
+        let mut report = String::new();
+        match details {
+            Some(details) => {
+                writeln!(report, "panic: {}", details.message).unwrap();
+                writeln!(report, "at: {}", details.location).unwrap();
+                writeln!(report).unwrap();
+                write!(report, "{}", registers_dump(cpu)).unwrap();
+                writeln!(report).unwrap();
+                writeln!(report, "backtrace:\n{}", details.backtrace).unwrap();
+            }
+            None => {
+                writeln!(report, "panic: unknown, the panic hook wasn't installed")
+                    .unwrap();
+                writeln!(report).unwrap();
+                write!(report, "{}", registers_dump(cpu)).unwrap();
+            }
+        }
+        fs::write(dir.join("report.txt"), report)?;
+
+        let bus = cpu.bus.borrow();
+        if let Some(bus_trace) = bus.observer::<BusTrace>() {
+            fs::write(dir.join("trace.txt"), BusTrace::dump(&bus_trace.accesses()))?;
+        }
+
+        let mut savestate = SavestateFile::new(0);
+        Checkpoint::save(cpu).write_chunk(&mut savestate);
+        fs::write(dir.join("savestate.ness"), savestate.to_bytes())?;
+
+        Ok(dir)
+    }
+}
+
+fn registers_dump(cpu: &Cpu6502) -> String {
+    let registers = cpu.registers();
+    format!(
+        "PC:{:04x} A:{:02x} X:{:02x} Y:{:02x} S:{:02x} P:{:08b}\n\
+         cycles: {}\n\
+         instructions: {}\n\
+         state: {:?}\n",
+        registers.pc,
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.s,
+        registers.p,
+        cpu.total_cycles(),
+        cpu.tick_count(),
+        cpu.state,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    fn test_write() {
+        install_panic_hook();
+        let (mut cpu, _) = assemble(
+            "
+              lda #$12
+              sta $10
+            ",
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("nes-crash-{}", std::process::id()));
+        let reporter = CrashReporter::new(&dir);
+        reporter.prepare(&mut cpu);
+        cpu.tick();
+        cpu.tick();
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| panic!("The emulator broke.")));
+        assert!(result.is_err());
+
+        let crash_dir = reporter.write(&cpu).unwrap();
+        let report = fs::read_to_string(crash_dir.join("report.txt")).unwrap();
+        assert!(report.starts_with(
+            "panic: The emulator broke.\nat: crates/nes-debugger/src/crash_report.rs"
+        ));
+        assert!(report.contains("PC:8004 A:12"));
+        let trace = fs::read_to_string(crash_dir.join("trace.txt")).unwrap();
+        assert!(trace.contains("W $0010 = $12"));
+        let savestate = SavestateFile::from_bytes(
+            &fs::read(crash_dir.join("savestate.ness")).unwrap(),
+        )
+        .unwrap();
+        // The savestate loads back into the machine, at the crash.
+        let registers = cpu.registers();
+        cpu.reset();
+        cpu.poke(0x10, 0x00);
+        Checkpoint::read_chunk(&savestate)
+            .unwrap()
+            .restore(&mut cpu)
+            .unwrap();
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(cpu.peek(0x10), 0x12);
+
+        // The next crash gets its own directory.
+        assert_ne!(reporter.write(&cpu).unwrap(), crash_dir);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
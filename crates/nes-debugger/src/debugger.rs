@@ -0,0 +1,309 @@
+//! Breakpoints, watchpoints, and stepping for anything that embeds the emulator, like
+//! a GUI, or bindings for another language. Every run returns a BreakReason, so the
+//! embedder can decide what to show without inspecting the CPU itself.
+//!
+//! The watchpoints live on the bus, see Bus::add_watchpoint, and the Debugger stops on
+//! them along with its own breakpoints.
+use nes_core::cpu_6502::{Cpu6502, CpuState};
+use nes_core::opcodes::OpCode;
+use nes_core::savestate::BreakpointInfo;
+use nes_core::uninit_ram::UninitRead;
+use nes_core::watchpoints::WatchpointHit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakpointId(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub pc: u16,
+    pub enabled: bool,
+    /// A note about why the breakpoint is there, or the label it was set on.
+    pub label: String,
+}
+
+/// Why a run stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakReason {
+    /// The cycle budget ran out.
+    BudgetElapsed,
+    /// The step finished.
+    Step,
+    /// Stopped before running the instruction at the breakpoint.
+    Breakpoint {
+        id: BreakpointId,
+        pc: u16,
+    },
+    /// An instruction touched a watched address. The pc is where the instruction
+    /// started, and the hit is the first watched access it made.
+    Watchpoint {
+        pc: u16,
+        hit: WatchpointHit,
+    },
+    /// An instruction read RAM that was never written, see UninitRamTrap.
+    UninitializedRead(UninitRead),
+    Jammed,
+    IllegalOpcode(u8),
+}
+
+/// Breakpoints, and the runs that stop on them. Every run goes at least one
+/// instruction before it stops on a breakpoint, so that a run can be continued from
+/// the breakpoint it stopped on.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16, label: &str) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            pc,
+            enabled: true,
+            label: label.into(),
+        });
+        id
+    }
+
+    /// Returns false if there was no such breakpoint.
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let length = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != length
+    }
+
+    /// Returns false if there was no such breakpoint.
+    pub fn set_breakpoint_enabled(&mut self, id: BreakpointId, enabled: bool) -> bool {
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.id == id)
+        {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// The breakpoints, to keep with a savestate, see DebugContext.
+    pub fn breakpoint_info(&self) -> Vec<BreakpointInfo> {
+        self.breakpoints
+            .iter()
+            .map(|breakpoint| BreakpointInfo {
+                pc: breakpoint.pc,
+                enabled: breakpoint.enabled,
+                label: breakpoint.label.clone(),
+            })
+            .collect()
+    }
+
+    /// Replace the breakpoints with the ones from a savestate. They get new ids.
+    pub fn load_breakpoint_info(&mut self, breakpoints: &[BreakpointInfo]) {
+        self.breakpoints.clear();
+        for info in breakpoints {
+            let id = self.add_breakpoint(info.pc, &info.label);
+            self.set_breakpoint_enabled(id, info.enabled);
+        }
+    }
+
+    fn breakpoint_at(&self, pc: u16) -> Option<BreakpointId> {
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.enabled && breakpoint.pc == pc)
+            .map(|breakpoint| breakpoint.id)
+    }
+
+    /// Run until a breakpoint or watchpoint is hit, or at least the budget of cycles
+    /// has run. Unlike Emulator::run_frame, this doesn't end the frame for the stats,
+    /// snapshots, or the profiler, so it can stop anywhere in a frame.
+    pub fn run_for_cycles(&self, cpu: &mut Cpu6502, budget: u64) -> BreakReason {
+        self.run(cpu, budget, |_, _| false)
+    }
+
+    /// Run a single instruction.
+    pub fn step(&self, cpu: &mut Cpu6502) -> BreakReason {
+        match self.tick(cpu) {
+            Some(reason) => reason,
+            None => BreakReason::Step,
+        }
+    }
+
+    /// Run a single instruction, but run a whole subroutine when it's a JSR, stopping
+    /// once it returns.
+    pub fn step_over(&self, cpu: &mut Cpu6502, budget: u64) -> BreakReason {
+        if cpu.peek(cpu.pc) != OpCode::JSR_abs as u8 {
+            return self.step(cpu);
+        }
+        let return_pc = cpu.pc.wrapping_add(3);
+        let s = cpu.s();
+        self.run(cpu, budget, |cpu, _| cpu.pc == return_pc && cpu.s() == s)
+    }
+
+    /// Run until the current subroutine or interrupt handler returns.
+    pub fn step_out(&self, cpu: &mut Cpu6502, budget: u64) -> BreakReason {
+        let s = cpu.s();
+        self.run(cpu, budget, |cpu, opcode| {
+            (opcode == OpCode::RTS as u8 || opcode == OpCode::RTI as u8) && cpu.s() > s
+        })
+    }
+
+    /// Run until the step is done, which is checked with the opcode of the instruction
+    /// that just ran.
+    fn run(
+        &self,
+        cpu: &mut Cpu6502,
+        budget: u64,
+        step_done: impl Fn(&Cpu6502, u8) -> bool,
+    ) -> BreakReason {
+        let start = cpu.total_cycles();
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(id) = self.breakpoint_at(cpu.pc) {
+                    return BreakReason::Breakpoint { id, pc: cpu.pc };
+                }
+            }
+            if cpu.total_cycles() - start >= budget {
+                return BreakReason::BudgetElapsed;
+            }
+            first = false;
+            let opcode = cpu.peek(cpu.pc);
+            if let Some(reason) = self.tick(cpu) {
+                return reason;
+            }
+            if step_done(cpu, opcode) {
+                return BreakReason::Step;
+            }
+        }
+    }
+
+    /// Run an instruction, and return why it should stop, if it should.
+    fn tick(&self, cpu: &mut Cpu6502) -> Option<BreakReason> {
+        let pc = cpu.pc;
+        match cpu.tick() {
+            CpuState::Running | CpuState::WaitingForInterrupt => {}
+            CpuState::Jammed => return Some(BreakReason::Jammed),
+            CpuState::IllegalOpcode(opcode) => {
+                return Some(BreakReason::IllegalOpcode(opcode))
+            }
+        }
+        let bus = cpu.bus.borrow();
+        if bus.has_watchpoint_hits() {
+            if let Some(hit) = bus.take_watchpoint_hits().into_iter().next() {
+                return Some(BreakReason::Watchpoint { pc, hit });
+            }
+        }
+        bus.take_uninit_break().map(BreakReason::UninitializedRead)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+    use nes_core::watchpoints::AccessKind;
+
+    fn cpu() -> (Cpu6502, nes_asm::symbols::Symbols) {
+        assemble(
+            "
+            loop:
+              jsr add
+              sta $10
+              jmp loop
+            add:
+              clc
+              adc #1
+              rts
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (mut cpu, symbols) = cpu();
+        let loop_pc = symbols.address("loop").unwrap();
+        let add_pc = symbols.address("add").unwrap();
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(add_pc, "add");
+
+        assert_eq!(
+            debugger.run_for_cycles(&mut cpu, 1000),
+            BreakReason::Breakpoint { id, pc: add_pc }
+        );
+        // The run continues from the breakpoint, and stops there again next time.
+        assert_eq!(
+            debugger.run_for_cycles(&mut cpu, 1000),
+            BreakReason::Breakpoint { id, pc: add_pc }
+        );
+        assert_eq!(cpu.a, 1);
+
+        assert!(debugger.set_breakpoint_enabled(id, false));
+        assert_eq!(
+            debugger.run_for_cycles(&mut cpu, 100),
+            BreakReason::BudgetElapsed
+        );
+
+        let watchpoint = cpu
+            .bus
+            .borrow_mut()
+            .add_watchpoint(0x10..=0x10, AccessKind::Write);
+        match debugger.run_for_cycles(&mut cpu, 1000) {
+            BreakReason::Watchpoint { pc, hit } => {
+                assert_eq!(pc, loop_pc + 3);
+                assert_eq!(hit.id, watchpoint);
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+
+        // The breakpoints can go into a savestate, and come back.
+        let info = debugger.breakpoint_info();
+        assert_eq!(info[0].pc, add_pc);
+        assert!(!info[0].enabled);
+        let mut debugger = Debugger::new();
+        debugger.load_breakpoint_info(&info);
+        assert_eq!(debugger.breakpoints()[0].label, "add");
+        assert!(debugger.remove_breakpoint(debugger.breakpoints()[0].id));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_stepping() {
+        let (mut cpu, symbols) = cpu();
+        let loop_pc = symbols.address("loop").unwrap();
+        let add_pc = symbols.address("add").unwrap();
+        let debugger = Debugger::new();
+
+        // Step over the whole subroutine.
+        assert_eq!(debugger.step_over(&mut cpu, 1000), BreakReason::Step);
+        assert_eq!(cpu.pc, loop_pc + 3);
+        assert_eq!(cpu.a, 1);
+        assert_eq!(debugger.step_over(&mut cpu, 1000), BreakReason::Step);
+        assert_eq!(cpu.pc, loop_pc + 5);
+
+        // Step into it, and then back out.
+        debugger.step(&mut cpu);
+        assert_eq!(debugger.step(&mut cpu), BreakReason::Step);
+        assert_eq!(cpu.pc, add_pc);
+        debugger.step(&mut cpu);
+        assert_eq!(debugger.step_out(&mut cpu, 1000), BreakReason::Step);
+        assert_eq!(cpu.pc, loop_pc + 3);
+        assert_eq!(cpu.a, 2);
+
+        // A step out that never returns runs out of budget.
+        assert_eq!(debugger.step_out(&mut cpu, 100), BreakReason::BudgetElapsed);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::crash_report::CrashReporter;
+use crate::scenario;
+use nes_core::bus::Bus;
+use nes_core::cpu_6502::{Cpu6502, CpuState, IllegalOpcodePolicy};
+use nes_core::mappers::SimpleProgram;
+use nes_core::region::NTSC_FRAME_CYCLES;
+use nes_core::virtual_console::{ConsoleHandle, VirtualConsole};
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+/// The SimpleProgram cartridge space.
+const MAX_PROGRAM_SIZE: usize = 0x8000;
+
+/// Hard limits for running a ROM that can't be trusted. The run stops with a
+/// failure as soon as any limit is hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_instructions: u64,
+    pub max_cycles: u64,
+    pub max_frames: u64,
+    /// The ROM is rejected before anything is loaded if it's larger than this.
+    pub max_rom_size: usize,
+    /// The bytes the program can print to its VirtualConsole, which is the only
+    /// memory that grows as it runs.
+    pub max_output: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_instructions: 10_000_000,
+            max_cycles: 100_000_000,
+            // 10 seconds of NTSC frames.
+            max_frames: 600,
+            max_rom_size: 1024 * 1024,
+            max_output: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    RomTooLarge {
+        size: usize,
+        max: usize,
+    },
+    InvalidRom {
+        message: String,
+    },
+    InstructionLimit {
+        pc: u16,
+    },
+    CycleLimit {
+        pc: u16,
+    },
+    FrameLimit {
+        pc: u16,
+    },
+    OutputLimit {
+        pc: u16,
+    },
+    /// The program ran an undocumented opcode with IllegalOpcodePolicy::Trap.
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+    },
+    /// The program exited through the VirtualConsole with a code other than 0.
+    ExitCode {
+        pc: u16,
+        code: u8,
+    },
+    /// The emulator itself panicked, e.g. from a memory access that isn't handled
+    /// yet. The panic is caught so that it doesn't take down the caller.
+    Crashed {
+        pc: u16,
+        message: String,
+    },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::RomTooLarge { size, max } => write!(
+                f,
+                "The ROM is {} bytes, which is larger than the {} byte limit.",
+                size, max
+            ),
+            Failure::InvalidRom { message } => write!(f, "Invalid ROM: {}", message),
+            Failure::InstructionLimit { pc } => {
+                write!(f, "Hit the instruction limit at ${:04x}.", pc)
+            }
+            Failure::CycleLimit { pc } => {
+                write!(f, "Hit the cycle limit at ${:04x}.", pc)
+            }
+            Failure::FrameLimit { pc } => {
+                write!(f, "Hit the frame limit at ${:04x}.", pc)
+            }
+            Failure::OutputLimit { pc } => {
+                write!(f, "Hit the output limit at ${:04x}.", pc)
+            }
+            Failure::IllegalOpcode { pc, opcode } => {
+                write!(f, "Ran the illegal opcode ${:02x} at ${:04x}.", opcode, pc)
+            }
+            Failure::ExitCode { pc, code } => {
+                write!(f, "Exited with the code {} at ${:04x}.", code, pc)
+            }
+            Failure::Crashed { pc, message } => {
+                write!(f, "The emulator crashed at ${:04x}: {}", pc, message)
+            }
+        }
+    }
+}
+
+/// The result of a headless run. The counters are filled in even when there is
+/// a failure, so that pipelines can see how far the ROM got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub frames: u64,
+    pub pc: u16,
+    /// What the program printed to the VirtualConsole.
+    pub output: String,
+    pub failure: Option<Failure>,
+    /// Where the crash report was written, or why it couldn't be, when the emulator
+    /// crashed with a CrashReporter set.
+    pub crash_report: Option<Result<PathBuf, String>>,
+}
+
+impl Report {
+    fn rejected(failure: Failure) -> Report {
+        Report {
+            instructions: 0,
+            cycles: 0,
+            frames: 0,
+            pc: 0,
+            output: String::new(),
+            failure: Some(failure),
+            crash_report: None,
+        }
+    }
+}
+
+/// Runs ROMs without any frontend, for automated analysis and fuzzing. Nothing is
+/// read from or written to the file system, the ROM is passed in as bytes, and the
+/// results are only returned in the Report. The only exception is the crash report,
+/// when a CrashReporter is set. The programs from run_asm and run_program
+/// get a VirtualConsole to print to, and exiting through it ends the run.
+pub struct HeadlessRunner {
+    limits: Limits,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    crash_reporter: Option<CrashReporter>,
+}
+
+impl HeadlessRunner {
+    pub fn new(limits: Limits) -> HeadlessRunner {
+        HeadlessRunner {
+            limits,
+            illegal_opcode_policy: IllegalOpcodePolicy::Emulate,
+            crash_reporter: None,
+        }
+    }
+
+    /// The policy for the CPUs created by run_asm and run_program. Use Trap to fail
+    /// as soon as the program runs an undocumented opcode.
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    /// Write a crash report when the emulator panics, see crash_report.
+    pub fn set_crash_reporter(&mut self, crash_reporter: Option<CrashReporter>) {
+        self.crash_reporter = crash_reporter;
+    }
+
+    /// Assemble the program text, and run it.
+    pub fn run_asm(&self, text: &str) -> Report {
+        if text.len() > self.limits.max_rom_size {
+            return Report::rejected(Failure::RomTooLarge {
+                size: text.len(),
+                max: self.limits.max_rom_size,
+            });
+        }
+        match scenario::assemble(text) {
+            Ok((mut cpu, _)) => {
+                cpu.illegal_opcode_policy = self.illegal_opcode_policy;
+                self.run_with_console(&mut cpu)
+            }
+            Err(error) => Report::rejected(Failure::InvalidRom {
+                message: error.to_string(),
+            }),
+        }
+    }
+
+    /// Run a raw program that is loaded at $8000.
+    pub fn run_program(&self, bytes: &[u8]) -> Report {
+        let max = self.limits.max_rom_size.min(MAX_PROGRAM_SIZE);
+        if bytes.len() > max {
+            return Report::rejected(Failure::RomTooLarge {
+                size: bytes.len(),
+                max,
+            });
+        }
+        let mut cpu =
+            Cpu6502::new(Bus::new_shared_bus(Box::new(SimpleProgram::load(bytes))));
+        cpu.illegal_opcode_policy = self.illegal_opcode_policy;
+        self.run_with_console(&mut cpu)
+    }
+
+    /// Run the CPU until it halts, crashes, or hits one of the limits.
+    pub fn run_cpu(&self, cpu: &mut Cpu6502) -> Report {
+        self.run(cpu, None)
+    }
+
+    fn run_with_console(&self, cpu: &mut Cpu6502) -> Report {
+        let console = VirtualConsole::attach(&mut cpu.bus.borrow_mut());
+        match console {
+            Ok(console) => self.run(cpu, Some(console)),
+            Err(message) => Report::rejected(Failure::InvalidRom { message }),
+        }
+    }
+
+    fn run(&self, cpu: &mut Cpu6502, console: Option<ConsoleHandle>) -> Report {
+        let mut instructions = 0;
+        let mut cycles = 0;
+        let mut failure = None;
+        let mut crash_report = None;
+        if let Some(crash_reporter) = &self.crash_reporter {
+            crash_reporter.prepare(cpu);
+        }
+
+        loop {
+            if instructions >= self.limits.max_instructions {
+                failure = Some(Failure::InstructionLimit { pc: cpu.pc });
+                break;
+            }
+            if cycles >= self.limits.max_cycles {
+                failure = Some(Failure::CycleLimit { pc: cpu.pc });
+                break;
+            }
+            if cycles / NTSC_FRAME_CYCLES >= self.limits.max_frames {
+                failure = Some(Failure::FrameLimit { pc: cpu.pc });
+                break;
+            }
+
+            let pc = cpu.pc;
+            match panic::catch_unwind(AssertUnwindSafe(|| cpu.tick())) {
+                Ok(CpuState::Running) | Ok(CpuState::WaitingForInterrupt) => {}
+                // The program halted normally.
+                Ok(CpuState::Jammed) => break,
+                Ok(CpuState::IllegalOpcode(opcode)) => {
+                    failure = Some(Failure::IllegalOpcode { pc: cpu.pc, opcode });
+                    break;
+                }
+                Err(payload) => {
+                    failure = Some(Failure::Crashed {
+                        pc,
+                        message: panic_message(payload),
+                    });
+                    if let Some(crash_reporter) = &self.crash_reporter {
+                        crash_report = Some(
+                            crash_reporter.write(cpu).map_err(|error| error.to_string()),
+                        );
+                    }
+                    break;
+                }
+            }
+            instructions += 1;
+            cycles += cpu.cycles as u64;
+            if let Some(console) = &console {
+                if console.output_len() > self.limits.max_output {
+                    failure = Some(Failure::OutputLimit { pc });
+                    break;
+                }
+                if let Some(code) = console.exit_code() {
+                    if code != 0 {
+                        failure = Some(Failure::ExitCode { pc, code });
+                    }
+                    break;
+                }
+            }
+        }
+
+        Report {
+            instructions,
+            cycles,
+            frames: cycles / NTSC_FRAME_CYCLES,
+            pc: cpu.pc,
+            output: console.map(|console| console.output()).unwrap_or_default(),
+            failure,
+            crash_report,
+        }
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    String::from("Unknown panic")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            max_instructions: 1000,
+            max_cycles: 10_000,
+            max_frames: 1,
+            max_rom_size: 0x100,
+            max_output: 0x10,
+        }
+    }
+
+    #[test]
+    fn test_halts() {
+        let report = HeadlessRunner::new(limits()).run_asm("lda #$01\nsta $00");
+        assert_eq!(report.failure, None);
+        assert_eq!(report.instructions, 2);
+        assert_eq!(report.cycles, 5);
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let report = HeadlessRunner::new(limits()).run_asm("loop:\njmp loop");
+        assert_eq!(
+            report.failure,
+            Some(Failure::InstructionLimit { pc: 0x8000 })
+        );
+        assert_eq!(report.instructions, 1000);
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        let report = HeadlessRunner::new(Limits {
+            max_instructions: u64::MAX,
+            ..limits()
+        })
+        .run_asm("loop:\njmp loop");
+        assert_eq!(report.failure, Some(Failure::CycleLimit { pc: 0x8000 }));
+    }
+
+    #[test]
+    fn test_virtual_console() {
+        let runner = HeadlessRunner::new(limits());
+        let report = runner.run_asm(
+            "
+            lda #$68  ; h
+            sta $401b
+            lda #$69  ; i
+            sta $401b
+            lda #$00
+            sta $401c
+          loop:
+            jmp loop
+            ",
+        );
+        assert_eq!(report.failure, None);
+        assert_eq!(report.output, "hi");
+        assert_eq!(report.instructions, 6);
+
+        let report = runner.run_asm("lda #$02\nsta $401c\nloop:\njmp loop");
+        assert_eq!(
+            report.failure,
+            Some(Failure::ExitCode {
+                pc: 0x8002,
+                code: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let report = HeadlessRunner::new(limits()).run_asm(
+            "
+          loop:
+            sta $401b  ; $8000
+            jmp loop
+            ",
+        );
+        assert_eq!(report.failure, Some(Failure::OutputLimit { pc: 0x8000 }));
+        assert_eq!(report.output.len(), 0x11);
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let report = HeadlessRunner::new(limits()).run_program(&[0xea; 0x101]);
+        assert_eq!(
+            report.failure,
+            Some(Failure::RomTooLarge {
+                size: 0x101,
+                max: 0x100
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_rom() {
+        let report = HeadlessRunner::new(limits()).run_asm("notanop");
+        assert!(matches!(report.failure, Some(Failure::InvalidRom { .. })));
+    }
+
+    #[test]
+    fn test_trap_illegal_opcodes() {
+        let mut runner = HeadlessRunner::new(limits());
+        runner.set_illegal_opcode_policy(IllegalOpcodePolicy::Trap);
+        let report = runner.run_program(&[0xea, 0xa7, 0x00, 0x02]);
+        assert_eq!(
+            report.failure,
+            Some(Failure::IllegalOpcode {
+                pc: 0x8001,
+                opcode: 0xa7
+            })
+        );
+        assert_eq!(report.instructions, 1);
+    }
+
+    #[test]
+    fn test_random_programs_never_escape() {
+        // A simple xorshift, so that the programs are the same on every run.
+        let mut state: u32 = 0x1234_5678;
+        let runner = HeadlessRunner::new(limits());
+        for _ in 0..50 {
+            let bytes: Vec<u8> = (0..0x100)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            let report = runner.run_program(&bytes);
+            assert!(report.instructions <= 1000);
+        }
+    }
+}
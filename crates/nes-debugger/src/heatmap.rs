@@ -0,0 +1,250 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::constants::memory_range;
+use std::cell::Cell;
+use std::ops::Range;
+
+/// How many times an address was accessed, in each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// How many times the address was run as part of an instruction, either as the
+    /// opcode or one of its operands.
+    pub executes: u64,
+}
+
+impl AccessCounts {
+    pub fn total(&self) -> u64 {
+        self.reads + self.writes + self.executes
+    }
+}
+
+/// Counts the reads, writes, and executes of every address, to see which memory is
+/// hot, and which memory is never touched at all. Frontends can color the memory by
+/// its heat, and homebrew authors can find the RAM that is unused, or thrashed.
+///
+/// This lives on the bus, so that it sees every access. Start it with
+/// Bus::set_observer, and the CPU records the instructions that it runs. The
+/// instruction fetches go over the bus, so they count as reads too. The RAM mirrors
+/// are counted on the RAM they mirror. Reads through Bus::peek_u8 and
+/// Bus::try_read_u8 aren't counted, so that tools can look at the memory without
+/// heating it up.
+pub struct Heatmap {
+    /// Reads come through the bus without mutable access, so they are counted in
+    /// cells.
+    reads: Vec<Cell<u64>>,
+    writes: Vec<u64>,
+    executes: Vec<u64>,
+    /// The highest total of any address, to scale the heat.
+    max_total: Cell<u64>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap {
+            reads: vec![Cell::new(0); 0x10000],
+            writes: vec![0; 0x10000],
+            executes: vec![0; 0x10000],
+            max_total: Cell::new(0),
+        }
+    }
+
+    pub fn record_read(&self, address: u16) {
+        let reads = &self.reads[address as usize];
+        reads.set(reads.get() + 1);
+        self.update_max(address);
+    }
+
+    pub fn record_write(&mut self, address: u16) {
+        self.writes[address as usize] += 1;
+        self.update_max(address);
+    }
+
+    /// Record an instruction of `size` bytes that ran at the pc.
+    pub fn record_execute(&mut self, pc: u16, size: u16) {
+        for offset in 0..size {
+            let address = pc.wrapping_add(offset);
+            self.executes[address as usize] += 1;
+            self.update_max(address);
+        }
+    }
+
+    fn update_max(&self, address: u16) {
+        let total = self.counts(address).total();
+        if total > self.max_total.get() {
+            self.max_total.set(total);
+        }
+    }
+
+    pub fn counts(&self, address: u16) -> AccessCounts {
+        let index = address as usize;
+        AccessCounts {
+            reads: self.reads[index].get(),
+            writes: self.writes[index],
+            executes: self.executes[index],
+        }
+    }
+
+    /// How hot the address is, from 0.0 for never accessed, to 1.0 for the hottest
+    /// address. The scale is logarithmic, as a loop counter gets many orders of
+    /// magnitude more accesses than most of the memory.
+    pub fn heat(&self, address: u16) -> f32 {
+        let total = self.counts(address).total();
+        if total == 0 {
+            return 0.0;
+        }
+        ((total as f32).ln_1p() / (self.max_total.get() as f32).ln_1p()).min(1.0)
+    }
+
+    /// The addresses in the range that were never accessed.
+    pub fn unused(&self, range: Range<u16>) -> Vec<u16> {
+        range
+            .filter(|&address| self.counts(address).total() == 0)
+            .collect()
+    }
+
+    /// The most accessed addresses in the range, the hottest first. Ties are in
+    /// address order.
+    pub fn hottest(&self, range: Range<u16>, count: usize) -> Vec<(u16, AccessCounts)> {
+        let mut addresses: Vec<(u16, AccessCounts)> = range
+            .map(|address| (address, self.counts(address)))
+            .filter(|(_, counts)| counts.total() != 0)
+            .collect();
+        addresses.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.total()));
+        addresses.truncate(count);
+        addresses
+    }
+
+    pub fn clear(&mut self) {
+        *self = Heatmap::new();
+    }
+
+    /// Summarize the internal RAM: the hottest addresses, and the unused ones.
+    pub fn report(&self) -> String {
+        let ram = memory_range::RAM_ACTUAL.start..memory_range::RAM_ACTUAL.end;
+        let mut report = String::from("Memory heatmap\n");
+
+        report.push_str("  Hottest RAM (reads, writes, executes):\n");
+        for (address, counts) in self.hottest(ram.clone(), 10) {
+            report.push_str(&format!(
+                "    ${:04x}: {} ({}, {}, {})\n",
+                address,
+                counts.total(),
+                counts.reads,
+                counts.writes,
+                counts.executes
+            ));
+        }
+
+        let unused = self.unused(ram);
+        report.push_str(&format!("  Unused RAM ({} bytes):\n", unused.len()));
+        for range in to_ranges(&unused) {
+            if range.start == range.end {
+                report.push_str(&format!("    ${:04x}\n", range.start));
+            } else {
+                report
+                    .push_str(&format!("    ${:04x}-${:04x}\n", range.start, range.end));
+            }
+        }
+        report
+    }
+}
+
+/// An inclusive range of addresses, for the report.
+struct AddressRange {
+    start: u16,
+    end: u16,
+}
+
+/// Merge the sorted addresses into runs.
+fn to_ranges(addresses: &[u16]) -> Vec<AddressRange> {
+    let mut ranges: Vec<AddressRange> = Vec::new();
+    for &address in addresses {
+        match ranges.last_mut() {
+            Some(range) if range.end + 1 == address => range.end = address,
+            _ => ranges.push(AddressRange {
+                start: address,
+                end: address,
+            }),
+        }
+    }
+    ranges
+}
+
+impl BusObserver for Heatmap {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        self.record_execute(instruction.pc, instruction.size);
+    }
+
+    fn record_read(&self, access: &ObservedAccess) {
+        Heatmap::record_read(self, access.folded);
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        Heatmap::record_write(self, access.folded);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+
+    #[test]
+    fn test_counts() {
+        let (mut cpu, _) = assemble(
+            "
+              ldx #3
+            loop:
+              inc $10
+              lda $0811  ; A mirror of $0011.
+              dex
+              bne loop
+            ",
+        )
+        .unwrap();
+        cpu.bus.borrow_mut().set_observer(Some(Heatmap::new()));
+        // ldx, and then the 4 instructions of the loop 3 times.
+        for _ in 0..13 {
+            cpu.tick();
+        }
+
+        let bus = cpu.bus.borrow();
+        let heatmap = bus.observer::<Heatmap>().unwrap();
+        // Each inc reads, writes the old value back, and then writes the new one.
+        assert_eq!(
+            heatmap.counts(0x0010),
+            AccessCounts {
+                reads: 3,
+                writes: 6,
+                executes: 0,
+            }
+        );
+        assert_eq!(heatmap.counts(0x0011).reads, 3);
+        assert_eq!(heatmap.counts(0x0811).reads, 0);
+        // The opcode of ldx ran once, and is read by its fetch.
+        assert_eq!(heatmap.counts(0x8000).executes, 1);
+        assert_eq!(heatmap.counts(0x8000).reads, 1);
+        assert_eq!(heatmap.counts(0x8002).executes, 3);
+        assert_eq!(heatmap.counts(0x0012).total(), 0);
+
+        assert_eq!(heatmap.hottest(0x0000..0x0800, 1)[0].0, 0x0010);
+        assert_eq!(heatmap.heat(0x0012), 0.0);
+        assert!(heatmap.heat(0x0011) < heatmap.heat(0x0010));
+        assert!(heatmap.heat(0x0010) <= 1.0);
+        assert_eq!(
+            heatmap.unused(0x000e..0x0014),
+            vec![0x000e, 0x000f, 0x0012, 0x0013]
+        );
+
+        let report = heatmap.report();
+        assert!(report.contains("$0010: 9 (3, 6, 0)"));
+        assert!(report.contains("$0000-$000f\n"));
+    }
+}
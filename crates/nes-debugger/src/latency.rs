@@ -0,0 +1,286 @@
+//! Measure the input latency of a game: how many frames pass between pressing a
+//! button and the game reacting to it. Each trial presses the button on a different
+//! frame, as the latency depends on where the press lands in the game's loop. The
+//! reaction is found by running the same frames with and without the press from a
+//! checkpoint, and looking for the first frame where they differ, so anything else
+//! that changes on its own, like a frame counter, doesn't count.
+use crate::scenario::frame_hash;
+use nes_core::checkpoint::Checkpoint;
+use nes_core::cpu_6502::Cpu6502;
+use nes_core::region::NTSC_FRAME_CYCLES;
+
+/// What counts as the game reacting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    /// A byte in memory changes, like the player's position.
+    MemoryChange { address: u16 },
+    /// Anything in the frame changes, see scenario::frame_hash. The buttons written
+    /// to the input address are left out.
+    FrameChange,
+}
+
+/// The frontend settings that add or remove frames of latency on top of the game's
+/// own latency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontendSettings {
+    pub name: &'static str,
+    /// Run-ahead emulates this many frames ahead and shows the last one, which hides
+    /// that many frames of the game's latency.
+    pub run_ahead: u64,
+    /// Frames that wait in a queue before they are shown, e.g. 1 for vsync with
+    /// double buffering, and 2 for triple buffering.
+    pub buffered_frames: u64,
+}
+
+impl FrontendSettings {
+    /// A spread of the usual setups, to compare against each other.
+    pub fn common() -> Vec<FrontendSettings> {
+        let settings = |name, run_ahead, buffered_frames| FrontendSettings {
+            name,
+            run_ahead,
+            buffered_frames,
+        };
+        vec![
+            settings("no vsync", 0, 0),
+            settings("vsync", 0, 1),
+            settings("vsync, triple buffered", 0, 2),
+            settings("run-ahead 1, vsync", 1, 1),
+            settings("run-ahead 2, vsync", 2, 1),
+        ]
+    }
+
+    /// The frames from the press to the reaction being shown, given the game's own
+    /// latency. Run-ahead can't show a reaction before the press.
+    pub fn end_to_end(&self, frames: u64) -> u64 {
+        frames.saturating_sub(self.run_ahead) + self.buffered_frames
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyReport {
+    /// The latency of each trial, in frames after the frame where the button was
+    /// pressed, or None if the game never reacted. The NMI from the end of the last
+    /// frame runs at the start of the press frame, so a game that reads the input
+    /// right away in its NMI has a latency of 0.
+    pub trials: Vec<Option<u64>>,
+}
+
+impl LatencyReport {
+    fn reacted(&self) -> impl Iterator<Item = u64> + '_ {
+        self.trials.iter().filter_map(|&frames| frames)
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.reacted().min()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.reacted().max()
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        let count = self.reacted().count();
+        if count == 0 {
+            return None;
+        }
+        Some(self.reacted().sum::<u64>() as f64 / count as f64)
+    }
+
+    pub fn report(&self, settings: &[FrontendSettings]) -> String {
+        let (min, max, average) = match (self.min(), self.max(), self.average()) {
+            (Some(min), Some(max), Some(average)) => (min, max, average),
+            _ => {
+                return format!(
+                    "The game never reacted in {} trials.\n",
+                    self.trials.len()
+                )
+            }
+        };
+        let mut report = format!(
+            "Input latency ({} trials): min {}, max {}, average {:.2} frames\n",
+            self.trials.len(),
+            min,
+            max,
+            average
+        );
+        let missed = self.trials.len() - self.reacted().count();
+        if missed > 0 {
+            report.push_str(&format!("  {} trials never reacted\n", missed));
+        }
+        for settings in settings {
+            report.push_str(&format!(
+                "  {:<24} {} to {} frames\n",
+                settings.name,
+                settings.end_to_end(min),
+                settings.end_to_end(max)
+            ));
+        }
+        report
+    }
+}
+
+pub struct LatencyHarness {
+    pub reaction: Reaction,
+    /// The harness runs a bare CPU without the controller ports, so the buttons are
+    /// written to this address at the start of every frame.
+    pub input_address: u16,
+    /// The buttons to press, A is the low bit.
+    pub buttons: u8,
+    /// Frames to run before the first trial, to get past the game's start up.
+    pub settle_frames: u64,
+    /// Each trial presses the button one frame later than the last.
+    pub trials: u64,
+    /// Give up on a trial after this many frames.
+    pub max_frames: u64,
+}
+
+impl LatencyHarness {
+    pub fn new(reaction: Reaction) -> LatencyHarness {
+        LatencyHarness {
+            reaction,
+            input_address: 0x00ff,
+            buttons: 0b0000_0001,
+            settle_frames: 60,
+            trials: 8,
+            max_frames: 30,
+        }
+    }
+
+    /// Run the trials. The CPU is left where the last trial started.
+    pub fn run(&self, cpu: &mut Cpu6502) -> LatencyReport {
+        for _ in 0..self.settle_frames {
+            self.run_frame(cpu, 0);
+        }
+
+        let mut trials = Vec::new();
+        for _ in 0..self.trials {
+            let start = Checkpoint::save(cpu);
+            let released: Vec<u32> = (0..self.max_frames)
+                .map(|_| {
+                    self.run_frame(cpu, 0);
+                    self.observe(cpu)
+                })
+                .collect();
+
+            start
+                .restore(cpu)
+                .expect("The checkpoint was saved from this CPU.");
+            let mut latency = None;
+            for (frame, &released) in released.iter().enumerate() {
+                self.run_frame(cpu, self.buttons);
+                if self.observe(cpu) != released {
+                    latency = Some(frame as u64);
+                    break;
+                }
+            }
+            trials.push(latency);
+
+            // Move the next press one frame later.
+            start
+                .restore(cpu)
+                .expect("The checkpoint was saved from this CPU.");
+            self.run_frame(cpu, 0);
+        }
+        LatencyReport { trials }
+    }
+
+    fn observe(&self, cpu: &mut Cpu6502) -> u32 {
+        match self.reaction {
+            Reaction::MemoryChange { address } => cpu.peek(address) as u32,
+            Reaction::FrameChange => {
+                // The next frame writes the buttons again before it runs.
+                cpu.poke(self.input_address, 0);
+                frame_hash(cpu)
+            }
+        }
+    }
+
+    /// There's no PPU yet, so when the program turns on the NMI through PPUCTRL, it's
+    /// signaled at the end of every frame, like the monkey tester does.
+    fn run_frame(&self, cpu: &mut Cpu6502, buttons: u8) {
+        cpu.poke(self.input_address, buttons);
+        cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+        if cpu.bus.borrow().is_nmi_enabled() {
+            cpu.set_nmi();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+    use nes_core::bus::Bus;
+    use nes_core::constants::InterruptVectors;
+    use nes_core::mappers::SimpleProgram;
+
+    /// Only read the input on every 4th frame, and copy it to $10.
+    const PROGRAM: &str = "
+        lda #$80
+        sta $2000
+      loop:
+        jmp loop
+      nmi:
+        inc $20
+        lda $20
+        and #$03
+        bne return
+        lda $ff
+        sta $10
+      return:
+        rti
+    ";
+
+    fn cpu() -> Cpu6502 {
+        let mut lexer = AsmLexer::new(PROGRAM);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, symbols, .. } = lexer.into_bytes().unwrap();
+        let mut program = SimpleProgram::load(&bytes);
+        let nmi = symbols.address("nmi").unwrap();
+        program.set_u16(InterruptVectors::NmiVector as u16, nmi);
+        Cpu6502::new(Bus::new_shared_bus(Box::new(program)))
+    }
+
+    #[test]
+    fn test_memory_change() {
+        let mut harness = LatencyHarness::new(Reaction::MemoryChange { address: 0x10 });
+        harness.trials = 4;
+        let report = harness.run(&mut cpu());
+        let mut trials: Vec<u64> =
+            report.trials.iter().map(|frames| frames.unwrap()).collect();
+        trials.sort_unstable();
+        // The NMI runs at the start of the press frame, and then it waits up to 3 more
+        // frames to read the input.
+        assert_eq!(trials, vec![0, 1, 2, 3]);
+        assert_eq!(report.average(), Some(1.5));
+
+        let vsync = &FrontendSettings::common()[1];
+        assert_eq!(vsync.end_to_end(0), 1);
+        let run_ahead = &FrontendSettings::common()[4];
+        assert_eq!(run_ahead.end_to_end(3), 2);
+        assert!(report
+            .report(&FrontendSettings::common())
+            .contains("min 0, max 3"));
+    }
+
+    #[test]
+    fn test_never_reacts() {
+        let mut harness = LatencyHarness::new(Reaction::MemoryChange { address: 0x30 });
+        harness.trials = 2;
+        harness.max_frames = 10;
+        let report = harness.run(&mut cpu());
+        assert_eq!(report.trials, vec![None, None]);
+        assert_eq!(report.report(&[]), "The game never reacted in 2 trials.\n");
+    }
+
+    #[test]
+    fn test_frame_change() {
+        let mut harness = LatencyHarness::new(Reaction::FrameChange);
+        harness.trials = 1;
+        // The frame counter changes every frame, but it's the same with and without
+        // the press. The NMI of the press frame is the 3rd, so the input is read in
+        // the next one.
+        harness.settle_frames = 3;
+        assert_eq!(harness.run(&mut cpu()).trials, vec![Some(1)]);
+    }
+}
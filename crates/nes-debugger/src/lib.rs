@@ -0,0 +1,26 @@
+//! The tools for finding out what a program is doing: the debugger, the bus
+//! observers, the scenario and regression runners, and the testers.
+
+// Remove this once this is a bit more mature.
+#![allow(dead_code)]
+// Clippy rules to disable.
+#![allow(clippy::new_without_default)]
+
+pub mod auto_label;
+pub mod bisect;
+pub mod bus_trace;
+pub mod crash_report;
+pub mod debugger;
+pub mod headless;
+pub mod heatmap;
+pub mod latency;
+pub mod monkey;
+pub mod notes;
+pub mod ram_audit;
+#[cfg(feature = "toml")]
+pub mod regress;
+pub mod rom_diff;
+pub mod scenario;
+pub mod smc_detector;
+pub mod trace_log;
+pub mod write_protection;
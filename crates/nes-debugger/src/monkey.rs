@@ -0,0 +1,375 @@
+//! A monkey tester that mashes random input into a program for a number of frames,
+//! while watching for it to crash. This is for soak testing homebrew, where a
+//! crash often only shows up with some odd combination of buttons. The input comes
+//! from a seeded generator, so a failing run can be replayed exactly.
+use crate::headless::panic_message;
+use nes_core::cpu_6502::stack_analyzer::{StackAnalyzer, StackWarning};
+use nes_core::cpu_6502::{Cpu6502, CpuState};
+use nes_core::movie::Ports;
+use nes_core::region::NTSC_FRAME_CYCLES;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+// The buttons use the same layout as the movies: RLDUTSBA.
+const RIGHT: u8 = 0b1000_0000;
+const LEFT: u8 = 0b0100_0000;
+const DOWN: u8 = 0b0010_0000;
+const UP: u8 = 0b0001_0000;
+
+/// A loop that stays inside this many bytes is considered to be spinning.
+const RUNAWAY_WINDOW: u16 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonkeyConfig {
+    pub seed: u64,
+    pub frames: u64,
+    /// Where the buttons for the first port are written at the start of every frame,
+    /// as the monkey drives a bare CPU, which doesn't have the controller ports.
+    pub input_address: u16,
+    /// How many frames each input is held for, as a player doesn't change the buttons
+    /// every frame.
+    pub hold_frames: u64,
+    /// Fail once the program has spun in a tight loop for this many frames, with no
+    /// NMI to get it out.
+    pub runaway_frames: u64,
+}
+
+impl Default for MonkeyConfig {
+    fn default() -> Self {
+        MonkeyConfig {
+            seed: 0,
+            // A minute of NTSC frames.
+            frames: 60 * 60,
+            input_address: 0x00ff,
+            hold_frames: 4,
+            runaway_frames: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonkeyFailure {
+    /// The program ran a KIL. The pc is left just past it.
+    Jammed {
+        frame: u64,
+        pc: u16,
+    },
+    IllegalOpcode {
+        frame: u64,
+        pc: u16,
+        opcode: u8,
+    },
+    /// The stack wrapped around, or ran into reserved memory.
+    Stack {
+        frame: u64,
+        warning: StackWarning,
+    },
+    RunawayLoop {
+        frame: u64,
+        pc: u16,
+    },
+    /// The emulator itself panicked.
+    Crashed {
+        frame: u64,
+        pc: u16,
+        message: String,
+    },
+}
+
+impl fmt::Display for MonkeyFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonkeyFailure::Jammed { frame, pc } => {
+                write!(f, "Frame {}: jammed at ${:04x}.", frame, pc)
+            }
+            MonkeyFailure::IllegalOpcode { frame, pc, opcode } => write!(
+                f,
+                "Frame {}: ran the illegal opcode ${:02x} at ${:04x}.",
+                frame, opcode, pc
+            ),
+            MonkeyFailure::Stack { frame, warning } => {
+                write!(f, "Frame {}: {}", frame, warning.message())
+            }
+            MonkeyFailure::RunawayLoop { frame, pc } => write!(
+                f,
+                "Frame {}: stuck in a loop at ${:04x} with no NMI to get it out.",
+                frame, pc
+            ),
+            MonkeyFailure::Crashed { frame, pc, message } => write!(
+                f,
+                "Frame {}: the emulator crashed at ${:04x}: {}",
+                frame, pc, message
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonkeyReport {
+    pub frames: u64,
+    /// The buttons for every frame that was run, to reproduce the failure.
+    pub inputs: Vec<Ports>,
+    pub failure: Option<MonkeyFailure>,
+}
+
+/// A splitmix64 generator. It's small, and any seed works, including 0.
+/// http://xoshiro.di.unimi.it/splitmix64.c
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    /// Random buttons, leaving out the directions that can't be pressed together on
+    /// a real controller.
+    fn buttons(&mut self) -> u8 {
+        let mut buttons = self.next_u64() as u8;
+        if buttons & (LEFT | RIGHT) == LEFT | RIGHT {
+            buttons &= !LEFT;
+        }
+        if buttons & (UP | DOWN) == UP | DOWN {
+            buttons &= !UP;
+        }
+        buttons
+    }
+}
+
+pub struct Monkey {
+    config: MonkeyConfig,
+}
+
+impl Monkey {
+    pub fn new(config: MonkeyConfig) -> Monkey {
+        Monkey { config }
+    }
+
+    /// Run the program with random input. There's no PPU yet, so when the program
+    /// turns on the NMI through PPUCTRL, it's signaled at the end of every frame.
+    pub fn run(&self, cpu: &mut Cpu6502) -> MonkeyReport {
+        let config = self.config;
+        let mut random = Random { state: config.seed };
+        if cpu.stack_analyzer.is_none() {
+            cpu.stack_analyzer = Some(StackAnalyzer::new());
+        }
+        let mut inputs = Vec::new();
+        let mut buttons = 0;
+        let mut frame_cycles = 0;
+        let mut spinning_frames = 0;
+
+        for frame in 0..config.frames {
+            if frame % config.hold_frames.max(1) == 0 {
+                buttons = random.buttons();
+            }
+            inputs.push([buttons, 0]);
+            cpu.poke(config.input_address, buttons);
+
+            let mut lowest_pc = cpu.pc;
+            let mut highest_pc = cpu.pc;
+            while frame_cycles < NTSC_FRAME_CYCLES {
+                let pc = cpu.pc;
+                let failure = match panic::catch_unwind(AssertUnwindSafe(|| cpu.tick())) {
+                    Ok(CpuState::Running) | Ok(CpuState::WaitingForInterrupt) => None,
+                    Ok(CpuState::Jammed) => {
+                        Some(MonkeyFailure::Jammed { frame, pc: cpu.pc })
+                    }
+                    Ok(CpuState::IllegalOpcode(opcode)) => {
+                        Some(MonkeyFailure::IllegalOpcode {
+                            frame,
+                            pc: cpu.pc,
+                            opcode,
+                        })
+                    }
+                    Err(payload) => Some(MonkeyFailure::Crashed {
+                        frame,
+                        pc,
+                        message: panic_message(payload),
+                    }),
+                };
+                let failure = failure.or_else(|| {
+                    stack_failure(cpu)
+                        .map(|warning| MonkeyFailure::Stack { frame, warning })
+                });
+                if let Some(failure) = failure {
+                    return MonkeyReport {
+                        frames: frame + 1,
+                        inputs,
+                        failure: Some(failure),
+                    };
+                }
+                frame_cycles += cpu.cycles as u64;
+                lowest_pc = lowest_pc.min(cpu.pc);
+                highest_pc = highest_pc.max(cpu.pc);
+            }
+            frame_cycles -= NTSC_FRAME_CYCLES;
+
+            let nmi_enabled = cpu.bus.borrow().is_nmi_enabled();
+            if nmi_enabled {
+                cpu.set_nmi();
+            }
+            if highest_pc - lowest_pc < RUNAWAY_WINDOW && !nmi_enabled {
+                spinning_frames += 1;
+                if spinning_frames >= config.runaway_frames {
+                    return MonkeyReport {
+                        frames: frame + 1,
+                        inputs,
+                        failure: Some(MonkeyFailure::RunawayLoop { frame, pc: cpu.pc }),
+                    };
+                }
+            } else {
+                spinning_frames = 0;
+            }
+        }
+
+        MonkeyReport {
+            frames: config.frames,
+            inputs,
+            failure: None,
+        }
+    }
+}
+
+/// The stack warnings that mean the program is broken, rather than getting close.
+fn stack_failure(cpu: &Cpu6502) -> Option<StackWarning> {
+    cpu.stack_analyzer.as_ref().and_then(|stack_analyzer| {
+        stack_analyzer
+            .warnings()
+            .iter()
+            .find(|warning| {
+                matches!(
+                    warning,
+                    StackWarning::Overflow { .. }
+                        | StackWarning::Underflow { .. }
+                        | StackWarning::Collision { .. }
+                )
+            })
+            .cloned()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+    use nes_core::bus::Bus;
+    use nes_core::constants::InterruptVectors;
+    use nes_core::mappers::SimpleProgram;
+
+    fn load(text: &str) -> Cpu6502 {
+        let mut lexer = AsmLexer::new(text);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, symbols, .. } = lexer.into_bytes().unwrap();
+        let mut program = SimpleProgram::load(&bytes);
+        if let Some(address) = symbols.address("nmi") {
+            program.set_u16(InterruptVectors::NmiVector as u16, address);
+        }
+        Cpu6502::new(Bus::new_shared_bus(Box::new(program)))
+    }
+
+    fn run(text: &str, config: MonkeyConfig) -> MonkeyReport {
+        Monkey::new(config).run(&mut load(text))
+    }
+
+    /// Crash when A and B are pressed together.
+    const FRAGILE: &str = "
+        lda #$80
+        sta $2000
+      loop:
+        jmp loop
+      nmi:
+        lda $ff
+        and #$03
+        cmp #$03
+        bne return
+        .byte $02
+      return:
+        rti
+    ";
+
+    #[test]
+    fn test_finds_the_crash() {
+        let config = MonkeyConfig {
+            seed: 7,
+            ..MonkeyConfig::default()
+        };
+        let report = run(FRAGILE, config);
+        let failure_frame = match report.failure {
+            Some(MonkeyFailure::Jammed { frame, .. }) => frame,
+            ref failure => panic!("Unexpected failure {:?}", failure),
+        };
+        assert_eq!(report.inputs.len() as u64, failure_frame + 1);
+        // The NMI ran on the frame before, with A and B held.
+        assert_eq!(report.inputs[failure_frame as usize - 1][0] & 0b11, 0b11);
+
+        // The same seed gives the same run.
+        assert_eq!(run(FRAGILE, config), report);
+        assert_ne!(
+            run(FRAGILE, MonkeyConfig { seed: 8, ..config }).inputs,
+            report.inputs
+        );
+    }
+
+    #[test]
+    fn test_impossible_directions() {
+        let mut random = Random { state: 0 };
+        for _ in 0..1000 {
+            let buttons = random.buttons();
+            assert_ne!(buttons & (LEFT | RIGHT), LEFT | RIGHT);
+            assert_ne!(buttons & (UP | DOWN), UP | DOWN);
+        }
+    }
+
+    #[test]
+    fn test_runaway_loop() {
+        let report = run("sei\nloop:\njmp loop", MonkeyConfig::default());
+        assert_eq!(
+            report.failure,
+            Some(MonkeyFailure::RunawayLoop {
+                frame: 59,
+                pc: 0x8001
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let report = run("loop:\npha\njmp loop", MonkeyConfig::default());
+        assert_eq!(
+            report.failure,
+            Some(MonkeyFailure::Stack {
+                frame: 0,
+                warning: StackWarning::Overflow { pc: 0x8000 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_survives() {
+        let config = MonkeyConfig {
+            frames: 100,
+            ..MonkeyConfig::default()
+        };
+        let mut cpu = load(
+            "
+            lda #$80
+            sta $2000
+          loop:
+            jmp loop
+          nmi:
+            inc $10
+            rti
+            ",
+        );
+        let report = Monkey::new(config).run(&mut cpu);
+        assert_eq!(report.failure, None);
+        assert_eq!(report.frames, 100);
+        // The NMI is signaled at the end of every frame, and run on the next one.
+        assert_eq!(cpu.peek(0x10), 99);
+    }
+}
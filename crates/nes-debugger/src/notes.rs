@@ -0,0 +1,351 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::region::Region;
+
+/// The noise channel's timer periods on NTSC, in CPU cycles.
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoteChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+}
+
+impl NoteChannel {
+    const ALL: [NoteChannel; 4] = [
+        NoteChannel::Pulse1,
+        NoteChannel::Pulse2,
+        NoteChannel::Triangle,
+        NoteChannel::Noise,
+    ];
+
+    /// The channel of an APU register from $4000-$400F.
+    fn for_register(address: u16) -> Option<NoteChannel> {
+        match address {
+            0x4000..=0x4003 => Some(NoteChannel::Pulse1),
+            0x4004..=0x4007 => Some(NoteChannel::Pulse2),
+            0x4008..=0x400b => Some(NoteChannel::Triangle),
+            0x400c..=0x400f => Some(NoteChannel::Noise),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteKind {
+    /// The channel started a note at the frequency.
+    On {
+        hz: f32,
+    },
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteEvent {
+    /// The CPU cycle of the instruction that wrote the register.
+    pub cycle: u64,
+    pub channel: NoteChannel,
+    pub kind: NoteKind,
+}
+
+impl NoteEvent {
+    /// The nearest note, e.g. "A4", for the pitched channels.
+    pub fn pitch_name(&self) -> Option<String> {
+        match self.kind {
+            NoteKind::On { hz } if self.channel != NoteChannel::Noise => {
+                Some(pitch_name(hz))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub type NoteHook = Box<dyn FnMut(&NoteEvent)>;
+
+/// The nearest note in scientific pitch notation, where A4 is 440 Hz.
+pub fn pitch_name(hz: f32) -> String {
+    let midi = midi_note(hz);
+    format!(
+        "{}{}",
+        NOTE_NAMES[midi.rem_euclid(12) as usize],
+        midi.div_euclid(12) - 1
+    )
+}
+
+fn midi_note(hz: f32) -> i32 {
+    (69.0 + 12.0 * (hz / 440.0).log2()).round() as i32
+}
+
+/// What the channel's registers were last set to.
+#[derive(Default)]
+struct ChannelState {
+    /// The bit in $4015.
+    enabled: bool,
+    /// The volume is up, or the triangle's linear counter isn't 0.
+    audible: bool,
+    /// The note was started with a write to the length counter, and hasn't been
+    /// stopped since.
+    triggered: bool,
+    /// The timer, or the noise period index.
+    timer: u16,
+    /// The frequency of the note that's playing.
+    playing: Option<f32>,
+}
+
+/// Turns the writes to the APU registers into the notes that the music is playing,
+/// for reverse engineering a game's music, or drawing a piano roll. There is no APU
+/// yet, so this works from the registers alone, and the notes are approximate: the
+/// envelopes and length counters aren't run, so a note only ends when the program
+/// silences it.
+///
+/// - Writing the length counter, e.g. $4003, starts a note.
+/// - Changing the timer while a note plays starts a new note, if it's a different
+///   pitch. Vibrato that stays near the pitch doesn't.
+/// - A volume of 0, a linear counter of 0 on the triangle, or turning the channel off
+///   in $4015 ends the note. The pulse channels are also silent below a timer of 8.
+///
+/// This lives on the bus, like the SmcDetector. Start it with
+/// Bus::set_observer. Either take the events as they pile up, or set a hook to
+/// get them as they happen.
+pub struct NoteDetector {
+    cpu_hz: f32,
+    cycle: u64,
+    channels: [ChannelState; 4],
+    events: Vec<NoteEvent>,
+    hook: Option<NoteHook>,
+}
+
+impl NoteDetector {
+    pub fn new(region: Region) -> NoteDetector {
+        NoteDetector {
+            cpu_hz: region.cpu_hz() as f32,
+            cycle: 0,
+            channels: Default::default(),
+            events: Vec::new(),
+            hook: None,
+        }
+    }
+
+    /// Call the hook on every event, as it happens. The bus is still borrowed while
+    /// the hook runs, so it can't look at the memory.
+    pub fn set_hook(&mut self, hook: impl FnMut(&NoteEvent) + 'static) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    /// The events since the last call.
+    pub fn take_events(&mut self) -> Vec<NoteEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn record_write(&mut self, address: u16, value: u8) {
+        if address == 0x4015 {
+            for (index, &channel) in NoteChannel::ALL.iter().enumerate() {
+                let state = &mut self.channels[index];
+                state.enabled = value & (1 << index) != 0;
+                if !state.enabled {
+                    state.triggered = false;
+                }
+                self.update(channel, false);
+            }
+            return;
+        }
+        let channel = match NoteChannel::for_register(address) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let state = &mut self.channels[channel as usize];
+        let mut retrigger = false;
+        match (channel, address & 0b11) {
+            // The volume, when it's constant. The envelope is always audible, as it
+            // isn't run.
+            (NoteChannel::Pulse1 | NoteChannel::Pulse2 | NoteChannel::Noise, 0) => {
+                let constant_volume = value & 0b1_0000 != 0;
+                state.audible = !constant_volume || value & 0b1111 != 0;
+            }
+            (NoteChannel::Triangle, 0) => state.audible = value & 0x7f != 0,
+            (NoteChannel::Noise, 2) => state.timer = (value & 0b1111) as u16,
+            (_, 2) => state.timer = (state.timer & 0x700) | value as u16,
+            (NoteChannel::Noise, 3) => {
+                state.triggered = true;
+                retrigger = true;
+            }
+            (_, 3) => {
+                state.timer = (state.timer & 0xff) | ((value as u16 & 0b111) << 8);
+                state.triggered = true;
+                retrigger = true;
+            }
+            _ => {}
+        }
+        self.update(channel, retrigger);
+    }
+
+    /// The frequency the channel is set to, or None if it's silent.
+    fn frequency(&self, channel: NoteChannel) -> Option<f32> {
+        let state = &self.channels[channel as usize];
+        if !state.enabled || !state.audible || !state.triggered {
+            return None;
+        }
+        let timer = state.timer as f32;
+        match channel {
+            NoteChannel::Pulse1 | NoteChannel::Pulse2 if state.timer < 8 => None,
+            NoteChannel::Pulse1 | NoteChannel::Pulse2 => {
+                Some(self.cpu_hz / (16.0 * (timer + 1.0)))
+            }
+            NoteChannel::Triangle => Some(self.cpu_hz / (32.0 * (timer + 1.0))),
+            NoteChannel::Noise => {
+                Some(self.cpu_hz / NOISE_PERIODS[state.timer as usize] as f32)
+            }
+        }
+    }
+
+    /// Compare what's playing to what the registers say now, and emit the events.
+    fn update(&mut self, channel: NoteChannel, retrigger: bool) {
+        let frequency = self.frequency(channel);
+        let playing = self.channels[channel as usize].playing;
+        let changed = match (playing, frequency) {
+            (Some(_), Some(_)) if retrigger => true,
+            (Some(playing), Some(hz)) if channel == NoteChannel::Noise => playing != hz,
+            (Some(playing), Some(hz)) => midi_note(playing) != midi_note(hz),
+            (None, None) => false,
+            _ => true,
+        };
+        if !changed {
+            return;
+        }
+        if playing.is_some() {
+            self.emit(channel, NoteKind::Off);
+        }
+        if let Some(hz) = frequency {
+            self.emit(channel, NoteKind::On { hz });
+        }
+        self.channels[channel as usize].playing = frequency;
+    }
+
+    fn emit(&mut self, channel: NoteChannel, kind: NoteKind) {
+        let event = NoteEvent {
+            cycle: self.cycle,
+            channel,
+            kind,
+        };
+        if let Some(ref mut hook) = self.hook {
+            hook(&event);
+        }
+        self.events.push(event);
+    }
+}
+
+impl BusObserver for NoteDetector {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        self.cycle = instruction.cycle;
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        if let Some(register) = access.register {
+            NoteDetector::record_write(self, register, access.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+
+    fn notes(events: &[NoteEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event.kind {
+                NoteKind::On { .. } => format!(
+                    "{:?} on {}",
+                    event.channel,
+                    event.pitch_name().unwrap_or_default()
+                ),
+                NoteKind::Off => format!("{:?} off", event.channel),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pitch_name() {
+        assert_eq!(pitch_name(440.0), "A4");
+        assert_eq!(pitch_name(261.63), "C4");
+        assert_eq!(pitch_name(450.0), "A4");
+        assert_eq!(pitch_name(466.16), "A#4");
+        assert_eq!(pitch_name(32.7), "C1");
+    }
+
+    #[test]
+    fn test_notes() {
+        let (mut cpu, _) = assemble(
+            "
+              lda #%00000101  ; Pulse 1 and the triangle.
+              sta $4015
+              lda #%00111111  ; Constant volume 15.
+              sta $4000
+              lda #$fd        ; A4 is a timer of $0fd.
+              sta $4002
+              lda #$00
+              sta $4003       ; Start it.
+              lda #$fb        ; Vibrato, still A4.
+              sta $4002
+              lda #$ef        ; A#4
+              sta $4002
+              lda #%00110000  ; Volume 0.
+              sta $4000
+              lda #$7f
+              sta $4008
+              lda #$fd        ; A3 on the triangle, an octave below the pulse.
+              sta $400a
+              lda #$00
+              sta $400b
+              sta $4015       ; Everything off.
+            ",
+        )
+        .unwrap();
+        let hits = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut detector = NoteDetector::new(Region::Ntsc);
+        let hook_hits = hits.clone();
+        detector.set_hook(move |_| hook_hits.set(hook_hits.get() + 1));
+        cpu.bus.borrow_mut().set_observer(Some(detector));
+        for _ in 0..21 {
+            cpu.tick();
+        }
+
+        let events = cpu
+            .bus
+            .borrow_mut()
+            .observer_mut::<NoteDetector>()
+            .unwrap()
+            .take_events();
+        assert_eq!(
+            notes(&events),
+            [
+                "Pulse1 on A4",
+                "Pulse1 off",
+                "Pulse1 on A#4",
+                "Pulse1 off",
+                "Triangle on A3",
+                "Triangle off",
+            ]
+        );
+        assert_eq!(hits.get(), 6);
+        // The events are timed by the cycles that ran before them.
+        assert!(events[0].cycle > 0);
+        assert!(events[0].cycle < events[1].cycle);
+        match events[0].kind {
+            NoteKind::On { hz } => assert!((hz - 440.0).abs() < 1.0),
+            NoteKind::Off => unreachable!(),
+        }
+    }
+}
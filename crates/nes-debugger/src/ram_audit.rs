@@ -0,0 +1,240 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::constants::memory_range;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+
+/// How an address in RAM is used, going by the writes to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamUsage {
+    /// Never written.
+    Unused,
+    /// Written exactly once, and then only read. These are constants and lookup
+    /// tables that the program fills in as it starts up.
+    WriteOnce,
+    /// Written many times, but only from one instruction, like a counter.
+    SingleWriter,
+    /// Written from more than one instruction, which is general purpose state.
+    MultipleWriters,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AddressAudit {
+    writes: u64,
+    /// The pc of every instruction that wrote to the address.
+    writers: BTreeSet<u16>,
+    reads: Cell<u64>,
+}
+
+/// Audits how the internal RAM is written, to help with reverse engineering a RAM
+/// map. Addresses that are written once and then only read are probably constants,
+/// while addresses written from many places are the game's state. The stack page is
+/// left out, as pushes write to it from everywhere.
+///
+/// This lives on the bus, so that it sees every access. Start it with
+/// Bus::set_observer, and the CPU keeps it up to date with the pc of the instruction
+/// being run. Reads through Bus::try_read_u8 aren't counted, so that tools can look
+/// at the RAM without skewing the numbers.
+pub struct RamAudit {
+    addresses: Vec<AddressAudit>,
+    /// The pc of the instruction currently being executed.
+    pc: u16,
+}
+
+impl RamAudit {
+    pub fn new() -> RamAudit {
+        RamAudit {
+            addresses: vec![
+                AddressAudit::default();
+                memory_range::RAM_ACTUAL.size() as usize
+            ],
+            pc: 0,
+        }
+    }
+
+    pub fn record_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Map the address to an index into the RAM, leaving out the mirrors, the stack,
+    /// and everything that isn't RAM.
+    fn index(address: u16) -> Option<usize> {
+        if address >= memory_range::RAM.end {
+            return None;
+        }
+        let address = address & memory_range::RAM_ACTUAL.mask();
+        if (address >> 8) as u8 == memory_range::STACK_PAGE {
+            return None;
+        }
+        Some(address as usize)
+    }
+
+    pub fn record_write(&mut self, address: u16) {
+        if let Some(index) = RamAudit::index(address) {
+            let audit = &mut self.addresses[index];
+            audit.writes += 1;
+            audit.writers.insert(self.pc);
+        }
+    }
+
+    /// Reads come through the bus without mutable access, so they are counted in a
+    /// cell.
+    pub fn record_read(&self, address: u16) {
+        if let Some(index) = RamAudit::index(address) {
+            let reads = &self.addresses[index].reads;
+            reads.set(reads.get() + 1);
+        }
+    }
+
+    pub fn usage(&self, address: u16) -> RamUsage {
+        let audit = match RamAudit::index(address) {
+            Some(index) => &self.addresses[index],
+            None => return RamUsage::Unused,
+        };
+        match (audit.writes, audit.writers.len()) {
+            (0, _) => RamUsage::Unused,
+            (1, _) => RamUsage::WriteOnce,
+            (_, 1) => RamUsage::SingleWriter,
+            _ => RamUsage::MultipleWriters,
+        }
+    }
+
+    /// The addresses with a given usage, in order.
+    pub fn addresses_with_usage(&self, usage: RamUsage) -> Vec<u16> {
+        (memory_range::RAM_ACTUAL.start..memory_range::RAM_ACTUAL.end)
+            .filter(|&address| {
+                RamAudit::index(address).is_some() && self.usage(address) == usage
+            })
+            .collect()
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::from("RAM audit\n");
+
+        let write_once = self.addresses_with_usage(RamUsage::WriteOnce);
+        report.push_str(&format!(
+            "  Written once, probably constants ({}):\n",
+            write_once.len()
+        ));
+        for address in write_once {
+            let audit = &self.addresses[address as usize];
+            let writer = audit.writers.iter().next().expect("There was a write.");
+            report.push_str(&format!(
+                "    ${:04x}: written at ${:04x}, read {} times\n",
+                address,
+                writer,
+                audit.reads.get()
+            ));
+        }
+
+        let multiple_writers = self.addresses_with_usage(RamUsage::MultipleWriters);
+        report.push_str(&format!(
+            "  Written from multiple places ({}):\n",
+            multiple_writers.len()
+        ));
+        for address in multiple_writers {
+            let audit = &self.addresses[address as usize];
+            let writers: Vec<String> = audit
+                .writers
+                .iter()
+                .map(|pc| format!("${:04x}", pc))
+                .collect();
+            report.push_str(&format!(
+                "    ${:04x}: {} writes from {}\n",
+                address,
+                audit.writes,
+                writers.join(", ")
+            ));
+        }
+        report
+    }
+}
+
+impl BusObserver for RamAudit {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        RamAudit::record_instruction(self, instruction.pc);
+    }
+
+    fn record_read(&self, access: &ObservedAccess) {
+        if access.ram {
+            RamAudit::record_read(self, access.address);
+        }
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        if access.ram {
+            RamAudit::record_write(self, access.address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+    use nes_core::cpu_6502::StopCondition;
+
+    #[test]
+    fn test_usage() {
+        let mut audit = RamAudit::new();
+        audit.record_instruction(0x8000);
+        audit.record_write(0x0010);
+        audit.record_write(0x0020);
+        audit.record_write(0x0020);
+        audit.record_instruction(0x8010);
+        audit.record_write(0x0030);
+        audit.record_instruction(0x8020);
+        // This is a mirror of $0030.
+        audit.record_write(0x0830);
+        audit.record_read(0x0010);
+
+        assert_eq!(audit.usage(0x0000), RamUsage::Unused);
+        assert_eq!(audit.usage(0x0010), RamUsage::WriteOnce);
+        assert_eq!(audit.usage(0x0020), RamUsage::SingleWriter);
+        assert_eq!(audit.usage(0x0030), RamUsage::MultipleWriters);
+        assert_eq!(
+            audit.addresses_with_usage(RamUsage::WriteOnce),
+            vec![0x0010]
+        );
+    }
+
+    #[test]
+    fn test_skips_the_stack() {
+        let mut audit = RamAudit::new();
+        audit.record_write(0x01ff);
+        audit.record_write(0x2000);
+        assert_eq!(audit.usage(0x01ff), RamUsage::Unused);
+        assert_eq!(audit.usage(0x2000), RamUsage::Unused);
+    }
+
+    #[test]
+    fn attributes_writes_to_instructions() {
+        let (mut cpu, _) = assemble(
+            "
+              lda #$07
+              sta $10    ; A constant.
+              ldx #$03
+              loop:
+              stx $11    ; A counter, written from one place.
+              dex
+              bne loop
+              stx $12    ; Written from two places.
+              lda $10
+              sta $12
+              pha        ; The stack is left out.
+            ",
+        )
+        .unwrap();
+        cpu.bus.borrow_mut().set_observer(Some(RamAudit::new()));
+        cpu.run(StopCondition::Jam);
+
+        let bus = cpu.bus.borrow();
+        let audit = bus.observer::<RamAudit>().unwrap();
+        assert_eq!(audit.usage(0x10), RamUsage::WriteOnce);
+        assert_eq!(audit.usage(0x11), RamUsage::SingleWriter);
+        assert_eq!(audit.usage(0x12), RamUsage::MultipleWriters);
+        assert_eq!(audit.usage(0x01ff), RamUsage::Unused);
+        assert!(audit
+            .report()
+            .contains("$0010: written at $8002, read 1 times"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Run a corpus of ROMs, and compare their frame hashes against the hashes from a
+//! known good build. This is for changes that affect the accuracy, where a single
+//! scenario can't show what broke across all of the games at once.
+//!
+//! interval = 60
+//!
+//! [[rom]]
+//! path = "game.asm"
+//! frames = 600
+//! hashes = [0x1234abcd, ...]
+//!
+//! The hashes roll the frame hashes of every frame together, see frame_hash, and one
+//! is kept every `interval` frames, and at the last frame. So once a ROM diverges,
+//! every hash after it is a mismatch too, and the first mismatch shows when it
+//! happened.
+use crate::scenario::{frame_hash, load_rom, ScenarioError};
+use nes_core::region::NTSC_FRAME_CYCLES;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+
+fn default_interval() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Corpus {
+    /// The frames between each of the stored hashes.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default, rename = "rom")]
+    pub roms: Vec<CorpusRom>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorpusRom {
+    /// The path to the ROM, relative to the corpus file.
+    pub path: PathBuf,
+    pub frames: u64,
+    /// The hashes from a good run. These are missing until they are recorded, see
+    /// Corpus::update.
+    #[serde(default)]
+    pub hashes: Vec<u32>,
+}
+
+/// How one of the stored hashes compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashResult {
+    Pass,
+    Fail,
+    /// There isn't a stored hash to compare against yet.
+    New,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomResult {
+    pub path: PathBuf,
+    /// The hashes from this run. Empty if the ROM couldn't be loaded.
+    pub hashes: Vec<u32>,
+    pub results: Vec<HashResult>,
+    pub error: Option<String>,
+}
+
+impl RomResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+            && self
+                .results
+                .iter()
+                .all(|&result| result == HashResult::Pass)
+    }
+
+    /// The row for the pass/fail matrix, with a column for each hash. A "." passed,
+    /// an "X" is the first mismatch, and an "x" the ones after it, which only follow
+    /// from the first. A "?" is a new hash.
+    pub fn row(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("error: {}", error);
+        }
+        let mut diverged = false;
+        self.results
+            .iter()
+            .map(|result| match result {
+                HashResult::Pass => '.',
+                HashResult::Fail if diverged => 'x',
+                HashResult::Fail => {
+                    diverged = true;
+                    'X'
+                }
+                HashResult::New => '?',
+            })
+            .collect()
+    }
+}
+
+impl Corpus {
+    pub fn load(path: &Path) -> Result<Corpus, ScenarioError> {
+        Corpus::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Corpus, ScenarioError> {
+        let corpus: Corpus = toml::from_str(text)?;
+        if corpus.interval == 0 {
+            return Err(ScenarioError::Message(
+                "The interval must be at least 1 frame.".into(),
+            ));
+        }
+        Ok(corpus)
+    }
+
+    /// Run every ROM in the corpus. The ROM paths are relative to the directory.
+    pub fn run(&self, directory: &Path) -> Vec<RomResult> {
+        self.roms
+            .iter()
+            .map(|rom| {
+                let hashes =
+                    match self.rolling_hashes(&directory.join(&rom.path), rom.frames) {
+                        Ok(hashes) => hashes,
+                        Err(error) => {
+                            return RomResult {
+                                path: rom.path.clone(),
+                                hashes: Vec::new(),
+                                results: Vec::new(),
+                                error: Some(error.to_string()),
+                            }
+                        }
+                    };
+                let results = hashes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, hash)| match rom.hashes.get(index) {
+                        Some(expected) if expected == hash => HashResult::Pass,
+                        Some(_) => HashResult::Fail,
+                        None => HashResult::New,
+                    })
+                    .collect();
+                RomResult {
+                    path: rom.path.clone(),
+                    hashes,
+                    results,
+                    error: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Store the hashes from the run as the good ones. The ROMs that couldn't be
+    /// loaded keep their old hashes.
+    pub fn update(&mut self, results: &[RomResult]) {
+        for (rom, result) in self.roms.iter_mut().zip(results) {
+            if result.error.is_none() {
+                rom.hashes = result.hashes.clone();
+            }
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("The corpus can always be serialized.")
+    }
+
+    /// Play the ROM for the frames, and keep the rolling hash every interval. There's
+    /// no PPU yet, so the NMI is signaled at the end of each frame once the program
+    /// turns it on, like the bisector does.
+    fn rolling_hashes(
+        &self,
+        path: &Path,
+        frames: u64,
+    ) -> Result<Vec<u32>, ScenarioError> {
+        let (mut cpu, _) = load_rom(path)?;
+        let mut hashes = Vec::new();
+        let mut hash = FNV_OFFSET;
+        for frame in 1..=frames {
+            cpu.run_for_cycles(NTSC_FRAME_CYCLES);
+            if cpu.bus.borrow().is_nmi_enabled() {
+                cpu.set_nmi();
+            }
+            hash = roll(hash, frame_hash(&cpu));
+            if frame % self.interval == 0 || frame == frames {
+                hashes.push(hash);
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+/// Fold the frame hash into the rolling hash, with the same FNV-1a as frame_hash.
+fn roll(hash: u32, frame_hash: u32) -> u32 {
+    frame_hash.to_le_bytes().iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let dir =
+            std::env::temp_dir().join(format!("nes-regress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("count.asm"),
+            "
+            loop:
+              inc $10
+              jmp loop
+            ",
+        )
+        .unwrap();
+        let mut corpus = Corpus::parse(
+            r#"
+            interval = 2
+
+            [[rom]]
+            path = "count.asm"
+            frames = 5
+
+            [[rom]]
+            path = "missing.asm"
+            frames = 1
+            "#,
+        )
+        .unwrap();
+
+        // A hash every 2 frames, and the last frame.
+        let results = corpus.run(&dir);
+        assert_eq!(results[0].hashes.len(), 3);
+        assert_eq!(results[0].row(), "???");
+        assert!(!results[0].passed());
+        assert!(results[1].row().starts_with("error: "));
+
+        // Recording the hashes makes the run pass, and they survive the round trip.
+        corpus.update(&results);
+        assert_eq!(corpus.roms[1].hashes, Vec::<u32>::new());
+        let mut corpus = Corpus::parse(&corpus.to_toml()).unwrap();
+        let results = corpus.run(&dir);
+        assert_eq!(results[0].row(), "...");
+        assert!(results[0].passed());
+
+        // Everything after the first mismatch is off too.
+        corpus.roms[0].hashes[1] ^= 1;
+        corpus.roms[0].hashes[2] ^= 1;
+        assert_eq!(corpus.run(&dir)[0].row(), ".Xx");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Corpus::parse("interval = 0").is_err());
+    }
+}
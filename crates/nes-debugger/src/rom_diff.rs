@@ -0,0 +1,321 @@
+//! Compare the code of two ROMs, like two revisions of a game, or a ROM before and
+//! after a patch. Both PRG ROMs are disassembled, split into routines, and the
+//! routines are lined up with a diff, so that the code that moved around because of
+//! a change elsewhere isn't reported. Only the routines that changed are shown, with
+//! a diff of their instructions.
+//!
+//! Without any help, the whole PRG ROM is disassembled as code, which turns the data
+//! into nonsense instructions. A code/data log, like the .cdl files FCEUX writes while
+//! a game is played, marks which bytes were run as code, and the rest is shown as
+//! data.
+use nes_core::opcodes::{self, Mode, OpCode, OPCODES};
+use std::fmt::Write;
+
+/// The bit of a code/data log byte that marks a PRG ROM byte as run as code.
+const CDL_CODE: u8 = 0b01;
+/// How many data bytes go on a .byte line.
+const DATA_LINE_BYTES: usize = 8;
+
+/// A line of the disassembly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// The offset into the PRG ROM. The CPU address depends on the mapper's banks,
+    /// so it isn't known here.
+    pub offset: usize,
+    pub text: String,
+    /// Returns, and jumps that don't come back, are the end of a routine.
+    ends_routine: bool,
+}
+
+/// Disassemble the PRG ROM. The code/data log, if there is one, has a byte for every
+/// byte of the PRG ROM.
+pub fn disassemble(prg: &[u8], code_data_log: Option<&[u8]>) -> Vec<Line> {
+    let is_code = |offset: usize| match code_data_log {
+        Some(log) => log.get(offset).is_some_and(|flags| flags & CDL_CODE != 0),
+        None => true,
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < prg.len() {
+        if is_code(offset) {
+            if let Some(line) = disassemble_instruction(prg, offset) {
+                offset += 1 + OPCODES[prg[offset] as usize].mode.operand_size() as usize;
+                lines.push(line);
+                continue;
+            }
+        }
+        // Anything that isn't an instruction is data, which runs until the next code.
+        let start = offset;
+        offset += 1;
+        while offset < prg.len() && offset - start < DATA_LINE_BYTES && !is_code(offset) {
+            offset += 1;
+        }
+        let bytes: Vec<String> = prg[start..offset]
+            .iter()
+            .map(|byte| format!("${:02x}", byte))
+            .collect();
+        lines.push(Line {
+            offset: start,
+            text: format!(".byte {}", bytes.join(", ")),
+            ends_routine: false,
+        });
+    }
+    lines
+}
+
+/// Returns None for the illegal opcodes, and the instructions cut off by the end of
+/// the ROM, which are most likely data.
+fn disassemble_instruction(prg: &[u8], offset: usize) -> Option<Line> {
+    let opcode = prg[offset];
+    if opcodes::is_illegal(opcode) {
+        return None;
+    }
+    let entry = &OPCODES[opcode as usize];
+    let operands =
+        prg.get(offset + 1..offset + 1 + entry.mode.operand_size() as usize)?;
+    let u8_operand = operands.first().copied().unwrap_or(0);
+    let u16_operand = match *operands {
+        [low, high] => u16::from_le_bytes([low, high]),
+        _ => 0,
+    };
+    let name = entry.name;
+    let text = match entry.mode {
+        Mode::Implied | Mode::None => name.to_string(),
+        Mode::Immediate => format!("{} #${:02x}", name, u8_operand),
+        Mode::ZeroPage => format!("{} ${:02x}", name, u8_operand),
+        Mode::ZeroPageX => format!("{} ${:02x},x", name, u8_operand),
+        Mode::ZeroPageY => format!("{} ${:02x},y", name, u8_operand),
+        Mode::IndirectX => format!("{} (${:02x},x)", name, u8_operand),
+        Mode::IndirectY => format!("{} (${:02x}),y", name, u8_operand),
+        // The branch offsets don't change when the code moves, so they are kept as is.
+        Mode::Relative => format!("{} ${:02x}", name, u8_operand),
+        Mode::Absolute => format!("{} ${:04x}", name, u16_operand),
+        Mode::AbsoluteIndexedX => format!("{} ${:04x},x", name, u16_operand),
+        Mode::AbsoluteIndexedY => format!("{} ${:04x},y", name, u16_operand),
+        Mode::Indirect => format!("{} (${:04x})", name, u16_operand),
+    };
+    Some(Line {
+        offset,
+        text,
+        ends_routine: opcode == OpCode::RTS as u8
+            || opcode == OpCode::RTI as u8
+            || opcode == OpCode::JMP_abs as u8
+            || opcode == OpCode::JMP_ind as u8,
+    })
+}
+
+/// Split the lines after every return or jump.
+fn routines(lines: &[Line]) -> Vec<&[Line]> {
+    lines.split_inclusive(|line| line.ends_routine).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// The shortest edit script from a to b, using Myers' diff algorithm.
+/// http://www.xmailserver.org/diff2.pdf
+fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    // The furthest x reached on each diagonal k = x - y, indexed by k + max + 1.
+    let index = |k: isize| (k + max + 1) as usize;
+    let mut v = vec![0; 2 * max as usize + 3];
+    // The diagonals from -d to d, before each step d, to walk the path back.
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v[index(-d)..=index(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let (previous_x, previous_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let previous_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            (get(previous_k), get(previous_k) - previous_k)
+        };
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == previous_x {
+                y -= 1;
+                edits.push(Edit::Insert(y as usize));
+            } else {
+                x -= 1;
+                edits.push(Edit::Delete(x as usize));
+            }
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+/// Compare two disassemblies, and write out a diff of the routines that changed, e.g.
+///
+/// ```text
+/// @@ $00010 $00012 @@
+///   $00010  lda #$01
+/// - $00012  sta $0300
+/// + $00014  sta $0301
+///   $00015  rts
+/// ```
+///
+/// Each side has its own PRG ROM offsets. Nothing is written out if the code is the
+/// same.
+pub fn diff_disassembly(a: &[Line], b: &[Line]) -> String {
+    let routines_a = routines(a);
+    let routines_b = routines(b);
+    let texts = |routines: &[&[Line]]| -> Vec<Vec<String>> {
+        routines
+            .iter()
+            .map(|routine| routine.iter().map(|line| line.text.clone()).collect())
+            .collect()
+    };
+
+    // Gather the runs of routines that changed, and diff their lines.
+    let mut out = String::new();
+    let mut changed_a: Vec<&Line> = Vec::new();
+    let mut changed_b: Vec<&Line> = Vec::new();
+    let edits = diff(&texts(&routines_a), &texts(&routines_b));
+    for edit in edits
+        .iter()
+        .copied()
+        .chain(std::iter::once(Edit::Equal(0, 0)))
+    {
+        match edit {
+            Edit::Delete(index) => changed_a.extend(routines_a[index]),
+            Edit::Insert(index) => changed_b.extend(routines_b[index]),
+            Edit::Equal(..) => {
+                if !changed_a.is_empty() || !changed_b.is_empty() {
+                    write_hunk(&mut out, &changed_a, &changed_b);
+                }
+                changed_a.clear();
+                changed_b.clear();
+            }
+        }
+    }
+    out
+}
+
+fn write_hunk(out: &mut String, a: &[&Line], b: &[&Line]) {
+    let start = |lines: &[&Line]| {
+        lines
+            .first()
+            .map_or(String::from("-"), |line| format!("${:05x}", line.offset))
+    };
+    writeln!(out, "@@ {} {} @@", start(a), start(b)).unwrap();
+
+    let text_a: Vec<&str> = a.iter().map(|line| line.text.as_str()).collect();
+    let text_b: Vec<&str> = b.iter().map(|line| line.text.as_str()).collect();
+    for edit in diff(&text_a, &text_b) {
+        let (sign, line) = match edit {
+            Edit::Equal(index, _) => (' ', a[index]),
+            Edit::Delete(index) => ('-', a[index]),
+            Edit::Insert(index) => ('+', b[index]),
+        };
+        writeln!(out, "{} ${:05x}  {}", sign, line.offset, line.text).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let edits = diff(&['a', 'b', 'c'], &['a', 'c', 'd']);
+        assert_eq!(
+            edits,
+            [
+                Edit::Equal(0, 0),
+                Edit::Delete(1),
+                Edit::Equal(2, 1),
+                Edit::Insert(2),
+            ]
+        );
+        assert!(diff::<char>(&[], &[]).is_empty());
+        assert_eq!(diff(&[], &['a']), [Edit::Insert(0)]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // lda #$01, an illegal opcode, sta $0300,x, rts
+        let prg = [0xa9, 0x01, 0x02, 0x9d, 0x00, 0x03, 0x60];
+        let text: Vec<String> = disassemble(&prg, None)
+            .into_iter()
+            .map(|line| format!("{:x} {}", line.offset, line.text))
+            .collect();
+        assert_eq!(
+            text,
+            ["0 lda #$01", "2 .byte $02", "3 sta $0300,x", "6 rts"]
+        );
+
+        // Only the first instruction was logged as code.
+        let log = [1, 1, 0, 0, 0, 0, 0];
+        let text: Vec<String> = disassemble(&prg, Some(&log))
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(text, ["lda #$01", ".byte $02, $9d, $00, $03, $60"]);
+    }
+
+    #[test]
+    fn test_diff_disassembly() {
+        // Two routines, where the second changes, and moves because the first grew.
+        let a = [
+            0xa9, 0x01, 0x60, // lda #$01, rts
+            0xa9, 0x02, 0x8d, 0x00, 0x03, 0x60, // lda #$02, sta $0300, rts
+        ];
+        let b = [
+            0xa9, 0x01, 0xea, 0x60, // lda #$01, nop, rts
+            0xa9, 0x02, 0x8d, 0x01, 0x03, 0x60, // lda #$02, sta $0301, rts
+        ];
+        let a = disassemble(&a, None);
+        assert_eq!(diff_disassembly(&a, &a), "");
+        assert_eq!(
+            diff_disassembly(&a, &disassemble(&b, None)),
+            "@@ $00000 $00000 @@\n\
+             \x20 $00000  lda #$01\n\
+             + $00002  nop\n\
+             \x20 $00002  rts\n\
+             \x20 $00003  lda #$02\n\
+             - $00005  sta $0300\n\
+             + $00006  sta $0301\n\
+             \x20 $00008  rts\n"
+        );
+    }
+}
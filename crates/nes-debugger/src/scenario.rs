@@ -0,0 +1,408 @@
+use nes_asm::asm::assemble_program;
+use nes_asm::symbols::Symbols;
+use nes_core::bus::Bus;
+use nes_core::cartridge::Cartridge;
+use nes_core::constants::memory_range;
+use nes_core::cpu_6502::Cpu6502;
+use nes_core::mappers::SimpleProgram;
+use nes_core::region::NTSC_FRAME_CYCLES;
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+/// A scenario is a small regression test for a program. It runs the program for
+/// a number of frames, feeds in input, and then checks the results.
+///
+/// rom = "fibonacci-u8.asm"
+///
+/// [[input]]
+/// frame = 0
+/// address = 0x10
+/// value = 0x01
+///
+/// [[assert]]
+/// type = "memory-equals"
+/// frame = 1
+/// address = 0x02
+/// value = 0x01
+///
+/// [[assert]]
+/// type = "pc-reaches"
+/// label = "return"
+#[derive(Debug)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct Scenario {
+    /// The path to the program, relative to the scenario file.
+    pub rom: PathBuf,
+    /// How many frames to run. This defaults to the last frame that is used by the
+    /// input or the assertions.
+    pub frames: Option<u64>,
+    #[cfg_attr(feature = "toml", serde(default))]
+    pub input: Vec<Input>,
+    #[cfg_attr(feature = "toml", serde(default, rename = "assert"))]
+    pub assertions: Vec<Assertion>,
+}
+
+/// A byte written to memory at the start of a frame. The scenarios run on a bare CPU
+/// without the controller ports, so this is how the programs get their input.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(deny_unknown_fields))]
+pub struct Input {
+    pub frame: u64,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize))]
+#[cfg_attr(feature = "toml", serde(tag = "type", rename_all = "kebab-case"))]
+pub enum Assertion {
+    /// Check a byte of memory at the end of a frame.
+    MemoryEquals { frame: u64, address: u16, value: u8 },
+    /// Check the hash of the frame at the end of a frame. See `frame_hash`.
+    FrameHash { frame: u64, hash: u32 },
+    /// The program counter needs to reach the label at some point in the scenario.
+    PcReaches { label: String },
+}
+
+impl Assertion {
+    fn frame(&self) -> Option<u64> {
+        match *self {
+            Assertion::MemoryEquals { frame, .. }
+            | Assertion::FrameHash { frame, .. } => Some(frame),
+            Assertion::PcReaches { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioFailure {
+    pub assertion: Assertion,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    IoError(io::Error),
+    #[cfg(feature = "toml")]
+    TomlError(toml::de::Error),
+    Message(String),
+}
+
+impl From<io::Error> for ScenarioError {
+    fn from(error: io::Error) -> Self {
+        ScenarioError::IoError(error)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::de::Error> for ScenarioError {
+    fn from(error: toml::de::Error) -> Self {
+        ScenarioError::TomlError(error)
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::IoError(error) => write!(f, "{}", error),
+            #[cfg(feature = "toml")]
+            ScenarioError::TomlError(error) => write!(f, "{}", error),
+            ScenarioError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Scenario {
+    #[cfg(feature = "toml")]
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let mut scenario = Scenario::parse(&fs::read_to_string(path)?)?;
+        if let Some(directory) = path.parent() {
+            scenario.rom = directory.join(&scenario.rom);
+        }
+        Ok(scenario)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Load the ROM and run the scenario.
+    pub fn run(&self) -> Result<Vec<ScenarioFailure>, ScenarioError> {
+        let (mut cpu, symbols) = load_rom(&self.rom)?;
+        self.run_cpu(&mut cpu, &symbols)
+    }
+
+    fn frame_count(&self) -> u64 {
+        if let Some(frames) = self.frames {
+            return frames;
+        }
+        let last_input = self.input.iter().map(|input| input.frame);
+        let last_assertion = self.assertions.iter().filter_map(Assertion::frame);
+        last_input.chain(last_assertion).max().unwrap_or(0) + 1
+    }
+
+    /// Run the scenario on an already loaded CPU. The labels are used to look up
+    /// the "pc-reaches" assertions.
+    pub fn run_cpu(
+        &self,
+        cpu: &mut Cpu6502,
+        symbols: &Symbols,
+    ) -> Result<Vec<ScenarioFailure>, ScenarioError> {
+        // Look up the addresses of the labels before running anything.
+        let mut pc_targets = Vec::new();
+        for (index, assertion) in self.assertions.iter().enumerate() {
+            if let Assertion::PcReaches { label } = assertion {
+                match symbols.address(label) {
+                    Some(address) => pc_targets.push((index, address)),
+                    None => {
+                        return Err(ScenarioError::Message(format!(
+                            "Could not find the label {:?} in the ROM.",
+                            label
+                        )))
+                    }
+                }
+            }
+        }
+
+        let mut reached = vec![false; self.assertions.len()];
+        let mut failures = Vec::new();
+        let mut is_running = true;
+        let mut frame_cycles = 0;
+
+        for frame in 0..self.frame_count() {
+            for input in self.input.iter().filter(|input| input.frame == frame) {
+                cpu.bus.borrow_mut().set_u8(input.address, input.value);
+            }
+
+            while is_running && frame_cycles < NTSC_FRAME_CYCLES {
+                for &(index, address) in &pc_targets {
+                    if cpu.pc == address {
+                        reached[index] = true;
+                    }
+                }
+                is_running = cpu.tick().is_running();
+                frame_cycles += cpu.cycles as u64;
+            }
+            // Carry any extra cycles from the last instruction into the next frame.
+            frame_cycles = frame_cycles.saturating_sub(NTSC_FRAME_CYCLES);
+
+            for assertion in &self.assertions {
+                if assertion.frame() != Some(frame) {
+                    continue;
+                }
+                if let Some(message) = check_frame_assertion(cpu, assertion) {
+                    failures.push(ScenarioFailure {
+                        assertion: assertion.clone(),
+                        message: format!("Frame {}: {}", frame, message),
+                    });
+                }
+            }
+        }
+
+        for (index, address) in pc_targets {
+            if !reached[index] {
+                let assertion = self.assertions[index].clone();
+                let message = match &assertion {
+                    Assertion::PcReaches { label } => format!(
+                        "The program counter never reached {} (${:04x}).",
+                        label, address
+                    ),
+                    _ => unreachable!(),
+                };
+                failures.push(ScenarioFailure { assertion, message });
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+fn check_frame_assertion(cpu: &Cpu6502, assertion: &Assertion) -> Option<String> {
+    match *assertion {
+        Assertion::MemoryEquals { address, value, .. } => {
+            let actual = cpu.bus.borrow().peek_u8(address);
+            if actual == value {
+                return None;
+            }
+            Some(format!(
+                "Expected ${:04x} to be {:#04x} but it was {:#04x}.",
+                address, value, actual
+            ))
+        }
+        Assertion::FrameHash { hash, .. } => {
+            let actual = frame_hash(cpu);
+            if actual == hash {
+                return None;
+            }
+            Some(format!(
+                "Expected the frame hash to be {:#010x} but it was {:#010x}.",
+                hash, actual
+            ))
+        }
+        Assertion::PcReaches { .. } => None,
+    }
+}
+
+/// Until there is a PPU to render a picture, the frame hash covers the internal RAM.
+/// This uses the 32 bit FNV-1a hash, so that the value is stable across platforms
+/// and Rust versions.
+pub fn frame_hash(cpu: &Cpu6502) -> u32 {
+    let bus = cpu.bus.borrow();
+    let mut hash: u32 = 0x811c_9dc5;
+    for address in memory_range::RAM_ACTUAL.start..memory_range::RAM_ACTUAL.end {
+        hash ^= bus.peek_u8(address) as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Load an assembly file, or an iNES file. The iNES files don't have any labels.
+pub fn load_rom(path: &Path) -> Result<(Cpu6502, Symbols), ScenarioError> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("asm") => assemble(&fs::read_to_string(path)?),
+        Some("nes") => {
+            let cartridge = Cartridge::load(path)
+                .map_err(|error| ScenarioError::Message(error.to_string()))?;
+            Ok((Cpu6502::new(cartridge.into_shared_bus()), Symbols::new()))
+        }
+        _ => Err(ScenarioError::Message(format!(
+            "Unsupported ROM type, only .asm and .nes files can be run: {}",
+            path.display()
+        ))),
+    }
+}
+
+pub fn assemble(text: &str) -> Result<(Cpu6502, Symbols), ScenarioError> {
+    let (bytes, symbols) = assemble_program(text).map_err(ScenarioError::Message)?;
+    let cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(SimpleProgram::load(&bytes))));
+    Ok((cpu, symbols))
+}
+
+#[cfg(all(test, feature = "toml"))]
+mod test {
+    use super::*;
+
+    const PROGRAM: &str = "
+          lda #$11
+          sta $00
+          lda $10
+          sta $01
+        done:
+    ";
+
+    fn run(scenario: &str) -> Vec<ScenarioFailure> {
+        let (mut cpu, symbols) = assemble(PROGRAM).unwrap();
+        Scenario::parse(scenario)
+            .unwrap()
+            .run_cpu(&mut cpu, &symbols)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_passing_scenario() {
+        let failures = run(r#"
+            rom = "program.asm"
+
+            [[input]]
+            frame = 0
+            address = 0x10
+            value = 0x22
+
+            [[assert]]
+            type = "memory-equals"
+            frame = 0
+            address = 0x00
+            value = 0x11
+
+            [[assert]]
+            type = "memory-equals"
+            frame = 0
+            address = 0x01
+            value = 0x22
+
+            [[assert]]
+            type = "pc-reaches"
+            label = "done"
+        "#);
+        assert_eq!(failures, vec![]);
+    }
+
+    #[test]
+    fn test_failing_scenario() {
+        let failures = run(r#"
+            rom = "program.asm"
+
+            [[assert]]
+            type = "memory-equals"
+            frame = 2
+            address = 0x00
+            value = 0x33
+        "#);
+        assert_eq!(
+            failures
+                .iter()
+                .map(|failure| failure.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Frame 2: Expected $0000 to be 0x33 but it was 0x11."]
+        );
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let (mut cpu, _) = assemble(PROGRAM).unwrap();
+        let empty_hash = frame_hash(&cpu);
+        while cpu.tick().is_running() {}
+        assert_ne!(frame_hash(&cpu), empty_hash);
+
+        let failures = run(&format!(
+            r#"
+                rom = "program.asm"
+
+                [[assert]]
+                type = "frame-hash"
+                frame = 0
+                hash = {}
+            "#,
+            frame_hash(&cpu)
+        ));
+        assert_eq!(failures, vec![]);
+    }
+
+    #[test]
+    fn test_assertions_do_not_read() {
+        let (cpu, _) = assemble("").unwrap();
+        cpu.bus.borrow_mut().set_vblank(true);
+        let assertion = Assertion::MemoryEquals {
+            frame: 0,
+            address: 0x2002,
+            value: 0x00,
+        };
+        // Reading PPUSTATUS would clear the vblank flag.
+        assert!(check_frame_assertion(&cpu, &assertion).is_some());
+        assert!(check_frame_assertion(&cpu, &assertion).is_some());
+        frame_hash(&cpu);
+        assert!(!cpu.bus.borrow().take_ppu_status_read());
+    }
+
+    #[test]
+    fn test_unknown_label() {
+        let (mut cpu, symbols) = assemble(PROGRAM).unwrap();
+        let result = Scenario::parse(
+            r#"
+                rom = "program.asm"
+
+                [[assert]]
+                type = "pc-reaches"
+                label = "missing"
+            "#,
+        )
+        .unwrap()
+        .run_cpu(&mut cpu, &symbols);
+        assert!(result.is_err());
+    }
+}
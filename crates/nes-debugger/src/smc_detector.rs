@@ -0,0 +1,207 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::constants::memory_range;
+
+/// A write to an address that had already been run as code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeWrite {
+    /// The pc of the instruction that made the write.
+    pub pc: u16,
+    pub address: u16,
+    pub value: u8,
+}
+
+pub type CodeWriteHook = Box<dyn FnMut(&CodeWrite)>;
+
+/// Detects self-modifying code, by remembering every byte that was run as part of an
+/// instruction, and catching the writes that land on them. Homebrew does this on
+/// purpose to save cycles, but it can also be a sign of a stray pointer. Anything that
+/// caches decoded instructions needs to throw them away when this happens.
+///
+/// This lives on the bus, so that it sees every write. Start it with
+/// Bus::set_observer, and the CPU tells it about each instruction it runs. The
+/// RAM mirrors are folded together. Writes to $8000 and up are mapper registers, and
+/// don't change the ROM, so they are never code writes.
+pub struct SmcDetector {
+    executed: Vec<bool>,
+    /// The pc of the instruction currently being executed.
+    pc: u16,
+    code_writes: Vec<CodeWrite>,
+    hook: Option<CodeWriteHook>,
+}
+
+impl SmcDetector {
+    pub fn new() -> SmcDetector {
+        SmcDetector {
+            executed: vec![false; memory_range::PRG_ROM.start as usize],
+            pc: 0,
+            code_writes: Vec::new(),
+            hook: None,
+        }
+    }
+
+    /// Call the hook on every code write, as it happens. The bus is still borrowed
+    /// while the hook runs, so it can't look at the memory.
+    pub fn set_hook(&mut self, hook: impl FnMut(&CodeWrite) + 'static) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    fn index(address: u16) -> Option<usize> {
+        if address >= memory_range::PRG_ROM.start {
+            return None;
+        }
+        if address < memory_range::RAM.end {
+            return Some((address & memory_range::RAM_ACTUAL.mask()) as usize);
+        }
+        Some(address as usize)
+    }
+
+    /// Mark the opcode and the operands of an instruction as executed.
+    pub fn record_instruction(&mut self, pc: u16, length: u16) {
+        self.pc = pc;
+        for offset in 0..length {
+            if let Some(index) = SmcDetector::index(pc.wrapping_add(offset)) {
+                self.executed[index] = true;
+            }
+        }
+    }
+
+    pub fn record_write(&mut self, address: u16, value: u8) {
+        if !self.is_executed(address) {
+            return;
+        }
+        let code_write = CodeWrite {
+            pc: self.pc,
+            address,
+            value,
+        };
+        if let Some(ref mut hook) = self.hook {
+            hook(&code_write);
+        }
+        self.code_writes.push(code_write);
+    }
+
+    pub fn is_executed(&self, address: u16) -> bool {
+        SmcDetector::index(address).is_some_and(|index| self.executed[index])
+    }
+
+    /// Every code write so far, in order.
+    pub fn code_writes(&self) -> &[CodeWrite] {
+        &self.code_writes
+    }
+
+    /// Forget what was executed and written, e.g. after loading new code into RAM.
+    pub fn clear(&mut self) {
+        self.executed
+            .iter_mut()
+            .for_each(|executed| *executed = false);
+        self.code_writes.clear();
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "Self-modifying code ({} code writes)\n",
+            self.code_writes.len()
+        );
+        for code_write in &self.code_writes {
+            report.push_str(&format!(
+                "  ${:04x}: wrote ${:02x} to ${:04x}\n",
+                code_write.pc, code_write.value, code_write.address
+            ));
+        }
+        report
+    }
+}
+
+impl BusObserver for SmcDetector {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        SmcDetector::record_instruction(self, instruction.pc, instruction.size);
+    }
+
+    fn record_write(&mut self, access: &ObservedAccess) {
+        SmcDetector::record_write(self, access.address, access.value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenario::assemble;
+    use nes_core::cpu_6502::StopCondition;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_code_writes() {
+        let mut detector = SmcDetector::new();
+        detector.record_instruction(0x0300, 3);
+        detector.record_instruction(0x8000, 2);
+        // Not executed.
+        detector.record_write(0x0303, 0x01);
+        // The operand of the instruction at $0300, through a mirror.
+        detector.record_write(0x0b01, 0x42);
+        // A mapper register.
+        detector.record_write(0x8000, 0x00);
+
+        assert_eq!(
+            detector.code_writes(),
+            [CodeWrite {
+                pc: 0x8000,
+                address: 0x0b01,
+                value: 0x42
+            }]
+        );
+        detector.clear();
+        assert!(!detector.is_executed(0x0300));
+        assert!(detector.code_writes().is_empty());
+    }
+
+    #[test]
+    fn test_hook() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let mut detector = SmcDetector::new();
+        let hook_writes = Rc::clone(&writes);
+        detector.set_hook(move |code_write| {
+            hook_writes.borrow_mut().push(code_write.address)
+        });
+        detector.record_instruction(0x6000, 1);
+        detector.record_write(0x6000, 0xea);
+        assert_eq!(*writes.borrow(), vec![0x6000]);
+    }
+
+    #[test]
+    fn catches_writes_to_code() {
+        let (mut cpu, _) = assemble(
+            "
+              lda #$e8   ; inx
+              sta $0300
+              lda #$60   ; rts
+              sta $0301
+              jsr $0300
+              lda #$c8   ; iny
+              sta $0300  ; $800f
+              jsr $0300
+            ",
+        )
+        .unwrap();
+        cpu.bus.borrow_mut().set_observer(Some(SmcDetector::new()));
+        cpu.run(StopCondition::Jam);
+
+        assert_eq!((cpu.x, cpu.y), (1, 1));
+        let bus = cpu.bus.borrow();
+        let detector = bus.observer::<SmcDetector>().unwrap();
+        assert_eq!(
+            detector.code_writes(),
+            [CodeWrite {
+                pc: 0x800f,
+                address: 0x0300,
+                value: 0xc8
+            }]
+        );
+        assert!(detector.is_executed(0x0301));
+        assert!(!detector.is_executed(0x0302));
+    }
+}
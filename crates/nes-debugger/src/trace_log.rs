@@ -0,0 +1,273 @@
+// Execution logs in the format of nestest.log, which is the golden log for the
+// nestest.nes CPU test. Diffing against it finds the first instruction where this
+// CPU goes wrong.
+// https://www.qmtpro.com/~nes/misc/nestest.log
+// https://www.qmtpro.com/~nes/misc/nestest.txt
+
+use nes_core::bus::Bus;
+use nes_core::cpu_6502::TraceEvent;
+use nes_core::opcodes::{self, Mode, OPCODES};
+
+/// The PPU runs 3 dots for every CPU cycle.
+const DOTS_PER_CYCLE: u64 = 3;
+const DOTS_PER_SCANLINE: u64 = 341;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+/// Format a line of the log, e.g.
+///
+/// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+///
+/// The memory values are read with `Bus::try_read_u8` so that they don't have side
+/// effects, and the unmapped addresses show up as $FF. There's no PPU running yet, so
+/// its position is worked out from the cycles. nestest.nes is run with the rendering
+/// off, so there are no skipped dots to account for.
+pub fn nestest_line(event: &TraceEvent, bus: &Bus) -> String {
+    let registers = event.registers;
+    let bytes: Vec<String> = std::iter::once(&event.opcode)
+        .chain(event.operands.iter())
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let illegal = if opcodes::is_illegal(event.opcode) {
+        '*'
+    } else {
+        ' '
+    };
+    let dots = event.cycles * DOTS_PER_CYCLE;
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        registers.pc,
+        bytes.join(" "),
+        illegal,
+        disassemble(event, bus),
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.p,
+        registers.s,
+        (dots / DOTS_PER_SCANLINE) % SCANLINES_PER_FRAME,
+        dots % DOTS_PER_SCANLINE,
+        event.cycles,
+    )
+}
+
+/// Disassemble the instruction, along with the addresses and values it's going to
+/// touch, e.g. "LDA ($80,X) @ 80 = 0200 = 5A".
+fn disassemble(event: &TraceEvent, bus: &Bus) -> String {
+    let read = |address: u16| bus.try_read_u8(address).unwrap_or(0xff);
+    let read_u16 = |low: u16, high: u16| u16::from_le_bytes([read(low), read(high)]);
+    // The pointers for the indirect modes wrap around the zero page.
+    let read_zero_page_u16 =
+        |pointer: u8| read_u16(pointer as u16, pointer.wrapping_add(1) as u16);
+
+    let registers = event.registers;
+    let name = nestest_name(event.opcode);
+    let name = name.as_str();
+    let u8_operand = event.operands.first().copied().unwrap_or(0);
+    let u16_operand = match event.operands.as_slice() {
+        &[low, high] => u16::from_le_bytes([low, high]),
+        _ => 0,
+    };
+    let is_jump = name == "JMP" || name == "JSR";
+
+    match event.mode {
+        Mode::Implied | Mode::None => match name {
+            // The shifts and rotates without an operand work on the accumulator.
+            "ASL" | "LSR" | "ROL" | "ROR" => format!("{} A", name),
+            _ => name.to_string(),
+        },
+        Mode::Immediate => format!("{} #${:02X}", name, u8_operand),
+        Mode::ZeroPage => {
+            format!(
+                "{} ${:02X} = {:02X}",
+                name,
+                u8_operand,
+                read(u8_operand as u16)
+            )
+        }
+        Mode::ZeroPageX | Mode::ZeroPageY => {
+            let (index, index_name) = if event.mode == Mode::ZeroPageX {
+                (registers.x, 'X')
+            } else {
+                (registers.y, 'Y')
+            };
+            let address = u8_operand.wrapping_add(index);
+            format!(
+                "{} ${:02X},{} @ {:02X} = {:02X}",
+                name,
+                u8_operand,
+                index_name,
+                address,
+                read(address as u16)
+            )
+        }
+        Mode::Absolute if is_jump => format!("{} ${:04X}", name, u16_operand),
+        Mode::Absolute => {
+            format!("{} ${:04X} = {:02X}", name, u16_operand, read(u16_operand))
+        }
+        Mode::AbsoluteIndexedX | Mode::AbsoluteIndexedY => {
+            let (index, index_name) = if event.mode == Mode::AbsoluteIndexedX {
+                (registers.x, 'X')
+            } else {
+                (registers.y, 'Y')
+            };
+            let address = u16_operand.wrapping_add(index as u16);
+            format!(
+                "{} ${:04X},{} @ {:04X} = {:02X}",
+                name,
+                u16_operand,
+                index_name,
+                address,
+                read(address)
+            )
+        }
+        Mode::Indirect => {
+            // The pointer's high byte doesn't carry into the next page.
+            let [low, high] = u16_operand.to_le_bytes();
+            let high_address = u16::from_le_bytes([low.wrapping_add(1), high]);
+            format!(
+                "{} (${:04X}) = {:04X}",
+                name,
+                u16_operand,
+                read_u16(u16_operand, high_address)
+            )
+        }
+        Mode::IndirectX => {
+            let pointer = u8_operand.wrapping_add(registers.x);
+            let address = read_zero_page_u16(pointer);
+            format!(
+                "{} (${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                name,
+                u8_operand,
+                pointer,
+                address,
+                read(address)
+            )
+        }
+        Mode::IndirectY => {
+            let base_address = read_zero_page_u16(u8_operand);
+            let address = base_address.wrapping_add(registers.y as u16);
+            format!(
+                "{} (${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                name,
+                u8_operand,
+                base_address,
+                address,
+                read(address)
+            )
+        }
+        Mode::Relative => {
+            let next_pc = registers.pc.wrapping_add(2);
+            let target = next_pc.wrapping_add(u8_operand as i8 as u16);
+            format!("{} ${:04X}", name, target)
+        }
+    }
+}
+
+/// nestest.log names ISC as ISB.
+fn nestest_name(opcode: u8) -> String {
+    match OPCODES[opcode as usize].name {
+        "isc" => String::from("ISB"),
+        name => name.to_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::{AsmLexer, BytesLabels};
+    use nes_core::bus::Bus;
+    use nes_core::cpu_6502::{Cpu6502, Registers};
+    use nes_core::mappers::SimpleProgram;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Run the instructions with the start state of the nestest.nes automation mode,
+    /// and collect the log.
+    fn log(text: &str, instructions: usize) -> Vec<String> {
+        let mut lexer = AsmLexer::new(text);
+        lexer.parse().unwrap();
+        let BytesLabels { bytes, .. } = lexer.into_bytes().unwrap();
+        let bus = Bus::new_shared_bus(Box::new(SimpleProgram::load(&bytes)));
+        let mut cpu = Cpu6502::new(Rc::clone(&bus));
+        cpu.s = 0xfd;
+        cpu.p = 0x24;
+        cpu.total_cycles = 7;
+
+        let lines = Rc::new(RefCell::new(vec![]));
+        {
+            let lines = Rc::clone(&lines);
+            cpu.set_trace_hook(move |event| {
+                lines.borrow_mut().push(nestest_line(event, &bus.borrow()))
+            });
+        }
+        for _ in 0..instructions {
+            cpu.tick();
+        }
+        lines.replace(vec![])
+    }
+
+    #[test]
+    fn test_nestest_lines() {
+        let lines = log(
+            "
+            jmp start
+            start:
+            ldx #$00
+            stx $01
+            lda ($80,X)
+            ldy #$10
+            lda $0633,y
+        ",
+            6,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                "8000  4C 03 80  JMP $8003                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "8003  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+                "8005  86 01     STX $01 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+                "8007  A1 80     LDA ($80,X) @ 80 = 0000 = 00    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15",
+                "8009  A0 10     LDY #$10                        A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 63 CYC:21",
+                "800B  B9 33 06  LDA $0633,Y @ 0643 = 00         A:00 X:00 Y:10 P:24 SP:FD PPU:  0, 69 CYC:23",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassembly() {
+        let mut bus = Bus::new_shared_bus(Box::new(SimpleProgram::new()));
+        let bus = Rc::get_mut(&mut bus).unwrap().get_mut();
+        let line = |pc, opcode, operands: &[u8]| {
+            let event = TraceEvent {
+                opcode,
+                mode: OPCODES[opcode as usize].mode,
+                operands: operands.to_vec(),
+                registers: Registers {
+                    pc,
+                    ..Default::default()
+                },
+                cycles: 0,
+            };
+            let line = nestest_line(&event, bus);
+            line[..line.find("A:").unwrap()].trim_end().to_string()
+        };
+        assert_eq!(line(0xc6bd, 0x04, &[0xa9]), "C6BD  04 A9    *NOP $A9 = 00");
+        assert_eq!(
+            line(0xc000, 0xe3, &[0x45]),
+            "C000  E3 45    *ISB ($45,X) @ 45 = 0000 = 00"
+        );
+        assert_eq!(line(0xc000, 0x4a, &[]), "C000  4A        LSR A");
+        // Branches are relative to the next instruction.
+        assert_eq!(line(0xc72a, 0xb0, &[0x04]), "C72A  B0 04     BCS $C730");
+        assert_eq!(line(0xc72a, 0xd0, &[0xfc]), "C72A  D0 FC     BNE $C728");
+        assert_eq!(
+            line(0xc000, 0x20, &[0x2d, 0xc7]),
+            "C000  20 2D C7  JSR $C72D"
+        );
+        // Nothing is mapped to the APU registers yet.
+        assert_eq!(
+            line(0xc000, 0xad, &[0x15, 0x40]),
+            "C000  AD 15 40  LDA $4015 = FF"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use nes_core::bus::{BusObserver, InstructionStart, ObservedAccess};
+use nes_core::constants::memory_range;
+use std::ops::RangeInclusive;
+
+/// What happens to a write into a protected region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protection {
+    /// Record the write, and still route it to the mapper, which may use it as a
+    /// register write.
+    Report,
+    /// Record the write, and drop it, so that nothing on the bus sees it. This keeps
+    /// a flat memory from having its "ROM" overwritten.
+    Block,
+}
+
+/// A write that landed in a protected region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RomWrite {
+    /// The pc of the instruction that made the write.
+    pub pc: u16,
+    pub address: u16,
+    pub value: u8,
+    pub protection: Protection,
+}
+
+/// Catches the writes into regions that shouldn't be written, like the PRG-ROM of a
+/// cartridge without any mapper registers, where a write is most likely a stray store
+/// through a bad pointer.
+///
+/// This lives on the bus, so that it sees every write. Start it with
+/// Bus::set_observer, and the CPU tells it about each instruction it runs, so
+/// that the writes can be traced back to the code that made them.
+pub struct WriteProtection {
+    regions: Vec<(RangeInclusive<u16>, Protection)>,
+    /// The pc of the instruction currently being executed.
+    pc: u16,
+    rom_writes: Vec<RomWrite>,
+}
+
+impl WriteProtection {
+    pub fn new() -> WriteProtection {
+        WriteProtection {
+            regions: Vec::new(),
+            pc: 0,
+            rom_writes: Vec::new(),
+        }
+    }
+
+    /// Report the writes to the whole PRG-ROM, for the cartridges that don't have
+    /// any mapper registers there, like NROM.
+    pub fn prg_rom() -> WriteProtection {
+        let mut write_protection = WriteProtection::new();
+        write_protection.protect(
+            memory_range::PRG_ROM.start..=memory_range::PRG_ROM.end,
+            Protection::Report,
+        );
+        write_protection
+    }
+
+    /// When regions overlap, the one that was protected first wins.
+    pub fn protect(&mut self, range: RangeInclusive<u16>, protection: Protection) {
+        self.regions.push((range, protection));
+    }
+
+    pub fn record_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Record the write if it's protected, and return true if it should be dropped.
+    pub fn check_write(&mut self, address: u16, value: u8) -> bool {
+        let protection = match self
+            .regions
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+        {
+            Some(&(_, protection)) => protection,
+            None => return false,
+        };
+        self.rom_writes.push(RomWrite {
+            pc: self.pc,
+            address,
+            value,
+            protection,
+        });
+        protection == Protection::Block
+    }
+
+    /// The protected writes, in the order they were made.
+    pub fn rom_writes(&self) -> &[RomWrite] {
+        &self.rom_writes
+    }
+
+    pub fn take_rom_writes(&mut self) -> Vec<RomWrite> {
+        std::mem::take(&mut self.rom_writes)
+    }
+
+    /// A line per write, e.g. "$8123 wrote $ff to $c000".
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for rom_write in &self.rom_writes {
+            report.push_str(&format!(
+                "${:04x} wrote ${:02x} to ${:04x}{}\n",
+                rom_write.pc,
+                rom_write.value,
+                rom_write.address,
+                match rom_write.protection {
+                    Protection::Report => "",
+                    Protection::Block => " (blocked)",
+                }
+            ));
+        }
+        report
+    }
+}
+
+impl BusObserver for WriteProtection {
+    fn record_instruction(&mut self, instruction: &InstructionStart) {
+        WriteProtection::record_instruction(self, instruction.pc);
+    }
+
+    fn check_write(&mut self, access: &ObservedAccess) -> bool {
+        WriteProtection::check_write(self, access.address, access.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_asm::asm::load_flat_machine;
+    use nes_core::cpu_6502::StopCondition;
+
+    #[test]
+    fn test_write_protection() {
+        let mut write_protection = WriteProtection::prg_rom();
+        write_protection.protect(0x6000..=0x6fff, Protection::Block);
+
+        write_protection.record_instruction(0x8123);
+        assert!(!write_protection.check_write(0x0200, 0x01));
+        assert!(!write_protection.check_write(0xc000, 0xff));
+        write_protection.record_instruction(0x8456);
+        assert!(write_protection.check_write(0x6000, 0x02));
+
+        assert_eq!(
+            write_protection.report(),
+            "$8123 wrote $ff to $c000\n$8456 wrote $02 to $6000 (blocked)\n"
+        );
+        assert_eq!(write_protection.take_rom_writes().len(), 2);
+        assert!(write_protection.rom_writes().is_empty());
+    }
+
+    #[test]
+    fn catches_stray_stores() {
+        let (mut machine, _) = load_flat_machine::<0x10000>(
+            "
+            lda #$ea
+            sta $9000  ; $8002
+            ",
+        )
+        .unwrap();
+        let mut write_protection = WriteProtection::new();
+        write_protection.protect(0x8000..=0xffff, Protection::Block);
+        machine
+            .cpu
+            .bus
+            .borrow_mut()
+            .set_observer(Some(write_protection));
+        machine.cpu.run(StopCondition::Jam);
+
+        let bus = machine.cpu.bus.borrow();
+        assert_eq!(
+            bus.observer::<WriteProtection>().unwrap().rom_writes(),
+            [RomWrite {
+                pc: 0x8002,
+                address: 0x9000,
+                value: 0xea,
+                protection: Protection::Block,
+            }]
+        );
+        // The flat memory never saw the write.
+        assert_eq!(bus.peek_u8(0x9000), 0x00);
+    }
+}
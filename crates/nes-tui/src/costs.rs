@@ -0,0 +1,102 @@
+use nes_core::cpu_6502::{Cpu6502, ExtraCycle};
+use nes_core::opcodes;
+
+/// The size and speed of the instructions as they are stepped through, for when a
+/// routine is being optimized. The totals count from a marker, which can be moved to
+/// the start of the routine.
+#[derive(Default)]
+pub struct Costs {
+    /// Show the cost of each instruction in the instruction pane.
+    pub annotate: bool,
+    instructions: u64,
+    bytes: u64,
+    cycles: u64,
+}
+
+impl Costs {
+    pub fn new() -> Costs {
+        Costs::default()
+    }
+
+    /// Start counting the totals from here.
+    pub fn set_marker(&mut self) {
+        *self = Costs {
+            annotate: self.annotate,
+            ..Costs::default()
+        };
+    }
+
+    /// Add an instruction that ran. The cycles are the ones it actually took, so they
+    /// include the extra cycles, and any interrupt that was serviced.
+    pub fn record(&mut self, bytes: u16, cycles: u64) {
+        self.instructions += 1;
+        self.bytes += bytes as u64;
+        self.cycles += cycles;
+    }
+
+    /// The totals since the marker, for the pane's title, e.g. "12 ran, 30b 45c".
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ran, {}b {}c",
+            self.instructions, self.bytes, self.cycles
+        )
+    }
+}
+
+/// The bytes of the instruction at the pc.
+pub fn instruction_size(cpu: &Cpu6502, pc: u16) -> u16 {
+    let entry = opcodes::decode(cpu.variant, cpu.bus.borrow().peek_u8(pc));
+    1 + entry.mode.operand_size()
+}
+
+/// The cost of an instruction before it runs, e.g. "3b 4-5c" for an indexed read that
+/// takes another cycle when it crosses a page.
+pub fn annotation(cpu: &Cpu6502, pc: u16) -> String {
+    let entry = opcodes::decode(cpu.variant, cpu.bus.borrow().peek_u8(pc));
+    let bytes = 1 + entry.mode.operand_size();
+    match entry.extra_cycle {
+        ExtraCycle::None => format!("{}b {}c", bytes, entry.cycles),
+        ExtraCycle::PageBoundary => {
+            format!("{}b {}-{}c", bytes, entry.cycles, entry.cycles + 1)
+        }
+        ExtraCycle::IfTaken => {
+            format!("{}b {}-{}c", bytes, entry.cycles, entry.cycles + 2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_debugger::scenario::assemble;
+
+    #[test]
+    fn test_costs() {
+        let (mut cpu, _) = assemble(
+            "
+              lda $0200,x
+              sta $10
+              bne done
+            done:
+            ",
+        )
+        .unwrap();
+        assert_eq!(annotation(&cpu, 0x8000), "3b 4-5c");
+        assert_eq!(annotation(&cpu, 0x8003), "2b 3c");
+        assert_eq!(annotation(&cpu, 0x8005), "2b 2-4c");
+
+        let mut costs = Costs::new();
+        costs.annotate = true;
+        for _ in 0..2 {
+            let bytes = instruction_size(&cpu, cpu.pc());
+            let cycles = cpu.total_cycles();
+            cpu.tick();
+            costs.record(bytes, cpu.total_cycles() - cycles);
+        }
+        assert_eq!(costs.summary(), "2 ran, 5b 7c");
+
+        costs.set_marker();
+        assert_eq!(costs.summary(), "0 ran, 0b 0c");
+        assert!(costs.annotate);
+    }
+}
@@ -1,33 +1,15 @@
 use std::path::Path;
 
-use nes::{
-    asm::{AddressToLabel, AsmLexer, BytesLabels},
-    bus::Bus,
-    cpu_6502::Cpu6502,
-    mappers::SimpleProgram,
-    opcodes::OpCode,
-};
+use nes_asm::{asm::load_flat_machine, symbols::Symbols};
+use nes_core::cpu_6502::Cpu6502;
 
-pub fn load_cpu<P: AsRef<Path>>(filename: P) -> (Cpu6502, AddressToLabel) {
+/// The samples are plain 6502 programs, so run them on a flat 64K machine.
+pub fn load_cpu<P: AsRef<Path>>(filename: P) -> (Cpu6502, Symbols) {
     let contents = std::fs::read_to_string(filename).unwrap();
-    let mut lexer = AsmLexer::new(&contents);
 
-    match lexer.parse() {
-        Ok(_) => {
-            let BytesLabels {
-                mut bytes,
-                address_to_label,
-            } = lexer.into_bytes().unwrap();
-            bytes.push(OpCode::KIL as u8);
-            (
-                Cpu6502::new(Bus::new_shared_bus(Box::new(SimpleProgram::load(&bytes)))),
-                address_to_label,
-            )
-        }
-        Err(parse_error) => {
-            parse_error.panic_nicely();
-            panic!("");
-        }
+    match load_flat_machine::<0x10000>(&contents) {
+        Ok((machine, symbols)) => (machine.cpu, symbols),
+        Err(message) => panic!("{}", message),
     }
 }
 
@@ -39,7 +21,7 @@ mod test_cpu {
 
     fn run_cpu_n_ticks(cpu: &mut Cpu6502, ticks: usize) {
         for _ in 0..ticks {
-            if !cpu.tick() {
+            if !cpu.tick().is_running() {
                 panic!("The CPU quit before the end.");
             }
         }
@@ -47,7 +29,7 @@ mod test_cpu {
 
     fn run_cpu_max_ticks(cpu: &mut Cpu6502) {
         for _ in 0..MAX_TICKS {
-            if !cpu.tick() {
+            if !cpu.tick().is_running() {
                 return;
             }
         }
@@ -56,7 +38,6 @@ mod test_cpu {
 
     fn get_ram_page_text(cpu: &Cpu6502, page_u8: u8, width: u16) -> Vec<String> {
         let mut strings = vec![];
-        let bus = cpu.bus.borrow();
 
         // Decide how many columns to make.
         let col_width = "$0000 0011 2233 4455 6677 8899 aabb ccdd eeff ".len();
@@ -82,7 +63,8 @@ mod test_cpu {
             // ^^^
             parts.push(format!("${:02x}{:x}_ ", page_u8, i));
             for j in 0..8 {
-                let [le, be] = bus.read_u16(page_u16 + i * 16 + j * 2).to_le_bytes();
+                let address = page_u16 + i * 16 + j * 2;
+                let [le, be] = [cpu.peek(address), cpu.peek(address + 1)];
                 // $0000 0011 2233 4455 6677 8899 aabb ccdd eeff
                 //       ^^^^
                 parts.push(format!("{:02x}{:02x} ", le, be));
@@ -107,7 +89,7 @@ mod test_cpu {
     fn run_cpu(filename: &str, ticks: Option<usize>) -> Cpu6502 {
         let mut path = PathBuf::new();
         path.push(std::env::var("CARGO_MANIFEST_DIR").unwrap());
-        path.push("src/bin/cpu-visualizer/asm/");
+        path.push("asm/");
         path.push(filename);
 
         let (mut cpu, _) = load_cpu(&path);
@@ -121,13 +103,13 @@ mod test_cpu {
 
     fn get_cpu_text(cpu: &Cpu6502) -> Vec<String> {
         vec![
-            format!("Ticks: {}", cpu.tick_count),
-            format!(" A: 0x{:02x} 0b{:08b}", cpu.a, cpu.a),
-            format!(" X: 0x{:02x} 0b{:08b}", cpu.x, cpu.x),
-            format!(" Y: 0x{:02x} 0b{:08b}", cpu.y, cpu.y),
-            format!("PC: 0x{:04x}", cpu.pc),
-            format!("SP: 0x{:02x} 0b{:08b}", cpu.s, cpu.s),
-            format!(" P: 0x{:02x} 0b{:08b}", cpu.p, cpu.p),
+            format!("Ticks: {}", cpu.tick_count()),
+            format!(" A: 0x{:02x} 0b{:08b}", cpu.a(), cpu.a()),
+            format!(" X: 0x{:02x} 0b{:08b}", cpu.x(), cpu.x()),
+            format!(" Y: 0x{:02x} 0b{:08b}", cpu.y(), cpu.y()),
+            format!("PC: 0x{:04x}", cpu.pc()),
+            format!("SP: 0x{:02x} 0b{:08b}", cpu.s(), cpu.s()),
+            format!(" P: 0x{:02x} 0b{:08b}", cpu.status(), cpu.status()),
             String::from("           NV__DIZC"),
         ]
     }
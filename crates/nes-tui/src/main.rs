@@ -0,0 +1,980 @@
+mod costs;
+mod load_cpu;
+mod theme;
+#[allow(dead_code)]
+mod util;
+
+use crate::costs::Costs;
+use crate::theme::{Theme, PALETTE_NAMES};
+use crate::util::event::{Event, Events};
+use nes_asm::symbols::Symbols;
+use nes_core::{
+    cpu_6502::{
+        branch_stats::{BranchStats, HotLoop},
+        cpu_stats::CpuStats,
+        stack_analyzer::StackAnalyzer,
+        vblank_analyzer::VblankAnalyzer,
+        Cpu6502,
+    },
+    memory_map,
+    opcodes::{self, Mode},
+    uninit_ram::{UninitPolicy, UninitRamTrap},
+};
+use nes_debugger::{
+    auto_label::AutoLabeler,
+    crash_report::{self, CrashReporter},
+    heatmap::Heatmap,
+    ram_audit::RamAudit,
+    smc_detector::SmcDetector,
+};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    env,
+    error::Error,
+    io,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+};
+use termion::{
+    event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen,
+};
+use tui::{
+    backend::TermionBackend,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Terminal,
+};
+
+struct CliArgs {
+    filename: String,
+    theme: Theme,
+    /// Run the CPU's analyzers, see attach_analyzers.
+    analyzers: bool,
+    /// Attach the bus observers, see attach_observers.
+    observers: bool,
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!(
+        "Usage: cpu-visualizer path/to/file.asm [--palette name] [--analyzers] [--observers]"
+    );
+    std::process::exit(1);
+}
+
+fn parse_cli_args() -> CliArgs {
+    let args: Vec<String> = env::args().collect();
+    let mut theme = Theme::default();
+    let mut analyzers = false;
+    let mut observers = false;
+    let mut flags = args.iter().skip(2);
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--palette" => {
+                let name = match flags.next() {
+                    Some(name) => name,
+                    None => exit_with_usage(),
+                };
+                theme = match Theme::from_name(name) {
+                    Some(theme) => theme,
+                    None => {
+                        eprintln!(
+                            "Unknown palette {:?}, use one of: {}",
+                            name,
+                            PALETTE_NAMES.join(", ")
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--analyzers" => analyzers = true,
+            "--observers" => observers = true,
+            _ => exit_with_usage(),
+        }
+    }
+    match args.get(1) {
+        Some(filename) => CliArgs {
+            filename: filename.clone(),
+            theme,
+            analyzers,
+            observers,
+        },
+        None => {
+            eprintln!(
+                "The CPU visualizer expects the first argument to be a path to a raw .asm file."
+            );
+            eprintln!(
+                "cargo run --bin cpu-visualizer crates/nes-tui/asm/add-with-carry.asm"
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    crash_report::install_panic_hook();
+    // Load the CPU first, as this can exit the process.
+    let CliArgs {
+        filename,
+        theme,
+        analyzers,
+        observers,
+    } = parse_cli_args();
+    let (mut cpu, mut symbols) = load_cpu::load_cpu(&filename);
+    // Name the subroutines and handlers that the program didn't label.
+    let mut auto_labeler = AutoLabeler::new();
+    auto_labeler.trace_static(&cpu.bus.borrow());
+    auto_labeler.seed(&mut symbols);
+    if analyzers {
+        attach_analyzers(&mut cpu);
+    }
+    if observers {
+        attach_observers(&cpu);
+    }
+    let crash_reporter = CrashReporter::in_temp_dir();
+    crash_reporter.prepare(&mut cpu);
+    let mut crash_report = None;
+    let mut costs = Costs::new();
+
+    // Terminal initialization
+    let stdout = io::stdout().into_raw_mode()?;
+    let stdout = MouseTerminal::from(stdout);
+    let stdout = AlternateScreen::from(stdout);
+    let backend = TermionBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    let events = Events::new();
+
+    let registers_rect_width = 40;
+    let instructions_rect_width = 40;
+    let mut last_drawn_tick_count = u64::MAX;
+    let mut executed_instructions = VecDeque::new();
+    // The page shown under the zero page, which starts on the stack.
+    let mut memory_page: u8 = 0x01;
+    // Skip over the pages that only mirror other memory.
+    let mut collapse_mirrors = false;
+    // The zero page and the shown page from the last draw, to highlight the changes.
+    let mut last_drawn_ram = read_ram(&cpu, memory_page);
+
+    'main: loop {
+        if last_drawn_tick_count != cpu.tick_count() {
+            // Only draw again if the cpu tick has changed.
+            terminal.draw(|frame| {
+                last_drawn_tick_count = cpu.tick_count();
+                let ram = read_ram(&cpu, memory_page);
+                let heat = read_heat(&cpu, memory_page);
+                let hot_loops = cpu
+                    .branch_stats
+                    .as_ref()
+                    .map(BranchStats::hot_loops)
+                    .unwrap_or_default();
+                let frame_rect = frame.size();
+                //
+                // col 0                    1         2           3  main_rect_height
+                //     |--------------------|---------|-----------|  -
+                //     | zero page          | instr   | registers |  |  - main_rect_inner_height
+                //     |                    | uctions |           |  |  |
+                //     |                    |         |           |  |  |
+                //     |--------------------|         |           |  |  |
+                //     | stack              |         |           |  |  |
+                //     |                    |         |           |  |  |
+                //     |                    |         |           |  |  -
+                //     |--------------------|---------|-----------|  -
+                let col0 = 0;
+                let col3 = frame_rect.width;
+                let col2 = col3 - registers_rect_width;
+                let col1 = col2 - instructions_rect_width;
+
+                let main_rect_height = frame_rect.height;
+                let main_rect_inner_height = main_rect_height - 2;
+
+                let ram_rect_width =
+                    frame_rect.width - registers_rect_width - instructions_rect_width;
+                let ram_rect_inner_width = ram_rect_width - 2;
+                let ram_rect = Rect::new(col0, 0, ram_rect_width, main_rect_height);
+
+                let instructions_rect =
+                    Rect::new(col1, 0, instructions_rect_width, main_rect_height);
+
+                let registers_rect =
+                    Rect::new(col2, 0, registers_rect_width, main_rect_height);
+
+                let block = Block::default()
+                    .style(Style::default().bg(theme.background).fg(theme.text));
+                frame.render_widget(block, frame_rect);
+
+                let zero_page_text = get_ram_page_text(
+                    &ram[0..0x100],
+                    &last_drawn_ram[0..0x100],
+                    &heat[0..0x100],
+                    0,
+                    ram_rect_inner_width,
+                    &theme,
+                );
+                let zero_page_rect = {
+                    let mut rect = ram_rect;
+                    rect.height = zero_page_text.len() as u16 + 2;
+                    rect
+                };
+
+                // Zero Page RAM
+                frame.render_widget(
+                    Paragraph::new(zero_page_text)
+                        .block(create_block("Zero Page RAM", &theme))
+                        .alignment(Alignment::Left),
+                    zero_page_rect,
+                );
+
+                let stack_page_text = get_ram_page_text(
+                    &ram[0x100..0x200],
+                    &last_drawn_ram[0x100..0x200],
+                    &heat[0x100..0x200],
+                    memory_page,
+                    ram_rect_inner_width,
+                    &theme,
+                );
+                let stack_page_rect = {
+                    let mut rect = ram_rect;
+                    rect.y = zero_page_rect.height;
+                    rect.height = stack_page_text.len() as u16 + 2;
+                    rect
+                };
+                // Stack Page RAM, or whichever page is shown.
+                let stack_title = match &cpu.stack_analyzer {
+                    _ if memory_page != 0x01 => memory_page_title(memory_page),
+                    Some(stack_analyzer) => format!(
+                        "Stack Page RAM (depth {}, max {}, calls {}, warnings {})",
+                        0xFF - cpu.s(),
+                        stack_analyzer.max_depth(),
+                        stack_analyzer.call_stack().len(),
+                        stack_analyzer.warnings().len()
+                    ),
+                    None => String::from("Stack Page RAM"),
+                };
+                frame.render_widget(
+                    Paragraph::new(stack_page_text)
+                        .block(create_block(stack_title, &theme))
+                        .alignment(Alignment::Left),
+                    stack_page_rect,
+                );
+
+                // Instructions, with the totals since the marker when annotating.
+                let instructions_title = if costs.annotate {
+                    format!("Instructions: {}", costs.summary())
+                } else {
+                    String::from("Instructions")
+                };
+                frame.render_widget(
+                    Paragraph::new(get_instructions_text(
+                        &cpu,
+                        main_rect_inner_height,
+                        &mut executed_instructions,
+                        &symbols,
+                        &hot_loops,
+                        &costs,
+                        &theme,
+                    ))
+                    .block(create_block(instructions_title, &theme))
+                    .alignment(Alignment::Left),
+                    instructions_rect,
+                );
+
+                // Registeres
+                let registers_text = vec![
+                    add_tick_count(cpu.tick_count(), &theme),
+                    add_register_span("A", cpu.a(), &theme),
+                    add_register_span("X", cpu.x(), &theme),
+                    add_register_span("Y", cpu.y(), &theme),
+                    add_pc_register_span(cpu.pc(), &theme),
+                    add_register_span("SP", cpu.s(), &theme),
+                    add_register_span("P", cpu.status(), &theme),
+                    add_status_register_info("NV__DIZC", &theme),
+                    add_status_register_info("||  ||||", &theme),
+                    add_status_register_info("||  |||+- Carry", &theme),
+                    add_status_register_info("||  ||+-- Zero", &theme),
+                    add_status_register_info("||  |+--- Interrupt Disable", &theme),
+                    add_status_register_info("||  +---- Decimal", &theme),
+                    add_status_register_info("|+-------- Overflow", &theme),
+                    add_status_register_info("+--------- Negative", &theme),
+                ];
+
+                let cpu_registers_rect = {
+                    let mut rect = registers_rect;
+                    rect.height = (registers_text.len() as u16 + 2).min(rect.height);
+                    rect
+                };
+                frame.render_widget(
+                    Paragraph::new(registers_text)
+                        .block(create_block("CPU Registers", &theme))
+                        .alignment(Alignment::Left)
+                        .wrap(Wrap { trim: true }),
+                    cpu_registers_rect,
+                );
+
+                // The last values written to the memory-mapped registers.
+                let io_registers_rect = {
+                    let mut rect = registers_rect;
+                    rect.y = cpu_registers_rect.height;
+                    rect.height = registers_rect.height - cpu_registers_rect.height;
+                    rect
+                };
+                frame.render_widget(
+                    Paragraph::new(get_io_registers_text(&cpu, &theme))
+                        .block(create_block("I/O Registers", &theme))
+                        .alignment(Alignment::Left)
+                        .wrap(Wrap { trim: true }),
+                    io_registers_rect,
+                );
+            })?;
+            last_drawn_ram = read_ram(&cpu, memory_page);
+        }
+
+        // Handle all of the keyboard events.
+        if let Event::Input(key) = events.next()? {
+            match key {
+                Key::Char('q') => {
+                    break;
+                }
+                // Soft reset, this keeps the RAM intact.
+                Key::Char('r') => {
+                    cpu.reset();
+                    executed_instructions.clear();
+                    // Force a redraw, since the tick count doesn't change.
+                    last_drawn_tick_count = u64::MAX;
+                }
+                // Show the cost of each instruction, and the totals since the marker.
+                Key::Char('c') => {
+                    costs.annotate = !costs.annotate;
+                    executed_instructions.clear();
+                    last_drawn_tick_count = u64::MAX;
+                }
+                // Start the totals from the next instruction.
+                Key::Char('m') => {
+                    costs.set_marker();
+                    last_drawn_tick_count = u64::MAX;
+                }
+                // Page through the memory under the zero page.
+                Key::Char('[') | Key::Char(']') => {
+                    memory_page =
+                        step_page(memory_page, key == Key::Char(']'), collapse_mirrors);
+                    last_drawn_ram = read_ram(&cpu, memory_page);
+                    last_drawn_tick_count = u64::MAX;
+                }
+                // Skip the mirrors when paging, and go from a mirror to the memory it
+                // repeats.
+                Key::Char('f') => {
+                    collapse_mirrors = !collapse_mirrors;
+                    if collapse_mirrors {
+                        memory_page = canonical_page(memory_page);
+                        last_drawn_ram = read_ram(&cpu, memory_page);
+                    }
+                    last_drawn_tick_count = u64::MAX;
+                }
+                Key::Char('n') | Key::Char('1') => {
+                    let is_running =
+                        tick(&mut cpu, &mut costs, &crash_reporter, &mut crash_report);
+                    if !is_running {
+                        break;
+                    }
+                }
+                // Skip through instructions much quicker.
+                Key::Char(c) => {
+                    if let Some(n) = c.to_digit(10) {
+                        if n != 0 {
+                            for _ in 0..((n + 1).pow(2)) {
+                                if !tick(
+                                    &mut cpu,
+                                    &mut costs,
+                                    &crash_reporter,
+                                    &mut crash_report,
+                                ) {
+                                    break 'main;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Leave the alternate screen before printing the reports of the attached tools.
+    drop(terminal);
+    if let Some(branch_stats) = &cpu.branch_stats {
+        eprint!("{}", branch_stats.report());
+    }
+    if let Some(stats) = &cpu.stats {
+        eprint!("{}", stats.report(10));
+    }
+    if let Some(stack_analyzer) = &cpu.stack_analyzer {
+        eprint!("{}", stack_analyzer.report());
+    }
+    if let Some(vblank_analyzer) = &cpu.vblank_analyzer {
+        eprint!("{}", vblank_analyzer.report());
+    }
+    if let Some(ram_audit) = cpu.bus.borrow().observer::<RamAudit>() {
+        eprint!("{}", ram_audit.report());
+    }
+    if let Some(smc_detector) = cpu.bus.borrow().observer::<SmcDetector>() {
+        eprint!("{}", smc_detector.report());
+    }
+    if let Some(heatmap) = cpu.bus.borrow().observer::<Heatmap>() {
+        eprint!("{}", heatmap.report());
+    }
+    if let Some(uninit_ram_trap) = cpu.bus.borrow().observer::<UninitRamTrap>() {
+        eprint!("{}", uninit_ram_trap.report());
+    }
+    match crash_report {
+        Some(Ok(dir)) => {
+            eprintln!("The emulator crashed, the report is in {}", dir.display());
+            std::process::exit(1);
+        }
+        Some(Err(error)) => {
+            eprintln!(
+                "The emulator crashed, and the report couldn't be written: {}",
+                error
+            );
+            std::process::exit(1);
+        }
+        None => Ok(()),
+    }
+}
+
+/// Collect the branch and instruction stats, and watch the stack and the vblank. The
+/// stats are shown next to the instructions, and the stack's depth in its title.
+fn attach_analyzers(cpu: &mut Cpu6502) {
+    cpu.branch_stats = Some(BranchStats::new());
+    cpu.stats = Some(CpuStats::new());
+    cpu.stack_analyzer = Some(StackAnalyzer::new());
+    cpu.vblank_analyzer = Some(VblankAnalyzer::new());
+}
+
+/// Watch every access to the bus. The heatmap colors the RAM.
+fn attach_observers(cpu: &Cpu6502) {
+    let mut bus = cpu.bus.borrow_mut();
+    bus.set_observer(Some(RamAudit::new()));
+    bus.set_observer(Some(SmcDetector::new()));
+    bus.set_observer(Some(Heatmap::new()));
+    bus.set_observer(Some(UninitRamTrap::new(UninitPolicy::Log)));
+}
+
+/// Tick the CPU, and return whether it's still running. A panic in the emulator stops
+/// it, and writes out the crash report.
+fn tick(
+    cpu: &mut Cpu6502,
+    costs: &mut Costs,
+    crash_reporter: &CrashReporter,
+    crash_report: &mut Option<io::Result<PathBuf>>,
+) -> bool {
+    let bytes = costs::instruction_size(cpu, cpu.pc());
+    let cycles = cpu.total_cycles();
+    match panic::catch_unwind(AssertUnwindSafe(|| cpu.tick())) {
+        Ok(state) => {
+            costs.record(bytes, cpu.total_cycles() - cycles);
+            state.is_running()
+        }
+        Err(_) => {
+            *crash_report = Some(crash_reporter.write(cpu));
+            false
+        }
+    }
+}
+
+/// The registers that are worth decoding as they are written.
+const DECODED_IO_REGISTERS: [u16; 5] = [0x2000, 0x2001, 0x2002, 0x4015, 0x4017];
+
+fn get_io_registers_text<'a>(cpu: &Cpu6502, theme: &Theme) -> Vec<Spans<'a>> {
+    let bus = cpu.bus.borrow();
+    let mut spans_list = vec![];
+    for &address in DECODED_IO_REGISTERS.iter() {
+        let register =
+            memory_map::register_for(address).expect("The address is a register.");
+        let mut parts = vec![Span::styled(
+            format!("{} ({})", register.name, register.access),
+            Style::default()
+                .fg(theme.keyword)
+                .add_modifier(Modifier::BOLD),
+        )];
+        match bus.last_register_write(address) {
+            Some(value) => {
+                parts.push(Span::styled(": 0x", Style::default().fg(theme.muted)));
+                parts.push(Span::styled(
+                    format!("{:02x}", value),
+                    Style::default().fg(theme.text),
+                ));
+                spans_list.push(Spans::from(parts));
+                //   nametable=$2000 increment=1 sprites=$0000
+                let fields: Vec<String> = register
+                    .fields
+                    .iter()
+                    .map(|field| field.describe(value))
+                    .collect();
+                spans_list.push(Spans::from(Span::styled(
+                    fields.join(" "),
+                    Style::default().fg(theme.alternate_text),
+                )));
+            }
+            None => {
+                parts.push(Span::styled(": --", Style::default().fg(theme.muted)));
+                spans_list.push(Spans::from(parts));
+            }
+        }
+    }
+    spans_list
+}
+
+fn create_block<'a>(title: impl Into<Cow<'a, str>>, theme: &Theme) -> Block<'a> {
+    Block::default()
+        .borders(Borders::ALL)
+        .style(Style::default().bg(theme.background).fg(theme.border))
+        .title(Span::styled(
+            title,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+}
+
+fn add_register_span<'a>(name: &'a str, value: u8, theme: &Theme) -> Spans<'a> {
+    let mut parts = vec![];
+    if name.len() == 1 {
+        parts.push(Span::styled("·", Style::default().fg(theme.background)));
+    }
+    parts.push(Span::styled(
+        name,
+        Style::default()
+            .fg(theme.keyword)
+            .add_modifier(Modifier::BOLD),
+    ));
+    parts.push(Span::styled(": 0x", Style::default().fg(theme.muted)));
+    parts.push(Span::styled(
+        format!("{:02x}", value),
+        Style::default().fg(theme.text),
+    ));
+    parts.push(Span::styled(" 0b", Style::default().fg(theme.muted)));
+    parts.push(Span::styled(
+        format!("{:08b}", value),
+        Style::default().fg(theme.text),
+    ));
+
+    Spans::from(parts)
+}
+
+fn add_pc_register_span(value: u16, theme: &Theme) -> Spans<'static> {
+    let mut parts = vec![];
+    parts.push(Span::styled(
+        "PC",
+        Style::default()
+            .fg(theme.keyword)
+            .add_modifier(Modifier::BOLD),
+    ));
+    parts.push(Span::styled(": 0x", Style::default().fg(theme.muted)));
+    parts.push(Span::styled(
+        format!("{:04x}", value),
+        Style::default().fg(theme.text),
+    ));
+
+    Spans::from(parts)
+}
+
+fn add_tick_count(count: u64, theme: &Theme) -> Spans<'static> {
+    let parts = vec![
+        Span::styled(
+            "Ticks: ",
+            Style::default()
+                .fg(theme.keyword)
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(count.to_string(), Style::default().fg(theme.text)),
+    ];
+
+    Spans::from(parts)
+}
+
+fn add_status_register_info<'a>(info: &'a str, theme: &Theme) -> Spans<'a> {
+    let parts = vec![
+        Span::styled("·          ", Style::default().fg(theme.background)),
+        Span::styled(info, Style::default().fg(theme.muted)),
+    ];
+    Spans::from(parts)
+}
+
+fn get_instructions_text<'a>(
+    cpu: &'a Cpu6502,
+    height: u16,
+    executed_instructions: &'a mut VecDeque<Spans<'static>>,
+    symbols: &Symbols,
+    hot_loops: &[HotLoop],
+    costs: &Costs,
+    theme: &Theme,
+) -> Vec<Spans<'a>> {
+    let mut spans_list: Vec<Spans> = vec![];
+    let mut pc = cpu.pc();
+    // Peek, so that disassembling doesn't set off the side effects of the registers.
+    let bus = cpu.bus.borrow();
+
+    // Make sure the VecDeque is sized correctly to the available of back buffer.
+    let executed_len = height / 3;
+    executed_instructions.truncate(executed_len as usize);
+
+    let next_instructructions_len =
+        height - executed_instructions.len() as u16 + height % 3;
+
+    for spans in executed_instructions.iter().rev() {
+        spans_list.push(spans.clone());
+    }
+
+    for i in 0..next_instructructions_len {
+        let mut parts = vec![];
+
+        let base_style = {
+            if i == 0 {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            }
+        };
+
+        // label:
+        // ^^^^^^
+        //   $4027 clc
+        for pc_label in symbols.labels_at(pc) {
+            let mut span =
+                Span::styled(format!("{}: ", pc_label.name), base_style.fg(theme.label));
+
+            // Is this selected?
+            if i == 0 {
+                // Remember this for in the list of executed instructions.
+                let mut dim_span = span.clone();
+                dim_span.style = base_style.fg(theme.dim);
+                executed_instructions.push_front(Spans::from(dim_span));
+
+                // Bold the current label too.
+                span.style = span.style.add_modifier(Modifier::BOLD);
+            }
+
+            spans_list.push(Spans::from(span));
+        }
+
+        let instruction_pc = pc;
+
+        // label:
+        //   $4027 clc
+        //   ^^^^^
+        parts.push(Span::styled(
+            format!("  ${:02x} ", pc.clone()),
+            base_style.fg(theme.address),
+        ));
+
+        let operation = bus.peek_u8(pc);
+        pc = pc.wrapping_add(1);
+
+        let entry = opcodes::decode(cpu.variant, operation);
+        let opcode = entry.name;
+        let mode = entry.mode;
+        parts.push(Span::styled(opcode, base_style.fg(theme.keyword)));
+
+        let mut get_u8 = || {
+            let value = bus.peek_u8(pc);
+            pc += 1;
+            value
+        };
+        let mut add_operand = |string| {
+            parts.push(Span::styled(string, base_style.fg(theme.text)));
+        };
+
+        match mode {
+            Mode::Absolute
+            | Mode::AbsoluteIndexedX
+            | Mode::AbsoluteIndexedY
+            | Mode::Indirect => {
+                let value = bus.peek_u16(pc);
+                pc += 2;
+
+                let mut address_style = base_style.fg(theme.text);
+
+                //   $4023 jmp section2 $4029
+                //             ^^^^^^^^
+                if let Some(label) = symbols.describe(value, None) {
+                    parts.push(Span::styled(
+                        format!(" {}", label),
+                        base_style.fg(theme.label),
+                    ));
+                    // Dim out the address.
+                    address_style = base_style.fg(theme.dim);
+                } else if let Some(register) = memory_map::register_for(value) {
+                    //   $4023 sta PPUCTRL $2000
+                    //             ^^^^^^^
+                    parts.push(Span::styled(
+                        format!(" {}", register.name),
+                        base_style.fg(theme.label),
+                    ));
+                    address_style = base_style.fg(theme.dim);
+                };
+
+                if mode == Mode::Indirect {
+                    //   $4023 jmp ($4029)
+                    //             ^
+                    parts.push(Span::styled("(", base_style.fg(theme.text)));
+                }
+
+                //   $4023 jmp section2 $4029
+                //                      ^^^^^
+                //   $4023 jmp $4029
+                //             ^^^^^
+                parts.push(Span::styled(format!(" ${:04x}\n", value), address_style));
+
+                // Handle indexed modes.
+                if mode == Mode::AbsoluteIndexedX {
+                    //   $4023 jmp $4029,X
+                    //                  ^^
+                    parts.push(Span::styled(",X", base_style.fg(theme.text)));
+                }
+                if mode == Mode::AbsoluteIndexedX {
+                    //   $4023 jmp $4029,Y
+                    //                  ^^
+                    parts.push(Span::styled(",Y", base_style.fg(theme.text)));
+                }
+
+                if mode == Mode::Indirect {
+                    //   $4023 jmp ($4029)
+                    //                   ^
+                    parts.push(Span::styled(")", base_style.fg(theme.text)));
+                }
+            }
+
+            // u8 operands:
+            Mode::Immediate => add_operand(format!(" #${:02x}\n", get_u8())),
+            Mode::ZeroPage => add_operand(format!(" ${:02x}\n", get_u8())),
+            Mode::ZeroPageX => add_operand(format!(" ${:02x},X\n", get_u8())),
+            Mode::ZeroPageY => add_operand(format!(" ${:02x},Y\n", get_u8())),
+            Mode::IndirectX => add_operand(format!(" (${:02x},X)\n", get_u8())),
+            Mode::IndirectY => add_operand(format!(" (${:02x}),Y\n", get_u8())),
+
+            Mode::Relative => {
+                let relative_value = get_u8() as i8;
+                let address: u16 = (instruction_pc as i32 + relative_value as i32) as u16;
+
+                match symbols.label(address) {
+                    Some(label) => {
+                        parts.push(Span::styled(
+                            format!(" {}", label),
+                            base_style.fg(theme.label),
+                        ));
+                        // Dim out the address.
+                        parts.push(Span::styled(
+                            format!(" {:+}\n", relative_value),
+                            base_style.fg(theme.dim),
+                        ))
+                    }
+                    None => add_operand(format!(" {:+}\n", relative_value)),
+                }
+
+                //   $4023 bne loop -3 2/3 hot 84%
+                //                     ^^^^^^^^^^^
+                if let Some(branch_stats) = &cpu.branch_stats {
+                    if let Some(counts) = branch_stats.branch_counts(instruction_pc) {
+                        let mut annotation =
+                            format!(" {}/{}", counts.taken, counts.total());
+                        if let Some(hot_loop) = hot_loops
+                            .iter()
+                            .find(|hot_loop| hot_loop.end == instruction_pc)
+                        {
+                            annotation.push_str(&format!(
+                                " hot {:.0}%",
+                                hot_loop.cycle_share * 100.0
+                            ));
+                        }
+                        parts.push(Span::styled(annotation, base_style.fg(theme.dim)));
+                    }
+                }
+            }
+
+            Mode::Implied | Mode::None => {}
+        }
+
+        //   $4023 lda $0200,X 3b 4-5c
+        //                     ^^^^^^^
+        if costs.annotate {
+            parts.push(Span::styled(
+                format!(" {}", costs::annotation(cpu, instruction_pc)),
+                base_style.fg(theme.dim),
+            ));
+        }
+
+        if i == 0 {
+            let mut span_dimmed = parts.clone();
+            for span in span_dimmed.iter_mut() {
+                span.style = base_style.fg(theme.dim);
+            }
+            // Remember this instruction for the next tick.
+            executed_instructions.push_front(Spans::from(span_dimmed));
+        }
+
+        spans_list.push(Spans::from(parts));
+    }
+
+    spans_list
+}
+
+/// The addresses of the zero page, followed by the shown page.
+fn page_addresses(page: u8) -> impl Iterator<Item = u16> {
+    let page_start = (page as u16) << 8;
+    (0..0x100).chain(page_start..=page_start | 0xff)
+}
+
+/// Copy out the zero page and the shown page.
+fn read_ram(cpu: &Cpu6502, page: u8) -> Vec<u8> {
+    // Peek, so that these reads don't count in the RAM audit.
+    let bus = cpu.bus.borrow();
+    page_addresses(page)
+        .map(|address| bus.peek_u8(address))
+        .collect()
+}
+
+/// How hot each byte of the zero page and the shown page is, see Heatmap. The heatmap
+/// counts the mirrors on the memory they repeat, so a mirror is as hot as that memory.
+fn read_heat(cpu: &Cpu6502, page: u8) -> Vec<f32> {
+    match cpu.bus.borrow().observer::<Heatmap>() {
+        Some(heatmap) => page_addresses(page)
+            .map(|address| heatmap.heat(memory_map::canonical_address(address)))
+            .collect(),
+        None => vec![0.0; 0x200],
+    }
+}
+
+/// The page that a page of mirrors repeats, or the page itself.
+fn canonical_page(page: u8) -> u8 {
+    (memory_map::canonical_address((page as u16) << 8) >> 8) as u8
+}
+
+/// The next or previous page to show. When collapsing the mirrors, the pages that
+/// only mirror other memory are skipped.
+fn step_page(page: u8, forward: bool, collapse_mirrors: bool) -> u8 {
+    let mut page = page;
+    loop {
+        page = if forward {
+            page.wrapping_add(1)
+        } else {
+            page.wrapping_sub(1)
+        };
+        if !collapse_mirrors || canonical_page(page) == page {
+            return page;
+        }
+    }
+}
+
+/// Name the page after its region, and point a mirror at the memory it repeats.
+fn memory_page_title(page: u8) -> String {
+    let address = (page as u16) << 8;
+    let region = memory_map::region_for(address);
+    match region.mirror {
+        Some(_) => format!(
+            "Page ${:02x} {}, same as ${:04x}-${:04x}",
+            page,
+            region.name,
+            region.canonical_address(address),
+            region.canonical_address(address | 0xff),
+        ),
+        None => format!("Page ${:02x} {}", page, region.name),
+    }
+}
+
+/// Fade the theme's hot color towards black as the byte cools down.
+fn heat_background(theme: &Theme, heat: f32) -> Option<Color> {
+    if heat == 0.0 {
+        return None;
+    }
+    match theme.hot {
+        Color::Rgb(r, g, b) => {
+            let scale = |channel: u8| (channel as f32 * heat) as u8;
+            Some(Color::Rgb(scale(r), scale(g), scale(b)))
+        }
+        // The named colors can't be faded, so only show the hot half.
+        hot if heat >= 0.5 => Some(hot),
+        _ => None,
+    }
+}
+
+fn get_ram_page_text(
+    page: &[u8],
+    previous_page: &[u8],
+    page_heat: &[f32],
+    page_u8: u8,
+    width: u16,
+    theme: &Theme,
+) -> Vec<Spans<'static>> {
+    let mut spans = vec![];
+    let style = Style::default();
+    let address_style = style.fg(theme.address);
+    let changed = style.fg(theme.changed).add_modifier(Modifier::BOLD);
+
+    // Decide how many columns to make.
+    let col_width = "$0000 0011 2233 4455 6677 8899 aabb ccdd eeff ".len();
+    let cols = (width / col_width as u16).max(1);
+
+    // Compute the page view.
+    // e.g.
+    // $0000 0011 2233 4455 6677 8899 aabb ccdd eeff
+    // $0010 0011 2233 4455 6677 8899 aabb ccdd eeff
+    // $0020 0011 2233 4455 6677 8899 aabb ccdd eeff
+    // ..... .... .... .... .... .... .... .... ....
+    // $00F0 0011 2233 4455 6677 8899 aabb ccdd eeff
+
+    spans.push(Spans::from(Span::styled(
+        "       0 1  2 3  4 5  6 7  8 9  a b  c d  e f ".repeat(cols as usize),
+        //     0011 2233 4455 6677 8899 aabb ccdd eeff
+        style.fg(theme.label),
+    )));
+
+    let mut parts = vec![];
+    for i in 0..16 {
+        // $00 0011 2233 4455 6677 8899 aabb ccdd eeff
+        // ^^^
+        // The rows of mirrors are dimmed, as the memory lives somewhere else.
+        let row_address = (page_u8 as u16) << 8 | (i as u16) << 4;
+        let row_style = if memory_map::region_for(row_address).mirror.is_some() {
+            style.fg(theme.dim)
+        } else {
+            address_style
+        };
+        parts.push(Span::styled(
+            format!("${:02x}{:x}_ ", page_u8, i),
+            row_style,
+        ));
+        for j in 0..8 {
+            let index = i * 16 + j * 2;
+            // $0000 0011 2233 4455 6677 8899 aabb ccdd eeff
+            //       ^^^^
+            let word_style = if j % 2 == 0 {
+                style.fg(theme.text)
+            } else {
+                style.fg(theme.alternate_text)
+            };
+            for offset in index..index + 2 {
+                let value = page[offset];
+                let mut value_style = if value == previous_page[offset] {
+                    word_style
+                } else {
+                    changed
+                };
+                if let Some(background) = heat_background(theme, page_heat[offset]) {
+                    value_style = value_style.bg(background);
+                }
+                parts.push(Span::styled(format!("{:02x}", value), value_style));
+            }
+            parts.push(Span::styled(" ", word_style));
+        }
+
+        if (i + 1) % cols as usize == 0 {
+            spans.push(Spans::from(parts.clone()));
+            parts.clear();
+        }
+    }
+    if !parts.is_empty() {
+        spans.push(Spans::from(parts.clone()));
+    }
+
+    spans
+}
@@ -0,0 +1,101 @@
+use tui::style::Color;
+
+/// The colors used by the visualizer. Pick one with `--palette`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub text: Color,
+    pub border: Color,
+    /// Addresses in the instructions and the RAM pages.
+    pub address: Color,
+    pub label: Color,
+    /// Opcodes and register names.
+    pub keyword: Color,
+    /// Every other word in the RAM pages, to make them easier to scan.
+    pub alternate_text: Color,
+    /// Instructions that already ran, and addresses that have a label.
+    pub dim: Color,
+    /// Punctuation and help text.
+    pub muted: Color,
+    /// RAM bytes that changed since the last draw.
+    pub changed: Color,
+    /// The background of the hottest RAM bytes, see Heatmap. The cooler bytes fade
+    /// towards black.
+    pub hot: Color,
+}
+
+pub const PALETTE_NAMES: &[&str] = &["default", "color-blind", "high-contrast"];
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "color-blind" => Some(Theme::color_blind()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// The Okabe-Ito colors, which stay distinct for the common kinds of color
+    /// blindness. The changed bytes don't rely on red against green.
+    /// https://jfly.uni-koeln.de/color/
+    fn color_blind() -> Theme {
+        Theme {
+            address: Color::Rgb(86, 180, 233),
+            label: Color::Rgb(204, 121, 167),
+            keyword: Color::Rgb(240, 228, 66),
+            changed: Color::Rgb(230, 159, 0),
+            hot: Color::Rgb(0, 114, 178),
+            ..Theme::default()
+        }
+    }
+
+    /// Only use the brightest colors, and don't dim anything out too far.
+    fn high_contrast() -> Theme {
+        Theme {
+            background: Color::Black,
+            text: Color::Rgb(255, 255, 255),
+            border: Color::Rgb(255, 255, 255),
+            address: Color::Rgb(0, 255, 255),
+            label: Color::Rgb(255, 128, 255),
+            keyword: Color::Rgb(255, 255, 0),
+            alternate_text: Color::Rgb(255, 255, 255),
+            dim: Color::Rgb(200, 200, 200),
+            muted: Color::Rgb(190, 190, 190),
+            changed: Color::Rgb(255, 160, 0),
+            hot: Color::Rgb(170, 0, 170),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: Color::Black,
+            text: Color::White,
+            border: Color::Rgb(150, 150, 150),
+            address: Color::Rgb(0, 200, 200),
+            label: Color::Rgb(200, 100, 200),
+            keyword: Color::Yellow,
+            alternate_text: Color::Rgb(200, 200, 200),
+            dim: Color::Rgb(170, 170, 170),
+            muted: Color::DarkGray,
+            changed: Color::Rgb(255, 80, 80),
+            hot: Color::Rgb(140, 40, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_palette_names() {
+        for name in PALETTE_NAMES {
+            assert!(Theme::from_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(Theme::from_name("default"), Some(Theme::default()));
+        assert_eq!(Theme::from_name("sepia"), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! The cartridge is a trait object, so anything can be plugged into the bus. This
+//! example makes a cartridge with a character output port at $6000, and runs a
+//! program that prints to it.
+//!
+//! cargo run --example custom_bus
+use nes::asm::{AsmLexer, BytesLabels};
+use nes::bus::Bus;
+use nes::constants::InterruptVectors;
+use nes::cpu_6502::{Cpu6502, CpuState};
+use nes::mappers::Mapper;
+use std::process;
+
+const OUTPUT_PORT: u16 = 0x6000;
+
+const PROGRAM: &str = "
+      ldx #$30 ; '0'
+    loop:
+      stx $6000
+      inx
+      cpx #$3a ; '9' + 1
+      bne loop
+      lda #$0a ; '\\n'
+      sta $6000
+      .byte $02 ; kil
+";
+
+struct PrinterCartridge {
+    program: Vec<u8>,
+    cycles: u64,
+}
+
+impl Mapper for PrinterCartridge {
+    fn read_cpu(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => Some(self.program[(addr & 0x7fff) as usize]),
+            _ => None,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) -> bool {
+        if addr == OUTPUT_PORT {
+            print!("{}", value as char);
+            return true;
+        }
+        addr >= 0x8000
+    }
+
+    // The mappers can watch the clock too.
+    fn cpu_cycle(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+fn main() {
+    let mut lexer = AsmLexer::new(PROGRAM);
+    if let Err(error) = lexer.parse() {
+        eprintln!("{}", error.nice_message());
+        process::exit(1);
+    }
+    let BytesLabels { bytes, .. } = match lexer.into_bytes() {
+        Ok(bytes_labels) => bytes_labels,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    // Load the program at $8000, and point the reset vector at it.
+    let mut program = vec![0; 0x8000];
+    program[..bytes.len()].copy_from_slice(&bytes);
+    let reset_vector = (InterruptVectors::ResetVector as u16 & 0x7fff) as usize;
+    program[reset_vector..reset_vector + 2].copy_from_slice(&0x8000u16.to_le_bytes());
+
+    let cartridge = PrinterCartridge { program, cycles: 0 };
+    let mut cpu = Cpu6502::new(Bus::new_shared_bus(Box::new(cartridge)));
+
+    let mut instructions = 0;
+    let state = loop {
+        let state = cpu.tick();
+        if !state.is_running() {
+            break state;
+        }
+        instructions += 1;
+    };
+
+    assert_eq!(state, CpuState::Jammed);
+    println!(
+        "Ran {} instructions, and stopped at ${:04x}",
+        instructions,
+        cpu.pc()
+    );
+}
@@ -0,0 +1,102 @@
+//! Run a program with the headless runner, and save its screen to a PNG. The PPU
+//! isn't emulated yet, so this uses the easy6502 convention of a 32x32 screen in
+//! RAM at $0200-$05ff, with one byte per pixel, and 16 colors.
+//!
+//! cargo run --example headless_screenshot -- [out.png]
+use nes::headless::{HeadlessRunner, Limits};
+use nes::scenario;
+use std::fs::File;
+use std::io::BufWriter;
+use std::{env, process};
+
+const SCREEN_ADDRESS: u16 = 0x0200;
+const SCREEN_SIZE: u16 = 32;
+// Scale up the pixels so that the image is easier to look at.
+const SCALE: u16 = 8;
+
+// The easy6502 palette, which is borrowed from the Commodore 64.
+const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0xff, 0xff, 0xff],
+    [0x88, 0x00, 0x00],
+    [0xaa, 0xff, 0xee],
+    [0xcc, 0x44, 0xcc],
+    [0x00, 0xcc, 0x55],
+    [0x00, 0x00, 0xaa],
+    [0xee, 0xee, 0x77],
+    [0xdd, 0x88, 0x55],
+    [0x66, 0x44, 0x00],
+    [0xff, 0x77, 0x77],
+    [0x33, 0x33, 0x33],
+    [0x77, 0x77, 0x77],
+    [0xaa, 0xff, 0x66],
+    [0x00, 0x88, 0xff],
+    [0xbb, 0xbb, 0xbb],
+];
+
+// Fill the screen with color bars, using the index of each pixel as its color.
+const PROGRAM: &str = "
+      ldy #$00
+    loop:
+      tya
+      sta $0200,y
+      adc #$01
+      sta $0300,y
+      adc #$01
+      sta $0400,y
+      adc #$01
+      sta $0500,y
+      iny
+      bne loop
+";
+
+fn main() {
+    let out = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("screenshot.png"));
+
+    let (mut cpu, _) = match scenario::assemble(PROGRAM) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let report = HeadlessRunner::new(Limits::default()).run_cpu(&mut cpu);
+    if let Some(failure) = report.failure {
+        eprintln!("{}", failure);
+        process::exit(1);
+    }
+
+    let size = SCREEN_SIZE * SCALE;
+    let mut rgb = Vec::with_capacity(size as usize * size as usize * 3);
+    for y in 0..size {
+        for x in 0..size {
+            let pixel = (y / SCALE) * SCREEN_SIZE + (x / SCALE);
+            let color = cpu.peek(SCREEN_ADDRESS + pixel) & 0x0f;
+            rgb.extend_from_slice(&PALETTE[color as usize]);
+        }
+    }
+
+    let file = match File::create(&out) {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!("Could not create {}: {}", out, error);
+            process::exit(1);
+        }
+    };
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size as u32, size as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb));
+    if let Err(error) = result {
+        eprintln!("Could not write the PNG: {}", error);
+        process::exit(1);
+    }
+    println!(
+        "Ran {} instructions, and saved the screen to {}",
+        report.instructions, out
+    );
+}
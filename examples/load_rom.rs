@@ -32,6 +32,9 @@ fn main() {
         Err(nes::rom::ROMLoadError::IoError(err)) => {
             eprintln!("Error loading ROM: {:?}", err);
         }
+        Err(nes::rom::ROMLoadError::BoardOverride(string)) => {
+            eprintln!("Error loading ROM: {:?}", string);
+        }
     };
 }
 
@@ -44,7 +47,7 @@ fn print_characters(rom: &ROM) {
     let tiles_per_col = {
         // Round up division.
         let mut v = tile_count / 8;
-        if v % 8 != 0 {
+        if !v.is_multiple_of(8) {
             v += 1;
         };
         v
@@ -54,7 +57,7 @@ fn print_characters(rom: &ROM) {
 
     let path = Path::new(r"image.png");
     let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
+    let w = &mut BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, png_width as u32, png_height as u32);
     encoder.set_color(png::ColorType::Grayscale);
@@ -0,0 +1,44 @@
+//! Run a game without any frontend, and print how far it got.
+//!
+//! cargo run --example run_rom -- path/to/game.nes
+use nes::bus::Bus;
+use nes::cartridge::Cartridge;
+use nes::cpu_6502::Cpu6502;
+use nes::headless::{HeadlessRunner, Limits};
+use std::path::Path;
+use std::{env, process};
+
+fn main() {
+    let filename = match env::args().nth(1) {
+        Some(filename) => filename,
+        None => {
+            eprintln!("Usage: cargo run --example run_rom -- path/to/game.nes");
+            process::exit(1);
+        }
+    };
+
+    let mapper = match Cartridge::load(Path::new(&filename)) {
+        Ok(cartridge) => cartridge.into_mapper(),
+        Err(error) => {
+            eprintln!("Error loading ROM: {}", error);
+            process::exit(1);
+        }
+    };
+
+    // The CPU starts at the reset vector that is in the cartridge.
+    let mut cpu = Cpu6502::new(Bus::new_shared_bus(mapper));
+
+    // The PPU's registers are only a stub, and nothing signals the vblank here, so
+    // games that wait for it will spin until they hit one of the limits. The runner
+    // reports that as a failure.
+    let report = HeadlessRunner::new(Limits::default()).run_cpu(&mut cpu);
+
+    println!("instructions: {}", report.instructions);
+    println!("cycles: {}", report.cycles);
+    println!("frames: {}", report.frames);
+    println!("pc: ${:04x}", report.pc);
+    if let Some(failure) = report.failure {
+        println!("failure: {}", failure);
+        process::exit(1);
+    }
+}
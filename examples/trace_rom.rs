@@ -0,0 +1,109 @@
+//! Print a line for every instruction that is run, with the registers before it
+//! runs. The lines are in the nestest.log format, so the logs can be diffed. Pass
+//! --nestest to start from the state that nestest.log expects, and to leave off the
+//! register annotations.
+//!
+//! cargo run --example trace_rom -- path/to/program.asm [instructions]
+//! cargo run --example trace_rom -- path/to/game.nes [instructions]
+//! cargo run --example trace_rom -- --nestest nestest.nes 8991 > out.log
+use nes::bus::Bus;
+use nes::cpu_6502::{Cpu6502, Registers, TraceEvent};
+use nes::mappers::mapper_for_rom;
+use nes::memory_map;
+use nes::opcodes::{Mode, OPCODES};
+use nes::rom::ROM;
+use nes::scenario;
+use nes::trace_log;
+use std::path::Path;
+use std::rc::Rc;
+use std::{env, process};
+
+fn main() {
+    let mut nestest = false;
+    let mut positional = vec![];
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--nestest" => nestest = true,
+            _ => positional.push(arg),
+        }
+    }
+    let (filename, count) = match positional.as_slice() {
+        [filename] => (filename, 100),
+        [filename, count] => match count.parse() {
+            Ok(count) => (filename, count),
+            Err(_) => usage(),
+        },
+        _ => usage(),
+    };
+
+    let mut cpu = match load_cpu(Path::new(filename)) {
+        Ok(cpu) => cpu,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    if nestest {
+        // The automation mode of nestest.nes starts at $C000, and the log starts
+        // after the 7 cycles of the reset sequence.
+        cpu.set_registers(Registers {
+            pc: 0xc000,
+            s: 0xfd,
+            p: 0x24,
+            ..Registers::default()
+        });
+        cpu.total_cycles = 7;
+    }
+
+    let bus = Rc::clone(&cpu.bus);
+    cpu.set_trace_hook(move |event: &TraceEvent| {
+        let registers = event.registers;
+        let line = trace_log::nestest_line(event, &bus.borrow());
+        // Show what stores to the registers do, e.g. "PPUCTRL (W): NMI=1". These go
+        // after the line, so that the log can still be diffed.
+        let stored = match OPCODES[event.opcode as usize].name {
+            "sta" => Some(registers.a),
+            "stx" => Some(registers.x),
+            "sty" => Some(registers.y),
+            _ => None,
+        };
+        let annotation = match (stored, event.mode, event.operands.as_slice()) {
+            (Some(value), Mode::Absolute, &[low, high]) => {
+                memory_map::annotate(u16::from_le_bytes([low, high]), value)
+            }
+            _ => None,
+        };
+        match annotation {
+            Some(annotation) if !nestest => println!("{}  {}", line, annotation),
+            _ => println!("{}", line),
+        }
+    });
+
+    for _ in 0..count {
+        let state = cpu.tick();
+        if !state.is_running() {
+            println!("Stopped: {:?}", state);
+            break;
+        }
+    }
+}
+
+fn load_cpu(path: &Path) -> Result<Cpu6502, String> {
+    if path.extension().and_then(|extension| extension.to_str()) == Some("nes") {
+        let mapper = ROM::load_ines_file(path)
+            .and_then(|rom| mapper_for_rom(&rom))
+            .map_err(|error| format!("Error loading ROM: {}", error))?;
+        return Ok(Cpu6502::new(Bus::new_shared_bus(mapper)));
+    }
+    scenario::load_rom(path)
+        .map(|(cpu, _)| cpu)
+        .map_err(|error| error.to_string())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: cargo run --example trace_rom -- [--nestest] path/to/rom [instructions]"
+    );
+    process::exit(1);
+}
@@ -0,0 +1,985 @@
+use colored::*;
+use nes::{
+    asm::AsmLexer,
+    bench_programs::Workload,
+    bisect::{Bisector, Condition},
+    cartridge::Cartridge,
+    chr::{self, Sheet},
+    crash_report::{self, CrashReporter},
+    emulator::Emulator,
+    encode::Encoder,
+    frontend::{MovieInput, RunLoop},
+    headless::{HeadlessRunner, Limits},
+    latency::{FrontendSettings, LatencyHarness, Reaction},
+    monkey::{Monkey, MonkeyConfig},
+    movie::{Movie, MoviePlayer},
+    nametable::{Nametable, NAMETABLE_SIZE},
+    peephole,
+    region::Region,
+    regress::Corpus,
+    rom::ROM,
+    rom_database::RomDatabase,
+    rom_diff,
+    rom_info::{sha1_hex, RomInfo},
+    savestate_import,
+    scenario::{self, Scenario},
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read},
+    path::Path,
+    process,
+};
+
+fn print_usage() {
+    eprintln!("Usage: nes test path/to/scenario.toml [more/scenarios.toml ...]");
+    eprintln!(
+        "       nes run path/to/program.asm [--max-instructions N] [--max-cycles N]"
+    );
+    eprintln!("                                   [--max-frames N] [--max-rom-size N]");
+    eprintln!("                                   [--max-output N]");
+    eprintln!("       nes lint path/to/program.asm");
+    eprintln!("       nes bench <workload> [size]");
+    eprintln!("       nes regress corpus.toml [--update]");
+    eprintln!("       nes monkey path/to/program.asm [--seed N] [--frames N]");
+    eprintln!("                                   [--input-address N]");
+    eprintln!(
+        "       nes bisect path/to/program.asm movie.fm2 --memory-equals ADDRESS=VALUE"
+    );
+    eprintln!("                                   --hashes|--write-hashes hashes.txt");
+    eprintln!("                                   [--input-address N] [--interval N]");
+    eprintln!("       nes latency path/to/program.asm [--memory ADDRESS] [--buttons N]");
+    eprintln!("                                   [--input-address N] [--trials N]");
+    eprintln!("       nes encode movie.fm2 rom.nes out.mkv");
+    eprintln!("       nes rominfo rom.nes [--db database.toml] [--fix]");
+    eprintln!("       nes diff a.nes b.nes [--cdl-a a.cdl] [--cdl-b b.cdl]");
+    eprintln!("       nes chr export rom.nes|tiles.chr sheet.png");
+    eprintln!("       nes chr import sheet.png tiles.chr|tiles.asm");
+    eprintln!("       nes map savestate.fcs|.mss rom.nes map.csv|map.tmx");
+    eprintln!(
+        "                                   [--nametable 0|1] [--pattern-table 0|1]"
+    );
+    eprintln!("cargo run --bin nes -- test scenarios/fibonacci-u8.toml");
+}
+
+fn main() {
+    crash_report::install_panic_hook();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, paths)) if command == "test" && !paths.is_empty() => {
+            if !run_tests(paths) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "run" && !args.is_empty() => {
+            if !run_headless(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "lint" && args.len() == 1 => {
+            if !lint(&args[0]) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "encode" && args.len() == 3 => {
+            if !encode(&args[0], &args[1], &args[2]) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "rominfo" && !args.is_empty() => {
+            if !rom_info(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "diff" && args.len() >= 2 => {
+            if !diff_roms(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "chr" && args.len() == 3 => {
+            let succeeded = match args[0].as_str() {
+                "export" => export_chr(&args[1], &args[2]),
+                "import" => import_chr(&args[1], &args[2]),
+                _ => {
+                    print_usage();
+                    false
+                }
+            };
+            if !succeeded {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "map" && args.len() >= 3 => {
+            if !export_map(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "monkey" && !args.is_empty() => {
+            if !run_monkey(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "bisect" && args.len() >= 4 => {
+            if !bisect(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "latency" && !args.is_empty() => {
+            if !measure_latency(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "regress" && !args.is_empty() => {
+            if !run_regress(args) {
+                process::exit(1);
+            }
+        }
+        Some((command, args)) if command == "bench" && !args.is_empty() => {
+            if !print_bench(args) {
+                process::exit(1);
+            }
+        }
+        _ => {
+            print_usage();
+            process::exit(1);
+        }
+    }
+}
+
+/// Run each of the scenarios, and return true if they all passed.
+fn run_tests(paths: &[String]) -> bool {
+    let mut failed_count = 0;
+
+    for path in paths {
+        let result = Scenario::load(Path::new(path)).and_then(|scenario| scenario.run());
+        match result {
+            Ok(failures) if failures.is_empty() => {
+                println!("{} {}", "pass".green(), path);
+            }
+            Ok(failures) => {
+                failed_count += 1;
+                println!("{} {}", "fail".red(), path);
+                for failure in failures {
+                    println!("     {}", failure.message);
+                }
+            }
+            Err(error) => {
+                failed_count += 1;
+                println!("{} {}", "error".red(), path);
+                println!("     {}", error);
+            }
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed",
+        paths.len() - failed_count,
+        failed_count
+    );
+    failed_count == 0
+}
+
+/// Assemble the program, and print the peephole warnings with the rows they are on.
+/// Returns false if it doesn't assemble, the warnings alone don't fail.
+fn lint(path: &str) -> bool {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+    let mut lexer = AsmLexer::new(&text);
+    if let Err(parse_error) = lexer.parse() {
+        println!("{}", parse_error.nice_message());
+        return false;
+    }
+    let assembled = match lexer.into_bytes() {
+        Ok(assembled) => assembled,
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+    };
+    let warnings = peephole::analyze(&assembled);
+    for warning in &warnings {
+        let row = warning.row.map(|row| row.to_string()).unwrap_or_default();
+        println!("{}:{}: {} {}", path, row, "warning:".yellow(), warning.kind);
+    }
+    println!("{} warnings", warnings.len());
+    true
+}
+
+/// Run the regression corpus, and print a pass/fail matrix with a row for each ROM.
+/// With --update, the hashes from this run are stored as the good ones.
+fn run_regress(args: &[String]) -> bool {
+    let (path, flags) = args.split_first().unwrap();
+    let update = match flags {
+        [] => false,
+        [flag] if flag == "--update" => true,
+        _ => {
+            print_usage();
+            return false;
+        }
+    };
+    let path = Path::new(path);
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+    let mut corpus = match Corpus::parse(&text) {
+        Ok(corpus) => corpus,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+
+    let results = corpus.run(path.parent().unwrap_or_else(|| Path::new("")));
+    let mut failed_count = 0;
+    for result in &results {
+        let row = result.row();
+        if result.passed() {
+            println!("{} {}", row.green(), result.path.display());
+        } else {
+            failed_count += 1;
+            println!("{} {}", row.red(), result.path.display());
+        }
+    }
+    println!(
+        "\n{} passed, {} failed, a column every {} frames",
+        results.len() - failed_count,
+        failed_count,
+        corpus.interval
+    );
+
+    if update {
+        corpus.update(&results);
+        // Keep the comments at the top of the file, the rest is written out again.
+        let mut header: String = text
+            .lines()
+            .take_while(|line| line.starts_with('#'))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        header.push_str(&corpus.to_toml());
+        if let Err(error) = fs::write(path, header) {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+        println!("Updated the hashes in {}", path.display());
+        return results.iter().all(|result| result.error.is_none());
+    }
+    failed_count == 0
+}
+
+fn parse_limits(args: &[String]) -> Option<Limits> {
+    let mut limits = Limits::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next()?.parse().ok()?;
+        match flag.as_str() {
+            "--max-instructions" => limits.max_instructions = value,
+            "--max-cycles" => limits.max_cycles = value,
+            "--max-frames" => limits.max_frames = value,
+            "--max-rom-size" => limits.max_rom_size = value as usize,
+            "--max-output" => limits.max_output = value as usize,
+            _ => return None,
+        }
+    }
+    Some(limits)
+}
+
+/// Run a ROM with hard limits, and print a report. Returns true if the ROM halted
+/// without any failures.
+fn run_headless(args: &[String]) -> bool {
+    let (path, flags) = args.split_first().unwrap();
+    let limits = match parse_limits(flags) {
+        Some(limits) => limits,
+        None => {
+            print_usage();
+            return false;
+        }
+    };
+
+    // Never read more than the limit into memory, one extra byte is enough to know
+    // that the ROM is too large.
+    let mut bytes = Vec::new();
+    let read = File::open(path).and_then(|file| {
+        file.take(limits.max_rom_size as u64 + 1)
+            .read_to_end(&mut bytes)
+    });
+    if let Err(error) = read {
+        println!("{} {}", "error".red(), error);
+        return false;
+    }
+
+    let mut runner = HeadlessRunner::new(limits);
+    runner.set_crash_reporter(Some(CrashReporter::in_temp_dir()));
+    let report = if path.ends_with(".asm") {
+        match String::from_utf8(bytes) {
+            Ok(text) => runner.run_asm(&text),
+            Err(_) => {
+                println!("{} The assembly file is not valid UTF-8.", "error".red());
+                return false;
+            }
+        }
+    } else {
+        runner.run_program(&bytes)
+    };
+
+    print!("{}", report.output);
+    println!("instructions: {}", report.instructions);
+    println!("cycles: {}", report.cycles);
+    println!("frames: {}", report.frames);
+    println!("pc: ${:04x}", report.pc);
+    match &report.crash_report {
+        Some(Ok(dir)) => println!("crash report: {}", dir.display()),
+        Some(Err(error)) => {
+            println!(
+                "{} Unable to write the crash report: {}",
+                "error".red(),
+                error
+            )
+        }
+        None => {}
+    }
+    match report.failure {
+        Some(failure) => {
+            println!("{} {}", "failure:".red(), failure);
+            false
+        }
+        None => {
+            println!("{}", "halted".green());
+            true
+        }
+    }
+}
+
+/// Mash random input into a program, and report if it crashes. The seed is printed
+/// so that a failure can be reproduced.
+fn run_monkey(args: &[String]) -> bool {
+    let (path, flags) = args.split_first().unwrap();
+    let mut config = MonkeyConfig::default();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let value = match flags.next().map(|value| value.parse::<u64>()) {
+            Some(Ok(value)) => value,
+            _ => {
+                print_usage();
+                return false;
+            }
+        };
+        match flag.as_str() {
+            "--seed" => config.seed = value,
+            "--frames" => config.frames = value,
+            "--input-address" if value <= 0xffff => config.input_address = value as u16,
+            _ => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+
+    let mut cpu = match scenario::load_rom(Path::new(path)) {
+        Ok((cpu, _)) => cpu,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+    let report = Monkey::new(config).run(&mut cpu);
+    println!("seed: {}", config.seed);
+    println!("frames: {}", report.frames);
+    match report.failure {
+        Some(failure) => {
+            println!("{} {}", "failure:".red(), failure);
+            if let Some(last) = report.inputs.last() {
+                println!("last input: {:08b}", last[0]);
+            }
+            false
+        }
+        None => {
+            println!("{}", "survived".green());
+            true
+        }
+    }
+}
+
+/// Find the first frame of a movie where the memory condition is true, or where the
+/// frame hash stops matching the hashes from a good run. With --write-hashes, the
+/// hashes for a good run are written out instead, one per line.
+fn bisect(args: &[String]) -> bool {
+    let (program_path, movie_path) = (&args[0], &args[1]);
+    let movie = match Movie::load(Path::new(movie_path)) {
+        Ok(movie) => movie,
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+    };
+    let mut cpu = match scenario::load_rom(Path::new(program_path)) {
+        Ok((cpu, _)) => cpu,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+
+    let mut bisector = Bisector::new(&movie);
+    let mut condition = None;
+    let mut write_hashes = None;
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        let value = match flags.next() {
+            Some(value) => value,
+            None => {
+                print_usage();
+                return false;
+            }
+        };
+        match (flag.as_str(), value.parse::<u64>()) {
+            ("--input-address", Ok(address)) if address <= 0xffff => {
+                bisector.input_address = address as u16
+            }
+            ("--interval", Ok(interval)) if interval > 0 => bisector.interval = interval,
+            ("--memory-equals", _) => {
+                let mut parts = value.split('=').map(|part| part.parse::<u64>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(address)), Some(Ok(value)), None)
+                        if address <= 0xffff && value <= 0xff =>
+                    {
+                        condition = Some(Condition::MemoryEquals {
+                            address: address as u16,
+                            value: value as u8,
+                        });
+                    }
+                    _ => {
+                        print_usage();
+                        return false;
+                    }
+                }
+            }
+            ("--hashes", _) => {
+                let text = match fs::read_to_string(value) {
+                    Ok(text) => text,
+                    Err(error) => {
+                        println!("{} Unable to read {}: {}", "error".red(), value, error);
+                        return false;
+                    }
+                };
+                let hashes: Result<Vec<u32>, _> = text
+                    .lines()
+                    .map(|line| u32::from_str_radix(line.trim(), 16))
+                    .collect();
+                match hashes {
+                    Ok(hashes) => {
+                        condition = Some(Condition::FrameHashMismatch { hashes })
+                    }
+                    Err(_) => {
+                        println!(
+                            "{} The hashes must be hex, one per line.",
+                            "error".red()
+                        );
+                        return false;
+                    }
+                }
+            }
+            ("--write-hashes", _) => write_hashes = Some(value),
+            _ => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+
+    if let Some(path) = write_hashes {
+        let hashes: String = bisector
+            .frame_hashes(&mut cpu)
+            .iter()
+            .map(|hash| format!("{:08x}\n", hash))
+            .collect();
+        if let Err(error) = fs::write(path, hashes) {
+            println!("{} Unable to write {}: {}", "error".red(), path, error);
+            return false;
+        }
+        println!(
+            "Wrote the hashes for {} frames to {}",
+            movie.frames.len(),
+            path
+        );
+        return true;
+    }
+    let condition = match condition {
+        Some(condition) => condition,
+        None => {
+            print_usage();
+            return false;
+        }
+    };
+
+    let report = bisector.run(&mut cpu, |cpu, frame| condition.check(cpu, frame));
+    println!("checks: {}", report.checks);
+    println!("frames run: {}", report.frames_run);
+    match report.frame {
+        Some(frame) => {
+            println!("{} frame {}, pc: ${:04x}", "found".red(), frame, cpu.pc);
+            true
+        }
+        None => {
+            println!(
+                "The condition was never true in {} frames.",
+                movie.frames.len()
+            );
+            false
+        }
+    }
+}
+
+/// Measure the frames between a button press and the game reacting, either in a byte
+/// of memory, or anywhere in the frame.
+fn measure_latency(args: &[String]) -> bool {
+    let mut cpu = match scenario::load_rom(Path::new(&args[0])) {
+        Ok((cpu, _)) => cpu,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+
+    let mut harness = LatencyHarness::new(Reaction::FrameChange);
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        let value = match flags.next().map(|value| value.parse::<u64>()) {
+            Some(Ok(value)) => value,
+            _ => {
+                print_usage();
+                return false;
+            }
+        };
+        match flag.as_str() {
+            "--memory" if value <= 0xffff => {
+                harness.reaction = Reaction::MemoryChange {
+                    address: value as u16,
+                }
+            }
+            "--buttons" if value > 0 && value <= 0xff => harness.buttons = value as u8,
+            "--input-address" if value <= 0xffff => harness.input_address = value as u16,
+            "--trials" if value > 0 => harness.trials = value,
+            _ => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+
+    let report = harness.run(&mut cpu);
+    print!("{}", report.report(&FrontendSettings::common()));
+    report.min().is_some()
+}
+
+/// Print the source of one of the generated benchmark workloads, so that it can be
+/// run or inspected.
+fn print_bench(args: &[String]) -> bool {
+    let workload = match Workload::from_name(&args[0]) {
+        Some(workload) => workload,
+        None => {
+            let names: Vec<&str> = Workload::ALL.iter().map(Workload::name).collect();
+            eprintln!("Unknown workload, choose from: {}", names.join(", "));
+            return false;
+        }
+    };
+    let size = match args.get(1).map(|size| size.parse::<u8>()) {
+        None => 0x20,
+        Some(Ok(size)) if size >= 2 => size,
+        Some(_) => {
+            eprintln!("The size must be a number from 2 to 255.");
+            return false;
+        }
+    };
+    print!("{}", workload.source(size));
+    true
+}
+
+/// Replay a movie headlessly, and pipe the frames and the audio to ffmpeg, see
+/// encode::Encoder.
+fn encode(movie_path: &str, rom_path: &str, out_path: &str) -> bool {
+    let movie = match Movie::load(Path::new(movie_path)) {
+        Ok(movie) => movie,
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+    };
+    let mut emulator = match Cartridge::load(Path::new(rom_path)) {
+        Ok(cartridge) => Emulator::new(cartridge.into_mapper()),
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+    if movie.is_pal() {
+        emulator.set_region(Region::Pal);
+    }
+    let mut encoder = match Encoder::start(
+        Path::new(out_path),
+        emulator.region().frames_per_second(),
+    ) {
+        Ok(encoder) => encoder,
+        Err(error) => {
+            println!("{} Unable to start ffmpeg: {}", "error".red(), error);
+            return false;
+        }
+    };
+    let mut audio = match encoder.audio_track(emulator.sample_rate()) {
+        Ok(audio) => audio,
+        Err(error) => {
+            println!(
+                "{} Unable to create the audio track: {}",
+                "error".red(),
+                error
+            );
+            return false;
+        }
+    };
+
+    let mut input = MovieInput::new(MoviePlayer::new(&movie));
+    let result = RunLoop::new().run(&mut emulator, &mut encoder, &mut audio, &mut input);
+    // The encoder has to be finished either way, so that ffmpeg isn't left running.
+    let finished = encoder.finish(audio);
+    let frames = match (result, finished) {
+        (Ok(frames), Ok(())) => frames,
+        (Err(message), _) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+        (_, Err(error)) => {
+            println!("{} Unable to encode the video: {}", "error".red(), error);
+            return false;
+        }
+    };
+    println!("Encoded {} frames to {}.", frames, out_path);
+    true
+}
+
+/// Print what's in the iNES header, and check the file for problems. Returns true if
+/// there weren't any problems, or they were all fixed.
+fn rom_info(args: &[String]) -> bool {
+    let (path, flags) = args.split_first().unwrap();
+    let mut database_path = None;
+    let mut should_fix = false;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--db" => match flags.next() {
+                Some(path) => database_path = Some(path),
+                None => {
+                    print_usage();
+                    return false;
+                }
+            },
+            "--fix" => should_fix = true,
+            _ => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+
+    // The user's database is checked before the embedded one.
+    let mut database = match database_path.map(|path| RomDatabase::load(Path::new(path)))
+    {
+        Some(Ok(database)) => database,
+        Some(Err(message)) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+        None => RomDatabase::default(),
+    };
+    database.merge(RomDatabase::embedded());
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            println!("{} {}", "error".red(), error);
+            return false;
+        }
+    };
+    let info = match RomInfo::inspect(&bytes, Some(&database)) {
+        Ok(info) => info,
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            return false;
+        }
+    };
+
+    let header = &info.header;
+    println!("PRG ROM: {} x 16 KB", header.prg_rom_banks);
+    println!("CHR ROM: {} x 8 KB", header.character_rom_banks);
+    if header.nes_2_0 {
+        println!("format: NES 2.0");
+        println!("mapper: {}.{}", header.mapping_number, header.submapper);
+        println!(
+            "PRG RAM: {} bytes, {} battery-backed",
+            header.prg_ram_size, header.prg_nvram_size
+        );
+        println!(
+            "CHR RAM: {} bytes, {} battery-backed",
+            header.chr_ram_size, header.chr_nvram_size
+        );
+        println!("console: {:?}", header.console_type);
+    } else {
+        println!("format: iNES");
+        println!("mapper: {}", header.mapping_number);
+    }
+    if header.four_screen_vram {
+        println!("mirroring: four screen");
+    } else {
+        println!("mirroring: {:?}", header.mirroring);
+    }
+    println!("battery: {}", header.persistent_memory);
+    println!("trainer: {}", header.has_trainer);
+    if header.nes_2_0 {
+        println!("TV system: {:?}", header.tv_system);
+    } else {
+        println!("TV system: {:?}", header.tv_system_rarely_used);
+    }
+    println!("region: {:?}", Region::detect(header, info.database_entry));
+    println!("file CRC32: {:08x}", info.file_crc32);
+    println!("ROM CRC32: {:08x}", info.rom_crc32);
+    println!("ROM SHA-1: {}", sha1_hex(&info.rom_sha1));
+    match info.database_entry {
+        Some(entry) => println!("database: {}", entry.name),
+        None => println!("database: no match"),
+    }
+
+    if info.problems.is_empty() {
+        println!("{}", "no problems found".green());
+        return true;
+    }
+    for problem in &info.problems {
+        println!("{} {}", "problem:".red(), problem);
+    }
+    if !should_fix {
+        return false;
+    }
+    let fixed_path = Path::new(path).with_extension("fixed.nes");
+    match info.fix(&bytes).and_then(|fixed| {
+        fs::write(&fixed_path, fixed).map_err(|error| error.to_string())
+    }) {
+        Ok(()) => {
+            println!("{} {}", "fixed:".green(), fixed_path.display());
+            true
+        }
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            false
+        }
+    }
+}
+
+/// Disassemble two ROMs, and print the routines that changed. The code/data logs say
+/// which bytes are code, otherwise the whole PRG ROM is treated as code.
+fn diff_roms(args: &[String]) -> bool {
+    let (paths, flags) = args.split_at(2);
+    let mut cdl_paths = [None, None];
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let side = match flag.as_str() {
+            "--cdl-a" => 0,
+            "--cdl-b" => 1,
+            _ => {
+                print_usage();
+                return false;
+            }
+        };
+        match flags.next() {
+            Some(path) => cdl_paths[side] = Some(path),
+            None => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+
+    let mut disassemblies = Vec::new();
+    for (path, cdl_path) in paths.iter().zip(&cdl_paths) {
+        let prg = match ROM::load_ines_file(Path::new(path)) {
+            Ok(rom) => rom.program_rom,
+            Err(error) => {
+                println!("{} {}: {}", "error".red(), path, error);
+                return false;
+            }
+        };
+        let log = match cdl_path.map(fs::read) {
+            Some(Ok(log)) if log.len() >= prg.len() => Some(log),
+            Some(Ok(_)) => {
+                println!(
+                    "{} The code/data log is shorter than the PRG ROM of {}.",
+                    "error".red(),
+                    path
+                );
+                return false;
+            }
+            Some(Err(error)) => {
+                println!("{} {}", "error".red(), error);
+                return false;
+            }
+            None => None,
+        };
+        disassemblies.push(rom_diff::disassemble(&prg, log.as_deref()));
+    }
+
+    let diff = rom_diff::diff_disassembly(&disassemblies[0], &disassemblies[1]);
+    if diff.is_empty() {
+        println!("{}", "the code is the same".green());
+    } else {
+        print!("{}", diff);
+    }
+    true
+}
+
+/// Export the CHR ROM of a .nes file, or a raw .chr file, to a PNG sheet.
+fn export_chr(in_path: &str, out_path: &str) -> bool {
+    let chr = if in_path.ends_with(".nes") {
+        ROM::load_ines_file(Path::new(in_path))
+            .map(|rom| rom.character_rom)
+            .map_err(|error| error.to_string())
+    } else {
+        fs::read(in_path).map_err(|error| error.to_string())
+    };
+    let result = chr.and_then(|chr| {
+        if chr.is_empty() {
+            return Err(String::from(
+                "There is no CHR ROM, the board uses CHR RAM instead.",
+            ));
+        }
+        let file = File::create(out_path).map_err(|error| error.to_string())?;
+        Sheet::from_chr(&chr).write_png(BufWriter::new(file))?;
+        Ok(chr.len() / chr::TILE_BYTES)
+    });
+    match result {
+        Ok(tiles) => {
+            println!("Exported {} tiles to {}", tiles, out_path);
+            true
+        }
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            false
+        }
+    }
+}
+
+/// Import an edited PNG sheet, and write it as raw CHR data, or as .byte directives
+/// that can be included in a program.
+fn import_chr(in_path: &str, out_path: &str) -> bool {
+    let result = File::open(in_path)
+        .map_err(|error| error.to_string())
+        .and_then(|file| Sheet::read_png(BufReader::new(file)))
+        .and_then(|sheet| sheet.to_chr())
+        .and_then(|chr| {
+            let contents = if out_path.ends_with(".asm") {
+                chr::to_asm(&chr).into_bytes()
+            } else {
+                chr.clone()
+            };
+            fs::write(out_path, contents).map_err(|error| error.to_string())?;
+            Ok(chr.len() / chr::TILE_BYTES)
+        });
+    match result {
+        Ok(tiles) => {
+            println!("Imported {} tiles to {}", tiles, out_path);
+            true
+        }
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            false
+        }
+    }
+}
+
+/// Export a nametable from another emulator's savestate as a CSV or Tiled map. Only
+/// the PPU's registers are emulated, not its VRAM, so the savestate is the only source
+/// of nametables. The tiles are drawn from the ROM's CHR.
+fn export_map(args: &[String]) -> bool {
+    let mut nametable_index = 0;
+    let mut pattern_table = 0;
+    let mut flags = args[3..].iter();
+    while let Some(flag) = flags.next() {
+        let value = match flags.next().map(|value| value.parse::<usize>()) {
+            Some(Ok(value)) if value < 2 => value,
+            _ => {
+                print_usage();
+                return false;
+            }
+        };
+        match flag.as_str() {
+            "--nametable" => nametable_index = value,
+            "--pattern-table" => pattern_table = value,
+            _ => {
+                print_usage();
+                return false;
+            }
+        }
+    }
+    let out_path = Path::new(&args[2]);
+
+    let result = fs::read(&args[0])
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| savestate_import::import(&bytes))
+        .and_then(|state| {
+            let nametables = state
+                .ppu
+                .nametables
+                .ok_or("The savestate doesn't have any nametables.")?;
+            let start = nametable_index * NAMETABLE_SIZE;
+            let bytes = nametables
+                .get(start..start + NAMETABLE_SIZE)
+                .ok_or("The savestate doesn't have that nametable.")?;
+            let nametable = Nametable::new(bytes)?;
+
+            if out_path.extension().and_then(|extension| extension.to_str()) != Some("tmx")
+            {
+                fs::write(out_path, nametable.tiles_csv())
+                    .and_then(|_| {
+                        fs::write(
+                            out_path.with_extension("palettes.csv"),
+                            nametable.palettes_csv(),
+                        )
+                    })
+                    .map_err(|error| error.to_string())?;
+                return Ok(());
+            }
+
+            // Write the pattern table next to the map, as the tileset image.
+            let chr = ROM::load_ines_file(Path::new(&args[1]))
+                .map_err(|error| error.to_string())?
+                .character_rom;
+            let pattern_table_size = 256 * chr::TILE_BYTES;
+            let start = pattern_table * pattern_table_size;
+            let tiles = chr.get(start..start + pattern_table_size).ok_or(
+                "The ROM doesn't have CHR ROM for that pattern table, it may use CHR RAM.",
+            )?;
+            let image_path = out_path.with_extension("png");
+            let file = File::create(&image_path).map_err(|error| error.to_string())?;
+            Sheet::from_chr(tiles).write_png(BufWriter::new(file))?;
+            let image_name = image_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            fs::write(out_path, nametable.to_tmx(&image_name))
+                .map_err(|error| error.to_string())
+        });
+    match result {
+        Ok(()) => {
+            println!("Exported the map to {}", out_path.display());
+            true
+        }
+        Err(message) => {
+            println!("{} {}", "error".red(), message);
+            false
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::frontend::{AudioSink, VideoSink};
+use crate::ppu::{FRAME_BYTES, FRAME_HEIGHT, FRAME_WIDTH};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Encodes frames into a video with ffmpeg, which needs to be on the PATH. The
+/// frames are piped in as they come, and the samples go to an AudioTrack next to the
+/// video, which finish muxes in. The audio is FLAC, so the video needs a container
+/// that takes it, like .mkv.
+pub struct Encoder {
+    ffmpeg: Child,
+    stdin: ChildStdin,
+    out: PathBuf,
+    video_path: PathBuf,
+}
+
+/// The raw samples for an Encoder, see Encoder::audio_track.
+pub struct AudioTrack {
+    file: BufWriter<File>,
+    path: PathBuf,
+    sample_rate: u32,
+}
+
+impl Encoder {
+    pub fn start(out: &Path, frame_rate: f64) -> io::Result<Encoder> {
+        let video_path = out.with_extension("video.mkv");
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(video_args(&video_path, frame_rate))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let stdin = ffmpeg.stdin.take().expect("The stdin was piped.");
+        Ok(Encoder {
+            ffmpeg,
+            stdin,
+            out: out.to_path_buf(),
+            video_path,
+        })
+    }
+
+    /// Create the file for the samples, which are mono, at the sample rate.
+    pub fn audio_track(&self, sample_rate: u32) -> io::Result<AudioTrack> {
+        let path = self.out.with_extension("audio.f32");
+        Ok(AudioTrack {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+            sample_rate,
+        })
+    }
+
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), FRAME_BYTES, "The frame is the wrong size.");
+        self.stdin.write_all(rgb)
+    }
+
+    /// Close the pipe, wait for ffmpeg to finish writing the frames, and then mux in
+    /// the audio. The files in between are removed, even when it fails.
+    pub fn finish(self, audio: AudioTrack) -> io::Result<()> {
+        let Encoder {
+            mut ffmpeg,
+            stdin,
+            out,
+            video_path,
+        } = self;
+        let AudioTrack {
+            file,
+            path: audio_path,
+            sample_rate,
+        } = audio;
+        drop(stdin);
+        let result = wait_for(&mut ffmpeg)
+            .and_then(|_| file.into_inner().map_err(|error| error.into_error()))
+            .and_then(|_| {
+                let mut mux = Command::new("ffmpeg")
+                    .args(mux_args(&video_path, &audio_path, sample_rate, &out))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .spawn()?;
+                wait_for(&mut mux)
+            });
+        // The files are only in the way now, and the video may not even have been
+        // written if ffmpeg failed.
+        let _ = fs::remove_file(&video_path);
+        let _ = fs::remove_file(&audio_path);
+        result
+    }
+}
+
+impl AudioTrack {
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn wait_for(ffmpeg: &mut Child) -> io::Result<()> {
+    let status = ffmpeg.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {}", status)));
+    }
+    Ok(())
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().copied().map(String::from).collect()
+}
+
+/// Read raw frames from stdin, and encode them losslessly, which is what TAS
+/// publishers expect to start from.
+pub fn video_args(out: &Path, frame_rate: f64) -> Vec<String> {
+    let size = format!("{}x{}", FRAME_WIDTH, FRAME_HEIGHT);
+    let frame_rate = frame_rate.to_string();
+    let mut video_args = args(&[
+        "-y",
+        "-loglevel",
+        "error",
+        "-f",
+        "rawvideo",
+        "-pixel_format",
+        "rgb24",
+        "-video_size",
+        &size,
+        "-framerate",
+        &frame_rate,
+        "-i",
+        "-",
+        "-c:v",
+        "libx264rgb",
+        "-qp",
+        "0",
+    ]);
+    video_args.push(out.to_string_lossy().into_owned());
+    video_args
+}
+
+/// Copy the video as is, and encode the mono samples losslessly next to it.
+pub fn mux_args(video: &Path, audio: &Path, sample_rate: u32, out: &Path) -> Vec<String> {
+    let sample_rate = sample_rate.to_string();
+    let mut mux_args = args(&["-y", "-loglevel", "error", "-i"]);
+    mux_args.push(video.to_string_lossy().into_owned());
+    mux_args.extend(args(&[
+        "-f",
+        "f32le",
+        "-ar",
+        &sample_rate,
+        "-ac",
+        "1",
+        "-i",
+    ]));
+    mux_args.push(audio.to_string_lossy().into_owned());
+    mux_args.extend(args(&["-c:v", "copy", "-c:a", "flac"]));
+    mux_args.push(out.to_string_lossy().into_owned());
+    mux_args
+}
+
+impl VideoSink for Encoder {
+    fn present_frame(&mut self, rgb: &[u8]) -> Result<(), String> {
+        self.write_frame(rgb)
+            .map_err(|error| format!("Unable to encode the frame: {}", error))
+    }
+}
+
+impl AudioSink for AudioTrack {
+    fn queue_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.write_samples(samples)
+            .map_err(|error| format!("Unable to encode the samples: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let args = video_args(Path::new("out.video.mkv"), 60.0988);
+        assert_eq!(args.last().unwrap(), "out.video.mkv");
+        let size = args.iter().position(|arg| arg == "-video_size").unwrap();
+        assert_eq!(args[size + 1], "256x240");
+        let rate = args.iter().position(|arg| arg == "-framerate").unwrap();
+        assert_eq!(args[rate + 1], "60.0988");
+        let input = args.iter().position(|arg| arg == "-i").unwrap();
+        assert_eq!(args[input + 1], "-");
+
+        let args = mux_args(
+            Path::new("out.video.mkv"),
+            Path::new("out.audio.f32"),
+            44_100,
+            Path::new("out.mkv"),
+        );
+        assert_eq!(args.last().unwrap(), "out.mkv");
+        let inputs: Vec<&String> = args
+            .iter()
+            .zip(&args[1..])
+            .filter(|(arg, _)| *arg == "-i")
+            .map(|(_, input)| input)
+            .collect();
+        assert_eq!(inputs, ["out.video.mkv", "out.audio.f32"]);
+        let rate = args.iter().position(|arg| arg == "-ar").unwrap();
+        assert_eq!(args[rate + 1], "44100");
+    }
+}
@@ -1,14 +1,8 @@
-// Remove this once this is a bit more mature.
-#![allow(dead_code)]
-// Clippy rules to disable.
-#![allow(clippy::new_without_default)]
+//! The emulator and its tools in one crate, for the `nes` binary and the examples.
+//! The pieces are their own crates, see "Using it as a library" in the README.
 
-pub mod asm;
-pub mod bus;
-pub mod constants;
-pub mod cpu_6502;
-pub mod emulator;
-pub mod mappers;
-pub mod opcodes;
-pub mod ppu;
-pub mod rom;
+pub use nes_asm::*;
+pub use nes_core::*;
+pub use nes_debugger::*;
+
+pub mod encode;